@@ -0,0 +1,218 @@
+use futures::{Poll, Future, Async};
+use futures::sync::oneshot;
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use super::DEFAULT_CPU_POOL;
+use sys::{long_path, retry};
+
+static DEFAULT_MAX_BATCH_SIZE: usize = 1024 * 1024;
+
+struct PendingRecord {
+    data: Bytes,
+    sender: oneshot::Sender<std::io::Result<u64>>,
+}
+
+struct CoordinatorQueue {
+    records: VecDeque<PendingRecord>,
+    /// В пуле выполняется запись пакетов
+    running: bool,
+}
+
+struct CoordinatorWriter {
+    file: std::fs::File,
+    /// Смещение, с которого начнется следующий пакет
+    position: u64,
+    poisoned: bool,
+}
+
+struct CoordinatorInner {
+    writer: Mutex<CoordinatorWriter>,
+    queue: Mutex<CoordinatorQueue>,
+}
+
+fn poisoned_error() -> std::io::Error {
+    std::io::Error::other("coordinator is poisoned by a previous write error")
+}
+
+/// Забирает из очереди записи очередного пакета (но не меньше одной записи)
+fn take_batch(inner: &CoordinatorInner, max_batch_size: usize) -> Option<Vec<PendingRecord>> {
+    let mut queue = inner.queue.lock().unwrap();
+    if queue.records.is_empty() {
+        queue.running = false;
+        return None;
+    }
+    let mut size = 0;
+    let mut batch = Vec::new();
+    while let Some(len) = queue.records.front().map(|record| record.data.len()) {
+        if !batch.is_empty() && size + len > max_batch_size {
+            break;
+        }
+        size += len;
+        batch.push(queue.records.pop_front().unwrap());
+    }
+    Some(batch)
+}
+
+/// Записывает пакет одним вызовом и возвращает смещение его начала
+fn write_batch(writer: &mut CoordinatorWriter, batch: &[PendingRecord], sync: bool) -> std::io::Result<u64> {
+    if writer.poisoned {
+        return Err(poisoned_error());
+    }
+    let mut buf = Vec::with_capacity(batch.iter().map(|record| record.data.len()).sum());
+    for record in batch {
+        buf.extend_from_slice(&record.data);
+    }
+    let result = writer.file.write_all(&buf)
+        .and_then(|_| if sync { retry(|| writer.file.sync_data()) } else { Ok(()) });
+    if let Err(error) = result {
+        // часть пакета могла быть записана, поэтому следующие смещения неизвестны
+        writer.poisoned = true;
+        return Err(error);
+    }
+    let offset = writer.position;
+    writer.position += buf.len() as u64;
+    Ok(offset)
+}
+
+fn drain(inner: &CoordinatorInner, max_batch_size: usize, sync: bool) {
+    while let Some(batch) = take_batch(inner, max_batch_size) {
+        let result = write_batch(&mut inner.writer.lock().unwrap(), &batch, sync);
+        let mut offset = 0;
+        for record in batch {
+            let len = record.data.len() as u64;
+            let _ = record.sender.send(match result {
+                Ok(start) => Ok(start + offset),
+                Err(ref error) => Err(std::io::Error::new(error.kind(), error.to_string())),
+            });
+            offset += len;
+        }
+    }
+}
+
+
+// AppendCoordinator
+
+/// Координатор дозаписи записей в один файл из множества задач.
+///
+/// Записи ставятся в очередь и дописываются в пуле потоков единственным обработчиком,
+/// который объединяет накопившиеся записи в пакеты не больше `max_batch_size` байт
+/// (запись большего размера образует отдельный пакет) и выполняет запись каждого пакета
+/// одним вызовом, а при `sync_batches(true)` - еще и сбрасывает его на диск.
+/// Каждая запись получает собственный future со смещением ее начала в файле.
+/// После ошибки записи пакета все последующие записи завершаются ошибкой.
+#[derive(Clone)]
+pub struct AppendCoordinator {
+    cpu_pool: &'static CpuPool,
+    inner: Arc<CoordinatorInner>,
+    max_batch_size: usize,
+    sync: bool,
+}
+impl AppendCoordinator {
+
+    /// Открывает файл для дозаписи, создавая его при необходимости
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> CpuFuture<AppendCoordinator, std::io::Error> {
+        Self::open_with_pool(&DEFAULT_CPU_POOL, path)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<AppendCoordinator, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let file = retry(|| std::fs::OpenOptions::new().append(true).create(true).open(long_path(&path)))?;
+            let position = file.metadata()?.len();
+            Ok(AppendCoordinator::from_std(cpu_pool, file, position))
+        })
+    }
+
+    /// Создает координатор из открытого в режиме дозаписи файла, длина которого равна `position`
+    pub fn from_std(cpu_pool: &'static CpuPool, file: std::fs::File, position: u64) -> AppendCoordinator {
+        AppendCoordinator {
+            cpu_pool,
+            inner: Arc::new(CoordinatorInner {
+                writer: Mutex::new(CoordinatorWriter {
+                    file,
+                    position,
+                    poisoned: false,
+                }),
+                queue: Mutex::new(CoordinatorQueue {
+                    records: VecDeque::new(),
+                    running: false,
+                }),
+            }),
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            sync: false,
+        }
+    }
+
+    /// Задает наибольший размер пакета в байтах
+    #[inline]
+    pub fn max_batch_size(mut self, max_batch_size: usize) -> Self {
+        assert!(max_batch_size > 0, "max batch size must be greater than zero");
+        self.max_batch_size = max_batch_size;
+        self
+    }
+
+    /// Сбрасывать ли каждый пакет на диск перед завершением futures его записей
+    #[inline]
+    pub fn sync_batches(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    /// Ставит запись в очередь дозаписи. Результатом является смещение начала записи.
+    pub fn append(&self, data: Bytes) -> AppendRecord {
+        let (sender, receiver) = oneshot::channel();
+        let start = {
+            let mut queue = self.inner.queue.lock().unwrap();
+            queue.records.push_back(PendingRecord { data, sender });
+            !std::mem::replace(&mut queue.running, true)
+        };
+        if start {
+            let inner = self.inner.clone();
+            let max_batch_size = self.max_batch_size;
+            let sync = self.sync;
+            self.cpu_pool.spawn_fn(move || {
+                drain(&inner, max_batch_size, sync);
+                Ok::<(), ()>(())
+            }).forget();
+        }
+        AppendRecord { receiver }
+    }
+}
+impl std::fmt::Debug for AppendCoordinator {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AppendCoordinator")
+            .field("max_batch_size", &self.max_batch_size)
+            .field("sync", &self.sync)
+            .finish()
+    }
+}
+
+
+// AppendRecord
+
+/// Результат дозаписи записи через `AppendCoordinator`: смещение начала записи
+pub struct AppendRecord {
+    receiver: oneshot::Receiver<std::io::Result<u64>>,
+}
+impl Future for AppendRecord {
+    type Item = u64;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(result)) => result.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => Err(std::io::Error::other("append coordinator stopped")),
+        }
+    }
+}
+impl std::fmt::Debug for AppendRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AppendRecord").finish()
+    }
+}
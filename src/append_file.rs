@@ -0,0 +1,87 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use super::DEFAULT_CPU_POOL;
+use sys::{long_path, retry};
+
+/// Наибольший размер записи, для которой гарантируется атомарность дозаписи
+/// между процессами (аналог `PIPE_BUF`)
+pub static ATOMIC_APPEND_LIMIT: usize = 4 * 1024;
+
+
+// AppendFile
+
+/// Файл, открытый в режиме атомарной дозаписи (`O_APPEND`, на Windows `FILE_APPEND_DATA`).
+///
+/// Каждая запись выполняется одним системным вызовом, при котором ядро атомарно перемещает
+/// позицию в конец файла, поэтому записи нескольких процессов, дописывающих в один файл,
+/// не перезаписывают друг друга. Записи размером до `ATOMIC_APPEND_LIMIT` не перемешиваются
+/// с записями других процессов на локальных файловых системах; для сетевых файловых систем
+/// (например, NFS) атомарность дозаписи не гарантируется. Если система записала только
+/// часть данных, запись завершается ошибкой, а не дописывается отдельным вызовом.
+///
+/// Результатом `append` является смещение начала записи: оно вычисляется по позиции
+/// дескриптора после записи, поэтому записи через один `AppendFile` выполняются по одной.
+#[derive(Clone)]
+pub struct AppendFile {
+    cpu_pool: &'static CpuPool,
+    file: Arc<Mutex<std::fs::File>>,
+}
+impl AppendFile {
+
+    /// Открывает файл в режиме дозаписи, создавая его при необходимости
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> CpuFuture<AppendFile, std::io::Error> {
+        Self::open_with_pool(&DEFAULT_CPU_POOL, path)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<AppendFile, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let file = retry(|| std::fs::OpenOptions::new().append(true).create(true).open(long_path(&path)))?;
+            Ok(AppendFile::from_std(cpu_pool, file))
+        })
+    }
+
+    /// Создает структуру из файла, уже открытого в режиме дозаписи
+    #[inline]
+    pub fn from_std(cpu_pool: &'static CpuPool, file: std::fs::File) -> AppendFile {
+        AppendFile {
+            cpu_pool,
+            file: Arc::new(Mutex::new(file)),
+        }
+    }
+
+    /// Дописывает запись одним системным вызовом. Результатом является смещение начала записи.
+    pub fn append(&self, data: Bytes) -> CpuFuture<u64, std::io::Error> {
+        let file = self.file.clone();
+        self.cpu_pool.spawn_fn(move || {
+            if data.is_empty() {
+                return file.lock().unwrap().seek(SeekFrom::End(0));
+            }
+            let mut file = file.lock().unwrap();
+            let size = retry(|| file.write(&data))?;
+            if size < data.len() {
+                return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "record was appended partially"));
+            }
+            let end = file.seek(SeekFrom::Current(0))?;
+            Ok(end - size as u64)
+        })
+    }
+
+    /// Сбрасывает на диск дописанные данные
+    pub fn sync_data(&self) -> CpuFuture<(), std::io::Error> {
+        let file = self.file.clone();
+        self.cpu_pool.spawn_fn(move || {
+            let file = file.lock().unwrap();
+            retry(|| file.sync_data())
+        })
+    }
+}
+impl std::fmt::Debug for AppendFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AppendFile").finish()
+    }
+}
@@ -0,0 +1,299 @@
+use futures::{Async, Future, Poll};
+use futures::future::{self, Either, Shared};
+use futures::sync::oneshot;
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use super::DEFAULT_CPU_POOL;
+use sys;
+use sys::{long_path, retry};
+
+
+// OffsetAppender
+
+struct AppenderState {
+    /// Смещение, с которого начнется следующая запись
+    next: u64,
+    /// Граница непрерывно записанных данных
+    written: u64,
+    /// Граница данных, гарантированно сохраненных на диск
+    durable: u64,
+    /// Завершенные записи, которые еще не примыкают к `written`
+    completed: BTreeMap<u64, u64>,
+    poisoned: bool,
+    /// Записи, переданные после последнего барьера
+    epoch: Arc<Epoch>,
+    /// Последний барьер, после которого начинаются новые записи
+    barrier: Option<Shared<oneshot::Receiver<()>>>,
+}
+
+struct EpochState {
+    pending: usize,
+    sealed: Option<oneshot::Sender<()>>,
+}
+
+/// Записи, переданные между двумя барьерами
+struct Epoch {
+    state: Mutex<EpochState>,
+}
+impl Epoch {
+    fn new() -> Arc<Epoch> {
+        Arc::new(Epoch {
+            state: Mutex::new(EpochState {
+                pending: 0,
+                sealed: None,
+            }),
+        })
+    }
+
+    fn start(epoch: &Arc<Epoch>) -> EpochGuard {
+        epoch.state.lock().unwrap().pending += 1;
+        EpochGuard(epoch.clone())
+    }
+
+    /// Закрывает эпоху; результат завершается после завершения всех ее записей
+    fn seal(&self) -> oneshot::Receiver<()> {
+        let (sender, receiver) = oneshot::channel();
+        let mut state = self.state.lock().unwrap();
+        if state.pending == 0 {
+            let _ = sender.send(());
+        } else {
+            state.sealed = Some(sender);
+        }
+        receiver
+    }
+}
+
+/// Отмечает завершение записи эпохи, в том числе отмененной или завершившейся паникой
+struct EpochGuard(Arc<Epoch>);
+impl Drop for EpochGuard {
+    fn drop(&mut self) {
+        let mut state = self.0.state.lock().unwrap();
+        state.pending -= 1;
+        if state.pending == 0 {
+            if let Some(sender) = state.sealed.take() {
+                let _ = sender.send(());
+            }
+        }
+    }
+}
+
+struct AppenderInner {
+    file: std::fs::File,
+    state: Mutex<AppenderState>,
+}
+
+/// Структура для дозаписи в конец файла с отслеживанием смещений.
+///
+/// Смещение каждой записи резервируется в момент вызова `append`, поэтому записи,
+/// выполняющиеся в пуле параллельно, не пересекаются, а результатом `append` является
+/// смещение начала записанных данных. `sync()` сбрасывает на диск все непрерывно записанные
+/// данные и возвращает границу, до которой данные гарантированно сохранены.
+///
+/// `barrier()` упорядочивает записи: записи, переданные после барьера, начинаются только
+/// после завершения всех предыдущих записей (и, при необходимости, сброса их на диск),
+/// при этом вызывающему коду не нужно дожидаться барьера перед следующими вызовами `append`.
+///
+/// После первой ошибки записи все последующие записи завершаются ошибкой,
+/// чтобы в файле не оставалось "дыр" перед успешно записанными данными.
+#[derive(Clone)]
+pub struct OffsetAppender {
+    cpu_pool: &'static CpuPool,
+    inner: Arc<AppenderInner>,
+}
+
+impl OffsetAppender {
+
+    /// Открывает файл для дозаписи, создавая его при необходимости
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> CpuFuture<OffsetAppender, std::io::Error> {
+        Self::open_with_pool(&DEFAULT_CPU_POOL, path)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<OffsetAppender, std::io::Error> {
+        let path = path.as_ref().to_path_buf();
+        cpu_pool.spawn_fn(move || {
+            let file = std::fs::OpenOptions::new().write(true).create(true).truncate(false).open(long_path(&path))?;
+            let offset = file.metadata()?.len();
+            Ok(OffsetAppender::from_std(cpu_pool, file, offset))
+        })
+    }
+
+    /// Создает структуру из открытого файла, дозапись в который начнется со смещения `offset`
+    pub fn from_std(cpu_pool: &'static CpuPool, file: std::fs::File, offset: u64) -> OffsetAppender {
+        OffsetAppender {
+            cpu_pool,
+            inner: Arc::new(AppenderInner {
+                file,
+                state: Mutex::new(AppenderState {
+                    next: offset,
+                    written: offset,
+                    durable: offset,
+                    completed: BTreeMap::new(),
+                    poisoned: false,
+                    epoch: Epoch::new(),
+                    barrier: None,
+                }),
+            }),
+        }
+    }
+
+    /// Смещение, с которого начнется следующая запись
+    pub fn position(&self) -> u64 {
+        self.inner.state.lock().unwrap().next
+    }
+
+    /// Граница данных, гарантированно сохраненных на диск
+    pub fn durable_position(&self) -> u64 {
+        self.inner.state.lock().unwrap().durable
+    }
+
+    /// Дописывает запись в конец файла. Результатом является смещение начала записи.
+    /// Запись выполняется и при освобождении результата, иначе зарезервированное смещение
+    /// осталось бы "дырой" в файле.
+    pub fn append(&self, data: Bytes) -> AppenderOp {
+        let (offset, guard, barrier) = {
+            let mut state = self.inner.state.lock().unwrap();
+            let offset = state.next;
+            state.next += data.len() as u64;
+            (offset, Epoch::start(&state.epoch), state.barrier.clone())
+        };
+        let inner = self.inner.clone();
+        let write = move || {
+            let _guard = guard;
+            if inner.state.lock().unwrap().poisoned {
+                return Err(poisoned_error());
+            }
+            let result = sys::write_all_at(&inner.file, &data, offset);
+            let mut state = inner.state.lock().unwrap();
+            if let Err(err) = result {
+                state.poisoned = true;
+                return Err(err);
+            }
+            let end = offset + data.len() as u64;
+            if offset == state.written {
+                state.written = end;
+                loop {
+                    let written = state.written;
+                    match state.completed.remove(&written) {
+                        Some(end) => state.written = end,
+                        None => break,
+                    }
+                }
+            } else {
+                state.completed.insert(offset, end);
+            }
+            Ok(offset)
+        };
+        let (sender, receiver) = oneshot::channel();
+        let send = move || {
+            let _ = sender.send(write());
+            Ok::<(), ()>(())
+        };
+        match barrier {
+            Some(barrier) => self.cpu_pool.spawn(barrier.then(move |_| send())).forget(),
+            None => self.cpu_pool.spawn_fn(send).forget(),
+        }
+        AppenderOp { receiver }
+    }
+
+    /// Барьер упорядочивания записей: записи, переданные после него, начнутся только после
+    /// завершения всех ранее переданных записей, а при `sync` - и после сброса их на диск.
+    /// Результатом является граница данных, записанных (при `sync` - сохраненных на диск)
+    /// до барьера. Ошибка сброса на диск, как и ошибка записи, делает дальнейшую запись невозможной.
+    /// Как и `append`, барьер не отменяется при освобождении результата.
+    pub fn barrier(&self, sync: bool) -> AppenderOp {
+        let (finished, previous, done) = {
+            let mut state = self.inner.state.lock().unwrap();
+            let epoch = std::mem::replace(&mut state.epoch, Epoch::new());
+            let (done, barrier) = oneshot::channel();
+            let previous = state.barrier.replace(barrier.shared());
+            (epoch.seal(), previous, done)
+        };
+        // записи закрытой эпохи начались после предыдущего барьера, но сама эпоха может быть пустой
+        let previous = match previous {
+            Some(previous) => Either::A(previous.then(|_| Ok::<(), ()>(()))),
+            None => Either::B(future::ok::<(), ()>(())),
+        };
+        let inner = self.inner.clone();
+        let (sender, receiver) = oneshot::channel();
+        self.cpu_pool.spawn(finished.then(|_| previous).then(move |_| {
+            let result = (|| {
+                let written = {
+                    let state = inner.state.lock().unwrap();
+                    if state.poisoned {
+                        return Err(poisoned_error());
+                    }
+                    state.written
+                };
+                if sync {
+                    retry(|| inner.file.sync_data())?;
+                    let mut state = inner.state.lock().unwrap();
+                    if written > state.durable {
+                        state.durable = written;
+                    }
+                }
+                Ok(written)
+            })();
+            if result.is_err() {
+                inner.state.lock().unwrap().poisoned = true;
+            }
+            let _ = done.send(());
+            let _ = sender.send(result);
+            Ok::<(), ()>(())
+        })).forget();
+        AppenderOp { receiver }
+    }
+
+    /// Сбрасывает на диск непрерывно записанные данные.
+    /// Результатом является граница данных, гарантированно сохраненных на диск.
+    pub fn sync(&self) -> CpuFuture<u64, std::io::Error> {
+        let inner = self.inner.clone();
+        self.cpu_pool.spawn_fn(move || {
+            let written = inner.state.lock().unwrap().written;
+            sys::retry(|| inner.file.sync_data())?;
+            let mut state = inner.state.lock().unwrap();
+            if written > state.durable {
+                state.durable = written;
+            }
+            Ok(state.durable)
+        })
+    }
+}
+impl std::fmt::Debug for OffsetAppender {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("OffsetAppender").finish()
+    }
+}
+
+fn poisoned_error() -> std::io::Error {
+    std::io::Error::other("appender is poisoned by a previous write error")
+}
+
+
+// AppenderOp
+
+/// Результат `append` или `barrier` структуры `OffsetAppender`: смещение начала записи
+/// или граница данных, записанных до барьера
+pub struct AppenderOp {
+    receiver: oneshot::Receiver<std::io::Result<u64>>,
+}
+impl Future for AppenderOp {
+    type Item = u64;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(result)) => result.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => Err(std::io::Error::other("appender operation aborted")),
+        }
+    }
+}
+impl std::fmt::Debug for AppenderOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AppenderOp").finish()
+    }
+}
@@ -0,0 +1,184 @@
+use futures::{Poll, Future, Async, AsyncSink, Sink, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::AsyncFileSink;
+use pool_registry;
+use sys::{long_path, retry, sync_parent_dir};
+
+static ATOMIC_WRITE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn remove_temporary(temporary: &Path) {
+    let _ = std::fs::remove_file(long_path(temporary));
+}
+
+
+// WriteAtomic
+
+/// Future атомарной замены файла содержимым потока.
+///
+/// Блоки потока записываются во временный файл рядом с целевым, после чего временный файл
+/// сбрасывается на диск, переименовывается в целевой и сбрасывается запись о нем
+/// в родительской директории. Читатели видят либо старое, либо новое содержимое целиком,
+/// а после сбоя питания на диске остается одно из них.
+///
+/// Если future завершается с ошибкой или уничтожается до завершения, временный файл
+/// удаляется в пуле потоков. Результат - количество записанных байт.
+pub struct WriteAtomic<S> {
+    stream: S,
+    cpu_pool: &'static CpuPool,
+    path: PathBuf,
+    temporary: PathBuf,
+    opening: Option<CpuFuture<std::fs::File, std::io::Error>>,
+    sink: Option<AsyncFileSink>,
+    committing: Option<CpuFuture<(), std::io::Error>>,
+    finished: bool,
+    done: bool,
+    written: u64,
+}
+
+/// Атомарно заменяет (или создает) файл `path` содержимым `stream`
+/// в пуле потоков, выбранном по пути (см. `PoolRegistry`)
+#[inline]
+pub fn write_atomic<S, P>(path: P, stream: S) -> WriteAtomic<S>
+    where S: Stream<Item = Bytes>,
+          S::Error: From<std::io::Error>,
+          P: AsRef<Path>
+{
+    write_atomic_with_pool(pool_registry::pool_for(path.as_ref()), path, stream)
+}
+
+pub fn write_atomic_with_pool<S, P>(cpu_pool: &'static CpuPool, path: P, stream: S) -> WriteAtomic<S>
+    where S: Stream<Item = Bytes>,
+          S::Error: From<std::io::Error>,
+          P: AsRef<Path>
+{
+    let path: PathBuf = path.as_ref().into();
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let temporary = path.with_file_name(format!(
+        ".{}.tmp-{}-{}",
+        name,
+        std::process::id(),
+        ATOMIC_WRITE_COUNTER.fetch_add(1, Ordering::SeqCst),
+    ));
+    let opening = {
+        let path = path.clone();
+        let temporary = temporary.clone();
+        cpu_pool.spawn_fn(move || {
+            if path.file_name().is_none() {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name"));
+            }
+            retry(|| std::fs::OpenOptions::new().write(true).create_new(true).open(long_path(&temporary)))
+        })
+    };
+    WriteAtomic {
+        stream,
+        cpu_pool,
+        path,
+        temporary,
+        opening: Some(opening),
+        sink: None,
+        committing: None,
+        finished: false,
+        done: false,
+        written: 0,
+    }
+}
+
+impl<S> WriteAtomic<S> {
+
+    /// Путь временного файла
+    #[inline]
+    pub fn temporary_path(&self) -> &Path {
+        &self.temporary
+    }
+}
+impl<S> Future for WriteAtomic<S>
+    where S: Stream<Item = Bytes>,
+          S::Error: From<std::io::Error>
+{
+    type Item = u64;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // завершенные `CpuFuture` не опрашиваются повторно (в том числе в `drop`)
+        if let Some(mut opening) = self.opening.take() {
+            match opening.poll()? {
+                Async::Ready(file) => self.sink = Some(AsyncFileSink::from_std(self.cpu_pool, file)),
+                Async::NotReady => {
+                    self.opening = Some(opening);
+                    return Ok(Async::NotReady);
+                },
+            }
+        }
+        if self.committing.is_none() {
+            {
+                let sink = self.sink.as_mut().expect("poll a WriteAtomic after it's done");
+                loop {
+                    if self.finished {
+                        // сбрасывает данные на диск
+                        try_ready!(sink.poll_close());
+                        break;
+                    }
+                    try_ready!(sink.poll_ready());
+                    match try_ready!(self.stream.poll()) {
+                        Some(chunk) => {
+                            self.written += chunk.len() as u64;
+                            if let AsyncSink::NotReady(_) = sink.start_send(chunk)? {
+                                unreachable!("sink is ready after `poll_ready`");
+                            }
+                        },
+                        None => self.finished = true,
+                    }
+                }
+            }
+            self.sink = None;
+            let path = self.path.clone();
+            let temporary = self.temporary.clone();
+            self.committing = Some(self.cpu_pool.spawn_fn(move || {
+                retry(|| std::fs::rename(long_path(&temporary), long_path(&path)))?;
+                sync_parent_dir(&path)
+            }));
+        }
+        let mut committing = self.committing.take().unwrap();
+        match committing.poll()? {
+            Async::Ready(()) => {
+                self.done = true;
+                Ok(Async::Ready(self.written))
+            },
+            Async::NotReady => {
+                self.committing = Some(committing);
+                Ok(Async::NotReady)
+            },
+        }
+    }
+}
+impl<S> Drop for WriteAtomic<S> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        self.sink = None;
+        let temporary = self.temporary.clone();
+        // временный файл удаляется после завершения начатой операции
+        let pending: Box<dyn Future<Item = (), Error = std::io::Error> + Send> = match (self.opening.take(), self.committing.take()) {
+            (Some(opening), _) => Box::new(opening.map(|_| ())),
+            (None, Some(committing)) => Box::new(committing),
+            (None, None) => Box::new(futures::future::ok(())),
+        };
+        self.cpu_pool.spawn(pending.then(move |_| {
+            remove_temporary(&temporary);
+            Ok::<(), ()>(())
+        })).forget();
+    }
+}
+impl<S> std::fmt::Debug for WriteAtomic<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("WriteAtomic")
+            .field("path", &self.path)
+            .field("written", &self.written)
+            .field("done", &self.done)
+            .finish()
+    }
+}
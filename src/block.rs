@@ -0,0 +1,259 @@
+use futures::{Poll, Async, AsyncSink, Sink, Stream, StartSend};
+use bytes::{Bytes, BytesMut, BufMut};
+
+/// Размер блока по умолчанию
+pub static DEFAULT_BLOCK_SIZE: usize = 4 * 1024;
+
+static BLOCK_MAGIC: [u8; 4] = *b"AFBK";
+static BLOCK_HEADER_SIZE: usize = 20;
+
+
+// Block
+
+/// Причина, по которой блок признан поврежденным
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockError {
+    /// Заголовок блока не распознан (например, блок не был записан или перезаписан чужими данными)
+    Header,
+    /// Контрольная сумма блока не совпадает
+    Checksum,
+    /// Порядковый номер блока не следует за номером предыдущего блока
+    Sequence { expected: u64, found: u64 },
+    /// Блок в конце файла записан не полностью
+    Torn,
+}
+
+/// Результат чтения очередного блока
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    Data {
+        /// Номер блока в файле
+        index: u64,
+        sequence: u64,
+        payload: Bytes,
+    },
+    Corrupt {
+        index: u64,
+        error: BlockError,
+    },
+}
+
+fn encode_block(buf: &mut BytesMut, block_size: usize, sequence: u64, payload: &[u8]) {
+    let start = buf.len();
+    buf.reserve(block_size);
+    buf.put_slice(&BLOCK_MAGIC);
+    buf.put_slice(&[0u8; 4]);
+    buf.put_slice(&sequence.to_le_bytes());
+    buf.put_slice(&(payload.len() as u32).to_le_bytes());
+    buf.put_slice(payload);
+    let padding = block_size - BLOCK_HEADER_SIZE - payload.len();
+    buf.put_slice(&vec![0u8; padding]);
+    let crc = crc32(&buf[start + 8..start + block_size]);
+    buf[start + 4..start + 8].copy_from_slice(&crc.to_le_bytes());
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+fn decode_block(block: &[u8]) -> Result<(u64, usize), BlockError> {
+    if block[..4] != BLOCK_MAGIC {
+        return Err(BlockError::Header);
+    }
+    let mut crc = [0u8; 4];
+    crc.copy_from_slice(&block[4..8]);
+    if u32::from_le_bytes(crc) != crc32(&block[8..]) {
+        return Err(BlockError::Checksum);
+    }
+    let mut sequence = [0u8; 8];
+    sequence.copy_from_slice(&block[8..16]);
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&block[16..20]);
+    let len = u32::from_le_bytes(len) as usize;
+    if len > block.len() - BLOCK_HEADER_SIZE {
+        return Err(BlockError::Header);
+    }
+    Ok((u64::from_le_bytes(sequence), len))
+}
+
+
+// BlockSink
+
+/// Адаптер, записывающий данные в формате блочного файла:
+/// блоки фиксированного размера с порядковым номером и контрольной суммой CRC32.
+///
+/// Каждый элемент занимает один или несколько блоков
+/// (если не помещается в полезную нагрузку одного блока).
+pub struct BlockSink<S> {
+    inner: S,
+    block_size: usize,
+    sequence: u64,
+}
+impl<S> BlockSink<S> {
+
+    #[inline]
+    pub fn new(inner: S) -> BlockSink<S> {
+        Self::with_block_size(inner, DEFAULT_BLOCK_SIZE, 0)
+    }
+
+    /// Создает адаптер с заданным размером блока и порядковым номером следующего блока
+    /// (для дозаписи в существующий блочный файл)
+    pub fn with_block_size(inner: S, block_size: usize, sequence: u64) -> BlockSink<S> {
+        assert!(block_size > BLOCK_HEADER_SIZE, "block size is too small");
+        BlockSink {
+            inner,
+            block_size,
+            sequence,
+        }
+    }
+
+    /// Порядковый номер следующего блока
+    #[inline]
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+impl<S> Sink for BlockSink<S>
+    where S: Sink<SinkItem = Bytes, SinkError = std::io::Error>
+{
+    type SinkItem = Bytes;
+    type SinkError = std::io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let capacity = self.block_size - BLOCK_HEADER_SIZE;
+        let count = std::cmp::max(1, item.len().div_ceil(capacity));
+        let mut buf = BytesMut::with_capacity(count * self.block_size);
+        let mut sequence = self.sequence;
+        if item.is_empty() {
+            encode_block(&mut buf, self.block_size, sequence, &[]);
+            sequence += 1;
+        }
+        for payload in item.chunks(capacity) {
+            encode_block(&mut buf, self.block_size, sequence, payload);
+            sequence += 1;
+        }
+        match self.inner.start_send(buf.freeze())? {
+            AsyncSink::Ready => {
+                self.sequence = sequence;
+                Ok(AsyncSink::Ready)
+            },
+            AsyncSink::NotReady(_) => Ok(AsyncSink::NotReady(item)),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.poll_complete()
+    }
+}
+impl<S> std::fmt::Debug for BlockSink<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("BlockSink")
+            .field("block_size", &self.block_size)
+            .field("sequence", &self.sequence)
+            .finish()
+    }
+}
+
+
+// BlockStream
+
+/// Адаптер, читающий блочный файл из потока фрагментов (например, `AsyncFileStream`).
+///
+/// Поврежденные блоки не прерывают поток, а сообщаются отдельными элементами `Block::Corrupt`.
+pub struct BlockStream<S> {
+    inner: S,
+    block_size: usize,
+    buf: BytesMut,
+    index: u64,
+    /// Номер и порядковый номер последнего корректного блока
+    last: Option<(u64, u64)>,
+    done: bool,
+}
+impl<S> BlockStream<S> {
+
+    #[inline]
+    pub fn new(inner: S) -> BlockStream<S> {
+        Self::with_block_size(inner, DEFAULT_BLOCK_SIZE)
+    }
+
+    pub fn with_block_size(inner: S, block_size: usize) -> BlockStream<S> {
+        assert!(block_size > BLOCK_HEADER_SIZE, "block size is too small");
+        BlockStream {
+            inner,
+            block_size,
+            buf: BytesMut::new(),
+            index: 0,
+            last: None,
+            done: false,
+        }
+    }
+
+    fn next_block(&mut self) -> Block {
+        let index = self.index;
+        self.index += 1;
+        let block = self.buf.split_to(self.block_size).freeze();
+        match decode_block(&block) {
+            Ok((sequence, len)) => {
+                // поврежденные блоки между корректными тоже занимают порядковые номера
+                let expected = self.last.map_or(sequence, |(last_index, last_sequence)| {
+                    last_sequence + (index - last_index)
+                });
+                self.last = Some((index, sequence));
+                if sequence != expected {
+                    return Block::Corrupt {
+                        index,
+                        error: BlockError::Sequence { expected, found: sequence },
+                    };
+                }
+                Block::Data {
+                    index,
+                    sequence,
+                    payload: block.slice(BLOCK_HEADER_SIZE, BLOCK_HEADER_SIZE + len),
+                }
+            },
+            Err(error) => Block::Corrupt { index, error },
+        }
+    }
+}
+impl<S> Stream for BlockStream<S>
+    where S: Stream<Item = Bytes, Error = std::io::Error>
+{
+    type Item = Block;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if self.buf.len() >= self.block_size {
+                return Ok(Async::Ready(Some(self.next_block())));
+            }
+            if self.done {
+                if self.buf.is_empty() {
+                    return Ok(Async::Ready(None));
+                }
+                self.buf.clear();
+                let index = self.index;
+                self.index += 1;
+                return Ok(Async::Ready(Some(Block::Corrupt { index, error: BlockError::Torn })));
+            }
+            match try_ready!(self.inner.poll()) {
+                Some(chunk) => self.buf.extend_from_slice(&chunk),
+                None => self.done = true,
+            }
+        }
+    }
+}
+impl<S> std::fmt::Debug for BlockStream<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("BlockStream")
+            .field("block_size", &self.block_size)
+            .field("index", &self.index)
+            .finish()
+    }
+}
@@ -0,0 +1,154 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::io::Seek;
+use std::path::{Path, PathBuf};
+use super::{AsyncFileStream, DEFAULT_CPU_POOL, DEFAULT_BUFFER_SIZE};
+use file_kind::{unsupported_kind, FileKind, FileKindExt};
+use sys::{long_path, retry};
+
+/// Размер сектора, если устройство его не сообщает
+static DEFAULT_SECTOR_SIZE: u32 = 512;
+
+/// `_IOR(0x12, 114, size_t)`: размер блочного устройства в байтах
+#[cfg(target_os = "linux")]
+fn blkgetsize64() -> libc::c_ulong {
+    (2 << 30) | ((std::mem::size_of::<usize>() as libc::c_ulong) << 16) | (0x12 << 8) | 114
+}
+
+/// `_IO(0x12, 104)`: логический размер сектора
+#[cfg(target_os = "linux")]
+static BLKSSZGET: libc::c_ulong = 0x1268;
+
+#[cfg(target_os = "linux")]
+fn device_geometry(file: &mut std::fs::File) -> std::io::Result<(u64, u32)> {
+    use std::os::unix::io::AsRawFd;
+    let mut size: u64 = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), blkgetsize64() as _, &mut size as *mut u64) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let mut sector_size: libc::c_int = 0;
+    if unsafe { libc::ioctl(file.as_raw_fd(), BLKSSZGET as _, &mut sector_size as *mut libc::c_int) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok((size, if sector_size > 0 { sector_size as u32 } else { DEFAULT_SECTOR_SIZE }))
+}
+
+/// На других системах размер определяется перемещением в конец устройства
+#[cfg(not(target_os = "linux"))]
+fn device_geometry(file: &mut std::fs::File) -> std::io::Result<(u64, u32)> {
+    let size = file.seek(std::io::SeekFrom::End(0))?;
+    file.seek(std::io::SeekFrom::Start(0))?;
+    Ok((size, DEFAULT_SECTOR_SIZE))
+}
+
+fn open_device(path: &Path) -> std::io::Result<(std::fs::File, FileKind, u64, u32)> {
+    let mut file = retry(|| std::fs::File::open(long_path(path)))?;
+    let metadata = file.metadata()?;
+    match metadata.kind() {
+        FileKind::BlockDevice => {
+            let (size, sector_size) = device_geometry(&mut file)?;
+            Ok((file, FileKind::BlockDevice, size, sector_size))
+        },
+        FileKind::File => Ok((file, FileKind::File, metadata.len(), DEFAULT_SECTOR_SIZE)),
+        kind => Err(unsupported_kind(kind, "raw device reading")),
+    }
+}
+
+
+// BlockDevice
+
+/// Блочное устройство (или его образ в обычном файле), открытое для чтения.
+///
+/// Размер запрашивается у устройства (`BLKGETSIZE64` на Linux), так как в метаданных
+/// он нулевой. Читаемые диапазоны должны быть выровнены по сектору, а их длина - кратна
+/// размеру сектора (кроме диапазона до конца устройства), поэтому они подходят и для
+/// чтения в обход кэша.
+pub struct BlockDevice {
+    cpu_pool: &'static CpuPool,
+    path: PathBuf,
+    kind: FileKind,
+    size: u64,
+    sector_size: u32,
+}
+impl BlockDevice {
+
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> CpuFuture<BlockDevice, std::io::Error> {
+        Self::open_with_pool(&DEFAULT_CPU_POOL, path)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<BlockDevice, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let (_, kind, size, sector_size) = open_device(&path)?;
+            Ok(BlockDevice { cpu_pool, path, kind, size, sector_size })
+        })
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `FileKind::BlockDevice` или `FileKind::File` для образа
+    #[inline]
+    pub fn kind(&self) -> FileKind {
+        self.kind
+    }
+
+    /// Размер устройства в байтах
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Логический размер сектора
+    #[inline]
+    pub fn sector_size(&self) -> u32 {
+        self.sector_size
+    }
+
+    fn check_range(&self, offset: u64, len: u64) -> std::io::Result<()> {
+        let sector_size = self.sector_size as u64;
+        if !offset.is_multiple_of(sector_size) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("offset {} is not aligned to sector size {}", offset, sector_size)));
+        }
+        let end = offset.checked_add(len).filter(|&end| end <= self.size)
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "range is out of device bounds"))?;
+        if !len.is_multiple_of(sector_size) && end != self.size {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("length {} is not a multiple of sector size {}", len, sector_size)));
+        }
+        Ok(())
+    }
+
+    /// Поток `len` байт устройства начиная с `offset`; устройство открывается заново,
+    /// поэтому несколько потоков читают независимо. Размер блоков кратен размеру сектора.
+    pub fn stream(&self, offset: u64, len: u64) -> CpuFuture<AsyncFileStream, std::io::Error> {
+        let check = self.check_range(offset, len);
+        let cpu_pool = self.cpu_pool;
+        let path = self.path.clone();
+        let sector_size = self.sector_size as usize;
+        self.cpu_pool.spawn_fn(move || {
+            check?;
+            let (mut file, _, _, _) = open_device(&path)?;
+            file.seek(std::io::SeekFrom::Start(offset))?;
+            let buffer_size = std::cmp::max(sector_size, DEFAULT_BUFFER_SIZE / sector_size * sector_size);
+            Ok(AsyncFileStream::from_std(cpu_pool, file, buffer_size).limit(len))
+        })
+    }
+
+    /// Поток всего содержимого устройства
+    #[inline]
+    pub fn stream_all(&self) -> CpuFuture<AsyncFileStream, std::io::Error> {
+        self.stream(0, self.size)
+    }
+}
+impl std::fmt::Debug for BlockDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("BlockDevice")
+            .field("path", &self.path)
+            .field("kind", &self.kind)
+            .field("size", &self.size)
+            .field("sector_size", &self.sector_size)
+            .finish()
+    }
+}
@@ -0,0 +1,217 @@
+use futures::{Poll, Async, Stream};
+use futures::task::{self, Task};
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use super::AsyncFileStream;
+
+/// Количество блоков, которые по умолчанию хранятся для отстающих подписчиков
+static DEFAULT_BROADCAST_CAPACITY: usize = 16;
+
+/// Поведение при заполнении буфера из-за самого медленного подписчика
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    /// Чтение источника приостанавливается, пока самый медленный подписчик не заберет блок
+    Wait,
+    /// Самый старый блок вытесняется, а не успевшие его получить подписчики отключаются с ошибкой
+    Disconnect,
+}
+
+struct BroadcastInner<S> {
+    source: S,
+    buffer: VecDeque<Bytes>,
+    /// Порядковый номер первого блока в буфере
+    base: u64,
+    capacity: usize,
+    policy: LagPolicy,
+    /// Номер следующего блока для каждого подписчика
+    subscribers: HashMap<usize, u64>,
+    next_id: usize,
+    waiters: Vec<Task>,
+    /// Завершение источника: `Some(None)` - конец потока, `Some(Some(..))` - ошибка
+    end: Option<Option<(std::io::ErrorKind, String)>>,
+}
+impl<S> BroadcastInner<S> {
+    fn notify_waiters(&mut self) {
+        for task in self.waiters.drain(..) {
+            task.notify();
+        }
+    }
+
+    /// Удаляет из буфера блоки, полученные всеми подписчиками
+    fn trim(&mut self) {
+        let end = self.base + self.buffer.len() as u64;
+        let min = self.subscribers.values().cloned().min().unwrap_or(end);
+        if min <= self.base {
+            return;
+        }
+        while self.base < min {
+            self.buffer.pop_front();
+            self.base += 1;
+        }
+        self.notify_waiters();
+    }
+}
+
+fn lagged_error() -> std::io::Error {
+    std::io::Error::other("subscriber lagged behind the broadcast buffer")
+}
+
+
+// Broadcast
+
+/// Раздача одного потока (например, `AsyncFileStream` часто запрашиваемого файла)
+/// нескольким подписчикам: каждый блок читается с диска один раз и передается всем.
+///
+/// Источник опрашивается из задач подписчиков, а прочитанные блоки хранятся в буфере
+/// на `capacity` блоков, пока их не получат все подписчики. Подписчик получает блоки,
+/// прочитанные после его подписки, поэтому для раздачи файла целиком подписчиков следует
+/// создать до начала чтения. При политике `LagPolicy::Wait` подписчик, который не опрашивается,
+/// останавливает раздачу для всех остальных.
+pub struct Broadcast<S = AsyncFileStream> {
+    inner: Arc<Mutex<BroadcastInner<S>>>,
+}
+impl<S: Stream<Item = Bytes, Error = std::io::Error>> Broadcast<S> {
+
+    pub fn new(source: S) -> Broadcast<S> {
+        Broadcast {
+            inner: Arc::new(Mutex::new(BroadcastInner {
+                source,
+                buffer: VecDeque::new(),
+                base: 0,
+                capacity: DEFAULT_BROADCAST_CAPACITY,
+                policy: LagPolicy::Wait,
+                subscribers: HashMap::new(),
+                next_id: 0,
+                waiters: Vec::new(),
+                end: None,
+            })),
+        }
+    }
+
+    /// Задает наибольшее количество блоков в буфере
+    #[inline]
+    pub fn capacity(self, capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be greater than zero");
+        self.inner.lock().unwrap().capacity = capacity;
+        self
+    }
+
+    /// Задает поведение при заполнении буфера
+    #[inline]
+    pub fn lag_policy(self, policy: LagPolicy) -> Self {
+        self.inner.lock().unwrap().policy = policy;
+        self
+    }
+
+    /// Создает подписчика, получающего все следующие блоки источника
+    pub fn subscribe(&self) -> Subscription<S> {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let position = inner.base + inner.buffer.len() as u64;
+        inner.subscribers.insert(id, position);
+        Subscription {
+            id,
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Количество подписчиков
+    pub fn subscribers(&self) -> usize {
+        self.inner.lock().unwrap().subscribers.len()
+    }
+}
+impl<S> std::fmt::Debug for Broadcast<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("Broadcast")
+            .field("capacity", &inner.capacity)
+            .field("policy", &inner.policy)
+            .field("subscribers", &inner.subscribers.len())
+            .field("buffered", &inner.buffer.len())
+            .finish()
+    }
+}
+
+
+// Subscription
+
+/// Поток блоков, получаемых подписчиком `Broadcast`
+pub struct Subscription<S = AsyncFileStream> {
+    id: usize,
+    inner: Arc<Mutex<BroadcastInner<S>>>,
+}
+impl<S: Stream<Item = Bytes, Error = std::io::Error>> Stream for Subscription<S> {
+    type Item = Bytes;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut position = match inner.subscribers.get(&self.id) {
+            Some(&position) => position,
+            // подписчик был отключен из-за отставания
+            None => return Ok(Async::Ready(None)),
+        };
+        loop {
+            if position < inner.base {
+                inner.subscribers.remove(&self.id);
+                inner.trim();
+                return Err(lagged_error());
+            }
+            let index = (position - inner.base) as usize;
+            if let Some(chunk) = inner.buffer.get(index).cloned() {
+                position += 1;
+                inner.subscribers.insert(self.id, position);
+                inner.trim();
+                return Ok(Async::Ready(Some(chunk)));
+            }
+            if let Some(ref end) = inner.end {
+                return match *end {
+                    Some((kind, ref message)) => Err(std::io::Error::new(kind, message.clone())),
+                    None => Ok(Async::Ready(None)),
+                };
+            }
+            if inner.buffer.len() >= inner.capacity {
+                match inner.policy {
+                    LagPolicy::Wait => {
+                        inner.waiters.push(task::current());
+                        return Ok(Async::NotReady);
+                    },
+                    LagPolicy::Disconnect => {
+                        inner.buffer.pop_front();
+                        inner.base += 1;
+                        // отключенные подписчики узнают об этом при следующем опросе
+                        inner.notify_waiters();
+                    },
+                }
+            }
+            match inner.source.poll() {
+                Ok(Async::Ready(Some(chunk))) => inner.buffer.push_back(chunk),
+                Ok(Async::Ready(None)) => inner.end = Some(None),
+                Err(error) => inner.end = Some(Some((error.kind(), error.to_string()))),
+                Ok(Async::NotReady) => {
+                    inner.waiters.push(task::current());
+                    return Ok(Async::NotReady);
+                },
+            }
+            inner.notify_waiters();
+        }
+    }
+}
+impl<S> Drop for Subscription<S> {
+    fn drop(&mut self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.subscribers.remove(&self.id);
+        inner.trim();
+        // источник мог опрашиваться из задачи этого подписчика: его продолжит опрашивать другой
+        inner.notify_waiters();
+    }
+}
+impl<S> std::fmt::Debug for Subscription<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Subscription")
+            .field("id", &self.id)
+            .finish()
+    }
+}
@@ -0,0 +1,65 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::ffi::OsStr;
+use std::path::{Component, Path, PathBuf};
+use super::DEFAULT_CPU_POOL;
+use sys::long_path;
+
+fn fold_case(name: &OsStr) -> String {
+    name.to_string_lossy().to_lowercase()
+}
+
+/// Находит в каталоге `dir` запись с именем `name` без учета регистра.
+/// При нескольких совпадениях выбирается первое в лексикографическом порядке.
+fn find_entry(dir: &Path, name: &OsStr) -> std::io::Result<Option<PathBuf>> {
+    let candidate = dir.join(name);
+    if std::fs::symlink_metadata(long_path(&candidate)).is_ok() {
+        return Ok(Some(candidate));
+    }
+    let folded = fold_case(name);
+    let mut found: Option<PathBuf> = None;
+    for entry in std::fs::read_dir(long_path(dir))? {
+        let entry = entry?;
+        if fold_case(&entry.file_name()) != folded {
+            continue;
+        }
+        let path = entry.path();
+        if found.as_ref().is_none_or(|found| path < *found) {
+            found = Some(path);
+        }
+    }
+    Ok(found)
+}
+
+/// Разрешает путь `name` внутри каталога `dir` без учета регистра имен,
+/// независимо от чувствительности файловой системы к регистру.
+///
+/// Каждый компонент пути сначала проверяется как есть, а при отсутствии ищется
+/// среди записей каталога; поиск выполняется в пуле потоков.
+/// Результатом является путь к найденной записи или `None`, если запись не найдена.
+#[inline]
+pub fn find_case_insensitive<D: AsRef<Path>, N: AsRef<Path>>(dir: D, name: N) -> CpuFuture<Option<PathBuf>, std::io::Error> {
+    find_case_insensitive_with_pool(&DEFAULT_CPU_POOL, dir, name)
+}
+
+pub fn find_case_insensitive_with_pool<D: AsRef<Path>, N: AsRef<Path>>(cpu_pool: &'static CpuPool, dir: D, name: N) -> CpuFuture<Option<PathBuf>, std::io::Error> {
+    let dir: PathBuf = dir.as_ref().into();
+    let name: PathBuf = name.as_ref().into();
+    cpu_pool.spawn_fn(move || {
+        let mut path = dir;
+        for component in name.components() {
+            match component {
+                Component::Normal(name) => {
+                    path = match find_entry(&path, name) {
+                        Ok(Some(path)) => path,
+                        Ok(None) => return Ok(None),
+                        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                        Err(error) => return Err(error),
+                    };
+                },
+                Component::CurDir => {},
+                component => path.push(component.as_os_str()),
+            }
+        }
+        Ok(Some(path))
+    })
+}
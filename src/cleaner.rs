@@ -0,0 +1,170 @@
+use futures::{Poll, Future, Async, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use super::DEFAULT_CPU_POOL;
+use walk::{walk_with_pool, WalkStream};
+use sys::{long_path, retry};
+
+/// Почему файл удаляется
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CleanupReason {
+    /// Файл старше `Cleaner::max_age`
+    Age,
+    /// Файл не поместился в `Cleaner::max_size` среди более новых файлов
+    Size,
+}
+
+/// Действие, выполненное (или, в режиме `dry_run`, только запланированное) `Cleaner`
+#[derive(Debug, Clone)]
+pub struct CleanupAction {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Время изменения файла
+    pub modified: SystemTime,
+    pub reason: CleanupReason,
+    /// Был ли файл удален (`false` в режиме `dry_run`)
+    pub removed: bool,
+}
+
+
+// Cleaner
+
+/// Очистка каталога по правилам хранения: удаляются файлы старше заданного возраста
+/// и самые старые файлы, не помещающиеся в заданный суммарный размер.
+///
+/// Дерево каталога обходится полностью (как в `walk`), после чего файлы удаляются по одному
+/// в пуле потоков, начиная с самых старых, а каждое действие передается в поток.
+/// Каталоги не удаляются. Ошибка удаления файла передается как ошибка потока,
+/// после чего очистку можно продолжить.
+pub struct Cleaner {
+    cpu_pool: &'static CpuPool,
+    root: PathBuf,
+    max_age: Option<Duration>,
+    max_size: Option<u64>,
+    dry_run: bool,
+    walk: Option<WalkStream>,
+    files: Vec<(PathBuf, u64, SystemTime)>,
+    actions: VecDeque<CleanupAction>,
+    removing: Option<CpuFuture<CleanupAction, std::io::Error>>,
+}
+impl Cleaner {
+
+    /// Очистка дерева каталога `root`; без заданных правил файлы не удаляются
+    #[inline]
+    pub fn new<P: AsRef<Path>>(root: P) -> Cleaner {
+        Self::new_with_pool(&DEFAULT_CPU_POOL, root)
+    }
+
+    pub fn new_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, root: P) -> Cleaner {
+        Cleaner {
+            cpu_pool,
+            root: root.as_ref().into(),
+            max_age: None,
+            max_size: None,
+            dry_run: false,
+            walk: Some(walk_with_pool(cpu_pool, root)),
+            files: Vec::new(),
+            actions: VecDeque::new(),
+            removing: None,
+        }
+    }
+
+    /// Удалять файлы, измененные раньше, чем `max_age` назад
+    #[inline]
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Оставлять самые новые файлы суммарным размером не больше `max_size` байт
+    #[inline]
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Только передавать в поток действия, не удаляя файлы
+    #[inline]
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Составляет список удалений: от новых файлов к старым, затем в обратном порядке
+    fn plan(&mut self) {
+        let mut files = std::mem::take(&mut self.files);
+        files.sort_by_key(|file| std::cmp::Reverse(file.2));
+        let cutoff = self.max_age.and_then(|max_age| SystemTime::now().checked_sub(max_age));
+        let mut kept = 0u64;
+        let mut actions = Vec::new();
+        for (path, size, modified) in files {
+            let reason = if cutoff.is_some_and(|cutoff| modified < cutoff) {
+                CleanupReason::Age
+            } else if self.max_size.is_some_and(|max_size| kept + size > max_size) {
+                CleanupReason::Size
+            } else {
+                kept += size;
+                continue;
+            };
+            actions.push(CleanupAction {
+                path,
+                size,
+                modified,
+                reason,
+                removed: false,
+            });
+        }
+        self.actions = actions.into_iter().rev().collect();
+    }
+}
+impl Stream for Cleaner {
+    type Item = CleanupAction;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Some(ref mut walk) = self.walk {
+            while let Some(entry) = try_ready!(walk.poll()) {
+                if entry.metadata.is_file() {
+                    let modified = entry.metadata.modified()?;
+                    self.files.push((entry.path, entry.metadata.len(), modified));
+                }
+            }
+        }
+        if self.walk.take().is_some() {
+            self.plan();
+        }
+        if let Some(ref mut removing) = self.removing {
+            let result = removing.poll();
+            if let Ok(Async::NotReady) = result {
+                return Ok(Async::NotReady);
+            }
+            self.removing = None;
+            return result.map(|action| action.map(Some));
+        }
+        let mut action = match self.actions.pop_front() {
+            Some(action) => action,
+            None => return Ok(Async::Ready(None)),
+        };
+        if self.dry_run {
+            return Ok(Async::Ready(Some(action)));
+        }
+        self.removing = Some(self.cpu_pool.spawn_fn(move || {
+            retry(|| std::fs::remove_file(long_path(&action.path)))?;
+            action.removed = true;
+            Ok(action)
+        }));
+        self.poll()
+    }
+}
+impl std::fmt::Debug for Cleaner {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Cleaner")
+            .field("root", &self.root)
+            .field("max_age", &self.max_age)
+            .field("max_size", &self.max_size)
+            .field("dry_run", &self.dry_run)
+            .finish()
+    }
+}
@@ -0,0 +1,272 @@
+use futures::{Poll, Future, Async, Stream};
+use futures::future::Join;
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use super::DEFAULT_CPU_POOL;
+use sys;
+use file_kind::{unsupported_kind, FileKind, FileKindExt};
+use sys::long_path;
+
+static DEFAULT_COMPARE_CHUNK_SIZE: usize = 64 * 1024;
+
+struct Opened {
+    a: Arc<std::fs::File>,
+    b: Arc<std::fs::File>,
+    len_a: u64,
+    len_b: u64,
+}
+
+fn open_pair(cpu_pool: &'static CpuPool, a: PathBuf, b: PathBuf) -> CpuFuture<Opened, std::io::Error> {
+    cpu_pool.spawn_fn(move || {
+        let a = std::fs::File::open(long_path(&a))?;
+        let b = std::fs::File::open(long_path(&b))?;
+        let (metadata_a, metadata_b) = (a.metadata()?, b.metadata()?);
+        // размер специальных файлов неизвестен, а чтение устройства может не завершаться
+        for metadata in &[&metadata_a, &metadata_b] {
+            if metadata.kind() != FileKind::File {
+                return Err(unsupported_kind(metadata.kind(), "comparison"));
+            }
+        }
+        let len_a = metadata_a.len();
+        let len_b = metadata_b.len();
+        Ok(Opened {
+            a: Arc::new(a),
+            b: Arc::new(b),
+            len_a,
+            len_b,
+        })
+    })
+}
+
+fn read_chunk(cpu_pool: &'static CpuPool, file: &Arc<std::fs::File>, offset: u64, len: usize) -> CpuFuture<Bytes, std::io::Error> {
+    let file = file.clone();
+    cpu_pool.spawn_fn(move || {
+        let mut buf = vec![0u8; len];
+        let size = sys::read_full_at(&file, &mut buf, offset)?;
+        buf.truncate(size);
+        Ok(Bytes::from(buf))
+    })
+}
+
+/// Последовательно читает одинаковые фрагменты двух файлов: оба фрагмента читаются параллельно
+struct ChunkPairs {
+    cpu_pool: &'static CpuPool,
+    chunk_size: usize,
+    opening: Option<CpuFuture<Opened, std::io::Error>>,
+    opened: Option<Opened>,
+    offset: u64,
+    reading: Option<Join<CpuFuture<Bytes, std::io::Error>, CpuFuture<Bytes, std::io::Error>>>,
+}
+impl ChunkPairs {
+    fn new(cpu_pool: &'static CpuPool, a: PathBuf, b: PathBuf) -> ChunkPairs {
+        ChunkPairs {
+            cpu_pool,
+            chunk_size: DEFAULT_COMPARE_CHUNK_SIZE,
+            opening: Some(open_pair(cpu_pool, a, b)),
+            opened: None,
+            offset: 0,
+            reading: None,
+        }
+    }
+
+    fn poll_opened(&mut self) -> Poll<(u64, u64), std::io::Error> {
+        if let Some(ref mut opening) = self.opening {
+            self.opened = Some(try_ready!(opening.poll()));
+        }
+        self.opening = None;
+        let opened = self.opened.as_ref().unwrap();
+        Ok(Async::Ready((opened.len_a, opened.len_b)))
+    }
+
+    /// Очередная пара фрагментов в пределах общей длины файлов и смещение ее начала
+    fn poll_chunk(&mut self) -> Poll<Option<(u64, Bytes, Bytes)>, std::io::Error> {
+        let (len_a, len_b) = try_ready!(self.poll_opened());
+        let common = std::cmp::min(len_a, len_b);
+        if self.reading.is_none() {
+            if self.offset >= common {
+                return Ok(Async::Ready(None));
+            }
+            let len = std::cmp::min(self.chunk_size as u64, common - self.offset) as usize;
+            let opened = self.opened.as_ref().unwrap();
+            self.reading = Some(
+                read_chunk(self.cpu_pool, &opened.a, self.offset, len)
+                    .join(read_chunk(self.cpu_pool, &opened.b, self.offset, len)),
+            );
+        }
+        let (a, b) = try_ready!(self.reading.as_mut().unwrap().poll());
+        self.reading = None;
+        let offset = self.offset;
+        if a.len() != b.len() || a.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "file changed during comparison"));
+        }
+        self.offset += a.len() as u64;
+        Ok(Async::Ready(Some((offset, a, b))))
+    }
+}
+
+
+// FilesEqual
+
+/// Future сравнения содержимого двух файлов.
+///
+/// Сначала сравниваются размеры, затем фрагменты обоих файлов, читаемые параллельно
+/// позиционным чтением; сравнение прекращается на первом различии.
+pub struct FilesEqual {
+    pairs: ChunkPairs,
+}
+
+/// Сравнивает содержимое двух файлов
+#[inline]
+pub fn files_equal<A: AsRef<Path>, B: AsRef<Path>>(a: A, b: B) -> FilesEqual {
+    files_equal_with_pool(&DEFAULT_CPU_POOL, a, b)
+}
+
+pub fn files_equal_with_pool<A: AsRef<Path>, B: AsRef<Path>>(cpu_pool: &'static CpuPool, a: A, b: B) -> FilesEqual {
+    FilesEqual {
+        pairs: ChunkPairs::new(cpu_pool, a.as_ref().into(), b.as_ref().into()),
+    }
+}
+
+impl FilesEqual {
+
+    /// Задает размер сравниваемых фрагментов
+    #[inline]
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must be greater than zero");
+        self.pairs.chunk_size = chunk_size;
+        self
+    }
+}
+impl Future for FilesEqual {
+    type Item = bool;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let (len_a, len_b) = try_ready!(self.pairs.poll_opened());
+        if len_a != len_b {
+            return Ok(Async::Ready(false));
+        }
+        loop {
+            match try_ready!(self.pairs.poll_chunk()) {
+                Some((_, a, b)) => {
+                    if a != b {
+                        return Ok(Async::Ready(false));
+                    }
+                },
+                None => return Ok(Async::Ready(true)),
+            }
+        }
+    }
+}
+impl std::fmt::Debug for FilesEqual {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("FilesEqual").finish()
+    }
+}
+
+
+// DiffRanges
+
+/// Поток диапазонов байт, в которых различается содержимое двух файлов.
+///
+/// Соседние различающиеся байты объединяются в один диапазон.
+/// Если файлы разной длины, то "хвост" более длинного файла является последним диапазоном.
+pub struct DiffRanges {
+    pairs: ChunkPairs,
+    current: Option<Range<u64>>,
+    finished: VecDeque<Range<u64>>,
+    done: bool,
+}
+
+/// Находит диапазоны байт, в которых различается содержимое двух файлов
+#[inline]
+pub fn diff_ranges<A: AsRef<Path>, B: AsRef<Path>>(a: A, b: B) -> DiffRanges {
+    diff_ranges_with_pool(&DEFAULT_CPU_POOL, a, b)
+}
+
+pub fn diff_ranges_with_pool<A: AsRef<Path>, B: AsRef<Path>>(cpu_pool: &'static CpuPool, a: A, b: B) -> DiffRanges {
+    DiffRanges {
+        pairs: ChunkPairs::new(cpu_pool, a.as_ref().into(), b.as_ref().into()),
+        current: None,
+        finished: VecDeque::new(),
+        done: false,
+    }
+}
+
+impl DiffRanges {
+
+    /// Задает размер сравниваемых фрагментов
+    #[inline]
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must be greater than zero");
+        self.pairs.chunk_size = chunk_size;
+        self
+    }
+
+    /// Добавляет различающийся диапазон, объединяя его с предыдущим, если они соседние
+    fn push(&mut self, range: Range<u64>) {
+        match self.current.take() {
+            Some(ref current) if current.end == range.start => {
+                self.current = Some(current.start..range.end);
+            },
+            Some(previous) => {
+                self.finished.push_back(previous);
+                self.current = Some(range);
+            },
+            None => {
+                self.current = Some(range);
+            },
+        }
+    }
+}
+impl Stream for DiffRanges {
+    type Item = Range<u64>;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(range) = self.finished.pop_front() {
+                return Ok(Async::Ready(Some(range)));
+            }
+            if self.done {
+                return Ok(Async::Ready(self.current.take()));
+            }
+            match try_ready!(self.pairs.poll_chunk()) {
+                Some((offset, a, b)) => {
+                    let mut i = 0;
+                    while i < a.len() {
+                        if a[i] == b[i] {
+                            i += 1;
+                            continue;
+                        }
+                        let start = i;
+                        while i < a.len() && a[i] != b[i] {
+                            i += 1;
+                        }
+                        self.push(offset + start as u64..offset + i as u64);
+                    }
+                },
+                None => {
+                    self.done = true;
+                    let (len_a, len_b) = try_ready!(self.pairs.poll_opened());
+                    let common = std::cmp::min(len_a, len_b);
+                    let longest = std::cmp::max(len_a, len_b);
+                    if longest > common {
+                        self.push(common..longest);
+                    }
+                },
+            }
+        }
+    }
+}
+impl std::fmt::Debug for DiffRanges {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DiffRanges")
+            .field("current", &self.current)
+            .finish()
+    }
+}
@@ -0,0 +1,172 @@
+use futures::Async;
+use futures::executor::{self, Notify};
+use futures03::task::{Context, Poll, Waker};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use super::AsyncFileSink;
+use executor::BlockingExecutor;
+
+/// Пробуждает задачу futures 0.3 по уведомлению futures 0.1
+struct WakerNotify(Waker);
+impl Notify for WakerNotify {
+    fn notify(&self, _id: usize) {
+        self.0.wake_by_ref();
+    }
+}
+
+/// Выполняет опрос futures 0.1 в контексте задачи futures 0.3
+fn poll_01<T, E, F>(cx: &mut Context, f: F) -> Poll<Result<T, E>>
+    where F: FnMut() -> futures::Poll<T, E>
+{
+    let notify = Arc::new(WakerNotify(cx.waker().clone()));
+    match executor::spawn(futures::future::poll_fn(f)).poll_future_notify(&notify, 0) {
+        Ok(Async::Ready(value)) => Poll::Ready(Ok(value)),
+        Ok(Async::NotReady) => Poll::Pending,
+        Err(error) => Poll::Ready(Err(error)),
+    }
+}
+
+
+// AsyncFileSink
+
+/// Полный контракт `Sink` futures 0.3: `poll_close` дожидается записи,
+/// сбрасывает данные на диск (fsync) и закрывает файл в пуле потоков
+impl<E: BlockingExecutor + Unpin, B: AsRef<[u8]> + Send + Unpin + 'static> futures03::Sink<B> for AsyncFileSink<E, B> {
+    type Error = std::io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        poll_01(cx, || this.poll_ready())
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: B) -> Result<(), Self::Error> {
+        match futures::Sink::start_send(self.get_mut(), item)? {
+            futures::AsyncSink::Ready => Ok(()),
+            futures::AsyncSink::NotReady(_) => {
+                Err(std::io::Error::other("`start_send` called before `poll_ready` completed"))
+            },
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        poll_01(cx, || futures::Sink::poll_complete(this))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        poll_01(cx, || this.poll_close())
+    }
+}
+
+
+// Compat03
+
+/// Обертка, предоставляющая интерфейсы futures 0.3 (`std::future::Future`, `Stream`,
+/// `AsyncRead`, `AsyncWrite`) для типов крейта, построенных на futures 0.1 и tokio 0.1,
+/// чтобы использовать их в `async`/`await` коде:
+///
+/// ```ignore
+/// let file = AsyncFileRead::open(path).compat03().await?;
+/// let data = AsyncFileStream::from_std(&pool, file, size).compat03().try_concat().await?;
+/// ```
+///
+/// Задача futures 0.3 пробуждается по завершении операции в пуле потоков. `AsyncRead`
+/// и `AsyncWrite` - только трейты `futures::io` (futures 0.3): трейты ввода-вывода
+/// tokio 0.2+ обертка не реализует.
+/// `AsyncFileSink` реализует `Sink` futures 0.3 сам, без обертки.
+pub struct Compat03<T> {
+    inner: T,
+}
+impl<T> Compat03<T> {
+
+    #[inline]
+    pub fn new(inner: T) -> Compat03<T> {
+        Compat03 {
+            inner,
+        }
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+impl<T: futures::Future + Unpin> Future for Compat03<T> {
+    type Output = Result<T::Item, T::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        poll_01(cx, || this.inner.poll())
+    }
+}
+impl<T: futures::Stream + Unpin> futures03::Stream for Compat03<T> {
+    type Item = Result<T::Item, T::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        match poll_01(cx, || this.inner.poll()) {
+            Poll::Ready(Ok(Some(item))) => Poll::Ready(Some(Ok(item))),
+            Poll::Ready(Ok(None)) => Poll::Ready(None),
+            Poll::Ready(Err(error)) => Poll::Ready(Some(Err(error))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+impl<T: std::io::Read + Unpin> futures03::io::AsyncRead for Compat03<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        poll_01(cx, || would_block(this.inner.read(buf)))
+    }
+}
+impl<T: tokio::io::AsyncWrite + Unpin> futures03::io::AsyncWrite for Compat03<T> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        poll_01(cx, || this.inner.poll_write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        poll_01(cx, || this.inner.poll_flush())
+    }
+
+    /// Вызывает `shutdown` (для `AsyncFileWrite` - со сбросом данных на диск)
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        poll_01(cx, || this.inner.shutdown())
+    }
+}
+impl<T> std::fmt::Debug for Compat03<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Compat03").finish()
+    }
+}
+
+/// `WouldBlock` операции, ожидающей пул потоков, означает `NotReady`
+fn would_block<T>(result: std::io::Result<T>) -> futures::Poll<T, std::io::Error> {
+    match result {
+        Ok(value) => Ok(Async::Ready(value)),
+        Err(ref error) if error.kind() == std::io::ErrorKind::WouldBlock => Ok(Async::NotReady),
+        Err(error) => Err(error),
+    }
+}
+
+/// Преобразование в обертку `Compat03` для любого типа
+pub trait Compat03Ext: Sized {
+    #[inline]
+    fn compat03(self) -> Compat03<Self> {
+        Compat03::new(self)
+    }
+}
+impl<T> Compat03Ext for T {}
@@ -0,0 +1,100 @@
+use futures::{Poll, Future, Async, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use super::{AsyncFileStream, DEFAULT_CPU_POOL, DEFAULT_BUFFER_SIZE};
+use sys::{long_path, retry};
+
+
+// ConcatFiles
+
+/// Поток содержимого нескольких файлов, следующих друг за другом без разделителей.
+///
+/// Каждый файл открывается в пуле потоков только после того, как прочитан предыдущий,
+/// поэтому одновременно открыт не более одного файла. Ошибка открытия или чтения файла
+/// завершает поток.
+pub struct ConcatFiles {
+    cpu_pool: &'static CpuPool,
+    buffer_size: usize,
+    paths: VecDeque<PathBuf>,
+    opening: Option<CpuFuture<std::fs::File, std::io::Error>>,
+    current: Option<AsyncFileStream>,
+}
+
+/// Объединяет содержимое файлов `paths` в один поток в порядке их перечисления
+#[inline]
+pub fn concat_files<I, P>(paths: I) -> ConcatFiles
+    where I: IntoIterator<Item = P>,
+          P: AsRef<Path>
+{
+    concat_files_with_pool(&DEFAULT_CPU_POOL, paths)
+}
+
+pub fn concat_files_with_pool<I, P>(cpu_pool: &'static CpuPool, paths: I) -> ConcatFiles
+    where I: IntoIterator<Item = P>,
+          P: AsRef<Path>
+{
+    ConcatFiles {
+        cpu_pool,
+        buffer_size: DEFAULT_BUFFER_SIZE,
+        paths: paths.into_iter().map(|path| path.as_ref().into()).collect(),
+        opening: None,
+        current: None,
+    }
+}
+
+impl ConcatFiles {
+
+    /// Задает размер блока, читаемого из файла за одно обращение к пулу потоков
+    #[inline]
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        assert!(buffer_size > 0, "buffer size must be greater than zero");
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Количество файлов, которые еще не были открыты
+    #[inline]
+    pub fn pending(&self) -> usize {
+        self.paths.len()
+    }
+}
+impl Stream for ConcatFiles {
+    type Item = Bytes;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(ref mut current) = self.current {
+                if let Some(chunk) = try_ready!(current.poll()) {
+                    return Ok(Async::Ready(Some(chunk)));
+                }
+            }
+            self.current = None;
+            if let Some(ref mut opening) = self.opening {
+                let file = try_ready!(opening.poll());
+                self.current = Some(AsyncFileStream::from_std(self.cpu_pool, file, self.buffer_size));
+            }
+            if self.opening.take().is_some() {
+                continue;
+            }
+            match self.paths.pop_front() {
+                Some(path) => {
+                    self.opening = Some(self.cpu_pool.spawn_fn(move || {
+                        retry(|| std::fs::File::open(long_path(&path)))
+                    }));
+                },
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+impl std::fmt::Debug for ConcatFiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ConcatFiles")
+            .field("buffer_size", &self.buffer_size)
+            .field("pending", &self.paths.len())
+            .finish()
+    }
+}
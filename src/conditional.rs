@@ -0,0 +1,171 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::{DEFAULT_CPU_POOL, DEFAULT_BUFFER_SIZE};
+use sys::{long_path, retry, sync_all};
+
+static CONDITIONAL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Совпадает ли содержимое файла с `data`; отсутствующий файл не совпадает
+fn same_content(path: &Path, data: &[u8]) -> std::io::Result<bool> {
+    let mut file = match std::fs::File::open(long_path(path)) {
+        Ok(file) => file,
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(error) => return Err(error),
+    };
+    if file.metadata()?.len() != data.len() as u64 {
+        return Ok(false);
+    }
+    let mut buf = vec![0u8; DEFAULT_BUFFER_SIZE];
+    let mut offset = 0;
+    loop {
+        let read = retry(|| file.read(&mut buf))?;
+        if read == 0 {
+            return Ok(offset == data.len());
+        }
+        if offset + read > data.len() || buf[..read] != data[offset..offset + read] {
+            return Ok(false);
+        }
+        offset += read;
+    }
+}
+
+/// Записывает `data` в файл `path`, только если его содержимое отличается (или файла нет),
+/// и возвращает, был ли файл записан. Неизмененный файл не открывается на запись,
+/// поэтому время его изменения сохраняется (например, для систем сборки).
+///
+/// Существующий файл сравнивается с данными блоками в пуле потоков, начиная с размера.
+#[inline]
+pub fn write_if_changed<P: AsRef<Path>>(path: P, data: Bytes) -> CpuFuture<bool, std::io::Error> {
+    write_if_changed_with_pool(&DEFAULT_CPU_POOL, path, data)
+}
+
+pub fn write_if_changed_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P, data: Bytes) -> CpuFuture<bool, std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || {
+        if same_content(&path, &data)? {
+            return Ok(false);
+        }
+        let mut file = retry(|| std::fs::File::create(long_path(&path)))?;
+        file.write_all(&data)?;
+        Ok(true)
+    })
+}
+
+
+// Precondition
+
+/// Версия файла для условной записи: время изменения с точностью до наносекунд и размер.
+/// Отличается от `ETag` в `serve_file`, точность которого - секунды.
+pub fn file_etag(metadata: &std::fs::Metadata) -> String {
+    let modified = metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}.{:x}-{:x}\"", modified.as_secs(), modified.subsec_nanos(), metadata.len())
+}
+
+/// Условие, которому должен удовлетворять файл перед записью
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Precondition {
+    /// Файл существует и не изменялся позже указанного времени
+    UnmodifiedSince(SystemTime),
+    /// Файл существует и его версия (`file_etag`) совпадает с указанной
+    ETag(String),
+    /// Файл не существует
+    Absent,
+}
+impl Precondition {
+    fn check(&self, metadata: Option<&std::fs::Metadata>) -> std::io::Result<bool> {
+        Ok(match (self, metadata) {
+            (&Precondition::Absent, metadata) => metadata.is_none(),
+            (_, None) => false,
+            (&Precondition::UnmodifiedSince(since), Some(metadata)) => metadata.modified()? <= since,
+            (Precondition::ETag(etag), Some(metadata)) => file_etag(metadata) == *etag,
+        })
+    }
+}
+
+/// Ошибка условной записи: файл изменился (или появился, или исчез) после получения
+/// его версии.
+///
+/// Передается внутри `std::io::Error` с видом `Other`:
+/// `error.get_ref().and_then(|error| error.downcast_ref::<PreconditionFailed>())`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreconditionFailed {
+    /// Текущая версия файла (`None`, если файла нет)
+    pub current: Option<String>,
+}
+impl std::fmt::Display for PreconditionFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.current {
+            Some(ref etag) => write!(f, "precondition failed: file has changed (current version {})", etag),
+            None => write!(f, "precondition failed: file does not exist"),
+        }
+    }
+}
+impl std::error::Error for PreconditionFailed {}
+
+fn metadata_if_exists(path: &Path) -> std::io::Result<Option<std::fs::Metadata>> {
+    match std::fs::metadata(long_path(path)) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+fn check_precondition(path: &Path, precondition: &Precondition) -> std::io::Result<()> {
+    let metadata = metadata_if_exists(path)?;
+    if precondition.check(metadata.as_ref())? {
+        return Ok(());
+    }
+    Err(std::io::Error::other(PreconditionFailed {
+        current: metadata.as_ref().map(file_etag),
+    }))
+}
+
+/// Записывает `data` в файл `path`, только если файл удовлетворяет условию `precondition`,
+/// и возвращает новую версию файла (`file_etag`); иначе возвращается ошибка `PreconditionFailed`.
+///
+/// Данные записываются во временный файл рядом с `path`, после чего условие проверяется
+/// повторно и временный файл атомарно заменяет `path`, поэтому читатели видят либо старое,
+/// либо новое содержимое. Условие проверяется без блокировок (оптимистично): одновременная
+/// запись другим процессом между проверкой и заменой не обнаруживается.
+#[inline]
+pub fn write_if_unmodified_since<P: AsRef<Path>>(path: P, data: Bytes, precondition: Precondition) -> CpuFuture<String, std::io::Error> {
+    write_if_unmodified_since_with_pool(&DEFAULT_CPU_POOL, path, data, precondition)
+}
+
+pub fn write_if_unmodified_since_with_pool<P: AsRef<Path>>(
+    cpu_pool: &'static CpuPool,
+    path: P,
+    data: Bytes,
+    precondition: Precondition,
+) -> CpuFuture<String, std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || {
+        check_precondition(&path, &precondition)?;
+        let name = path.file_name()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name"))?;
+        let temporary = path.with_file_name(format!(
+            ".{}.write-{}-{}",
+            name.to_string_lossy(),
+            std::process::id(),
+            CONDITIONAL_COUNTER.fetch_add(1, Ordering::SeqCst),
+        ));
+        let result = (|| {
+            let mut file = retry(|| std::fs::OpenOptions::new().write(true).create_new(true).open(long_path(&temporary)))?;
+            file.write_all(&data)?;
+            retry(|| sync_all(&file))?;
+            check_precondition(&path, &precondition)?;
+            retry(|| std::fs::rename(long_path(&temporary), long_path(&path)))?;
+            Ok(file_etag(&file.metadata()?))
+        })();
+        if result.is_err() {
+            let _ = std::fs::remove_file(long_path(&temporary));
+        }
+        result
+    })
+}
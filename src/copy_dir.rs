@@ -0,0 +1,178 @@
+use futures::{Poll, Future, Async, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::path::{Path, PathBuf};
+use super::DEFAULT_CPU_POOL;
+use walk::{walk_with_pool, WalkStream, WalkEntry};
+use sys::{self, long_path, retry};
+
+/// Способ копирования файлов
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyMode {
+    /// Данные файлов копируются
+    Copy,
+    /// Файлы клонируются (reflink), а если файловая система этого не поддерживает - копируются
+    CloneWhenPossible,
+}
+
+/// Как была скопирована запись
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CopyMethod {
+    /// Файл клонирован (reflink) без копирования данных
+    Cloned,
+    /// Данные файла скопированы
+    Copied,
+    /// Создан каталог
+    Directory,
+    /// Создана символическая ссылка с тем же содержимым
+    Symlink,
+}
+
+/// Скопированная запись дерева каталогов
+#[derive(Debug, Clone)]
+pub struct CopiedEntry {
+    /// Исходный путь
+    pub src: PathBuf,
+    /// Путь копии
+    pub dst: PathBuf,
+    pub method: CopyMethod,
+    /// Размер скопированного файла
+    pub size: u64,
+}
+
+fn copy_file(src: &Path, dst: &Path, mode: CopyMode, metadata: &std::fs::Metadata) -> std::io::Result<CopyMethod> {
+    if mode == CopyMode::CloneWhenPossible {
+        let source = retry(|| std::fs::File::open(long_path(src)))?;
+        let target = retry(|| std::fs::File::create(long_path(dst)))?;
+        if sys::reflink(&source, &target)? {
+            target.set_permissions(metadata.permissions())?;
+            return Ok(CopyMethod::Cloned);
+        }
+    }
+    retry(|| std::fs::copy(long_path(src), long_path(dst)))?;
+    Ok(CopyMethod::Copied)
+}
+
+fn copy_entry(entry: WalkEntry, dst: PathBuf, mode: CopyMode) -> std::io::Result<CopiedEntry> {
+    let file_type = entry.metadata.file_type();
+    let (method, size) = if file_type.is_dir() {
+        match std::fs::create_dir(long_path(&dst)) {
+            Err(ref error) if error.kind() == std::io::ErrorKind::AlreadyExists => {},
+            result => result?,
+        }
+        (CopyMethod::Directory, 0)
+    } else if file_type.is_symlink() {
+        copy_symlink(&entry.path, &dst)?;
+        (CopyMethod::Symlink, 0)
+    } else {
+        (copy_file(&entry.path, &dst, mode, &entry.metadata)?, entry.metadata.len())
+    };
+    Ok(CopiedEntry {
+        src: entry.path,
+        dst,
+        method,
+        size,
+    })
+}
+
+#[cfg(unix)]
+fn copy_symlink(src: &Path, dst: &Path) -> std::io::Result<()> {
+    let target = std::fs::read_link(long_path(src))?;
+    std::os::unix::fs::symlink(target, long_path(dst))
+}
+
+#[cfg(windows)]
+fn copy_symlink(src: &Path, dst: &Path) -> std::io::Result<()> {
+    let target = std::fs::read_link(long_path(src))?;
+    if std::fs::metadata(long_path(src))?.is_dir() {
+        std::os::windows::fs::symlink_dir(target, long_path(dst))
+    } else {
+        std::os::windows::fs::symlink_file(target, long_path(dst))
+    }
+}
+
+
+// CopyDir
+
+/// Поток записей, скопированных при рекурсивном копировании каталога.
+///
+/// Дерево обходится так же, как в `walk`, а каждая запись копируется в пуле потоков,
+/// после чего передается в поток с указанием способа копирования (`CopyMethod`).
+/// Ошибка копирования записи передается как ошибка потока, после чего копирование
+/// можно продолжить.
+pub struct CopyDir {
+    cpu_pool: &'static CpuPool,
+    src: PathBuf,
+    dst: PathBuf,
+    mode: CopyMode,
+    creating_root: Option<CpuFuture<(), std::io::Error>>,
+    walk: WalkStream,
+    copying: Option<CpuFuture<CopiedEntry, std::io::Error>>,
+}
+
+/// Копирует каталог `src` со всем содержимым в `dst` (каталог `dst` создается при необходимости)
+#[inline]
+pub fn copy_dir_all<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> CopyDir {
+    copy_dir_all_with_pool(&DEFAULT_CPU_POOL, src, dst)
+}
+
+pub fn copy_dir_all_with_pool<S: AsRef<Path>, D: AsRef<Path>>(cpu_pool: &'static CpuPool, src: S, dst: D) -> CopyDir {
+    let root: PathBuf = dst.as_ref().into();
+    CopyDir {
+        cpu_pool,
+        src: src.as_ref().into(),
+        dst: dst.as_ref().into(),
+        mode: CopyMode::Copy,
+        creating_root: Some(cpu_pool.spawn_fn(move || std::fs::create_dir_all(long_path(&root)))),
+        walk: walk_with_pool(cpu_pool, src),
+        copying: None,
+    }
+}
+
+impl CopyDir {
+
+    /// Задает способ копирования файлов
+    #[inline]
+    pub fn mode(mut self, mode: CopyMode) -> Self {
+        self.mode = mode;
+        self
+    }
+}
+impl Stream for CopyDir {
+    type Item = CopiedEntry;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Some(ref mut creating_root) = self.creating_root {
+            try_ready!(creating_root.poll());
+        }
+        self.creating_root = None;
+        if let Some(ref mut copying) = self.copying {
+            let result = copying.poll();
+            if let Ok(Async::NotReady) = result {
+                return Ok(Async::NotReady);
+            }
+            self.copying = None;
+            return result.map(|entry| entry.map(Some));
+        }
+        let entry = match try_ready!(self.walk.poll()) {
+            Some(entry) => entry,
+            None => return Ok(Async::Ready(None)),
+        };
+        let dst = match entry.path.strip_prefix(&self.src) {
+            Ok(relative) => self.dst.join(relative),
+            Err(_) => return Err(std::io::Error::other("walk entry is outside of the source directory")),
+        };
+        let mode = self.mode;
+        self.copying = Some(self.cpu_pool.spawn_fn(move || copy_entry(entry, dst, mode)));
+        self.poll()
+    }
+}
+impl std::fmt::Debug for CopyDir {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CopyDir")
+            .field("src", &self.src)
+            .field("dst", &self.dst)
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
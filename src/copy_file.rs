@@ -0,0 +1,204 @@
+use futures::{Poll, Future, Async, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use pool_registry;
+use sys::{self, long_path, retry};
+
+/// Количество байт, копируемых за одно обращение к пулу потоков
+static DEFAULT_COPY_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Состояние копирования, передаваемое в пул потоков и обратно
+struct CopyState {
+    src: std::fs::File,
+    dst: std::fs::File,
+    /// Поддерживается ли копирование без передачи данных через память процесса
+    zero_copy: bool,
+    buf: Vec<u8>,
+}
+impl CopyState {
+    fn copy_chunk(&mut self, chunk_size: usize) -> std::io::Result<usize> {
+        if self.zero_copy {
+            match sys::copy_chunk(&self.src, &self.dst, chunk_size)? {
+                Some(size) => return Ok(size),
+                None => self.zero_copy = false,
+            }
+        }
+        if self.buf.is_empty() {
+            self.buf = vec![0; std::cmp::min(chunk_size, super::DEFAULT_BUFFER_SIZE * 8)];
+        }
+        let mut copied = 0;
+        while copied < chunk_size {
+            let len = std::cmp::min(self.buf.len(), chunk_size - copied);
+            let size = retry(|| self.src.read(&mut self.buf[..len]))?;
+            if size == 0 {
+                break;
+            }
+            self.dst.write_all(&self.buf[..size])?;
+            copied += size;
+        }
+        Ok(copied)
+    }
+}
+
+
+// CopyProgress
+
+/// Поток хода копирования файла: после каждого скопированного фрагмента передается
+/// общее количество скопированных байт.
+///
+/// Фрагменты копируются в пуле потоков; на Linux - без передачи данных через память процесса
+/// (`copy_file_range`, `sendfile`), иначе - через буфер. Права доступа копируются
+/// как в `std::fs::copy`.
+pub struct CopyProgress {
+    cpu_pool: &'static CpuPool,
+    chunk_size: usize,
+    opening: Option<CpuFuture<(CopyState, u64), std::io::Error>>,
+    copying: Option<CpuFuture<(CopyState, usize), std::io::Error>>,
+    state: Option<CopyState>,
+    copied: u64,
+    total: Option<u64>,
+}
+
+/// Копирует `src` в `dst` (файл создается или перезаписывается), сообщая о ходе копирования.
+/// Пул потоков выбирается по пути `dst` (см. `PoolRegistry`).
+#[inline]
+pub fn copy_progress<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> CopyProgress {
+    copy_progress_with_pool(pool_registry::pool_for(dst.as_ref()), src, dst)
+}
+
+pub fn copy_progress_with_pool<P: AsRef<Path>, Q: AsRef<Path>>(cpu_pool: &'static CpuPool, src: P, dst: Q) -> CopyProgress {
+    let src: PathBuf = src.as_ref().into();
+    let dst: PathBuf = dst.as_ref().into();
+    CopyProgress {
+        cpu_pool,
+        chunk_size: DEFAULT_COPY_CHUNK_SIZE,
+        opening: Some(cpu_pool.spawn_fn(move || {
+            let src = retry(|| std::fs::File::open(long_path(&src)))?;
+            let metadata = src.metadata()?;
+            if !metadata.is_file() {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "the source path is not a file"));
+            }
+            let dst = retry(|| std::fs::File::create(long_path(&dst)))?;
+            dst.set_permissions(metadata.permissions())?;
+            let state = CopyState {
+                src,
+                dst,
+                zero_copy: true,
+                buf: Vec::new(),
+            };
+            Ok((state, metadata.len()))
+        })),
+        copying: None,
+        state: None,
+        copied: 0,
+        total: None,
+    }
+}
+
+impl CopyProgress {
+
+    /// Задает количество байт, копируемых за одно обращение к пулу потоков
+    /// (и, соответственно, частоту сообщений о ходе копирования)
+    #[inline]
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must be greater than zero");
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Размер исходного файла на момент открытия
+    #[inline]
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Количество скопированных байт
+    #[inline]
+    pub fn copied(&self) -> u64 {
+        self.copied
+    }
+}
+impl Stream for CopyProgress {
+    type Item = u64;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Some(ref mut opening) = self.opening {
+            let (state, total) = try_ready!(opening.poll());
+            self.state = Some(state);
+            self.total = Some(total);
+        }
+        self.opening = None;
+        if self.copying.is_none() {
+            let mut state = match self.state.take() {
+                Some(state) => state,
+                None => return Ok(Async::Ready(None)),
+            };
+            let chunk_size = self.chunk_size;
+            self.copying = Some(self.cpu_pool.spawn_fn(move || {
+                let size = state.copy_chunk(chunk_size)?;
+                Ok((state, size))
+            }));
+        }
+        let (state, size) = try_ready!(self.copying.as_mut().unwrap().poll());
+        self.copying = None;
+        if size == 0 {
+            // файлы закрываются в пуле потоков
+            self.cpu_pool.spawn_fn(move || {
+                drop(state);
+                Ok::<(), ()>(())
+            }).forget();
+            return Ok(Async::Ready(None));
+        }
+        self.state = Some(state);
+        self.copied += size as u64;
+        Ok(Async::Ready(Some(self.copied)))
+    }
+}
+impl std::fmt::Debug for CopyProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CopyProgress")
+            .field("copied", &self.copied)
+            .field("total", &self.total)
+            .finish()
+    }
+}
+
+
+// CopyFile
+
+/// Future копирования файла (см. `CopyProgress`); результат - количество скопированных байт
+pub struct CopyFile {
+    progress: CopyProgress,
+}
+
+/// Копирует `src` в `dst` (файл создается или перезаписывается).
+/// Пул потоков выбирается по пути `dst` (см. `PoolRegistry`).
+#[inline]
+pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> CopyFile {
+    copy_with_pool(pool_registry::pool_for(dst.as_ref()), src, dst)
+}
+
+pub fn copy_with_pool<P: AsRef<Path>, Q: AsRef<Path>>(cpu_pool: &'static CpuPool, src: P, dst: Q) -> CopyFile {
+    CopyFile {
+        progress: copy_progress_with_pool(cpu_pool, src, dst),
+    }
+}
+
+impl Future for CopyFile {
+    type Item = u64;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        while try_ready!(self.progress.poll()).is_some() {}
+        Ok(Async::Ready(self.progress.copied()))
+    }
+}
+impl std::fmt::Debug for CopyFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CopyFile")
+            .field("progress", &self.progress)
+            .finish()
+    }
+}
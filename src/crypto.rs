@@ -0,0 +1,302 @@
+use futures::{Poll, Future, Async, AsyncSink, Sink, Stream, StartSend};
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::{Bytes, BytesMut, BufMut};
+use chacha20poly1305::{XChaCha20Poly1305, Key, XNonce};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use std::sync::Arc;
+
+static HEADER_MAGIC: [u8; 5] = *b"AFXC1";
+static NONCE_PREFIX_SIZE: usize = 16;
+static HEADER_SIZE: usize = 5 + 16;
+static FRAME_HEADER_SIZE: usize = 5;
+static TAG_SIZE: usize = 16;
+static FLAG_DATA: u8 = 0;
+static FLAG_FINAL: u8 = 1;
+
+/// Максимальный размер открытого текста в одном фрагменте
+pub static MAX_ENCRYPTED_CHUNK_SIZE: usize = 64 * 1024;
+
+fn chunk_nonce(prefix: &[u8], counter: u64) -> XNonce {
+    let mut nonce = [0u8; 24];
+    nonce[..NONCE_PREFIX_SIZE].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_SIZE..].copy_from_slice(&counter.to_le_bytes());
+    XNonce::from(nonce)
+}
+
+fn crypto_error() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "encrypted stream authentication failed")
+}
+
+
+// EncryptedSink
+
+/// Адаптер, шифрующий данные перед записью в нижележащий sink (например, `AsyncFileSink`).
+///
+/// Используется XChaCha20-Poly1305 с разбиением на фрагменты: каждый элемент шифруется
+/// отдельно с уникальным nonce (случайный префикс потока и номер фрагмента),
+/// а последний фрагмент помечается, что позволяет обнаружить обрезание файла.
+/// Шифрование выполняется в пуле потоков. Для записи последнего фрагмента
+/// необходимо закрыть sink (`Sink::close`).
+pub struct EncryptedSink<S> {
+    cpu_pool: &'static CpuPool,
+    inner: S,
+    cipher: Arc<XChaCha20Poly1305>,
+    prefix: [u8; 16],
+    counter: u64,
+    header: bool,
+    finished: bool,
+    encrypting: Option<CpuFuture<Bytes, std::io::Error>>,
+    encrypted: Option<Bytes>,
+}
+impl<S> EncryptedSink<S> {
+
+    pub fn new(cpu_pool: &'static CpuPool, inner: S, key: &[u8; 32]) -> EncryptedSink<S> {
+        EncryptedSink {
+            cpu_pool,
+            inner,
+            cipher: Arc::new(XChaCha20Poly1305::new(&Key::from(*key))),
+            prefix: rand::random(),
+            counter: 0,
+            header: false,
+            finished: false,
+            encrypting: None,
+            encrypted: None,
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn encrypt(&mut self, flag: u8, plaintext: Bytes) {
+        let cipher = self.cipher.clone();
+        let prefix = self.prefix;
+        let header = !self.header;
+        self.header = true;
+        let counter = self.counter;
+        // большие элементы шифруются несколькими фрагментами
+        let chunks = std::cmp::max(1, plaintext.len().div_ceil(MAX_ENCRYPTED_CHUNK_SIZE));
+        self.counter += chunks as u64;
+        self.encrypting = Some(self.cpu_pool.spawn_fn(move || {
+            let mut buf = BytesMut::with_capacity(
+                HEADER_SIZE + plaintext.len() + chunks * (FRAME_HEADER_SIZE + TAG_SIZE),
+            );
+            if header {
+                buf.put_slice(&HEADER_MAGIC);
+                buf.put_slice(&prefix);
+            }
+            for i in 0..chunks {
+                let start = i * MAX_ENCRYPTED_CHUNK_SIZE;
+                let end = std::cmp::min(start + MAX_ENCRYPTED_CHUNK_SIZE, plaintext.len());
+                let nonce = chunk_nonce(&prefix, counter + i as u64);
+                let ciphertext = cipher
+                    .encrypt(&nonce, Payload { msg: &plaintext[start..end], aad: &[flag] })
+                    .map_err(|_| std::io::Error::other("encryption failed"))?;
+                buf.put_u8(flag);
+                buf.put_slice(&(ciphertext.len() as u32).to_le_bytes());
+                buf.put_slice(&ciphertext);
+            }
+            Ok(buf.freeze())
+        }));
+    }
+}
+impl<S> EncryptedSink<S>
+    where S: Sink<SinkItem = Bytes, SinkError = std::io::Error>
+{
+    /// Передает зашифрованные фрагменты в нижележащий sink
+    fn poll_encrypted(&mut self) -> Poll<(), std::io::Error> {
+        loop {
+            if let Some(encrypted) = self.encrypted.take() {
+                if let AsyncSink::NotReady(encrypted) = self.inner.start_send(encrypted)? {
+                    self.encrypted = Some(encrypted);
+                    return Ok(Async::NotReady);
+                }
+            }
+            match self.encrypting {
+                Some(ref mut future) => {
+                    self.encrypted = Some(try_ready!(future.poll()));
+                },
+                None => return Ok(Async::Ready(())),
+            }
+            self.encrypting = None;
+        }
+    }
+}
+impl<S> Sink for EncryptedSink<S>
+    where S: Sink<SinkItem = Bytes, SinkError = std::io::Error>
+{
+    type SinkItem = Bytes;
+    type SinkError = std::io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if self.finished {
+            return Err(std::io::Error::other("encrypted sink already closed"));
+        }
+        if let Async::NotReady = self.poll_encrypted()? {
+            return Ok(AsyncSink::NotReady(item));
+        }
+        self.encrypt(FLAG_DATA, item);
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        try_ready!(self.poll_encrypted());
+        self.inner.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        try_ready!(self.poll_encrypted());
+        if !self.finished {
+            self.finished = true;
+            self.encrypt(FLAG_FINAL, Bytes::new());
+            try_ready!(self.poll_encrypted());
+        }
+        self.inner.close()
+    }
+}
+impl<S> std::fmt::Debug for EncryptedSink<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("EncryptedSink")
+            .field("counter", &self.counter)
+            .finish()
+    }
+}
+
+
+// DecryptingStream
+
+/// Адаптер, расшифровывающий поток фрагментов, записанный `EncryptedSink`.
+///
+/// Расшифровка выполняется в пуле потоков. Поврежденные или подмененные фрагменты,
+/// а также обрезанный поток приводят к ошибке `InvalidData`.
+pub struct DecryptingStream<S> {
+    cpu_pool: &'static CpuPool,
+    inner: S,
+    inner_done: bool,
+    cipher: Arc<XChaCha20Poly1305>,
+    prefix: Option<[u8; 16]>,
+    counter: u64,
+    finished: bool,
+    buf: BytesMut,
+    decrypting: Option<CpuFuture<Bytes, std::io::Error>>,
+}
+impl<S> DecryptingStream<S> {
+
+    pub fn new(cpu_pool: &'static CpuPool, inner: S, key: &[u8; 32]) -> DecryptingStream<S> {
+        DecryptingStream {
+            cpu_pool,
+            inner,
+            inner_done: false,
+            cipher: Arc::new(XChaCha20Poly1305::new(&Key::from(*key))),
+            prefix: None,
+            counter: 0,
+            finished: false,
+            buf: BytesMut::new(),
+            decrypting: None,
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    /// Запускает расшифровку очередного фрагмента, если он полностью получен
+    fn next_frame(&mut self) -> std::io::Result<bool> {
+        let prefix = match self.prefix {
+            Some(prefix) => prefix,
+            None => {
+                if self.buf.len() < HEADER_SIZE {
+                    return Ok(false);
+                }
+                let header = self.buf.split_to(HEADER_SIZE);
+                if header[..HEADER_MAGIC.len()] != HEADER_MAGIC {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "unknown encrypted stream format"));
+                }
+                let mut prefix = [0u8; 16];
+                prefix.copy_from_slice(&header[HEADER_MAGIC.len()..]);
+                self.prefix = Some(prefix);
+                prefix
+            },
+        };
+        if self.buf.len() < FRAME_HEADER_SIZE {
+            return Ok(false);
+        }
+        let flag = self.buf[0];
+        let mut len = [0u8; 4];
+        len.copy_from_slice(&self.buf[1..FRAME_HEADER_SIZE]);
+        let len = u32::from_le_bytes(len) as usize;
+        if (flag != FLAG_DATA && flag != FLAG_FINAL) || len < TAG_SIZE || len > MAX_ENCRYPTED_CHUNK_SIZE + TAG_SIZE {
+            return Err(crypto_error());
+        }
+        if self.buf.len() < FRAME_HEADER_SIZE + len {
+            return Ok(false);
+        }
+        self.buf.advance(FRAME_HEADER_SIZE);
+        let ciphertext = self.buf.split_to(len).freeze();
+        if flag == FLAG_FINAL {
+            self.finished = true;
+        }
+
+        let cipher = self.cipher.clone();
+        let nonce = chunk_nonce(&prefix, self.counter);
+        self.counter += 1;
+        self.decrypting = Some(self.cpu_pool.spawn_fn(move || {
+            cipher.decrypt(&nonce, Payload { msg: &ciphertext, aad: &[flag] })
+                .map(Bytes::from)
+                .map_err(|_| crypto_error())
+        }));
+        Ok(true)
+    }
+}
+impl<S> Stream for DecryptingStream<S>
+    where S: Stream<Item = Bytes, Error = std::io::Error>
+{
+    type Item = Bytes;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(ref mut future) = self.decrypting {
+                let plaintext = try_ready!(future.poll());
+                self.decrypting = None;
+                if !plaintext.is_empty() {
+                    return Ok(Async::Ready(Some(plaintext)));
+                }
+                continue;
+            }
+            if self.finished {
+                if !self.buf.is_empty() {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "trailing data after encrypted stream"));
+                }
+                // данные после завершающего фрагмента не допускаются
+                if !self.inner_done {
+                    match try_ready!(self.inner.poll()) {
+                        Some(chunk) => self.buf.extend_from_slice(&chunk),
+                        None => self.inner_done = true,
+                    }
+                    continue;
+                }
+                return Ok(Async::Ready(None));
+            }
+            if self.next_frame()? {
+                continue;
+            }
+            if self.inner_done {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "encrypted stream is truncated"));
+            }
+            match try_ready!(self.inner.poll()) {
+                Some(chunk) => self.buf.extend_from_slice(&chunk),
+                None => self.inner_done = true,
+            }
+        }
+    }
+}
+impl<S> std::fmt::Debug for DecryptingStream<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DecryptingStream")
+            .field("counter", &self.counter)
+            .finish()
+    }
+}
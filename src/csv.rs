@@ -0,0 +1,436 @@
+use futures::{Poll, Future, Async, AsyncSink, Sink, Stream, StartSend};
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use csv_crate::{ByteRecord, ReaderBuilder, WriterBuilder};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use super::{AsyncFileSink, AsyncFileStream, DEFAULT_CPU_POOL, DEFAULT_BUFFER_SIZE};
+use sys::{long_path, retry};
+
+/// Сколько записей сериализуется за одно обращение к пулу потоков
+static DEFAULT_CSV_BATCH_SIZE: usize = 256;
+
+fn csv_error(error: csv_crate::Error) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, error)
+}
+
+/// Разобранные записи, заголовки, неразобранный остаток данных и ошибка разбора,
+/// прервавшая пакет после этих записей
+type Parsed<T> = (Vec<T>, Option<ByteRecord>, Vec<u8>, Option<std::io::Error>);
+
+/// Открытие файла в пуле потоков и создание из него потока или sink блоков
+type Opening<S> = (CpuFuture<std::fs::File, std::io::Error>, fn(&'static CpuPool, std::fs::File) -> S);
+
+/// Разбирает полные записи из `buf`. Пока данные не закончились (`eof`), последняя запись
+/// может быть неполной, поэтому она остается в буфере до следующего блока.
+fn parse_records<T: DeserializeOwned>(
+    buf: Vec<u8>,
+    mut headers: Option<ByteRecord>,
+    has_headers: bool,
+    delimiter: u8,
+    eof: bool,
+) -> std::io::Result<Parsed<T>> {
+    let mut records = Vec::new();
+    let mut error = None;
+    let consumed = {
+        let mut reader = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(delimiter)
+            .from_reader(&buf[..]);
+        let mut record = ByteRecord::new();
+        let mut consumed = 0;
+        loop {
+            match reader.read_byte_record(&mut record) {
+                Ok(true) => {},
+                Ok(false) => {
+                    consumed = buf.len();
+                    break;
+                },
+                Err(e) => {
+                    error = Some(csv_error(e));
+                    break;
+                },
+            }
+            let end = reader.position().byte() as usize;
+            if !eof && end >= buf.len() {
+                break;
+            }
+            if has_headers && headers.is_none() {
+                headers = Some(record.clone());
+            } else {
+                match record.deserialize(headers.as_ref()) {
+                    Ok(value) => records.push(value),
+                    Err(e) => {
+                        error = Some(csv_error(e));
+                        break;
+                    },
+                }
+            }
+            consumed = end;
+        }
+        consumed
+    };
+    Ok((records, headers, buf[consumed..].to_vec(), error))
+}
+
+
+// CsvStream
+
+/// Поток типизированных записей CSV поверх потока блоков данных (например, `AsyncFileStream`).
+///
+/// Записи разбираются и десериализуются в пуле потоков по мере поступления блоков;
+/// записи, разорванные границей блока, собираются из соседних блоков.
+/// Ошибка разбора завершает поток.
+pub struct CsvStream<T, S = AsyncFileStream> {
+    cpu_pool: &'static CpuPool,
+    /// Открытие файла и создание из него потока блоков
+    opening: Option<Opening<S>>,
+    inner: Option<S>,
+    has_headers: bool,
+    delimiter: u8,
+    buf: Vec<u8>,
+    headers: Option<ByteRecord>,
+    records: VecDeque<T>,
+    parsing: Option<CpuFuture<Parsed<T>, std::io::Error>>,
+    error: Option<std::io::Error>,
+    eof: bool,
+    done: bool,
+}
+
+/// Читает записи CSV из файла `path`; первая строка файла считается заголовком
+#[inline]
+pub fn read_csv<T, P>(path: P) -> CsvStream<T>
+    where T: DeserializeOwned + Send + 'static,
+          P: AsRef<Path>
+{
+    read_csv_with_pool(&DEFAULT_CPU_POOL, path)
+}
+
+pub fn read_csv_with_pool<T, P>(cpu_pool: &'static CpuPool, path: P) -> CsvStream<T>
+    where T: DeserializeOwned + Send + 'static,
+          P: AsRef<Path>
+{
+    let path: PathBuf = path.as_ref().into();
+    let mut stream = CsvStream::with_inner(cpu_pool, None);
+    stream.opening = Some((
+        cpu_pool.spawn_fn(move || retry(|| std::fs::File::open(long_path(&path)))),
+        |cpu_pool, file| AsyncFileStream::from_std(cpu_pool, file, DEFAULT_BUFFER_SIZE),
+    ));
+    stream
+}
+
+impl<T, S> CsvStream<T, S>
+    where T: DeserializeOwned + Send + 'static,
+          S: Stream<Item = Bytes, Error = std::io::Error>
+{
+    pub fn new(cpu_pool: &'static CpuPool, inner: S) -> CsvStream<T, S> {
+        Self::with_inner(cpu_pool, Some(inner))
+    }
+
+    fn with_inner(cpu_pool: &'static CpuPool, inner: Option<S>) -> CsvStream<T, S> {
+        CsvStream {
+            cpu_pool,
+            opening: None,
+            inner,
+            has_headers: true,
+            delimiter: b',',
+            buf: Vec::new(),
+            headers: None,
+            records: VecDeque::new(),
+            parsing: None,
+            error: None,
+            eof: false,
+            done: false,
+        }
+    }
+
+    /// Считать ли первую запись заголовком (имена полей для десериализации структур)
+    #[inline]
+    pub fn has_headers(mut self, has_headers: bool) -> Self {
+        self.has_headers = has_headers;
+        self
+    }
+
+    /// Задает разделитель полей
+    #[inline]
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Заголовок, если он уже прочитан
+    pub fn headers(&self) -> Option<Vec<String>> {
+        self.headers.as_ref().map(|headers| {
+            headers.iter().map(|field| String::from_utf8_lossy(field).into_owned()).collect()
+        })
+    }
+
+    fn parse(&mut self) {
+        let buf = std::mem::take(&mut self.buf);
+        let headers = self.headers.take();
+        let has_headers = self.has_headers;
+        let delimiter = self.delimiter;
+        let eof = self.eof;
+        self.parsing = Some(self.cpu_pool.spawn_fn(move || parse_records(buf, headers, has_headers, delimiter, eof)));
+    }
+}
+impl<T, S> Stream for CsvStream<T, S>
+    where T: DeserializeOwned + Send + 'static,
+          S: Stream<Item = Bytes, Error = std::io::Error>
+{
+    type Item = T;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(record) = self.records.pop_front() {
+                return Ok(Async::Ready(Some(record)));
+            }
+            if let Some(error) = self.error.take() {
+                self.done = true;
+                return Err(error);
+            }
+            if self.done {
+                return Ok(Async::Ready(None));
+            }
+            if let Some((ref mut opening, from_std)) = self.opening {
+                self.inner = Some(from_std(self.cpu_pool, try_ready!(opening.poll())));
+            }
+            self.opening = None;
+            if let Some(ref mut parsing) = self.parsing {
+                match parsing.poll() {
+                    Ok(Async::Ready((records, headers, rest, error))) => {
+                        self.records = records.into();
+                        self.headers = headers;
+                        self.buf = rest;
+                        self.error = error;
+                    },
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(error) => {
+                        self.parsing = None;
+                        self.done = true;
+                        return Err(error);
+                    },
+                }
+            }
+            self.parsing = None;
+            if self.eof {
+                self.done = true;
+                continue;
+            }
+            let chunk = match self.inner {
+                Some(ref mut inner) => try_ready!(inner.poll()),
+                None => None,
+            };
+            match chunk {
+                Some(chunk) => self.buf.extend_from_slice(&chunk),
+                None => self.eof = true,
+            }
+            self.parse();
+        }
+    }
+}
+impl<T, S> std::fmt::Debug for CsvStream<T, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CsvStream")
+            .field("has_headers", &self.has_headers)
+            .field("delimiter", &self.delimiter)
+            .field("buffered", &self.buf.len())
+            .field("eof", &self.eof)
+            .finish()
+    }
+}
+
+
+// CsvSink
+
+/// Адаптер, сериализующий типизированные записи в CSV и передающий данные в sink блоков
+/// (например, `AsyncFileSink`).
+///
+/// Записи накапливаются и сериализуются в пуле потоков пакетами по `batch_size` записей
+/// (а также при `poll_complete` и `close`). Для структур перед первой записью выводится
+/// заголовок с именами полей.
+pub struct CsvSink<T, S = AsyncFileSink> {
+    cpu_pool: &'static CpuPool,
+    /// Открытие файла и создание из него sink блоков
+    opening: Option<Opening<S>>,
+    inner: Option<S>,
+    has_headers: bool,
+    delimiter: u8,
+    batch_size: usize,
+    pending: Vec<T>,
+    /// Записан ли уже заголовок
+    started: bool,
+    serializing: Option<CpuFuture<Bytes, std::io::Error>>,
+    serialized: Option<Bytes>,
+    closed: bool,
+}
+
+/// Записывает записи CSV в файл `path` (файл создается или перезаписывается)
+#[inline]
+pub fn write_csv<T, P>(path: P) -> CsvSink<T>
+    where T: Serialize + Send + 'static,
+          P: AsRef<Path>
+{
+    write_csv_with_pool(&DEFAULT_CPU_POOL, path)
+}
+
+pub fn write_csv_with_pool<T, P>(cpu_pool: &'static CpuPool, path: P) -> CsvSink<T>
+    where T: Serialize + Send + 'static,
+          P: AsRef<Path>
+{
+    let path: PathBuf = path.as_ref().into();
+    let mut sink = CsvSink::with_inner(cpu_pool, None);
+    sink.opening = Some((
+        cpu_pool.spawn_fn(move || retry(|| std::fs::File::create(long_path(&path)))),
+        AsyncFileSink::from_std,
+    ));
+    sink
+}
+
+impl<T, S> CsvSink<T, S>
+    where T: Serialize + Send + 'static,
+          S: Sink<SinkItem = Bytes, SinkError = std::io::Error>
+{
+    pub fn new(cpu_pool: &'static CpuPool, inner: S) -> CsvSink<T, S> {
+        Self::with_inner(cpu_pool, Some(inner))
+    }
+
+    fn with_inner(cpu_pool: &'static CpuPool, inner: Option<S>) -> CsvSink<T, S> {
+        CsvSink {
+            cpu_pool,
+            opening: None,
+            inner,
+            has_headers: true,
+            delimiter: b',',
+            batch_size: DEFAULT_CSV_BATCH_SIZE,
+            pending: Vec::new(),
+            started: false,
+            serializing: None,
+            serialized: None,
+            closed: false,
+        }
+    }
+
+    /// Выводить ли заголовок с именами полей структур
+    #[inline]
+    pub fn has_headers(mut self, has_headers: bool) -> Self {
+        self.has_headers = has_headers;
+        self
+    }
+
+    /// Задает разделитель полей
+    #[inline]
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Задает количество записей, сериализуемых за одно обращение к пулу потоков
+    #[inline]
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch size must be greater than zero");
+        self.batch_size = batch_size;
+        self
+    }
+
+    fn serialize(&mut self) {
+        let records = std::mem::take(&mut self.pending);
+        let has_headers = self.has_headers && !self.started;
+        let delimiter = self.delimiter;
+        self.started = true;
+        self.serializing = Some(self.cpu_pool.spawn_fn(move || {
+            let mut writer = WriterBuilder::new()
+                .has_headers(has_headers)
+                .delimiter(delimiter)
+                .from_writer(Vec::new());
+            for record in records {
+                writer.serialize(record).map_err(csv_error)?;
+            }
+            let data = writer.into_inner().map_err(|error| error.into_error())?;
+            Ok(Bytes::from(data))
+        }));
+    }
+
+    /// Передает сериализованные данные в sink; `Ready`, когда передавать больше нечего
+    fn poll_serialized(&mut self) -> Poll<(), std::io::Error> {
+        if let Some((ref mut opening, from_std)) = self.opening {
+            self.inner = Some(from_std(self.cpu_pool, try_ready!(opening.poll())));
+        }
+        self.opening = None;
+        let inner = match self.inner {
+            Some(ref mut inner) => inner,
+            None => return Err(std::io::Error::other("csv sink has no inner sink")),
+        };
+        loop {
+            if let Some(data) = self.serialized.take() {
+                if let AsyncSink::NotReady(data) = inner.start_send(data)? {
+                    self.serialized = Some(data);
+                    return Ok(Async::NotReady);
+                }
+            }
+            match self.serializing {
+                Some(ref mut future) => {
+                    self.serialized = Some(try_ready!(future.poll()));
+                },
+                None => return Ok(Async::Ready(())),
+            }
+            self.serializing = None;
+        }
+    }
+}
+impl<T, S> Sink for CsvSink<T, S>
+    where T: Serialize + Send + 'static,
+          S: Sink<SinkItem = Bytes, SinkError = std::io::Error>
+{
+    type SinkItem = T;
+    type SinkError = std::io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if self.closed {
+            return Err(std::io::Error::other("csv sink already closed"));
+        }
+        if self.pending.len() >= self.batch_size {
+            if let Async::NotReady = self.poll_serialized()? {
+                return Ok(AsyncSink::NotReady(item));
+            }
+            self.serialize();
+        }
+        self.pending.push(item);
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        try_ready!(self.poll_serialized());
+        if !self.pending.is_empty() {
+            self.serialize();
+            try_ready!(self.poll_serialized());
+        }
+        match self.inner {
+            Some(ref mut inner) => inner.poll_complete(),
+            None => Ok(Async::Ready(())),
+        }
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        try_ready!(self.poll_complete());
+        self.closed = true;
+        match self.inner {
+            Some(ref mut inner) => inner.close(),
+            None => Ok(Async::Ready(())),
+        }
+    }
+}
+impl<T, S> std::fmt::Debug for CsvSink<T, S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CsvSink")
+            .field("has_headers", &self.has_headers)
+            .field("delimiter", &self.delimiter)
+            .field("batch_size", &self.batch_size)
+            .field("pending", &self.pending.len())
+            .finish()
+    }
+}
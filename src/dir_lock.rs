@@ -0,0 +1,359 @@
+use futures::{Poll, Future, Async};
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use super::DEFAULT_CPU_POOL;
+use lease::{acquire_lease, is_stale, read_lease, LeaseFile, LeaseInfo, KeepAlive};
+use sys::{long_path, retry};
+use timeout::Deadline;
+
+/// Имя файла блокировки в защищаемом каталоге
+pub static DIR_LOCK_FILE_NAME: &str = ".lock";
+
+static DEFAULT_DIR_LOCK_TTL_MS: u64 = 30_000;
+static DEFAULT_DIR_LOCK_RETRY_MS: u64 = 500;
+
+#[cfg(unix)]
+fn host_name() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&byte| byte == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn host_name() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+/// Завершился ли процесс `pid` этого узла; если это неизвестно, процесс считается живым
+#[cfg(unix)]
+fn is_process_dead(pid: u32) -> bool {
+    if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+        return false;
+    }
+    std::io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH)
+}
+
+#[cfg(not(unix))]
+fn is_process_dead(_pid: u32) -> bool {
+    false
+}
+
+fn format_time(time: SystemTime) -> String {
+    let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}.{:09}", time.as_secs(), time.subsec_nanos())
+}
+
+fn parse_time(value: &str) -> Option<SystemTime> {
+    let mut parts = value.splitn(2, '.');
+    let secs: u64 = parts.next()?.parse().ok()?;
+    let nanos: u32 = parts.next().unwrap_or("0").parse().ok()?;
+    Some(UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+/// Когда блокировку другого владельца можно перехватить
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StalePolicy {
+    /// Никогда: блокировка освобождается только владельцем (или удалением файла вручную)
+    Never,
+    /// Когда владелец перестал продлевать блокировку дольше `ttl`
+    Expired,
+    /// Когда блокировка истекла, а также сразу, если владелец - завершившийся процесс
+    /// этого же узла
+    ExpiredOrDeadOwner,
+}
+
+/// Владелец блокировки каталога
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirLockOwner {
+    /// Идентификатор владельца
+    pub owner: String,
+    pub pid: u32,
+    pub host: String,
+    /// Время захвата блокировки
+    pub started_at: SystemTime,
+    /// Время окончания блокировки, если владелец перестанет ее продлевать
+    pub expires_at: SystemTime,
+    pub description: String,
+}
+impl DirLockOwner {
+    fn parse(content: &str) -> Option<DirLockOwner> {
+        let info = LeaseInfo::parse(content)?;
+        let mut pid = None;
+        let mut host = String::new();
+        let mut started_at = None;
+        let mut description = String::new();
+        for line in content.lines() {
+            let mut parts = line.splitn(2, ": ");
+            let (key, value) = (parts.next()?, parts.next().unwrap_or(""));
+            match key {
+                "pid" => pid = value.parse().ok(),
+                "host" => host = value.to_string(),
+                "started_at" => started_at = parse_time(value),
+                "description" => description = value.to_string(),
+                _ => {},
+            }
+        }
+        Some(DirLockOwner {
+            owner: info.owner,
+            pid: pid?,
+            host,
+            started_at: started_at?,
+            expires_at: info.expires_at,
+            description,
+        })
+    }
+
+    /// Истекла ли блокировка
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= SystemTime::now()
+    }
+}
+
+fn is_stale_lock(path: &Path, content: &str, ttl: Duration, policy: StalePolicy) -> std::io::Result<bool> {
+    match policy {
+        StalePolicy::Never => Ok(false),
+        StalePolicy::Expired => is_stale(path, content, ttl),
+        StalePolicy::ExpiredOrDeadOwner => {
+            if let Some(owner) = DirLockOwner::parse(content) {
+                if owner.host == host_name() && owner.pid != std::process::id() && is_process_dead(owner.pid) {
+                    return Ok(true);
+                }
+            }
+            is_stale(path, content, ttl)
+        },
+    }
+}
+
+
+// DirLock
+
+/// Исключительная блокировка каталога данных между экземплярами сервиса.
+///
+/// Блокировка - это файл `.lock` в каталоге, созданный атомарно (см. `LeaseFile`),
+/// со сведениями о владельце: процесс, узел, время захвата и описание. Владелец продлевает
+/// блокировку потоком `DirLockGuard::heartbeat`; когда блокировку другого владельца можно
+/// перехватить, определяет `StalePolicy`.
+#[derive(Clone)]
+pub struct DirLock {
+    cpu_pool: &'static CpuPool,
+    dir: PathBuf,
+    ttl: Duration,
+    heartbeat: Option<Duration>,
+    stale_policy: StalePolicy,
+    retry_interval: Duration,
+    description: String,
+}
+impl DirLock {
+
+    #[inline]
+    pub fn new<P: AsRef<Path>>(dir: P) -> DirLock {
+        Self::new_with_pool(&DEFAULT_CPU_POOL, dir)
+    }
+
+    pub fn new_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, dir: P) -> DirLock {
+        DirLock {
+            cpu_pool,
+            dir: dir.as_ref().into(),
+            ttl: Duration::from_millis(DEFAULT_DIR_LOCK_TTL_MS),
+            heartbeat: None,
+            stale_policy: StalePolicy::Expired,
+            retry_interval: Duration::from_millis(DEFAULT_DIR_LOCK_RETRY_MS),
+            description: String::new(),
+        }
+    }
+
+    /// Задает срок, после которого непродленная блокировка считается устаревшей
+    #[inline]
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Задает интервал продления блокировки (по умолчанию треть `ttl`)
+    #[inline]
+    pub fn heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = Some(interval);
+        self
+    }
+
+    #[inline]
+    pub fn stale_policy(mut self, stale_policy: StalePolicy) -> Self {
+        self.stale_policy = stale_policy;
+        self
+    }
+
+    /// Задает интервал между попытками захвата в `lock`
+    #[inline]
+    pub fn retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// Задает описание владельца, видимое другим экземплярам (например, версию сервиса)
+    #[inline]
+    pub fn description(mut self, description: &str) -> Self {
+        self.description = description.replace('\n', " ");
+        self
+    }
+
+    #[inline]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Пытается захватить блокировку, перехватывая устаревшую;
+    /// `None`, если каталог заблокирован другим владельцем
+    pub fn try_lock(&self) -> CpuFuture<Option<DirLockGuard>, std::io::Error> {
+        let lock = self.clone();
+        self.cpu_pool.spawn_fn(move || {
+            retry(|| std::fs::create_dir_all(long_path(&lock.dir)))?;
+            let metadata = format!(
+                "pid: {}\nhost: {}\nstarted_at: {}\ndescription: {}\n",
+                std::process::id(),
+                host_name(),
+                format_time(SystemTime::now()),
+                lock.description,
+            );
+            let path = lock.dir.join(DIR_LOCK_FILE_NAME);
+            let (ttl, stale_policy) = (lock.ttl, lock.stale_policy);
+            let lease = acquire_lease(lock.cpu_pool, path, ttl, metadata, |path, content| {
+                is_stale_lock(path, content, ttl, stale_policy)
+            })?;
+            Ok(lease.map(|lease| DirLockGuard {
+                lease,
+                heartbeat: lock.heartbeat.unwrap_or(ttl / 3),
+            }))
+        })
+    }
+
+    /// Захватывает блокировку, повторяя попытки каждые `retry_interval`.
+    /// Ограничить ожидание можно через `Timeouted`.
+    pub fn lock(&self) -> AcquireDirLock {
+        AcquireDirLock {
+            lock: self.clone(),
+            attempt: Some(self.try_lock()),
+            waiting: None,
+        }
+    }
+
+    /// Читает сведения о текущем владельце блокировки; `None`, если каталог не заблокирован
+    pub fn owner(&self) -> CpuFuture<Option<DirLockOwner>, std::io::Error> {
+        let path = self.dir.join(DIR_LOCK_FILE_NAME);
+        self.cpu_pool.spawn_fn(move || {
+            Ok(read_lease(&path)?.as_ref().and_then(|content| DirLockOwner::parse(content)))
+        })
+    }
+}
+impl std::fmt::Debug for DirLock {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DirLock")
+            .field("dir", &self.dir)
+            .field("ttl", &self.ttl)
+            .field("heartbeat", &self.heartbeat)
+            .field("stale_policy", &self.stale_policy)
+            .field("description", &self.description)
+            .finish()
+    }
+}
+
+
+// AcquireDirLock
+
+/// Future захвата блокировки каталога (см. `DirLock::lock`)
+pub struct AcquireDirLock {
+    lock: DirLock,
+    attempt: Option<CpuFuture<Option<DirLockGuard>, std::io::Error>>,
+    waiting: Option<Deadline>,
+}
+impl Future for AcquireDirLock {
+    type Item = DirLockGuard;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            if let Some(ref mut waiting) = self.waiting {
+                if !waiting.poll_elapsed() {
+                    return Ok(Async::NotReady);
+                }
+            }
+            if self.waiting.take().is_some() {
+                self.attempt = Some(self.lock.try_lock());
+            }
+            let guard = match self.attempt.as_mut().unwrap().poll()? {
+                Async::Ready(guard) => guard,
+                Async::NotReady => return Ok(Async::NotReady),
+            };
+            self.attempt = None;
+            match guard {
+                Some(guard) => return Ok(Async::Ready(guard)),
+                None => self.waiting = Some(Deadline::new(Instant::now() + self.lock.retry_interval)),
+            }
+        }
+    }
+}
+impl std::fmt::Debug for AcquireDirLock {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AcquireDirLock")
+            .field("lock", &self.lock)
+            .finish()
+    }
+}
+
+
+// DirLockGuard
+
+/// Захваченная блокировка каталога; освобождается при удалении последней копии
+/// (в пуле потоков без ожидания) или через `release`
+#[derive(Clone)]
+pub struct DirLockGuard {
+    lease: LeaseFile,
+    heartbeat: Duration,
+}
+impl DirLockGuard {
+
+    /// Путь к файлу блокировки
+    #[inline]
+    pub fn path(&self) -> &Path {
+        self.lease.path()
+    }
+
+    /// Идентификатор владельца
+    #[inline]
+    pub fn owner(&self) -> &str {
+        self.lease.owner()
+    }
+
+    #[inline]
+    pub fn expires_at(&self) -> SystemTime {
+        self.lease.expires_at()
+    }
+
+    /// Поток продления блокировки с заданным интервалом; его нужно выполнять, пока
+    /// блокировка удерживается. Ошибка потока означает, что блокировку перехватили.
+    #[inline]
+    pub fn heartbeat(&self) -> KeepAlive {
+        self.lease.keep_alive(self.heartbeat)
+    }
+
+    #[inline]
+    pub fn refresh(&self) -> CpuFuture<SystemTime, std::io::Error> {
+        self.lease.refresh()
+    }
+
+    #[inline]
+    pub fn release(self) -> CpuFuture<(), std::io::Error> {
+        self.lease.release()
+    }
+}
+impl std::fmt::Debug for DirLockGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DirLockGuard")
+            .field("lease", &self.lease)
+            .field("heartbeat", &self.heartbeat)
+            .finish()
+    }
+}
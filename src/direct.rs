@@ -0,0 +1,190 @@
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::sync::{Arc, RwLock};
+use buffer::IoBuffer;
+use sys;
+
+/// Выравнивание буферов, смещений и размеров для `O_DIRECT` по умолчанию:
+/// подходит для накопителей с логическим блоком до 4 КиБ
+pub static DIRECT_IO_ALIGNMENT: usize = 4096;
+
+#[inline]
+fn round_up(len: usize, alignment: usize) -> usize {
+    len.div_ceil(alignment) * alignment
+}
+
+/// Читает до `len` байт с текущей позиции через выровненный буфер (размер чтения
+/// округляется вверх до `alignment`); прочитанное сверх `len` отбрасывается
+pub fn read_aligned(file: &mut std::fs::File, len: usize, alignment: usize) -> std::io::Result<Vec<u8>> {
+    let mut buf = IoBuffer::aligned(round_up(len, alignment), alignment);
+    let size = sys::retry(|| file.read(&mut buf[..]))?;
+    Ok(buf[..std::cmp::min(size, len)].to_vec())
+}
+
+/// Читает до `len` байт по смещению `offset` через выровненный буфер (см. `read_aligned`)
+pub fn read_aligned_at(file: &std::fs::File, len: usize, offset: u64, alignment: usize) -> std::io::Result<Vec<u8>> {
+    let mut buf = IoBuffer::aligned(round_up(len, alignment), alignment);
+    let size = sys::read_full_at(file, &mut buf[..], offset)?;
+    Ok(buf[..std::cmp::min(size, len)].to_vec())
+}
+
+
+// DirectBuffer
+
+/// Выровненный буфер записи `AsyncFileWrite` в режиме `direct_io`.
+///
+/// Данные накапливаются в буфере и записываются целыми выровненными блоками.
+/// Неполный последний блок записывается дополненным (байтами файла за ним или нулями),
+/// длина файла восстанавливается `set_len`, а позиция остается в начале этого блока,
+/// так что следующая запись перезапишет его вместе с новыми данными.
+pub struct DirectBuffer {
+    buf: Arc<RwLock<IoBuffer>>,
+    filled: usize,
+    /// Начало буфера, уже записанное в файл (неполный блок предыдущей записи)
+    counted: usize,
+    alignment: usize,
+}
+impl DirectBuffer {
+
+    pub fn new(buffer_size: usize, alignment: usize) -> DirectBuffer {
+        let len = round_up(std::cmp::max(buffer_size, 1), alignment);
+        DirectBuffer {
+            buf: Arc::new(RwLock::new(IoBuffer::aligned(len, alignment))),
+            filled: 0,
+            counted: 0,
+            alignment,
+        }
+    }
+
+    #[inline]
+    pub fn alignment(&self) -> usize {
+        self.alignment
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.buf.read().unwrap().len()
+    }
+
+    #[inline]
+    pub fn is_full(&self) -> bool {
+        self.filled == self.capacity()
+    }
+
+    /// Копирует в буфер сколько поместится из `src`
+    pub fn append(&mut self, src: &[u8]) -> usize {
+        let mut buf = self.buf.write().unwrap();
+        let size = std::cmp::min(src.len(), buf.len() - self.filled);
+        buf[self.filled..self.filled + size].copy_from_slice(&src[..size]);
+        self.filled += size;
+        size
+    }
+
+    /// Передает накопленные данные для записи в пуле потоков; в буфере остается
+    /// неполный последний блок (он будет записан повторно вместе со следующими данными)
+    pub fn take(&mut self) -> Option<DirectWrite> {
+        if self.filled == 0 {
+            return None;
+        }
+        let write = DirectWrite {
+            buf: self.buf.clone(),
+            filled: self.filled,
+            counted: self.counted,
+            alignment: self.alignment,
+        };
+        self.filled %= self.alignment;
+        self.counted = self.filled;
+        Some(write)
+    }
+
+    /// Возвращает в буфер данные записи, завершившейся ошибкой (см. `DirectWrite::write`):
+    /// буфер не изменяется, пока запись выполняется, поэтому достаточно прежних размеров
+    pub fn restore(&mut self, filled: usize, counted: usize) {
+        self.filled = filled;
+        self.counted = counted;
+    }
+
+    /// Отбрасывает неполный блок, уже записанный `take` (например, перед перемещением позиции)
+    #[inline]
+    pub fn clear(&mut self) {
+        self.filled = 0;
+        self.counted = 0;
+    }
+
+    /// Принятые данные, еще не записанные в файл
+    pub fn unwritten(&self) -> Vec<u8> {
+        self.buf.read().unwrap()[self.counted..self.filled].to_vec()
+    }
+
+    /// Количество данных в буфере
+    #[inline]
+    pub fn filled(&self) -> usize {
+        self.filled
+    }
+}
+
+
+// DirectWrite
+
+/// Запись накопленных данных `DirectBuffer`, выполняемая в пуле потоков
+pub struct DirectWrite {
+    buf: Arc<RwLock<IoBuffer>>,
+    filled: usize,
+    counted: usize,
+    alignment: usize,
+}
+impl DirectWrite {
+
+    /// Объем данных и уже записанное ранее начало (см. `DirectBuffer::restore`)
+    #[inline]
+    pub fn sizes(&self) -> (usize, usize) {
+        (self.filled, self.counted)
+    }
+
+    /// Данные, которые эта запись добавляет в файл впервые
+    #[inline]
+    pub fn fresh(&self) -> usize {
+        self.filled - self.counted
+    }
+
+    /// Записывает данные с текущей позиции (выровненной по `alignment`); возвращает длину
+    /// неполного последнего блока, на начале которого остается позиция.
+    /// При ошибке позиция возвращается в начало данных, чтобы запись можно было повторить.
+    pub fn write(&self, file: &mut std::fs::File) -> std::io::Result<usize> {
+        let start = file.stream_position()?;
+        self.write_blocks(file).inspect_err(|_| {
+            let _ = file.seek(SeekFrom::Start(start));
+        })
+    }
+
+    fn write_blocks(&self, file: &mut std::fs::File) -> std::io::Result<usize> {
+        let mut buf = self.buf.write().unwrap();
+        let aligned = self.filled / self.alignment * self.alignment;
+        let tail = self.filled - aligned;
+        file.write_all(&buf[..aligned])?;
+        if tail > 0 {
+            let padded = aligned + self.alignment;
+            let start = file.stream_position()?;
+            let old_len = file.metadata()?.len();
+            let end = start + tail as u64;
+            if old_len > end {
+                // байты файла за концом данных сохраняются
+                let mut block = IoBuffer::aligned(self.alignment, self.alignment);
+                sys::read_full_at(file, &mut block[..], start)?;
+                buf[self.filled..padded].copy_from_slice(&block[tail..]);
+            } else {
+                for byte in buf[self.filled..padded].iter_mut() {
+                    *byte = 0;
+                }
+            }
+            file.write_all(&buf[aligned..padded])?;
+            let len = std::cmp::max(old_len, end);
+            if start + (self.alignment as u64) > len {
+                sys::retry(|| file.set_len(len))?;
+            }
+            file.seek(SeekFrom::Start(start))?;
+            let tail_data = buf[aligned..self.filled].to_vec();
+            buf[..tail].copy_from_slice(&tail_data);
+        }
+        Ok(tail)
+    }
+}
@@ -0,0 +1,223 @@
+use futures::{Poll, Future, Stream};
+use futures::future::Loop;
+use futures_cpupool::CpuPool;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use super::DEFAULT_CPU_POOL;
+use walk::{walk_with_pool, WalkStream};
+use compare::files_equal_with_pool;
+use sys::long_path;
+
+static DEFAULT_DUPLICATES_CONCURRENCY: usize = 8;
+static DEFAULT_PARTIAL_HASH_SIZE: u64 = 4 * 1024;
+static HASH_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Группа файлов с одинаковым содержимым
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    pub size: u64,
+    /// Пути файлов группы в отсортированном порядке
+    pub paths: Vec<PathBuf>,
+}
+
+/// Хеш первых `limit` байт файла
+fn hash_file(path: &Path, limit: u64) -> std::io::Result<u64> {
+    let mut file = std::fs::File::open(long_path(path))?.take(limit);
+    let mut hasher = DefaultHasher::new();
+    let mut buf = vec![0u8; HASH_CHUNK_SIZE];
+    loop {
+        match file.read(&mut buf) {
+            Ok(0) => return Ok(hasher.finish()),
+            Ok(size) => hasher.write(&buf[..size]),
+            Err(ref error) if error.kind() == std::io::ErrorKind::Interrupted => {},
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Разбивает группы на подгруппы по хешу первых `limit` байт, отбрасывая файлы без пары.
+/// Файлы всех групп хешируются общим потоком заданий, не более `concurrency` одновременно;
+/// файлы, которые не удалось прочитать, пропускаются.
+fn split_groups(cpu_pool: &'static CpuPool, concurrency: usize, limit: u64, groups: Vec<DuplicateGroup>)
+    -> impl Future<Item = Vec<DuplicateGroup>, Error = std::io::Error> + Send
+{
+    let files = groups.into_iter()
+        .flat_map(|group| {
+            let size = group.size;
+            group.paths.into_iter().map(move |path| (size, path))
+        });
+    futures::stream::iter_ok(files)
+        .map(move |(size, path)| {
+            cpu_pool
+                .spawn_fn(move || hash_file(&path, limit).map(|hash| (size, hash, path)))
+                .then(|result| Ok::<_, std::io::Error>(result.ok()))
+        })
+        .buffer_unordered(concurrency)
+        .fold(HashMap::new(), |mut groups, hashed| {
+            if let Some((size, hash, path)) = hashed {
+                groups.entry((size, hash)).or_insert_with(Vec::new).push(path);
+            }
+            Ok::<_, std::io::Error>(groups)
+        })
+        .map(|groups: HashMap<(u64, u64), Vec<PathBuf>>| {
+            groups.into_iter()
+                .filter(|(_, paths)| paths.len() > 1)
+                .map(|((size, _), paths)| DuplicateGroup { size, paths })
+                .collect()
+        })
+}
+
+/// Подтверждает группу побайтовым сравнением: совпадение хешей не гарантирует совпадения
+/// содержимого. Файлы сравниваются с первым файлом группы по очереди, отличающиеся
+/// от него сравниваются между собой заново; файлы, которые не удалось прочитать, пропускаются.
+pub fn confirm_group(cpu_pool: &'static CpuPool, group: DuplicateGroup)
+    -> impl Future<Item = Vec<DuplicateGroup>, Error = std::io::Error> + Send
+{
+    let size = group.size;
+    futures::future::loop_fn((group.paths, Vec::new()), move |(mut paths, mut confirmed)| {
+        let first = paths.remove(0);
+        let reference = first.clone();
+        futures::stream::iter_ok(paths)
+            .and_then(move |path| {
+                files_equal_with_pool(cpu_pool, &reference, &path)
+                    .then(|result| Ok::<_, std::io::Error>((result.unwrap_or(false), path)))
+            })
+            .fold((vec![first], Vec::new()), |(mut equal, mut rest), (same, path)| {
+                if same {
+                    equal.push(path);
+                } else {
+                    rest.push(path);
+                }
+                Ok::<_, std::io::Error>((equal, rest))
+            })
+            .map(move |(mut paths, rest)| {
+                if paths.len() > 1 {
+                    paths.sort();
+                    confirmed.push(DuplicateGroup { size, paths });
+                }
+                if rest.len() > 1 {
+                    Loop::Continue((rest, confirmed))
+                } else {
+                    Loop::Break(confirmed)
+                }
+            })
+    })
+}
+
+
+// FindDuplicates
+
+/// Поток групп файлов-дубликатов в дереве каталогов.
+///
+/// Файлы сначала группируются по размеру, затем кандидаты сравниваются по хешу начала файла,
+/// после этого по хешу всего содержимого, и группа подтверждается побайтовым сравнением.
+/// Хеширование и сравнение выполняются в пуле потоков, при этом одновременно выполняется
+/// не более `concurrency` операций хеширования или сравнения файлов.
+/// Пустые файлы, символические ссылки и нечитаемые файлы не учитываются.
+pub struct FindDuplicates {
+    cpu_pool: &'static CpuPool,
+    walk: Option<WalkStream>,
+    concurrency: usize,
+    partial_size: u64,
+    inner: Option<Box<dyn Stream<Item = DuplicateGroup, Error = std::io::Error> + Send>>,
+}
+
+/// Находит файлы-дубликаты в дереве каталогов `dir`
+#[inline]
+pub fn find_duplicates<P: AsRef<Path>>(dir: P) -> FindDuplicates {
+    find_duplicates_with_pool(&DEFAULT_CPU_POOL, dir)
+}
+
+pub fn find_duplicates_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, dir: P) -> FindDuplicates {
+    FindDuplicates {
+        cpu_pool,
+        walk: Some(walk_with_pool(cpu_pool, dir)),
+        concurrency: DEFAULT_DUPLICATES_CONCURRENCY,
+        partial_size: DEFAULT_PARTIAL_HASH_SIZE,
+        inner: None,
+    }
+}
+
+impl FindDuplicates {
+
+    /// Задает максимальное количество одновременно хешируемых файлов
+    #[inline]
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        assert!(concurrency > 0, "concurrency must be greater than zero");
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Задает размер начала файла, по которому выполняется предварительное сравнение
+    #[inline]
+    pub fn partial_size(mut self, partial_size: u64) -> Self {
+        assert!(partial_size > 0, "partial size must be greater than zero");
+        self.partial_size = partial_size;
+        self
+    }
+
+    fn start(&mut self, walk: WalkStream) -> Box<dyn Stream<Item = DuplicateGroup, Error = std::io::Error> + Send> {
+        let cpu_pool = self.cpu_pool;
+        let concurrency = self.concurrency;
+        let partial_size = self.partial_size;
+        let by_size = walk
+            .then(|entry| Ok::<_, std::io::Error>(entry.ok()))
+            .fold(HashMap::new(), |mut groups, entry| {
+                if let Some(entry) = entry {
+                    let size = entry.metadata.len();
+                    if entry.metadata.is_file() && size > 0 {
+                        groups.entry(size).or_insert_with(Vec::new).push(entry.path);
+                    }
+                }
+                Ok::<_, std::io::Error>(groups)
+            })
+            .map(|groups: HashMap<u64, Vec<PathBuf>>| {
+                groups.into_iter()
+                    .filter(|(_, paths)| paths.len() > 1)
+                    .map(|(size, paths)| DuplicateGroup { size, paths })
+                    .collect()
+            });
+        // этапы выполняются по очереди, поэтому ограничение `concurrency` общее для всех файлов
+        let candidates = by_size
+            .and_then(move |groups| split_groups(cpu_pool, concurrency, partial_size, groups))
+            .and_then(move |groups| {
+                // начало файла совпадает со всем содержимым
+                let (small, large): (Vec<_>, Vec<_>) = groups.into_iter()
+                    .partition(|group| group.size <= partial_size);
+                split_groups(cpu_pool, concurrency, u64::MAX, large)
+                    .map(move |mut groups| {
+                        groups.extend(small);
+                        groups
+                    })
+            });
+        Box::new(candidates
+            .map(futures::stream::iter_ok)
+            .flatten_stream()
+            .map(move |group| confirm_group(cpu_pool, group))
+            .buffer_unordered(concurrency)
+            .map(futures::stream::iter_ok)
+            .flatten())
+    }
+}
+impl Stream for FindDuplicates {
+    type Item = DuplicateGroup;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Some(walk) = self.walk.take() {
+            self.inner = Some(self.start(walk));
+        }
+        self.inner.as_mut().unwrap().poll()
+    }
+}
+impl std::fmt::Debug for FindDuplicates {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("FindDuplicates")
+            .field("concurrency", &self.concurrency)
+            .field("partial_size", &self.partial_size)
+            .finish()
+    }
+}
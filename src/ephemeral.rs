@@ -0,0 +1,158 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::{DEFAULT_CPU_POOL, DEFAULT_BUFFER_SIZE, AsyncFileSink, AsyncFileStream, AsyncFileWrite};
+#[cfg(windows)]
+use options::FILE_FLAG_DELETE_ON_CLOSE;
+use sys::{long_path, retry};
+
+static EPHEMERAL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn ephemeral_path(dir: &Path) -> PathBuf {
+    dir.join(format!(".ephemeral-{}-{}", std::process::id(), EPHEMERAL_COUNTER.fetch_add(1, Ordering::SeqCst)))
+}
+
+/// Создает файл без имени (`O_TMPFILE`); `None`, если файловая система или ядро его не поддерживают
+#[cfg(target_os = "linux")]
+fn create_tmpfile(dir: &Path) -> std::io::Result<Option<std::fs::File>> {
+    use std::os::unix::fs::OpenOptionsExt;
+    let result = retry(|| std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_TMPFILE)
+        .mode(0o600)
+        .open(long_path(dir)));
+    match result {
+        Ok(file) => Ok(Some(file)),
+        Err(error) => match error.raw_os_error() {
+            Some(libc::EOPNOTSUPP) | Some(libc::EISDIR) | Some(libc::EINVAL) => Ok(None),
+            _ => Err(error),
+        },
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn create_tmpfile(_dir: &Path) -> std::io::Result<Option<std::fs::File>> {
+    Ok(None)
+}
+
+/// Создает файл, который не переживет своих дескрипторов
+#[cfg(not(windows))]
+pub fn create_ephemeral(dir: &Path) -> std::io::Result<std::fs::File> {
+    if let Some(file) = create_tmpfile(dir)? {
+        return Ok(file);
+    }
+    loop {
+        let path = ephemeral_path(dir);
+        let file = match retry(|| std::fs::OpenOptions::new().read(true).write(true).create_new(true).open(long_path(&path))) {
+            Ok(file) => file,
+            Err(ref error) if error.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        };
+        std::fs::remove_file(long_path(&path))?;
+        return Ok(file);
+    }
+}
+
+#[cfg(windows)]
+pub fn create_ephemeral(dir: &Path) -> std::io::Result<std::fs::File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    loop {
+        let path = ephemeral_path(dir);
+        let result = retry(|| std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .custom_flags(FILE_FLAG_DELETE_ON_CLOSE)
+            .open(long_path(&path)));
+        match result {
+            Err(ref error) if error.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            result => return result,
+        }
+    }
+}
+
+
+// EphemeralFile
+
+/// Файл без имени, удаляемый системой при закрытии последнего дескриптора,
+/// например, для сброса на диск данных, не помещающихся в памяти.
+///
+/// На Linux файл создается без имени (`O_TMPFILE`), если файловая система это поддерживает,
+/// на остальных Unix удаляется из каталога сразу после создания, на Windows создается
+/// с `FILE_FLAG_DELETE_ON_CLOSE`, поэтому файл не остается на диске даже при аварийном
+/// завершении процесса. Данные записываются и читаются через обычные асинхронные обертки
+/// (`sink`, `writer`, `stream`), использующие копии дескриптора.
+pub struct EphemeralFile {
+    cpu_pool: &'static CpuPool,
+    file: std::fs::File,
+}
+impl EphemeralFile {
+
+    /// Создает файл во временном каталоге системы
+    #[inline]
+    pub fn create() -> CpuFuture<EphemeralFile, std::io::Error> {
+        Self::create_in_with_pool(&DEFAULT_CPU_POOL, std::env::temp_dir())
+    }
+
+    pub fn create_with_pool(cpu_pool: &'static CpuPool) -> CpuFuture<EphemeralFile, std::io::Error> {
+        Self::create_in_with_pool(cpu_pool, std::env::temp_dir())
+    }
+
+    /// Создает файл в каталоге `dir`
+    #[inline]
+    pub fn create_in<P: AsRef<Path>>(dir: P) -> CpuFuture<EphemeralFile, std::io::Error> {
+        Self::create_in_with_pool(&DEFAULT_CPU_POOL, dir)
+    }
+
+    pub fn create_in_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, dir: P) -> CpuFuture<EphemeralFile, std::io::Error> {
+        let dir: PathBuf = dir.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            Ok(EphemeralFile {
+                cpu_pool,
+                file: create_ephemeral(&dir)?,
+            })
+        })
+    }
+
+    #[inline]
+    pub fn file(&self) -> &std::fs::File {
+        &self.file
+    }
+
+    /// Копия дескриптора файла (позиция в файле у копий общая)
+    pub fn try_clone_file(&self) -> std::io::Result<std::fs::File> {
+        self.file.try_clone()
+    }
+
+    /// Асинхронная запись с текущей позиции
+    pub fn sink(&self) -> std::io::Result<AsyncFileSink> {
+        Ok(AsyncFileSink::from_std(self.cpu_pool, self.try_clone_file()?))
+    }
+
+    /// Асинхронная запись с текущей позиции через буфер
+    pub fn writer(&self) -> std::io::Result<AsyncFileWrite> {
+        Ok(AsyncFileWrite::from_std(self.cpu_pool, self.try_clone_file()?, DEFAULT_BUFFER_SIZE))
+    }
+
+    /// Поток содержимого файла с начала; позиция переводится в начало файла,
+    /// поэтому запись к этому моменту должна быть завершена
+    pub fn stream(&self) -> std::io::Result<AsyncFileStream> {
+        use std::io::{Seek, SeekFrom};
+        let mut file = self.try_clone_file()?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(AsyncFileStream::from_std(self.cpu_pool, file, DEFAULT_BUFFER_SIZE))
+    }
+
+    #[inline]
+    pub fn into_std(self) -> std::fs::File {
+        self.file
+    }
+}
+impl std::fmt::Debug for EphemeralFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("EphemeralFile")
+            .field("file", &self.file)
+            .finish()
+    }
+}
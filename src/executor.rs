@@ -0,0 +1,121 @@
+use futures::Async;
+use futures_cpupool::CpuPool;
+use std::sync::{Arc, Mutex};
+use tokio::executor::Executor;
+use pool_task::PoolTask;
+use metrics::{self, MetricsSink};
+use super::DEFAULT_CPU_POOL;
+
+/// Исполнитель блокирующих операций с файлами.
+///
+/// Обертки (`AsyncFileRead`, `AsyncFileWrite`, `AsyncFileSink`, `AsyncFileStream`) выполняют
+/// в нем каждую операцию; по умолчанию это `&'static CpuPool`. Реализован для `CpuPool`,
+/// `Arc<CpuPool>` (пул, созданный во время работы), `TokioBlocking` и `PriorityPool`
+/// (очередь с приоритетами).
+pub trait BlockingExecutor: Clone + Send + Sync + 'static {
+    /// Выполняет задачу, которая может блокировать поток
+    fn execute(&self, job: Box<dyn FnOnce() + Send>);
+
+    /// Выполняет `f` и возвращает Future ее результата; паника в `f` превращается в ошибку.
+    /// Если Future освобождена до начала выполнения `f`, `f` не выполняется (см. `PoolTask::forget`).
+    #[inline]
+    fn spawn_blocking<F, T>(&self, f: F) -> PoolTask<T>
+        where F: FnOnce() -> std::io::Result<T> + Send + 'static,
+              T: Send + 'static
+    {
+        PoolTask::spawn(self, f)
+    }
+
+    /// Получатель метрик операций (см. `Metered`, `pool_metrics`), которому обертки
+    /// сообщают о прочитанных и записанных байтах
+    #[inline]
+    fn metrics(&self) -> Option<Arc<dyn MetricsSink>> {
+        None
+    }
+}
+
+impl BlockingExecutor for CpuPool {
+    fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+        let job = match metrics::registered_pool_metrics(self) {
+            Some(pool_metrics) => metrics::metered_job(pool_metrics, job),
+            None => job,
+        };
+        self.spawn_fn(move || {
+            job();
+            Ok::<(), ()>(())
+        }).forget();
+    }
+
+    fn metrics(&self) -> Option<Arc<dyn MetricsSink>> {
+        metrics::registered_pool_metrics(self).map(|pool_metrics| pool_metrics as Arc<dyn MetricsSink>)
+    }
+}
+
+impl<E: BlockingExecutor> BlockingExecutor for &'static E {
+    #[inline]
+    fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+        (**self).execute(job)
+    }
+
+    #[inline]
+    fn metrics(&self) -> Option<Arc<dyn MetricsSink>> {
+        (**self).metrics()
+    }
+}
+
+impl<E: BlockingExecutor> BlockingExecutor for Arc<E> {
+    #[inline]
+    fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+        (**self).execute(job)
+    }
+
+    #[inline]
+    fn metrics(&self) -> Option<Arc<dyn MetricsSink>> {
+        (**self).metrics()
+    }
+}
+
+
+// TokioBlocking
+
+/// Выполнение операций в пуле потоков tokio с пометкой `tokio_threadpool::blocking`:
+/// рабочий поток на время операции передает свои задачи другому потоку, поэтому отдельный
+/// пул не нужен.
+///
+/// Операция запускается как задача текущего исполнителя tokio. Вне пула потоков tokio
+/// (например, в `current_thread` или без исполнителя) она выполняется в `DEFAULT_CPU_POOL`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioBlocking;
+
+impl BlockingExecutor for TokioBlocking {
+    fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+        // задача остается доступной, если исполнитель tokio не примет ее
+        let job = Arc::new(Mutex::new(Some(job)));
+        let pending = job.clone();
+        let task = futures::future::poll_fn(move || {
+            match tokio_threadpool::blocking(|| {
+                let job = pending.lock().unwrap().take();
+                if let Some(job) = job {
+                    job();
+                }
+            }) {
+                Ok(Async::Ready(())) => Ok(Async::Ready(())),
+                Ok(Async::NotReady) => Ok(Async::NotReady),
+                // поток не принадлежит пулу tokio
+                Err(_) => {
+                    let job = pending.lock().unwrap().take();
+                    if let Some(job) = job {
+                        DEFAULT_CPU_POOL.execute(job);
+                    }
+                    Ok(Async::Ready(()))
+                },
+            }
+        });
+        if tokio::executor::DefaultExecutor::current().spawn(Box::new(task)).is_err() {
+            let job = job.lock().unwrap().take();
+            if let Some(job) = job {
+                DEFAULT_CPU_POOL.execute(job);
+            }
+        }
+    }
+}
@@ -0,0 +1,413 @@
+use futures::{Poll, Future, Async, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use super::{DEFAULT_CPU_POOL, DEFAULT_BUFFER_SIZE};
+use pool_registry::absolute_path;
+use watch::{watch_with_pool, Watch, FsEvent};
+use sys::{self, long_path, retry};
+
+static DEFAULT_FILE_CACHE_CAPACITY: usize = 256;
+
+/// Открытые файлы с вытеснением давно не использованных (см. `PageCache` в `paged`)
+struct CacheState {
+    /// Файл и отметка его последнего использования
+    files: HashMap<PathBuf, (CachedFile, u64)>,
+    /// Пути в порядке последнего использования
+    order: BTreeMap<u64, PathBuf>,
+    clock: u64,
+    capacity: usize,
+}
+impl CacheState {
+    fn get(&mut self, path: &Path) -> Option<CachedFile> {
+        self.clock += 1;
+        let clock = self.clock;
+        let file = match self.files.get_mut(path) {
+            Some(&mut (ref file, ref mut used)) => {
+                self.order.remove(used);
+                *used = clock;
+                file.clone()
+            },
+            None => return None,
+        };
+        self.order.insert(clock, path.into());
+        Some(file)
+    }
+
+    fn insert(&mut self, file: CachedFile) {
+        self.clock += 1;
+        let path = file.path.clone();
+        if let Some((_, used)) = self.files.insert(path.clone(), (file, self.clock)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.clock, path);
+        while self.files.len() > self.capacity {
+            let oldest = match self.order.keys().next() {
+                Some(&oldest) => oldest,
+                None => break,
+            };
+            if let Some(path) = self.order.remove(&oldest) {
+                self.files.remove(&path);
+            }
+        }
+    }
+
+    fn remove(&mut self, path: &Path) -> bool {
+        match self.files.remove(path) {
+            Some((_, used)) => {
+                self.order.remove(&used);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.files.clear();
+        self.order.clear();
+    }
+}
+
+/// Изменился ли файл с момента открытия (по размеру и времени изменения)
+fn is_stale(file: &CachedFile, metadata: &std::fs::Metadata) -> bool {
+    metadata.len() != file.len || metadata.modified().ok() != file.modified
+}
+
+
+// FileCache
+
+/// Кэш открытых файлов для чтения, например, для раздачи статических файлов.
+///
+/// `open` возвращает файл из кэша, если он не изменился с момента открытия: в пуле потоков
+/// выполняется только `stat` вместо `open`/`close`, а без проверки (`validate(false)`)
+/// файл из кэша возвращается без обращения к пулу. Измененный файл открывается заново.
+/// Кэш хранит `capacity` файлов и вытесняет давно не использованные; вытесненный файл
+/// закрывается, когда освобождены все его копии `CachedFile`.
+///
+/// Файлы читаются позиционно (`CachedFile::read_at`, `CachedFile::stream`), поэтому один
+/// открытый файл одновременно обслуживает любое количество читателей. Пути в кэше
+/// дополняются текущей директорией; символические ссылки не раскрываются.
+#[derive(Clone)]
+pub struct FileCache {
+    cpu_pool: &'static CpuPool,
+    state: Arc<Mutex<CacheState>>,
+    validate: bool,
+}
+impl FileCache {
+
+    #[inline]
+    pub fn new(capacity: usize) -> FileCache {
+        Self::with_pool(&DEFAULT_CPU_POOL, capacity)
+    }
+
+    pub fn with_pool(cpu_pool: &'static CpuPool, capacity: usize) -> FileCache {
+        assert!(capacity > 0, "file cache capacity must be greater than zero");
+        FileCache {
+            cpu_pool,
+            state: Arc::new(Mutex::new(CacheState {
+                files: HashMap::new(),
+                order: BTreeMap::new(),
+                clock: 0,
+                capacity,
+            })),
+            validate: true,
+        }
+    }
+
+    /// Проверять ли при `open`, что файл в кэше не изменился (по умолчанию - да).
+    /// Без проверки изменения обнаруживаются только через `watch` или `invalidate`.
+    #[inline]
+    pub fn validate(mut self, validate: bool) -> Self {
+        self.validate = validate;
+        self
+    }
+
+    /// Открывает файл для чтения или берет его из кэша
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> OpenCached {
+        let path = absolute_path(path.as_ref());
+        if !self.validate {
+            let cached = self.state.lock().unwrap().get(&path);
+            if let Some(cached) = cached {
+                return OpenCached { state: OpenCachedState::Ready(Some(cached)) };
+            }
+        }
+        let cpu_pool = self.cpu_pool;
+        let state = self.state.clone();
+        let validate = self.validate;
+        OpenCached {
+            state: OpenCachedState::Opening(cpu_pool.spawn_fn(move || {
+                if validate {
+                    let cached = state.lock().unwrap().get(&path);
+                    if let Some(cached) = cached {
+                        match std::fs::metadata(long_path(&path)) {
+                            Ok(ref metadata) if !is_stale(&cached, metadata) => return Ok(cached),
+                            _ => {},
+                        }
+                    }
+                }
+                let file = retry(|| std::fs::File::open(long_path(&path)))?;
+                let metadata = file.metadata()?;
+                let cached = CachedFile {
+                    cpu_pool,
+                    file: Arc::new(file),
+                    path,
+                    len: metadata.len(),
+                    modified: metadata.modified().ok(),
+                };
+                state.lock().unwrap().insert(cached.clone());
+                Ok(cached)
+            })),
+        }
+    }
+
+    /// Удаляет файл из кэша; возвращает `true`, если он был в кэше
+    pub fn invalidate<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.state.lock().unwrap().remove(&absolute_path(path.as_ref()))
+    }
+
+    pub fn clear(&self) {
+        self.state.lock().unwrap().clear();
+    }
+
+    /// Количество файлов в кэше
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.state.lock().unwrap().capacity
+    }
+
+    /// Поток изменений файлов каталога `dir` (см. `watch`), удаляющий измененные
+    /// и удаленные файлы из кэша; изменения передаются дальше после их учета.
+    /// Кэш обновляется, пока поток опрашивается.
+    pub fn watch<P: AsRef<Path>>(&self, dir: P) -> CacheWatch {
+        CacheWatch {
+            cache: self.clone(),
+            watch: watch_with_pool(self.cpu_pool, absolute_path(dir.as_ref())),
+        }
+    }
+}
+impl Default for FileCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_FILE_CACHE_CAPACITY)
+    }
+}
+impl std::fmt::Debug for FileCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("FileCache")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity())
+            .field("validate", &self.validate)
+            .finish()
+    }
+}
+
+
+// OpenCached
+
+enum OpenCachedState {
+    Ready(Option<CachedFile>),
+    Opening(CpuFuture<CachedFile, std::io::Error>),
+}
+
+/// Future открытия файла через `FileCache::open`
+pub struct OpenCached {
+    state: OpenCachedState,
+}
+impl Future for OpenCached {
+    type Item = CachedFile;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.state {
+            OpenCachedState::Ready(ref mut cached) => Ok(Async::Ready(cached.take().expect("poll an OpenCached after it's done"))),
+            OpenCachedState::Opening(ref mut future) => future.poll(),
+        }
+    }
+}
+impl std::fmt::Debug for OpenCached {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("OpenCached").finish()
+    }
+}
+
+
+// CachedFile
+
+/// Открытый файл из `FileCache` для позиционного чтения; копии разделяют дескриптор
+#[derive(Clone)]
+pub struct CachedFile {
+    cpu_pool: &'static CpuPool,
+    file: Arc<std::fs::File>,
+    path: PathBuf,
+    len: u64,
+    modified: Option<SystemTime>,
+}
+impl CachedFile {
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Размер файла на момент открытия
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Время изменения файла на момент открытия
+    #[inline]
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.modified
+    }
+
+    /// Дескриптор для операций, которых нет в `CachedFile` (например, `read_exact_at`)
+    #[inline]
+    pub fn file(&self) -> &Arc<std::fs::File> {
+        &self.file
+    }
+
+    /// Читает до `len` байт по смещению `offset` (меньше - только в конце файла)
+    pub fn read_at(&self, offset: u64, len: usize) -> CpuFuture<Bytes, std::io::Error> {
+        let file = self.file.clone();
+        self.cpu_pool.spawn_fn(move || {
+            let mut buf = vec![0; len];
+            let size = sys::read_full_at(&file, &mut buf, offset)?;
+            buf.truncate(size);
+            Ok(Bytes::from(buf))
+        })
+    }
+
+    /// Поток содержимого файла (в пределах размера на момент открытия)
+    #[inline]
+    pub fn stream(&self) -> CachedFileStream {
+        self.stream_range(0, self.len)
+    }
+
+    /// Поток `len` байт с позиции `offset` (например, для запросов с `Range`)
+    pub fn stream_range(&self, offset: u64, len: u64) -> CachedFileStream {
+        CachedFileStream {
+            file: self.clone(),
+            offset,
+            remaining: len,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            reading: None,
+        }
+    }
+}
+impl std::fmt::Debug for CachedFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CachedFile")
+            .field("path", &self.path)
+            .field("len", &self.len)
+            .field("modified", &self.modified)
+            .finish()
+    }
+}
+
+
+// CachedFileStream
+
+/// Поток фрагментов `CachedFile`, читаемых позиционно в пуле потоков
+pub struct CachedFileStream {
+    file: CachedFile,
+    offset: u64,
+    remaining: u64,
+    buffer_size: usize,
+    reading: Option<CpuFuture<Bytes, std::io::Error>>,
+}
+impl CachedFileStream {
+
+    /// Размер фрагмента (по умолчанию `DEFAULT_BUFFER_SIZE`)
+    #[inline]
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        assert!(buffer_size > 0, "buffer size must be greater than zero");
+        self.buffer_size = buffer_size;
+        self
+    }
+}
+impl Stream for CachedFileStream {
+    type Item = Bytes;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.reading.is_none() {
+            if self.remaining == 0 {
+                return Ok(Async::Ready(None));
+            }
+            let len = std::cmp::min(self.remaining, self.buffer_size as u64) as usize;
+            self.reading = Some(self.file.read_at(self.offset, len));
+        }
+        let chunk = try_ready!(self.reading.as_mut().unwrap().poll());
+        self.reading = None;
+        if chunk.is_empty() {
+            // файл укоротился после открытия
+            self.remaining = 0;
+            return Ok(Async::Ready(None));
+        }
+        self.offset += chunk.len() as u64;
+        self.remaining -= chunk.len() as u64;
+        Ok(Async::Ready(Some(chunk)))
+    }
+}
+impl std::fmt::Debug for CachedFileStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CachedFileStream")
+            .field("path", &self.file.path)
+            .field("offset", &self.offset)
+            .field("remaining", &self.remaining)
+            .finish()
+    }
+}
+
+
+// CacheWatch
+
+/// Поток изменений каталога, удаляющий измененные файлы из `FileCache` (см. `FileCache::watch`)
+pub struct CacheWatch {
+    cache: FileCache,
+    watch: Watch,
+}
+impl Stream for CacheWatch {
+    type Item = FsEvent;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let event = try_ready!(self.watch.poll());
+        match event {
+            Some(FsEvent::Created(ref path)) | Some(FsEvent::Modified(ref path)) | Some(FsEvent::Removed(ref path)) => {
+                self.cache.invalidate(path);
+            },
+            Some(FsEvent::Renamed { ref from, ref to }) => {
+                self.cache.invalidate(from);
+                self.cache.invalidate(to);
+            },
+            // события могли быть потеряны
+            Some(FsEvent::Rescan) => self.cache.clear(),
+            None => {},
+        }
+        Ok(Async::Ready(event))
+    }
+}
+impl std::fmt::Debug for CacheWatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("CacheWatch")
+            .field("path", &self.watch.path())
+            .finish()
+    }
+}
@@ -0,0 +1,129 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::path::{Path, PathBuf};
+use super::DEFAULT_CPU_POOL;
+use sys::long_path;
+
+/// Вид файла, включая специальные файлы Unix (`/dev/*`, каналы, сокеты)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+    /// Символьное устройство (`/dev/null`, `/dev/tty`): не имеет размера,
+    /// не поддерживает позиционное чтение и отображение в память
+    CharDevice,
+    /// Блочное устройство (`/dev/sda`): размер в метаданных равен нулю
+    BlockDevice,
+    /// Именованный канал
+    Fifo,
+    Socket,
+    Other,
+}
+impl FileKind {
+
+    #[cfg(unix)]
+    pub fn from_file_type(file_type: &std::fs::FileType) -> FileKind {
+        use std::os::unix::fs::FileTypeExt;
+        if file_type.is_file() {
+            FileKind::File
+        } else if file_type.is_dir() {
+            FileKind::Dir
+        } else if file_type.is_symlink() {
+            FileKind::Symlink
+        } else if file_type.is_char_device() {
+            FileKind::CharDevice
+        } else if file_type.is_block_device() {
+            FileKind::BlockDevice
+        } else if file_type.is_fifo() {
+            FileKind::Fifo
+        } else if file_type.is_socket() {
+            FileKind::Socket
+        } else {
+            FileKind::Other
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn from_file_type(file_type: &std::fs::FileType) -> FileKind {
+        if file_type.is_file() {
+            FileKind::File
+        } else if file_type.is_dir() {
+            FileKind::Dir
+        } else if file_type.is_symlink() {
+            FileKind::Symlink
+        } else {
+            FileKind::Other
+        }
+    }
+
+    /// Вид открытого файла
+    #[inline]
+    pub fn of(file: &std::fs::File) -> std::io::Result<FileKind> {
+        Ok(file.metadata()?.kind())
+    }
+
+    #[inline]
+    pub fn is_device(self) -> bool {
+        self == FileKind::CharDevice || self == FileKind::BlockDevice
+    }
+
+    /// Соответствует ли размер из метаданных объему данных, которые можно прочитать.
+    /// Для остальных видов чтение выполняется до конца данных без опоры на размер.
+    #[inline]
+    pub fn has_reliable_size(self) -> bool {
+        self == FileKind::File
+    }
+
+    /// Поддерживает ли файл позиционное чтение и перемещение позиции
+    #[inline]
+    pub fn is_seekable(self) -> bool {
+        self == FileKind::File || self == FileKind::BlockDevice
+    }
+}
+impl std::fmt::Display for FileKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match *self {
+            FileKind::File => "regular file",
+            FileKind::Dir => "directory",
+            FileKind::Symlink => "symbolic link",
+            FileKind::CharDevice => "character device",
+            FileKind::BlockDevice => "block device",
+            FileKind::Fifo => "fifo",
+            FileKind::Socket => "socket",
+            FileKind::Other => "special file",
+        })
+    }
+}
+
+/// Вид файла по метаданным
+pub trait FileKindExt {
+    fn kind(&self) -> FileKind;
+}
+impl FileKindExt for std::fs::Metadata {
+    #[inline]
+    fn kind(&self) -> FileKind {
+        FileKind::from_file_type(&self.file_type())
+    }
+}
+impl FileKindExt for std::fs::FileType {
+    #[inline]
+    fn kind(&self) -> FileKind {
+        FileKind::from_file_type(self)
+    }
+}
+
+/// Ошибка для операций, не применимых к специальным файлам
+pub fn unsupported_kind(kind: FileKind, operation: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("{} is not supported for a {}", operation, kind))
+}
+
+/// Определяет вид файла `path`, не переходя по символической ссылке
+#[inline]
+pub fn file_kind<P: AsRef<Path>>(path: P) -> CpuFuture<FileKind, std::io::Error> {
+    file_kind_with_pool(&DEFAULT_CPU_POOL, path)
+}
+
+pub fn file_kind_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<FileKind, std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || Ok(std::fs::symlink_metadata(long_path(&path))?.kind()))
+}
@@ -0,0 +1,320 @@
+use futures::{Poll, Future, Async, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use super::{DEFAULT_CPU_POOL, DEFAULT_BUFFER_SIZE};
+use timeout::Deadline;
+use file_kind::{FileKind, FileKindExt};
+use watch::{watch_with_pool, Watch, FsEvent};
+use sys::{self, long_path, retry};
+
+/// Интервал проверки файла после достижения его конца
+static DEFAULT_FOLLOW_INTERVAL_MS: u64 = 250;
+
+/// Событие потока `AsyncFileFollow`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FollowEvent {
+    /// Новые данные файла
+    Data(Bytes),
+    /// Файл ротирован: дальнейшие данные читаются из нового файла с начала
+    Rotated,
+}
+
+/// Идентификатор файла, по которому обнаруживается подмена файла по пути
+#[cfg(unix)]
+fn identity(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+enum FollowStep {
+    Data(Bytes),
+    Rotated,
+    /// Новых данных нет
+    Idle,
+}
+
+/// Открытый файл и позиция чтения, передаваемые в пул потоков и обратно
+struct Follower {
+    file: std::fs::File,
+    identity: Option<(u64, u64)>,
+    kind: FileKind,
+    position: u64,
+}
+impl Follower {
+    fn open(path: &Path, from_end: bool) -> std::io::Result<Follower> {
+        let file = retry(|| std::fs::File::open(long_path(path)))?;
+        let metadata = file.metadata()?;
+        let kind = metadata.kind();
+        Ok(Follower {
+            identity: identity(&metadata),
+            kind,
+            position: if from_end && kind.has_reliable_size() { metadata.len() } else { 0 },
+            file,
+        })
+    }
+
+    fn read(&mut self, buffer_size: usize) -> std::io::Result<Option<Bytes>> {
+        let mut buf = vec![0u8; buffer_size];
+        let size = if self.kind.is_seekable() {
+            sys::read_at(&self.file, &mut buf, self.position)?
+        } else {
+            retry(|| std::io::Read::read(&mut self.file, &mut buf))?
+        };
+        if size == 0 {
+            return Ok(None);
+        }
+        buf.truncate(size);
+        self.position += size as u64;
+        Ok(Some(buf.into()))
+    }
+
+    fn step(&mut self, path: &Path, buffer_size: usize) -> std::io::Result<FollowStep> {
+        if let Some(data) = self.read(buffer_size)? {
+            return Ok(FollowStep::Data(data));
+        }
+        // размер устройств и каналов не отражает объем данных: они читаются без проверок ротации
+        if !self.kind.has_reliable_size() {
+            return Ok(FollowStep::Idle);
+        }
+        // copytruncate: файл усечен на месте
+        if self.file.metadata()?.len() < self.position {
+            self.position = 0;
+            return Ok(FollowStep::Rotated);
+        }
+        // переименование и создание нового файла: старый файл дочитывается до конца
+        let metadata = match std::fs::metadata(long_path(path)) {
+            Ok(metadata) => metadata,
+            Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(FollowStep::Idle),
+            Err(error) => return Err(error),
+        };
+        let current = identity(&metadata);
+        if current.is_none() || current == self.identity {
+            return Ok(FollowStep::Idle);
+        }
+        if let Some(data) = self.read(buffer_size)? {
+            return Ok(FollowStep::Data(data));
+        }
+        *self = Follower::open(path, false)?;
+        Ok(FollowStep::Rotated)
+    }
+}
+
+
+// AsyncFileFollow
+
+enum FollowState {
+    Opening(CpuFuture<Follower, std::io::Error>),
+    Ready(Follower),
+    Reading(CpuFuture<(Follower, std::io::Result<FollowStep>), std::io::Error>),
+    Waiting(Follower, Deadline),
+    Swapping,
+}
+
+/// Поток данных файла в режиме `tail -f`: после достижения конца файла поток не завершается,
+/// а периодически проверяет файл в пуле потоков и передает дописанные данные.
+///
+/// Ротация файла обнаруживается в обоих вариантах logrotate: при переименовании и создании
+/// нового файла старый файл дочитывается до конца, после чего чтение продолжается
+/// с начала нового файла; при усечении файла на месте (`copytruncate`) чтение продолжается
+/// с начала файла. В обоих случаях в поток передается `FollowEvent::Rotated`.
+/// Подмена файла по пути обнаруживается только на Unix.
+///
+/// Устройства и именованные каналы читаются последовательно до появления данных,
+/// без опоры на размер и без обнаружения ротации.
+///
+/// С `watch_changes(true)` файл проверяется сразу после изменения (см. `Watch`),
+/// а интервал проверки остается запасным.
+pub struct AsyncFileFollow {
+    cpu_pool: &'static CpuPool,
+    path: PathBuf,
+    buffer_size: usize,
+    interval: Duration,
+    watch: Option<Watch>,
+    state: FollowState,
+}
+impl AsyncFileFollow {
+
+    /// Читает файл с начала
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> AsyncFileFollow {
+        Self::open_with_pool(&DEFAULT_CPU_POOL, path, false)
+    }
+
+    /// Читает только данные, дописанные в файл после открытия
+    #[inline]
+    pub fn open_at_end<P: AsRef<Path>>(path: P) -> AsyncFileFollow {
+        Self::open_with_pool(&DEFAULT_CPU_POOL, path, true)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P, from_end: bool) -> AsyncFileFollow {
+        let path: PathBuf = path.as_ref().into();
+        let opening_path = path.clone();
+        AsyncFileFollow {
+            cpu_pool,
+            path,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            interval: Duration::from_millis(DEFAULT_FOLLOW_INTERVAL_MS),
+            watch: None,
+            state: FollowState::Opening(cpu_pool.spawn_fn(move || Follower::open(&opening_path, from_end))),
+        }
+    }
+
+    /// Задает максимальный размер передаваемых фрагментов данных
+    #[inline]
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        assert!(buffer_size > 0, "buffer size must be greater than zero");
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    /// Задает интервал проверки файла после достижения его конца
+    #[inline]
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Наблюдать ли за каталогом файла, чтобы передавать дописанные данные и обнаруживать
+    /// ротацию без ожидания интервала проверки. Если наблюдение становится невозможным,
+    /// файл проверяется только с интервалом.
+    pub fn watch_changes(mut self, watch: bool) -> Self {
+        self.watch = if watch {
+            let dir = match self.path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            };
+            Some(watch_with_pool(self.cpu_pool, dir))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Изменялся ли файл по событиям наблюдения; текущая задача пробуждается
+    /// при следующем событии
+    fn poll_changed(&mut self) -> bool {
+        let name = self.path.file_name();
+        let concerns = |path: &Path| path.file_name() == name;
+        let mut changed = false;
+        let mut failed = false;
+        if let Some(ref mut watch) = self.watch {
+            loop {
+                match watch.poll() {
+                    Ok(Async::Ready(Some(event))) => {
+                        changed |= match event {
+                            FsEvent::Created(ref path) | FsEvent::Modified(ref path) | FsEvent::Removed(ref path) => concerns(path),
+                            FsEvent::Renamed { ref from, ref to } => concerns(from) || concerns(to),
+                            FsEvent::Rescan => true,
+                        };
+                    },
+                    Ok(Async::NotReady) => break,
+                    Ok(Async::Ready(None)) | Err(_) => {
+                        failed = true;
+                        break;
+                    },
+                }
+            }
+        }
+        if failed {
+            self.watch = None;
+        }
+        changed
+    }
+
+    /// Позиция чтения в текущем файле
+    pub fn position(&self) -> Option<u64> {
+        match self.state {
+            FollowState::Ready(ref follower) | FollowState::Waiting(ref follower, _) => Some(follower.position),
+            _ => None,
+        }
+    }
+}
+impl Stream for AsyncFileFollow {
+    type Item = FollowEvent;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            match std::mem::replace(&mut self.state, FollowState::Swapping) {
+                FollowState::Opening(mut future) => match future.poll() {
+                    Ok(Async::Ready(follower)) => self.state = FollowState::Ready(follower),
+                    Ok(Async::NotReady) => {
+                        self.state = FollowState::Opening(future);
+                        return Ok(Async::NotReady);
+                    },
+                    Err(error) => {
+                        // после ошибки открытия повторный опрос снова открывает файл
+                        let path = self.path.clone();
+                        self.state = FollowState::Opening(self.cpu_pool.spawn_fn(move || Follower::open(&path, false)));
+                        return Err(error);
+                    },
+                },
+                FollowState::Ready(mut follower) => {
+                    let path = self.path.clone();
+                    let buffer_size = self.buffer_size;
+                    self.state = FollowState::Reading(self.cpu_pool.spawn_fn(move || {
+                        let step = follower.step(&path, buffer_size);
+                        Ok((follower, step))
+                    }));
+                },
+                FollowState::Reading(mut future) => match future.poll() {
+                    Ok(Async::Ready((follower, Err(error)))) => {
+                        // после ошибки повторный опрос продолжает чтение с той же позиции
+                        self.state = FollowState::Ready(follower);
+                        return Err(error);
+                    },
+                    Ok(Async::Ready((follower, Ok(FollowStep::Data(data))))) => {
+                        self.state = FollowState::Ready(follower);
+                        return Ok(Async::Ready(Some(FollowEvent::Data(data))));
+                    },
+                    Ok(Async::Ready((follower, Ok(FollowStep::Rotated)))) => {
+                        self.state = FollowState::Ready(follower);
+                        return Ok(Async::Ready(Some(FollowEvent::Rotated)));
+                    },
+                    Ok(Async::Ready((follower, Ok(FollowStep::Idle)))) => {
+                        self.state = FollowState::Waiting(follower, Deadline::new(Instant::now() + self.interval));
+                    },
+                    Ok(Async::NotReady) => {
+                        self.state = FollowState::Reading(future);
+                        return Ok(Async::NotReady);
+                    },
+                    Err(error) => {
+                        // файл потерян вместе с операцией: он открывается по пути заново
+                        let path = self.path.clone();
+                        self.state = FollowState::Opening(self.cpu_pool.spawn_fn(move || Follower::open(&path, false)));
+                        return Err(error);
+                    },
+                },
+                FollowState::Waiting(follower, mut deadline) => {
+                    // события наблюдения опрашиваются в любом случае, чтобы не накапливать их
+                    let changed = self.poll_changed();
+                    if deadline.poll_elapsed() || changed {
+                        self.state = FollowState::Ready(follower);
+                    } else {
+                        self.state = FollowState::Waiting(follower, deadline);
+                        return Ok(Async::NotReady);
+                    }
+                },
+                FollowState::Swapping => unreachable!(),
+            }
+        }
+    }
+}
+impl std::fmt::Debug for AsyncFileFollow {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AsyncFileFollow")
+            .field("path", &self.path)
+            .field("buffer_size", &self.buffer_size)
+            .field("interval", &self.interval)
+            .field("watch", &self.watch.is_some())
+            .field("position", &self.position())
+            .finish()
+    }
+}
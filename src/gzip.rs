@@ -0,0 +1,340 @@
+use futures::{Poll, Future, Async, AsyncSink, Sink, StartSend};
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::{Bytes, BytesMut};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use flate2::bufread::{GzDecoder, MultiGzDecoder};
+use std::io::{BufRead, BufReader, Read, Write, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use sys::{long_path, retry, sync_all};
+
+/// Объем несжатых данных в одном gzip-блоке по умолчанию
+pub static DEFAULT_GZ_SPAN: usize = 1024 * 1024;
+
+static GZ_INDEX_HEADER: &str = "async_fs-gz-index 1";
+
+
+// GzIndex
+
+/// Точка входа в сжатый файл: начало независимого gzip-блока
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GzIndexEntry {
+    pub uncompressed_offset: u64,
+    pub compressed_offset: u64,
+}
+
+/// Индекс для произвольного доступа к gzip-файлу, состоящему из нескольких независимых блоков
+/// (такой файл является корректным gzip и распаковывается обычными средствами)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GzIndex {
+    pub entries: Vec<GzIndexEntry>,
+    pub uncompressed_len: u64,
+}
+impl GzIndex {
+
+    /// Строит индекс по существующему gzip-файлу, находя границы его блоков.
+    /// Для файла из одного блока индекс будет содержать одну точку входа.
+    pub fn scan<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<GzIndex, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let mut reader = CountingReader {
+                inner: BufReader::new(std::fs::File::open(long_path(&path))?),
+                position: 0,
+            };
+            let mut index = GzIndex::default();
+            while !reader.fill_buf()?.is_empty() {
+                let entry = GzIndexEntry {
+                    uncompressed_offset: index.uncompressed_len,
+                    compressed_offset: reader.position,
+                };
+                let len = std::io::copy(&mut GzDecoder::new(&mut reader), &mut std::io::sink())?;
+                if len > 0 || index.entries.is_empty() {
+                    index.entries.push(entry);
+                }
+                index.uncompressed_len += len;
+            }
+            Ok(index)
+        })
+    }
+
+    /// Загружает индекс из файла
+    pub fn load<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<GzIndex, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let mut data = String::new();
+            std::fs::File::open(long_path(&path))?.read_to_string(&mut data)?;
+            GzIndex::parse(&data)
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed gzip index"))
+        })
+    }
+
+    /// Сохраняет индекс в файл
+    pub fn save<P: AsRef<Path>>(&self, cpu_pool: &'static CpuPool, path: P) -> CpuFuture<(), std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        let data = self.to_string();
+        cpu_pool.spawn_fn(move || {
+            let mut file = std::fs::File::create(long_path(&path))?;
+            file.write_all(data.as_bytes())?;
+            retry(|| sync_all(&file))
+        })
+    }
+
+    fn parse(data: &str) -> Option<GzIndex> {
+        let mut lines = data.lines();
+        if lines.next()? != GZ_INDEX_HEADER {
+            return None;
+        }
+        let uncompressed_len = lines.next()?.trim().parse().ok()?;
+        let entries = lines
+            .map(|line| {
+                let mut fields = line.split_whitespace();
+                Some(GzIndexEntry {
+                    uncompressed_offset: fields.next()?.parse().ok()?,
+                    compressed_offset: fields.next()?.parse().ok()?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(GzIndex { entries, uncompressed_len })
+    }
+
+    /// Точка входа, с которой следует начинать распаковку для получения данных по смещению `offset`
+    pub fn lookup(&self, offset: u64) -> Option<GzIndexEntry> {
+        match self.entries.binary_search_by_key(&offset, |entry| entry.uncompressed_offset) {
+            Ok(i) => Some(self.entries[i]),
+            Err(0) => None,
+            Err(i) => Some(self.entries[i - 1]),
+        }
+    }
+}
+impl std::fmt::Display for GzIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", GZ_INDEX_HEADER)?;
+        writeln!(f, "{}", self.uncompressed_len)?;
+        for entry in &self.entries {
+            writeln!(f, "{} {}", entry.uncompressed_offset, entry.compressed_offset)?;
+        }
+        Ok(())
+    }
+}
+
+struct CountingReader<R> {
+    inner: R,
+    position: u64,
+}
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let size = self.inner.read(buf)?;
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+    fn consume(&mut self, amt: usize) {
+        self.position += amt as u64;
+        self.inner.consume(amt)
+    }
+}
+
+
+// IndexedGzSink
+
+/// Адаптер, сжимающий данные в gzip независимыми блоками и строящий индекс для произвольного доступа.
+///
+/// Каждые `span` байт несжатых данных сжимаются в пуле потоков отдельным gzip-блоком.
+/// Индекс доступен через `index()` после закрытия sink (`Sink::close`).
+pub struct IndexedGzSink<S> {
+    cpu_pool: &'static CpuPool,
+    inner: S,
+    span: usize,
+    level: Compression,
+    buf: BytesMut,
+    index: GzIndex,
+    compressed_len: u64,
+    compressing: Option<CpuFuture<Bytes, std::io::Error>>,
+    compressed: Option<Bytes>,
+    closed: bool,
+}
+impl<S> IndexedGzSink<S> {
+
+    pub fn new(cpu_pool: &'static CpuPool, inner: S) -> IndexedGzSink<S> {
+        IndexedGzSink {
+            cpu_pool,
+            inner,
+            span: DEFAULT_GZ_SPAN,
+            level: Compression::default(),
+            buf: BytesMut::new(),
+            index: GzIndex::default(),
+            compressed_len: 0,
+            compressing: None,
+            compressed: None,
+            closed: false,
+        }
+    }
+
+    /// Задает объем несжатых данных в одном блоке
+    #[inline]
+    pub fn span(mut self, span: usize) -> Self {
+        assert!(span > 0, "span must be greater than zero");
+        self.span = span;
+        self
+    }
+
+    /// Задает уровень сжатия
+    #[inline]
+    pub fn level(mut self, level: Compression) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Индекс уже сжатых блоков
+    #[inline]
+    pub fn index(&self) -> &GzIndex {
+        &self.index
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn compress(&mut self, data: Bytes) {
+        self.index.entries.push(GzIndexEntry {
+            uncompressed_offset: self.index.uncompressed_len,
+            compressed_offset: self.compressed_len,
+        });
+        self.index.uncompressed_len += data.len() as u64;
+        let level = self.level;
+        self.compressing = Some(self.cpu_pool.spawn_fn(move || {
+            let mut encoder = GzEncoder::new(Vec::with_capacity(data.len() / 2), level);
+            encoder.write_all(&data)?;
+            Ok(Bytes::from(encoder.finish()?))
+        }));
+    }
+}
+impl<S> IndexedGzSink<S>
+    where S: Sink<SinkItem = Bytes, SinkError = std::io::Error>
+{
+    fn poll_compressed(&mut self) -> Poll<(), std::io::Error> {
+        loop {
+            if let Some(compressed) = self.compressed.take() {
+                let len = compressed.len() as u64;
+                if let AsyncSink::NotReady(compressed) = self.inner.start_send(compressed)? {
+                    self.compressed = Some(compressed);
+                    return Ok(Async::NotReady);
+                }
+                self.compressed_len += len;
+            }
+            match self.compressing {
+                Some(ref mut future) => {
+                    self.compressed = Some(try_ready!(future.poll()));
+                },
+                None => return Ok(Async::Ready(())),
+            }
+            self.compressing = None;
+        }
+    }
+}
+impl<S> Sink for IndexedGzSink<S>
+    where S: Sink<SinkItem = Bytes, SinkError = std::io::Error>
+{
+    type SinkItem = Bytes;
+    type SinkError = std::io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if self.closed {
+            return Err(std::io::Error::other("gzip sink already closed"));
+        }
+        if self.buf.len() >= self.span {
+            if let Async::NotReady = self.poll_compressed()? {
+                return Ok(AsyncSink::NotReady(item));
+            }
+            let data = self.buf.split_to(self.span).freeze();
+            self.compress(data);
+        }
+        self.buf.extend_from_slice(&item);
+        Ok(AsyncSink::Ready)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        while self.buf.len() >= self.span {
+            try_ready!(self.poll_compressed());
+            let data = self.buf.split_to(self.span).freeze();
+            self.compress(data);
+        }
+        try_ready!(self.poll_compressed());
+        self.inner.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        try_ready!(self.poll_complete());
+        if !self.closed {
+            self.closed = true;
+            if !self.buf.is_empty() {
+                let data = self.buf.take().freeze();
+                self.compress(data);
+            }
+        }
+        try_ready!(self.poll_compressed());
+        self.inner.close()
+    }
+}
+impl<S> std::fmt::Debug for IndexedGzSink<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("IndexedGzSink")
+            .field("span", &self.span)
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+
+// IndexedGzFile
+
+/// Gzip-файл с индексом, позволяющим распаковывать данные начиная с ближайшего к смещению блока
+#[derive(Debug, Clone)]
+pub struct IndexedGzFile {
+    cpu_pool: &'static CpuPool,
+    path: PathBuf,
+    index: Arc<GzIndex>,
+}
+impl IndexedGzFile {
+
+    pub fn new<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P, index: GzIndex) -> IndexedGzFile {
+        IndexedGzFile {
+            cpu_pool,
+            path: path.as_ref().into(),
+            index: Arc::new(index),
+        }
+    }
+
+    #[inline]
+    pub fn index(&self) -> &GzIndex {
+        &self.index
+    }
+
+    /// Читает не более `len` байт несжатых данных начиная со смещения `offset`
+    pub fn read_range(&self, offset: u64, len: usize) -> CpuFuture<Bytes, std::io::Error> {
+        let path = self.path.clone();
+        let index = self.index.clone();
+        self.cpu_pool.spawn_fn(move || {
+            let entry = match index.lookup(offset) {
+                Some(entry) if offset < index.uncompressed_len => entry,
+                _ => return Ok(Bytes::new()),
+            };
+            let mut file = std::fs::File::open(long_path(&path))?;
+            file.seek(SeekFrom::Start(entry.compressed_offset))?;
+            let mut decoder = MultiGzDecoder::new(BufReader::new(file));
+            let skip = offset - entry.uncompressed_offset;
+            if std::io::copy(&mut (&mut decoder).take(skip), &mut std::io::sink())? < skip {
+                return Ok(Bytes::new());
+            }
+            let mut buf = Vec::with_capacity(len);
+            decoder.take(len as u64).read_to_end(&mut buf)?;
+            Ok(Bytes::from(buf))
+        })
+    }
+}
@@ -0,0 +1,314 @@
+use futures::{Poll, Future, Async, AsyncSink, Sink, Stream, StartSend};
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+#[cfg(feature = "crypto")] use sha2::{Sha256, Digest as Sha2Digest};
+
+/// Алгоритм контрольной суммы (см. `HashingStream`, `HashingSink`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    Crc32,
+    #[cfg(feature = "crypto")]
+    Sha256,
+}
+
+enum Hasher {
+    Crc32(crc32fast::Hasher),
+    #[cfg(feature = "crypto")]
+    Sha256(Sha256),
+}
+impl Hasher {
+    fn new(algorithm: HashAlgorithm) -> Hasher {
+        match algorithm {
+            HashAlgorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+            #[cfg(feature = "crypto")]
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match *self {
+            Hasher::Crc32(ref mut hasher) => hasher.update(data),
+            #[cfg(feature = "crypto")]
+            Hasher::Sha256(ref mut hasher) => hasher.input(data),
+        }
+    }
+
+    fn finish(self) -> Digest {
+        match self {
+            Hasher::Crc32(hasher) => Digest {
+                algorithm: HashAlgorithm::Crc32,
+                bytes: hasher.finalize().to_be_bytes().to_vec(),
+            },
+            #[cfg(feature = "crypto")]
+            Hasher::Sha256(hasher) => Digest {
+                algorithm: HashAlgorithm::Sha256,
+                bytes: hasher.result().to_vec(),
+            },
+        }
+    }
+}
+
+
+// Digest
+
+/// Контрольная сумма данных; `Display` выводит ее в шестнадцатеричном виде
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Digest {
+    algorithm: HashAlgorithm,
+    bytes: Vec<u8>,
+}
+impl Digest {
+
+    /// Контрольная сумма из байтов (например, для проверки `HashingStream::expect`)
+    pub fn new(algorithm: HashAlgorithm, bytes: Vec<u8>) -> Digest {
+        Digest {
+            algorithm,
+            bytes,
+        }
+    }
+
+    /// Контрольная сумма из шестнадцатеричной строки
+    pub fn from_hex(algorithm: HashAlgorithm, hex: &str) -> Option<Digest> {
+        if !hex.len().is_multiple_of(2) {
+            return None;
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+        Some(Digest::new(algorithm, bytes))
+    }
+
+    #[inline]
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Значение CRC32 (байты хранятся в порядке big-endian)
+    pub fn crc32(&self) -> Option<u32> {
+        match self.algorithm {
+            HashAlgorithm::Crc32 if self.bytes.len() == 4 => {
+                Some(self.bytes.iter().fold(0, |value, &byte| value << 8 | u32::from(byte)))
+            },
+            _ => None,
+        }
+    }
+}
+impl std::fmt::Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for byte in &self.bytes {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Последовательное вычисление контрольной суммы в пуле потоков:
+/// фрагмент хешируется, пока читается или записывается следующий
+struct Hashing {
+    cpu_pool: &'static CpuPool,
+    hasher: Option<Hasher>,
+    pending: Option<CpuFuture<Hasher, std::io::Error>>,
+    digest: Option<Digest>,
+}
+impl Hashing {
+    fn new(cpu_pool: &'static CpuPool, algorithm: HashAlgorithm) -> Hashing {
+        Hashing {
+            cpu_pool,
+            hasher: Some(Hasher::new(algorithm)),
+            pending: None,
+            digest: None,
+        }
+    }
+
+    /// Ожидает хеширование предыдущего фрагмента
+    fn poll_idle(&mut self) -> Poll<(), std::io::Error> {
+        if let Some(ref mut pending) = self.pending {
+            self.hasher = Some(try_ready!(pending.poll()));
+        }
+        self.pending = None;
+        Ok(Async::Ready(()))
+    }
+
+    /// Начинает хеширование фрагмента; предыдущее должно быть завершено (`poll_idle`)
+    fn start(&mut self, data: Bytes) {
+        if let Some(mut hasher) = self.hasher.take() {
+            self.pending = Some(self.cpu_pool.spawn_fn(move || {
+                hasher.update(&data);
+                Ok(hasher)
+            }));
+        }
+    }
+
+    fn poll_finish(&mut self) -> Poll<(), std::io::Error> {
+        try_ready!(self.poll_idle());
+        if let Some(hasher) = self.hasher.take() {
+            self.digest = Some(hasher.finish());
+        }
+        Ok(Async::Ready(()))
+    }
+}
+
+
+// HashingStream
+
+/// Адаптер, вычисляющий контрольную сумму данных, проходящих через поток
+/// (например, `AsyncFileStream`).
+///
+/// Фрагменты передаются дальше сразу, а хешируются в пуле потоков параллельно с чтением
+/// следующего фрагмента. После окончания потока контрольная сумма доступна через `digest`;
+/// если задана ожидаемая (`expect`), при несовпадении поток завершается ошибкой
+/// `InvalidData` вместо признака окончания.
+pub struct HashingStream<S> {
+    inner: S,
+    hashing: Hashing,
+    /// Фрагмент, ожидающий завершения хеширования предыдущего
+    chunk: Option<Bytes>,
+    ended: bool,
+    expected: Option<Digest>,
+}
+impl<S> HashingStream<S> {
+
+    pub fn new(cpu_pool: &'static CpuPool, inner: S, algorithm: HashAlgorithm) -> HashingStream<S> {
+        HashingStream {
+            inner,
+            hashing: Hashing::new(cpu_pool, algorithm),
+            chunk: None,
+            ended: false,
+            expected: None,
+        }
+    }
+
+    /// Проверяет контрольную сумму по окончании потока
+    #[inline]
+    pub fn expect(mut self, digest: Digest) -> Self {
+        self.expected = Some(digest);
+        self
+    }
+
+    /// Контрольная сумма всех данных; `None` до окончания потока
+    #[inline]
+    pub fn digest(&self) -> Option<&Digest> {
+        self.hashing.digest.as_ref()
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+impl<S> Stream for HashingStream<S>
+    where S: Stream<Item = Bytes, Error = std::io::Error>
+{
+    type Item = Bytes;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.hashing.digest.is_some() {
+            return Ok(Async::Ready(None));
+        }
+        if self.chunk.is_none() && !self.ended {
+            match try_ready!(self.inner.poll()) {
+                Some(chunk) => self.chunk = Some(chunk),
+                None => self.ended = true,
+            }
+        }
+        try_ready!(self.hashing.poll_idle());
+        if let Some(chunk) = self.chunk.take() {
+            self.hashing.start(chunk.clone());
+            return Ok(Async::Ready(Some(chunk)));
+        }
+        try_ready!(self.hashing.poll_finish());
+        match self.expected {
+            Some(ref expected) if Some(expected) != self.hashing.digest.as_ref() => {
+                Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "checksum mismatch"))
+            },
+            _ => Ok(Async::Ready(None)),
+        }
+    }
+}
+impl<S> std::fmt::Debug for HashingStream<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("HashingStream")
+            .field("digest", &self.hashing.digest)
+            .field("expected", &self.expected)
+            .finish()
+    }
+}
+
+
+// HashingSink
+
+/// Адаптер, вычисляющий контрольную сумму данных, проходящих в нижележащий sink
+/// (например, `AsyncFileSink`).
+///
+/// Элемент хешируется в пуле потоков, пока записывается; следующий элемент принимается
+/// после завершения хеширования предыдущего. Контрольная сумма доступна через `digest`
+/// после закрытия sink (`Sink::close`).
+pub struct HashingSink<S> {
+    inner: S,
+    hashing: Hashing,
+}
+impl<S> HashingSink<S> {
+
+    pub fn new(cpu_pool: &'static CpuPool, inner: S, algorithm: HashAlgorithm) -> HashingSink<S> {
+        HashingSink {
+            inner,
+            hashing: Hashing::new(cpu_pool, algorithm),
+        }
+    }
+
+    /// Контрольная сумма всех принятых данных; `None` до закрытия sink
+    #[inline]
+    pub fn digest(&self) -> Option<&Digest> {
+        self.hashing.digest.as_ref()
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+impl<S> Sink for HashingSink<S>
+    where S: Sink<SinkItem = Bytes, SinkError = std::io::Error>
+{
+    type SinkItem = Bytes;
+    type SinkError = std::io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if self.hashing.digest.is_some() {
+            return Err(std::io::Error::other("hashing sink already closed"));
+        }
+        if let Async::NotReady = self.hashing.poll_idle()? {
+            return Ok(AsyncSink::NotReady(item));
+        }
+        let result = self.inner.start_send(item.clone())?;
+        if result.is_ready() {
+            self.hashing.start(item);
+        }
+        Ok(result)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        try_ready!(self.inner.poll_complete());
+        self.hashing.poll_idle()
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        try_ready!(self.inner.close());
+        self.hashing.poll_finish()
+    }
+}
+impl<S> std::fmt::Debug for HashingSink<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("HashingSink")
+            .field("digest", &self.hashing.digest)
+            .finish()
+    }
+}
@@ -0,0 +1,183 @@
+use futures::{Poll, Async, Stream};
+use std::collections::VecDeque;
+
+/// Количество байт в строке дампа
+static HEXDUMP_LINE_SIZE: usize = 16;
+
+/// Форматирует данные строками в стиле `hexdump -C`: смещение, байты в шестнадцатеричном виде
+/// и их ASCII-представление (непечатаемые символы заменяются точкой).
+/// Смещения строк отсчитываются от `offset`.
+pub fn format_hexdump(data: &[u8], offset: u64) -> Vec<String> {
+    data.chunks(HEXDUMP_LINE_SIZE).enumerate().map(|(index, line)| {
+        let mut hex = String::with_capacity(HEXDUMP_LINE_SIZE * 3 + 1);
+        for i in 0..HEXDUMP_LINE_SIZE {
+            if i == HEXDUMP_LINE_SIZE / 2 {
+                hex.push(' ');
+            }
+            match line.get(i) {
+                Some(byte) => hex.push_str(&format!("{:02x} ", byte)),
+                None => hex.push_str("   "),
+            }
+        }
+        let ascii: String = line.iter()
+            .map(|&byte| if (0x20..0x7f).contains(&byte) { byte as char } else { '.' })
+            .collect();
+        format!("{:08x}  {} |{}|", offset + (index * HEXDUMP_LINE_SIZE) as u64, hex, ascii)
+    }).collect()
+}
+
+
+// Hexdump
+
+/// Поток, передающий блоки исходного потока без изменений и выводящий их дамп
+/// (см. `format_hexdump`), например, для отладки разбора кадров.
+///
+/// По умолчанию дамп выводится в stderr и только в отладочной сборке; вывод задается
+/// `output`, а включается и выключается `enabled`. Смещения отсчитываются от начала потока.
+pub struct Hexdump<S> {
+    inner: S,
+    enabled: bool,
+    label: String,
+    output: Box<dyn FnMut(&str) + Send>,
+    offset: u64,
+}
+
+/// Выводит дамп блоков потока (см. `Hexdump`)
+pub fn hexdump<S>(stream: S) -> Hexdump<S>
+    where S: Stream,
+          S::Item: AsRef<[u8]>
+{
+    Hexdump {
+        inner: stream,
+        enabled: cfg!(debug_assertions),
+        label: String::new(),
+        output: Box::new(|line| eprintln!("{}", line)),
+        offset: 0,
+    }
+}
+
+impl<S> Hexdump<S> {
+
+    /// Включает или выключает вывод дампа
+    #[inline]
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    /// Задает префикс строк дампа, чтобы различать несколько потоков
+    #[inline]
+    pub fn label(mut self, label: &str) -> Self {
+        self.label = label.to_string();
+        self
+    }
+
+    /// Задает получателя строк дампа вместо stderr
+    #[inline]
+    pub fn output<F: FnMut(&str) + Send + 'static>(mut self, output: F) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+impl<S> Stream for Hexdump<S>
+    where S: Stream,
+          S::Item: AsRef<[u8]>
+{
+    type Item = S::Item;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let item = try_ready!(self.inner.poll());
+        if let Some(ref chunk) = item {
+            let data = chunk.as_ref();
+            if self.enabled {
+                for line in format_hexdump(data, self.offset) {
+                    if self.label.is_empty() {
+                        (self.output)(&line);
+                    } else {
+                        (self.output)(&format!("{} {}", self.label, line));
+                    }
+                }
+            }
+            self.offset += data.len() as u64;
+        }
+        Ok(Async::Ready(item))
+    }
+}
+impl<S> std::fmt::Debug for Hexdump<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Hexdump")
+            .field("enabled", &self.enabled)
+            .field("label", &self.label)
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
+
+
+// HexdumpLines
+
+/// Поток строк дампа блоков исходного потока (см. `format_hexdump`)
+pub struct HexdumpLines<S> {
+    inner: S,
+    offset: u64,
+    lines: VecDeque<String>,
+}
+
+/// Преобразует блоки потока в строки дампа (см. `HexdumpLines`)
+pub fn hexdump_lines<S>(stream: S) -> HexdumpLines<S>
+    where S: Stream,
+          S::Item: AsRef<[u8]>
+{
+    HexdumpLines {
+        inner: stream,
+        offset: 0,
+        lines: VecDeque::new(),
+    }
+}
+
+impl<S> Stream for HexdumpLines<S>
+    where S: Stream,
+          S::Item: AsRef<[u8]>
+{
+    type Item = String;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(line) = self.lines.pop_front() {
+                return Ok(Async::Ready(Some(line)));
+            }
+            match try_ready!(self.inner.poll()) {
+                Some(chunk) => {
+                    let data = chunk.as_ref();
+                    self.lines = format_hexdump(data, self.offset).into();
+                    self.offset += data.len() as u64;
+                },
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+impl<S> std::fmt::Debug for HexdumpLines<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("HexdumpLines")
+            .field("offset", &self.offset)
+            .finish()
+    }
+}
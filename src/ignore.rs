@@ -0,0 +1,201 @@
+use std::path::Path;
+use walk::WalkEntry;
+
+/// Сопоставляет путь с шаблоном в стиле gitignore: `*`, `?`, `[...]` и `**`
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(&'*') if pattern.get(1) == Some(&'*') => {
+            if pattern.get(2) == Some(&'/') {
+                // "**/" соответствует нулю или нескольким каталогам
+                let rest = &pattern[3..];
+                glob_match(rest, text) || (0..text.len())
+                    .any(|i| text[i] == '/' && glob_match(rest, &text[i + 1..]))
+            } else {
+                let rest = &pattern[2..];
+                (0..text.len() + 1).any(|i| glob_match(rest, &text[i..]))
+            }
+        },
+        Some(&'*') => {
+            let rest = &pattern[1..];
+            for i in 0..text.len() + 1 {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if i < text.len() && text[i] == '/' {
+                    break;
+                }
+            }
+            false
+        },
+        Some(&'?') => !text.is_empty() && text[0] != '/' && glob_match(&pattern[1..], &text[1..]),
+        Some(&'[') => {
+            match (text.first(), match_class(&pattern[1..])) {
+                (Some(&c), Some((set, negated, len))) if c != '/' => {
+                    set.iter().any(|&(from, to)| from <= c && c <= to) != negated
+                        && glob_match(&pattern[len + 1..], &text[1..])
+                },
+                (Some(&c), None) => c == '[' && glob_match(&pattern[1..], &text[1..]),
+                _ => false,
+            }
+        },
+        Some(&'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &text[1..])
+        },
+        Some(&c) => text.first() == Some(&c) && glob_match(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Класс символов: диапазоны, признак отрицания и длина до `]` включительно
+type CharClass = (Vec<(char, char)>, bool, usize);
+
+/// Разбирает класс символов после `[`
+fn match_class(pattern: &[char]) -> Option<CharClass> {
+    let mut i = 0;
+    let negated = match pattern.first() {
+        Some(&'!') | Some(&'^') => {
+            i += 1;
+            true
+        },
+        _ => false,
+    };
+    let mut set = Vec::new();
+    let start = i;
+    while i < pattern.len() {
+        let c = pattern[i];
+        if c == ']' && i > start {
+            return Some((set, negated, i + 1));
+        }
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            set.push((c, pattern[i + 2]));
+            i += 3;
+        } else {
+            set.push((c, c));
+            i += 1;
+        }
+    }
+    None
+}
+
+struct IgnorePattern {
+    pattern: Vec<char>,
+    negated: bool,
+    dir_only: bool,
+    /// Шаблон содержит `/` и сопоставляется с путем относительно корня, а не с именем
+    anchored: bool,
+}
+impl IgnorePattern {
+    fn parse(line: &str) -> Option<IgnorePattern> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = if let Some(line) = line.strip_prefix('!') {
+            (true, line)
+        } else if line.starts_with("\\!") || line.starts_with("\\#") {
+            (false, &line[1..])
+        } else {
+            (false, line)
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        if line.is_empty() {
+            return None;
+        }
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        Some(IgnorePattern {
+            pattern: line.chars().collect(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, relative: &[char], name: &[char], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        glob_match(&self.pattern, if self.anchored { relative } else { name })
+    }
+}
+
+
+// IgnoreRules
+
+/// Предикат исключения записи (см. `IgnoreRules::exclude_if`)
+type EntryPredicate = Box<dyn Fn(&WalkEntry) -> bool + Send + Sync>;
+
+/// Правила исключения записей при обходе дерева каталогов:
+/// шаблоны в стиле gitignore (относительно корня обхода) и произвольные предикаты.
+///
+/// Исключенные каталоги не читаются, поэтому их содержимое не обходится.
+pub struct IgnoreRules {
+    patterns: Vec<IgnorePattern>,
+    predicates: Vec<EntryPredicate>,
+}
+impl IgnoreRules {
+
+    #[inline]
+    pub fn new() -> IgnoreRules {
+        IgnoreRules {
+            patterns: Vec::new(),
+            predicates: Vec::new(),
+        }
+    }
+
+    /// Добавляет шаблон (одну строку в формате gitignore, например `target/` или `!keep.log`)
+    pub fn pattern(mut self, pattern: &str) -> Self {
+        self.patterns.extend(IgnorePattern::parse(pattern));
+        self
+    }
+
+    /// Добавляет шаблоны из содержимого файла в формате gitignore
+    pub fn patterns(mut self, content: &str) -> Self {
+        self.patterns.extend(content.lines().filter_map(IgnorePattern::parse));
+        self
+    }
+
+    /// Добавляет предикат: запись исключается, если он возвращает `true`
+    pub fn exclude_if<F>(mut self, predicate: F) -> Self
+        where F: Fn(&WalkEntry) -> bool + Send + Sync + 'static
+    {
+        self.predicates.push(Box::new(predicate));
+        self
+    }
+
+    /// Проверяет, исключается ли запись; `relative` - путь записи относительно корня обхода
+    pub fn is_ignored(&self, entry: &WalkEntry, relative: &Path) -> bool {
+        let relative: Vec<char> = relative.components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join("/")
+            .chars()
+            .collect();
+        let name = match relative.iter().rposition(|&c| c == '/') {
+            Some(i) => &relative[i + 1..],
+            None => &relative[..],
+        };
+        let is_dir = entry.metadata.is_dir();
+        let ignored = self.patterns.iter()
+            .rev()
+            .find(|pattern| pattern.matches(&relative, name, is_dir))
+            .is_some_and(|pattern| !pattern.negated);
+        ignored || self.predicates.iter().any(|predicate| predicate(entry))
+    }
+}
+impl Default for IgnoreRules {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl std::fmt::Debug for IgnoreRules {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("IgnoreRules")
+            .field("patterns", &self.patterns.len())
+            .field("predicates", &self.predicates.len())
+            .finish()
+    }
+}
@@ -0,0 +1,268 @@
+use futures::{Poll, Future, Async};
+use tokio::io::{AsyncRead, AsyncWrite};
+use bytes::{Buf, IntoBuf};
+use std::ops::DerefMut;
+
+static DEFAULT_COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+
+// Copy
+
+/// Future копирования данных из `AsyncRead` в `AsyncWrite`.
+///
+/// В отличие от `tokio::io::copy` использует кольцевой буфер, который переиспользуется
+/// на протяжении всего копирования: чтение в свободную часть буфера и запись заполненной части
+/// выполняются одновременно, а если данные в буфере "перевалили" через его конец,
+/// то запись выполняется векторно (`write_buf`) одним вызовом для обоих сегментов.
+///
+/// Результатом является количество скопированных байт, а также исходные reader и writer.
+pub struct Copy<R, W> {
+    reader: Option<R>,
+    writer: Option<W>,
+    buf: Box<dyn DerefMut<Target = [u8]> + Send>,
+    start: usize,
+    len: usize,
+    limit: Option<u64>,
+    read_done: bool,
+    amt: u64,
+}
+
+/// Копирует все данные из `reader` в `writer`
+pub fn copy<R, W>(reader: R, writer: W) -> Copy<R, W>
+    where R: AsyncRead,
+          W: AsyncWrite,
+{
+    Copy {
+        reader: Some(reader),
+        writer: Some(writer),
+        buf: Box::new(vec![0u8; DEFAULT_COPY_BUFFER_SIZE]),
+        start: 0,
+        len: 0,
+        limit: None,
+        read_done: false,
+        amt: 0,
+    }
+}
+
+impl<R, W> Copy<R, W> {
+
+    /// Задает размер кольцевого буфера
+    #[inline]
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        assert!(buffer_size > 0, "buffer size must be greater than zero");
+        assert!(self.len == 0 && self.amt == 0, "`Copy` already started");
+        self.buf = Box::new(vec![0u8; buffer_size]);
+        self
+    }
+
+    /// Задает собственный кольцевой буфер, например, `PooledBuffer` на больших страницах
+    pub fn buffer<B: DerefMut<Target = [u8]> + Send + 'static>(mut self, buf: B) -> Self {
+        assert!(!buf.is_empty(), "buffer size must be greater than zero");
+        assert!(self.len == 0 && self.amt == 0, "`Copy` already started");
+        self.buf = Box::new(buf);
+        self
+    }
+
+    /// Ограничивает количество копируемых байт
+    #[inline]
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    fn can_read(&self) -> bool {
+        !self.read_done && self.len < self.buf.len() && self.limit.is_none_or(|limit| limit > 0)
+    }
+}
+
+impl<R, W> Future for Copy<R, W>
+    where R: AsyncRead,
+          W: AsyncWrite,
+{
+    type Item = (u64, R, W);
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let mut progress = false;
+
+            if self.can_read() {
+                let cap = self.buf.len();
+                if self.len == 0 {
+                    self.start = 0;
+                }
+                let tail = (self.start + self.len) % cap;
+                let mut end = if tail >= self.start { cap } else { self.start };
+                if let Some(limit) = self.limit {
+                    if ((end - tail) as u64) > limit {
+                        end = tail + limit as usize;
+                    }
+                }
+                let reader = self.reader.as_mut().unwrap();
+                if let Async::Ready(size) = reader.poll_read(&mut self.buf[tail..end])? {
+                    if size == 0 {
+                        self.read_done = true;
+                    } else {
+                        self.len += size;
+                        if let Some(ref mut limit) = self.limit {
+                            *limit -= size as u64;
+                        }
+                    }
+                    progress = true;
+                }
+            }
+
+            if self.len > 0 {
+                let cap = self.buf.len();
+                let end = self.start + self.len;
+                let size = {
+                    let head = &self.buf[self.start..std::cmp::min(end, cap)];
+                    let wrapped = &self.buf[..end.saturating_sub(cap)];
+                    let mut chunks = head.into_buf().chain(wrapped);
+                    let writer = self.writer.as_mut().unwrap();
+                    match writer.write_buf(&mut chunks)? {
+                        Async::Ready(0) => {
+                            return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "write zero byte into writer"));
+                        },
+                        Async::Ready(size) => size,
+                        Async::NotReady => 0,
+                    }
+                };
+                if size > 0 {
+                    self.start = (self.start + size) % cap;
+                    self.len -= size;
+                    self.amt += size as u64;
+                    progress = true;
+                }
+            }
+
+            if self.len == 0 && !self.can_read() {
+                try_ready!(self.writer.as_mut().unwrap().poll_flush());
+                let reader = self.reader.take().unwrap();
+                let writer = self.writer.take().unwrap();
+                return Ok(Async::Ready((self.amt, reader, writer)));
+            }
+
+            if !progress {
+                return Ok(Async::NotReady);
+            }
+        }
+    }
+}
+impl<R, W> std::fmt::Debug for Copy<R, W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Copy")
+            .field("amt", &self.amt)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+
+// AsyncBufWriter
+
+/// Буферизованная запись в `AsyncWrite`.
+///
+/// Мелкие записи накапливаются во внутреннем буфере и передаются `writer` (например,
+/// `AsyncFileWrite`, каждая запись которого - обращение к пулу потоков) только при заполнении
+/// буфера, а также при `flush` и `shutdown`. Запись не меньше размера буфера при пустом буфере
+/// передается напрямую.
+pub struct AsyncBufWriter<W> {
+    writer: W,
+    buf: Vec<u8>,
+    written: usize,
+}
+
+impl<W: AsyncWrite> AsyncBufWriter<W> {
+
+    #[inline]
+    pub fn new(writer: W) -> AsyncBufWriter<W> {
+        Self::with_capacity(super::DEFAULT_BUFFER_SIZE, writer)
+    }
+
+    pub fn with_capacity(capacity: usize, writer: W) -> AsyncBufWriter<W> {
+        assert!(capacity > 0, "buffer size must be greater than zero");
+        AsyncBufWriter {
+            writer,
+            buf: Vec::with_capacity(capacity),
+            written: 0,
+        }
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Запись напрямую в `writer` в обход буфера нарушит порядок данных
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    /// Данные, еще не переданные `writer`
+    #[inline]
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf[self.written..]
+    }
+
+    /// Возвращает `writer`; данные, еще не переданные ему, теряются
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Передает `writer` накопленные данные
+    pub fn poll_flush_buf(&mut self) -> Poll<(), std::io::Error> {
+        while self.written < self.buf.len() {
+            match self.writer.write(&self.buf[self.written..]) {
+                Ok(0) => return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "write zero byte into writer")),
+                Ok(size) => self.written += size,
+                Err(ref error) if error.kind() == std::io::ErrorKind::WouldBlock => return Ok(Async::NotReady),
+                Err(error) => return Err(error),
+            }
+        }
+        self.buf.clear();
+        self.written = 0;
+        Ok(Async::Ready(()))
+    }
+}
+
+impl<W: AsyncWrite> std::io::Write for AsyncBufWriter<W> {
+    fn write(&mut self, src: &[u8]) -> std::io::Result<usize> {
+        if self.buf.len() + src.len() > self.buf.capacity() {
+            if let Async::NotReady = self.poll_flush_buf()? {
+                return Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "block"));
+            }
+        }
+        if src.len() >= self.buf.capacity() {
+            self.writer.write(src)
+        } else {
+            self.buf.extend_from_slice(src);
+            Ok(src.len())
+        }
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let Async::NotReady = self.poll_flush_buf()? {
+            return Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "block"));
+        }
+        self.writer.flush()
+    }
+}
+
+impl<W: AsyncWrite> AsyncWrite for AsyncBufWriter<W> {
+    fn shutdown(&mut self) -> Poll<(), std::io::Error> {
+        try_ready!(self.poll_flush_buf());
+        self.writer.shutdown()
+    }
+}
+
+impl<W: std::fmt::Debug> std::fmt::Debug for AsyncBufWriter<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AsyncBufWriter")
+            .field("writer", &self.writer)
+            .field("buffered", &(self.buf.len() - self.written))
+            .field("capacity", &self.buf.capacity())
+            .finish()
+    }
+}
@@ -0,0 +1,375 @@
+use futures::{Poll, Future, Async, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use super::DEFAULT_CPU_POOL;
+use sys::{long_path, retry, sync_all};
+use timeout::Deadline;
+
+static LEASE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Уникальный идентификатор владельца аренды: процесс, счетчик и время создания
+pub fn lease_owner_token() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!("{}-{}-{}.{:09}", std::process::id(), LEASE_COUNTER.fetch_add(1, Ordering::SeqCst), now.as_secs(), now.subsec_nanos())
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> std::io::Result<PathBuf> {
+    let name = path.file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name"))?;
+    Ok(path.with_file_name(format!(".{}.{}-{}-{}", name.to_string_lossy(), suffix, std::process::id(), LEASE_COUNTER.fetch_add(1, Ordering::SeqCst))))
+}
+
+fn lease_lost() -> std::io::Error {
+    std::io::Error::other("lease has been lost")
+}
+
+/// Сведения об аренде, записанные в файл аренды
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeaseInfo {
+    /// Идентификатор владельца
+    pub owner: String,
+    /// Время окончания аренды
+    pub expires_at: SystemTime,
+}
+impl LeaseInfo {
+
+    /// Истекла ли аренда (владелец не продлил ее вовремя)
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= SystemTime::now()
+    }
+
+    pub fn to_content(&self) -> String {
+        let expires_at = self.expires_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        format!("owner: {}\nexpires_at: {}.{:09}\n", self.owner, expires_at.as_secs(), expires_at.subsec_nanos())
+    }
+
+    pub fn parse(content: &str) -> Option<LeaseInfo> {
+        let mut owner = None;
+        let mut expires_at = None;
+        for line in content.lines() {
+            if let Some(value) = line.strip_prefix("owner: ") {
+                owner = Some(value.to_string());
+            } else if let Some(value) = line.strip_prefix("expires_at: ") {
+                let mut parts = value.splitn(2, '.');
+                let secs: u64 = parts.next()?.parse().ok()?;
+                let nanos: u32 = parts.next().unwrap_or("0").parse().ok()?;
+                expires_at = Some(UNIX_EPOCH + Duration::new(secs, nanos));
+            }
+        }
+        Some(LeaseInfo { owner: owner?, expires_at: expires_at? })
+    }
+}
+
+/// Содержимое файла аренды; `None`, если файла нет
+pub fn read_lease(path: &Path) -> std::io::Result<Option<String>> {
+    match std::fs::read_to_string(long_path(path)) {
+        Ok(content) => Ok(Some(content)),
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Устарела ли аренда с содержимым `content`. Недописанный файл (владелец завершился
+/// между созданием и записью) считается устаревшим по времени изменения.
+pub fn is_stale(path: &Path, content: &str, ttl: Duration) -> std::io::Result<bool> {
+    if let Some(info) = LeaseInfo::parse(content) {
+        return Ok(info.is_expired());
+    }
+    let modified = match std::fs::metadata(long_path(path)) {
+        Ok(metadata) => metadata.modified()?,
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(error) => return Err(error),
+    };
+    Ok(modified + ttl <= SystemTime::now())
+}
+
+/// Атомарно создает файл аренды; `false`, если файл уже существует
+pub fn create_lease(path: &Path, content: &str) -> std::io::Result<bool> {
+    let mut file = match retry(|| std::fs::OpenOptions::new().write(true).create_new(true).open(long_path(path))) {
+        Ok(file) => file,
+        Err(ref error) if error.kind() == std::io::ErrorKind::AlreadyExists => return Ok(false),
+        Err(error) => return Err(error),
+    };
+    file.write_all(content.as_bytes())?;
+    retry(|| sync_all(&file))?;
+    Ok(true)
+}
+
+/// Разрывает устаревшую аренду, содержимое которой было прочитано как `observed`.
+///
+/// Файл сначала атомарно переименовывается, поэтому из нескольких процессов, одновременно
+/// разрывающих аренду, это удается только одному. Если под прежним именем к этому моменту
+/// оказалась уже новая аренда, она возвращается на место.
+pub fn break_lease(path: &Path, observed: &str) -> std::io::Result<bool> {
+    let moved = sibling_path(path, "stale")?;
+    match std::fs::rename(long_path(path), long_path(&moved)) {
+        Ok(()) => {},
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(error) => return Err(error),
+    }
+    let content = std::fs::read_to_string(long_path(&moved)).unwrap_or_default();
+    let broken = content == observed;
+    if !broken {
+        match std::fs::hard_link(long_path(&moved), long_path(path)) {
+            Ok(()) => {},
+            Err(ref error) if error.kind() == std::io::ErrorKind::AlreadyExists => {},
+            Err(error) => return Err(error),
+        }
+    }
+    let _ = std::fs::remove_file(long_path(&moved));
+    Ok(broken)
+}
+
+/// Переписывает файл аренды, если она все еще принадлежит `owner`
+pub fn rewrite_lease(path: &Path, owner: &str, content: &str) -> std::io::Result<()> {
+    match read_lease(path)?.as_ref().and_then(|content| LeaseInfo::parse(content)) {
+        Some(ref info) if info.owner == owner => {},
+        _ => return Err(lease_lost()),
+    }
+    let temporary = sibling_path(path, "refresh")?;
+    let result = (|| {
+        let mut file = retry(|| std::fs::OpenOptions::new().write(true).create_new(true).open(long_path(&temporary)))?;
+        file.write_all(content.as_bytes())?;
+        retry(|| sync_all(&file))?;
+        retry(|| std::fs::rename(long_path(&temporary), long_path(path)))
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(long_path(&temporary));
+    }
+    result
+}
+
+/// Удаляет файл аренды, если она все еще принадлежит `owner`
+pub fn remove_lease(path: &Path, owner: &str) -> std::io::Result<()> {
+    match read_lease(path)?.as_ref().and_then(|content| LeaseInfo::parse(content)) {
+        Some(ref info) if info.owner == owner => {},
+        _ => return Ok(()),
+    }
+    match std::fs::remove_file(long_path(path)) {
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Пытается захватить аренду `path` в текущем потоке. `metadata` - дополнительные строки
+/// файла аренды, сохраняемые при продлении; `stale` решает, можно ли разорвать чужую аренду.
+pub fn acquire_lease<F>(cpu_pool: &'static CpuPool, path: PathBuf, ttl: Duration, metadata: String, stale: F) -> std::io::Result<Option<LeaseFile>>
+    where F: Fn(&Path, &str) -> std::io::Result<bool>
+{
+    let owner = lease_owner_token();
+    // вторая попытка - после разрыва устаревшей аренды
+    for _ in 0..2 {
+        let info = LeaseInfo {
+            owner: owner.clone(),
+            expires_at: SystemTime::now() + ttl,
+        };
+        if create_lease(&path, &(info.to_content() + &metadata))? {
+            return Ok(Some(LeaseFile {
+                inner: Arc::new(LeaseInner {
+                    cpu_pool,
+                    path,
+                    owner,
+                    ttl,
+                    metadata,
+                    expires_at: Mutex::new(info.expires_at),
+                    released: AtomicBool::new(false),
+                }),
+            }));
+        }
+        let content = match read_lease(&path)? {
+            Some(content) => content,
+            None => continue,
+        };
+        if !stale(&path, &content)? || !break_lease(&path, &content)? {
+            return Ok(None);
+        }
+    }
+    Ok(None)
+}
+
+
+// LeaseFile
+
+struct LeaseInner {
+    cpu_pool: &'static CpuPool,
+    path: PathBuf,
+    owner: String,
+    ttl: Duration,
+    metadata: String,
+    expires_at: Mutex<SystemTime>,
+    released: AtomicBool,
+}
+impl LeaseInner {
+    fn refresh(&self) -> std::io::Result<SystemTime> {
+        let info = LeaseInfo {
+            owner: self.owner.clone(),
+            expires_at: SystemTime::now() + self.ttl,
+        };
+        rewrite_lease(&self.path, &self.owner, &(info.to_content() + &self.metadata))?;
+        *self.expires_at.lock().unwrap() = info.expires_at;
+        Ok(info.expires_at)
+    }
+}
+impl Drop for LeaseInner {
+    fn drop(&mut self) {
+        if !self.released.load(Ordering::SeqCst) {
+            let path = self.path.clone();
+            let owner = self.owner.clone();
+            self.cpu_pool.spawn_fn(move || remove_lease(&path, &owner)).forget();
+        }
+    }
+}
+
+/// Аренда с ограниченным сроком действия, записанная в файл: файл содержит идентификатор
+/// владельца и время окончания аренды.
+///
+/// Аренда захватывается атомарным созданием файла, поэтому работает и на сетевых файловых
+/// системах (NFS), где блокировки `FileLock` ненадежны. Владелец продлевает аренду `refresh`
+/// или потоком `keep_alive`; аренду, которую владелец не продлил вовремя (например, после его
+/// аварийного завершения), другие процессы разрывают при захвате. Часы процессов должны
+/// быть синхронизированы с точностью заметно выше срока аренды.
+///
+/// При удалении последней копии структуры аренда освобождается в пуле потоков без ожидания.
+#[derive(Clone)]
+pub struct LeaseFile {
+    inner: Arc<LeaseInner>,
+}
+impl LeaseFile {
+
+    /// Пытается захватить аренду `path` на срок `ttl`, разрывая устаревшую;
+    /// `None`, если аренда принадлежит другому владельцу
+    #[inline]
+    pub fn acquire<P: AsRef<Path>>(path: P, ttl: Duration) -> CpuFuture<Option<LeaseFile>, std::io::Error> {
+        Self::acquire_with_pool(&DEFAULT_CPU_POOL, path, ttl)
+    }
+
+    pub fn acquire_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P, ttl: Duration) -> CpuFuture<Option<LeaseFile>, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            acquire_lease(cpu_pool, path, ttl, String::new(), |path, content| is_stale(path, content, ttl))
+        })
+    }
+
+    /// Читает сведения об аренде `path`; `None`, если аренды нет
+    #[inline]
+    pub fn inspect<P: AsRef<Path>>(path: P) -> CpuFuture<Option<LeaseInfo>, std::io::Error> {
+        Self::inspect_with_pool(&DEFAULT_CPU_POOL, path)
+    }
+
+    pub fn inspect_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<Option<LeaseInfo>, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            Ok(read_lease(&path)?.as_ref().and_then(|content| LeaseInfo::parse(content)))
+        })
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.inner.path
+    }
+
+    /// Идентификатор владельца аренды
+    #[inline]
+    pub fn owner(&self) -> &str {
+        &self.inner.owner
+    }
+
+    #[inline]
+    pub fn ttl(&self) -> Duration {
+        self.inner.ttl
+    }
+
+    /// Время окончания аренды по последнему продлению
+    pub fn expires_at(&self) -> SystemTime {
+        *self.inner.expires_at.lock().unwrap()
+    }
+
+    /// Продлевает аренду на `ttl` от текущего момента и возвращает новое время окончания.
+    /// Если аренду разорвал другой процесс, возвращается ошибка.
+    pub fn refresh(&self) -> CpuFuture<SystemTime, std::io::Error> {
+        let inner = self.inner.clone();
+        self.inner.cpu_pool.spawn_fn(move || inner.refresh())
+    }
+
+    /// Поток, продлевающий аренду каждые `interval` и возвращающий новое время окончания.
+    ///
+    /// Поток завершается после освобождения аренды или удаления всех копий `LeaseFile`,
+    /// а ошибкой - если аренда потеряна. `interval` должен быть заметно меньше `ttl`.
+    pub fn keep_alive(&self, interval: Duration) -> KeepAlive {
+        KeepAlive {
+            inner: Arc::downgrade(&self.inner),
+            interval,
+            refreshing: None,
+            waiting: Deadline::new(Instant::now() + interval),
+        }
+    }
+
+    /// Освобождает аренду, если она все еще принадлежит этому владельцу
+    pub fn release(self) -> CpuFuture<(), std::io::Error> {
+        self.inner.released.store(true, Ordering::SeqCst);
+        let path = self.inner.path.clone();
+        let owner = self.inner.owner.clone();
+        self.inner.cpu_pool.spawn_fn(move || remove_lease(&path, &owner))
+    }
+}
+impl std::fmt::Debug for LeaseFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("LeaseFile")
+            .field("path", &self.inner.path)
+            .field("owner", &self.inner.owner)
+            .field("ttl", &self.inner.ttl)
+            .finish()
+    }
+}
+
+
+// KeepAlive
+
+/// Поток продления аренды (см. `LeaseFile::keep_alive`)
+pub struct KeepAlive {
+    inner: Weak<LeaseInner>,
+    interval: Duration,
+    refreshing: Option<CpuFuture<SystemTime, std::io::Error>>,
+    waiting: Deadline,
+}
+impl Stream for KeepAlive {
+    type Item = SystemTime;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(mut refreshing) = self.refreshing.take() {
+                return match refreshing.poll()? {
+                    Async::Ready(expires_at) => {
+                        self.waiting = Deadline::new(Instant::now() + self.interval);
+                        Ok(Async::Ready(Some(expires_at)))
+                    },
+                    Async::NotReady => {
+                        self.refreshing = Some(refreshing);
+                        Ok(Async::NotReady)
+                    },
+                };
+            }
+            if !self.waiting.poll_elapsed() {
+                return Ok(Async::NotReady);
+            }
+            let inner = match self.inner.upgrade() {
+                Some(ref inner) if !inner.released.load(Ordering::SeqCst) => inner.clone(),
+                _ => return Ok(Async::Ready(None)),
+            };
+            let cpu_pool = inner.cpu_pool;
+            self.refreshing = Some(cpu_pool.spawn_fn(move || inner.refresh()));
+        }
+    }
+}
+impl std::fmt::Debug for KeepAlive {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("KeepAlive")
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
@@ -1,22 +1,204 @@
-#![feature(async_await)]
-#![feature(test)]
-#![feature(rustc_private)]
-
 #[macro_use] extern crate lazy_static;
-extern crate futures;
+#[macro_use] extern crate futures;
 extern crate futures_cpupool;
 extern crate tokio;
+extern crate tokio_threadpool;
 extern crate bytes;
+extern crate httpdate;
+extern crate crc32fast;
+#[cfg(feature = "hyper")] extern crate hyper;
+#[cfg(feature = "crypto")] extern crate chacha20poly1305;
+#[cfg(feature = "crypto")] extern crate rand;
+#[cfg(feature = "crypto")] extern crate hmac;
+#[cfg(feature = "crypto")] extern crate sha2;
+#[cfg(feature = "gzip")] extern crate flate2;
+#[cfg(feature = "csv")] extern crate csv_crate;
+#[cfg(feature = "csv")] extern crate serde;
+#[cfg(feature = "futures03")] extern crate futures03;
+#[cfg(unix)] extern crate libc;
+#[cfg(unix)] extern crate mio;
+#[cfg(windows)] extern crate winapi;
 
-use futures::{Poll, Future, Async, AsyncSink};
+use futures::{Poll, Async, AsyncSink};
 use futures_cpupool::{CpuPool, CpuFuture};
 use std::sync::{Arc, RwLock};
+use std::collections::VecDeque;
 use std::convert::AsRef;
+use std::path::{Path, PathBuf};
 use std::io::{Write, Read};
 use std::convert::TryFrom;
-use bytes::{Bytes};
+use bytes::{Bytes, BytesMut};
+use tune::AdaptiveSize;
+use direct::{DirectBuffer, DirectWrite};
+use metrics::Meter;
+use recovery::{WriteProgress, FileGuard};
 
 mod tests;
+mod sys;
+mod pool_task;
+mod executor;
+mod timeout;
+mod throttle;
+mod hashing;
+pub mod io;
+pub mod ops;
+mod multipart;
+mod upload;
+mod appender;
+mod append_file;
+mod rotating;
+mod append_coordinator;
+mod block;
+mod compare;
+mod walk;
+mod ignore;
+mod report;
+mod list;
+mod read_dir;
+mod safe_path;
+#[cfg(unix)] mod root_dir;
+mod case_lookup;
+mod file_kind;
+mod block_device;
+mod options;
+#[cfg(any(unix, windows))] mod lock;
+mod serialized;
+mod truncate;
+mod whole_file;
+mod atomic_write;
+mod copy_file;
+mod range;
+mod concat;
+mod tee;
+mod broadcast;
+mod paged;
+mod file_cache;
+mod positional;
+#[cfg(unix)] mod pollable;
+mod tune;
+mod buffer;
+mod direct;
+mod recovery;
+#[cfg(unix)] mod mmap;
+#[cfg(target_os = "macos")] mod macos;
+#[cfg(unix)] pub mod shm;
+mod reflink;
+mod copy_dir;
+mod cleaner;
+mod scoped_dir;
+mod ephemeral;
+mod temp_file;
+mod follow;
+mod watch;
+mod hexdump;
+mod lines;
+mod scrub;
+mod quarantine;
+mod conditional;
+#[cfg(any(unix, windows))] mod versioned;
+mod lease;
+mod dir_lock;
+mod duplicates;
+mod raw;
+mod with_file;
+mod seek;
+mod set_len;
+mod pool_registry;
+mod metrics;
+mod priority;
+#[cfg(feature = "hyper")] mod serve;
+#[cfg(feature = "crypto")] mod crypto;
+#[cfg(feature = "crypto")] mod signature;
+#[cfg(feature = "gzip")] mod gzip;
+#[cfg(feature = "csv")] mod csv;
+#[cfg(feature = "futures03")] mod compat;
+#[cfg(all(feature = "io_uring", target_os = "linux"))] mod uring;
+#[cfg(all(feature = "overlapped", windows))] mod overlapped;
+// асинхронный ввод-вывод системы: io_uring на Linux, перекрывающиеся операции (IOCP) на Windows
+#[cfg(all(feature = "io_uring", target_os = "linux"))] use uring as native;
+#[cfg(all(feature = "overlapped", windows))] use overlapped as native;
+
+pub use multipart::{save_multipart, boundary_from_content_type, sanitize_file_name, SaveMultipart, SavedFile};
+pub use upload::UploadSession;
+pub use appender::{OffsetAppender, AppenderOp};
+pub use append_file::{AppendFile, ATOMIC_APPEND_LIMIT};
+pub use rotating::RotatingFileSink;
+pub use append_coordinator::{AppendCoordinator, AppendRecord};
+pub use block::{BlockSink, BlockStream, Block, BlockError, DEFAULT_BLOCK_SIZE};
+pub use compare::{files_equal, files_equal_with_pool, diff_ranges, diff_ranges_with_pool, FilesEqual, DiffRanges};
+pub use walk::{walk, walk_with_pool, WalkStream, WalkEntry};
+pub use ignore::IgnoreRules;
+pub use report::{LargestFiles, OlderThan, ExtensionTotals, ExtensionTotal};
+pub use report::{dir_stats, dir_stats_with_pool, DirStats, DirStatsStream};
+pub use report::{modified_since, modified_since_with_pool, ModifiedSince};
+pub use list::{list_dir, list_dir_with_pool, ListDir, ListEntry, SortBy};
+pub use read_dir::{read_dir, read_dir_with_pool, ReadDir, DirEntry};
+pub use safe_path::SafePath;
+#[cfg(unix)] pub use root_dir::RootDir;
+pub use case_lookup::{find_case_insensitive, find_case_insensitive_with_pool};
+pub use file_kind::{file_kind, file_kind_with_pool, FileKind, FileKindExt};
+pub use block_device::BlockDevice;
+pub use options::AsyncOpenOptions;
+pub use sys::set_retry_interrupted;
+pub use timeout::{Timeouted, Elapsed};
+pub use throttle::{Throttled, RateLimiter};
+pub use hashing::{HashingStream, HashingSink, HashAlgorithm, Digest};
+pub use with_file::WithFile;
+pub use seek::{AsyncSeek, Seek};
+pub use set_len::{AsyncSetLen, SetLen};
+pub use pool_registry::PoolRegistry;
+pub use metrics::{pool_metrics, MetricsSink, PoolMetrics, Metered, Histogram, HISTOGRAM_BUCKETS};
+pub use executor::{BlockingExecutor, TokioBlocking};
+pub use priority::{PriorityPool, Priority};
+pub use pool_task::PoolTask;
+#[cfg(feature = "futures03")] pub use compat::{Compat03, Compat03Ext};
+#[cfg(all(feature = "io_uring", target_os = "linux"))] pub use uring::io_uring_available;
+#[cfg(all(feature = "overlapped", windows))] pub use overlapped::overlapped_available;
+pub use serialized::{SerializedFile, SerializedOp};
+pub use truncate::{truncate, truncate_with_pool};
+pub use whole_file::{read, read_with_pool, read_to_string, read_to_string_with_pool, write, write_with_pool};
+pub use atomic_write::{write_atomic, write_atomic_with_pool, WriteAtomic};
+pub use copy_file::{copy, copy_with_pool, copy_progress, copy_progress_with_pool, CopyFile, CopyProgress};
+pub use range::{skip_bytes, skip_bytes_seek, take_bytes, SkipBytes, TakeBytes, SeekableStream};
+pub use concat::{concat_files, concat_files_with_pool, ConcatFiles};
+pub use tee::{tee_to_file, tee_to_file_with_pool, TeeToFile};
+pub use broadcast::{Broadcast, Subscription, LagPolicy};
+pub use paged::PagedReader;
+pub use file_cache::{FileCache, CachedFile, CachedFileStream, OpenCached, CacheWatch};
+pub use tune::{tune, tune_with_pool, TuneReport};
+pub use buffer::{IoBuffer, BufferPool, PooledBuffer, HUGE_PAGE_SIZE};
+pub use direct::DIRECT_IO_ALIGNMENT;
+pub use reflink::{clone_file, clone_file_with_pool, supports_reflink, supports_reflink_with_pool, snapshot, snapshot_with_pool, Snapshot};
+pub use copy_dir::{copy_dir_all, copy_dir_all_with_pool, CopyDir, CopyMode, CopyMethod, CopiedEntry};
+pub use cleaner::{Cleaner, CleanupAction, CleanupReason};
+pub use scoped_dir::ScopedDir;
+pub use ephemeral::EphemeralFile;
+pub use temp_file::{AsyncTempFile, tempdir, tempdir_with_pool};
+pub use follow::{AsyncFileFollow, FollowEvent};
+pub use watch::{watch, watch_with_pool, Watch, FsEvent};
+pub use lines::AsyncFileLines;
+pub use hexdump::{hexdump, hexdump_lines, format_hexdump, Hexdump, HexdumpLines};
+pub use scrub::{Scrubber, ScrubManifest, ScrubDigest, ScrubReport, ScrubProblem};
+pub use quarantine::{quarantine, quarantine_with_pool, Quarantined, QUARANTINE_SIDECAR_EXTENSION};
+pub use conditional::{write_if_changed, write_if_changed_with_pool};
+pub use conditional::{write_if_unmodified_since, write_if_unmodified_since_with_pool, file_etag, Precondition, PreconditionFailed};
+pub use lease::{LeaseFile, LeaseInfo, KeepAlive};
+pub use dir_lock::{DirLock, DirLockGuard, DirLockOwner, AcquireDirLock, StalePolicy, DIR_LOCK_FILE_NAME};
+pub use positional::{read_exact_at, read_exact_at_with_pool, write_all_at, write_all_at_with_pool};
+pub use positional::{read_exact_vectored_at, read_exact_vectored_at_with_pool, write_all_vectored_at, write_all_vectored_at_with_pool};
+#[cfg(any(unix, windows))] pub use options::AsyncOpenOptionsExt;
+#[cfg(unix)] pub use pollable::PollableFile;
+#[cfg(unix)] pub use mmap::{MmapStream, MmapChunk};
+#[cfg(unix)] pub use shm::{SharedMemory, SharedMapping};
+#[cfg(target_os = "macos")] pub use macos::MacosFileExt;
+#[cfg(any(unix, windows))] pub use lock::{FileLock, FileLockGuard, AcquireLock, LockMode, LockPath, lock, lock_with_pool};
+#[cfg(any(unix, windows))] pub use versioned::{VersionedFile, VersionToken};
+pub use duplicates::{find_duplicates, find_duplicates_with_pool, FindDuplicates, DuplicateGroup};
+#[cfg(feature = "hyper")] pub use serve::{serve_file, serve_file_with_pool, ServeFile};
+#[cfg(feature = "crypto")] pub use crypto::{EncryptedSink, DecryptingStream, MAX_ENCRYPTED_CHUNK_SIZE};
+#[cfg(feature = "crypto")] pub use signature::{SigningSink, VerifyingStream, signature_path};
+#[cfg(feature = "gzip")] pub use gzip::{IndexedGzSink, IndexedGzFile, GzIndex, GzIndexEntry, DEFAULT_GZ_SPAN};
+#[cfg(feature = "csv")] pub use csv::{read_csv, read_csv_with_pool, write_csv, write_csv_with_pool, CsvStream, CsvSink};
 
 static DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
 
@@ -53,45 +235,739 @@ lazy_static! {
     /// В данном случае, 2 потока позволяют заполнять простой очереди вызовов к ядру системы,
     /// между короткими промежутками времени, в которые потоки выполняют инструкции
     /// неопосредственно не связанные с вводом-выводом. Например, принимают следующее сообщение из канала.
+    ///
+    /// Выбор пула по пути файла можно поручить `PoolRegistry`.
     pub static ref DEFAULT_CPU_POOL: CpuPool = CpuPool::new(2);
 }
 
+/// Изменение длины или выделение места под файл (`poll_set_len`, `poll_allocate`)
+fn resize(file: &std::fs::File, len: u64, allocate: bool) -> std::io::Result<()> {
+    if allocate {
+        sys::allocate(file, len)
+    } else {
+        sys::retry(|| file.set_len(len))
+    }
+}
+
+fn read_at<E: BlockingExecutor>(executor: &E, raw: sys::RawFile, offset: u64, len: usize) -> PoolTask<Bytes> {
+    let file = sys::clone_raw_file(raw);
+    let meter = Meter::new(executor);
+    executor.spawn_blocking(move || {
+        let mut buf = vec![0; len];
+        let size = meter.read(sys::read_full_at(&file?, &mut buf, offset)?);
+        buf.truncate(size);
+        Ok(Bytes::from(buf))
+    })
+}
+
+
+// io_uring, overlapped
+
+/// Запись буфера обертки через асинхронный ввод-вывод системы (io_uring или перекрывающиеся
+/// операции, если включен и доступен) или в пуле потоков
+fn spawn_write<E: BlockingExecutor>(executor: &E, native_io: bool, file: std::fs::File, buf: Arc<RwLock<Vec<u8>>>, progress: &Arc<WriteProgress>) -> PoolTask<(std::fs::File, usize, std::time::Duration)> {
+    #[cfg(any(all(feature = "io_uring", target_os = "linux"), all(feature = "overlapped", windows)))]
+    let file = if native_io {
+        let started = std::time::Instant::now();
+        // буфер обертки доступен ей во время записи, поэтому в кольцо передается копия
+        let data = buf.read().unwrap().clone();
+        let meter = Meter::new(executor);
+        let progress = progress.clone();
+        let finish = move |file: std::fs::File, _: Vec<u8>, size: std::io::Result<usize>| {
+            let file = FileGuard::new(&progress, file);
+            let size = progress.add_written(meter.written(size?));
+            Ok((file.into_inner(), size, started.elapsed()))
+        };
+        match native::write(file, data, finish) {
+            Ok(task) => return task,
+            Err((file, _)) => file,
+        }
+    } else {
+        file
+    };
+    #[cfg(not(any(all(feature = "io_uring", target_os = "linux"), all(feature = "overlapped", windows))))]
+    let _ = native_io;
+    let meter = Meter::new(executor);
+    let progress = progress.clone();
+    executor.spawn_blocking(move || {
+        let mut file = FileGuard::new(&progress, file);
+        let started = std::time::Instant::now();
+        let size = meter.written(sys::retry(|| file.write(&buf.read().unwrap()[..]))?);
+        progress.add_written(size);
+        Ok((file.into_inner(), size, started.elapsed()))
+    })
+}
+
+/// Записывает накопленные данные `direct_io` (см. `DirectWrite::write`); при ошибке
+/// их размеры сохраняются для `AsyncFileWrite::recover`
+fn write_direct_pending(pending: Option<DirectWrite>, file: &mut std::fs::File, progress: &WriteProgress) -> std::io::Result<usize> {
+    let pending = match pending {
+        Some(pending) => pending,
+        None => return Ok(0),
+    };
+    match pending.write(file) {
+        Ok(tail) => {
+            progress.add_written(pending.fresh());
+            Ok(tail)
+        },
+        Err(error) => {
+            let (filled, counted) = pending.sizes();
+            progress.retain_direct(filled, counted);
+            Err(error)
+        },
+    }
+}
+
+/// Чтение `AsyncFileRead` в `buf` через асинхронный ввод-вывод системы (см. `spawn_write`) или в пуле потоков
+fn spawn_read<E: BlockingExecutor>(executor: &E, native_io: bool, file: std::fs::File, buf: BytesMut) -> PoolTask<(std::fs::File, BytesMut)> {
+    #[cfg(any(all(feature = "io_uring", target_os = "linux"), all(feature = "overlapped", windows)))]
+    let (file, buf) = if native_io {
+        let meter = Meter::new(executor);
+        let finish = move |file, mut buf: BytesMut, size: std::io::Result<usize>| {
+            buf.truncate(meter.read(size?));
+            Ok((file, buf))
+        };
+        match native::read(file, buf, finish) {
+            Ok(task) => return task,
+            Err(returned) => returned,
+        }
+    } else {
+        (file, buf)
+    };
+    #[cfg(not(any(all(feature = "io_uring", target_os = "linux"), all(feature = "overlapped", windows))))]
+    let _ = native_io;
+    let (mut file, mut buf) = (file, buf);
+    let meter = Meter::new(executor);
+    executor.spawn_blocking(move || {
+        let size = meter.read(sys::retry(|| file.read(&mut buf[..]))?);
+        buf.truncate(size);
+        Ok((file, buf))
+    })
+}
+
+/// Чтение блока `AsyncFileStream` (см. `spawn_read`)
+fn spawn_stream_read<E: BlockingExecutor>(executor: &E, native_io: bool, file: std::fs::File, buf: Vec<u8>) -> PoolTask<(std::fs::File, Vec<u8>, std::time::Duration)> {
+    #[cfg(any(all(feature = "io_uring", target_os = "linux"), all(feature = "overlapped", windows)))]
+    let (file, buf) = if native_io {
+        let started = std::time::Instant::now();
+        let meter = Meter::new(executor);
+        let finish = move |file, mut buf: Vec<u8>, size: std::io::Result<usize>| {
+            buf.truncate(meter.read(size?));
+            Ok((file, buf, started.elapsed()))
+        };
+        match native::read(file, buf, finish) {
+            Ok(task) => return task,
+            Err(returned) => returned,
+        }
+    } else {
+        (file, buf)
+    };
+    #[cfg(not(any(all(feature = "io_uring", target_os = "linux"), all(feature = "overlapped", windows))))]
+    let _ = native_io;
+    let (mut file, mut buf) = (file, buf);
+    let meter = Meter::new(executor);
+    executor.spawn_blocking(move || {
+        let started = std::time::Instant::now();
+        let size = meter.read(sys::retry(|| file.read(&mut buf[..]))?);
+        buf.truncate(size);
+        Ok((file, buf, started.elapsed()))
+    })
+}
+
 
 // AsyncFileWrite
 
 enum AsyncFileWriteState {
-    Write(CpuFuture<(std::fs::File, usize), std::io::Error>),
-    Flush(CpuFuture<std::fs::File, std::io::Error>),
+    Write(PoolTask<(std::fs::File, usize, std::time::Duration)>),
+    Flush(PoolTask<std::fs::File>),
+    SetLen(PoolTask<std::fs::File>),
+    Sync(PoolTask<std::fs::File>),
+    Shutdown(PoolTask<std::fs::File>),
+    WriteVectored(PoolTask<std::fs::File>),
+    Seek(PoolTask<(std::fs::File, u64)>),
     Ready(std::fs::File),
     Swapping,
 }
 
-/// Структура для асинхронной записи файла
-pub struct AsyncFileWrite {
-    cpu_pool: &'static CpuPool,
+/// Структура для асинхронной записи файла.
+///
+/// Если запись не может быть выполнена сразу, возвращается `WouldBlock`, а текущая задача
+/// (при наличии) пробуждается по завершении операции в пуле потоков.
+///
+/// Если операция в пуле потоков завершилась ошибкой, обертка не закрывает файл:
+/// после `recover` операцию можно повторить, а `into_parts` возвращает файл
+/// и принятые, но не записанные данные.
+pub struct AsyncFileWrite<E = &'static CpuPool> {
+    executor: E,
+    raw: sys::RawFile,
     state: AsyncFileWriteState,
     buf: Arc<RwLock<Vec<u8>>>,
+    progress: Arc<WriteProgress>,
+    trim_on_shutdown: bool,
+    adaptive: Option<AdaptiveSize>,
+    direct: Option<DirectBuffer>,
+    native_io: bool,
 }
 impl AsyncFileWrite {
 
     #[inline]
     pub fn from_std (cpu_pool: &'static CpuPool, file: std::fs::File, buffer_size: usize) -> AsyncFileWrite {
+        Self::from_std_with_executor(cpu_pool, file, buffer_size)
+    }
+
+    /// Создает (или усекает) файл `path` в пуле потоков, не блокируя вызывающий поток
+    #[inline]
+    pub fn create<P: AsRef<Path>>(path: P) -> CpuFuture<AsyncFileWrite, std::io::Error> {
+        Self::create_with_pool(pool_registry::pool_for(path.as_ref()), path)
+    }
+
+    pub fn create_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<AsyncFileWrite, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let file = sys::retry(|| std::fs::File::create(sys::long_path(&path)))?;
+            Ok(AsyncFileWrite::from_std(cpu_pool, file, DEFAULT_BUFFER_SIZE))
+        })
+    }
+}
+impl<E: BlockingExecutor> AsyncFileWrite<E> {
+
+    /// Обертка, выполняющая операции в `executor` вместо `CpuPool` (см. `BlockingExecutor`)
+    #[inline]
+    pub fn from_std_with_executor(executor: E, file: std::fs::File, buffer_size: usize) -> AsyncFileWrite<E> {
         AsyncFileWrite {
-            cpu_pool,
+            executor,
+            raw: sys::raw_file(&file),
             state: AsyncFileWriteState::Ready(file),
-            buf: Arc::new(RwLock::new(Vec::with_capacity(buffer_size)))
+            buf: Arc::new(RwLock::new(Vec::with_capacity(buffer_size))),
+            progress: WriteProgress::new(),
+            trim_on_shutdown: false,
+            adaptive: None,
+            direct: None,
+            native_io: false,
+        }
+    }
+
+    #[inline]
+    pub fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    /// Включает подбор размера буфера записи по достигнутой скорости (см. `tune`):
+    /// размер изменяется вдвое, пока это ускоряет запись. Учитываются только записи,
+    /// заполняющие буфер целиком.
+    pub fn adaptive(mut self, adaptive: bool) -> Self {
+        self.adaptive = if adaptive {
+            Some(AdaptiveSize::new(self.buf.read().unwrap().capacity()))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Режим записи для файлов, открытых с `O_DIRECT` (см. `AsyncOpenOptionsExt::direct`):
+    /// данные накапливаются в буфере, выровненном по `alignment` (размер буфера округляется
+    /// вверх до `alignment`), и записываются целыми блоками. `write` принимает данные, пока
+    /// в буфере есть место, а заполненный буфер записывается в пуле потоков.
+    ///
+    /// `flush`, `shutdown`, `poll_sync_*` и `poll_seek` записывают и неполный последний блок
+    /// (см. `DirectBuffer`), поэтому позиция записи должна быть выровнена (например, в новом
+    /// или усеченном файле). Векторная (`poll_write_vectored`) и позиционная (`write_at`)
+    /// запись в этом режиме не поддерживаются; `adaptive` не действует.
+    pub fn direct_io(mut self, alignment: usize) -> Self {
+        let buffer_size = self.buf.read().unwrap().capacity();
+        self.direct = Some(DirectBuffer::new(buffer_size, alignment));
+        self
+    }
+
+    /// Выполнять ли запись через io_uring вместо пула потоков (возможность `io_uring`, Linux 5.6+).
+    /// Если io_uring недоступен или его очередь заполнена, запись выполняется в пуле потоков;
+    /// без возможности `io_uring` настройка не действует. Режим `direct_io` всегда использует пул.
+    #[inline]
+    pub fn io_uring(mut self, enabled: bool) -> Self {
+        self.native_io = enabled;
+        self
+    }
+
+    /// Выполнять ли запись перекрывающимися операциями через порт завершения (IOCP) вместо пула
+    /// потоков (возможность `overlapped`, Windows). Для каждой записи файл открывается повторно
+    /// с `FILE_FLAG_OVERLAPPED` (`ReOpenFile`); если это невозможно (например, файл открыт только
+    /// для дозаписи), запись выполняется в пуле потоков. Без возможности `overlapped` настройка
+    /// не действует (см. `io_uring`).
+    #[inline]
+    pub fn overlapped(mut self, enabled: bool) -> Self {
+        self.native_io = enabled;
+        self
+    }
+
+    /// Усекать ли файл при `shutdown` до текущей позиции записи, чтобы в нем не оставалось
+    /// предварительно выделенного места, заполненного нулями
+    #[inline]
+    pub fn trim_on_shutdown(mut self, trim: bool) -> Self {
+        self.trim_on_shutdown = trim;
+        self
+    }
+
+    /// Новая обертка над копией дескриптора файла с тем же пулом потоков и размером буфера.
+    ///
+    /// Дескриптор дублируется сразу (без обращения к диску), в том числе во время операции
+    /// в пуле потоков, поэтому через копию можно, например, запрашивать метаданные параллельно
+    /// с чтением или записью. Позиция в файле у копий общая.
+    pub fn try_clone(&self) -> std::io::Result<AsyncFileWrite<E>> {
+        let buffer_size = self.buf.read().unwrap().capacity();
+        let clone = AsyncFileWrite::from_std_with_executor(self.executor.clone(), sys::clone_raw_file(self.raw)?, buffer_size)
+            .io_uring(self.native_io);
+        Ok(match self.direct {
+            Some(ref direct) => clone.direct_io(direct.alignment()),
+            None => clone,
+        })
+    }
+
+    /// Выполняет `f` над файлом в пуле потоков обертки, например, для операций, которые
+    /// крейт не предоставляет (`ioctl`, `fcntl`). Начатая операция сначала завершается,
+    /// а ее результат отбрасывается (как в `poll_set_len`). Результат - обертка, готовая
+    /// к дальнейшей работе, и результат `f`; при ошибке `f` обертка освобождается.
+    pub fn with_file<F, R>(self, f: F) -> WithFile<Self, F, R>
+        where F: FnOnce(&mut std::fs::File) -> std::io::Result<R> + Send + 'static,
+              R: Send + 'static
+    {
+        WithFile::new(self, f)
+    }
+
+    /// Ограничивает время ожидания каждой операции (см. `Timeouted`)
+    #[inline]
+    pub fn timeout(self, timeout: std::time::Duration) -> Timeouted<Self> {
+        Timeouted::new(self, timeout)
+    }
+
+    /// Ограничивает скорость обмена данными (см. `Throttled`); копии `limiter` разделяют бюджет
+    #[inline]
+    pub fn throttle(self, limiter: RateLimiter) -> Throttled<Self> {
+        Throttled::new(self, limiter)
+    }
+
+    /// Sink записей, кодируемых `codec` в файл (например, `LengthDelimitedCodec`).
+    /// Закрытие sink (`close`) вызывает `shutdown` и сбрасывает данные на диск.
+    #[inline]
+    pub fn framed<C: tokio::codec::Encoder>(self, codec: C) -> tokio::codec::FramedWrite<Self, C> {
+        tokio::codec::FramedWrite::new(self, codec)
+    }
+
+    /// Проверяет, будет ли следующий вызов `write` или `flush` обработан без `WouldBlock`
+    /// для ожидания уже начатой операции. Если операция еще выполняется,
+    /// текущая задача пробуждается по ее завершении.
+    pub fn poll_ready(&mut self) -> Poll<(), std::io::Error> {
+        match self.state {
+            AsyncFileWriteState::Write(ref mut task) => Ok(task.poll_finished()),
+            AsyncFileWriteState::Flush(ref mut task) => Ok(task.poll_finished()),
+            AsyncFileWriteState::SetLen(ref mut task) => Ok(task.poll_finished()),
+            AsyncFileWriteState::Sync(ref mut task) => Ok(task.poll_finished()),
+            AsyncFileWriteState::WriteVectored(ref mut task) => Ok(task.poll_finished()),
+            AsyncFileWriteState::Shutdown(ref mut task) => Ok(task.poll_finished()),
+            AsyncFileWriteState::Seek(ref mut task) => Ok(task.poll_finished()),
+            AsyncFileWriteState::Ready(_) => Ok(Async::Ready(())),
+            AsyncFileWriteState::Swapping => Err(std::io::Error::other("`File` instance already shutdown")),
+        }
+    }
+
+    /// Байты, записанные в файл последовательными операциями обертки (`write`,
+    /// `poll_write_vectored`, запись буфера `direct_io`), которые завершились успешно.
+    /// Позиционная запись (`write_at`) не учитывается; векторная запись, завершившаяся ошибкой,
+    /// могла записать часть данных, которая здесь тоже не учтена.
+    #[inline]
+    pub fn written(&self) -> u64 {
+        self.progress.written()
+    }
+
+    /// Значение `written` на момент последнего успешного сброса на диск
+    /// (`poll_sync_all`, `poll_sync_data` или `shutdown`): столько данных гарантированно сохранено
+    #[inline]
+    pub fn synced(&self) -> u64 {
+        self.progress.synced()
+    }
+
+    /// Возвращает обертку в рабочее состояние после ошибки операции в пуле потоков.
+    ///
+    /// Операция, завершившаяся ошибкой, сохраняет файл, а в режиме `direct_io` данные
+    /// неудачной записи возвращаются в буфер (позиция остается в начале этих данных),
+    /// так что после `recover` операцию можно повторить. Возвращает `WouldBlock`,
+    /// если операция еще выполняется или завершилась успешно, но ее результат не получен;
+    /// для готовой обертки ничего не делает.
+    pub fn recover(&mut self) -> std::io::Result<()> {
+        match self.state {
+            AsyncFileWriteState::Ready(_) => return Ok(()),
+            AsyncFileWriteState::Swapping => return Err(std::io::Error::other("`File` instance already shutdown")),
+            _ => {},
+        }
+        match self.progress.take_salvaged() {
+            Some((file, direct)) => {
+                if let (Some(ref mut buffer), Some((filled, counted))) = (self.direct.as_mut(), direct) {
+                    buffer.restore(filled, counted);
+                }
+                self.state = AsyncFileWriteState::Ready(file);
+                Ok(())
+            },
+            None => Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "`File` instance is blocked")),
+        }
+    }
+
+    /// Разбирает обертку (в том числе после ошибки операции, см. `recover`) на файл и данные,
+    /// принятые `write` в режиме `direct_io`, но еще не записанные в файл. Позиция файла
+    /// остается в начале этих данных. Как и `TryFrom`, возвращает `WouldBlock`,
+    /// если операция еще выполняется.
+    pub fn into_parts(mut self) -> std::io::Result<(std::fs::File, Vec<u8>)> {
+        self.recover()?;
+        let unwritten = match self.direct {
+            Some(ref direct) => direct.unwritten(),
+            None => Vec::new(),
+        };
+        Ok((std::fs::File::try_from(self)?, unwritten))
+    }
+
+    /// Изменяет длину файла (усекает его или дополняет нулями) в пуле потоков.
+    ///
+    /// Вызывается повторно с тем же `len`, пока не вернет `Ready`. Начатые ранее запись
+    /// или сброс сначала завершаются. Позиция записи не изменяется, поэтому после усечения
+    /// ее следует учитывать, чтобы не оставить в файле "дыру".
+    #[inline]
+    pub fn poll_set_len(&mut self, len: u64) -> Poll<(), std::io::Error> {
+        self.poll_resize(len, false)
+    }
+
+    /// Заранее выделяет на диске место под первые `len` байт файла (`fallocate` на Linux,
+    /// `F_PREALLOCATE` на macOS), чтобы запись большого файла не завершилась ошибкой
+    /// `ENOSPC` на середине и файл меньше фрагментировался. Длина файла увеличивается
+    /// до `len` (см. `trim_on_shutdown`), но не уменьшается. Если файловая система
+    /// не поддерживает выделение места, длина только увеличивается.
+    ///
+    /// Вызывается повторно с тем же `len`, пока не вернет `Ready` (см. `poll_set_len`).
+    #[inline]
+    pub fn poll_allocate(&mut self, len: u64) -> Poll<(), std::io::Error> {
+        self.poll_resize(len, true)
+    }
+
+    /// Future изменения длины файла (см. `poll_set_len`)
+    #[inline]
+    pub fn set_len(self, len: u64) -> SetLen<Self> {
+        SetLen::new(self, len)
+    }
+
+    /// Future выделения места под файл (см. `poll_allocate`)
+    #[inline]
+    pub fn allocate(self, len: u64) -> SetLen<Self> {
+        SetLen::allocate(self, len)
+    }
+
+    fn poll_resize(&mut self, len: u64, allocate: bool) -> Poll<(), std::io::Error> {
+        loop {
+            match self.state {
+                AsyncFileWriteState::SetLen(ref mut task) => {
+                    let file = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                    return Ok(Async::Ready(()));
+                },
+                AsyncFileWriteState::Write(ref mut task) => {
+                    let (file, _, _) = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Flush(ref mut task) | AsyncFileWriteState::Shutdown(ref mut task) | AsyncFileWriteState::Sync(ref mut task) | AsyncFileWriteState::WriteVectored(ref mut task) => {
+                    let file = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Seek(ref mut task) => {
+                    let (file, _) = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Ready(_) => {
+                    if let AsyncFileWriteState::Ready(file) = std::mem::replace(&mut self.state, AsyncFileWriteState::Swapping) {
+                        let progress = self.progress.clone();
+                        self.state = AsyncFileWriteState::SetLen(self.executor.spawn_blocking(move || {
+                            let file = FileGuard::new(&progress, file);
+                            resize(&file, len, allocate)?;
+                            Ok(file.into_inner())
+                        }));
+                    }
+                },
+                AsyncFileWriteState::Swapping => {
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
+                },
+            }
+        }
+    }
+
+    /// Перемещает позицию записи в пуле потоков (см. `AsyncSeek`).
+    ///
+    /// Вызывается повторно с тем же `pos`, пока не вернет `Ready` с новой позицией.
+    /// Начатые ранее запись или сброс сначала завершаются.
+    pub fn poll_seek(&mut self, pos: std::io::SeekFrom) -> Poll<u64, std::io::Error> {
+        loop {
+            match self.state {
+                AsyncFileWriteState::Seek(ref mut task) => {
+                    let (file, position) = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                    return Ok(Async::Ready(position));
+                },
+                AsyncFileWriteState::Write(ref mut task) => {
+                    let (file, _, _) = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Flush(ref mut task) | AsyncFileWriteState::SetLen(ref mut task) | AsyncFileWriteState::Shutdown(ref mut task) | AsyncFileWriteState::Sync(ref mut task) | AsyncFileWriteState::WriteVectored(ref mut task) => {
+                    let file = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Ready(_) => {
+                    if let AsyncFileWriteState::Ready(file) = std::mem::replace(&mut self.state, AsyncFileWriteState::Swapping) {
+                        let pending = self.direct.as_mut().and_then(DirectBuffer::take);
+                        if let Some(ref mut direct) = self.direct {
+                            direct.clear();
+                        }
+                        let progress = self.progress.clone();
+                        self.state = AsyncFileWriteState::Seek(self.executor.spawn_blocking(move || {
+                            let mut file = FileGuard::new(&progress, file);
+                            let tail = write_direct_pending(pending, &mut file, &progress)?;
+                            // позиция остается в начале неполного блока
+                            let pos = match pos {
+                                std::io::SeekFrom::Current(offset) => std::io::SeekFrom::Current(offset + tail as i64),
+                                pos => pos,
+                            };
+                            let position = std::io::Seek::seek(&mut *file, pos)?;
+                            Ok((file.into_inner(), position))
+                        }));
+                    }
+                },
+                AsyncFileWriteState::Swapping => {
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
+                },
+            }
+        }
+    }
+
+    /// Future перемещения позиции записи (см. `poll_seek`)
+    #[inline]
+    pub fn seek(self, pos: std::io::SeekFrom) -> Seek<Self> {
+        Seek::new(self, pos)
+    }
+
+    /// Записывает `data` целиком по смещению `offset` (`pwrite`), не изменяя позицию записи.
+    ///
+    /// Выполняется через копию дескриптора независимо от последовательных операций,
+    /// поэтому несколько позиционных записей могут выполняться одновременно.
+    pub fn write_at(&self, offset: u64, data: Bytes) -> PoolTask<()> {
+        let file = sys::clone_raw_file(self.raw);
+        let meter = Meter::new(&self.executor);
+        self.executor.spawn_blocking(move || {
+            sys::write_all_at(&file?, &data, offset)?;
+            meter.written(data.len());
+            Ok(())
+        })
+    }
+
+    /// Записывает буферы целиком с текущей позиции векторной записью (`writev`) за одно
+    /// обращение к пулу потоков, например, заголовок и тело записи без их объединения.
+    ///
+    /// Вызывается повторно с теми же `bufs`, пока не вернет `Ready`. Начатые ранее запись
+    /// или сброс сначала завершаются.
+    pub fn poll_write_vectored(&mut self, bufs: &[Bytes]) -> Poll<(), std::io::Error> {
+        if self.direct.is_some() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "vectored writes are not supported in direct I/O mode"));
+        }
+        loop {
+            match self.state {
+                AsyncFileWriteState::WriteVectored(ref mut task) => {
+                    let file = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                    return Ok(Async::Ready(()));
+                },
+                AsyncFileWriteState::Write(ref mut task) => {
+                    let (file, _, _) = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Flush(ref mut task) | AsyncFileWriteState::SetLen(ref mut task) | AsyncFileWriteState::Shutdown(ref mut task) | AsyncFileWriteState::Sync(ref mut task) => {
+                    let file = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Seek(ref mut task) => {
+                    let (file, _) = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Ready(_) => {
+                    if let AsyncFileWriteState::Ready(file) = std::mem::replace(&mut self.state, AsyncFileWriteState::Swapping) {
+                        let bufs = bufs.to_vec();
+                        let meter = Meter::new(&self.executor);
+                        let progress = self.progress.clone();
+                        self.state = AsyncFileWriteState::WriteVectored(self.executor.spawn_blocking(move || {
+                            let mut file = FileGuard::new(&progress, file);
+                            sys::write_all_vectored(&mut file, &bufs)?;
+                            progress.add_written(meter.written(bufs.iter().map(|buf| buf.len()).sum()));
+                            Ok(file.into_inner())
+                        }));
+                    }
+                },
+                AsyncFileWriteState::Swapping => {
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
+                },
+            }
+        }
+    }
+
+    /// Сбрасывает данные и метаданные файла на диск (`sync_all`) в пуле потоков.
+    ///
+    /// Вызывается повторно, пока не вернет `Ready`. Начатые ранее запись или сброс
+    /// сначала завершаются.
+    #[inline]
+    pub fn poll_sync_all(&mut self) -> Poll<(), std::io::Error> {
+        self.poll_sync(false)
+    }
+
+    /// Сбрасывает на диск данные файла без метаданных, не нужных для их чтения (`sync_data`)
+    /// (см. `poll_sync_all`)
+    #[inline]
+    pub fn poll_sync_data(&mut self) -> Poll<(), std::io::Error> {
+        self.poll_sync(true)
+    }
+
+    fn poll_sync(&mut self, data_only: bool) -> Poll<(), std::io::Error> {
+        loop {
+            match self.state {
+                AsyncFileWriteState::Sync(ref mut task) => {
+                    let file = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                    return Ok(Async::Ready(()));
+                },
+                AsyncFileWriteState::Write(ref mut task) => {
+                    let (file, _, _) = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Flush(ref mut task) | AsyncFileWriteState::SetLen(ref mut task) | AsyncFileWriteState::Shutdown(ref mut task) | AsyncFileWriteState::WriteVectored(ref mut task) => {
+                    let file = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Seek(ref mut task) => {
+                    let (file, _) = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Ready(_) => {
+                    if let AsyncFileWriteState::Ready(file) = std::mem::replace(&mut self.state, AsyncFileWriteState::Swapping) {
+                        let pending = self.direct.as_mut().and_then(DirectBuffer::take);
+                        let progress = self.progress.clone();
+                        self.state = AsyncFileWriteState::Sync(self.executor.spawn_blocking(move || {
+                            let mut file = FileGuard::new(&progress, file);
+                            write_direct_pending(pending, &mut file, &progress)?;
+                            if data_only {
+                                sys::retry(|| file.sync_data())?;
+                            } else {
+                                sys::retry(|| sys::sync_all(&file))?;
+                            }
+                            progress.mark_synced();
+                            Ok(file.into_inner())
+                        }));
+                    }
+                },
+                AsyncFileWriteState::Swapping => {
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
+                },
+            }
+        }
+    }
+
+    /// Запись в режиме `direct_io`: данные копируются в выровненный буфер,
+    /// а заполненный буфер записывается в пуле потоков
+    fn write_direct(&mut self, src: &[u8]) -> std::io::Result<usize> {
+        loop {
+            match self.state {
+                AsyncFileWriteState::Write(ref mut task) => {
+                    match task.poll()? {
+                        Async::Ready((file, _, _)) => {
+                            self.state = AsyncFileWriteState::Ready(file);
+                        },
+                        _ => {
+                            break;
+                        }
+                    }
+                },
+                AsyncFileWriteState::Ready(_) => {
+                    let mut accepted = None;
+                    if let Some(ref mut direct) = self.direct {
+                        if !direct.is_full() {
+                            let size = direct.append(src);
+                            if !direct.is_full() {
+                                return Ok(size);
+                            }
+                            accepted = Some(size);
+                        }
+                    }
+                    self.start_direct_write();
+                    // данные, заполнившие буфер, уже приняты
+                    if let Some(size) = accepted {
+                        return Ok(size);
+                    }
+                },
+                AsyncFileWriteState::Flush(ref mut task) | AsyncFileWriteState::SetLen(ref mut task) | AsyncFileWriteState::Shutdown(ref mut task) | AsyncFileWriteState::Sync(ref mut task) | AsyncFileWriteState::WriteVectored(ref mut task) => {
+                    match task.poll()? {
+                        Async::Ready(file) => {
+                            self.state = AsyncFileWriteState::Ready(file);
+                        },
+                        _ => {
+                            break;
+                        }
+                    }
+                },
+                AsyncFileWriteState::Seek(ref mut task) => {
+                    match task.poll()? {
+                        Async::Ready((file, _)) => {
+                            self.state = AsyncFileWriteState::Ready(file);
+                        },
+                        _ => {
+                            break;
+                        }
+                    }
+                },
+                AsyncFileWriteState::Swapping => {
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
+                },
+            }
+        }
+
+        Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "block"))
+    }
+
+    /// Начинает запись заполненного выровненного буфера
+    fn start_direct_write(&mut self) {
+        if let AsyncFileWriteState::Ready(file) = std::mem::replace(&mut self.state, AsyncFileWriteState::Swapping) {
+            let direct = self.direct.as_mut().unwrap();
+            let size = direct.filled();
+            let pending = direct.take();
+            let meter = Meter::new(&self.executor);
+            let progress = self.progress.clone();
+            self.state = AsyncFileWriteState::Write(self.executor.spawn_blocking(move || {
+                let mut file = FileGuard::new(&progress, file);
+                let started = std::time::Instant::now();
+                write_direct_pending(pending, &mut file, &progress)?;
+                meter.written(size);
+                Ok((file.into_inner(), size, started.elapsed()))
+            }));
         }
     }
+
 }
 
-impl std::io::Write for AsyncFileWrite {
+impl<E: BlockingExecutor> std::io::Write for AsyncFileWrite<E> {
     fn write(&mut self, src: &[u8]) -> std::io::Result<usize> {
+        if self.direct.is_some() {
+            return self.write_direct(src);
+        }
         loop {
             match self.state {
                 AsyncFileWriteState::Write(ref mut future) => {
                     match future.poll()? {
-                        Async::Ready((file, size)) => {
+                        Async::Ready((file, size, elapsed)) => {
                             self.state = AsyncFileWriteState::Ready(file);
+                            if let Some(ref mut adaptive) = self.adaptive {
+                                let mut buf = self.buf.write().unwrap();
+                                let buffer_size = adaptive.record(size, elapsed);
+                                if buffer_size != buf.capacity() {
+                                    *buf = Vec::with_capacity(buffer_size);
+                                }
+                            }
                             return Ok(size);
                         },
                         _ => {
@@ -100,7 +976,7 @@ impl std::io::Write for AsyncFileWrite {
                     }
                 },
                 AsyncFileWriteState::Ready(_) => {
-                    if let AsyncFileWriteState::Ready(mut file) = std::mem::replace(&mut self.state, AsyncFileWriteState::Swapping) {
+                    if let AsyncFileWriteState::Ready(file) = std::mem::replace(&mut self.state, AsyncFileWriteState::Swapping) {
                         let buf = {
                             let mut buf = self.buf.write().unwrap();
                             buf.truncate(0);
@@ -112,14 +988,31 @@ impl std::io::Write for AsyncFileWrite {
                             buf.extend_from_slice(&src[..len]);
                             self.buf.clone()
                         };
-                        self.state = AsyncFileWriteState::Write(self.cpu_pool.spawn_fn(move || {
-                            let size = file.write(&buf.read().unwrap()[..])?;
-                            Ok((file, size))
-                        }));
+                        self.state = AsyncFileWriteState::Write(spawn_write(&self.executor, self.native_io, file, buf, &self.progress));
+                    }
+                },
+                AsyncFileWriteState::SetLen(ref mut future) | AsyncFileWriteState::Shutdown(ref mut future) | AsyncFileWriteState::Sync(ref mut future) | AsyncFileWriteState::WriteVectored(ref mut future) => {
+                    match future.poll()? {
+                        Async::Ready(file) => {
+                            self.state = AsyncFileWriteState::Ready(file);
+                        },
+                        _ => {
+                            break;
+                        }
+                    }
+                },
+                AsyncFileWriteState::Seek(ref mut future) => {
+                    match future.poll()? {
+                        Async::Ready((file, _)) => {
+                            self.state = AsyncFileWriteState::Ready(file);
+                        },
+                        _ => {
+                            break;
+                        }
                     }
                 },
                 AsyncFileWriteState::Swapping => {
-                    return Err(std::io::Error::new(std::io::ErrorKind::Other, "`File` instance already shutdown"));
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
                 },
                 _ => {
                     break;
@@ -144,15 +1037,39 @@ impl std::io::Write for AsyncFileWrite {
                     }
                 },
                 AsyncFileWriteState::Ready(_) => {
-                    if let AsyncFileWriteState::Ready(mut file) = std::mem::replace(&mut self.state, AsyncFileWriteState::Swapping) {
-                        self.state = AsyncFileWriteState::Flush(self.cpu_pool.spawn_fn(|| {
-                            let _ = file.flush()?;
-                            Ok(file)
+                    if let AsyncFileWriteState::Ready(file) = std::mem::replace(&mut self.state, AsyncFileWriteState::Swapping) {
+                        let pending = self.direct.as_mut().and_then(DirectBuffer::take);
+                        let progress = self.progress.clone();
+                        self.state = AsyncFileWriteState::Flush(self.executor.spawn_blocking(move || {
+                            let mut file = FileGuard::new(&progress, file);
+                            write_direct_pending(pending, &mut file, &progress)?;
+                            sys::retry(|| file.flush())?;
+                            Ok(file.into_inner())
                         }));
                     }
                 },
+                AsyncFileWriteState::SetLen(ref mut future) | AsyncFileWriteState::Shutdown(ref mut future) | AsyncFileWriteState::Sync(ref mut future) | AsyncFileWriteState::WriteVectored(ref mut future) => {
+                    match future.poll()? {
+                        Async::Ready(file) => {
+                            self.state = AsyncFileWriteState::Ready(file);
+                        },
+                        _ => {
+                            break;
+                        }
+                    }
+                },
+                AsyncFileWriteState::Seek(ref mut future) => {
+                    match future.poll()? {
+                        Async::Ready((file, _)) => {
+                            self.state = AsyncFileWriteState::Ready(file);
+                        },
+                        _ => {
+                            break;
+                        }
+                    }
+                },
                 AsyncFileWriteState::Swapping => {
-                    return Err(std::io::Error::new(std::io::ErrorKind::Other, "`File` instance already shutdown"));
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
                 },
                 _ => {
                     break;
@@ -164,28 +1081,72 @@ impl std::io::Write for AsyncFileWrite {
     }
 }
 
-impl tokio::io::AsyncWrite for AsyncFileWrite {
+impl<E: BlockingExecutor> tokio::io::AsyncWrite for AsyncFileWrite<E> {
+    /// Дожидается начатой операции, сбрасывает данные на диск (`sync_all`), при `trim_on_shutdown`
+    /// предварительно усекая файл, и только после этого возвращает `Ready`
     fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
-        Ok(Async::Ready(()))
-    }
-}
-impl From<std::fs::File> for AsyncFileWrite {
+        loop {
+            match self.state {
+                AsyncFileWriteState::Shutdown(ref mut task) => {
+                    let file = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                    return Ok(Async::Ready(()));
+                },
+                AsyncFileWriteState::Write(ref mut task) => {
+                    let (file, _, _) = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Flush(ref mut task) | AsyncFileWriteState::SetLen(ref mut task) | AsyncFileWriteState::Sync(ref mut task) | AsyncFileWriteState::WriteVectored(ref mut task) => {
+                    let file = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Seek(ref mut task) => {
+                    let (file, _) = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Ready(_) => {
+                    if let AsyncFileWriteState::Ready(file) = std::mem::replace(&mut self.state, AsyncFileWriteState::Swapping) {
+                        let trim = self.trim_on_shutdown;
+                        let pending = self.direct.as_mut().and_then(DirectBuffer::take);
+                        let progress = self.progress.clone();
+                        self.state = AsyncFileWriteState::Shutdown(self.executor.spawn_blocking(move || {
+                            let mut file = FileGuard::new(&progress, file);
+                            let tail = write_direct_pending(pending, &mut file, &progress)?;
+                            if trim {
+                                let position = std::io::Seek::seek(&mut *file, std::io::SeekFrom::Current(0))? + tail as u64;
+                                sys::retry(|| file.set_len(position))?;
+                            }
+                            sys::retry(|| file.flush())?;
+                            sys::retry(|| sys::sync_all(&file))?;
+                            progress.mark_synced();
+                            Ok(file.into_inner())
+                        }));
+                    }
+                },
+                AsyncFileWriteState::Swapping => {
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
+                },
+            }
+        }
+    }
+}
+impl From<std::fs::File> for AsyncFileWrite {
     fn from(file: std::fs::File) -> Self {
         Self::from_std(&DEFAULT_CPU_POOL, file, DEFAULT_BUFFER_SIZE)
     }
 }
-impl TryFrom<AsyncFileWrite> for std::fs::File {
+impl<E> TryFrom<AsyncFileWrite<E>> for std::fs::File {
     type Error = std::io::Error;
 
-    fn try_from(file: AsyncFileWrite) -> Result<Self, Self::Error> {
+    fn try_from(file: AsyncFileWrite<E>) -> Result<Self, Self::Error> {
         match file.state {
             AsyncFileWriteState::Ready(file) => Ok(file),
-            AsyncFileWriteState::Swapping => Err(std::io::Error::new(std::io::ErrorKind::Other, "`File` instance already shutdown")),
+            AsyncFileWriteState::Swapping => Err(std::io::Error::other("`File` instance already shutdown")),
             _ => Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "`File` instance is blocked"))
         }
     }
 }
-impl std::fmt::Debug for AsyncFileWrite {
+impl<E> std::fmt::Debug for AsyncFileWrite<E> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         f.debug_struct("AsyncFileWrite").finish()
     }
@@ -194,74 +1155,396 @@ impl std::fmt::Debug for AsyncFileWrite {
 
 // AsyncFileSink
 
-enum AsyncFileSinkState {
-    Write(CpuFuture<std::fs::File, std::io::Error>),
+enum AsyncFileSinkState<B> {
+    /// Запись части очереди; результат - неполностью записанные элементы и записанная часть первого из них
+    Write(PoolTask<(std::fs::File, VecDeque<B>, usize)>),
+    Sync(PoolTask<std::fs::File>),
+    SetLen(PoolTask<std::fs::File>),
     Ready(std::fs::File),
+    Closing(PoolTask<()>),
+    Closed,
     Swapping,
 }
 
-/// Структура для асинхронной записи файла
-pub struct AsyncFileSink {
-    cpu_pool: &'static CpuPool,
-    state: AsyncFileSinkState,
+/// Структура для асинхронной записи файла.
+///
+/// Элементом может быть любой буфер `B: AsRef<[u8]>` (по умолчанию `Bytes`), например `Vec<u8>`
+/// или `&'static [u8]`, без копирования в `Bytes`.
+pub struct AsyncFileSink<E = &'static CpuPool, B = Bytes> {
+    executor: E,
+    raw: sys::RawFile,
+    state: AsyncFileSinkState<B>,
+    trim_on_close: bool,
+    /// Элементы, принятые во время записи и ожидающие следующей
+    queue: VecDeque<B>,
+    /// Уже записанная часть первого элемента очереди (см. `max_chunk_size`)
+    queue_offset: usize,
+    queued_bytes: usize,
+    max_queued_items: usize,
+    max_queued_bytes: usize,
+    max_chunk_size: usize,
 }
 impl AsyncFileSink {
 
     #[inline]
     pub fn from_std (cpu_pool: &'static CpuPool, file: std::fs::File) -> AsyncFileSink {
+        Self::from_std_with_executor(cpu_pool, file)
+    }
+
+    /// Создает (или усекает) файл `path` в пуле потоков (см. `AsyncFileWrite::create`)
+    #[inline]
+    pub fn create<P: AsRef<Path>>(path: P) -> CpuFuture<AsyncFileSink, std::io::Error> {
+        Self::create_with_pool(pool_registry::pool_for(path.as_ref()), path)
+    }
+
+    pub fn create_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<AsyncFileSink, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let file = sys::retry(|| std::fs::File::create(sys::long_path(&path)))?;
+            Ok(AsyncFileSink::from_std(cpu_pool, file))
+        })
+    }
+}
+impl<E: BlockingExecutor, B: AsRef<[u8]> + Send + 'static> AsyncFileSink<E, B> {
+
+    /// Обертка, выполняющая операции в `executor` (см. `AsyncFileWrite::from_std_with_executor`)
+    #[inline]
+    pub fn from_std_with_executor(executor: E, file: std::fs::File) -> AsyncFileSink<E, B> {
         AsyncFileSink {
-            cpu_pool,
+            executor,
+            raw: sys::raw_file(&file),
             state: AsyncFileSinkState::Ready(file),
+            trim_on_close: false,
+            queue: VecDeque::new(),
+            queue_offset: 0,
+            queued_bytes: 0,
+            max_queued_items: 0,
+            max_queued_bytes: 0,
+            max_chunk_size: 0,
         }
     }
 
-}
-impl futures::Sink for AsyncFileSink {
-    type SinkItem = Bytes;
-    type SinkError = std::io::Error;
+    #[inline]
+    pub fn executor(&self) -> &E {
+        &self.executor
+    }
 
-    fn start_send(&mut self, item: Self::SinkItem) -> futures::StartSend<Self::SinkItem, Self::SinkError> {
+    /// Усекать ли файл в `poll_close` до текущей позиции записи, чтобы в нем не оставалось
+    /// предварительно выделенного места, заполненного нулями
+    #[inline]
+    pub fn trim_on_close(mut self, trim: bool) -> Self {
+        self.trim_on_close = trim;
+        self
+    }
+
+    /// Очередь элементов, принимаемых, пока выполняется запись: `start_send` возвращает
+    /// `NotReady`, только когда в очереди `max_items` элементов или не меньше `max_bytes` байт
+    /// (последний принятый элемент может превысить предел). Накопленные элементы
+    /// записываются следующей записью одним буфером. По умолчанию очереди нет.
+    pub fn write_queue(mut self, max_items: usize, max_bytes: usize) -> Self {
+        self.max_queued_items = max_items;
+        self.max_queued_bytes = max_bytes;
+        self
+    }
+
+    /// Наибольший объем одной записи в пуле потоков: элемент больше `max_chunk_size` записывается
+    /// по частям, и между частями задача, опрашивающая sink, может выполнять другую работу,
+    /// а поток пула не занят одним элементом надолго. Если sink освобожден во время записи,
+    /// текущая часть дописывается, а оставшиеся части элемента не записываются.
+    /// По умолчанию (0) элементы записываются целиком.
+    #[inline]
+    pub fn max_chunk_size(mut self, max_chunk_size: usize) -> Self {
+        self.max_chunk_size = max_chunk_size;
+        self
+    }
+
+    /// Принятые, но еще не записанные байты (без выполняемой сейчас записи)
+    #[inline]
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes
+    }
+
+    fn queue_has_room(&self) -> bool {
+        self.queue.len() < self.max_queued_items && (self.queue.is_empty() || self.queued_bytes < self.max_queued_bytes)
+    }
 
+    /// Дожидается текущей записи или сброса, не начиная следующую запись
+    fn poll_write(&mut self) -> Poll<(), std::io::Error> {
         match self.state {
             AsyncFileSinkState::Write(ref mut future) => {
-                match future.poll()? {
-                    Async::Ready(mut file) => {
-                        self.state = AsyncFileSinkState::Write(self.cpu_pool.spawn_fn(move || {
-                            file.write_all(item.as_ref())?;
-                            Ok(file)
-                        }));
-                        Ok(AsyncSink::Ready)
-                    }
-                    _ => Ok(AsyncSink::NotReady(item))
+                let (file, rest, offset) = try_ready!(future.poll());
+                // незаписанные части возвращаются в начало очереди
+                self.queued_bytes += rest.iter().map(|item| item.as_ref().len()).sum::<usize>() - offset;
+                for item in rest.into_iter().rev() {
+                    self.queue.push_front(item);
+                }
+                self.queue_offset = offset;
+                self.state = AsyncFileSinkState::Ready(file);
+            },
+            AsyncFileSinkState::Sync(ref mut future) | AsyncFileSinkState::SetLen(ref mut future) => {
+                let file = try_ready!(future.poll());
+                self.state = AsyncFileSinkState::Ready(file);
+            },
+            _ => {},
+        }
+        Ok(Async::Ready(()))
+    }
+
+    /// Начинает запись элементов очереди (не больше `max_chunk_size` байт) одной векторной
+    /// записью (`writev`), без их объединения
+    fn start_write(&mut self) {
+        if let AsyncFileSinkState::Ready(mut file) = std::mem::replace(&mut self.state, AsyncFileSinkState::Swapping) {
+            let items = std::mem::take(&mut self.queue);
+            let offset = std::mem::replace(&mut self.queue_offset, 0);
+            self.queued_bytes = 0;
+            let max_chunk_size = self.max_chunk_size;
+            let meter = Meter::new(&self.executor);
+            self.state = AsyncFileSinkState::Write(self.executor.spawn_blocking(move || {
+                let (rest, offset) = write_chunk(&mut file, items, offset, max_chunk_size, &meter)?;
+                Ok((file, rest, offset))
+            }));
+        }
+    }
+
+    /// Принимает несколько элементов (например, заголовок и тело записи), которые записываются
+    /// подряд одной векторной записью (`writev`) без объединения в один буфер.
+    ///
+    /// Как и `start_send`, возвращает `NotReady` с элементами, если их нельзя принять сейчас;
+    /// пока выполняется запись, элементы принимаются, только если в очереди (см. `write_queue`) есть место.
+    pub fn start_send_vectored(&mut self, items: Vec<B>) -> futures::StartSend<Vec<B>, std::io::Error> {
+        self.poll_write()?;
+        match self.state {
+            AsyncFileSinkState::Write(_) | AsyncFileSinkState::Sync(_) | AsyncFileSinkState::SetLen(_) => {
+                if !self.queue_has_room() {
+                    return Ok(AsyncSink::NotReady(items));
                 }
+                self.queue_items(items);
+                Ok(AsyncSink::Ready)
             },
             AsyncFileSinkState::Ready(_) => {
-                if let AsyncFileSinkState::Ready(mut file) = std::mem::replace(&mut self.state, AsyncFileSinkState::Swapping) {
-                    self.state = AsyncFileSinkState::Write(self.cpu_pool.spawn_fn(move || {
-                        file.write_all(item.as_ref())?;
-                        Ok(file)
-                    }));
-                    return Ok(AsyncSink::Ready);
+                self.queue_items(items);
+                if !self.queue.is_empty() {
+                    self.start_write();
                 }
-                Ok(AsyncSink::NotReady(item))
+                Ok(AsyncSink::Ready)
+            },
+            AsyncFileSinkState::Closing(_) | AsyncFileSinkState::Closed | AsyncFileSinkState::Swapping => {
+                Err(std::io::Error::other("`File` instance already shutdown"))
             },
-            AsyncFileSinkState::Swapping => Err(std::io::Error::new(std::io::ErrorKind::Other, "`File` instance already shutdown")),
         }
     }
 
-    fn poll_complete(&mut self) -> futures::Poll<(), Self::SinkError> {
+    fn queue_items(&mut self, items: Vec<B>) {
+        for item in items {
+            if !item.as_ref().is_empty() {
+                self.queued_bytes += item.as_ref().len();
+                self.queue.push_back(item);
+            }
+        }
+    }
+
+    /// Новая обертка над копией дескриптора файла (см. `AsyncFileWrite::try_clone`)
+    pub fn try_clone(&self) -> std::io::Result<AsyncFileSink<E, B>> {
+        Ok(AsyncFileSink::from_std_with_executor(self.executor.clone(), sys::clone_raw_file(self.raw)?))
+    }
+
+    /// Выполняет `f` над файлом в пуле потоков обертки (см. `AsyncFileWrite::with_file`)
+    pub fn with_file<F, R>(self, f: F) -> WithFile<Self, F, R>
+        where F: FnOnce(&mut std::fs::File) -> std::io::Result<R> + Send + 'static,
+              R: Send + 'static
+    {
+        WithFile::new(self, f)
+    }
+
+    /// Ограничивает время ожидания каждой операции (см. `Timeouted`)
+    #[inline]
+    pub fn timeout(self, timeout: std::time::Duration) -> Timeouted<Self> {
+        Timeouted::new(self, timeout)
+    }
+
+    /// Ограничивает скорость обмена данными (см. `Throttled`); копии `limiter` разделяют бюджет
+    #[inline]
+    pub fn throttle(self, limiter: RateLimiter) -> Throttled<Self> {
+        Throttled::new(self, limiter)
+    }
+
+    /// Проверяет, будет ли следующий элемент принят `start_send` без возврата `NotReady(item)`.
+    /// Следующая запись начинается только после завершения предыдущей, поэтому готовность
+    /// означает, что предыдущая запись выполнена или в очереди (см. `write_queue`) есть место;
+    /// иначе текущая задача пробуждается по завершении записи.
+    pub fn poll_ready(&mut self) -> Poll<(), std::io::Error> {
+        if let Async::NotReady = self.poll_write()? {
+            if self.queue_has_room() {
+                return Ok(Async::Ready(()));
+            }
+            return Ok(Async::NotReady);
+        }
+        futures::Sink::poll_complete(self)
+    }
+
+    /// Записывает принятые элементы и сбрасывает данные и метаданные файла на диск (`sync_all`)
+    /// в пуле потоков, не закрывая файл. Вызывается повторно, пока не вернет `Ready`.
+    #[inline]
+    pub fn poll_sync_all(&mut self) -> Poll<(), std::io::Error> {
+        self.poll_sync(false)
+    }
+
+    /// Записывает принятые элементы и сбрасывает на диск данные файла (`sync_data`)
+    /// (см. `poll_sync_all`)
+    #[inline]
+    pub fn poll_sync_data(&mut self) -> Poll<(), std::io::Error> {
+        self.poll_sync(true)
+    }
+
+    fn poll_sync(&mut self, data_only: bool) -> Poll<(), std::io::Error> {
+        loop {
+            if let AsyncFileSinkState::Sync(ref mut future) = self.state {
+                let file = try_ready!(future.poll());
+                self.state = AsyncFileSinkState::Ready(file);
+                return Ok(Async::Ready(()));
+            }
+            try_ready!(futures::Sink::poll_complete(self));
+            match std::mem::replace(&mut self.state, AsyncFileSinkState::Swapping) {
+                AsyncFileSinkState::Ready(file) => {
+                    self.state = AsyncFileSinkState::Sync(self.executor.spawn_blocking(move || {
+                        if data_only {
+                            sys::retry(|| file.sync_data())?;
+                        } else {
+                            sys::retry(|| sys::sync_all(&file))?;
+                        }
+                        Ok(file)
+                    }));
+                },
+                state => {
+                    self.state = state;
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
+                },
+            }
+        }
+    }
+
+    /// Записывает принятые элементы и изменяет длину файла в пуле потоков
+    /// (см. `AsyncFileWrite::poll_set_len`)
+    #[inline]
+    pub fn poll_set_len(&mut self, len: u64) -> Poll<(), std::io::Error> {
+        self.poll_resize(len, false)
+    }
+
+    /// Записывает принятые элементы и выделяет место под файл (см. `AsyncFileWrite::poll_allocate`)
+    #[inline]
+    pub fn poll_allocate(&mut self, len: u64) -> Poll<(), std::io::Error> {
+        self.poll_resize(len, true)
+    }
+
+    /// Future изменения длины файла (см. `poll_set_len`)
+    #[inline]
+    pub fn set_len(self, len: u64) -> SetLen<Self> {
+        SetLen::new(self, len)
+    }
+
+    /// Future выделения места под файл (см. `poll_allocate`)
+    #[inline]
+    pub fn allocate(self, len: u64) -> SetLen<Self> {
+        SetLen::allocate(self, len)
+    }
+
+    fn poll_resize(&mut self, len: u64, allocate: bool) -> Poll<(), std::io::Error> {
+        loop {
+            if let AsyncFileSinkState::SetLen(ref mut future) = self.state {
+                let file = try_ready!(future.poll());
+                self.state = AsyncFileSinkState::Ready(file);
+                return Ok(Async::Ready(()));
+            }
+            try_ready!(futures::Sink::poll_complete(self));
+            match std::mem::replace(&mut self.state, AsyncFileSinkState::Swapping) {
+                AsyncFileSinkState::Ready(file) => {
+                    self.state = AsyncFileSinkState::SetLen(self.executor.spawn_blocking(move || {
+                        resize(&file, len, allocate)?;
+                        Ok(file)
+                    }));
+                },
+                state => {
+                    self.state = state;
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
+                },
+            }
+        }
+    }
+
+    /// Завершает запись: дожидается предыдущей записи, при `trim_on_close` усекает файл,
+    /// сбрасывает данные на диск и закрывает файл в пуле потоков. После закрытия запись и получение `File` невозможны.
+    pub fn poll_close(&mut self) -> Poll<(), std::io::Error> {
+        loop {
+            match self.state {
+                AsyncFileSinkState::Closing(ref mut future) => {
+                    try_ready!(future.poll());
+                    self.state = AsyncFileSinkState::Closed;
+                    return Ok(Async::Ready(()));
+                },
+                AsyncFileSinkState::Closed => return Ok(Async::Ready(())),
+                _ => {
+                    try_ready!(futures::Sink::poll_complete(self));
+                    if let AsyncFileSinkState::Ready(mut file) = std::mem::replace(&mut self.state, AsyncFileSinkState::Swapping) {
+                        let trim = self.trim_on_close;
+                        self.state = AsyncFileSinkState::Closing(self.executor.spawn_blocking(move || {
+                            if trim {
+                                let position = std::io::Seek::seek(&mut file, std::io::SeekFrom::Current(0))?;
+                                sys::retry(|| file.set_len(position))?;
+                            }
+                            sys::retry(|| sys::sync_all(&file))?;
+                            drop(file);
+                            Ok(())
+                        }));
+                    }
+                },
+            }
+        }
+    }
+}
+impl<E: BlockingExecutor, B: AsRef<[u8]> + Send + 'static> futures::Sink for AsyncFileSink<E, B> {
+    type SinkItem = B;
+    type SinkError = std::io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> futures::StartSend<Self::SinkItem, Self::SinkError> {
+        self.poll_write()?;
         match self.state {
-            AsyncFileSinkState::Write(ref mut future) => {
-                match future.poll()? {
-                    Async::Ready(file) => {
-                        self.state = AsyncFileSinkState::Ready(file);
-                        Ok(Async::Ready(()))
-                    },
-                    _ => Ok(Async::NotReady)
+            AsyncFileSinkState::Write(_) | AsyncFileSinkState::Sync(_) | AsyncFileSinkState::SetLen(_) => {
+                if !self.queue_has_room() {
+                    return Ok(AsyncSink::NotReady(item));
                 }
+                self.queued_bytes += item.as_ref().len();
+                self.queue.push_back(item);
+                Ok(AsyncSink::Ready)
+            },
+            AsyncFileSinkState::Ready(_) => {
+                // элементы очереди записываются раньше нового
+                self.queued_bytes += item.as_ref().len();
+                self.queue.push_back(item);
+                self.start_write();
+                Ok(AsyncSink::Ready)
+            },
+            AsyncFileSinkState::Closing(_) | AsyncFileSinkState::Closed | AsyncFileSinkState::Swapping => {
+                Err(std::io::Error::other("`File` instance already shutdown"))
             },
-            AsyncFileSinkState::Ready(_) => Ok(Async::Ready(())),
-            AsyncFileSinkState::Swapping => Err(std::io::Error::new(std::io::ErrorKind::Other, "`File` instance already shutdown")),
+        }
+    }
+
+    fn poll_complete(&mut self) -> futures::Poll<(), Self::SinkError> {
+        loop {
+            try_ready!(self.poll_write());
+            match self.state {
+                AsyncFileSinkState::Ready(_) => {
+                    if self.queue.is_empty() {
+                        return Ok(Async::Ready(()));
+                    }
+                    self.start_write();
+                },
+                AsyncFileSinkState::Closed => return Ok(Async::Ready(())),
+                AsyncFileSinkState::Write(_) | AsyncFileSinkState::Sync(_) | AsyncFileSinkState::SetLen(_) => unreachable!(),
+                AsyncFileSinkState::Closing(_) | AsyncFileSinkState::Swapping => {
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
+                },
+            }
         }
     }
 }
@@ -270,159 +1553,787 @@ impl From<std::fs::File> for AsyncFileSink {
         Self::from_std(&DEFAULT_CPU_POOL, file)
     }
 }
-impl TryFrom<AsyncFileSink> for std::fs::File {
+impl<E, B> TryFrom<AsyncFileSink<E, B>> for std::fs::File {
     type Error = std::io::Error;
 
-    fn try_from(file: AsyncFileSink) -> Result<Self, Self::Error> {
-        match file.state {
+    fn try_from(mut file: AsyncFileSink<E, B>) -> Result<Self, Self::Error> {
+        if !file.queue.is_empty() {
+            return Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "`File` instance is blocked"));
+        }
+        match std::mem::replace(&mut file.state, AsyncFileSinkState::Swapping) {
             AsyncFileSinkState::Ready(file) => Ok(file),
-            AsyncFileSinkState::Swapping => Err(std::io::Error::new(std::io::ErrorKind::Other, "`File` instance already shutdown")),
+            AsyncFileSinkState::Closing(_) | AsyncFileSinkState::Closed | AsyncFileSinkState::Swapping => Err(std::io::Error::other("`File` instance already shutdown")),
             _ => Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "`File` instance is blocked"))
         }
     }
 }
-impl std::fmt::Debug for AsyncFileSink {
+impl<E, B> Drop for AsyncFileSink<E, B> {
+    fn drop(&mut self) {
+        // принятые элементы дописываются и после освобождения sink (см. `PoolTask::forget`),
+        // кроме частей, оставшихся после `max_chunk_size`
+        if let AsyncFileSinkState::Write(task) = std::mem::replace(&mut self.state, AsyncFileSinkState::Swapping) {
+            task.forget();
+        }
+    }
+}
+impl<E, B> std::fmt::Debug for AsyncFileSink<E, B> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         f.debug_struct("AsyncFileSink").finish()
     }
 }
 
+/// Записывает с текущей позиции элементы `items` (первый - начиная с `offset`), но не больше
+/// `max_chunk_size` байт (0 - без ограничения); возвращает незаписанные элементы
+/// и записанную часть первого из них
+fn write_chunk<B: AsRef<[u8]>>(file: &mut std::fs::File, mut items: VecDeque<B>, offset: usize, max_chunk_size: usize, meter: &Meter) -> std::io::Result<(VecDeque<B>, usize)> {
+    let mut limit = if max_chunk_size == 0 { usize::MAX } else { max_chunk_size };
+    let (mut written, mut rest_offset) = (0, 0);
+    {
+        let mut slices: Vec<&[u8]> = Vec::with_capacity(items.len());
+        for (index, item) in items.iter().enumerate() {
+            let start = if index == 0 { offset } else { 0 };
+            let data = &item.as_ref()[start..];
+            if data.len() > limit {
+                slices.push(&data[..limit]);
+                rest_offset = start + limit;
+                break;
+            }
+            slices.push(data);
+            limit -= data.len();
+            written = index + 1;
+            if limit == 0 {
+                break;
+            }
+        }
+        if slices.len() == 1 {
+            file.write_all(slices[0])?;
+        } else {
+            sys::write_all_vectored(file, &slices)?;
+        }
+        meter.written(slices.iter().map(|slice| slice.len()).sum());
+    }
+    items.drain(..written);
+    Ok((items, rest_offset))
+}
+
 
 // AsyncFileRead
 
 enum AsyncFileReadState {
-    Read(CpuFuture<(std::fs::File, usize), std::io::Error>),
+    Read(PoolTask<(std::fs::File, BytesMut)>),
+    ReadVectored(PoolTask<(std::fs::File, Vec<Bytes>)>),
+    Seek(PoolTask<(std::fs::File, u64)>),
     Ready(std::fs::File),
     Swapping,
 }
 
-/// Структура для асинхронного чтения файла
-pub struct AsyncFileRead {
-    cpu_pool: &'static CpuPool,
+/// Структура для асинхронного чтения файла.
+///
+/// Если чтение не может быть выполнено сразу, возвращается `WouldBlock`, а текущая задача
+/// (при наличии) пробуждается по завершении операции в пуле потоков.
+///
+/// Задача в пуле потоков читает в буфер, которым владеет, и возвращает его обертке;
+/// `poll_read_bytes` отдает этот буфер вызывающему без копирования.
+pub struct AsyncFileRead<E = &'static CpuPool> {
+    executor: E,
+    raw: sys::RawFile,
     state: AsyncFileReadState,
-    buf: Arc<RwLock<Vec<u8>>>
+    /// Буфер следующего чтения; на время чтения передается в пул потоков
+    buf: BytesMut,
+    buffer_size: usize,
+    /// Прочитанные, но еще не отданные данные (если при повторном вызове `read`
+    /// передан буфер меньше исходного)
+    pending: BytesMut,
+    nowait: bool,
+    native_io: bool,
 }
 impl AsyncFileRead {
     #[inline]
     pub fn from_std (cpu_pool: &'static CpuPool, file: std::fs::File, buffer_size: usize) -> AsyncFileRead {
-        let mut buf = Vec::with_capacity(buffer_size);
-        unsafe {
-            buf.set_len(buffer_size);
-        }
+        Self::from_std_with_executor(cpu_pool, file, buffer_size)
+    }
+
+    /// Открывает файл `path` на чтение в пуле потоков, не блокируя вызывающий поток
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> CpuFuture<AsyncFileRead, std::io::Error> {
+        Self::open_with_pool(pool_registry::pool_for(path.as_ref()), path)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<AsyncFileRead, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let file = sys::retry(|| std::fs::File::open(sys::long_path(&path)))?;
+            Ok(AsyncFileRead::from_std(cpu_pool, file, DEFAULT_BUFFER_SIZE))
+        })
+    }
+}
+impl<E: BlockingExecutor> AsyncFileRead<E> {
+
+    /// Обертка, выполняющая операции в `executor` (см. `AsyncFileWrite::from_std_with_executor`)
+    #[inline]
+    pub fn from_std_with_executor(executor: E, file: std::fs::File, buffer_size: usize) -> AsyncFileRead<E> {
         AsyncFileRead {
-            cpu_pool,
+            executor,
+            raw: sys::raw_file(&file),
             state: AsyncFileReadState::Ready(file),
-            buf: Arc::new(RwLock::new(buf))
+            buf: BytesMut::with_capacity(buffer_size),
+            buffer_size,
+            pending: BytesMut::new(),
+            nowait: false,
+            native_io: false,
         }
     }
-}
 
+    #[inline]
+    pub fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    /// Пытаться ли сначала прочитать данные из кэша страниц в текущем потоке
+    /// (`preadv2` с `RWF_NOWAIT` на Linux), обращаясь к пулу потоков, только если данных в кэше нет
+    #[inline]
+    pub fn nowait_reads(mut self, nowait: bool) -> Self {
+        self.nowait = nowait;
+        self
+    }
 
-impl std::io::Read for AsyncFileRead {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    /// Выполнять ли чтение через io_uring вместо пула потоков (см. `AsyncFileWrite::io_uring`)
+    #[inline]
+    pub fn io_uring(mut self, enabled: bool) -> Self {
+        self.native_io = enabled;
+        self
+    }
+
+    /// Выполнять ли чтение перекрывающимися операциями (см. `AsyncFileWrite::overlapped`)
+    #[inline]
+    pub fn overlapped(mut self, enabled: bool) -> Self {
+        self.native_io = enabled;
+        self
+    }
+
+    /// Новая обертка над копией дескриптора файла (см. `AsyncFileWrite::try_clone`)
+    pub fn try_clone(&self) -> std::io::Result<AsyncFileRead<E>> {
+        Ok(AsyncFileRead::from_std_with_executor(self.executor.clone(), sys::clone_raw_file(self.raw)?, self.buffer_size)
+            .io_uring(self.native_io))
+    }
+
+    /// Выполняет `f` над файлом в пуле потоков обертки (см. `AsyncFileWrite::with_file`)
+    pub fn with_file<F, R>(self, f: F) -> WithFile<Self, F, R>
+        where F: FnOnce(&mut std::fs::File) -> std::io::Result<R> + Send + 'static,
+              R: Send + 'static
+    {
+        WithFile::new(self, f)
+    }
+
+    /// Ограничивает время ожидания каждой операции (см. `Timeouted`)
+    #[inline]
+    pub fn timeout(self, timeout: std::time::Duration) -> Timeouted<Self> {
+        Timeouted::new(self, timeout)
+    }
+
+    /// Ограничивает скорость обмена данными (см. `Throttled`); копии `limiter` разделяют бюджет
+    #[inline]
+    pub fn throttle(self, limiter: RateLimiter) -> Throttled<Self> {
+        Throttled::new(self, limiter)
+    }
+
+    /// Поток записей файла, декодируемых `codec` (например, `LengthDelimitedCodec`)
+    #[inline]
+    pub fn framed<C: tokio::codec::Decoder>(self, codec: C) -> tokio::codec::FramedRead<Self, C> {
+        tokio::codec::FramedRead::new(self, codec)
+    }
+
+    /// Перемещает позицию чтения в пуле потоков (см. `AsyncSeek`).
+    ///
+    /// Вызывается повторно с тем же `pos`, пока не вернет `Ready` с новой позицией.
+    /// Начатое ранее чтение сначала завершается, а прочитанные им данные отбрасываются.
+    pub fn poll_seek(&mut self, pos: std::io::SeekFrom) -> Poll<u64, std::io::Error> {
         loop {
             match self.state {
-                AsyncFileReadState::Read(ref mut future) => {
-                    match future.poll()? {
-                        Async::Ready((file, size)) => {
-                            self.state = AsyncFileReadState::Ready(file);
-                            buf[..size].clone_from_slice(&self.buf.read().unwrap()[..size]);
-                            return Ok(size);
-                        },
-                        _ => {
-                            break;
-                        }
-                    }
+                AsyncFileReadState::ReadVectored(ref mut task) => {
+                    let (file, _) = try_ready!(task.poll());
+                    self.state = AsyncFileReadState::Ready(file);
+                },
+                AsyncFileReadState::Seek(ref mut task) => {
+                    let (file, position) = try_ready!(task.poll());
+                    self.state = AsyncFileReadState::Ready(file);
+                    return Ok(Async::Ready(position));
+                },
+                AsyncFileReadState::Read(ref mut task) => {
+                    let (file, data) = try_ready!(task.poll());
+                    self.state = AsyncFileReadState::Ready(file);
+                    self.reuse_buffer(data);
                 },
                 AsyncFileReadState::Ready(_) => {
+                    self.pending.clear();
                     if let AsyncFileReadState::Ready(mut file) = std::mem::replace(&mut self.state, AsyncFileReadState::Swapping) {
-                        let mut len = buf.len();
-                        {
-                            let cap = self.buf.write().unwrap().capacity();
-                            if len > cap {
-                                len = cap;
-                            }
-                        }
-                        let self_buf = self.buf.clone();
-
-                        self.state = AsyncFileReadState::Read(self.cpu_pool.spawn_fn(move || {
-                            let size = file.read(&mut self_buf.write().unwrap()[..len])?;
-                            Ok((file, size))
+                        self.state = AsyncFileReadState::Seek(self.executor.spawn_blocking(move || {
+                            let position = std::io::Seek::seek(&mut file, pos)?;
+                            Ok((file, position))
                         }));
                     }
                 },
                 AsyncFileReadState::Swapping => {
-                    return Err(std::io::Error::new(std::io::ErrorKind::Other, "`File` instance already shutdown"));
-                }
-            };
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
+                },
+            }
         }
+    }
 
-        Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "block"))
+    /// Future перемещения позиции чтения (см. `poll_seek`)
+    #[inline]
+    pub fn seek(self, pos: std::io::SeekFrom) -> Seek<Self> {
+        Seek::new(self, pos)
     }
-}
-impl tokio::io::AsyncRead for AsyncFileRead {}
 
+    /// Читает до `len` байт по смещению `offset` (`pread`), не изменяя позицию чтения;
+    /// меньше `len` байт возвращается только в конце файла.
+    ///
+    /// Выполняется через копию дескриптора независимо от последовательных операций,
+    /// поэтому несколько позиционных чтений одного файла могут выполняться одновременно.
+    pub fn read_at(&self, offset: u64, len: usize) -> PoolTask<Bytes> {
+        read_at(&self.executor, self.raw, offset, len)
+    }
 
-impl From<std::fs::File> for AsyncFileRead {
-    fn from(file: std::fs::File) -> Self {
-        Self::from_std(&DEFAULT_CPU_POOL, file, DEFAULT_BUFFER_SIZE)
+    /// Читает следующий блок (до размера буфера) и отдает буфер, в который читала задача
+    /// в пуле потоков, без копирования; `None` в конце файла.
+    ///
+    /// Вызывается повторно, пока не вернет `Ready`. Следующее чтение выполняется в новый буфер.
+    pub fn poll_read_bytes(&mut self) -> Poll<Option<BytesMut>, std::io::Error> {
+        if !self.pending.is_empty() {
+            return Ok(Async::Ready(Some(std::mem::replace(&mut self.pending, BytesMut::new()))));
+        }
+        let data = try_ready!(self.poll_read_block(self.buffer_size));
+        if data.is_empty() {
+            self.reuse_buffer(data);
+            return Ok(Async::Ready(None));
+        }
+        Ok(Async::Ready(Some(data)))
     }
-}
-impl TryFrom<AsyncFileRead> for std::fs::File {
-    type Error = std::io::Error;
 
-    fn try_from(file: AsyncFileRead) -> Result<Self, Self::Error> {
-        match file.state {
-            AsyncFileReadState::Ready(file) => Ok(file),
-            AsyncFileReadState::Swapping => Err(std::io::Error::new(std::io::ErrorKind::Other, "`File` instance already shutdown")),
-            _ => Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "`File` instance is blocked"))
+    /// Читает с текущей позиции подряд расположенные фрагменты длиной `lens` (например,
+    /// заголовок и тело записи) одним векторным чтением (`readv`) за одно обращение к пулу потоков.
+    ///
+    /// Фрагменты заполняются целиком, кроме конца файла: тогда последний непустой фрагмент
+    /// может быть короче, а следующие за ним - пустыми. Вызывается повторно с теми же `lens`,
+    /// пока не вернет `Ready`.
+    pub fn poll_read_vectored(&mut self, lens: &[usize]) -> Poll<Vec<Bytes>, std::io::Error> {
+        loop {
+            match self.state {
+                AsyncFileReadState::ReadVectored(ref mut task) => {
+                    let (file, bufs) = try_ready!(task.poll());
+                    self.state = AsyncFileReadState::Ready(file);
+                    return Ok(Async::Ready(bufs));
+                },
+                AsyncFileReadState::Read(ref mut task) => {
+                    let (file, data) = try_ready!(task.poll());
+                    self.state = AsyncFileReadState::Ready(file);
+                    self.reuse_buffer(data);
+                },
+                AsyncFileReadState::Seek(ref mut task) => {
+                    let (file, _) = try_ready!(task.poll());
+                    self.state = AsyncFileReadState::Ready(file);
+                },
+                AsyncFileReadState::Ready(_) => {
+                    // прочитанные, но не отданные данные читаются повторно
+                    let back = -(self.pending.len() as i64);
+                    self.pending.clear();
+                    if let AsyncFileReadState::Ready(mut file) = std::mem::replace(&mut self.state, AsyncFileReadState::Swapping) {
+                        let lens = lens.to_vec();
+                        let meter = Meter::new(&self.executor);
+                        self.state = AsyncFileReadState::ReadVectored(self.executor.spawn_blocking(move || {
+                            if back != 0 {
+                                std::io::Seek::seek(&mut file, std::io::SeekFrom::Current(back))?;
+                            }
+                            let mut bufs: Vec<Vec<u8>> = lens.iter().map(|&len| vec![0u8; len]).collect();
+                            let mut size = meter.read(sys::read_full_vectored(&mut file, &mut bufs)?);
+                            for buf in bufs.iter_mut() {
+                                let len = std::cmp::min(size, buf.len());
+                                buf.truncate(len);
+                                size -= len;
+                            }
+                            Ok((file, bufs.into_iter().map(Bytes::from).collect()))
+                        }));
+                    }
+                },
+                AsyncFileReadState::Swapping => {
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
+                },
+            }
         }
     }
-}
-impl std::fmt::Debug for AsyncFileRead {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        f.debug_struct("AsyncFileRead").finish()
+
+    /// Дожидается начатого чтения или начинает чтение до `len` байт (не больше размера буфера);
+    /// результат - буфер с прочитанными данными
+    fn poll_read_block(&mut self, len: usize) -> Poll<BytesMut, std::io::Error> {
+        loop {
+            match self.state {
+                AsyncFileReadState::Read(ref mut task) => {
+                    let (file, data) = try_ready!(task.poll());
+                    self.state = AsyncFileReadState::Ready(file);
+                    return Ok(Async::Ready(data));
+                },
+                AsyncFileReadState::Ready(_) => {
+                    if let AsyncFileReadState::Ready(file) = std::mem::replace(&mut self.state, AsyncFileReadState::Swapping) {
+                        let len = std::cmp::min(len, self.buffer_size);
+                        let mut buf = std::mem::replace(&mut self.buf, BytesMut::new());
+                        buf.clear();
+                        // буфер заполняется нулями, а не используется неинициализированным
+                        buf.resize(len, 0);
+                        self.state = AsyncFileReadState::Read(spawn_read(&self.executor, self.native_io, file, buf));
+                    }
+                },
+                AsyncFileReadState::ReadVectored(ref mut task) => {
+                    let (file, _) = try_ready!(task.poll());
+                    self.state = AsyncFileReadState::Ready(file);
+                },
+                AsyncFileReadState::Seek(ref mut task) => {
+                    let (file, _) = try_ready!(task.poll());
+                    self.state = AsyncFileReadState::Ready(file);
+                },
+                AsyncFileReadState::Swapping => {
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
+                },
+            }
+        }
     }
-}
 
+    /// Возвращает полностью отданный буфер для следующих чтений
+    #[inline]
+    fn reuse_buffer(&mut self, mut data: BytesMut) {
+        data.clear();
+        self.buf = data;
+    }
 
-// AsyncFileStream
+}
+
+
+impl<E: BlockingExecutor> std::io::Read for AsyncFileRead<E> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if !self.pending.is_empty() {
+            let size = std::cmp::min(buf.len(), self.pending.len());
+            buf[..size].copy_from_slice(&self.pending[..size]);
+            self.pending.advance(size);
+            return Ok(size);
+        }
+        if let AsyncFileReadState::Ready(ref file) = self.state {
+            if self.nowait {
+                if let Some(size) = sys::read_nowait(file, buf)? {
+                    return Ok(size);
+                }
+            }
+        }
+        match self.poll_read_block(buf.len())? {
+            Async::Ready(mut data) => {
+                let size = std::cmp::min(buf.len(), data.len());
+                buf[..size].copy_from_slice(&data[..size]);
+                if size < data.len() {
+                    data.advance(size);
+                    self.pending = data;
+                } else {
+                    self.reuse_buffer(data);
+                }
+                Ok(size)
+            },
+            Async::NotReady => Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "block")),
+        }
+    }
+}
+impl<E: BlockingExecutor> tokio::io::AsyncRead for AsyncFileRead<E> {}
+
+
+impl From<std::fs::File> for AsyncFileRead {
+    fn from(file: std::fs::File) -> Self {
+        Self::from_std(&DEFAULT_CPU_POOL, file, DEFAULT_BUFFER_SIZE)
+    }
+}
+impl<E> TryFrom<AsyncFileRead<E>> for std::fs::File {
+    type Error = std::io::Error;
+
+    /// Прочитанные, но не отданные данные отбрасываются: позиция файла возвращается к ним
+    fn try_from(file: AsyncFileRead<E>) -> Result<Self, Self::Error> {
+        let pending = file.pending.len() as i64;
+        match file.state {
+            AsyncFileReadState::Ready(mut file) => {
+                if pending > 0 {
+                    std::io::Seek::seek(&mut file, std::io::SeekFrom::Current(-pending))?;
+                }
+                Ok(file)
+            },
+            AsyncFileReadState::Swapping => Err(std::io::Error::other("`File` instance already shutdown")),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "`File` instance is blocked"))
+        }
+    }
+}
+impl<E> std::fmt::Debug for AsyncFileRead<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AsyncFileRead").finish()
+    }
+}
+
+
+// AsyncFileStream
 
 enum AsyncFileStreamState {
-    Read(CpuFuture<(std::fs::File, Vec<u8>), std::io::Error>),
+    Read(PoolTask<(std::fs::File, Vec<u8>, std::time::Duration)>),
+    Seek(PoolTask<(std::fs::File, u64)>),
     Ready(std::fs::File),
     Swapping,
 }
 
+/// Чтения, выполняющиеся с опережением (см. `AsyncFileStream::read_ahead`)
+struct Prefetch {
+    /// Копия дескриптора для позиционных чтений
+    file: Arc<std::fs::File>,
+    /// Смещение следующего возвращаемого блока
+    position: u64,
+    /// Смещение следующего запускаемого чтения
+    offset: u64,
+    /// Запрошенный размер и чтение, в порядке смещений
+    reads: VecDeque<(usize, PoolTask<Vec<u8>>)>,
+}
+
 /// Структура для асинхронного чтения файла
-pub struct AsyncFileStream {
-    cpu_pool: &'static CpuPool,
+pub struct AsyncFileStream<E = &'static CpuPool> {
+    executor: E,
+    raw: sys::RawFile,
     state: AsyncFileStreamState,
     buffer_size: usize,
+    adaptive: Option<AdaptiveSize>,
+    nowait: bool,
+    /// Сколько байт осталось прочитать до конца текущего окна
+    remaining: Option<u64>,
+    read_ahead: usize,
+    prefetch: Option<Prefetch>,
+    /// Выравнивание чтений в режиме `direct_io`
+    direct: Option<usize>,
+    native_io: bool,
 }
 impl AsyncFileStream {
     #[inline]
     pub fn from_std (cpu_pool: &'static CpuPool, file: std::fs::File, buffer_size: usize) -> AsyncFileStream {
+        Self::from_std_with_executor(cpu_pool, file, buffer_size)
+    }
+
+    /// Поток ровно `len` байт файла начиная с `offset` (см. `range`)
+    #[inline]
+    pub fn from_range(cpu_pool: &'static CpuPool, file: std::fs::File, offset: u64, len: u64, buffer_size: usize) -> AsyncFileStream {
+        Self::from_std(cpu_pool, file, buffer_size).range(offset, len)
+    }
+
+    /// Открывает файл `path` на чтение в пуле потоков (см. `AsyncFileRead::open`)
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> CpuFuture<AsyncFileStream, std::io::Error> {
+        Self::open_with_pool(pool_registry::pool_for(path.as_ref()), path)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<AsyncFileStream, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let file = sys::retry(|| std::fs::File::open(sys::long_path(&path)))?;
+            Ok(AsyncFileStream::from_std(cpu_pool, file, DEFAULT_BUFFER_SIZE))
+        })
+    }
+}
+impl<E: BlockingExecutor> AsyncFileStream<E> {
+
+    /// Обертка, выполняющая операции в `executor` (см. `AsyncFileWrite::from_std_with_executor`)
+    #[inline]
+    pub fn from_std_with_executor(executor: E, file: std::fs::File, buffer_size: usize) -> AsyncFileStream<E> {
         AsyncFileStream {
-            cpu_pool,
+            executor,
+            raw: sys::raw_file(&file),
             state: AsyncFileStreamState::Ready(file),
-            buffer_size
+            buffer_size,
+            adaptive: None,
+            nowait: false,
+            remaining: None,
+            read_ahead: 0,
+            prefetch: None,
+            direct: None,
+            native_io: false,
+        }
+    }
+
+    #[inline]
+    pub fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    /// Включает подбор размера блока по достигнутой скорости чтения (см. `tune`):
+    /// размер изменяется вдвое, пока это ускоряет чтение
+    pub fn adaptive(mut self, adaptive: bool) -> Self {
+        self.adaptive = if adaptive {
+            Some(AdaptiveSize::new(self.buffer_size))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Пытаться ли сначала прочитать блок из кэша страниц в текущем потоке
+    /// (`preadv2` с `RWF_NOWAIT` на Linux), обращаясь к пулу потоков, только если данных в кэше нет
+    #[inline]
+    pub fn nowait_reads(mut self, nowait: bool) -> Self {
+        self.nowait = nowait;
+        self
+    }
+
+    /// Включает чтение с опережением: поток держит в пуле потоков до `depth` чтений следующих
+    /// блоков (позиционных, через копию дескриптора) и отдает их по порядку, поэтому диск
+    /// не простаивает, пока потребитель обрабатывает блок. Значения 0 и 1 отключают режим.
+    ///
+    /// Размер блока в этом режиме не подбирается (`adaptive`), а чтение из кэша в текущем
+    /// потоке (`nowait_reads`) не выполняется. Позиция файла переносится на конец
+    /// отданных данных перед `poll_seek`, `with_file` и получением `File`.
+    #[inline]
+    pub fn read_ahead(mut self, depth: usize) -> Self {
+        self.read_ahead = depth;
+        self
+    }
+
+    /// Режим чтения для файлов, открытых с `O_DIRECT` (см. `AsyncOpenOptionsExt::direct`):
+    /// блоки читаются в буферы, выровненные по `alignment`, а размер чтения округляется вверх
+    /// до `alignment`. Смещения чтений (`range`, `poll_seek`) должны быть выровнены; при
+    /// ограничении (`limit`) позиция файла может оказаться дальше отданных данных.
+    /// `nowait_reads` в этом режиме не действует.
+    #[inline]
+    pub fn direct_io(mut self, alignment: usize) -> Self {
+        self.direct = Some(alignment);
+        self
+    }
+
+    /// Выполнять ли чтение через io_uring вместо пула потоков (см. `AsyncFileWrite::io_uring`).
+    /// Чтение с опережением (`read_ahead`) и режим `direct_io` всегда используют пул.
+    #[inline]
+    pub fn io_uring(mut self, enabled: bool) -> Self {
+        self.native_io = enabled;
+        self
+    }
+
+    /// Выполнять ли чтение перекрывающимися операциями (см. `AsyncFileWrite::overlapped`).
+    /// Чтение с опережением (`read_ahead`) и режим `direct_io` всегда используют пул.
+    #[inline]
+    pub fn overlapped(mut self, enabled: bool) -> Self {
+        self.native_io = enabled;
+        self
+    }
+
+    /// Ограничивает количество читаемых байт: после `limit` байт поток завершается
+    #[inline]
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.remaining = Some(limit);
+        self
+    }
+
+    /// Поток строк файла (см. `AsyncFileLines`)
+    #[inline]
+    pub fn lines(self) -> AsyncFileLines<Self> {
+        AsyncFileLines::new(self)
+    }
+
+    /// Ограничивает поток диапазоном `len` байт начиная с `offset`: позиция перемещается
+    /// в пуле потоков перед первым чтением, последний блок укорачивается до конца диапазона,
+    /// а если файл заканчивается раньше, поток завершается в конце файла.
+    ///
+    /// Вызывается до начала чтения; иначе паникует.
+    pub fn range(mut self, offset: u64, len: u64) -> Self {
+        match std::mem::replace(&mut self.state, AsyncFileStreamState::Swapping) {
+            AsyncFileStreamState::Ready(mut file) => {
+                self.state = AsyncFileStreamState::Seek(self.executor.spawn_blocking(move || {
+                    let position = std::io::Seek::seek(&mut file, std::io::SeekFrom::Start(offset))?;
+                    Ok((file, position))
+                }));
+            },
+            _ => panic!("`range` must be set before reading the stream"),
+        }
+        self.remaining = Some(len);
+        self
+    }
+
+    /// Задает (или снимает) ограничение для следующего окна чтения, считая от текущей позиции.
+    ///
+    /// Вместе с `poll_seek` позволяет читать из одного открытого файла несколько окон подряд:
+    /// после завершения окна поток снова возвращает данные, как только задано новое ограничение.
+    #[inline]
+    pub fn set_limit(&mut self, limit: Option<u64>) {
+        self.remaining = limit;
+    }
+
+    /// Сколько байт осталось прочитать до конца текущего окна
+    #[inline]
+    pub fn remaining(&self) -> Option<u64> {
+        self.remaining
+    }
+
+    /// Перемещает позицию чтения в пуле потоков между чтениями блоков.
+    ///
+    /// Вызывается повторно с тем же `pos`, пока не вернет `Ready` с новой позицией.
+    /// Начатое ранее чтение сначала завершается, а прочитанный им блок отбрасывается.
+    /// Ограничение `limit` не сбрасывается.
+    pub fn poll_seek(&mut self, pos: std::io::SeekFrom) -> Poll<u64, std::io::Error> {
+        self.stop_prefetch()?;
+        loop {
+            match self.state {
+                AsyncFileStreamState::Seek(ref mut future) => {
+                    let (file, position) = try_ready!(future.poll());
+                    self.state = AsyncFileStreamState::Ready(file);
+                    return Ok(Async::Ready(position));
+                },
+                AsyncFileStreamState::Read(ref mut future) => {
+                    let (file, _, _) = try_ready!(future.poll());
+                    self.state = AsyncFileStreamState::Ready(file);
+                },
+                AsyncFileStreamState::Ready(_) => {
+                    if let AsyncFileStreamState::Ready(mut file) = std::mem::replace(&mut self.state, AsyncFileStreamState::Swapping) {
+                        self.state = AsyncFileStreamState::Seek(self.executor.spawn_blocking(move || {
+                            let position = std::io::Seek::seek(&mut file, pos)?;
+                            Ok((file, position))
+                        }));
+                    }
+                },
+                AsyncFileStreamState::Swapping => {
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
+                },
+            }
+        }
+    }
+
+    /// Future перемещения позиции чтения (см. `poll_seek`)
+    #[inline]
+    pub fn seek(self, pos: std::io::SeekFrom) -> Seek<Self> {
+        Seek::new(self, pos)
+    }
+
+    /// Читает до `len` байт по смещению `offset`, не изменяя позицию потока
+    /// (см. `AsyncFileRead::read_at`)
+    pub fn read_at(&self, offset: u64, len: usize) -> PoolTask<Bytes> {
+        read_at(&self.executor, self.raw, offset, len)
+    }
+
+    /// Новая обертка над копией дескриптора файла (см. `AsyncFileWrite::try_clone`)
+    pub fn try_clone(&self) -> std::io::Result<AsyncFileStream<E>> {
+        let mut clone = AsyncFileStream::from_std_with_executor(self.executor.clone(), sys::clone_raw_file(self.raw)?, self.buffer_size);
+        clone.direct = self.direct;
+        clone.native_io = self.native_io;
+        Ok(clone)
+    }
+
+    /// Выполняет `f` над файлом в пуле потоков обертки (см. `AsyncFileWrite::with_file`)
+    pub fn with_file<F, R>(self, f: F) -> WithFile<Self, F, R>
+        where F: FnOnce(&mut std::fs::File) -> std::io::Result<R> + Send + 'static,
+              R: Send + 'static
+    {
+        WithFile::new(self, f)
+    }
+
+    /// Ограничивает время ожидания каждой операции (см. `Timeouted`)
+    #[inline]
+    pub fn timeout(self, timeout: std::time::Duration) -> Timeouted<Self> {
+        Timeouted::new(self, timeout)
+    }
+
+    /// Ограничивает скорость обмена данными (см. `Throttled`); копии `limiter` разделяют бюджет
+    #[inline]
+    pub fn throttle(self, limiter: RateLimiter) -> Throttled<Self> {
+        Throttled::new(self, limiter)
+    }
+
+    /// Завершает чтение с опережением: незавершенные чтения отбрасываются, а позиция файла
+    /// переносится на конец отданных данных (`lseek` не обращается к диску)
+    fn stop_prefetch(&mut self) -> std::io::Result<()> {
+        if let Some(prefetch) = self.prefetch.take() {
+            if let AsyncFileStreamState::Ready(ref mut file) = self.state {
+                std::io::Seek::seek(file, std::io::SeekFrom::Start(prefetch.position))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_prefetch(&mut self) -> Poll<Option<Bytes>, std::io::Error> {
+        if self.prefetch.is_none() {
+            let position = match self.state {
+                AsyncFileStreamState::Ready(ref mut file) => std::io::Seek::seek(file, std::io::SeekFrom::Current(0))?,
+                _ => unreachable!(),
+            };
+            self.prefetch = Some(Prefetch {
+                file: Arc::new(sys::clone_raw_file(self.raw)?),
+                position,
+                offset: position,
+                reads: VecDeque::new(),
+            });
+        }
+        let executor = &self.executor;
+        let prefetch = self.prefetch.as_mut().unwrap();
+        while prefetch.reads.len() < self.read_ahead {
+            let in_flight = prefetch.offset - prefetch.position;
+            let size = match self.remaining {
+                Some(remaining) => std::cmp::min(self.buffer_size as u64, remaining.saturating_sub(in_flight)) as usize,
+                None => self.buffer_size,
+            };
+            if size == 0 {
+                break;
+            }
+            let file = prefetch.file.clone();
+            let offset = prefetch.offset;
+            let direct = self.direct;
+            let meter = Meter::new(executor);
+            prefetch.reads.push_back((size, executor.spawn_blocking(move || {
+                if let Some(alignment) = direct {
+                    let buf = direct::read_aligned_at(&file, size, offset, alignment)?;
+                    meter.read(buf.len());
+                    return Ok(buf);
+                }
+                let mut buf = vec![0; size];
+                let len = meter.read(sys::read_full_at(&file, &mut buf, offset)?);
+                buf.truncate(len);
+                Ok(buf)
+            })));
+            prefetch.offset += size as u64;
+        }
+        let mut buf = match prefetch.reads.front_mut() {
+            Some(&mut (_, ref mut task)) => try_ready!(task.poll()),
+            None => return Ok(Async::Ready(None)),
+        };
+        let (size, _) = prefetch.reads.pop_front().unwrap();
+        if let Some(ref mut remaining) = self.remaining {
+            // ограничение могло уменьшиться после запуска чтения
+            if buf.len() as u64 > *remaining {
+                buf.truncate(*remaining as usize);
+            }
+            *remaining -= buf.len() as u64;
+        }
+        prefetch.position += buf.len() as u64;
+        if buf.len() < size {
+            // конец файла: следующие чтения повторятся с новой позиции
+            prefetch.reads.clear();
+            prefetch.offset = prefetch.position;
         }
+        Ok(Async::Ready(if buf.is_empty() { None } else { Some(Bytes::from(buf)) }))
     }
 }
-impl futures::stream::Stream for AsyncFileStream {
+impl<E: BlockingExecutor> futures::stream::Stream for AsyncFileStream<E> {
     type Item = Bytes;
     type Error = std::io::Error;
 
     fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.prefetch.is_some() {
+            return self.poll_prefetch();
+        }
         loop {
             match self.state {
                 AsyncFileStreamState::Read(ref mut future) => {
                     match future.poll()? {
-                        Async::Ready((file, buf)) => {
+                        Async::Ready((file, buf, elapsed)) => {
                             self.state = AsyncFileStreamState::Ready(file);
+                            if let Some(ref mut adaptive) = self.adaptive {
+                                self.buffer_size = adaptive.record(buf.len(), elapsed);
+                            }
+                            if let Some(ref mut remaining) = self.remaining {
+                                *remaining -= buf.len() as u64;
+                            }
                             return Ok(Async::Ready(
-                                if buf.len() > 0 {
+                                if !buf.is_empty() {
                                     Some(Bytes::from(buf))
                                 } else {
                                     None
@@ -434,22 +2345,52 @@ impl futures::stream::Stream for AsyncFileStream {
                         }
                     }
                 },
+                AsyncFileStreamState::Seek(ref mut future) => {
+                    // перемещение, не дождавшееся результата в `poll_seek`
+                    let (file, _) = try_ready!(future.poll());
+                    self.state = AsyncFileStreamState::Ready(file);
+                },
                 AsyncFileStreamState::Ready(_) => {
-                    if let AsyncFileStreamState::Ready(mut file) = std::mem::replace(&mut self.state, AsyncFileStreamState::Swapping) {
-                        let buffer_size = self.buffer_size;
-                        let mut buf: Vec<u8> = Vec::with_capacity(buffer_size);
-                        unsafe {
-                            buf.set_len(buffer_size);
+                    if self.remaining == Some(0) {
+                        return Ok(Async::Ready(None));
+                    }
+                    if self.read_ahead > 1 {
+                        return self.poll_prefetch();
+                    }
+                    let buffer_size = match self.remaining {
+                        Some(remaining) if remaining < self.buffer_size as u64 => remaining as usize,
+                        _ => self.buffer_size,
+                    };
+                    if let Some(alignment) = self.direct {
+                        if let AsyncFileStreamState::Ready(mut file) = std::mem::replace(&mut self.state, AsyncFileStreamState::Swapping) {
+                            let meter = Meter::new(&self.executor);
+                            self.state = AsyncFileStreamState::Read(self.executor.spawn_blocking(move || {
+                                let started = std::time::Instant::now();
+                                let buf = direct::read_aligned(&mut file, buffer_size, alignment)?;
+                                meter.read(buf.len());
+                                Ok((file, buf, started.elapsed()))
+                            }));
+                        }
+                        continue;
+                    }
+                    let mut buf: Vec<u8> = vec![0; buffer_size];
+                    if self.nowait {
+                        if let AsyncFileStreamState::Ready(ref file) = self.state {
+                            if let Some(size) = sys::read_nowait(file, &mut buf)? {
+                                buf.truncate(size);
+                                if let Some(ref mut remaining) = self.remaining {
+                                    *remaining -= size as u64;
+                                }
+                                return Ok(Async::Ready(if size > 0 { Some(Bytes::from(buf)) } else { None }));
+                            }
                         }
-                        self.state = AsyncFileStreamState::Read(self.cpu_pool.spawn_fn(move || {
-                            let size = file.read(&mut buf[..buffer_size])?;
-                            buf.truncate(size);
-                            Ok((file, buf))
-                        }));
+                    }
+                    if let AsyncFileStreamState::Ready(file) = std::mem::replace(&mut self.state, AsyncFileStreamState::Swapping) {
+                        self.state = AsyncFileStreamState::Read(spawn_stream_read(&self.executor, self.native_io, file, buf));
                     }
                 },
                 AsyncFileStreamState::Swapping => {
-                    return Err(std::io::Error::new(std::io::ErrorKind::Other, "`File` instance already shutdown"));
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
                 }
             };
         }
@@ -461,19 +2402,484 @@ impl From<std::fs::File> for AsyncFileStream {
         Self::from_std(&DEFAULT_CPU_POOL, file, DEFAULT_BUFFER_SIZE)
     }
 }
-impl TryFrom<AsyncFileStream> for std::fs::File {
+impl<E: BlockingExecutor> TryFrom<AsyncFileStream<E>> for std::fs::File {
     type Error = std::io::Error;
 
-    fn try_from(file: AsyncFileStream) -> Result<Self, Self::Error> {
+    fn try_from(mut file: AsyncFileStream<E>) -> Result<Self, Self::Error> {
+        file.stop_prefetch()?;
         match file.state {
             AsyncFileStreamState::Ready(file) => Ok(file),
-            AsyncFileStreamState::Swapping => Err(std::io::Error::new(std::io::ErrorKind::Other, "`File` instance already shutdown")),
+            AsyncFileStreamState::Swapping => Err(std::io::Error::other("`File` instance already shutdown")),
             _ => Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "`File` instance is blocked"))
         }
     }
 }
-impl std::fmt::Debug for AsyncFileStream {
+impl<E> std::fmt::Debug for AsyncFileStream<E> {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         f.debug_struct("AsyncFileStream").finish()
     }
 }
+
+
+// AsyncFile
+
+enum AsyncFileState {
+    Read(PoolTask<(std::fs::File, Vec<u8>, usize)>),
+    Write(PoolTask<(std::fs::File, Vec<u8>, usize)>),
+    Flush(PoolTask<std::fs::File>),
+    SetLen(PoolTask<std::fs::File>),
+    Sync(PoolTask<std::fs::File>),
+    Shutdown(PoolTask<std::fs::File>),
+    Seek(PoolTask<(std::fs::File, u64)>),
+    Ready(std::fs::File),
+    Swapping,
+}
+
+/// Структура для асинхронного чтения, записи и перемещения позиции в одном файле
+/// (например, для чтения с последующим изменением на месте).
+///
+/// Операции выполняются в пуле потоков строго по очереди: следующая начинается только после
+/// завершения предыдущей. Если операция не может быть выполнена сразу, возвращается
+/// `WouldBlock`, а текущая задача пробуждается по завершении операции в пуле потоков.
+///
+/// Чтение выполняется блоками размера буфера; непрочитанный остаток блока отбрасывается
+/// перед записью или перемещением позиции, так что позиция файла всегда соответствует
+/// прочитанным и записанным данным.
+pub struct AsyncFile<E = &'static CpuPool> {
+    executor: E,
+    raw: sys::RawFile,
+    state: AsyncFileState,
+    /// Буфер чтения и записи; на время операции передается в пул потоков
+    buf: Vec<u8>,
+    buffer_size: usize,
+    /// Непрочитанные данные последнего блока: `buf[read_pos..read_len]`
+    read_pos: usize,
+    read_len: usize,
+}
+impl AsyncFile {
+
+    #[inline]
+    pub fn from_std(cpu_pool: &'static CpuPool, file: std::fs::File, buffer_size: usize) -> AsyncFile {
+        Self::from_std_with_executor(cpu_pool, file, buffer_size)
+    }
+
+    /// Открывает существующий файл `path` на чтение и запись в пуле потоков
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> CpuFuture<AsyncFile, std::io::Error> {
+        Self::open_with_pool(pool_registry::pool_for(path.as_ref()), path)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<AsyncFile, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let file = sys::retry(|| std::fs::OpenOptions::new().read(true).write(true).open(sys::long_path(&path)))?;
+            Ok(AsyncFile::from_std(cpu_pool, file, DEFAULT_BUFFER_SIZE))
+        })
+    }
+
+    /// Создает (или усекает) файл `path` и открывает его на чтение и запись в пуле потоков
+    #[inline]
+    pub fn create<P: AsRef<Path>>(path: P) -> CpuFuture<AsyncFile, std::io::Error> {
+        Self::create_with_pool(pool_registry::pool_for(path.as_ref()), path)
+    }
+
+    pub fn create_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<AsyncFile, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let file = sys::retry(|| {
+                std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(sys::long_path(&path))
+            })?;
+            Ok(AsyncFile::from_std(cpu_pool, file, DEFAULT_BUFFER_SIZE))
+        })
+    }
+}
+impl<E: BlockingExecutor> AsyncFile<E> {
+
+    /// Обертка, выполняющая операции в `executor` (см. `AsyncFileWrite::from_std_with_executor`)
+    #[inline]
+    pub fn from_std_with_executor(executor: E, file: std::fs::File, buffer_size: usize) -> AsyncFile<E> {
+        AsyncFile {
+            executor,
+            raw: sys::raw_file(&file),
+            state: AsyncFileState::Ready(file),
+            buf: Vec::with_capacity(buffer_size),
+            buffer_size,
+            read_pos: 0,
+            read_len: 0,
+        }
+    }
+
+    #[inline]
+    pub fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    /// Новая обертка над копией дескриптора файла (см. `AsyncFileWrite::try_clone`)
+    pub fn try_clone(&self) -> std::io::Result<AsyncFile<E>> {
+        Ok(AsyncFile::from_std_with_executor(self.executor.clone(), sys::clone_raw_file(self.raw)?, self.buffer_size))
+    }
+
+    /// Выполняет `f` над файлом в пуле потоков обертки (см. `AsyncFileWrite::with_file`).
+    /// Непрочитанный остаток блока отбрасывается, позиция файла возвращается к прочитанным данным.
+    pub fn with_file<F, R>(self, f: F) -> WithFile<Self, F, R>
+        where F: FnOnce(&mut std::fs::File) -> std::io::Result<R> + Send + 'static,
+              R: Send + 'static
+    {
+        WithFile::new(self, f)
+    }
+
+    /// Ограничивает время ожидания каждой операции (см. `Timeouted`)
+    #[inline]
+    pub fn timeout(self, timeout: std::time::Duration) -> Timeouted<Self> {
+        Timeouted::new(self, timeout)
+    }
+
+    /// Ограничивает скорость обмена данными (см. `Throttled`); копии `limiter` разделяют бюджет
+    #[inline]
+    pub fn throttle(self, limiter: RateLimiter) -> Throttled<Self> {
+        Throttled::new(self, limiter)
+    }
+
+    /// Поток и sink записей, кодируемых и декодируемых `codec`, над одним файлом
+    #[inline]
+    pub fn framed<C: tokio::codec::Encoder + tokio::codec::Decoder>(self, codec: C) -> tokio::codec::Framed<Self, C> {
+        tokio::codec::Framed::new(self, codec)
+    }
+
+    /// Размер непрочитанного остатка блока, на который позиция файла опережает прочитанные данные
+    #[inline]
+    fn read_ahead(&self) -> usize {
+        self.read_len - self.read_pos
+    }
+
+    /// Отбрасывает непрочитанный остаток блока; возвращает смещение, на которое нужно
+    /// вернуть позицию файла
+    #[inline]
+    fn discard_read_ahead(&mut self) -> i64 {
+        let read_ahead = self.read_ahead() as i64;
+        self.read_pos = 0;
+        self.read_len = 0;
+        -read_ahead
+    }
+
+    /// Дожидается завершения начатой операции; результат чтения сохраняется в буфере,
+    /// результаты остальных операций отбрасываются
+    fn poll_idle(&mut self) -> Poll<(), std::io::Error> {
+        loop {
+            match self.state {
+                AsyncFileState::Read(ref mut task) => {
+                    let (file, buf, size) = try_ready!(task.poll());
+                    self.buf = buf;
+                    self.read_pos = 0;
+                    self.read_len = size;
+                    self.state = AsyncFileState::Ready(file);
+                },
+                AsyncFileState::Write(ref mut task) => {
+                    let (file, buf, _) = try_ready!(task.poll());
+                    self.buf = buf;
+                    self.state = AsyncFileState::Ready(file);
+                },
+                AsyncFileState::Flush(ref mut task) | AsyncFileState::SetLen(ref mut task) | AsyncFileState::Sync(ref mut task) | AsyncFileState::Shutdown(ref mut task) => {
+                    let file = try_ready!(task.poll());
+                    self.state = AsyncFileState::Ready(file);
+                },
+                AsyncFileState::Seek(ref mut task) => {
+                    let (file, _) = try_ready!(task.poll());
+                    self.state = AsyncFileState::Ready(file);
+                },
+                AsyncFileState::Ready(_) => return Ok(Async::Ready(())),
+                AsyncFileState::Swapping => {
+                    return Err(std::io::Error::other("`File` instance already shutdown"));
+                },
+            }
+        }
+    }
+
+    /// Забирает файл у готовой к работе обертки
+    fn take_ready_file(&mut self) -> std::fs::File {
+        match std::mem::replace(&mut self.state, AsyncFileState::Swapping) {
+            AsyncFileState::Ready(file) => file,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Проверяет, будет ли следующая операция начата без ожидания уже начатой
+    /// (см. `AsyncFileWrite::poll_ready`)
+    pub fn poll_ready(&mut self) -> Poll<(), std::io::Error> {
+        match self.state {
+            AsyncFileState::Read(ref mut task) | AsyncFileState::Write(ref mut task) => Ok(task.poll_finished()),
+            AsyncFileState::Flush(ref mut task) | AsyncFileState::SetLen(ref mut task) | AsyncFileState::Sync(ref mut task) | AsyncFileState::Shutdown(ref mut task) => Ok(task.poll_finished()),
+            AsyncFileState::Seek(ref mut task) => Ok(task.poll_finished()),
+            AsyncFileState::Ready(_) => Ok(Async::Ready(())),
+            AsyncFileState::Swapping => Err(std::io::Error::other("`File` instance already shutdown")),
+        }
+    }
+
+    /// Перемещает позицию файла в пуле потоков (см. `AsyncSeek`).
+    ///
+    /// Вызывается повторно с тем же `pos`, пока не вернет `Ready` с новой позицией.
+    /// Начатая ранее операция сначала завершается; `SeekFrom::Current` отсчитывается
+    /// от прочитанных данных.
+    pub fn poll_seek(&mut self, pos: std::io::SeekFrom) -> Poll<u64, std::io::Error> {
+        if let AsyncFileState::Seek(ref mut task) = self.state {
+            let (file, position) = try_ready!(task.poll());
+            self.state = AsyncFileState::Ready(file);
+            return Ok(Async::Ready(position));
+        }
+        try_ready!(self.poll_idle());
+        let back = self.discard_read_ahead();
+        let pos = match pos {
+            std::io::SeekFrom::Current(offset) => std::io::SeekFrom::Current(offset + back),
+            pos => pos,
+        };
+        let mut file = self.take_ready_file();
+        self.state = AsyncFileState::Seek(self.executor.spawn_blocking(move || {
+            let position = std::io::Seek::seek(&mut file, pos)?;
+            Ok((file, position))
+        }));
+        self.poll_seek(pos)
+    }
+
+    /// Future перемещения позиции (см. `poll_seek`)
+    #[inline]
+    pub fn seek(self, pos: std::io::SeekFrom) -> Seek<Self> {
+        Seek::new(self, pos)
+    }
+
+    /// Изменяет длину файла в пуле потоков (см. `AsyncFileWrite::poll_set_len`)
+    #[inline]
+    pub fn poll_set_len(&mut self, len: u64) -> Poll<(), std::io::Error> {
+        self.poll_resize(len, false)
+    }
+
+    /// Выделяет место под файл в пуле потоков (см. `AsyncFileWrite::poll_allocate`)
+    #[inline]
+    pub fn poll_allocate(&mut self, len: u64) -> Poll<(), std::io::Error> {
+        self.poll_resize(len, true)
+    }
+
+    /// Future изменения длины файла (см. `poll_set_len`)
+    #[inline]
+    pub fn set_len(self, len: u64) -> SetLen<Self> {
+        SetLen::new(self, len)
+    }
+
+    /// Future выделения места под файл (см. `poll_allocate`)
+    #[inline]
+    pub fn allocate(self, len: u64) -> SetLen<Self> {
+        SetLen::allocate(self, len)
+    }
+
+    fn poll_resize(&mut self, len: u64, allocate: bool) -> Poll<(), std::io::Error> {
+        if let AsyncFileState::SetLen(ref mut task) = self.state {
+            let file = try_ready!(task.poll());
+            self.state = AsyncFileState::Ready(file);
+            return Ok(Async::Ready(()));
+        }
+        try_ready!(self.poll_idle());
+        let file = self.take_ready_file();
+        self.state = AsyncFileState::SetLen(self.executor.spawn_blocking(move || {
+            resize(&file, len, allocate)?;
+            Ok(file)
+        }));
+        self.poll_resize(len, allocate)
+    }
+
+    /// Сбрасывает данные и метаданные файла на диск (`sync_all`) в пуле потоков
+    /// (см. `AsyncFileWrite::poll_sync_all`)
+    #[inline]
+    pub fn poll_sync_all(&mut self) -> Poll<(), std::io::Error> {
+        self.poll_sync(false)
+    }
+
+    /// Сбрасывает на диск данные файла (`sync_data`) (см. `AsyncFileWrite::poll_sync_data`)
+    #[inline]
+    pub fn poll_sync_data(&mut self) -> Poll<(), std::io::Error> {
+        self.poll_sync(true)
+    }
+
+    fn poll_sync(&mut self, data_only: bool) -> Poll<(), std::io::Error> {
+        if let AsyncFileState::Sync(ref mut task) = self.state {
+            let file = try_ready!(task.poll());
+            self.state = AsyncFileState::Ready(file);
+            return Ok(Async::Ready(()));
+        }
+        try_ready!(self.poll_idle());
+        let file = self.take_ready_file();
+        self.state = AsyncFileState::Sync(self.executor.spawn_blocking(move || {
+            if data_only {
+                sys::retry(|| file.sync_data())?;
+            } else {
+                sys::retry(|| sys::sync_all(&file))?;
+            }
+            Ok(file)
+        }));
+        self.poll_sync(data_only)
+    }
+
+    /// Читает до `len` байт по смещению `offset`, не изменяя позицию (см. `AsyncFileRead::read_at`)
+    pub fn read_at(&self, offset: u64, len: usize) -> PoolTask<Bytes> {
+        read_at(&self.executor, self.raw, offset, len)
+    }
+
+    /// Записывает `data` целиком по смещению `offset`, не изменяя позицию
+    /// (см. `AsyncFileWrite::write_at`)
+    pub fn write_at(&self, offset: u64, data: Bytes) -> PoolTask<()> {
+        let file = sys::clone_raw_file(self.raw);
+        let meter = Meter::new(&self.executor);
+        self.executor.spawn_blocking(move || {
+            sys::write_all_at(&file?, &data, offset)?;
+            meter.written(data.len());
+            Ok(())
+        })
+    }
+}
+
+impl<E: BlockingExecutor> std::io::Read for AsyncFile<E> {
+    fn read(&mut self, dst: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if self.read_ahead() > 0 {
+                let size = std::cmp::min(dst.len(), self.read_ahead());
+                dst[..size].copy_from_slice(&self.buf[self.read_pos..self.read_pos + size]);
+                self.read_pos += size;
+                return Ok(size);
+            }
+            if dst.is_empty() {
+                return Ok(0);
+            }
+            match self.state {
+                AsyncFileState::Read(_) => {
+                    match self.poll_idle()? {
+                        // конец файла
+                        Async::Ready(()) if self.read_len == 0 => return Ok(0),
+                        Async::Ready(()) => {},
+                        Async::NotReady => break,
+                    }
+                },
+                AsyncFileState::Ready(_) => {
+                    let mut file = self.take_ready_file();
+                    let mut buf = std::mem::take(&mut self.buf);
+                    buf.resize(self.buffer_size, 0);
+                    let meter = Meter::new(&self.executor);
+                    self.state = AsyncFileState::Read(self.executor.spawn_blocking(move || {
+                        let size = meter.read(sys::retry(|| file.read(&mut buf[..]))?);
+                        Ok((file, buf, size))
+                    }));
+                },
+                _ => {
+                    if let Async::NotReady = self.poll_idle()? {
+                        break;
+                    }
+                },
+            }
+        }
+
+        Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "block"))
+    }
+}
+impl<E: BlockingExecutor> tokio::io::AsyncRead for AsyncFile<E> {}
+
+impl<E: BlockingExecutor> std::io::Write for AsyncFile<E> {
+    fn write(&mut self, src: &[u8]) -> std::io::Result<usize> {
+        if let AsyncFileState::Write(ref mut task) = self.state {
+            return match task.poll()? {
+                Async::Ready((file, buf, size)) => {
+                    self.buf = buf;
+                    self.state = AsyncFileState::Ready(file);
+                    Ok(size)
+                },
+                Async::NotReady => Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "block")),
+            };
+        }
+        if let Async::NotReady = self.poll_idle()? {
+            return Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "block"));
+        }
+        // запись начинается с позиции прочитанных данных
+        let back = self.discard_read_ahead();
+        let mut file = self.take_ready_file();
+        let mut buf = std::mem::take(&mut self.buf);
+        buf.clear();
+        buf.extend_from_slice(&src[..std::cmp::min(src.len(), self.buffer_size)]);
+        let meter = Meter::new(&self.executor);
+        self.state = AsyncFileState::Write(self.executor.spawn_blocking(move || {
+            if back != 0 {
+                std::io::Seek::seek(&mut file, std::io::SeekFrom::Current(back))?;
+            }
+            let size = meter.written(sys::retry(|| file.write(&buf[..]))?);
+            Ok((file, buf, size))
+        }));
+        self.write(src)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let AsyncFileState::Flush(ref mut task) = self.state {
+            return match task.poll()? {
+                Async::Ready(file) => {
+                    self.state = AsyncFileState::Ready(file);
+                    Ok(())
+                },
+                Async::NotReady => Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "`File` instance is blocked")),
+            };
+        }
+        if let Async::NotReady = self.poll_idle()? {
+            return Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "`File` instance is blocked"));
+        }
+        let mut file = self.take_ready_file();
+        self.state = AsyncFileState::Flush(self.executor.spawn_blocking(move || {
+            sys::retry(|| file.flush())?;
+            Ok(file)
+        }));
+        self.flush()
+    }
+}
+
+impl<E: BlockingExecutor> tokio::io::AsyncWrite for AsyncFile<E> {
+    /// Дожидается начатой операции и сбрасывает данные на диск (`sync_all`)
+    fn shutdown(&mut self) -> futures::Poll<(), std::io::Error> {
+        if let AsyncFileState::Shutdown(ref mut task) = self.state {
+            let file = try_ready!(task.poll());
+            self.state = AsyncFileState::Ready(file);
+            return Ok(Async::Ready(()));
+        }
+        try_ready!(self.poll_idle());
+        let mut file = self.take_ready_file();
+        self.state = AsyncFileState::Shutdown(self.executor.spawn_blocking(move || {
+            sys::retry(|| file.flush())?;
+            sys::retry(|| sys::sync_all(&file))?;
+            Ok(file)
+        }));
+        self.shutdown()
+    }
+}
+impl From<std::fs::File> for AsyncFile {
+    fn from(file: std::fs::File) -> Self {
+        Self::from_std(&DEFAULT_CPU_POOL, file, DEFAULT_BUFFER_SIZE)
+    }
+}
+impl<E> TryFrom<AsyncFile<E>> for std::fs::File {
+    type Error = std::io::Error;
+
+    /// Непрочитанный остаток блока отбрасывается: позиция файла возвращается
+    /// к прочитанным данным
+    fn try_from(file: AsyncFile<E>) -> Result<Self, Self::Error> {
+        let read_ahead = (file.read_len - file.read_pos) as i64;
+        match file.state {
+            AsyncFileState::Ready(mut file) => {
+                if read_ahead > 0 {
+                    std::io::Seek::seek(&mut file, std::io::SeekFrom::Current(-read_ahead))?;
+                }
+                Ok(file)
+            },
+            AsyncFileState::Swapping => Err(std::io::Error::other("`File` instance already shutdown")),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "`File` instance is blocked"))
+        }
+    }
+}
+impl<E> std::fmt::Debug for AsyncFile<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AsyncFile")
+            .field("buffer_size", &self.buffer_size)
+            .field("read_ahead", &(self.read_len - self.read_pos))
+            .finish()
+    }
+}
@@ -0,0 +1,122 @@
+use futures::{Poll, Async, Stream};
+use bytes::{Bytes, BytesMut};
+
+
+// AsyncFileLines
+
+/// Поток строк из потока блоков данных (например, `AsyncFileStream`).
+///
+/// Строки передаются без завершающего `\n` (и `\r` перед ним); строка может быть
+/// разделена между блоками. Последняя строка без перевода строки также передается.
+/// Строка длиннее `max_line_length` передается как ошибка `InvalidData`, после чего
+/// ее остаток пропускается и чтение можно продолжить со следующей строки.
+pub struct AsyncFileLines<S> {
+    inner: S,
+    buf: BytesMut,
+    /// Часть буфера, уже проверенная на наличие перевода строки
+    searched: usize,
+    max_line_length: Option<usize>,
+    discarding: bool,
+    done: bool,
+}
+impl<S> AsyncFileLines<S>
+    where S: Stream,
+          S::Item: AsRef<[u8]>,
+          S::Error: From<std::io::Error>
+{
+    pub fn new(stream: S) -> AsyncFileLines<S> {
+        AsyncFileLines {
+            inner: stream,
+            buf: BytesMut::new(),
+            searched: 0,
+            max_line_length: None,
+            discarding: false,
+            done: false,
+        }
+    }
+
+    /// Ограничивает длину строки (без перевода строки)
+    #[inline]
+    pub fn max_line_length(mut self, max_line_length: usize) -> Self {
+        self.max_line_length = Some(max_line_length);
+        self
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    fn too_long(&self) -> S::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, "line is too long").into()
+    }
+
+    fn is_too_long(&self, len: usize) -> bool {
+        self.max_line_length.is_some_and(|max_line_length| len > max_line_length)
+    }
+}
+impl<S> Stream for AsyncFileLines<S>
+    where S: Stream,
+          S::Item: AsRef<[u8]>,
+          S::Error: From<std::io::Error>
+{
+    type Item = Bytes;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(index) = self.buf[self.searched..].iter().position(|&byte| byte == b'\n') {
+                let mut line = self.buf.split_to(self.searched + index + 1);
+                self.searched = 0;
+                if self.discarding {
+                    self.discarding = false;
+                    continue;
+                }
+                let mut len = line.len() - 1;
+                if len > 0 && line[len - 1] == b'\r' {
+                    len -= 1;
+                }
+                line.truncate(len);
+                if self.is_too_long(len) {
+                    return Err(self.too_long());
+                }
+                return Ok(Async::Ready(Some(line.freeze())));
+            }
+            self.searched = self.buf.len();
+            // `+ 1`: допустимая строка может завершаться `\r\n`
+            if self.is_too_long(self.buf.len().saturating_sub(1)) {
+                self.buf.clear();
+                self.searched = 0;
+                if !self.discarding {
+                    self.discarding = true;
+                    return Err(self.too_long());
+                }
+            }
+            if self.done {
+                if self.buf.is_empty() || self.discarding {
+                    return Ok(Async::Ready(None));
+                }
+                let line = self.buf.take();
+                self.searched = 0;
+                return Ok(Async::Ready(Some(line.freeze())));
+            }
+            match try_ready!(self.inner.poll()) {
+                Some(chunk) => self.buf.extend_from_slice(chunk.as_ref()),
+                None => self.done = true,
+            }
+        }
+    }
+}
+impl<S> std::fmt::Debug for AsyncFileLines<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AsyncFileLines")
+            .field("buffered", &self.buf.len())
+            .field("max_line_length", &self.max_line_length)
+            .finish()
+    }
+}
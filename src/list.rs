@@ -0,0 +1,134 @@
+use futures::{Poll, Future};
+use futures_cpupool::CpuPool;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use super::DEFAULT_CPU_POOL;
+use sys::long_path;
+
+/// Количество записей, метаданные которых запрашиваются за одно обращение к пулу потоков
+static STAT_BATCH_SIZE: usize = 128;
+
+/// Порядок сортировки списка содержимого каталога (по возрастанию)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortBy {
+    Name,
+    Size,
+    Mtime,
+}
+
+/// Запись списка содержимого каталога
+#[derive(Debug, Clone)]
+pub struct ListEntry {
+    pub path: PathBuf,
+    pub file_name: OsString,
+    pub file_type: std::fs::FileType,
+    /// Метаданные записи (символические ссылки не разыменовываются)
+    pub metadata: Option<std::fs::Metadata>,
+}
+
+fn sort_entries(entries: &mut [ListEntry], sort_by: SortBy) {
+    match sort_by {
+        SortBy::Name => entries.sort_by(|a, b| a.file_name.cmp(&b.file_name)),
+        SortBy::Size => entries.sort_by(|a, b| {
+            let size = |entry: &ListEntry| entry.metadata.as_ref().map(|metadata| metadata.len());
+            size(a).cmp(&size(b)).then_with(|| a.file_name.cmp(&b.file_name))
+        }),
+        SortBy::Mtime => entries.sort_by(|a, b| {
+            let mtime = |entry: &ListEntry| entry.metadata.as_ref().and_then(|metadata| metadata.modified().ok());
+            mtime(a).cmp(&mtime(b)).then_with(|| a.file_name.cmp(&b.file_name))
+        }),
+    }
+}
+
+/// Запрашивает метаданные пакета записей; записи, удаленные после чтения каталога, отбрасываются
+fn stat_batch(entries: Vec<ListEntry>) -> std::io::Result<Vec<ListEntry>> {
+    let mut result = Vec::with_capacity(entries.len());
+    for mut entry in entries {
+        match std::fs::symlink_metadata(long_path(&entry.path)) {
+            Ok(metadata) => {
+                entry.metadata = Some(metadata);
+                result.push(entry);
+            },
+            Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => {},
+            Err(error) => return Err(error),
+        }
+    }
+    Ok(result)
+}
+
+
+// ListDir
+
+/// Future отсортированного списка содержимого каталога.
+///
+/// Каталог читается в пуле потоков, после чего метаданные записей запрашиваются
+/// пакетами, которые выполняются в пуле параллельно.
+pub struct ListDir {
+    inner: Box<dyn Future<Item = Vec<ListEntry>, Error = std::io::Error> + Send>,
+}
+
+/// Читает содержимое каталога `path` и сортирует его в порядке `sort_by`.
+///
+/// Метаданные запрашиваются, если `include_metadata` установлен
+/// или если они нужны для сортировки.
+#[inline]
+pub fn list_dir<P: AsRef<Path>>(path: P, sort_by: SortBy, include_metadata: bool) -> ListDir {
+    list_dir_with_pool(&DEFAULT_CPU_POOL, path, sort_by, include_metadata)
+}
+
+pub fn list_dir_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P, sort_by: SortBy, include_metadata: bool) -> ListDir {
+    let path: PathBuf = path.as_ref().into();
+    let stat = include_metadata || sort_by != SortBy::Name;
+    let reading = cpu_pool.spawn_fn(move || {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(long_path(&path))? {
+            let entry = entry?;
+            entries.push(ListEntry {
+                path: entry.path(),
+                file_name: entry.file_name(),
+                file_type: entry.file_type()?,
+                metadata: None,
+            });
+        }
+        Ok(entries)
+    });
+    let inner = reading
+        .and_then(move |mut entries| {
+            let mut batches = Vec::new();
+            if stat {
+                // пакеты отделяются с конца; порядок записей восстанавливает сортировка
+                while !entries.is_empty() {
+                    let batch = entries.split_off(entries.len().saturating_sub(STAT_BATCH_SIZE));
+                    batches.push(cpu_pool.spawn_fn(move || stat_batch(batch)));
+                }
+            }
+            futures::future::join_all(batches).map(move |batches| {
+                if stat {
+                    batches.into_iter().flatten().collect()
+                } else {
+                    entries
+                }
+            })
+        })
+        .and_then(move |mut entries| cpu_pool.spawn_fn(move || {
+            sort_entries(&mut entries, sort_by);
+            Ok(entries)
+        }));
+    ListDir {
+        inner: Box::new(inner),
+    }
+}
+
+impl Future for ListDir {
+    type Item = Vec<ListEntry>;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll()
+    }
+}
+impl std::fmt::Debug for ListDir {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ListDir").finish()
+    }
+}
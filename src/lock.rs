@@ -0,0 +1,529 @@
+use futures::{Poll, Future, Async};
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use executor::BlockingExecutor;
+use pool_task::PoolTask;
+use pool_registry;
+use super::{DEFAULT_CPU_POOL, AsyncFileWrite, AsyncFileSink, AsyncFileRead, AsyncFileStream, AsyncFile};
+use sys::{self, long_path};
+
+/// Начальная задержка между попытками захвата блокировки
+static LOCK_INITIAL_BACKOFF_MS: u64 = 1;
+static DEFAULT_LOCK_MAX_BACKOFF_MS: u64 = 50;
+
+/// Вид блокировки
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Разделяемая блокировка (несколько читателей)
+    Shared,
+    /// Исключительная блокировка (один писатель)
+    Exclusive,
+}
+
+/// Пытается захватить блокировку всего файла, не ожидая; `false`, если файл заблокирован другим владельцем
+#[cfg(unix)]
+pub fn try_lock_file(file: &std::fs::File, mode: LockMode) -> std::io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    let operation = match mode {
+        LockMode::Shared => libc::LOCK_SH,
+        LockMode::Exclusive => libc::LOCK_EX,
+    };
+    loop {
+        if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } == 0 {
+            return Ok(true);
+        }
+        let error = std::io::Error::last_os_error();
+        match error.kind() {
+            std::io::ErrorKind::Interrupted => {},
+            std::io::ErrorKind::WouldBlock => return Ok(false),
+            _ => return Err(error),
+        }
+    }
+}
+
+#[cfg(unix)]
+pub fn unlock_file(file: &std::fs::File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) } == 0 {
+        return Ok(());
+    }
+    Err(std::io::Error::last_os_error())
+}
+
+/// Блокировки диапазонов, принадлежащие открытому файлу, а не процессу
+#[cfg(target_os = "linux")]
+static F_OFD_SETLK: libc::c_int = 37;
+
+/// Признак отсутствия `F_OFD_SETLK` в ядре
+#[cfg(target_os = "linux")]
+static OFD_LOCKS_UNSUPPORTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(unix)]
+fn set_region_lock(file: &std::fs::File, lock_type: libc::c_int, offset: u64, len: u64) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let max = libc::off_t::MAX as u64;
+    if offset > max || len > max - offset {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "lock range is out of bounds"));
+    }
+    let mut flock: libc::flock = unsafe { std::mem::zeroed() };
+    flock.l_type = lock_type as _;
+    flock.l_whence = libc::SEEK_SET as _;
+    flock.l_start = offset as libc::off_t;
+    flock.l_len = len as libc::off_t;
+    #[cfg(target_os = "linux")]
+    {
+        use std::sync::atomic::Ordering;
+        if !OFD_LOCKS_UNSUPPORTED.load(Ordering::Relaxed) {
+            loop {
+                if unsafe { libc::fcntl(file.as_raw_fd(), F_OFD_SETLK, &flock) } == 0 {
+                    return Ok(());
+                }
+                let error = std::io::Error::last_os_error();
+                match error.raw_os_error() {
+                    Some(libc::EINTR) => {},
+                    Some(libc::EINVAL) => {
+                        OFD_LOCKS_UNSUPPORTED.store(true, Ordering::Relaxed);
+                        break;
+                    },
+                    _ => return Err(error),
+                }
+            }
+        }
+    }
+    // блокировки `F_SETLK` принадлежат процессу и снимаются при закрытии любого дескриптора файла
+    loop {
+        if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETLK, &flock) } == 0 {
+            return Ok(());
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn try_lock_region(file: &std::fs::File, mode: LockMode, offset: u64, len: u64) -> std::io::Result<bool> {
+    let lock_type = match mode {
+        LockMode::Shared => libc::F_RDLCK,
+        LockMode::Exclusive => libc::F_WRLCK,
+    };
+    match set_region_lock(file, lock_type, offset, len) {
+        Ok(()) => Ok(true),
+        Err(ref error) if error.raw_os_error() == Some(libc::EAGAIN) || error.raw_os_error() == Some(libc::EACCES) => Ok(false),
+        Err(error) => Err(error),
+    }
+}
+
+#[cfg(unix)]
+fn unlock_region(file: &std::fs::File, offset: u64, len: u64) -> std::io::Result<()> {
+    set_region_lock(file, libc::F_UNLCK, offset, len)
+}
+
+#[cfg(windows)]
+fn region_overlapped(offset: u64) -> winapi::um::minwinbase::OVERLAPPED {
+    let mut overlapped: winapi::um::minwinbase::OVERLAPPED = unsafe { std::mem::zeroed() };
+    unsafe {
+        let position = overlapped.u.s_mut();
+        position.Offset = offset as u32;
+        position.OffsetHigh = (offset >> 32) as u32;
+    }
+    overlapped
+}
+
+#[cfg(windows)]
+fn try_lock_region(file: &std::fs::File, mode: LockMode, offset: u64, len: u64) -> std::io::Result<bool> {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::shared::winerror::ERROR_LOCK_VIOLATION;
+    use winapi::um::fileapi::LockFileEx;
+    use winapi::um::minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY};
+    let flags = match mode {
+        LockMode::Shared => LOCKFILE_FAIL_IMMEDIATELY,
+        LockMode::Exclusive => LOCKFILE_FAIL_IMMEDIATELY | LOCKFILE_EXCLUSIVE_LOCK,
+    };
+    let mut overlapped = region_overlapped(offset);
+    if unsafe { LockFileEx(file.as_raw_handle() as _, flags, 0, len as u32, (len >> 32) as u32, &mut overlapped) } != 0 {
+        return Ok(true);
+    }
+    let error = std::io::Error::last_os_error();
+    if error.raw_os_error() == Some(ERROR_LOCK_VIOLATION as i32) {
+        return Ok(false);
+    }
+    Err(error)
+}
+
+#[cfg(windows)]
+fn unlock_region(file: &std::fs::File, offset: u64, len: u64) -> std::io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::fileapi::UnlockFileEx;
+    let mut overlapped = region_overlapped(offset);
+    if unsafe { UnlockFileEx(file.as_raw_handle() as _, 0, len as u32, (len >> 32) as u32, &mut overlapped) } != 0 {
+        return Ok(());
+    }
+    Err(std::io::Error::last_os_error())
+}
+
+/// На Windows блокировка всего файла - это блокировка максимального диапазона
+#[cfg(windows)]
+pub fn try_lock_file(file: &std::fs::File, mode: LockMode) -> std::io::Result<bool> {
+    try_lock_region(file, mode, 0, !0)
+}
+
+#[cfg(windows)]
+pub fn unlock_file(file: &std::fs::File) -> std::io::Result<()> {
+    unlock_region(file, 0, !0)
+}
+
+/// Захватываемая область: весь файл или диапазон `(offset, len)`
+type LockRange = Option<(u64, u64)>;
+
+fn try_lock_range(file: &std::fs::File, mode: LockMode, range: LockRange) -> std::io::Result<bool> {
+    match range {
+        Some((offset, len)) => try_lock_region(file, mode, offset, len),
+        None => try_lock_file(file, mode),
+    }
+}
+
+fn unlock_range(file: &std::fs::File, range: LockRange) -> std::io::Result<()> {
+    match range {
+        Some((offset, len)) => unlock_region(file, offset, len),
+        None => unlock_file(file),
+    }
+}
+
+
+// FileLock
+
+/// Блокировка файла между процессами с одинаковой семантикой на Unix (`flock`, `fcntl`)
+/// и Windows (`LockFileEx`): целиком или по диапазонам байт.
+///
+/// Блокировка принадлежит открытому файлу: она освобождается при освобождении
+/// `FileLockGuard` или закрытии файла. Повторный захват блокировки через тот же `FileLock`,
+/// пока она удерживается, не допускается; для диапазонов это относится к пересекающимся
+/// диапазонам.
+#[derive(Clone)]
+pub struct FileLock<E = &'static CpuPool> {
+    executor: E,
+    file: Arc<std::fs::File>,
+}
+impl FileLock {
+
+    /// Открывает (при необходимости создавая) файл блокировки `path`
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> CpuFuture<FileLock, std::io::Error> {
+        Self::open_with_pool(&DEFAULT_CPU_POOL, path)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<FileLock, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(long_path(&path))?;
+            Ok(FileLock::from_std(cpu_pool, file))
+        })
+    }
+
+    #[inline]
+    pub fn from_std(cpu_pool: &'static CpuPool, file: std::fs::File) -> FileLock {
+        Self::from_std_with_executor(cpu_pool, file)
+    }
+}
+impl<E: BlockingExecutor> FileLock<E> {
+
+    /// Блокировка, попытки захвата которой выполняются в `executor` (см. `BlockingExecutor`)
+    #[inline]
+    pub fn from_std_with_executor(executor: E, file: std::fs::File) -> FileLock<E> {
+        FileLock {
+            executor,
+            file: Arc::new(file),
+        }
+    }
+
+    fn try_acquire(&self, mode: LockMode, range: LockRange) -> std::io::Result<Option<FileLockGuard>> {
+        if try_lock_range(&self.file, mode, range)? {
+            return Ok(Some(FileLockGuard { file: self.file.clone(), mode, range, locked: true }));
+        }
+        Ok(None)
+    }
+
+    fn acquire(&self, mode: LockMode, range: LockRange) -> AcquireLock<E> {
+        let file = self.file.clone();
+        AcquireLock {
+            executor: self.executor.clone(),
+            file: self.file.clone(),
+            mode,
+            range,
+            attempt: self.executor.spawn_blocking(move || try_lock_range(&file, mode, range)),
+            backoff: Duration::from_millis(LOCK_INITIAL_BACKOFF_MS),
+            max_backoff: Duration::from_millis(DEFAULT_LOCK_MAX_BACKOFF_MS),
+            deadline: None,
+        }
+    }
+
+    /// Пытается захватить блокировку, не ожидая; `None`, если файл заблокирован
+    #[inline]
+    pub fn try_lock(&self, mode: LockMode) -> std::io::Result<Option<FileLockGuard>> {
+        self.try_acquire(mode, None)
+    }
+
+    /// Захватывает блокировку.
+    ///
+    /// Попытки выполняются в пуле потоков без ожидания внутри системного вызова,
+    /// с экспоненциально растущей задержкой между ними, поэтому ожидание блокировки
+    /// не занимает поток пула на неограниченное время.
+    #[inline]
+    pub fn lock(&self, mode: LockMode) -> AcquireLock<E> {
+        self.acquire(mode, None)
+    }
+
+    /// Пытается захватить блокировку `len` байт начиная с `offset`, не ожидая;
+    /// `None`, если диапазон (или его часть) заблокирован
+    pub fn try_lock_range(&self, offset: u64, len: u64, mode: LockMode) -> std::io::Result<Option<FileLockGuard>> {
+        assert!(len > 0, "lock range length must be greater than zero");
+        self.try_acquire(mode, Some((offset, len)))
+    }
+
+    /// Захватывает блокировку `len` байт начиная с `offset`.
+    ///
+    /// Процессы, блокирующие непересекающиеся диапазоны, не ожидают друг друга.
+    /// Диапазон может выходить за конец файла. Блокировки диапазонов независимы
+    /// от блокировки всего файла через `lock`.
+    pub fn lock_range(&self, offset: u64, len: u64, mode: LockMode) -> AcquireLock<E> {
+        assert!(len > 0, "lock range length must be greater than zero");
+        self.acquire(mode, Some((offset, len)))
+    }
+}
+impl<E> FileLock<E> {
+
+    #[inline]
+    pub fn file(&self) -> &std::fs::File {
+        &self.file
+    }
+}
+impl<E> std::fmt::Debug for FileLock<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("FileLock").finish()
+    }
+}
+
+
+// AcquireLock
+
+/// Future захвата блокировки файла
+pub struct AcquireLock<E = &'static CpuPool> {
+    executor: E,
+    file: Arc<std::fs::File>,
+    mode: LockMode,
+    range: LockRange,
+    attempt: PoolTask<bool>,
+    backoff: Duration,
+    max_backoff: Duration,
+    deadline: Option<Instant>,
+}
+impl<E> AcquireLock<E> {
+
+    /// Задает максимальную задержку между попытками
+    #[inline]
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Ограничивает время ожидания: по его истечении future завершается ошибкой `TimedOut`
+    #[inline]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(Instant::now() + timeout);
+        self
+    }
+}
+impl<E: BlockingExecutor> Future for AcquireLock<E> {
+    type Item = FileLockGuard;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            if try_ready!(self.attempt.poll()) {
+                return Ok(Async::Ready(FileLockGuard { file: self.file.clone(), mode: self.mode, range: self.range, locked: true }));
+            }
+            let now = Instant::now();
+            let mut backoff = self.backoff;
+            if let Some(deadline) = self.deadline {
+                if now >= deadline {
+                    return Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out waiting for file lock"));
+                }
+                backoff = std::cmp::min(backoff, deadline - now);
+            }
+            self.backoff = std::cmp::min(self.backoff * 2, self.max_backoff);
+            let file = self.file.clone();
+            let mode = self.mode;
+            let range = self.range;
+            self.attempt = self.executor.spawn_blocking(move || {
+                std::thread::sleep(backoff);
+                try_lock_range(&file, mode, range)
+            });
+        }
+    }
+}
+impl<E> std::fmt::Debug for AcquireLock<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AcquireLock")
+            .field("mode", &self.mode)
+            .field("range", &self.range)
+            .field("backoff", &self.backoff)
+            .finish()
+    }
+}
+
+
+// FileLockGuard
+
+/// Захваченная блокировка файла; освобождается при освобождении guard
+pub struct FileLockGuard {
+    file: Arc<std::fs::File>,
+    mode: LockMode,
+    range: LockRange,
+    locked: bool,
+}
+impl FileLockGuard {
+
+    #[inline]
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    /// Заблокированный диапазон `(offset, len)`; `None` для блокировки всего файла
+    #[inline]
+    pub fn range(&self) -> Option<(u64, u64)> {
+        self.range
+    }
+
+    /// Освобождает блокировку, сообщая об ошибке
+    pub fn unlock(mut self) -> std::io::Result<()> {
+        self.locked = false;
+        unlock_range(&self.file, self.range)
+    }
+}
+impl Drop for FileLockGuard {
+    fn drop(&mut self) {
+        if self.locked {
+            let _ = unlock_range(&self.file, self.range);
+        }
+    }
+}
+impl std::fmt::Debug for FileLockGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("FileLockGuard")
+            .field("mode", &self.mode)
+            .field("range", &self.range)
+            .finish()
+    }
+}
+
+
+// LockPath
+
+enum LockPathState {
+    Open(CpuFuture<FileLock, std::io::Error>),
+    Acquire(AcquireLock),
+}
+
+/// Открывает (при необходимости создавая) файл `path` и захватывает его блокировку, например,
+/// для согласования нескольких процессов, работающих с одним каталогом. Файл остается
+/// открытым, пока существует `FileLockGuard`; блокировка освобождается при его освобождении.
+#[inline]
+pub fn lock<P: AsRef<Path>>(path: P, mode: LockMode) -> LockPath {
+    lock_with_pool(pool_registry::pool_for(path.as_ref()), path, mode)
+}
+
+pub fn lock_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P, mode: LockMode) -> LockPath {
+    LockPath {
+        state: LockPathState::Open(FileLock::open_with_pool(cpu_pool, path)),
+        mode,
+        timeout: None,
+    }
+}
+
+/// Future открытия файла и захвата его блокировки (см. `lock`)
+pub struct LockPath {
+    state: LockPathState,
+    mode: LockMode,
+    timeout: Option<Duration>,
+}
+impl LockPath {
+
+    /// Ограничивает время ожидания блокировки (см. `AcquireLock::timeout`)
+    #[inline]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+}
+impl Future for LockPath {
+    type Item = FileLockGuard;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            self.state = match self.state {
+                LockPathState::Open(ref mut future) => {
+                    let lock = try_ready!(future.poll());
+                    let acquire = lock.lock(self.mode);
+                    LockPathState::Acquire(match self.timeout {
+                        Some(timeout) => acquire.timeout(timeout),
+                        None => acquire,
+                    })
+                },
+                LockPathState::Acquire(ref mut future) => return future.poll(),
+            };
+        }
+    }
+}
+impl std::fmt::Debug for LockPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("LockPath")
+            .field("mode", &self.mode)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+
+/// Блокировка файла обертки: `file_lock`, `lock_exclusive`, `lock_shared` и `try_lock`
+macro_rules! impl_file_lock {
+    ($wrapper:ident $(, $param:ident)*) => {
+        impl<E: BlockingExecutor $(, $param)*> $wrapper<E $(, $param)*> {
+
+            /// Блокировка файла обертки (см. `FileLock`) над копией его дескриптора
+            /// с тем же исполнителем. Блокировка принадлежит открытому файлу, поэтому
+            /// действует и для операций самой обертки.
+            pub fn file_lock(&self) -> std::io::Result<FileLock<E>> {
+                Ok(FileLock::from_std_with_executor(self.executor.clone(), sys::clone_raw_file(self.raw)?))
+            }
+
+            /// Захватывает исключительную блокировку файла (см. `FileLock::lock`)
+            #[inline]
+            pub fn lock_exclusive(&self) -> std::io::Result<AcquireLock<E>> {
+                Ok(self.file_lock()?.lock(LockMode::Exclusive))
+            }
+
+            /// Захватывает разделяемую блокировку файла (см. `FileLock::lock`)
+            #[inline]
+            pub fn lock_shared(&self) -> std::io::Result<AcquireLock<E>> {
+                Ok(self.file_lock()?.lock(LockMode::Shared))
+            }
+
+            /// Пытается захватить блокировку файла, не ожидая; `None`, если файл заблокирован
+            #[inline]
+            pub fn try_lock(&self, mode: LockMode) -> std::io::Result<Option<FileLockGuard>> {
+                self.file_lock()?.try_lock(mode)
+            }
+        }
+    };
+}
+
+impl_file_lock!(AsyncFileWrite);
+impl_file_lock!(AsyncFileSink, B);
+impl_file_lock!(AsyncFileRead);
+impl_file_lock!(AsyncFileStream);
+impl_file_lock!(AsyncFile);
@@ -0,0 +1,377 @@
+use futures_cpupool::CpuPool;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicUsize, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use executor::BlockingExecutor;
+
+/// Количество корзин гистограммы (см. `Histogram`)
+pub const HISTOGRAM_BUCKETS: usize = 32;
+
+lazy_static! {
+    static ref POOL_METRICS: RwLock<Vec<(usize, Arc<PoolMetrics>)>> = RwLock::new(Vec::new());
+}
+/// Счетчики включены хотя бы для одного пула: без них поиск по `POOL_METRICS` не выполняется
+static POOL_METRICS_ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[inline]
+fn pool_key(cpu_pool: &CpuPool) -> usize {
+    cpu_pool as *const CpuPool as usize
+}
+
+/// Счетчики операций оберток, выполняемых непосредственно в `cpu_pool`
+/// (например, `AsyncFileRead::open` или `from_std(&POOL, ...)`); включаются первым вызовом.
+///
+/// Пул определяется по адресу, поэтому счетчики предназначены для статических пулов
+/// (`DEFAULT_CPU_POOL`, пулы `PoolRegistry`). Операции `open`/`create` и другие задачи,
+/// запущенные через `CpuPool::spawn_fn`, не учитываются.
+pub fn pool_metrics(cpu_pool: &CpuPool) -> Arc<PoolMetrics> {
+    let key = pool_key(cpu_pool);
+    let mut registered = POOL_METRICS.write().unwrap();
+    if let Some((_, metrics)) = registered.iter().find(|&&(registered_key, _)| registered_key == key) {
+        return metrics.clone();
+    }
+    let metrics = Arc::new(PoolMetrics::new());
+    registered.push((key, metrics.clone()));
+    POOL_METRICS_ENABLED.store(true, Ordering::SeqCst);
+    metrics
+}
+
+/// Счетчики пула, если они включены (см. `pool_metrics`)
+pub fn registered_pool_metrics(cpu_pool: &CpuPool) -> Option<Arc<PoolMetrics>> {
+    if !POOL_METRICS_ENABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let key = pool_key(cpu_pool);
+    POOL_METRICS.read().unwrap().iter()
+        .find(|&&(registered_key, _)| registered_key == key)
+        .map(|(_, metrics)| metrics.clone())
+}
+
+/// Задача исполнителя, сообщающая `sink` о постановке в очередь, начале и завершении
+pub fn metered_job(sink: Arc<dyn MetricsSink>, job: Box<dyn FnOnce() + Send>) -> Box<dyn FnOnce() + Send> {
+    sink.queued();
+    let queued = Instant::now();
+    Box::new(move || {
+        sink.started(queued.elapsed());
+        let started = Instant::now();
+        job();
+        sink.finished(started.elapsed());
+    })
+}
+
+
+// MetricsSink
+
+/// Получатель метрик блокирующих операций (см. `Metered`, `pool_metrics`).
+///
+/// Методы вызываются из потоков пула и из задач, опрашивающих обертки, поэтому должны
+/// быть быстрыми и не блокирующими.
+pub trait MetricsSink: Send + Sync + 'static {
+    /// Операция поставлена в очередь исполнителя
+    fn queued(&self);
+
+    /// Операция начала выполняться; `wait` - время ожидания в очереди
+    fn started(&self, wait: Duration);
+
+    /// Операция завершена за `elapsed`. Операция, отмененная до начала выполнения
+    /// (см. `PoolTask`), тоже завершается - с почти нулевой длительностью.
+    fn finished(&self, elapsed: Duration);
+
+    /// Обертка прочитала `bytes` байт из файла
+    fn read(&self, bytes: u64);
+
+    /// Обертка записала `bytes` байт в файл
+    fn written(&self, bytes: u64);
+}
+
+
+// Meter
+
+/// Учет прочитанных и записанных байтов в метриках исполнителя обертки (см. `BlockingExecutor::metrics`)
+#[derive(Clone)]
+pub struct Meter(Option<Arc<dyn MetricsSink>>);
+impl Meter {
+
+    #[inline]
+    pub fn new<E: BlockingExecutor>(executor: &E) -> Meter {
+        Meter(executor.metrics())
+    }
+
+    /// Учитывает прочитанные байты и возвращает их количество
+    #[inline]
+    pub fn read(&self, size: usize) -> usize {
+        if let Some(ref sink) = self.0 {
+            sink.read(size as u64);
+        }
+        size
+    }
+
+    /// Учитывает записанные байты и возвращает их количество
+    #[inline]
+    pub fn written(&self, size: usize) -> usize {
+        if let Some(ref sink) = self.0 {
+            sink.written(size as u64);
+        }
+        size
+    }
+}
+
+
+// Histogram
+
+/// Гистограмма длительностей: корзина `i` содержит значения меньше `2^i` мкс
+/// (и не меньше границы предыдущей корзины), последняя корзина не ограничена сверху
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    total: Duration,
+}
+impl Histogram {
+
+    /// Верхняя граница (не включительно) корзины `index`; `None` для последней корзины
+    pub fn bucket_bound(index: usize) -> Option<Duration> {
+        if index + 1 < HISTOGRAM_BUCKETS {
+            Some(Duration::from_micros(1 << index))
+        } else {
+            None
+        }
+    }
+
+    fn bucket_index(value: Duration) -> usize {
+        let micros = value.as_micros();
+        let index = 128 - micros.leading_zeros() as usize;
+        std::cmp::min(index, HISTOGRAM_BUCKETS - 1)
+    }
+
+    /// Количество значений в каждой корзине
+    #[inline]
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Сумма всех значений
+    #[inline]
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(Duration::from_nanos((self.total.as_nanos() / u128::from(count)) as u64)),
+        }
+    }
+
+    /// Оценка сверху квантиля `q` (от 0 до 1): граница корзины, в которую он попадает;
+    /// `None`, если значений нет или квантиль попадает в последнюю корзину
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = std::cmp::max(1, (q.clamp(0.0, 1.0) * count as f64).ceil() as u64);
+        let mut seen = 0;
+        for (index, &bucket) in self.buckets.iter().enumerate() {
+            seen += bucket;
+            if seen >= rank {
+                return Self::bucket_bound(index);
+            }
+        }
+        None
+    }
+}
+
+/// Гистограмма, пополняемая из нескольких потоков
+struct AtomicHistogram {
+    buckets: Vec<AtomicU64>,
+    total_nanos: AtomicU64,
+}
+impl AtomicHistogram {
+    fn new() -> AtomicHistogram {
+        AtomicHistogram {
+            buckets: (0..HISTOGRAM_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            total_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, value: Duration) {
+        self.buckets[Histogram::bucket_index(value)].fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(value.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Histogram {
+        Histogram {
+            buckets: self.buckets.iter().map(|bucket| bucket.load(Ordering::Relaxed)).collect(),
+            total: Duration::from_nanos(self.total_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+
+// PoolMetrics
+
+/// Счетчики операций: глубина очереди, выполняемые операции, гистограммы ожидания в очереди
+/// и длительности выполнения, прочитанные и записанные байты.
+///
+/// Один объект можно передать нескольким `Metered` (счетчики пула или группы файлов)
+/// или одному (счетчики отдельного файла).
+pub struct PoolMetrics {
+    queued: AtomicUsize,
+    in_flight: AtomicUsize,
+    completed: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    wait: AtomicHistogram,
+    latency: AtomicHistogram,
+}
+impl PoolMetrics {
+
+    pub fn new() -> PoolMetrics {
+        PoolMetrics {
+            queued: AtomicUsize::new(0),
+            in_flight: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            wait: AtomicHistogram::new(),
+            latency: AtomicHistogram::new(),
+        }
+    }
+
+    /// Операции в очереди исполнителя, еще не начавшие выполняться
+    #[inline]
+    pub fn queue_depth(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Выполняемые сейчас операции
+    #[inline]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Завершенные операции
+    #[inline]
+    pub fn completed(&self) -> u64 {
+        self.completed.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::SeqCst)
+    }
+
+    /// Время ожидания операций в очереди
+    #[inline]
+    pub fn wait_histogram(&self) -> Histogram {
+        self.wait.snapshot()
+    }
+
+    /// Длительность выполнения операций
+    #[inline]
+    pub fn latency_histogram(&self) -> Histogram {
+        self.latency.snapshot()
+    }
+}
+impl MetricsSink for PoolMetrics {
+    fn queued(&self) {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn started(&self, wait: Duration) {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        self.wait.record(wait);
+    }
+
+    fn finished(&self, elapsed: Duration) {
+        self.latency.record(elapsed);
+        self.completed.fetch_add(1, Ordering::SeqCst);
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn read(&self, bytes: u64) {
+        self.bytes_read.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    fn written(&self, bytes: u64) {
+        self.bytes_written.fetch_add(bytes, Ordering::Relaxed);
+    }
+}
+impl Default for PoolMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl std::fmt::Debug for PoolMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PoolMetrics")
+            .field("queue_depth", &self.queue_depth())
+            .field("in_flight", &self.in_flight())
+            .field("completed", &self.completed())
+            .field("bytes_read", &self.bytes_read())
+            .field("bytes_written", &self.bytes_written())
+            .finish()
+    }
+}
+
+
+// Metered
+
+/// Исполнитель, сообщающий `sink` о каждой операции обертки (см. `MetricsSink`).
+///
+/// ```ignore
+/// let metrics = Arc::new(PoolMetrics::new());
+/// let file = AsyncFileWrite::from_std_with_executor(Metered::new(&*POOL, metrics.clone()), file, size);
+/// ```
+///
+/// Прочитанные и записанные байты учитываются только в `sink`, а очередь и длительность
+/// операций - и в счетчиках пула (`pool_metrics`), если они включены.
+#[derive(Clone)]
+pub struct Metered<E> {
+    executor: E,
+    sink: Arc<dyn MetricsSink>,
+}
+impl<E: BlockingExecutor> Metered<E> {
+    pub fn new(executor: E, sink: Arc<dyn MetricsSink>) -> Metered<E> {
+        Metered {
+            executor,
+            sink,
+        }
+    }
+
+    #[inline]
+    pub fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    #[inline]
+    pub fn sink(&self) -> &Arc<dyn MetricsSink> {
+        &self.sink
+    }
+}
+impl<E: BlockingExecutor> BlockingExecutor for Metered<E> {
+    #[inline]
+    fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+        self.executor.execute(metered_job(self.sink.clone(), job))
+    }
+
+    #[inline]
+    fn metrics(&self) -> Option<Arc<dyn MetricsSink>> {
+        Some(self.sink.clone())
+    }
+}
+impl<E> std::fmt::Debug for Metered<E> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Metered").finish()
+    }
+}
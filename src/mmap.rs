@@ -0,0 +1,287 @@
+use futures::{Poll, Future, Async, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::{Bytes, IntoBuf};
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::collections::VecDeque;
+use super::{DEFAULT_CPU_POOL, DEFAULT_BUFFER_SIZE};
+use file_kind::{unsupported_kind, FileKind, FileKindExt};
+use sys::{long_path, retry};
+
+/// Отображение файла в память
+pub struct Mapping {
+    pub ptr: *mut u8,
+    pub len: usize,
+}
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+impl Mapping {
+    fn new(file: &std::fs::File) -> std::io::Result<Option<Mapping>> {
+        Self::map(file, false)
+    }
+
+    /// Отображает весь файл; `None` для пустого файла. Для отображения на запись
+    /// файл должен быть открыт на чтение и запись.
+    pub fn map(file: &std::fs::File, writable: bool) -> std::io::Result<Option<Mapping>> {
+        let metadata = file.metadata()?;
+        // символьные устройства и каналы не отображаются (или отображаются с побочными
+        // эффектами драйвера), а размер блочного устройства в метаданных нулевой
+        match metadata.kind() {
+            kind @ FileKind::CharDevice | kind @ FileKind::BlockDevice | kind @ FileKind::Fifo | kind @ FileKind::Socket | kind @ FileKind::Dir => {
+                return Err(unsupported_kind(kind, "memory mapping"));
+            },
+            _ => {},
+        }
+        let len = metadata.len();
+        if len == 0 {
+            return Ok(None);
+        }
+        if len > usize::MAX as u64 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "file is too large to be mapped"));
+        }
+        let len = len as usize;
+        let ptr = unsafe {
+            let protection = if writable { libc::PROT_READ | libc::PROT_WRITE } else { libc::PROT_READ };
+            libc::mmap(std::ptr::null_mut(), len, protection, libc::MAP_SHARED, file.as_raw_fd(), 0)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Some(Mapping { ptr: ptr as *mut u8, len }))
+    }
+}
+impl Mapping {
+    /// Загружает страницы `[offset, offset + len)` в память: запрашивает упреждающее чтение
+    /// (`MADV_WILLNEED`) и обращается к каждой странице, блокируя поток до их загрузки
+    fn prefault(&self, offset: usize, len: usize) {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let begin = offset / page_size * page_size;
+        let end = std::cmp::min(offset + len, self.len);
+        unsafe {
+            // ошибка совета не мешает загрузке страниц обращением к ним
+            libc::madvise(self.ptr.add(begin) as *mut libc::c_void, end - begin, libc::MADV_WILLNEED);
+            let mut page = begin;
+            while page < end {
+                std::ptr::read_volatile(self.ptr.add(std::cmp::max(page, offset)));
+                page += page_size;
+            }
+        }
+    }
+}
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+
+// MmapChunk
+
+/// Фрагмент отображенного в память файла, не копирующий данные.
+///
+/// Фрагмент удерживает отображение, пока существует он сам или его копии (`clone`, `slice`).
+/// `Bytes` из `bytes` 0.4 не позволяет ссылаться на чужую память, поэтому фрагмент является
+/// отдельным типом: он реализует `IntoBuf` для векторной записи без копирования,
+/// а в `Bytes` преобразуется с копированием (`to_bytes`).
+#[derive(Clone)]
+pub struct MmapChunk {
+    mapping: Arc<Mapping>,
+    offset: usize,
+    len: usize,
+}
+impl MmapChunk {
+
+    /// Часть фрагмента `[begin, end)`, ссылающаяся на то же отображение
+    pub fn slice(&self, begin: usize, end: usize) -> MmapChunk {
+        assert!(begin <= end && end <= self.len, "slice is out of bounds");
+        MmapChunk {
+            mapping: self.mapping.clone(),
+            offset: self.offset + begin,
+            len: end - begin,
+        }
+    }
+
+    /// Копирует данные фрагмента в `Bytes`
+    pub fn to_bytes(&self) -> Bytes {
+        Bytes::from(&self[..])
+    }
+}
+impl Deref for MmapChunk {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.mapping.ptr.add(self.offset), self.len) }
+    }
+}
+impl AsRef<[u8]> for MmapChunk {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+impl IntoBuf for MmapChunk {
+    type Buf = std::io::Cursor<MmapChunk>;
+
+    fn into_buf(self) -> Self::Buf {
+        std::io::Cursor::new(self)
+    }
+}
+impl std::fmt::Debug for MmapChunk {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MmapChunk")
+            .field("offset", &self.offset)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+
+// MmapStream
+
+enum MmapStreamState {
+    Mapping(CpuFuture<Option<Mapping>, std::io::Error>),
+    Mapped(Arc<Mapping>),
+    Done,
+}
+
+/// Поток содержимого файла, отображенного в память, фрагментами без копирования данных.
+///
+/// Отображение создается в пуле потоков, а фрагменты ссылаются прямо на страницы кэша файла,
+/// поэтому для раздачи редко изменяемых файлов данные не копируются ни в пуле, ни при записи.
+/// Чтение страниц с диска происходит при первом обращении к данным фрагмента (в потоке
+/// потребителя), если не включена загрузка страниц в пуле потоков (`prefault`). Файл не должен усекаться, пока существуют фрагменты: обращение к отображению
+/// за концом файла завершает процесс сигналом `SIGBUS`.
+pub struct MmapStream {
+    cpu_pool: &'static CpuPool,
+    state: MmapStreamState,
+    chunk_size: usize,
+    position: usize,
+    prefault: usize,
+    /// Смещение, длина и загрузка страниц следующих фрагментов, в порядке смещений
+    faults: VecDeque<(usize, usize, CpuFuture<(), std::io::Error>)>,
+}
+impl MmapStream {
+
+    /// Открывает файл и отображает его в память в пуле потоков
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> MmapStream {
+        Self::open_with_pool(&DEFAULT_CPU_POOL, path)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> MmapStream {
+        let path: PathBuf = path.as_ref().into();
+        Self::with_mapping(cpu_pool, cpu_pool.spawn_fn(move || {
+            let file = retry(|| std::fs::File::open(long_path(&path)))?;
+            Mapping::new(&file)
+        }))
+    }
+
+    pub fn from_std(cpu_pool: &'static CpuPool, file: std::fs::File) -> MmapStream {
+        Self::with_mapping(cpu_pool, cpu_pool.spawn_fn(move || Mapping::new(&file)))
+    }
+
+    fn with_mapping(cpu_pool: &'static CpuPool, mapping: CpuFuture<Option<Mapping>, std::io::Error>) -> MmapStream {
+        MmapStream {
+            cpu_pool,
+            state: MmapStreamState::Mapping(mapping),
+            chunk_size: DEFAULT_BUFFER_SIZE,
+            position: 0,
+            prefault: 0,
+            faults: VecDeque::new(),
+        }
+    }
+
+    /// Задает размер фрагментов
+    #[inline]
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must be greater than zero");
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Загружать ли страницы фрагментов в пуле потоков: поток держит до `depth` загрузок
+    /// следующих фрагментов и отдает фрагмент, когда его страницы уже в памяти, поэтому
+    /// потребитель не блокируется на обращении к диску. Значение 0 отключает режим.
+    #[inline]
+    pub fn prefault(mut self, depth: usize) -> Self {
+        self.prefault = depth;
+        self
+    }
+
+    /// Запускает загрузку страниц следующих фрагментов, пока их не станет `prefault`
+    fn start_faults(&mut self, mapping: &Arc<Mapping>) {
+        let mut next = self.faults.back().map_or(self.position, |&(offset, len, _)| offset + len);
+        while self.faults.len() < self.prefault && next < mapping.len {
+            let len = std::cmp::min(self.chunk_size, mapping.len - next);
+            let pages = mapping.clone();
+            let offset = next;
+            self.faults.push_back((offset, len, self.cpu_pool.spawn_fn(move || {
+                pages.prefault(offset, len);
+                Ok(())
+            })));
+            next += len;
+        }
+    }
+}
+impl Stream for MmapStream {
+    type Item = MmapChunk;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            match self.state {
+                MmapStreamState::Mapping(ref mut future) => {
+                    self.state = match try_ready!(future.poll()) {
+                        Some(mapping) => MmapStreamState::Mapped(Arc::new(mapping)),
+                        None => MmapStreamState::Done,
+                    };
+                },
+                MmapStreamState::Mapped(ref mapping) if self.prefault > 0 => {
+                    let mapping = mapping.clone();
+                    self.start_faults(&mapping);
+                    let (offset, len) = match self.faults.front_mut() {
+                        Some(&mut (offset, len, ref mut future)) => {
+                            try_ready!(future.poll());
+                            (offset, len)
+                        },
+                        None => return Ok(Async::Ready(None)),
+                    };
+                    self.faults.pop_front();
+                    self.position = offset + len;
+                    self.start_faults(&mapping);
+                    return Ok(Async::Ready(Some(MmapChunk {
+                        mapping,
+                        offset,
+                        len,
+                    })));
+                },
+                MmapStreamState::Mapped(ref mapping) => {
+                    if self.position >= mapping.len {
+                        return Ok(Async::Ready(None));
+                    }
+                    let len = std::cmp::min(self.chunk_size, mapping.len - self.position);
+                    let chunk = MmapChunk {
+                        mapping: mapping.clone(),
+                        offset: self.position,
+                        len,
+                    };
+                    self.position += len;
+                    return Ok(Async::Ready(Some(chunk)));
+                },
+                MmapStreamState::Done => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+impl std::fmt::Debug for MmapStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("MmapStream")
+            .field("chunk_size", &self.chunk_size)
+            .field("position", &self.position)
+            .field("prefault", &self.prefault)
+            .finish()
+    }
+}
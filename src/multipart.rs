@@ -0,0 +1,464 @@
+use futures::{Poll, Future, Async, AsyncSink, Sink, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::{Bytes, BytesMut};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::{AsyncFileSink, DEFAULT_CPU_POOL};
+use sys::{long_path, retry, sync_all};
+
+static MAX_HEADERS_SIZE: usize = 16 * 1024;
+static MAX_FILE_NAME_LEN: usize = 200;
+
+static PART_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+
+/// Запись о сохраненной части `multipart/form-data`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedFile {
+    /// Имя поля формы
+    pub field_name: String,
+    /// Имя файла, переданное клиентом (без обработки)
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    /// Путь, по которому сохранена часть
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Извлекает `boundary` из значения заголовка `Content-Type`
+pub fn boundary_from_content_type(content_type: &str) -> Option<String> {
+    let mut params = content_type.split(';');
+    let mime = params.next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params
+        .filter_map(|param| {
+            let eq = param.find('=')?;
+            if param[..eq].trim().eq_ignore_ascii_case("boundary") {
+                Some(param[eq + 1..].trim().trim_matches('"').to_string())
+            } else {
+                None
+            }
+        })
+        .next()
+        .filter(|boundary| !boundary.is_empty())
+}
+
+/// Приводит имя файла, переданное клиентом, к безопасному виду:
+/// отбрасывает путь, заменяет недопустимые символы и запрещает скрытые имена.
+pub fn sanitize_file_name(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or("");
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' { c } else { '_' })
+        .skip_while(|&c| c == '.')
+        .take(MAX_FILE_NAME_LEN)
+        .collect();
+    if sanitized.is_empty() {
+        sanitized.push_str("file");
+    }
+    sanitized
+}
+
+
+// SaveMultipart
+
+enum ParseState {
+    Preamble,
+    Boundary,
+    Headers,
+    Body,
+    End,
+}
+
+struct PartInfo {
+    field_name: String,
+    file_name: Option<String>,
+    content_type: Option<String>,
+    temp_path: PathBuf,
+    size: u64,
+}
+
+enum PartState {
+    Idle,
+    Opening(CpuFuture<std::fs::File, std::io::Error>, PartInfo),
+    Writing(AsyncFileSink, PartInfo),
+    Closing(AsyncFileSink, PartInfo),
+    Finalizing(CpuFuture<SavedFile, std::io::Error>),
+    Swapping,
+}
+
+/// Поток, сохраняющий части тела `multipart/form-data` в директорию.
+///
+/// Каждая часть сначала записывается во временный файл в целевой директории,
+/// а после получения завершающей границы синхронизируется на диск и атомарно
+/// получает окончательное имя (без перезаписи существующих файлов).
+/// Элементами потока являются записи о сохраненных файлах.
+pub struct SaveMultipart<S> {
+    cpu_pool: &'static CpuPool,
+    body: S,
+    body_done: bool,
+    dir: PathBuf,
+    delimiter: Bytes,
+    buf: BytesMut,
+    parse_state: ParseState,
+    part_state: PartState,
+    max_file_size: Option<u64>,
+    max_parts: Option<usize>,
+    parts: usize,
+}
+
+/// Сохраняет части тела `multipart/form-data` в директорию `dir`
+pub fn save_multipart<S, P>(body: S, boundary: &str, dir: P) -> SaveMultipart<S>
+    where S: Stream,
+          S::Item: AsRef<[u8]>,
+          S::Error: Into<std::io::Error>,
+          P: AsRef<Path>,
+{
+    SaveMultipart {
+        cpu_pool: &DEFAULT_CPU_POOL,
+        body,
+        body_done: false,
+        dir: dir.as_ref().into(),
+        delimiter: Bytes::from(format!("\r\n--{}", boundary)),
+        // преамбула может начинаться сразу с границы, без предшествующего перевода строки
+        buf: BytesMut::from(&b"\r\n"[..]),
+        parse_state: ParseState::Preamble,
+        part_state: PartState::Idle,
+        max_file_size: None,
+        max_parts: None,
+        parts: 0,
+    }
+}
+
+impl<S> SaveMultipart<S> {
+
+    /// Задает пул потоков для файловых операций
+    #[inline]
+    pub fn cpu_pool(mut self, cpu_pool: &'static CpuPool) -> Self {
+        self.cpu_pool = cpu_pool;
+        self
+    }
+
+    /// Ограничивает размер каждой части
+    #[inline]
+    pub fn max_file_size(mut self, max_file_size: u64) -> Self {
+        self.max_file_size = Some(max_file_size);
+        self
+    }
+
+    /// Ограничивает количество частей
+    #[inline]
+    pub fn max_parts(mut self, max_parts: usize) -> Self {
+        self.max_parts = Some(max_parts);
+        self
+    }
+
+    fn start_part(&mut self, headers: &[u8]) -> std::io::Result<()> {
+        self.parts += 1;
+        if self.max_parts.is_some_and(|max_parts| self.parts > max_parts) {
+            return Err(invalid_data("too many parts in multipart body"));
+        }
+
+        let headers = std::str::from_utf8(headers)
+            .map_err(|_| invalid_data("invalid part headers encoding"))?;
+        let mut field_name = None;
+        let mut file_name = None;
+        let mut content_type = None;
+        for line in headers.split("\r\n") {
+            let colon = match line.find(':') {
+                Some(colon) => colon,
+                None => continue,
+            };
+            let (name, value) = (line[..colon].trim(), line[colon + 1..].trim());
+            if name.eq_ignore_ascii_case("content-disposition") {
+                for param in value.split(';').skip(1) {
+                    let eq = match param.find('=') {
+                        Some(eq) => eq,
+                        None => continue,
+                    };
+                    let (key, value) = (param[..eq].trim(), param[eq + 1..].trim().trim_matches('"'));
+                    if key.eq_ignore_ascii_case("name") {
+                        field_name = Some(value.to_string());
+                    } else if key.eq_ignore_ascii_case("filename") {
+                        file_name = Some(value.to_string());
+                    }
+                }
+            } else if name.eq_ignore_ascii_case("content-type") {
+                content_type = Some(value.to_string());
+            }
+        }
+        let field_name = field_name.ok_or_else(|| invalid_data("part without field name"))?;
+
+        let temp_path = self.dir.join(format!(
+            ".multipart-{}-{}.part",
+            std::process::id(),
+            PART_COUNTER.fetch_add(1, Ordering::SeqCst),
+        ));
+        let path = temp_path.clone();
+        self.part_state = PartState::Opening(
+            self.cpu_pool.spawn_fn(move || {
+                std::fs::OpenOptions::new().write(true).create_new(true).open(long_path(&path))
+            }),
+            PartInfo {
+                field_name,
+                file_name,
+                content_type,
+                temp_path,
+                size: 0,
+            },
+        );
+        Ok(())
+    }
+
+    fn write_chunk(&mut self, chunk: Bytes) -> std::io::Result<()> {
+        if let PartState::Writing(ref mut sink, ref mut info) = self.part_state {
+            info.size += chunk.len() as u64;
+            if self.max_file_size.is_some_and(|max_file_size| info.size > max_file_size) {
+                return Err(invalid_data("part exceeds size limit"));
+            }
+            if let AsyncSink::NotReady(_) = sink.start_send(chunk)? {
+                return Err(std::io::Error::other("`File` instance is blocked"));
+            }
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self, sink: AsyncFileSink, info: PartInfo) -> std::io::Result<()> {
+        let file = std::fs::File::try_from(sink)?;
+        let dir = self.dir.clone();
+        self.part_state = PartState::Finalizing(self.cpu_pool.spawn_fn(move || {
+            let name = sanitize_file_name(info.file_name.as_ref().unwrap_or(&info.field_name));
+            let path = retry(|| sync_all(&file))
+                .and_then(|_| {
+                    drop(file);
+                    persist_unique(&info.temp_path, &dir, &name)
+                })
+                .inspect_err(|_| {
+                    let _ = std::fs::remove_file(long_path(&info.temp_path));
+                })?;
+            Ok(SavedFile {
+                field_name: info.field_name,
+                file_name: info.file_name,
+                content_type: info.content_type,
+                path,
+                size: info.size,
+            })
+        }));
+        Ok(())
+    }
+
+    fn abort(&mut self) {
+        let temp_path = match std::mem::replace(&mut self.part_state, PartState::Idle) {
+            PartState::Opening(_, info) | PartState::Writing(_, info) | PartState::Closing(_, info) => info.temp_path,
+            _ => return,
+        };
+        self.cpu_pool.spawn_fn(move || std::fs::remove_file(long_path(&temp_path))).forget();
+    }
+}
+
+impl<S> Stream for SaveMultipart<S>
+    where S: Stream,
+          S::Item: AsRef<[u8]>,
+          S::Error: Into<std::io::Error>,
+{
+    type Item = SavedFile;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let result = self.poll_parts();
+        if result.is_err() {
+            self.abort();
+        }
+        result
+    }
+}
+
+impl<S> SaveMultipart<S>
+    where S: Stream,
+          S::Item: AsRef<[u8]>,
+          S::Error: Into<std::io::Error>,
+{
+    fn poll_parts(&mut self) -> Poll<Option<SavedFile>, std::io::Error> {
+        loop {
+            match self.part_state {
+                PartState::Opening(ref mut future, _) => {
+                    let file = match future.poll() {
+                        Ok(Async::Ready(file)) => file,
+                        Ok(Async::NotReady) => return Ok(Async::NotReady),
+                        Err(err) => {
+                            self.part_state = PartState::Idle;
+                            return Err(err);
+                        },
+                    };
+                    if let PartState::Opening(_, info) = std::mem::replace(&mut self.part_state, PartState::Swapping) {
+                        self.part_state = PartState::Writing(AsyncFileSink::from_std(self.cpu_pool, file), info);
+                    }
+                    continue;
+                },
+                PartState::Writing(ref mut sink, _) => {
+                    try_ready!(sink.poll_complete());
+                },
+                PartState::Closing(ref mut sink, _) => {
+                    try_ready!(sink.poll_complete());
+                    if let PartState::Closing(sink, info) = std::mem::replace(&mut self.part_state, PartState::Swapping) {
+                        self.finalize(sink, info)?;
+                    }
+                    continue;
+                },
+                PartState::Finalizing(ref mut future) => {
+                    let result = future.poll();
+                    if let Ok(Async::NotReady) = result {
+                        return Ok(Async::NotReady);
+                    }
+                    self.part_state = PartState::Idle;
+                    if let Async::Ready(saved) = result? {
+                        return Ok(Async::Ready(Some(saved)));
+                    }
+                },
+                PartState::Idle => {},
+                PartState::Swapping => {
+                    return Err(std::io::Error::other("multipart stream already failed"));
+                },
+            }
+
+            let delimiter = self.delimiter.clone();
+            match self.parse_state {
+                ParseState::Preamble => {
+                    if let Some(index) = find(&self.buf, &delimiter) {
+                        self.buf.advance(index + delimiter.len());
+                        self.parse_state = ParseState::Boundary;
+                        continue;
+                    }
+                    let keep = std::cmp::min(self.buf.len(), delimiter.len() - 1);
+                    let len = self.buf.len();
+                    self.buf.advance(len - keep);
+                },
+                ParseState::Boundary => {
+                    if self.buf.len() >= 2 {
+                        if &self.buf[..2] == b"--" {
+                            self.parse_state = ParseState::End;
+                            continue;
+                        }
+                        // после границы допускаются пробельные символы до перевода строки
+                        if let Some(index) = find(&self.buf, b"\r\n") {
+                            if self.buf[..index].iter().any(|&c| c != b' ' && c != b'\t') {
+                                return Err(invalid_data("malformed multipart boundary"));
+                            }
+                            self.buf.advance(index + 2);
+                            self.parse_state = ParseState::Headers;
+                            continue;
+                        }
+                    }
+                },
+                ParseState::Headers => {
+                    if self.buf.len() >= 2 && &self.buf[..2] == b"\r\n" {
+                        self.buf.advance(2);
+                        self.start_part(b"")?;
+                        self.parse_state = ParseState::Body;
+                        continue;
+                    }
+                    if let Some(index) = find(&self.buf, b"\r\n\r\n") {
+                        let headers = self.buf.split_to(index + 4);
+                        self.start_part(&headers[..index])?;
+                        self.parse_state = ParseState::Body;
+                        continue;
+                    }
+                    if self.buf.len() > MAX_HEADERS_SIZE {
+                        return Err(invalid_data("multipart part headers are too large"));
+                    }
+                },
+                ParseState::Body => {
+                    if let Some(index) = find(&self.buf, &delimiter) {
+                        let chunk = self.buf.split_to(index).freeze();
+                        self.buf.advance(delimiter.len());
+                        if !chunk.is_empty() {
+                            self.write_chunk(chunk)?;
+                        }
+                        self.parse_state = ParseState::Boundary;
+                        if let PartState::Writing(sink, info) = std::mem::replace(&mut self.part_state, PartState::Idle) {
+                            self.part_state = PartState::Closing(sink, info);
+                        }
+                        continue;
+                    }
+                    let safe = self.buf.len().saturating_sub(delimiter.len() - 1);
+                    if safe > 0 {
+                        let chunk = self.buf.split_to(safe).freeze();
+                        self.write_chunk(chunk)?;
+                        continue;
+                    }
+                },
+                ParseState::End => {
+                    return Ok(Async::Ready(None));
+                },
+            }
+
+            if self.body_done {
+                return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "multipart body ended unexpectedly"));
+            }
+            match self.body.poll().map_err(Into::into)? {
+                Async::Ready(Some(chunk)) => self.buf.extend_from_slice(chunk.as_ref()),
+                Async::Ready(None) => self.body_done = true,
+                Async::NotReady => return Ok(Async::NotReady),
+            }
+        }
+    }
+}
+impl<S> Drop for SaveMultipart<S> {
+    fn drop(&mut self) {
+        self.abort();
+    }
+}
+impl<S> std::fmt::Debug for SaveMultipart<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SaveMultipart")
+            .field("dir", &self.dir)
+            .field("parts", &self.parts)
+            .finish()
+    }
+}
+
+fn persist_unique(temp_path: &Path, dir: &Path, name: &str) -> std::io::Result<PathBuf> {
+    let (stem, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 => (&name[..dot], &name[dot..]),
+        _ => (name, ""),
+    };
+    for i in 0..1000 {
+        let path = if i == 0 {
+            dir.join(name)
+        } else {
+            dir.join(format!("{}-{}{}", stem, i, ext))
+        };
+        // жесткая ссылка не перезаписывает существующий файл, в отличие от `rename`
+        match std::fs::hard_link(long_path(temp_path), long_path(&path)) {
+            Ok(()) => {
+                std::fs::remove_file(long_path(temp_path))?;
+                return Ok(path);
+            },
+            Err(ref err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                if long_path(&path).exists() {
+                    continue;
+                }
+                if std::fs::rename(long_path(temp_path), long_path(&path)).is_ok() {
+                    return Ok(path);
+                }
+                return Err(err);
+            },
+        }
+    }
+    Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, "unable to choose a unique file name"))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.len() > haystack.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,160 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::path::{Path, PathBuf};
+use pool_registry;
+use sys::{long_path, retry};
+
+/// Метаданные файла или директории `path` (символические ссылки раскрываются).
+/// Операция выполняется в пуле потоков, выбранном по пути (см. `PoolRegistry`).
+#[inline]
+pub fn metadata<P: AsRef<Path>>(path: P) -> CpuFuture<std::fs::Metadata, std::io::Error> {
+    metadata_with_pool(pool_registry::pool_for(path.as_ref()), path)
+}
+
+pub fn metadata_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<std::fs::Metadata, std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || retry(|| std::fs::metadata(long_path(&path))))
+}
+
+/// Метаданные `path` без раскрытия символической ссылки
+#[inline]
+pub fn symlink_metadata<P: AsRef<Path>>(path: P) -> CpuFuture<std::fs::Metadata, std::io::Error> {
+    symlink_metadata_with_pool(pool_registry::pool_for(path.as_ref()), path)
+}
+
+pub fn symlink_metadata_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<std::fs::Metadata, std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || retry(|| std::fs::symlink_metadata(long_path(&path))))
+}
+
+/// Переименовывает `from` в `to`, заменяя существующий файл `to`
+#[inline]
+pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> CpuFuture<(), std::io::Error> {
+    rename_with_pool(pool_registry::pool_for(from.as_ref()), from, to)
+}
+
+pub fn rename_with_pool<P: AsRef<Path>, Q: AsRef<Path>>(cpu_pool: &'static CpuPool, from: P, to: Q) -> CpuFuture<(), std::io::Error> {
+    let from: PathBuf = from.as_ref().into();
+    let to: PathBuf = to.as_ref().into();
+    cpu_pool.spawn_fn(move || retry(|| std::fs::rename(long_path(&from), long_path(&to))))
+}
+
+#[inline]
+pub fn remove_file<P: AsRef<Path>>(path: P) -> CpuFuture<(), std::io::Error> {
+    remove_file_with_pool(pool_registry::pool_for(path.as_ref()), path)
+}
+
+pub fn remove_file_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<(), std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || retry(|| std::fs::remove_file(long_path(&path))))
+}
+
+/// Удаляет пустую директорию
+#[inline]
+pub fn remove_dir<P: AsRef<Path>>(path: P) -> CpuFuture<(), std::io::Error> {
+    remove_dir_with_pool(pool_registry::pool_for(path.as_ref()), path)
+}
+
+pub fn remove_dir_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<(), std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || retry(|| std::fs::remove_dir(long_path(&path))))
+}
+
+/// Удаляет директорию со всем содержимым одной операцией в пуле потоков
+#[inline]
+pub fn remove_dir_all<P: AsRef<Path>>(path: P) -> CpuFuture<(), std::io::Error> {
+    remove_dir_all_with_pool(pool_registry::pool_for(path.as_ref()), path)
+}
+
+pub fn remove_dir_all_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<(), std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || retry(|| std::fs::remove_dir_all(long_path(&path))))
+}
+
+/// Создает директорию; родительская директория должна существовать
+#[inline]
+pub fn create_dir<P: AsRef<Path>>(path: P) -> CpuFuture<(), std::io::Error> {
+    create_dir_with_pool(pool_registry::pool_for(path.as_ref()), path)
+}
+
+pub fn create_dir_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<(), std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || retry(|| std::fs::create_dir(long_path(&path))))
+}
+
+/// Создает директорию вместе с недостающими родительскими
+#[inline]
+pub fn create_dir_all<P: AsRef<Path>>(path: P) -> CpuFuture<(), std::io::Error> {
+    create_dir_all_with_pool(pool_registry::pool_for(path.as_ref()), path)
+}
+
+pub fn create_dir_all_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<(), std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || retry(|| std::fs::create_dir_all(long_path(&path))))
+}
+
+/// Копирует содержимое и права доступа `from` в `to`; результат - количество скопированных байт.
+/// Пул потоков выбирается по пути `to`.
+#[inline]
+pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> CpuFuture<u64, std::io::Error> {
+    copy_with_pool(pool_registry::pool_for(to.as_ref()), from, to)
+}
+
+pub fn copy_with_pool<P: AsRef<Path>, Q: AsRef<Path>>(cpu_pool: &'static CpuPool, from: P, to: Q) -> CpuFuture<u64, std::io::Error> {
+    let from: PathBuf = from.as_ref().into();
+    let to: PathBuf = to.as_ref().into();
+    cpu_pool.spawn_fn(move || retry(|| std::fs::copy(long_path(&from), long_path(&to))))
+}
+
+/// Создает жесткую ссылку `dst` на файл `src`
+#[inline]
+pub fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> CpuFuture<(), std::io::Error> {
+    hard_link_with_pool(pool_registry::pool_for(dst.as_ref()), src, dst)
+}
+
+pub fn hard_link_with_pool<P: AsRef<Path>, Q: AsRef<Path>>(cpu_pool: &'static CpuPool, src: P, dst: Q) -> CpuFuture<(), std::io::Error> {
+    let src: PathBuf = src.as_ref().into();
+    let dst: PathBuf = dst.as_ref().into();
+    cpu_pool.spawn_fn(move || retry(|| std::fs::hard_link(long_path(&src), long_path(&dst))))
+}
+
+/// Создает символическую ссылку `dst`, указывающую на `src`
+#[cfg(unix)]
+#[inline]
+pub fn symlink<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> CpuFuture<(), std::io::Error> {
+    symlink_with_pool(pool_registry::pool_for(dst.as_ref()), src, dst)
+}
+
+#[cfg(unix)]
+pub fn symlink_with_pool<P: AsRef<Path>, Q: AsRef<Path>>(cpu_pool: &'static CpuPool, src: P, dst: Q) -> CpuFuture<(), std::io::Error> {
+    let src: PathBuf = src.as_ref().into();
+    let dst: PathBuf = dst.as_ref().into();
+    cpu_pool.spawn_fn(move || retry(|| std::os::unix::fs::symlink(&src, long_path(&dst))))
+}
+
+/// Создает символическую ссылку `dst` на файл `src`
+#[cfg(windows)]
+#[inline]
+pub fn symlink_file<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> CpuFuture<(), std::io::Error> {
+    symlink_file_with_pool(pool_registry::pool_for(dst.as_ref()), src, dst)
+}
+
+#[cfg(windows)]
+pub fn symlink_file_with_pool<P: AsRef<Path>, Q: AsRef<Path>>(cpu_pool: &'static CpuPool, src: P, dst: Q) -> CpuFuture<(), std::io::Error> {
+    let src: PathBuf = src.as_ref().into();
+    let dst: PathBuf = dst.as_ref().into();
+    cpu_pool.spawn_fn(move || retry(|| std::os::windows::fs::symlink_file(&src, long_path(&dst))))
+}
+
+/// Создает символическую ссылку `dst` на директорию `src`
+#[cfg(windows)]
+#[inline]
+pub fn symlink_dir<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> CpuFuture<(), std::io::Error> {
+    symlink_dir_with_pool(pool_registry::pool_for(dst.as_ref()), src, dst)
+}
+
+#[cfg(windows)]
+pub fn symlink_dir_with_pool<P: AsRef<Path>, Q: AsRef<Path>>(cpu_pool: &'static CpuPool, src: P, dst: Q) -> CpuFuture<(), std::io::Error> {
+    let src: PathBuf = src.as_ref().into();
+    let dst: PathBuf = dst.as_ref().into();
+    cpu_pool.spawn_fn(move || retry(|| std::os::windows::fs::symlink_dir(&src, long_path(&dst))))
+}
@@ -0,0 +1,315 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::path::{Path, PathBuf};
+use super::DEFAULT_BUFFER_SIZE;
+#[cfg(target_os = "linux")] use direct::DIRECT_IO_ALIGNMENT;
+use super::{AsyncFileRead, AsyncFileWrite, AsyncFileStream, AsyncFileSink};
+use serialized::SerializedFile;
+use pool_registry;
+use sys::{long_path, retry};
+
+#[cfg(windows)]
+pub static FILE_FLAG_DELETE_ON_CLOSE: u32 = 0x0400_0000;
+
+
+// AsyncOpenOptions
+
+/// Аналог `std::fs::OpenOptions`, открывающий файл в пуле потоков
+#[derive(Clone)]
+pub struct AsyncOpenOptions {
+    cpu_pool: Option<&'static CpuPool>,
+    options: std::fs::OpenOptions,
+    buffer_size: usize,
+    #[cfg(unix)]
+    custom_flags: i32,
+    #[cfg(windows)]
+    custom_flags: u32,
+    #[cfg(target_os = "macos")]
+    no_cache: bool,
+    #[cfg(target_os = "linux")]
+    direct: bool,
+}
+impl AsyncOpenOptions {
+
+    pub fn new() -> AsyncOpenOptions {
+        AsyncOpenOptions {
+            cpu_pool: None,
+            options: std::fs::OpenOptions::new(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            #[cfg(any(unix, windows))]
+            custom_flags: 0,
+            #[cfg(target_os = "macos")]
+            no_cache: false,
+            #[cfg(target_os = "linux")]
+            direct: false,
+        }
+    }
+
+    /// Задает пул потоков, в котором открывается файл; по умолчанию пул выбирается
+    /// по пути файла (см. `PoolRegistry`)
+    pub fn cpu_pool(&mut self, cpu_pool: &'static CpuPool) -> &mut Self {
+        self.cpu_pool = Some(cpu_pool);
+        self
+    }
+
+    /// Размер буфера (блока) для оберток, возвращаемых `open_read`, `open_write` и `open_stream`
+    pub fn buffer_size(&mut self, buffer_size: usize) -> &mut Self {
+        assert!(buffer_size > 0, "buffer size must be greater than zero");
+        self.buffer_size = buffer_size;
+        self
+    }
+
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.options.read(read);
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.options.write(write);
+        self
+    }
+
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.options.append(append);
+        self
+    }
+
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.options.truncate(truncate);
+        self
+    }
+
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.options.create(create);
+        self
+    }
+
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.options.create_new(create_new);
+        self
+    }
+
+    /// Открывает файл `path` в пуле потоков
+    #[inline]
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> CpuFuture<std::fs::File, std::io::Error> {
+        self.open_as(path, |_, file, _| file)
+    }
+
+    /// Открывает файл и оборачивает его в `AsyncFileRead` с заданными пулом и размером буфера
+    #[inline]
+    pub fn open_read<P: AsRef<Path>>(&self, path: P) -> CpuFuture<AsyncFileRead, std::io::Error> {
+        self.open_as(path, AsyncFileRead::from_std)
+    }
+
+    /// Открывает файл и оборачивает его в `AsyncFileWrite` с заданными пулом и размером буфера
+    /// (при `direct` - в режиме `AsyncFileWrite::direct_io`)
+    pub fn open_write<P: AsRef<Path>>(&self, path: P) -> CpuFuture<AsyncFileWrite, std::io::Error> {
+        let direct = self.direct_alignment();
+        self.open_as(path, move |cpu_pool, file, buffer_size| {
+            let file = AsyncFileWrite::from_std(cpu_pool, file, buffer_size);
+            match direct {
+                Some(alignment) => file.direct_io(alignment),
+                None => file,
+            }
+        })
+    }
+
+    /// Открывает файл и оборачивает его в `AsyncFileStream` с заданными пулом и размером блока
+    /// (при `direct` - в режиме `AsyncFileStream::direct_io`)
+    pub fn open_stream<P: AsRef<Path>>(&self, path: P) -> CpuFuture<AsyncFileStream, std::io::Error> {
+        let direct = self.direct_alignment();
+        self.open_as(path, move |cpu_pool, file, buffer_size| {
+            let file = AsyncFileStream::from_std(cpu_pool, file, buffer_size);
+            match direct {
+                Some(alignment) => file.direct_io(alignment),
+                None => file,
+            }
+        })
+    }
+
+    /// Выравнивание буферов оберток, если файл открывается с `O_DIRECT`
+    #[cfg(target_os = "linux")]
+    fn direct_alignment(&self) -> Option<usize> {
+        if self.direct {
+            Some(DIRECT_IO_ALIGNMENT)
+        } else {
+            None
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn direct_alignment(&self) -> Option<usize> {
+        None
+    }
+
+    /// Открывает файл и оборачивает его в `AsyncFileSink`
+    #[inline]
+    pub fn open_sink<P: AsRef<Path>>(&self, path: P) -> CpuFuture<AsyncFileSink, std::io::Error> {
+        self.open_as(path, |cpu_pool, file, _| AsyncFileSink::from_std(cpu_pool, file))
+    }
+
+    /// Открывает файл для чтения и записи одним дескриптором (см. `SerializedFile`)
+    #[inline]
+    pub fn open_serialized<P: AsRef<Path>>(&self, path: P) -> CpuFuture<SerializedFile, std::io::Error> {
+        self.open_as(path, |cpu_pool, file, _| SerializedFile::from_std(cpu_pool, file))
+    }
+
+    fn open_as<P, T, F>(&self, path: P, wrap: F) -> CpuFuture<T, std::io::Error>
+        where P: AsRef<Path>,
+              T: Send + 'static,
+              F: FnOnce(&'static CpuPool, std::fs::File, usize) -> T + Send + 'static
+    {
+        let path: PathBuf = path.as_ref().into();
+        let options = self.options.clone();
+        let cpu_pool = self.cpu_pool.unwrap_or_else(|| pool_registry::pool_for(&path));
+        let buffer_size = self.buffer_size;
+        #[cfg(target_os = "macos")]
+        let no_cache = self.no_cache;
+        cpu_pool.spawn_fn(move || {
+            let file = retry(|| options.open(long_path(&path)))?;
+            #[cfg(target_os = "macos")]
+            {
+                if no_cache {
+                    use macos::MacosFileExt;
+                    file.set_no_cache(true)?;
+                }
+            }
+            Ok(wrap(cpu_pool, file, buffer_size))
+        })
+    }
+}
+impl Default for AsyncOpenOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl std::fmt::Debug for AsyncOpenOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AsyncOpenOptions")
+            .field("options", &self.options)
+            .field("buffer_size", &self.buffer_size)
+            .finish()
+    }
+}
+
+
+// AsyncOpenOptionsExt
+
+/// Параметры открытия файла, специфичные для Unix (аналог `std::os::unix::fs::OpenOptionsExt`)
+#[cfg(unix)]
+pub trait AsyncOpenOptionsExt {
+    /// Права доступа создаваемого файла (с учетом umask)
+    fn mode(&mut self, mode: u32) -> &mut Self;
+    /// Дополнительные флаги `open` (например, `O_NOATIME`); добавляются к уже заданным
+    fn custom_flags(&mut self, flags: i32) -> &mut Self;
+    /// Открытие в обход страничного кэша (`F_NOCACHE` после открытия): на macOS нет `O_DIRECT`
+    #[cfg(target_os = "macos")]
+    fn no_cache(&mut self, no_cache: bool) -> &mut Self;
+    /// Открытие в обход страничного кэша (`O_DIRECT`). Обертки `open_write` и `open_stream`
+    /// работают с буферами, выровненными по `DIRECT_IO_ALIGNMENT` (см. `AsyncFileWrite::direct_io`);
+    /// остальные обертки и `open` требуют выровненных буферов, смещений и размеров от вызывающего.
+    #[cfg(target_os = "linux")]
+    fn direct(&mut self, direct: bool) -> &mut Self;
+}
+
+#[cfg(unix)]
+impl AsyncOpenOptionsExt for AsyncOpenOptions {
+    fn mode(&mut self, mode: u32) -> &mut Self {
+        use std::os::unix::fs::OpenOptionsExt;
+        self.options.mode(mode);
+        self
+    }
+
+    fn custom_flags(&mut self, flags: i32) -> &mut Self {
+        use std::os::unix::fs::OpenOptionsExt;
+        self.custom_flags |= flags;
+        self.options.custom_flags(self.custom_flags);
+        self
+    }
+
+    #[cfg(target_os = "macos")]
+    fn no_cache(&mut self, no_cache: bool) -> &mut Self {
+        self.no_cache = no_cache;
+        self
+    }
+
+    #[cfg(target_os = "linux")]
+    fn direct(&mut self, direct: bool) -> &mut Self {
+        use std::os::unix::fs::OpenOptionsExt;
+        self.direct = direct;
+        if direct {
+            self.custom_flags |= libc::O_DIRECT;
+        } else {
+            self.custom_flags &= !libc::O_DIRECT;
+        }
+        self.options.custom_flags(self.custom_flags);
+        self
+    }
+}
+
+/// Параметры открытия файла, специфичные для Windows (аналог `std::os::windows::fs::OpenOptionsExt`).
+///
+/// Позволяют согласовать совместный доступ к файлу с другими процессами:
+/// например, по умолчанию файл открывается с `FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE`,
+/// а `share_mode(0)` запрещает другим процессам открывать его до закрытия.
+#[cfg(windows)]
+pub trait AsyncOpenOptionsExt {
+    /// Запрашиваемые права доступа (`dwDesiredAccess`) вместо задаваемых `read`/`write`
+    fn access_mode(&mut self, access: u32) -> &mut Self;
+    /// Режим совместного доступа (`dwShareMode`)
+    fn share_mode(&mut self, share: u32) -> &mut Self;
+    /// Атрибуты создаваемого файла (`FILE_ATTRIBUTE_*`)
+    fn attributes(&mut self, attributes: u32) -> &mut Self;
+    /// Флаги качества обслуживания безопасности (`SECURITY_*`) для именованных каналов
+    fn security_qos_flags(&mut self, flags: u32) -> &mut Self;
+    /// Дополнительные флаги `CreateFile` (`FILE_FLAG_*`); добавляются к уже заданным
+    fn custom_flags(&mut self, flags: u32) -> &mut Self;
+    /// Удаление файла после закрытия всех его дескрипторов (`FILE_FLAG_DELETE_ON_CLOSE`).
+    /// Другие процессы смогут открыть такой файл, только если режим совместного доступа
+    /// включает `FILE_SHARE_DELETE`.
+    fn delete_on_close(&mut self, delete_on_close: bool) -> &mut Self;
+}
+
+#[cfg(windows)]
+impl AsyncOpenOptionsExt for AsyncOpenOptions {
+    fn access_mode(&mut self, access: u32) -> &mut Self {
+        use std::os::windows::fs::OpenOptionsExt;
+        self.options.access_mode(access);
+        self
+    }
+
+    fn share_mode(&mut self, share: u32) -> &mut Self {
+        use std::os::windows::fs::OpenOptionsExt;
+        self.options.share_mode(share);
+        self
+    }
+
+    fn attributes(&mut self, attributes: u32) -> &mut Self {
+        use std::os::windows::fs::OpenOptionsExt;
+        self.options.attributes(attributes);
+        self
+    }
+
+    fn security_qos_flags(&mut self, flags: u32) -> &mut Self {
+        use std::os::windows::fs::OpenOptionsExt;
+        self.options.security_qos_flags(flags);
+        self
+    }
+
+    fn custom_flags(&mut self, flags: u32) -> &mut Self {
+        use std::os::windows::fs::OpenOptionsExt;
+        self.custom_flags |= flags;
+        self.options.custom_flags(self.custom_flags);
+        self
+    }
+
+    fn delete_on_close(&mut self, delete_on_close: bool) -> &mut Self {
+        use std::os::windows::fs::OpenOptionsExt;
+        if delete_on_close {
+            self.custom_flags |= FILE_FLAG_DELETE_ON_CLOSE;
+        } else {
+            self.custom_flags &= !FILE_FLAG_DELETE_ON_CLOSE;
+        }
+        self.options.custom_flags(self.custom_flags);
+        self
+    }
+}
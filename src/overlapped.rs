@@ -0,0 +1,244 @@
+use std::os::windows::io::AsRawHandle;
+use winapi::shared::minwindef::{DWORD, FALSE};
+use winapi::shared::basetsd::ULONG_PTR;
+use winapi::shared::winerror::{ERROR_IO_PENDING, ERROR_HANDLE_EOF};
+use winapi::um::fileapi::{ReadFile, WriteFile, SetFilePointerEx};
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::ioapiset::{CreateIoCompletionPort, GetQueuedCompletionStatus};
+use winapi::um::minwinbase::{OVERLAPPED, LPOVERLAPPED};
+use winapi::um::winbase::{ReOpenFile, FILE_FLAG_OVERLAPPED, FILE_BEGIN, FILE_CURRENT, INFINITE};
+use winapi::um::winnt::{HANDLE, GENERIC_READ, GENERIC_WRITE, FILE_SHARE_READ, FILE_SHARE_WRITE, FILE_SHARE_DELETE, LARGE_INTEGER};
+use pool_task::{PoolTask, Completion};
+
+lazy_static! {
+    static ref PORT: Option<Port> = Port::new().ok();
+}
+
+/// Доступен ли порт завершения ввода-вывода (IOCP) для перекрывающихся операций
+pub fn overlapped_available() -> bool {
+    PORT.is_some()
+}
+
+/// Результат операции; чтение за концом файла завершается `ERROR_HANDLE_EOF`
+fn io_result(size: DWORD, error: Option<std::io::Error>) -> std::io::Result<usize> {
+    match error {
+        Some(ref error) if error.raw_os_error() == Some(ERROR_HANDLE_EOF as i32) => Ok(0),
+        Some(error) => Err(error),
+        None => Ok(size as usize),
+    }
+}
+
+fn seek(file: &std::fs::File, position: i64, method: DWORD) -> std::io::Result<u64> {
+    let mut distance: LARGE_INTEGER = unsafe { std::mem::zeroed() };
+    let mut new_position: LARGE_INTEGER = unsafe { std::mem::zeroed() };
+    unsafe {
+        *distance.QuadPart_mut() = position;
+        if SetFilePointerEx(file.as_raw_handle() as HANDLE, distance, &mut new_position, method) == FALSE {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(*new_position.QuadPart() as u64)
+    }
+}
+
+
+// OverlappedHandle
+
+/// Дескриптор файла, открытый повторно с `FILE_FLAG_OVERLAPPED` и привязанный к порту
+struct OverlappedHandle(HANDLE);
+unsafe impl Send for OverlappedHandle {}
+impl OverlappedHandle {
+    fn new(file: &std::fs::File, write: bool, port: &Port) -> std::io::Result<OverlappedHandle> {
+        let access = if write { GENERIC_WRITE } else { GENERIC_READ };
+        let handle = unsafe {
+            ReOpenFile(file.as_raw_handle() as HANDLE, access, FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE, FILE_FLAG_OVERLAPPED)
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(std::io::Error::last_os_error());
+        }
+        let handle = OverlappedHandle(handle);
+        if unsafe { CreateIoCompletionPort(handle.0, port.0, 0, 0) }.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(handle)
+    }
+}
+impl Drop for OverlappedHandle {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0);
+        }
+    }
+}
+
+
+// Operation
+
+/// Операция, ожидающая пакета завершения: владеет файлом и буфером до завершения
+trait Operation: Send {
+    fn complete(self: Box<Self>, result: std::io::Result<usize>);
+}
+
+struct ReadWrite<B, F, T> {
+    file: std::fs::File,
+    handle: OverlappedHandle,
+    buf: B,
+    position: u64,
+    finish: F,
+    completion: Completion<T>,
+}
+impl<B, F, T> Operation for ReadWrite<B, F, T>
+    where B: Send,
+          F: FnOnce(std::fs::File, B, std::io::Result<usize>) -> std::io::Result<T> + Send,
+          T: Send
+{
+    fn complete(self: Box<Self>, result: std::io::Result<usize>) {
+        let operation = *self;
+        drop(operation.handle);
+        // перекрывающаяся операция не перемещает позицию файла
+        let result = result.and_then(|size| {
+            seek(&operation.file, (operation.position + size as u64) as i64, FILE_BEGIN)?;
+            Ok(size)
+        });
+        let result = (operation.finish)(operation.file, operation.buf, result);
+        operation.completion.complete(result);
+    }
+}
+
+/// Запрос в ядре: `OVERLAPPED` должна оставаться на месте до пакета завершения,
+/// по ее адресу поток порта находит операцию
+#[repr(C)]
+struct Request {
+    overlapped: OVERLAPPED,
+    operation: Option<Box<dyn Operation>>,
+}
+
+
+// Port
+
+/// Порт завершения с потоком, ожидающим пакетов и пробуждающим задачи
+struct Port(HANDLE);
+unsafe impl Send for Port {}
+unsafe impl Sync for Port {}
+impl Port {
+    fn new() -> std::io::Result<Port> {
+        let handle = unsafe { CreateIoCompletionPort(INVALID_HANDLE_VALUE, std::ptr::null_mut(), 0, 0) };
+        if handle.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+        let port = handle as usize;
+        std::thread::Builder::new()
+            .name("async_fs-overlapped".into())
+            .spawn(move || Port::reap(port as HANDLE))?;
+        Ok(Port(handle))
+    }
+
+    /// Ожидает пакеты завершения и передает результаты операций
+    fn reap(port: HANDLE) {
+        loop {
+            let mut size: DWORD = 0;
+            let mut key: ULONG_PTR = 0;
+            let mut overlapped: LPOVERLAPPED = std::ptr::null_mut();
+            let ok = unsafe { GetQueuedCompletionStatus(port, &mut size, &mut key, &mut overlapped, INFINITE) };
+            if overlapped.is_null() {
+                // ошибка самого порта, а не операции
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                continue;
+            }
+            let error = if ok == FALSE { Some(std::io::Error::last_os_error()) } else { None };
+            let mut request = unsafe { Box::from_raw(overlapped as *mut Request) };
+            if let Some(operation) = request.operation.take() {
+                operation.complete(io_result(size, error));
+            }
+        }
+    }
+}
+
+fn submit_rw<B, F, T, L>(write: bool, file: std::fs::File, buf: B, locate: L, finish: F) -> Result<PoolTask<T>, (std::fs::File, B)>
+    where B: Send + 'static,
+          F: FnOnce(std::fs::File, B, std::io::Result<usize>) -> std::io::Result<T> + Send + 'static,
+          T: Send + 'static,
+          L: FnOnce(&mut B) -> (usize, usize)
+{
+    let port = match *PORT {
+        Some(ref port) => port,
+        None => return Err((file, buf)),
+    };
+    // например, файл открыт только для дозаписи (`append`) или без нужного доступа
+    let handle = match OverlappedHandle::new(&file, write, port) {
+        Ok(handle) => handle,
+        Err(_) => return Err((file, buf)),
+    };
+    let position = match seek(&file, 0, FILE_CURRENT) {
+        Ok(position) => position,
+        Err(_) => return Err((file, buf)),
+    };
+    let raw = handle.0;
+    let (task, completion) = PoolTask::pending();
+    // адрес данных берется после перемещения буфера в кучу (см. `uring::submit_rw`)
+    let mut operation = Box::new(ReadWrite {
+        file,
+        handle,
+        buf,
+        position,
+        finish,
+        completion,
+    });
+    let (addr, len) = locate(&mut operation.buf);
+    let len = std::cmp::min(len, DWORD::max_value() as usize) as DWORD;
+    let mut request = Box::new(Request {
+        overlapped: unsafe { std::mem::zeroed() },
+        operation: Some(operation),
+    });
+    unsafe {
+        let offset = request.overlapped.u.s_mut();
+        offset.Offset = position as DWORD;
+        offset.OffsetHigh = (position >> 32) as DWORD;
+    }
+    let request = Box::into_raw(request);
+    let ok = unsafe {
+        if write {
+            WriteFile(raw, addr as *const _, len, std::ptr::null_mut(), &mut (*request).overlapped)
+        } else {
+            ReadFile(raw, addr as *mut _, len, std::ptr::null_mut(), &mut (*request).overlapped)
+        }
+    };
+    if ok == FALSE {
+        let error = std::io::Error::last_os_error();
+        if error.raw_os_error() != Some(ERROR_IO_PENDING as i32) {
+            // операция не начата, пакета завершения не будет
+            let mut request = unsafe { Box::from_raw(request) };
+            if let Some(operation) = request.operation.take() {
+                operation.complete(io_result(0, Some(error)));
+            }
+        }
+    }
+    // при успехе без ожидания пакет завершения тоже приходит в порт
+    Ok(task)
+}
+
+/// Читает в `buf` с текущей позиции файла перекрывающейся операцией (IOCP); `finish` получает
+/// файл, буфер и количество прочитанных байт. Если порт недоступен или файл нельзя открыть
+/// повторно для перекрывающихся операций, файл и буфер возвращаются для выполнения
+/// операции в пуле потоков.
+pub fn read<B, F, T>(file: std::fs::File, buf: B, finish: F) -> Result<PoolTask<T>, (std::fs::File, B)>
+    where B: AsMut<[u8]> + Send + 'static,
+          F: FnOnce(std::fs::File, B, std::io::Result<usize>) -> std::io::Result<T> + Send + 'static,
+          T: Send + 'static
+{
+    submit_rw(false, file, buf, |buf| {
+        let slice = buf.as_mut();
+        (slice.as_mut_ptr() as usize, slice.len())
+    }, finish)
+}
+
+/// Записывает `buf` с текущей позиции файла перекрывающейся операцией (см. `read`)
+pub fn write<B, F, T>(file: std::fs::File, buf: B, finish: F) -> Result<PoolTask<T>, (std::fs::File, B)>
+    where B: AsRef<[u8]> + Send + 'static,
+          F: FnOnce(std::fs::File, B, std::io::Result<usize>) -> std::io::Result<T> + Send + 'static,
+          T: Send + 'static
+{
+    submit_rw(true, file, buf, |buf| {
+        let slice = buf.as_ref();
+        (slice.as_ptr() as usize, slice.len())
+    }, finish)
+}
@@ -0,0 +1,187 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::{Bytes, BytesMut};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use super::DEFAULT_CPU_POOL;
+use sys::{self, long_path, retry};
+
+static DEFAULT_PAGE_SIZE: usize = 4 * 1024;
+static DEFAULT_MAX_PAGES: usize = 256;
+
+/// Кэш страниц с вытеснением давно не использованных
+struct PageCache {
+    /// Страница и отметка ее последнего использования
+    pages: HashMap<u64, (Bytes, u64)>,
+    /// Страницы в порядке их последнего использования
+    order: BTreeMap<u64, u64>,
+    clock: u64,
+    max_pages: usize,
+}
+impl PageCache {
+    fn get(&mut self, index: u64) -> Option<Bytes> {
+        self.clock += 1;
+        let clock = self.clock;
+        let page = match self.pages.get_mut(&index) {
+            Some(&mut (ref page, ref mut used)) => {
+                self.order.remove(used);
+                *used = clock;
+                page.clone()
+            },
+            None => return None,
+        };
+        self.order.insert(clock, index);
+        Some(page)
+    }
+
+    fn insert(&mut self, index: u64, page: Bytes) {
+        self.clock += 1;
+        if let Some((_, used)) = self.pages.insert(index, (page, self.clock)) {
+            self.order.remove(&used);
+        }
+        self.order.insert(self.clock, index);
+        while self.pages.len() > self.max_pages {
+            let oldest = match self.order.keys().next() {
+                Some(&oldest) => oldest,
+                None => break,
+            };
+            if let Some(index) = self.order.remove(&oldest) {
+                self.pages.remove(&index);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.pages.clear();
+        self.order.clear();
+    }
+}
+
+struct PagedInner {
+    file: std::fs::File,
+    page_size: usize,
+    cache: Mutex<PageCache>,
+}
+impl PagedInner {
+    fn page(&self, index: u64) -> std::io::Result<Bytes> {
+        if let Some(page) = self.cache.lock().unwrap().get(index) {
+            return Ok(page);
+        }
+        let mut buf = vec![0u8; self.page_size];
+        let size = sys::read_full_at(&self.file, &mut buf, index * self.page_size as u64)?;
+        buf.truncate(size);
+        let page = Bytes::from(buf);
+        self.cache.lock().unwrap().insert(index, page.clone());
+        Ok(page)
+    }
+
+    fn read_at(&self, offset: u64, len: usize) -> std::io::Result<Bytes> {
+        let page_size = self.page_size as u64;
+        let mut result: Option<BytesMut> = None;
+        let mut position = offset;
+        let end = offset + len as u64;
+        while position < end {
+            let index = position / page_size;
+            let page = self.page(index)?;
+            let start = (position - index * page_size) as usize;
+            if start >= page.len() {
+                break;
+            }
+            let stop = std::cmp::min(page.len() as u64, end - index * page_size) as usize;
+            if result.is_none() && stop as u64 + index * page_size == end {
+                // данные целиком в одной странице: возвращаются без копирования
+                return Ok(page.slice(start, stop));
+            }
+            result.get_or_insert_with(|| BytesMut::with_capacity(len)).extend_from_slice(&page[start..stop]);
+            position = index * page_size + stop as u64;
+            if page.len() < self.page_size {
+                break;
+            }
+        }
+        Ok(result.map(BytesMut::freeze).unwrap_or_default())
+    }
+}
+
+
+// PagedReader
+
+/// Файл для произвольного чтения небольшими фрагментами через кэш страниц.
+///
+/// Данные читаются в пуле потоков выровненными страницами размером `page_size` позиционным
+/// чтением, а страницы хранятся в кэше на `max_pages` страниц с вытеснением давно не использованных.
+/// Поэтому множество мелких чтений из соседних областей (например, узлов B-дерева)
+/// не приводит к системному вызову на каждое чтение. Кэш не отслеживает изменения файла:
+/// после записи в файл его следует сбросить вызовом `invalidate`.
+#[derive(Clone)]
+pub struct PagedReader {
+    cpu_pool: &'static CpuPool,
+    inner: Arc<PagedInner>,
+}
+impl PagedReader {
+
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> CpuFuture<PagedReader, std::io::Error> {
+        Self::open_with_pool(&DEFAULT_CPU_POOL, path)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<PagedReader, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let file = retry(|| std::fs::File::open(long_path(&path)))?;
+            Ok(PagedReader::from_std(cpu_pool, file))
+        })
+    }
+
+    #[inline]
+    pub fn from_std(cpu_pool: &'static CpuPool, file: std::fs::File) -> PagedReader {
+        Self::with_page_size(cpu_pool, file, DEFAULT_PAGE_SIZE, DEFAULT_MAX_PAGES)
+    }
+
+    /// Создает структуру с размером страницы `page_size` и кэшем на `max_pages` страниц
+    pub fn with_page_size(cpu_pool: &'static CpuPool, file: std::fs::File, page_size: usize, max_pages: usize) -> PagedReader {
+        assert!(page_size > 0, "page size must be greater than zero");
+        assert!(max_pages > 0, "max pages must be greater than zero");
+        PagedReader {
+            cpu_pool,
+            inner: Arc::new(PagedInner {
+                file,
+                page_size,
+                cache: Mutex::new(PageCache {
+                    pages: HashMap::new(),
+                    order: BTreeMap::new(),
+                    clock: 0,
+                    max_pages,
+                }),
+            }),
+        }
+    }
+
+    #[inline]
+    pub fn page_size(&self) -> usize {
+        self.inner.page_size
+    }
+
+    /// Читает до `len` байт по смещению `offset` (меньше - только в конце файла)
+    pub fn read_at(&self, offset: u64, len: usize) -> CpuFuture<Bytes, std::io::Error> {
+        let inner = self.inner.clone();
+        self.cpu_pool.spawn_fn(move || inner.read_at(offset, len))
+    }
+
+    /// Количество страниц в кэше
+    pub fn cached_pages(&self) -> usize {
+        self.inner.cache.lock().unwrap().pages.len()
+    }
+
+    /// Сбрасывает кэш страниц
+    pub fn invalidate(&self) {
+        self.inner.cache.lock().unwrap().clear();
+    }
+}
+impl std::fmt::Debug for PagedReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PagedReader")
+            .field("page_size", &self.inner.page_size)
+            .field("cached_pages", &self.cached_pages())
+            .finish()
+    }
+}
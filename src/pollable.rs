@@ -0,0 +1,123 @@
+use futures::Poll;
+use futures_cpupool::{CpuPool, CpuFuture};
+use mio::{Evented, PollOpt, Ready, Token};
+use mio::unix::EventedFd;
+use std::io::{Read, Write};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use tokio::reactor::PollEvented2;
+use super::DEFAULT_CPU_POOL;
+use sys::{long_path, retry};
+
+/// Файл в неблокирующем режиме, регистрируемый в реакторе `mio`
+struct EventedFile(std::fs::File);
+impl Evented for EventedFile {
+    fn register(&self, poll: &mio::Poll, token: Token, interest: Ready, opts: PollOpt) -> std::io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).register(poll, token, interest, opts)
+    }
+
+    fn reregister(&self, poll: &mio::Poll, token: Token, interest: Ready, opts: PollOpt) -> std::io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &mio::Poll) -> std::io::Result<()> {
+        EventedFd(&self.0.as_raw_fd()).deregister(poll)
+    }
+}
+impl Read for EventedFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+impl Write for EventedFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+fn set_nonblocking(file: &std::fs::File) -> std::io::Result<()> {
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+
+// PollableFile
+
+/// Файл, поддерживающий ожидание готовности (FIFO, терминал, некоторые символьные устройства),
+/// чтение и запись которого выполняются через реактор tokio, а не в пуле потоков.
+///
+/// В отличие от `AsyncFileRead` чтение, которое может никогда не завершиться (например,
+/// из FIFO без данных), не занимает поток пула. Обычные файлы и блочные устройства
+/// всегда "готовы", поэтому для них возвращается ошибка `InvalidInput`.
+/// Чтение из FIFO, у которого нет ни одного пишущего процесса, возвращает конец файла.
+pub struct PollableFile {
+    io: PollEvented2<EventedFile>,
+}
+impl PollableFile {
+
+    /// Открывает файл на чтение без ожидания пишущей стороны FIFO
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> CpuFuture<PollableFile, std::io::Error> {
+        Self::open_with_pool(&DEFAULT_CPU_POOL, path)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<PollableFile, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let file = retry(|| {
+                std::fs::OpenOptions::new()
+                    .read(true)
+                    .custom_flags(libc::O_NONBLOCK)
+                    .open(long_path(&path))
+            })?;
+            PollableFile::from_std(file)
+        })
+    }
+
+    /// Переводит открытый файл в неблокирующий режим. Регистрация в реакторе
+    /// выполняется при первой операции в контексте среды исполнения tokio.
+    pub fn from_std(file: std::fs::File) -> std::io::Result<PollableFile> {
+        let file_type = file.metadata()?.file_type();
+        if !(file_type.is_fifo() || file_type.is_char_device() || file_type.is_socket()) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "file does not support readiness polling"));
+        }
+        set_nonblocking(&file)?;
+        Ok(PollableFile {
+            io: PollEvented2::new(EventedFile(file)),
+        })
+    }
+}
+impl Read for PollableFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+impl Write for PollableFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.io.flush()
+    }
+}
+impl tokio::io::AsyncRead for PollableFile {}
+impl tokio::io::AsyncWrite for PollableFile {
+    fn shutdown(&mut self) -> Poll<(), std::io::Error> {
+        tokio::io::AsyncWrite::shutdown(&mut self.io)
+    }
+}
+impl std::fmt::Debug for PollableFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PollableFile").finish()
+    }
+}
@@ -0,0 +1,118 @@
+use futures_cpupool::CpuPool;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use metrics::{pool_metrics, PoolMetrics};
+use super::DEFAULT_CPU_POOL;
+
+lazy_static! {
+    static ref GLOBAL_REGISTRY: PoolRegistry = PoolRegistry::new();
+}
+
+/// Абсолютный путь без обращения к файловой системе (символические ссылки не раскрываются)
+pub fn absolute_path(path: &Path) -> PathBuf {
+    if path.is_absolute() {
+        return path.into();
+    }
+    match std::env::current_dir() {
+        Ok(dir) => dir.join(path),
+        Err(_) => path.into(),
+    }
+}
+
+/// Пул потоков для `path` из глобального реестра (см. `PoolRegistry::global`)
+#[inline]
+pub fn pool_for(path: &Path) -> &'static CpuPool {
+    GLOBAL_REGISTRY.pool_for(path)
+}
+
+
+// PoolRegistry
+
+/// Соответствие префиксов путей (точек монтирования) пулам потоков.
+///
+/// Позволяет выделить отдельный пул для каждого физического носителя (см. `DEFAULT_CPU_POOL`):
+/// конструкторы `open`/`create` без явного пула (`AsyncFileRead::open`, `AsyncFileWrite::create`,
+/// `AsyncOpenOptions` без `cpu_pool` и т.д.) выбирают пул из глобального реестра по пути файла.
+/// Выбирается самый длинный совпадающий префикс (по компонентам пути, так что `/data`
+/// не совпадает с `/database`); если совпадений нет - `DEFAULT_CPU_POOL`.
+///
+/// Относительные пути дополняются текущей директорией; символические ссылки не раскрываются.
+pub struct PoolRegistry {
+    prefixes: RwLock<Vec<(PathBuf, &'static CpuPool)>>,
+}
+impl PoolRegistry {
+
+    pub fn new() -> PoolRegistry {
+        PoolRegistry {
+            prefixes: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Реестр, используемый конструкторами `open`/`create`
+    #[inline]
+    pub fn global() -> &'static PoolRegistry {
+        &GLOBAL_REGISTRY
+    }
+
+    /// Направляет операции с файлами внутри `prefix` в `cpu_pool`;
+    /// пул, ранее зарегистрированный для того же префикса, заменяется
+    pub fn register<P: AsRef<Path>>(&self, prefix: P, cpu_pool: &'static CpuPool) -> &Self {
+        let prefix = absolute_path(prefix.as_ref());
+        let mut prefixes = self.prefixes.write().unwrap();
+        prefixes.retain(|(registered, _)| *registered != prefix);
+        prefixes.push((prefix, cpu_pool));
+        // более длинные префиксы проверяются первыми
+        prefixes.sort_by_key(|entry| std::cmp::Reverse(entry.0.components().count()));
+        self
+    }
+
+    /// Удаляет префикс; возвращает `true`, если он был зарегистрирован
+    pub fn unregister<P: AsRef<Path>>(&self, prefix: P) -> bool {
+        let prefix = absolute_path(prefix.as_ref());
+        let mut prefixes = self.prefixes.write().unwrap();
+        let len = prefixes.len();
+        prefixes.retain(|(registered, _)| *registered != prefix);
+        prefixes.len() != len
+    }
+
+    /// Пул потоков для `path`: пул самого длинного зарегистрированного префикса
+    /// или `DEFAULT_CPU_POOL`
+    pub fn pool_for<P: AsRef<Path>>(&self, path: P) -> &'static CpuPool {
+        let path = absolute_path(path.as_ref());
+        self.prefixes.read().unwrap().iter()
+            .find(|(prefix, _)| path.starts_with(prefix))
+            .map(|&(_, cpu_pool)| cpu_pool)
+            .unwrap_or(&DEFAULT_CPU_POOL)
+    }
+
+    /// Зарегистрированные префиксы, начиная с самых длинных
+    pub fn prefixes(&self) -> Vec<PathBuf> {
+        self.prefixes.read().unwrap().iter().map(|(prefix, _)| prefix.clone()).collect()
+    }
+
+    /// Счетчики пула, обслуживающего `path` (см. `pool_metrics`); включаются первым вызовом
+    #[inline]
+    pub fn metrics_for<P: AsRef<Path>>(&self, path: P) -> Arc<PoolMetrics> {
+        pool_metrics(self.pool_for(path))
+    }
+
+    /// Счетчики пулов всех зарегистрированных префиксов, начиная с самых длинных
+    /// (префиксы с общим пулом разделяют и счетчики)
+    pub fn metrics(&self) -> Vec<(PathBuf, Arc<PoolMetrics>)> {
+        self.prefixes.read().unwrap().iter()
+            .map(|&(ref prefix, cpu_pool)| (prefix.clone(), pool_metrics(cpu_pool)))
+            .collect()
+    }
+}
+impl Default for PoolRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl std::fmt::Debug for PoolRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PoolRegistry")
+            .field("prefixes", &self.prefixes())
+            .finish()
+    }
+}
@@ -0,0 +1,160 @@
+use futures::{Poll, Async, Future};
+use futures::task::{self, AtomicTask};
+use executor::BlockingExecutor;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+struct Shared<T> {
+    result: Mutex<Option<std::io::Result<T>>>,
+    task: AtomicTask,
+    /// Ожидающая сторона освобождена: еще не начатую операцию выполнять не нужно
+    cancelled: AtomicBool,
+}
+
+
+// PoolTask
+
+/// Операция, выполняемая в пуле потоков (см. `BlockingExecutor`), с явным пробуждением ожидающей задачи.
+///
+/// В отличие от `CpuFuture`, опрос вне контекста задачи не приводит к панике:
+/// результат просто проверяется без регистрации. При опросе внутри задачи она запоминается
+/// и гарантированно пробуждается по завершении операции, даже если между опросами
+/// задача сменилась (например, при передаче объекта другому исполнителю).
+///
+/// Как и `CpuFuture`, при освобождении отменяет операцию, которая еще не начала выполняться
+/// (например, ждет в очереди пула, занятого зависшими операциями); начатая операция
+/// выполняется до конца, а ее результат (вместе с файлом) освобождается в пуле потоков.
+/// `forget` оставляет операцию выполняться без ожидания. После получения результата
+/// повторный опрос возвращает ошибку, а не ожидает бесконечно.
+pub struct PoolTask<T> {
+    shared: Arc<Shared<T>>,
+    /// Результат уже получен
+    done: bool,
+    forgotten: bool,
+}
+impl<T: Send + 'static> PoolTask<T> {
+
+    pub fn spawn<E, F>(executor: &E, f: F) -> PoolTask<T>
+        where E: BlockingExecutor,
+              F: FnOnce() -> std::io::Result<T> + Send + 'static
+    {
+        let (task, completion) = PoolTask::pending();
+        executor.execute(Box::new(move || {
+            if completion.is_cancelled() {
+                return;
+            }
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(f))
+                .unwrap_or_else(|_| Err(std::io::Error::other("pool operation panicked")));
+            completion.complete(result);
+        }));
+        task
+    }
+
+    /// Операция, которую завершает не пул потоков, а владелец `Completion` (например, io_uring)
+    pub fn pending() -> (PoolTask<T>, Completion<T>) {
+        let shared = Arc::new(Shared {
+            result: Mutex::new(None),
+            task: AtomicTask::new(),
+            cancelled: AtomicBool::new(false),
+        });
+        (PoolTask { shared: shared.clone(), done: false, forgotten: false }, Completion { shared })
+    }
+
+    fn take(&mut self) -> Option<std::io::Result<T>> {
+        let result = self.shared.result.lock().unwrap().take();
+        if result.is_some() {
+            self.done = true;
+        }
+        result
+    }
+
+    fn is_finished(&self) -> bool {
+        self.shared.result.lock().unwrap().is_some()
+    }
+
+    /// Проверяет завершение операции, не забирая результат;
+    /// текущая задача (если она есть) регистрируется для пробуждения
+    pub fn poll_finished(&mut self) -> Async<()> {
+        if self.done {
+            return Async::Ready(());
+        }
+        if !self.is_finished() && task::is_in_task() {
+            self.shared.task.register();
+        }
+        if self.is_finished() {
+            return Async::Ready(());
+        }
+        Async::NotReady
+    }
+
+    /// Проверяет завершение операции, регистрируя текущую задачу (если она есть) для пробуждения
+    pub fn poll(&mut self) -> Poll<T, std::io::Error> {
+        if self.done {
+            // например, обертка, потерявшая файл вместе с ошибкой операции
+            return Err(std::io::Error::other("pool operation already completed"));
+        }
+        if let Some(result) = self.take() {
+            return result.map(Async::Ready);
+        }
+        if task::is_in_task() {
+            self.shared.task.register();
+            // операция могла завершиться до регистрации задачи
+            if let Some(result) = self.take() {
+                return result.map(Async::Ready);
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+impl<T: Send + 'static> Future for PoolTask<T> {
+    type Item = T;
+    type Error = std::io::Error;
+
+    #[inline]
+    fn poll(&mut self) -> Poll<T, std::io::Error> {
+        PoolTask::poll(self)
+    }
+}
+impl<T> PoolTask<T> {
+
+    /// Оставляет операцию выполняться после освобождения `PoolTask`, не дожидаясь результата
+    pub fn forget(mut self) {
+        self.forgotten = true;
+    }
+}
+impl<T> Drop for PoolTask<T> {
+    fn drop(&mut self) {
+        if !self.forgotten {
+            self.shared.cancelled.store(true, Ordering::SeqCst);
+        }
+    }
+}
+impl<T> std::fmt::Debug for PoolTask<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PoolTask")
+            .field("finished", &self.shared.result.lock().unwrap().is_some())
+            .finish()
+    }
+}
+
+
+// Completion
+
+/// Завершение операции `PoolTask`, созданной `PoolTask::pending`
+pub struct Completion<T> {
+    shared: Arc<Shared<T>>,
+}
+impl<T> Completion<T> {
+
+    /// Освобождена ли `PoolTask` без `forget`: результат никто не ожидает
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.shared.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Сохраняет результат и пробуждает ожидающую задачу
+    pub fn complete(self, result: std::io::Result<T>) {
+        *self.shared.result.lock().unwrap() = Some(result);
+        self.shared.task.notify();
+    }
+}
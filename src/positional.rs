@@ -0,0 +1,68 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::sync::Arc;
+use super::DEFAULT_CPU_POOL;
+use sys;
+
+/// Читает ровно `len` байт по смещению `offset`, не изменяя позицию курсора файла.
+///
+/// Короткие чтения повторяются в пуле потоков, пока буфер не будет заполнен;
+/// если файл заканчивается раньше, возвращается ошибка `UnexpectedEof`.
+/// Файл передается через `Arc`, поэтому позиционные операции над ним могут выполняться одновременно.
+#[inline]
+pub fn read_exact_at(file: Arc<std::fs::File>, offset: u64, len: usize) -> CpuFuture<Bytes, std::io::Error> {
+    read_exact_at_with_pool(&DEFAULT_CPU_POOL, file, offset, len)
+}
+
+pub fn read_exact_at_with_pool(cpu_pool: &'static CpuPool, file: Arc<std::fs::File>, offset: u64, len: usize) -> CpuFuture<Bytes, std::io::Error> {
+    cpu_pool.spawn_fn(move || {
+        let mut buf = vec![0u8; len];
+        sys::read_exact_at(&file, &mut buf, offset)?;
+        Ok(Bytes::from(buf))
+    })
+}
+
+/// Записывает данные целиком по смещению `offset`, не изменяя позицию курсора файла (где это возможно).
+///
+/// Короткие записи повторяются в пуле потоков; если система не принимает данные,
+/// возвращается ошибка `WriteZero`.
+#[inline]
+pub fn write_all_at(file: Arc<std::fs::File>, offset: u64, data: Bytes) -> CpuFuture<(), std::io::Error> {
+    write_all_at_with_pool(&DEFAULT_CPU_POOL, file, offset, data)
+}
+
+pub fn write_all_at_with_pool(cpu_pool: &'static CpuPool, file: Arc<std::fs::File>, offset: u64, data: Bytes) -> CpuFuture<(), std::io::Error> {
+    cpu_pool.spawn_fn(move || sys::write_all_at(&file, &data, offset))
+}
+
+/// Читает по смещению `offset` несколько расположенных подряд фрагментов длиной `lens`
+/// (например, заголовок и тело записи) одним векторным чтением (`preadv`) в пуле потоков.
+///
+/// Короткие чтения повторяются; если файл заканчивается раньше, возвращается ошибка `UnexpectedEof`.
+/// На платформах без `preadv` фрагменты читаются по одному в том же обращении к пулу.
+#[inline]
+pub fn read_exact_vectored_at(file: Arc<std::fs::File>, offset: u64, lens: Vec<usize>) -> CpuFuture<Vec<Bytes>, std::io::Error> {
+    read_exact_vectored_at_with_pool(&DEFAULT_CPU_POOL, file, offset, lens)
+}
+
+pub fn read_exact_vectored_at_with_pool(cpu_pool: &'static CpuPool, file: Arc<std::fs::File>, offset: u64, lens: Vec<usize>) -> CpuFuture<Vec<Bytes>, std::io::Error> {
+    cpu_pool.spawn_fn(move || {
+        let mut bufs: Vec<Vec<u8>> = lens.iter().map(|&len| vec![0u8; len]).collect();
+        let size = sys::read_full_vectored_at(&file, &mut bufs, offset)?;
+        if size < lens.iter().sum::<usize>() {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "failed to fill whole buffers"));
+        }
+        Ok(bufs.into_iter().map(Bytes::from).collect())
+    })
+}
+
+/// Записывает несколько буферов подряд, начиная со смещения `offset`, векторной записью
+/// (`pwritev`) в пуле потоков, например, несколько измененных страниц одним вызовом.
+#[inline]
+pub fn write_all_vectored_at(file: Arc<std::fs::File>, offset: u64, bufs: Vec<Bytes>) -> CpuFuture<(), std::io::Error> {
+    write_all_vectored_at_with_pool(&DEFAULT_CPU_POOL, file, offset, bufs)
+}
+
+pub fn write_all_vectored_at_with_pool(cpu_pool: &'static CpuPool, file: Arc<std::fs::File>, offset: u64, bufs: Vec<Bytes>) -> CpuFuture<(), std::io::Error> {
+    cpu_pool.spawn_fn(move || sys::write_all_vectored_at(&file, &bufs, offset))
+}
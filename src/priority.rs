@@ -0,0 +1,156 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, Condvar};
+use executor::BlockingExecutor;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Класс приоритета операций с файлом (см. `PriorityPool`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Операции, от которых зависит время ответа (например, обработчики запросов)
+    High,
+    Normal,
+    /// Фоновые операции (резервное копирование, очистка), выполняемые,
+    /// когда в очереди нет операций с более высоким приоритетом
+    Background,
+}
+impl Priority {
+    #[inline]
+    fn index(self) -> usize {
+        match self {
+            Priority::High => 0,
+            Priority::Normal => 1,
+            Priority::Background => 2,
+        }
+    }
+}
+impl Default for Priority {
+    #[inline]
+    fn default() -> Self {
+        Priority::Normal
+    }
+}
+
+struct Queues {
+    jobs: [VecDeque<Job>; 3],
+    shutdown: bool,
+}
+
+struct Shared {
+    queues: Mutex<Queues>,
+    condvar: Condvar,
+}
+impl Shared {
+    fn run(&self) {
+        loop {
+            let job = {
+                let mut queues = self.queues.lock().unwrap();
+                loop {
+                    if let Some(job) = queues.jobs.iter_mut().filter_map(|jobs| jobs.pop_front()).next() {
+                        break job;
+                    }
+                    if queues.shutdown {
+                        return;
+                    }
+                    queues = self.condvar.wait(queues).unwrap();
+                }
+            };
+            // паника задачи не должна уменьшать число потоков пула
+            let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(job));
+        }
+    }
+}
+
+/// Останавливает потоки пула (после выполнения очереди), когда освобождены все его копии
+struct Workers {
+    shared: Arc<Shared>,
+}
+impl Drop for Workers {
+    fn drop(&mut self) {
+        self.shared.queues.lock().unwrap().shutdown = true;
+        self.shared.condvar.notify_all();
+    }
+}
+
+
+// PriorityPool
+
+/// Пул потоков, выполняющий операции в порядке приоритета (см. `Priority`), а внутри
+/// одного класса - в порядке поступления.
+///
+/// В отличие от `CpuPool` с очередью FIFO, операции `High` выполняются раньше уже ожидающих
+/// операций `Normal` и `Background`, поэтому массовая фоновая работа не задерживает операции,
+/// чувствительные к задержке. Начатая операция не прерывается, а фоновые операции ждут,
+/// пока в очереди есть более приоритетные.
+///
+/// Приоритет задается для каждой обертки копией пула:
+///
+/// ```ignore
+/// let pool = PriorityPool::new(4);
+/// let backup = AsyncFileStream::from_std_with_executor(pool.with_priority(Priority::Background), file, size);
+/// let request = AsyncFileRead::from_std_with_executor(pool.with_priority(Priority::High), file, size);
+/// ```
+///
+/// Потоки завершаются после освобождения всех копий пула и выполнения очереди.
+#[derive(Clone)]
+pub struct PriorityPool {
+    workers: Arc<Workers>,
+    priority: Priority,
+}
+impl PriorityPool {
+
+    /// Создает пул из `threads` потоков; приоритет операций - `Priority::Normal`
+    pub fn new(threads: usize) -> PriorityPool {
+        let shared = Arc::new(Shared {
+            queues: Mutex::new(Queues {
+                jobs: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+                shutdown: false,
+            }),
+            condvar: Condvar::new(),
+        });
+        for index in 0..std::cmp::max(threads, 1) {
+            let runner = shared.clone();
+            std::thread::Builder::new()
+                .name(format!("async_fs-priority-{}", index))
+                .spawn(move || runner.run())
+                .expect("failed to start a priority pool thread");
+        }
+        PriorityPool {
+            workers: Arc::new(Workers { shared }),
+            priority: Priority::Normal,
+        }
+    }
+
+    /// Копия пула, выполняющая операции с приоритетом `priority`
+    #[inline]
+    pub fn with_priority(&self, priority: Priority) -> PriorityPool {
+        PriorityPool {
+            workers: self.workers.clone(),
+            priority,
+        }
+    }
+
+    #[inline]
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Операции класса `priority`, ожидающие в очереди
+    pub fn queued(&self, priority: Priority) -> usize {
+        self.workers.shared.queues.lock().unwrap().jobs[priority.index()].len()
+    }
+}
+impl BlockingExecutor for PriorityPool {
+    fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+        let shared = &self.workers.shared;
+        shared.queues.lock().unwrap().jobs[self.priority.index()].push_back(job);
+        shared.condvar.notify_one();
+    }
+}
+impl std::fmt::Debug for PriorityPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PriorityPool")
+            .field("priority", &self.priority)
+            .finish()
+    }
+}
@@ -0,0 +1,79 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::DEFAULT_CPU_POOL;
+use sys::{long_path, retry, sync_all};
+
+static QUARANTINE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Расширение файла с описанием причины карантина
+pub static QUARANTINE_SIDECAR_EXTENSION: &str = "quarantine";
+
+/// Файл, перемещенный в карантин
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quarantined {
+    /// Новый путь файла в каталоге карантина
+    pub path: PathBuf,
+    /// Путь файла с описанием причины
+    pub sidecar: PathBuf,
+}
+
+fn move_to_quarantine(path: &Path, dir: &Path, reason: &str) -> std::io::Result<Quarantined> {
+    let name = path.file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name"))?
+        .to_string_lossy()
+        .into_owned();
+    let size = std::fs::symlink_metadata(long_path(path))?.len();
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    retry(|| std::fs::create_dir_all(long_path(dir)))?;
+    let target = loop {
+        let target = dir.join(format!("{}-{}-{}-{}", now, std::process::id(), QUARANTINE_COUNTER.fetch_add(1, Ordering::SeqCst), name));
+        if std::fs::symlink_metadata(long_path(&target)).is_err() {
+            break target;
+        }
+    };
+    // переименование атомарно, поэтому файл сразу перестает быть доступен по прежнему пути;
+    // между файловыми системами оно невозможно, и файл остается на месте
+    retry(|| std::fs::rename(long_path(path), long_path(&target)))?;
+
+    let mut sidecar_name = target.file_name().unwrap().to_os_string();
+    sidecar_name.push(".");
+    sidecar_name.push(QUARANTINE_SIDECAR_EXTENSION);
+    let sidecar = target.with_file_name(sidecar_name);
+    let mut temporary_name = sidecar.file_name().unwrap().to_os_string();
+    temporary_name.push(".tmp");
+    let temporary = sidecar.with_file_name(temporary_name);
+    {
+        let mut file = retry(|| std::fs::File::create(long_path(&temporary)))?;
+        writeln!(file, "original: {}", path.display())?;
+        writeln!(file, "size: {}", size)?;
+        writeln!(file, "quarantined_at: {}", now)?;
+        writeln!(file, "reason: {}", reason.replace('\n', " "))?;
+        retry(|| sync_all(&file))?;
+    }
+    retry(|| std::fs::rename(long_path(&temporary), long_path(&sidecar)))?;
+    Ok(Quarantined { path: target, sidecar })
+}
+
+/// Перемещает поврежденный файл `path` в каталог карантина `dir` (каталог создается при
+/// необходимости), чтобы он больше не выдавался, и записывает рядом с ним файл
+/// `*.quarantine` с исходным путем, размером, временем и причиной `reason`
+/// (например, `ScrubProblem` из отчета `Scrubber`).
+///
+/// Файл перемещается переименованием, поэтому каталог карантина должен находиться
+/// на той же файловой системе; иначе возвращается ошибка, а файл остается на месте.
+#[inline]
+pub fn quarantine<P: AsRef<Path>, D: AsRef<Path>>(path: P, dir: D, reason: &str) -> CpuFuture<Quarantined, std::io::Error> {
+    quarantine_with_pool(&DEFAULT_CPU_POOL, path, dir, reason)
+}
+
+pub fn quarantine_with_pool<P: AsRef<Path>, D: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P, dir: D, reason: &str) -> CpuFuture<Quarantined, std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    let dir: PathBuf = dir.as_ref().into();
+    let reason = reason.to_string();
+    cpu_pool.spawn_fn(move || move_to_quarantine(&path, &dir, &reason))
+}
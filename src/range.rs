@@ -0,0 +1,178 @@
+use futures::{Poll, Async, Stream};
+use bytes::Bytes;
+use std::io::SeekFrom;
+use super::AsyncFileStream;
+use executor::BlockingExecutor;
+
+/// Поток, позицию которого можно переместить между чтениями блоков
+pub trait SeekableStream: Stream {
+
+    /// Перемещает позицию потока. Вызывается повторно с тем же `pos`, пока не вернет `Ready`.
+    fn poll_seek(&mut self, pos: SeekFrom) -> Poll<u64, Self::Error>;
+}
+impl<E: BlockingExecutor> SeekableStream for AsyncFileStream<E> {
+    #[inline]
+    fn poll_seek(&mut self, pos: SeekFrom) -> Poll<u64, std::io::Error> {
+        AsyncFileStream::poll_seek(self, pos)
+    }
+}
+
+fn poll_seek_skip<S: SeekableStream>(stream: &mut S, n: u64) -> Poll<u64, S::Error> {
+    stream.poll_seek(SeekFrom::Current(n as i64))
+}
+
+
+// SkipBytes
+
+/// Перемещение позиции исходного потока вперед вместо чтения пропускаемых байт
+type SeekFn<S> = fn(&mut S, u64) -> Poll<u64, <S as Stream>::Error>;
+
+/// Поток, пропускающий первые `n` байт исходного потока
+pub struct SkipBytes<S: Stream> {
+    inner: S,
+    remaining: u64,
+    seek: Option<SeekFn<S>>,
+}
+
+/// Пропускает первые `n` байт потока, читая и отбрасывая их
+pub fn skip_bytes<S: Stream<Item = Bytes>>(stream: S, n: u64) -> SkipBytes<S> {
+    SkipBytes {
+        inner: stream,
+        remaining: n,
+        seek: None,
+    }
+}
+
+/// Пропускает первые `n` байт потока перемещением его позиции, не читая их.
+///
+/// Чтение из потока не должно быть начато: данные уже прочитанного блока не учитываются.
+pub fn skip_bytes_seek<S: SeekableStream<Item = Bytes>>(stream: S, n: u64) -> SkipBytes<S> {
+    SkipBytes {
+        inner: stream,
+        remaining: n,
+        seek: Some(poll_seek_skip::<S>),
+    }
+}
+
+impl<S: Stream> SkipBytes<S> {
+
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+impl<S: Stream<Item = Bytes>> Stream for SkipBytes<S> {
+    type Item = Bytes;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.remaining > 0 {
+            if let Some(seek) = self.seek {
+                try_ready!(seek(&mut self.inner, self.remaining));
+                self.remaining = 0;
+            }
+        }
+        loop {
+            let mut chunk = match try_ready!(self.inner.poll()) {
+                Some(chunk) => chunk,
+                None => return Ok(Async::Ready(None)),
+            };
+            if self.remaining == 0 {
+                return Ok(Async::Ready(Some(chunk)));
+            }
+            if (chunk.len() as u64) <= self.remaining {
+                self.remaining -= chunk.len() as u64;
+                continue;
+            }
+            let tail = chunk.split_off(self.remaining as usize);
+            self.remaining = 0;
+            return Ok(Async::Ready(Some(tail)));
+        }
+    }
+}
+impl<S: Stream> std::fmt::Debug for SkipBytes<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SkipBytes")
+            .field("remaining", &self.remaining)
+            .field("seek", &self.seek.is_some())
+            .finish()
+    }
+}
+
+
+// TakeBytes
+
+/// Поток, завершающийся после первых `n` байт исходного потока
+pub struct TakeBytes<S> {
+    inner: S,
+    remaining: u64,
+}
+
+/// Ограничивает поток первыми `n` байтами, обрезая последний блок при необходимости.
+/// После достижения ограничения исходный поток больше не опрашивается.
+pub fn take_bytes<S: Stream<Item = Bytes>>(stream: S, n: u64) -> TakeBytes<S> {
+    TakeBytes {
+        inner: stream,
+        remaining: n,
+    }
+}
+
+impl<S> TakeBytes<S> {
+
+    /// Сколько байт еще может вернуть поток
+    #[inline]
+    pub fn remaining(&self) -> u64 {
+        self.remaining
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+impl<S: Stream<Item = Bytes>> Stream for TakeBytes<S> {
+    type Item = Bytes;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.remaining == 0 {
+            return Ok(Async::Ready(None));
+        }
+        let mut chunk = match try_ready!(self.inner.poll()) {
+            Some(chunk) => chunk,
+            None => return Ok(Async::Ready(None)),
+        };
+        if (chunk.len() as u64) > self.remaining {
+            chunk.truncate(self.remaining as usize);
+        }
+        self.remaining -= chunk.len() as u64;
+        Ok(Async::Ready(Some(chunk)))
+    }
+}
+impl<S> std::fmt::Debug for TakeBytes<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("TakeBytes")
+            .field("remaining", &self.remaining)
+            .finish()
+    }
+}
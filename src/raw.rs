@@ -0,0 +1,139 @@
+use std::convert::TryFrom;
+#[cfg(unix)] use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+#[cfg(windows)] use std::os::windows::io::{AsRawHandle, FromRawHandle, IntoRawHandle, RawHandle};
+use super::{AsyncFileWrite, AsyncFileSink, AsyncFileRead, AsyncFileStream, AsyncFile};
+use executor::BlockingExecutor;
+use ephemeral::EphemeralFile;
+#[cfg(any(unix, windows))] use lock::FileLock;
+#[cfg(unix)] use shm::SharedMemory;
+
+fn into_std<T>(wrapper: T) -> std::fs::File
+    where std::fs::File: TryFrom<T, Error = std::io::Error>
+{
+    match std::fs::File::try_from(wrapper) {
+        Ok(file) => file,
+        Err(error) => panic!("cannot take the file out of the wrapper: {}", error),
+    }
+}
+
+#[cfg(unix)]
+macro_rules! impl_raw_fd {
+    ($wrapper:ident $(, $param:ident)*) => {
+        impl<E $(, $param)*> AsRawFd for $wrapper<E $(, $param)*> {
+            /// Дескриптор может быть занят операцией, выполняющейся в пуле потоков, поэтому
+            /// изменять через него позицию или флаги и закрывать его нельзя; регистрация в epoll,
+            /// передача через сокет (`SCM_RIGHTS`) и `fstat` безопасны
+            #[inline]
+            fn as_raw_fd(&self) -> RawFd {
+                self.raw
+            }
+        }
+        impl FromRawFd for $wrapper {
+            /// Обертка использует стандартный пул потоков
+            #[inline]
+            unsafe fn from_raw_fd(fd: RawFd) -> Self {
+                Self::from(<std::fs::File as FromRawFd>::from_raw_fd(fd))
+            }
+        }
+        impl<E: BlockingExecutor $(, $param)*> IntoRawFd for $wrapper<E $(, $param)*> {
+            /// Паникует, если операция в пуле потоков не завершена
+            /// (см. `TryFrom<...> for std::fs::File`)
+            #[inline]
+            fn into_raw_fd(self) -> RawFd {
+                into_std(self).into_raw_fd()
+            }
+        }
+    };
+}
+
+#[cfg(windows)]
+macro_rules! impl_raw_fd {
+    ($wrapper:ident $(, $param:ident)*) => {
+        impl<E $(, $param)*> AsRawHandle for $wrapper<E $(, $param)*> {
+            /// Дескриптор может быть занят операцией, выполняющейся в пуле потоков
+            #[inline]
+            fn as_raw_handle(&self) -> RawHandle {
+                self.raw as RawHandle
+            }
+        }
+        impl FromRawHandle for $wrapper {
+            /// Обертка использует стандартный пул потоков
+            #[inline]
+            unsafe fn from_raw_handle(handle: RawHandle) -> Self {
+                Self::from(<std::fs::File as FromRawHandle>::from_raw_handle(handle))
+            }
+        }
+        impl<E: BlockingExecutor $(, $param)*> IntoRawHandle for $wrapper<E $(, $param)*> {
+            /// Паникует, если операция в пуле потоков не завершена
+            #[inline]
+            fn into_raw_handle(self) -> RawHandle {
+                into_std(self).into_raw_handle()
+            }
+        }
+    };
+}
+
+impl_raw_fd!(AsyncFileWrite);
+impl_raw_fd!(AsyncFileSink, B);
+impl_raw_fd!(AsyncFileRead);
+impl_raw_fd!(AsyncFileStream);
+impl_raw_fd!(AsyncFile);
+
+#[cfg(unix)]
+impl AsRawFd for EphemeralFile {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.file().as_raw_fd()
+    }
+}
+#[cfg(unix)]
+impl IntoRawFd for EphemeralFile {
+    #[inline]
+    fn into_raw_fd(self) -> RawFd {
+        self.into_std().into_raw_fd()
+    }
+}
+#[cfg(windows)]
+impl AsRawHandle for EphemeralFile {
+    #[inline]
+    fn as_raw_handle(&self) -> RawHandle {
+        self.file().as_raw_handle()
+    }
+}
+#[cfg(windows)]
+impl IntoRawHandle for EphemeralFile {
+    #[inline]
+    fn into_raw_handle(self) -> RawHandle {
+        self.into_std().into_raw_handle()
+    }
+}
+
+#[cfg(unix)]
+impl<E> AsRawFd for FileLock<E> {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.file().as_raw_fd()
+    }
+}
+#[cfg(windows)]
+impl<E> AsRawHandle for FileLock<E> {
+    #[inline]
+    fn as_raw_handle(&self) -> RawHandle {
+        self.file().as_raw_handle()
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for SharedMemory {
+    #[inline]
+    fn as_raw_fd(&self) -> RawFd {
+        self.file().as_raw_fd()
+    }
+}
+#[cfg(unix)]
+impl IntoRawFd for SharedMemory {
+    #[inline]
+    fn into_raw_fd(self) -> RawFd {
+        self.into_std().into_raw_fd()
+    }
+}
@@ -0,0 +1,180 @@
+use futures::{Poll, Future, Async, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use pool_registry;
+use sys::{long_path, retry};
+
+/// Количество записей, читаемых за одно обращение к пулу потоков
+static DEFAULT_READ_DIR_BATCH_SIZE: usize = 256;
+
+
+// DirEntry
+
+/// Запись каталога, прочитанная `ReadDir`
+#[derive(Clone)]
+pub struct DirEntry {
+    cpu_pool: &'static CpuPool,
+    path: PathBuf,
+    file_name: OsString,
+    file_type: std::fs::FileType,
+}
+impl DirEntry {
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    #[inline]
+    pub fn into_path(self) -> PathBuf {
+        self.path
+    }
+
+    #[inline]
+    pub fn file_name(&self) -> &OsString {
+        &self.file_name
+    }
+
+    /// Тип записи, полученный при чтении каталога (символические ссылки не разыменовываются)
+    #[inline]
+    pub fn file_type(&self) -> std::fs::FileType {
+        self.file_type
+    }
+
+    /// Запрашивает метаданные записи в пуле потоков (символические ссылки не разыменовываются)
+    pub fn metadata(&self) -> CpuFuture<std::fs::Metadata, std::io::Error> {
+        let path = self.path.clone();
+        self.cpu_pool.spawn_fn(move || retry(|| std::fs::symlink_metadata(long_path(&path))))
+    }
+}
+impl std::fmt::Debug for DirEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DirEntry")
+            .field("path", &self.path)
+            .field("file_type", &self.file_type)
+            .finish()
+    }
+}
+
+
+// ReadDir
+
+struct ReadDirState {
+    path: Option<PathBuf>,
+    dir: Option<std::fs::ReadDir>,
+}
+impl ReadDirState {
+    fn read_batch(&mut self, cpu_pool: &'static CpuPool, batch_size: usize) -> VecDeque<std::io::Result<DirEntry>> {
+        let mut entries = VecDeque::with_capacity(batch_size);
+        if let Some(path) = self.path.take() {
+            match retry(|| std::fs::read_dir(long_path(&path))) {
+                Ok(dir) => self.dir = Some(dir),
+                Err(error) => entries.push_back(Err(error)),
+            }
+        }
+        while entries.len() < batch_size {
+            let entry = match self.dir.as_mut().and_then(|dir| dir.next()) {
+                Some(entry) => entry,
+                None => {
+                    self.dir = None;
+                    break;
+                },
+            };
+            entries.push_back(entry.and_then(|entry| Ok(DirEntry {
+                cpu_pool,
+                path: entry.path(),
+                file_name: entry.file_name(),
+                file_type: entry.file_type()?,
+            })));
+        }
+        entries
+    }
+}
+
+/// Пакет записей, прочитанный в пуле потоков, вместе с состоянием чтения
+type ReadDirBatch = (ReadDirState, VecDeque<std::io::Result<DirEntry>>);
+
+/// Поток записей каталога (без `.` и `..`, в порядке, возвращаемом системой).
+///
+/// Записи читаются в пуле потоков пакетами, поэтому перечисление больших каталогов
+/// не блокирует вызывающий поток. Ошибки чтения отдельных записей передаются как ошибки потока,
+/// после чего чтение можно продолжить.
+pub struct ReadDir {
+    cpu_pool: &'static CpuPool,
+    batch_size: usize,
+    state: Option<ReadDirState>,
+    reading: Option<CpuFuture<ReadDirBatch, std::io::Error>>,
+    entries: VecDeque<std::io::Result<DirEntry>>,
+}
+
+/// Читает содержимое каталога `path` в пуле потоков, выбранном по пути (см. `PoolRegistry`)
+#[inline]
+pub fn read_dir<P: AsRef<Path>>(path: P) -> ReadDir {
+    read_dir_with_pool(pool_registry::pool_for(path.as_ref()), path)
+}
+
+pub fn read_dir_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> ReadDir {
+    ReadDir {
+        cpu_pool,
+        batch_size: DEFAULT_READ_DIR_BATCH_SIZE,
+        state: Some(ReadDirState {
+            path: Some(path.as_ref().into()),
+            dir: None,
+        }),
+        reading: None,
+        entries: VecDeque::new(),
+    }
+}
+
+impl ReadDir {
+
+    /// Задает количество записей, читаемых за одно обращение к пулу потоков
+    #[inline]
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch size must be greater than zero");
+        self.batch_size = batch_size;
+        self
+    }
+}
+impl Stream for ReadDir {
+    type Item = DirEntry;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(entry) = self.entries.pop_front() {
+                return entry.map(|entry| Async::Ready(Some(entry)));
+            }
+            if let Some(ref mut reading) = self.reading {
+                let (state, entries) = try_ready!(reading.poll());
+                self.state = Some(state);
+                self.entries = entries;
+            }
+            self.reading = None;
+            if !self.entries.is_empty() {
+                continue;
+            }
+            match self.state.take() {
+                Some(ref state) if state.path.is_none() && state.dir.is_none() => return Ok(Async::Ready(None)),
+                Some(mut state) => {
+                    let cpu_pool = self.cpu_pool;
+                    let batch_size = self.batch_size;
+                    self.reading = Some(cpu_pool.spawn_fn(move || {
+                        let entries = state.read_batch(cpu_pool, batch_size);
+                        Ok((state, entries))
+                    }));
+                },
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+impl std::fmt::Debug for ReadDir {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ReadDir")
+            .field("batch_size", &self.batch_size)
+            .finish()
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::ops::{Deref, DerefMut};
+
+/// Что осталось от операции записи, завершившейся ошибкой
+struct Salvaged {
+    file: Option<std::fs::File>,
+    /// Неудачная запись `direct_io`: объем данных в буфере и уже учтенное в `written` начало
+    direct: Option<(usize, usize)>,
+}
+
+/// Ход последовательной записи `AsyncFileWrite`, общий для обертки и ее операций в пуле потоков
+/// (см. `AsyncFileWrite::written`, `AsyncFileWrite::recover`)
+pub struct WriteProgress {
+    written: AtomicU64,
+    synced: AtomicU64,
+    salvaged: Mutex<Salvaged>,
+}
+impl WriteProgress {
+
+    pub fn new() -> Arc<WriteProgress> {
+        Arc::new(WriteProgress {
+            written: AtomicU64::new(0),
+            synced: AtomicU64::new(0),
+            salvaged: Mutex::new(Salvaged {
+                file: None,
+                direct: None,
+            }),
+        })
+    }
+
+    #[inline]
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    pub fn synced(&self) -> u64 {
+        self.synced.load(Ordering::SeqCst)
+    }
+
+    #[inline]
+    pub fn add_written(&self, size: usize) -> usize {
+        self.written.fetch_add(size as u64, Ordering::SeqCst);
+        size
+    }
+
+    /// Все записанное сброшено на диск
+    #[inline]
+    pub fn mark_synced(&self) {
+        self.synced.store(self.written(), Ordering::SeqCst);
+    }
+
+    /// Сохраняет сведения о неудачной записи `direct_io` (см. `DirectBuffer::restore`)
+    pub fn retain_direct(&self, filled: usize, counted: usize) {
+        self.salvaged.lock().unwrap().direct = Some((filled, counted));
+    }
+
+    /// Забирает файл, сохраненный операцией при ошибке, и сведения о неудачной записи `direct_io`
+    pub fn take_salvaged(&self) -> Option<(std::fs::File, Option<(usize, usize)>)> {
+        let mut salvaged = self.salvaged.lock().unwrap();
+        let file = salvaged.file.take()?;
+        Some((file, salvaged.direct.take()))
+    }
+}
+
+
+// FileGuard
+
+/// Файл операции в пуле потоков: если операция завершилась ошибкой (или паникой) и не вернула
+/// файл (`into_inner`), он сохраняется в `WriteProgress` вместо закрытия
+pub struct FileGuard {
+    file: Option<std::fs::File>,
+    progress: Arc<WriteProgress>,
+}
+impl FileGuard {
+
+    pub fn new(progress: &Arc<WriteProgress>, file: std::fs::File) -> FileGuard {
+        FileGuard {
+            file: Some(file),
+            progress: progress.clone(),
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(mut self) -> std::fs::File {
+        self.file.take().unwrap()
+    }
+}
+impl Deref for FileGuard {
+    type Target = std::fs::File;
+
+    #[inline]
+    fn deref(&self) -> &std::fs::File {
+        self.file.as_ref().unwrap()
+    }
+}
+impl DerefMut for FileGuard {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut std::fs::File {
+        self.file.as_mut().unwrap()
+    }
+}
+impl Drop for FileGuard {
+    fn drop(&mut self) {
+        if let Some(file) = self.file.take() {
+            self.progress.salvaged.lock().unwrap().file = Some(file);
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::DEFAULT_CPU_POOL;
+use sys::{self, long_path, retry};
+
+static SNAPSHOT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn unsupported_error() -> std::io::Error {
+    std::io::Error::other("file system does not support reflink (copy-on-write) copies")
+}
+
+/// Клонирует `src` в новый файл `dst`; при неудаче созданный файл удаляется
+fn clone_to(src: &Path, dst: &Path) -> std::io::Result<std::fs::File> {
+    let source = retry(|| std::fs::File::open(long_path(src)))?;
+    let target = retry(|| std::fs::OpenOptions::new().read(true).write(true).create_new(true).open(long_path(dst)))?;
+    let result = sys::reflink(&source, &target)
+        .and_then(|cloned| if cloned { Ok(()) } else { Err(unsupported_error()) });
+    if let Err(error) = result {
+        drop(target);
+        let _ = std::fs::remove_file(long_path(dst));
+        return Err(error);
+    }
+    Ok(target)
+}
+
+/// Создает `dst` мгновенной копией `src` (reflink, `FICLONE`), разделяющей с ним блоки
+/// до первого изменения любого из файлов. Файл `dst` не должен существовать.
+///
+/// Поддерживается на Linux для файловых систем с copy-on-write (Btrfs, XFS, OCFS2 и др.)
+/// в пределах одной файловой системы; в остальных случаях возвращается ошибка
+/// с описанием причины, а данные не копируются.
+#[inline]
+pub fn clone_file<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> CpuFuture<(), std::io::Error> {
+    clone_file_with_pool(&DEFAULT_CPU_POOL, src, dst)
+}
+
+pub fn clone_file_with_pool<S: AsRef<Path>, D: AsRef<Path>>(cpu_pool: &'static CpuPool, src: S, dst: D) -> CpuFuture<(), std::io::Error> {
+    let src: PathBuf = src.as_ref().into();
+    let dst: PathBuf = dst.as_ref().into();
+    cpu_pool.spawn_fn(move || clone_to(&src, &dst).map(|_| ()))
+}
+
+/// Проверяет, поддерживает ли файловая система каталога `dir` reflink-копии,
+/// клонируя в нем временный файл
+#[inline]
+pub fn supports_reflink<P: AsRef<Path>>(dir: P) -> CpuFuture<bool, std::io::Error> {
+    supports_reflink_with_pool(&DEFAULT_CPU_POOL, dir)
+}
+
+pub fn supports_reflink_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, dir: P) -> CpuFuture<bool, std::io::Error> {
+    let dir: PathBuf = dir.as_ref().into();
+    cpu_pool.spawn_fn(move || {
+        let id = SNAPSHOT_COUNTER.fetch_add(1, Ordering::SeqCst);
+        let src_path = dir.join(format!(".reflink-probe-{}-{}", std::process::id(), id));
+        let dst_path = dir.join(format!(".reflink-probe-{}-{}.clone", std::process::id(), id));
+        let result = (|| {
+            let source = retry(|| std::fs::OpenOptions::new().read(true).write(true).create_new(true).open(long_path(&src_path)))?;
+            let target = retry(|| std::fs::OpenOptions::new().write(true).create_new(true).open(long_path(&dst_path)))?;
+            sys::reflink(&source, &target)
+        })();
+        let _ = std::fs::remove_file(long_path(&src_path));
+        let _ = std::fs::remove_file(long_path(&dst_path));
+        result
+    })
+}
+
+
+// Snapshot
+
+/// Мгновенный снимок файла (reflink-копия), удаляемый вместе со структурой.
+///
+/// Снимок создается рядом с исходным файлом и не изменяется при последующей записи в исходный
+/// файл, поэтому из него можно прочитать согласованное состояние, не останавливая запись.
+pub struct Snapshot {
+    cpu_pool: &'static CpuPool,
+    path: PathBuf,
+    file: Option<std::fs::File>,
+}
+
+/// Создает снимок файла `path` (см. `clone_file`). Файл снимка открыт на чтение и запись.
+#[inline]
+pub fn snapshot<P: AsRef<Path>>(path: P) -> CpuFuture<Snapshot, std::io::Error> {
+    snapshot_with_pool(&DEFAULT_CPU_POOL, path)
+}
+
+pub fn snapshot_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<Snapshot, std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || {
+        let name = path.file_name()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name"))?;
+        let snapshot_path = path.with_file_name(format!(
+            ".{}.snapshot-{}-{}",
+            name.to_string_lossy(),
+            std::process::id(),
+            SNAPSHOT_COUNTER.fetch_add(1, Ordering::SeqCst),
+        ));
+        let file = clone_to(&path, &snapshot_path)?;
+        Ok(Snapshot {
+            cpu_pool,
+            path: snapshot_path,
+            file: Some(file),
+        })
+    })
+}
+
+impl Snapshot {
+
+    /// Путь к файлу снимка
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    #[inline]
+    pub fn file(&self) -> &std::fs::File {
+        self.file.as_ref().unwrap()
+    }
+
+    /// Копия дескриптора файла снимка, например, для `AsyncFileStream`.
+    /// Файл снимка удаляется вместе со структурой, но открытый дескриптор остается рабочим.
+    pub fn try_clone_file(&self) -> std::io::Result<std::fs::File> {
+        self.file().try_clone()
+    }
+}
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        drop(self.file.take());
+        let path = std::mem::replace(&mut self.path, PathBuf::new());
+        self.cpu_pool.spawn_fn(move || std::fs::remove_file(long_path(&path))).forget();
+    }
+}
+impl std::fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("path", &self.path)
+            .finish()
+    }
+}
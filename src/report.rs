@@ -0,0 +1,351 @@
+use futures::{Poll, Async, Stream};
+use futures_cpupool::CpuPool;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use super::DEFAULT_CPU_POOL;
+use walk::{walk_with_pool, WalkStream, WalkEntry};
+
+/// Итоги по файлам с одним расширением
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExtensionTotal {
+    pub files: u64,
+    pub size: u64,
+}
+
+/// Текущие итоги обхода дерева каталогов
+#[derive(Debug, Clone, Default)]
+pub struct DirStats {
+    pub files: u64,
+    pub dirs: u64,
+    pub symlinks: u64,
+    /// Суммарный размер файлов
+    pub size: u64,
+    /// Самый большой файл
+    pub largest: Option<WalkEntry>,
+}
+
+impl WalkStream {
+
+    /// Поток `n` самых больших файлов дерева (см. `LargestFiles`)
+    #[inline]
+    pub fn largest_files(self, n: usize) -> LargestFiles {
+        LargestFiles {
+            walk: self,
+            n,
+            top: Vec::with_capacity(n + 1),
+            snapshot: Snapshot::new(),
+        }
+    }
+
+    /// Поток файлов дерева, измененных раньше `cutoff`
+    #[inline]
+    pub fn older_than(self, cutoff: SystemTime) -> OlderThan {
+        OlderThan {
+            walk: self,
+            cutoff,
+        }
+    }
+
+    /// Поток путей файлов дерева, измененных позже `cutoff` (см. `ModifiedSince`)
+    #[inline]
+    pub fn modified_since(self, cutoff: SystemTime) -> ModifiedSince {
+        ModifiedSince {
+            walk: self,
+            cutoff,
+        }
+    }
+
+    /// Поток итогов по расширениям файлов дерева (см. `ExtensionTotals`)
+    #[inline]
+    pub fn extension_totals(self) -> ExtensionTotals {
+        ExtensionTotals {
+            walk: self,
+            totals: BTreeMap::new(),
+            snapshot: Snapshot::new(),
+        }
+    }
+
+    /// Поток текущих итогов обхода дерева (см. `DirStatsStream`)
+    #[inline]
+    pub fn dir_stats(self) -> DirStatsStream {
+        DirStatsStream {
+            walk: self,
+            stats: DirStats::default(),
+            snapshot: Snapshot::new(),
+        }
+    }
+}
+
+/// Поток путей файлов дерева каталогов `root`, измененных позже `cutoff` (см. `ModifiedSince`)
+#[inline]
+pub fn modified_since<P: AsRef<Path>>(root: P, cutoff: SystemTime) -> ModifiedSince {
+    modified_since_with_pool(&DEFAULT_CPU_POOL, root, cutoff)
+}
+
+pub fn modified_since_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, root: P, cutoff: SystemTime) -> ModifiedSince {
+    walk_with_pool(cpu_pool, root).modified_since(cutoff)
+}
+
+/// Поток текущих итогов обхода дерева каталогов `root` (см. `DirStatsStream`)
+#[inline]
+pub fn dir_stats<P: AsRef<Path>>(root: P) -> DirStatsStream {
+    dir_stats_with_pool(&DEFAULT_CPU_POOL, root)
+}
+
+pub fn dir_stats_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, root: P) -> DirStatsStream {
+    walk_with_pool(cpu_pool, root).dir_stats()
+}
+
+/// Определяет, когда следует отдать промежуточный результат:
+/// перед ожиданием очередного пакета записей, если результат изменился,
+/// и по окончании обхода, если итоговый результат еще не был отдан
+struct Snapshot {
+    changed: bool,
+    emitted: bool,
+    done: bool,
+}
+impl Snapshot {
+    fn new() -> Snapshot {
+        Snapshot {
+            changed: false,
+            emitted: false,
+            done: false,
+        }
+    }
+
+    /// Опрашивает обход, передавая записи в `add`; возвращает `true`, если нужно отдать результат
+    fn poll<F: FnMut(WalkEntry) -> bool>(&mut self, walk: &mut WalkStream, mut add: F) -> Poll<bool, std::io::Error> {
+        if self.done {
+            return Ok(Async::Ready(false));
+        }
+        loop {
+            match walk.poll()? {
+                Async::Ready(Some(entry)) => {
+                    if add(entry) {
+                        self.changed = true;
+                    }
+                },
+                Async::Ready(None) => {
+                    self.done = true;
+                    return Ok(Async::Ready(self.take() || !self.emitted));
+                },
+                Async::NotReady => {
+                    if self.take() {
+                        return Ok(Async::Ready(true));
+                    }
+                    return Ok(Async::NotReady);
+                },
+            }
+        }
+    }
+
+    fn take(&mut self) -> bool {
+        if self.changed {
+            self.changed = false;
+            self.emitted = true;
+            return true;
+        }
+        false
+    }
+}
+
+
+// LargestFiles
+
+/// Поток `n` самых больших файлов дерева, упорядоченных по убыванию размера.
+///
+/// Каждый элемент является текущим результатом, который отдается по мере изменения,
+/// не дожидаясь окончания обхода; последний элемент является итоговым результатом.
+pub struct LargestFiles {
+    walk: WalkStream,
+    n: usize,
+    top: Vec<WalkEntry>,
+    snapshot: Snapshot,
+}
+impl Stream for LargestFiles {
+    type Item = Vec<WalkEntry>;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let n = self.n;
+        let top = &mut self.top;
+        let ready = try_ready!(self.snapshot.poll(&mut self.walk, |entry| {
+            if !entry.metadata.is_file() {
+                return false;
+            }
+            let size = entry.metadata.len();
+            if top.len() == n && top.last().is_none_or(|last| last.metadata.len() >= size) {
+                return false;
+            }
+            let position = top.iter().position(|top| top.metadata.len() < size).unwrap_or(top.len());
+            top.insert(position, entry);
+            top.truncate(n);
+            true
+        }));
+        Ok(Async::Ready(if ready { Some(self.top.clone()) } else { None }))
+    }
+}
+impl std::fmt::Debug for LargestFiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("LargestFiles")
+            .field("n", &self.n)
+            .finish()
+    }
+}
+
+
+// OlderThan
+
+/// Поток файлов дерева, измененных раньше заданного момента времени
+pub struct OlderThan {
+    walk: WalkStream,
+    cutoff: SystemTime,
+}
+impl Stream for OlderThan {
+    type Item = WalkEntry;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            match try_ready!(self.walk.poll()) {
+                Some(entry) => {
+                    if entry.metadata.is_file() && entry.metadata.modified()? < self.cutoff {
+                        return Ok(Async::Ready(Some(entry)));
+                    }
+                },
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+impl std::fmt::Debug for OlderThan {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("OlderThan")
+            .field("cutoff", &self.cutoff)
+            .finish()
+    }
+}
+
+
+// ModifiedSince
+
+/// Поток путей файлов дерева, измененных позже заданного момента времени.
+///
+/// Метаданные читаются при обходе в пуле потоков пакетами (см. `WalkStream::batch_size`),
+/// поэтому поток подходит для периодического поиска новых файлов при инкрементальной
+/// обработке, дополняя наблюдение за изменениями.
+pub struct ModifiedSince {
+    walk: WalkStream,
+    cutoff: SystemTime,
+}
+impl Stream for ModifiedSince {
+    type Item = PathBuf;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            match try_ready!(self.walk.poll()) {
+                Some(entry) => {
+                    if entry.metadata.is_file() && entry.metadata.modified()? > self.cutoff {
+                        return Ok(Async::Ready(Some(entry.path)));
+                    }
+                },
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+impl std::fmt::Debug for ModifiedSince {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ModifiedSince")
+            .field("cutoff", &self.cutoff)
+            .finish()
+    }
+}
+
+
+// ExtensionTotals
+
+/// Поток итогов (количество и суммарный размер файлов) по расширениям файлов дерева.
+/// Файлы без расширения учитываются под ключом `None`.
+///
+/// Каждый элемент является текущим результатом, который отдается по мере изменения,
+/// не дожидаясь окончания обхода; последний элемент является итоговым результатом.
+pub struct ExtensionTotals {
+    walk: WalkStream,
+    totals: BTreeMap<Option<String>, ExtensionTotal>,
+    snapshot: Snapshot,
+}
+impl Stream for ExtensionTotals {
+    type Item = BTreeMap<Option<String>, ExtensionTotal>;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let totals = &mut self.totals;
+        let ready = try_ready!(self.snapshot.poll(&mut self.walk, |entry| {
+            if !entry.metadata.is_file() {
+                return false;
+            }
+            let extension = entry.path.extension().map(|extension| extension.to_string_lossy().into_owned());
+            let total = totals.entry(extension).or_default();
+            total.files += 1;
+            total.size += entry.metadata.len();
+            true
+        }));
+        Ok(Async::Ready(if ready { Some(self.totals.clone()) } else { None }))
+    }
+}
+impl std::fmt::Debug for ExtensionTotals {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ExtensionTotals")
+            .field("totals", &self.totals)
+            .finish()
+    }
+}
+
+
+// DirStatsStream
+
+/// Поток текущих итогов обхода дерева: количества файлов, каталогов и символических ссылок,
+/// суммарного размера файлов и самого большого файла.
+///
+/// Каждый элемент является текущим результатом, который отдается по мере изменения,
+/// не дожидаясь окончания обхода; последний элемент является итоговым результатом.
+pub struct DirStatsStream {
+    walk: WalkStream,
+    stats: DirStats,
+    snapshot: Snapshot,
+}
+impl Stream for DirStatsStream {
+    type Item = DirStats;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let stats = &mut self.stats;
+        let ready = try_ready!(self.snapshot.poll(&mut self.walk, |entry| {
+            let file_type = entry.metadata.file_type();
+            if file_type.is_dir() {
+                stats.dirs += 1;
+            } else if file_type.is_symlink() {
+                stats.symlinks += 1;
+            } else {
+                let size = entry.metadata.len();
+                stats.files += 1;
+                stats.size += size;
+                if stats.largest.as_ref().is_none_or(|largest| largest.metadata.len() < size) {
+                    stats.largest = Some(entry);
+                }
+            }
+            true
+        }));
+        Ok(Async::Ready(if ready { Some(self.stats.clone()) } else { None }))
+    }
+}
+impl std::fmt::Debug for DirStatsStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("DirStatsStream")
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
@@ -0,0 +1,270 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::ffi::CString;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use super::DEFAULT_CPU_POOL;
+use safe_path::normalize;
+
+#[cfg(target_os = "linux")]
+static SYS_OPENAT2: libc::c_long = 437;
+#[cfg(target_os = "linux")]
+static RESOLVE_NO_MAGICLINKS: u64 = 0x02;
+#[cfg(target_os = "linux")]
+static RESOLVE_NO_SYMLINKS: u64 = 0x04;
+#[cfg(target_os = "linux")]
+static RESOLVE_BENEATH: u64 = 0x08;
+
+/// Признак отсутствия `openat2` в ядре
+#[cfg(target_os = "linux")]
+static OPENAT2_UNSUPPORTED: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+#[cfg(target_os = "linux")]
+static METADATA_FLAGS: libc::c_int = libc::O_PATH;
+#[cfg(not(target_os = "linux"))]
+static METADATA_FLAGS: libc::c_int = libc::O_RDONLY | libc::O_NONBLOCK;
+
+fn to_cstring(path: &Path) -> std::io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "path contains a nul byte"))
+}
+
+/// Повторяет системный вызов, прерванный сигналом
+fn cvt<F: FnMut() -> libc::c_int>(mut f: F) -> std::io::Result<libc::c_int> {
+    loop {
+        let ret = f();
+        if ret != -1 {
+            return Ok(ret);
+        }
+        let error = std::io::Error::last_os_error();
+        if error.kind() != std::io::ErrorKind::Interrupted {
+            return Err(error);
+        }
+    }
+}
+
+fn openat(dirfd: RawFd, name: &Path, flags: libc::c_int, mode: libc::mode_t) -> std::io::Result<std::fs::File> {
+    let name = to_cstring(name)?;
+    let fd = cvt(|| unsafe {
+        libc::openat(dirfd, name.as_ptr(), flags | libc::O_NOFOLLOW | libc::O_CLOEXEC, mode as libc::c_uint)
+    })?;
+    Ok(unsafe { std::fs::File::from_raw_fd(fd) })
+}
+
+#[repr(C)]
+#[cfg(target_os = "linux")]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+/// Открывает путь относительно `dirfd` через `openat2` с `RESOLVE_BENEATH`;
+/// `None`, если ядро не поддерживает `openat2`
+#[cfg(target_os = "linux")]
+fn openat2(dirfd: RawFd, path: &Path, flags: libc::c_int, mode: libc::mode_t) -> Option<std::io::Result<std::fs::File>> {
+    use std::sync::atomic::Ordering;
+    if OPENAT2_UNSUPPORTED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let path = match to_cstring(path) {
+        Ok(path) => path,
+        Err(error) => return Some(Err(error)),
+    };
+    let how = OpenHow {
+        flags: (flags | libc::O_NOFOLLOW | libc::O_CLOEXEC) as u64,
+        mode: mode as u64,
+        resolve: RESOLVE_BENEATH | RESOLVE_NO_SYMLINKS | RESOLVE_NO_MAGICLINKS,
+    };
+    let result = cvt(|| unsafe {
+        libc::syscall(SYS_OPENAT2, dirfd, path.as_ptr(), &how as *const OpenHow, std::mem::size_of::<OpenHow>()) as libc::c_int
+    });
+    match result {
+        Ok(fd) => Some(Ok(unsafe { std::fs::File::from_raw_fd(fd) })),
+        Err(ref error) if error.raw_os_error() == Some(libc::ENOSYS) || error.raw_os_error() == Some(libc::EPERM) => {
+            OPENAT2_UNSUPPORTED.store(true, Ordering::Relaxed);
+            None
+        },
+        Err(error) => Some(Err(error)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn openat2(_dirfd: RawFd, _path: &Path, _flags: libc::c_int, _mode: libc::mode_t) -> Option<std::io::Result<std::fs::File>> {
+    None
+}
+
+/// Открывает нормализованный путь внутри каталога `dir`, не разыменовывая символические ссылки
+fn open_beneath(dir: &std::fs::File, components: &[PathBuf], flags: libc::c_int, mode: libc::mode_t) -> std::io::Result<std::fs::File> {
+    let (last, parents) = match components.split_last() {
+        Some(split) => split,
+        None => return openat(dir.as_raw_fd(), Path::new("."), flags, mode),
+    };
+    let path: PathBuf = components.iter().collect();
+    if let Some(result) = openat2(dir.as_raw_fd(), &path, flags, mode) {
+        return result;
+    }
+    // без `openat2` путь проходится по одному компоненту с `O_NOFOLLOW`
+    let mut parent: Option<std::fs::File> = None;
+    for component in parents {
+        let fd = parent.as_ref().unwrap_or(dir).as_raw_fd();
+        parent = Some(openat(fd, component, libc::O_RDONLY | libc::O_DIRECTORY, 0)?);
+    }
+    openat(parent.as_ref().unwrap_or(dir).as_raw_fd(), last, flags, mode)
+}
+
+fn checked_components(relative: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let components = normalize(relative)?;
+    if components.is_empty() {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "path refers to the root directory itself"));
+    }
+    Ok(components)
+}
+
+/// Открывает родительский каталог последнего компонента пути и возвращает его вместе с именем
+fn open_parent(dir: &std::fs::File, relative: &Path) -> std::io::Result<(std::fs::File, CString)> {
+    let components = checked_components(relative)?;
+    let (last, parents) = components.split_last().unwrap();
+    let parent = open_beneath(dir, parents, libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+    Ok((parent, to_cstring(last)?))
+}
+
+
+// RootDir
+
+/// Дескриптор каталога, все операции которого выполняются относительно него
+/// (`openat`, `mkdirat`, `unlinkat`).
+///
+/// Пути разрешаются только внутри каталога: абсолютные пути и выход через `..` отклоняются,
+/// а символические ссылки не разыменовываются (на Linux используется `openat2` с `RESOLVE_BENEATH`).
+/// В отличие от `SafePath`, проверка и открытие выполняются одним системным вызовом,
+/// поэтому подмена компонентов пути между ними невозможна.
+/// Все операции выполняются в пуле потоков.
+#[derive(Clone)]
+pub struct RootDir {
+    cpu_pool: &'static CpuPool,
+    dir: Arc<std::fs::File>,
+}
+impl RootDir {
+
+    /// Открывает каталог `path`
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> CpuFuture<RootDir, std::io::Error> {
+        Self::open_with_pool(&DEFAULT_CPU_POOL, path)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<RootDir, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let dir = std::fs::File::open(path)?;
+            if !dir.metadata()?.is_dir() {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "root is not a directory"));
+            }
+            Ok(RootDir::from_std(cpu_pool, dir))
+        })
+    }
+
+    /// Создает дескриптор из уже открытого каталога
+    #[inline]
+    pub fn from_std(cpu_pool: &'static CpuPool, dir: std::fs::File) -> RootDir {
+        RootDir {
+            cpu_pool,
+            dir: Arc::new(dir),
+        }
+    }
+
+    fn spawn<T, F>(&self, f: F) -> CpuFuture<T, std::io::Error>
+        where T: Send + 'static,
+              F: FnOnce(&std::fs::File) -> std::io::Result<T> + Send + 'static
+    {
+        let dir = self.dir.clone();
+        self.cpu_pool.spawn_fn(move || f(&dir))
+    }
+
+    /// Открывает файл на чтение
+    pub fn open_file<P: AsRef<Path>>(&self, relative: P) -> CpuFuture<std::fs::File, std::io::Error> {
+        let relative: PathBuf = relative.as_ref().into();
+        self.spawn(move |dir| open_beneath(dir, &checked_components(&relative)?, libc::O_RDONLY, 0))
+    }
+
+    /// Создает (или усекает) файл и открывает его на запись
+    pub fn create_file<P: AsRef<Path>>(&self, relative: P) -> CpuFuture<std::fs::File, std::io::Error> {
+        let relative: PathBuf = relative.as_ref().into();
+        self.spawn(move |dir| {
+            let flags = libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC;
+            open_beneath(dir, &checked_components(&relative)?, flags, 0o666)
+        })
+    }
+
+    /// Читает содержимое файла целиком
+    pub fn read<P: AsRef<Path>>(&self, relative: P) -> CpuFuture<Bytes, std::io::Error> {
+        let relative: PathBuf = relative.as_ref().into();
+        self.spawn(move |dir| {
+            let mut file = open_beneath(dir, &checked_components(&relative)?, libc::O_RDONLY, 0)?;
+            let mut buf = Vec::new();
+            file.read_to_end(&mut buf)?;
+            Ok(Bytes::from(buf))
+        })
+    }
+
+    /// Метаданные записи (символическая ссылка в конце пути не разыменовывается)
+    pub fn metadata<P: AsRef<Path>>(&self, relative: P) -> CpuFuture<std::fs::Metadata, std::io::Error> {
+        let relative: PathBuf = relative.as_ref().into();
+        self.spawn(move |dir| {
+            let components = normalize(&relative)?;
+            open_beneath(dir, &components, METADATA_FLAGS, 0)?.metadata()
+        })
+    }
+
+    /// Открывает вложенный каталог как отдельный дескриптор
+    pub fn open_dir<P: AsRef<Path>>(&self, relative: P) -> CpuFuture<RootDir, std::io::Error> {
+        let relative: PathBuf = relative.as_ref().into();
+        let cpu_pool = self.cpu_pool;
+        self.spawn(move |dir| {
+            let dir = open_beneath(dir, &normalize(&relative)?, libc::O_RDONLY | libc::O_DIRECTORY, 0)?;
+            Ok(RootDir::from_std(cpu_pool, dir))
+        })
+    }
+
+    /// Создает каталог
+    pub fn create_dir<P: AsRef<Path>>(&self, relative: P) -> CpuFuture<(), std::io::Error> {
+        let relative: PathBuf = relative.as_ref().into();
+        self.spawn(move |dir| {
+            let (parent, name) = open_parent(dir, &relative)?;
+            cvt(|| unsafe { libc::mkdirat(parent.as_raw_fd(), name.as_ptr(), 0o777) }).map(|_| ())
+        })
+    }
+
+    /// Удаляет файл
+    pub fn remove_file<P: AsRef<Path>>(&self, relative: P) -> CpuFuture<(), std::io::Error> {
+        let relative: PathBuf = relative.as_ref().into();
+        self.spawn(move |dir| {
+            let (parent, name) = open_parent(dir, &relative)?;
+            cvt(|| unsafe { libc::unlinkat(parent.as_raw_fd(), name.as_ptr(), 0) }).map(|_| ())
+        })
+    }
+
+    /// Удаляет пустой каталог
+    pub fn remove_dir<P: AsRef<Path>>(&self, relative: P) -> CpuFuture<(), std::io::Error> {
+        let relative: PathBuf = relative.as_ref().into();
+        self.spawn(move |dir| {
+            let (parent, name) = open_parent(dir, &relative)?;
+            cvt(|| unsafe { libc::unlinkat(parent.as_raw_fd(), name.as_ptr(), libc::AT_REMOVEDIR) }).map(|_| ())
+        })
+    }
+}
+impl AsRawFd for RootDir {
+    fn as_raw_fd(&self) -> RawFd {
+        self.dir.as_raw_fd()
+    }
+}
+impl std::fmt::Debug for RootDir {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RootDir")
+            .field("fd", &self.dir.as_raw_fd())
+            .finish()
+    }
+}
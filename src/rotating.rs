@@ -0,0 +1,277 @@
+use futures::{Poll, Future, Async, AsyncSink, StartSend, Sink};
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use super::{AsyncFileSink, DEFAULT_CPU_POOL};
+use sys::{long_path, retry};
+
+fn already_closed() -> std::io::Error {
+    std::io::Error::other("`RotatingFileSink` already closed")
+}
+
+fn open_append(path: &Path) -> std::io::Result<std::fs::File> {
+    retry(|| std::fs::OpenOptions::new().append(true).create(true).open(long_path(path)))
+}
+
+/// Время в UTC в виде `YYYYMMDD-HHMMSS`: имена ротированных файлов упорядочены по времени
+fn timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+    let (days, rest) = (seconds / 86400, seconds % 86400);
+    // преобразование дней от 1970-01-01 в дату григорианского календаря
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, rest / 3600, rest / 60 % 60, rest % 60)
+}
+
+/// Разбирает имя ротированного файла `<name>.<timestamp>[.<n>]`
+fn rotated_key(name: &str, file_name: &str) -> Option<(String, u64)> {
+    if !file_name.starts_with(name) || file_name.as_bytes().get(name.len()) != Some(&b'.') {
+        return None;
+    }
+    let suffix = &file_name[name.len() + 1..];
+    let (stamp, counter) = match suffix.find('.') {
+        Some(index) => (&suffix[..index], suffix[index + 1..].parse().ok()?),
+        None => (suffix, 0),
+    };
+    let bytes = stamp.as_bytes();
+    if bytes.len() != 15 || bytes[8] != b'-' || !bytes.iter().enumerate().all(|(i, byte)| i == 8 || byte.is_ascii_digit()) {
+        return None;
+    }
+    Some((stamp.to_owned(), counter))
+}
+
+/// Ротированные файлы `name` в каталоге `dir` с ключами упорядочивания
+fn list_rotated(dir: &Path, name: &str) -> std::io::Result<Vec<((String, u64), PathBuf)>> {
+    let mut rotated = Vec::new();
+    for entry in std::fs::read_dir(long_path(dir))? {
+        let entry = entry?;
+        if let Some(key) = rotated_key(name, &entry.file_name().to_string_lossy()) {
+            rotated.push((key, entry.path()));
+        }
+    }
+    rotated.sort();
+    Ok(rotated)
+}
+
+/// Переименовывает текущий файл, удаляет лишние старые файлы и открывает новый
+fn rotate(path: &Path, keep: Option<usize>) -> std::io::Result<std::fs::File> {
+    let name = path.file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name"))?
+        .to_string_lossy()
+        .into_owned();
+    let dir = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+    let mut old = list_rotated(dir, &name)?;
+    let stamp = timestamp(SystemTime::now());
+    // несколько ротаций за секунду различаются возрастающим номером
+    let counter = old.iter()
+        .filter(|&&((ref old_stamp, _), _)| *old_stamp >= stamp)
+        .map(|&((_, counter), _)| counter + 1)
+        .max();
+    let rotated = match counter {
+        Some(counter) => path.with_file_name(format!("{}.{}.{}", name, stamp, counter)),
+        None => path.with_file_name(format!("{}.{}", name, stamp)),
+    };
+    match std::fs::rename(long_path(path), long_path(&rotated)) {
+        // файл мог быть удален или перемещен извне
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => {},
+        Ok(()) => old.push(((stamp, counter.unwrap_or(0)), rotated)),
+        Err(error) => return Err(error),
+    }
+    if let Some(keep) = keep {
+        if old.len() > keep {
+            for (_, old_path) in &old[..old.len() - keep] {
+                match std::fs::remove_file(long_path(old_path)) {
+                    Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => {},
+                    result => result?,
+                }
+            }
+        }
+    }
+    open_append(path)
+}
+
+// RotatingFileSink
+
+enum RotatingState {
+    Writing(AsyncFileSink),
+    /// Запись принятых элементов и закрытие файла перед ротацией
+    Closing(AsyncFileSink),
+    Rotating(CpuFuture<std::fs::File, std::io::Error>),
+    Closed,
+}
+
+/// Дозапись в файл с ротацией по размеру или времени, например, для журналов.
+///
+/// Когда очередной элемент не помещается в `max_size` или с открытия файла прошло `max_age`,
+/// принятые элементы записываются, файл закрывается и переименовывается в `<name>.<время UTC>`
+/// (`app.log.20261016-120000`), после чего открывается новый файл `path`. Из ротированных
+/// файлов остаются `keep` последних. Переименование, удаление и открытие выполняются
+/// в пуле потоков; во время ротации `start_send` возвращает `NotReady`.
+///
+/// Элемент не разделяется между файлами, поэтому элемент больше `max_size` записывается
+/// в отдельный файл целиком.
+pub struct RotatingFileSink {
+    cpu_pool: &'static CpuPool,
+    path: PathBuf,
+    state: RotatingState,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    keep: Option<usize>,
+    /// Размер текущего файла
+    written: u64,
+    opened: Instant,
+}
+impl RotatingFileSink {
+
+    /// Открывает файл `path` на дозапись (создавая его при необходимости) в пуле потоков
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> CpuFuture<RotatingFileSink, std::io::Error> {
+        Self::open_with_pool(&DEFAULT_CPU_POOL, path)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<RotatingFileSink, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let file = open_append(&path)?;
+            let written = file.metadata()?.len();
+            Ok(RotatingFileSink {
+                cpu_pool,
+                path,
+                state: RotatingState::Writing(AsyncFileSink::from_std(cpu_pool, file)),
+                max_size: None,
+                max_age: None,
+                keep: None,
+                written,
+                opened: Instant::now(),
+            })
+        })
+    }
+
+    /// Ротация, когда размер файла превысил бы `max_size` байт
+    #[inline]
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    /// Ротация, когда с открытия файла прошло `max_age` (проверяется при записи элемента)
+    #[inline]
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Сколько ротированных файлов хранить; более старые удаляются при ротации.
+    /// По умолчанию удаления нет.
+    #[inline]
+    pub fn keep(mut self, keep: usize) -> Self {
+        self.keep = Some(keep);
+        self
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn rotation_due(&self, len: usize) -> bool {
+        if self.written == 0 {
+            return false;
+        }
+        self.max_size.is_some_and(|max_size| self.written + len as u64 > max_size)
+            || self.max_age.is_some_and(|max_age| self.opened.elapsed() >= max_age)
+    }
+
+    /// Продолжает начатую ротацию
+    fn poll_rotation(&mut self) -> Poll<(), std::io::Error> {
+        loop {
+            self.state = match self.state {
+                RotatingState::Writing(_) => return Ok(Async::Ready(())),
+                RotatingState::Closing(ref mut sink) => {
+                    try_ready!(sink.poll_close());
+                    let path = self.path.clone();
+                    let keep = self.keep;
+                    RotatingState::Rotating(self.cpu_pool.spawn_fn(move || rotate(&path, keep)))
+                },
+                RotatingState::Rotating(ref mut future) => {
+                    let file = try_ready!(future.poll());
+                    self.written = 0;
+                    self.opened = Instant::now();
+                    RotatingState::Writing(AsyncFileSink::from_std(self.cpu_pool, file))
+                },
+                RotatingState::Closed => return Err(already_closed()),
+            };
+        }
+    }
+}
+impl Sink for RotatingFileSink {
+    type SinkItem = Bytes;
+    type SinkError = std::io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if let Async::NotReady = self.poll_rotation()? {
+            return Ok(AsyncSink::NotReady(item));
+        }
+        if self.rotation_due(item.len()) {
+            if let RotatingState::Writing(sink) = std::mem::replace(&mut self.state, RotatingState::Closed) {
+                self.state = RotatingState::Closing(sink);
+            }
+            if let Async::NotReady = self.poll_rotation()? {
+                return Ok(AsyncSink::NotReady(item));
+            }
+        }
+        let len = item.len() as u64;
+        match self.state {
+            RotatingState::Writing(ref mut sink) => {
+                let result = sink.start_send(item)?;
+                if let AsyncSink::Ready = result {
+                    self.written += len;
+                }
+                Ok(result)
+            },
+            _ => Err(already_closed()),
+        }
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        try_ready!(self.poll_rotation());
+        match self.state {
+            RotatingState::Writing(ref mut sink) => sink.poll_complete(),
+            _ => Err(already_closed()),
+        }
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        if let RotatingState::Closed = self.state {
+            return Ok(Async::Ready(()));
+        }
+        try_ready!(self.poll_rotation());
+        if let RotatingState::Writing(ref mut sink) = self.state {
+            try_ready!(sink.poll_close());
+        }
+        self.state = RotatingState::Closed;
+        Ok(Async::Ready(()))
+    }
+}
+impl std::fmt::Debug for RotatingFileSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RotatingFileSink")
+            .field("path", &self.path)
+            .field("max_size", &self.max_size)
+            .field("max_age", &self.max_age)
+            .field("keep", &self.keep)
+            .field("written", &self.written)
+            .finish()
+    }
+}
@@ -0,0 +1,114 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::path::{Component, Path, PathBuf};
+use super::DEFAULT_CPU_POOL;
+use sys::long_path;
+
+fn escape_error() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::PermissionDenied, "path escapes the root directory")
+}
+
+/// Лексически нормализует недоверенный относительный путь, не допуская выхода за корень
+pub fn normalize(untrusted: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut components = Vec::new();
+    for component in untrusted.components() {
+        match component {
+            Component::Normal(name) => components.push(PathBuf::from(name)),
+            Component::CurDir => {},
+            Component::ParentDir => {
+                if components.pop().is_none() {
+                    return Err(escape_error());
+                }
+            },
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "absolute path is not allowed"));
+            },
+        }
+    }
+    Ok(components)
+}
+
+
+// SafePath
+
+/// Путь, гарантированно находящийся внутри корневого каталога.
+///
+/// Получается только через `SafePath::resolve`, поэтому может безопасно
+/// передаваться в функции открытия файлов (`AsRef<Path>`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SafePath {
+    root: PathBuf,
+    path: PathBuf,
+}
+impl SafePath {
+
+    /// Разрешает недоверенный относительный путь `untrusted` внутри корня `root`.
+    ///
+    /// Абсолютные пути и выход за корень через `..` отклоняются сразу, а существующая часть пути
+    /// канонизируется в пуле потоков с разыменованием символических ссылок и проверяется на то,
+    /// что она не указывает за пределы корня. Несуществующий "хвост" пути допускается
+    /// (например, для создания файла).
+    #[inline]
+    pub fn resolve<R: AsRef<Path>, P: AsRef<Path>>(root: R, untrusted: P) -> CpuFuture<SafePath, std::io::Error> {
+        Self::resolve_with_pool(&DEFAULT_CPU_POOL, root, untrusted)
+    }
+
+    pub fn resolve_with_pool<R: AsRef<Path>, P: AsRef<Path>>(cpu_pool: &'static CpuPool, root: R, untrusted: P) -> CpuFuture<SafePath, std::io::Error> {
+        let root: PathBuf = root.as_ref().into();
+        let components = normalize(untrusted.as_ref());
+        cpu_pool.spawn_fn(move || {
+            let components = components?;
+            let root = long_path(&root).canonicalize()?;
+            let mut path = root.clone();
+            let mut components = components.into_iter();
+            for component in components.by_ref() {
+                let candidate = path.join(&component);
+                match long_path(&candidate).canonicalize() {
+                    Ok(canonical) => {
+                        if !canonical.starts_with(&root) {
+                            return Err(escape_error());
+                        }
+                        path = canonical;
+                    },
+                    Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => {
+                        // висячая символическая ссылка может указывать за пределы корня
+                        if std::fs::symlink_metadata(long_path(&candidate)).is_ok() {
+                            return Err(escape_error());
+                        }
+                        path = candidate;
+                        break;
+                    },
+                    Err(error) => return Err(error),
+                }
+            }
+            path.extend(components);
+            Ok(SafePath { root, path })
+        })
+    }
+
+    /// Канонический путь корневого каталога
+    #[inline]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Путь относительно корневого каталога
+    #[inline]
+    pub fn relative(&self) -> &Path {
+        self.path.strip_prefix(&self.root).unwrap_or(&self.path)
+    }
+
+    #[inline]
+    pub fn as_path(&self) -> &Path {
+        &self.path
+    }
+
+    #[inline]
+    pub fn into_path_buf(self) -> PathBuf {
+        self.path
+    }
+}
+impl AsRef<Path> for SafePath {
+    fn as_ref(&self) -> &Path {
+        &self.path
+    }
+}
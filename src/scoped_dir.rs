@@ -0,0 +1,114 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::DEFAULT_CPU_POOL;
+use sys::{long_path, retry};
+
+static SCOPED_DIR_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Создает новый каталог с уникальным именем `{prefix}{pid}-{n}-{nanos}` в `parent`
+fn create_unique(parent: &Path, prefix: &str) -> std::io::Result<PathBuf> {
+    loop {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.subsec_nanos())
+            .unwrap_or(0);
+        let path = parent.join(format!(
+            "{}{}-{}-{}",
+            prefix,
+            std::process::id(),
+            SCOPED_DIR_COUNTER.fetch_add(1, Ordering::SeqCst),
+            nanos,
+        ));
+        match retry(|| std::fs::create_dir(long_path(&path))) {
+            Ok(()) => return Ok(path),
+            Err(ref error) if error.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+fn remove_all(path: &Path) -> std::io::Result<()> {
+    match std::fs::remove_dir_all(long_path(path)) {
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+
+// ScopedDir
+
+/// Временный каталог, который удаляется со всем содержимым вместе со структурой.
+///
+/// При удалении структуры каталог удаляется в пуле потоков без ожидания. Чтобы дождаться
+/// удаления (например, в конце теста), используется `cleanup`, а чтобы сохранить
+/// каталог - `keep`.
+pub struct ScopedDir {
+    cpu_pool: &'static CpuPool,
+    path: Option<PathBuf>,
+}
+impl ScopedDir {
+
+    /// Создает каталог с именем, начинающимся с `prefix`, во временном каталоге системы
+    #[inline]
+    pub fn create(prefix: &str) -> CpuFuture<ScopedDir, std::io::Error> {
+        Self::create_in_with_pool(&DEFAULT_CPU_POOL, std::env::temp_dir(), prefix)
+    }
+
+    pub fn create_with_pool(cpu_pool: &'static CpuPool, prefix: &str) -> CpuFuture<ScopedDir, std::io::Error> {
+        Self::create_in_with_pool(cpu_pool, std::env::temp_dir(), prefix)
+    }
+
+    /// Создает каталог с именем, начинающимся с `prefix`, в каталоге `parent`
+    #[inline]
+    pub fn create_in<P: AsRef<Path>>(parent: P, prefix: &str) -> CpuFuture<ScopedDir, std::io::Error> {
+        Self::create_in_with_pool(&DEFAULT_CPU_POOL, parent, prefix)
+    }
+
+    pub fn create_in_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, parent: P, prefix: &str) -> CpuFuture<ScopedDir, std::io::Error> {
+        let parent: PathBuf = parent.as_ref().into();
+        let prefix = prefix.to_string();
+        cpu_pool.spawn_fn(move || {
+            let path = create_unique(&parent, &prefix)?;
+            Ok(ScopedDir {
+                cpu_pool,
+                path: Some(path),
+            })
+        })
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        self.path.as_ref().unwrap()
+    }
+
+    /// Удаляет каталог со всем содержимым; future завершается после удаления
+    pub fn cleanup(mut self) -> CpuFuture<(), std::io::Error> {
+        let path = self.path.take().unwrap();
+        self.cpu_pool.spawn_fn(move || remove_all(&path))
+    }
+
+    /// Отменяет удаление каталога и возвращает путь к нему
+    pub fn keep(mut self) -> PathBuf {
+        self.path.take().unwrap()
+    }
+}
+impl AsRef<Path> for ScopedDir {
+    fn as_ref(&self) -> &Path {
+        self.path()
+    }
+}
+impl Drop for ScopedDir {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            self.cpu_pool.spawn_fn(move || remove_all(&path)).forget();
+        }
+    }
+}
+impl std::fmt::Debug for ScopedDir {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ScopedDir")
+            .field("path", &self.path)
+            .finish()
+    }
+}
@@ -0,0 +1,336 @@
+use futures::{Poll, Future, Async, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use super::{DEFAULT_CPU_POOL, DEFAULT_BUFFER_SIZE};
+use timeout::Deadline;
+use sys::{long_path, retry, sync_all};
+
+static SCRUB_MANIFEST_HEADER: &str = "async_fs-scrub-manifest 1";
+
+/// Скорость чтения по умолчанию (байт в секунду)
+static DEFAULT_SCRUB_RATE: u64 = 16 * 1024 * 1024;
+
+/// Размер и контрольная сумма (CRC-32) содержимого файла
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScrubDigest {
+    pub size: u64,
+    pub crc32: u32,
+}
+
+fn file_digest(path: &Path) -> std::io::Result<ScrubDigest> {
+    let mut file = retry(|| std::fs::File::open(long_path(path)))?;
+    let mut hasher = crc32fast::Hasher::new();
+    let mut buf = vec![0u8; DEFAULT_BUFFER_SIZE];
+    let mut size = 0u64;
+    loop {
+        let read = retry(|| file.read(&mut buf))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+        size += read as u64;
+    }
+    Ok(ScrubDigest { size, crc32: hasher.finalize() })
+}
+
+fn collect_digests(root: &Path, dir: &Path, entries: &mut BTreeMap<PathBuf, ScrubDigest>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(long_path(dir))? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        if file_type.is_dir() {
+            collect_digests(root, &path, entries)?;
+        } else if file_type.is_file() {
+            let digest = file_digest(&path)?;
+            entries.insert(path.strip_prefix(root).unwrap_or(&path).to_path_buf(), digest);
+        }
+    }
+    Ok(())
+}
+
+
+// ScrubManifest
+
+/// Контрольные суммы файлов дерева каталогов (пути относительно корня дерева)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScrubManifest {
+    pub entries: BTreeMap<PathBuf, ScrubDigest>,
+}
+impl ScrubManifest {
+
+    /// Вычисляет контрольные суммы всех файлов дерева `root`
+    pub fn build<P: AsRef<Path>>(cpu_pool: &'static CpuPool, root: P) -> CpuFuture<ScrubManifest, std::io::Error> {
+        let root: PathBuf = root.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let mut manifest = ScrubManifest::default();
+            collect_digests(&root, &root, &mut manifest.entries)?;
+            Ok(manifest)
+        })
+    }
+
+    /// Загружает манифест из файла
+    pub fn load<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<ScrubManifest, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let mut data = String::new();
+            retry(|| std::fs::File::open(long_path(&path)))?.read_to_string(&mut data)?;
+            ScrubManifest::parse(&data)
+                .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed scrub manifest"))
+        })
+    }
+
+    /// Сохраняет манифест в файл
+    pub fn save<P: AsRef<Path>>(&self, cpu_pool: &'static CpuPool, path: P) -> CpuFuture<(), std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        let data = self.to_string();
+        cpu_pool.spawn_fn(move || {
+            let mut file = retry(|| std::fs::File::create(long_path(&path)))?;
+            file.write_all(data.as_bytes())?;
+            retry(|| sync_all(&file))
+        })
+    }
+
+    fn parse(data: &str) -> Option<ScrubManifest> {
+        let mut lines = data.lines();
+        if lines.next()? != SCRUB_MANIFEST_HEADER {
+            return None;
+        }
+        let entries = lines
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut fields = line.splitn(3, ' ');
+                let crc32 = u32::from_str_radix(fields.next()?, 16).ok()?;
+                let size = fields.next()?.parse().ok()?;
+                Some((fields.next()?.into(), ScrubDigest { size, crc32 }))
+            })
+            .collect::<Option<BTreeMap<_, _>>>()?;
+        Some(ScrubManifest { entries })
+    }
+}
+impl std::fmt::Display for ScrubManifest {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", SCRUB_MANIFEST_HEADER)?;
+        for (path, digest) in &self.entries {
+            writeln!(f, "{:08x} {} {}", digest.crc32, digest.size, path.display())?;
+        }
+        Ok(())
+    }
+}
+
+
+// ScrubReport
+
+/// Обнаруженное повреждение файла
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScrubProblem {
+    /// Файл отсутствует
+    Missing,
+    /// Размер файла не совпадает с манифестом
+    SizeMismatch { expected: u64, actual: u64 },
+    /// Содержимое файла не совпадает с контрольной суммой манифеста
+    ChecksumMismatch { expected: u32, actual: u32 },
+}
+impl std::fmt::Display for ScrubProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            ScrubProblem::Missing => write!(f, "file is missing"),
+            ScrubProblem::SizeMismatch { expected, actual } => write!(f, "size mismatch: expected {}, found {}", expected, actual),
+            ScrubProblem::ChecksumMismatch { expected, actual } => write!(f, "crc32 mismatch: expected {:08x}, found {:08x}", expected, actual),
+        }
+    }
+}
+
+/// Отчет о повреждении файла
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScrubReport {
+    pub path: PathBuf,
+    pub problem: ScrubProblem,
+}
+
+
+// Scrubber
+
+/// Проверяемый файл, передаваемый в пул потоков и обратно
+struct ScrubFile {
+    path: PathBuf,
+    expected: ScrubDigest,
+    file: Option<std::fs::File>,
+    hasher: crc32fast::Hasher,
+    size: u64,
+}
+
+enum ScrubStep {
+    /// Прочитан очередной блок файла
+    Read(ScrubFile, usize),
+    /// Проверка файла завершена
+    Done(Option<ScrubReport>),
+}
+
+impl ScrubFile {
+    fn step(mut self, chunk_size: usize) -> std::io::Result<ScrubStep> {
+        if self.file.is_none() {
+            match retry(|| std::fs::File::open(long_path(&self.path))) {
+                Ok(file) => self.file = Some(file),
+                Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => {
+                    return Ok(ScrubStep::Done(Some(ScrubReport { path: self.path, problem: ScrubProblem::Missing })));
+                },
+                Err(error) => return Err(error),
+            }
+        }
+        let mut buf = vec![0u8; chunk_size];
+        let read = {
+            let file = self.file.as_mut().unwrap();
+            retry(|| file.read(&mut buf))?
+        };
+        if read > 0 {
+            self.hasher.update(&buf[..read]);
+            self.size += read as u64;
+            return Ok(ScrubStep::Read(self, read));
+        }
+        let crc32 = self.hasher.finalize();
+        let problem = if self.size != self.expected.size {
+            Some(ScrubProblem::SizeMismatch { expected: self.expected.size, actual: self.size })
+        } else if crc32 != self.expected.crc32 {
+            Some(ScrubProblem::ChecksumMismatch { expected: self.expected.crc32, actual: crc32 })
+        } else {
+            None
+        };
+        let path = self.path;
+        Ok(ScrubStep::Done(problem.map(|problem| ScrubReport { path, problem })))
+    }
+}
+
+/// Фоновая проверка целостности файлов дерева каталогов по манифесту контрольных сумм
+/// (см. `ScrubManifest`), например, для обнаружения незаметной порчи данных в архивах.
+///
+/// Файлы манифеста перечитываются блоками в пуле потоков со скоростью не выше `rate`:
+/// между блоками поток ожидает, не занимая потоки пула. В поток передаются только отчеты
+/// о поврежденных и отсутствующих файлах; поток завершается после проверки всех файлов.
+/// Ошибка чтения файла передается как ошибка потока, после чего проверка продолжается
+/// со следующего файла.
+pub struct Scrubber {
+    cpu_pool: &'static CpuPool,
+    root: PathBuf,
+    files: VecDeque<(PathBuf, ScrubDigest)>,
+    rate: u64,
+    chunk_size: usize,
+    started: Option<Instant>,
+    /// Прочитано байт с начала проверки
+    scrubbed: u64,
+    reading: Option<CpuFuture<ScrubStep, std::io::Error>>,
+    waiting: Option<(ScrubFile, Deadline)>,
+}
+impl Scrubber {
+
+    #[inline]
+    pub fn new<P: AsRef<Path>>(root: P, manifest: ScrubManifest) -> Scrubber {
+        Self::new_with_pool(&DEFAULT_CPU_POOL, root, manifest)
+    }
+
+    pub fn new_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, root: P, manifest: ScrubManifest) -> Scrubber {
+        Scrubber {
+            cpu_pool,
+            root: root.as_ref().into(),
+            files: manifest.entries.into_iter().collect(),
+            rate: DEFAULT_SCRUB_RATE,
+            chunk_size: DEFAULT_BUFFER_SIZE,
+            started: None,
+            scrubbed: 0,
+            reading: None,
+            waiting: None,
+        }
+    }
+
+    /// Задает максимальную скорость чтения в байтах в секунду
+    #[inline]
+    pub fn rate(mut self, rate: u64) -> Self {
+        assert!(rate > 0, "rate must be greater than zero");
+        self.rate = rate;
+        self
+    }
+
+    /// Задает размер блока чтения
+    #[inline]
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0, "chunk size must be greater than zero");
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Сколько файлов осталось проверить (не считая проверяемого)
+    #[inline]
+    pub fn remaining(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Сколько байт прочитано с начала проверки
+    #[inline]
+    pub fn scrubbed(&self) -> u64 {
+        self.scrubbed
+    }
+
+    fn spawn(&mut self, file: ScrubFile) {
+        let chunk_size = self.chunk_size;
+        self.reading = Some(self.cpu_pool.spawn_fn(move || file.step(chunk_size)));
+    }
+}
+impl Stream for Scrubber {
+    type Item = ScrubReport;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some((file, mut deadline)) = self.waiting.take() {
+                if !deadline.poll_elapsed() {
+                    self.waiting = Some((file, deadline));
+                    return Ok(Async::NotReady);
+                }
+                self.spawn(file);
+            }
+            if let Some(ref mut reading) = self.reading {
+                let result = reading.poll();
+                if let Ok(Async::NotReady) = result {
+                    return Ok(Async::NotReady);
+                }
+                self.reading = None;
+                match result? {
+                    Async::Ready(ScrubStep::Read(file, read)) => {
+                        self.scrubbed += read as u64;
+                        let started = *self.started.get_or_insert_with(Instant::now);
+                        let elapsed = Duration::from_millis(self.scrubbed * 1000 / self.rate);
+                        self.waiting = Some((file, Deadline::new(started + elapsed)));
+                        continue;
+                    },
+                    Async::Ready(ScrubStep::Done(Some(report))) => return Ok(Async::Ready(Some(report))),
+                    Async::Ready(ScrubStep::Done(None)) => {},
+                    Async::NotReady => unreachable!(),
+                }
+            }
+            let (path, expected) = match self.files.pop_front() {
+                Some(file) => file,
+                None => return Ok(Async::Ready(None)),
+            };
+            self.started.get_or_insert_with(Instant::now);
+            self.spawn(ScrubFile {
+                path: self.root.join(path),
+                expected,
+                file: None,
+                hasher: crc32fast::Hasher::new(),
+                size: 0,
+            });
+        }
+    }
+}
+impl std::fmt::Debug for Scrubber {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Scrubber")
+            .field("root", &self.root)
+            .field("rate", &self.rate)
+            .field("remaining", &self.remaining())
+            .field("scrubbed", &self.scrubbed)
+            .finish()
+    }
+}
@@ -0,0 +1,69 @@
+use futures::{Poll, Future, Async};
+use std::io::SeekFrom;
+use super::{AsyncFileWrite, AsyncFileRead, AsyncFileStream, AsyncFile};
+use executor::BlockingExecutor;
+
+/// Асинхронное перемещение позиции файла (аналог `std::io::Seek`)
+pub trait AsyncSeek {
+
+    /// Перемещает позицию. Вызывается повторно с тем же `pos`, пока не вернет `Ready`
+    /// с новой позицией, отсчитываемой от начала файла.
+    fn poll_seek(&mut self, pos: SeekFrom) -> Poll<u64, std::io::Error>;
+}
+impl<E: BlockingExecutor> AsyncSeek for AsyncFileWrite<E> {
+    #[inline]
+    fn poll_seek(&mut self, pos: SeekFrom) -> Poll<u64, std::io::Error> {
+        AsyncFileWrite::poll_seek(self, pos)
+    }
+}
+impl<E: BlockingExecutor> AsyncSeek for AsyncFileRead<E> {
+    #[inline]
+    fn poll_seek(&mut self, pos: SeekFrom) -> Poll<u64, std::io::Error> {
+        AsyncFileRead::poll_seek(self, pos)
+    }
+}
+impl<E: BlockingExecutor> AsyncSeek for AsyncFileStream<E> {
+    #[inline]
+    fn poll_seek(&mut self, pos: SeekFrom) -> Poll<u64, std::io::Error> {
+        AsyncFileStream::poll_seek(self, pos)
+    }
+}
+impl<E: BlockingExecutor> AsyncSeek for AsyncFile<E> {
+    #[inline]
+    fn poll_seek(&mut self, pos: SeekFrom) -> Poll<u64, std::io::Error> {
+        AsyncFile::poll_seek(self, pos)
+    }
+}
+
+
+// Seek
+
+/// Future перемещения позиции; результат - объект и новая позиция
+pub struct Seek<T> {
+    inner: Option<T>,
+    pos: SeekFrom,
+}
+impl<T> Seek<T> {
+    pub fn new(inner: T, pos: SeekFrom) -> Seek<T> {
+        Seek {
+            inner: Some(inner),
+            pos,
+        }
+    }
+}
+impl<T: AsyncSeek> Future for Seek<T> {
+    type Item = (T, u64);
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let position = try_ready!(self.inner.as_mut().expect("poll a Seek after it's done").poll_seek(self.pos));
+        Ok(Async::Ready((self.inner.take().unwrap(), position)))
+    }
+}
+impl<T> std::fmt::Debug for Seek<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Seek")
+            .field("pos", &self.pos)
+            .finish()
+    }
+}
@@ -0,0 +1,190 @@
+use futures::{Poll, Future, Async};
+use futures::sync::oneshot;
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::collections::VecDeque;
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use pool_registry;
+use sys::{self, long_path, retry};
+
+type Job = Box<dyn FnOnce(&mut std::fs::File) + Send>;
+
+struct SerializedQueue {
+    jobs: VecDeque<Job>,
+    /// В пуле выполняется обработчик очереди
+    running: bool,
+}
+
+struct SerializedInner {
+    file: Mutex<std::fs::File>,
+    queue: Mutex<SerializedQueue>,
+}
+
+/// Выполняет операции очереди по одной, пока она не опустеет
+fn drain(inner: &SerializedInner) {
+    loop {
+        let job = {
+            let mut queue = inner.queue.lock().unwrap();
+            match queue.jobs.pop_front() {
+                Some(job) => job,
+                None => {
+                    queue.running = false;
+                    return;
+                },
+            }
+        };
+        job(&mut inner.file.lock().unwrap());
+    }
+}
+
+
+// SerializedFile
+
+/// Файл, операции над которым из разных задач выполняются в пуле потоков
+/// строго в порядке их передачи.
+///
+/// Каждая операция возвращает собственный future с ее результатом, при этом следующая
+/// операция начинается только после завершения предыдущей. Ошибка одной операции
+/// не отменяет последующие. Например, несколько задач могут дописывать записи в общий журнал,
+/// открытый в режиме дозаписи, и сбрасывать его на диск, не координируя друг друга.
+#[derive(Clone)]
+pub struct SerializedFile {
+    cpu_pool: &'static CpuPool,
+    inner: Arc<SerializedInner>,
+}
+impl SerializedFile {
+
+    /// Открывает файл на чтение и дозапись, создавая его при необходимости
+    #[inline]
+    pub fn open<P: AsRef<Path>>(path: P) -> CpuFuture<SerializedFile, std::io::Error> {
+        Self::open_with_pool(pool_registry::pool_for(path.as_ref()), path)
+    }
+
+    pub fn open_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<SerializedFile, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let file = retry(|| std::fs::OpenOptions::new().read(true).append(true).create(true).open(long_path(&path)))?;
+            Ok(SerializedFile::from_std(cpu_pool, file))
+        })
+    }
+
+    #[inline]
+    pub fn from_std(cpu_pool: &'static CpuPool, file: std::fs::File) -> SerializedFile {
+        SerializedFile {
+            cpu_pool,
+            inner: Arc::new(SerializedInner {
+                file: Mutex::new(file),
+                queue: Mutex::new(SerializedQueue {
+                    jobs: VecDeque::new(),
+                    running: false,
+                }),
+            }),
+        }
+    }
+
+    /// Ставит операцию в очередь. Операции выполняются в порядке вызова `submit`.
+    pub fn submit<T, F>(&self, f: F) -> SerializedOp<T>
+        where T: Send + 'static,
+              F: FnOnce(&mut std::fs::File) -> std::io::Result<T> + Send + 'static
+    {
+        let (sender, receiver) = oneshot::channel();
+        let job: Job = Box::new(move |file: &mut std::fs::File| {
+            // паника операции не должна останавливать очередь: ее future получит ошибку
+            if let Ok(result) = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(file))) {
+                let _ = sender.send(result);
+            }
+        });
+        let start = {
+            let mut queue = self.inner.queue.lock().unwrap();
+            queue.jobs.push_back(job);
+            !std::mem::replace(&mut queue.running, true)
+        };
+        if start {
+            let inner = self.inner.clone();
+            self.cpu_pool.spawn_fn(move || {
+                drain(&inner);
+                Ok::<(), ()>(())
+            }).forget();
+        }
+        SerializedOp { receiver }
+    }
+
+    /// Записывает данные целиком в текущую позицию (в конец файла в режиме дозаписи)
+    pub fn write(&self, data: Bytes) -> SerializedOp<()> {
+        self.submit(move |file| file.write_all(&data))
+    }
+
+    /// Записывает данные целиком по смещению `offset`, не изменяя позицию курсора файла
+    pub fn write_at(&self, offset: u64, data: Bytes) -> SerializedOp<()> {
+        self.submit(move |file| sys::write_all_at(file, &data, offset))
+    }
+
+    /// Читает до `len` байт по смещению `offset` (меньше - только в конце файла)
+    pub fn read_at(&self, offset: u64, len: usize) -> SerializedOp<Bytes> {
+        self.submit(move |file| {
+            let mut buf = vec![0u8; len];
+            let size = sys::read_full_at(file, &mut buf, offset)?;
+            buf.truncate(size);
+            Ok(Bytes::from(buf))
+        })
+    }
+
+    /// Читает файл целиком
+    pub fn read_to_end(&self) -> SerializedOp<Bytes> {
+        self.submit(|file| {
+            let mut buf = Vec::new();
+            file.seek(SeekFrom::Start(0))?;
+            file.read_to_end(&mut buf)?;
+            Ok(Bytes::from(buf))
+        })
+    }
+
+    /// Сбрасывает на диск данные файла
+    pub fn sync_data(&self) -> SerializedOp<()> {
+        self.submit(|file| retry(|| file.sync_data()))
+    }
+
+    /// Сбрасывает на диск данные и метаданные файла
+    pub fn sync_all(&self) -> SerializedOp<()> {
+        self.submit(|file| retry(|| sys::sync_all(file)))
+    }
+
+    /// Метаданные файла с учетом всех ранее переданных операций
+    pub fn metadata(&self) -> SerializedOp<std::fs::Metadata> {
+        self.submit(|file| file.metadata())
+    }
+}
+impl std::fmt::Debug for SerializedFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SerializedFile")
+            .field("queued", &self.inner.queue.lock().unwrap().jobs.len())
+            .finish()
+    }
+}
+
+
+// SerializedOp
+
+/// Результат операции `SerializedFile`
+pub struct SerializedOp<T> {
+    receiver: oneshot::Receiver<std::io::Result<T>>,
+}
+impl<T> Future for SerializedOp<T> {
+    type Item = T;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.receiver.poll() {
+            Ok(Async::Ready(result)) => result.map(Async::Ready),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(_) => Err(std::io::Error::other("serialized operation panicked")),
+        }
+    }
+}
+impl<T> std::fmt::Debug for SerializedOp<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SerializedOp").finish()
+    }
+}
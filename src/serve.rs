@@ -0,0 +1,244 @@
+use futures::{Poll, Future, Async, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use hyper::{Body, Response, StatusCode};
+use hyper::header::{self, HeaderValue};
+use hyper::http::response::Parts;
+use bytes::Bytes;
+use std::io::{Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use super::{AsyncFileStream, DEFAULT_CPU_POOL, DEFAULT_BUFFER_SIZE};
+use sys::long_path;
+
+
+// ServeFile
+
+struct Prepared {
+    parts: Parts,
+    content: Option<(std::fs::File, u64)>,
+}
+
+/// Future подготовки ответа для отдачи статического файла через hyper.
+///
+/// Результатом являются части ответа (статус и заголовки `ETag`, `Last-Modified`,
+/// `Content-Length`, `Content-Range`) и тело ответа, построенное поверх `AsyncFileStream`.
+/// Открытие файла и чтение его метаданных выполняются в пуле потоков.
+pub struct ServeFile {
+    cpu_pool: &'static CpuPool,
+    future: CpuFuture<Prepared, std::io::Error>,
+}
+
+/// Отдает файл с учетом заголовков `Range` и `If-Modified-Since`
+pub fn serve_file<P>(path: P, range: Option<HeaderValue>, if_modified_since: Option<HeaderValue>) -> ServeFile
+    where P: AsRef<Path>
+{
+    serve_file_with_pool(&DEFAULT_CPU_POOL, path, range, if_modified_since)
+}
+
+/// Отдает файл с учетом заголовков `Range` и `If-Modified-Since`, используя заданный пул потоков
+pub fn serve_file_with_pool<P>(
+    cpu_pool: &'static CpuPool,
+    path: P,
+    range: Option<HeaderValue>,
+    if_modified_since: Option<HeaderValue>,
+) -> ServeFile
+    where P: AsRef<Path>
+{
+    let path: PathBuf = path.as_ref().into();
+    ServeFile {
+        cpu_pool,
+        future: cpu_pool.spawn_fn(move || prepare(&path, range, if_modified_since)),
+    }
+}
+
+impl Future for ServeFile {
+    type Item = (Parts, Body);
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let prepared = try_ready!(self.future.poll());
+        let body = match prepared.content {
+            Some((file, len)) => Body::wrap_stream(RangeStream {
+                stream: AsyncFileStream::from_std(self.cpu_pool, file, DEFAULT_BUFFER_SIZE),
+                remaining: len,
+            }),
+            None => Body::empty(),
+        };
+        Ok(Async::Ready((prepared.parts, body)))
+    }
+}
+impl std::fmt::Debug for ServeFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("ServeFile").finish()
+    }
+}
+
+fn prepare(path: &Path, range: Option<HeaderValue>, if_modified_since: Option<HeaderValue>) -> std::io::Result<Prepared> {
+    let mut file = match std::fs::File::open(long_path(path)) {
+        Ok(file) => file,
+        Err(ref err) if err.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Prepared {
+                parts: build_parts(StatusCode::NOT_FOUND, &[]),
+                content: None,
+            });
+        },
+        Err(err) => return Err(err),
+    };
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return Ok(Prepared {
+            parts: build_parts(StatusCode::NOT_FOUND, &[]),
+            content: None,
+        });
+    }
+
+    let size = metadata.len();
+    // точность заголовка `Last-Modified` - секунды
+    let modified = metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| UNIX_EPOCH + std::time::Duration::from_secs(duration.as_secs()));
+    let etag = format!("\"{:x}-{:x}\"", modified.map_or(0, |modified| {
+        modified.duration_since(UNIX_EPOCH).unwrap().as_secs()
+    }), size);
+
+    let mut headers = vec![
+        (header::ETAG, etag),
+        (header::ACCEPT_RANGES, "bytes".to_string()),
+    ];
+    if let Some(modified) = modified {
+        headers.push((header::LAST_MODIFIED, httpdate::fmt_http_date(modified)));
+
+        let since = if_modified_since.as_ref()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| httpdate::parse_http_date(value).ok());
+        if let Some(since) = since {
+            if modified <= since {
+                return Ok(Prepared {
+                    parts: build_parts(StatusCode::NOT_MODIFIED, &headers),
+                    content: None,
+                });
+            }
+        }
+    }
+
+    let range = match range.as_ref().and_then(|value| value.to_str().ok()) {
+        Some(value) => match parse_range(value, size) {
+            Ok(range) => range,
+            Err(()) => {
+                headers.push((header::CONTENT_RANGE, format!("bytes */{}", size)));
+                return Ok(Prepared {
+                    parts: build_parts(StatusCode::RANGE_NOT_SATISFIABLE, &headers),
+                    content: None,
+                });
+            },
+        },
+        None => None,
+    };
+
+    let (status, offset, len) = match range {
+        Some((start, end)) => {
+            headers.push((header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, size)));
+            (StatusCode::PARTIAL_CONTENT, start, end + 1 - start)
+        },
+        None => (StatusCode::OK, 0, size),
+    };
+    headers.push((header::CONTENT_LENGTH, len.to_string()));
+
+    if offset > 0 {
+        file.seek(SeekFrom::Start(offset))?;
+    }
+    Ok(Prepared {
+        parts: build_parts(status, &headers),
+        content: Some((file, len)),
+    })
+}
+
+fn build_parts(status: StatusCode, headers: &[(header::HeaderName, String)]) -> Parts {
+    let mut response = Response::new(());
+    *response.status_mut() = status;
+    for (name, value) in headers {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            response.headers_mut().insert(name.clone(), value);
+        }
+    }
+    response.into_parts().0
+}
+
+/// Разбирает заголовок `Range`.
+/// Поддерживается только один диапазон, при нескольких диапазонах отдается весь файл,
+/// как и при недопустимом диапазоне (конец раньше начала).
+/// Возвращает включительные границы диапазона.
+fn parse_range(value: &str, size: u64) -> Result<Option<(u64, u64)>, ()> {
+    let value = value.trim();
+    if !value.starts_with("bytes=") {
+        return Ok(None);
+    }
+    let spec = value["bytes=".len()..].trim();
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let dash = match spec.find('-') {
+        Some(dash) => dash,
+        None => return Ok(None),
+    };
+    let (start, end) = (spec[..dash].trim(), spec[dash + 1..].trim());
+    let range = if start.is_empty() {
+        let suffix: u64 = match end.parse() {
+            Ok(suffix) => suffix,
+            Err(_) => return Ok(None),
+        };
+        if suffix == 0 || size == 0 {
+            return Err(());
+        }
+        (size.saturating_sub(suffix), size - 1)
+    } else {
+        let start: u64 = match start.parse() {
+            Ok(start) => start,
+            Err(_) => return Ok(None),
+        };
+        let end: u64 = if end.is_empty() {
+            size.saturating_sub(1)
+        } else {
+            match end.parse() {
+                // недопустимый диапазон игнорируется, как и нераспознанный
+                Ok(end) if end < start => return Ok(None),
+                Ok(end) => std::cmp::min(end, size.saturating_sub(1)),
+                Err(_) => return Ok(None),
+            }
+        };
+        if start >= size {
+            return Err(());
+        }
+        (start, end)
+    };
+    Ok(Some(range))
+}
+
+
+// RangeStream
+
+/// Поток, обрезающий `AsyncFileStream` до заданного количества байт
+struct RangeStream {
+    stream: AsyncFileStream,
+    remaining: u64,
+}
+impl Stream for RangeStream {
+    type Item = Bytes;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.remaining == 0 {
+            return Ok(Async::Ready(None));
+        }
+        match try_ready!(self.stream.poll()) {
+            Some(mut chunk) => {
+                if chunk.len() as u64 > self.remaining {
+                    chunk.truncate(self.remaining as usize);
+                }
+                self.remaining -= chunk.len() as u64;
+                Ok(Async::Ready(Some(chunk)))
+            },
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use futures::{Poll, Future, Async};
+use super::{AsyncFileWrite, AsyncFileSink, AsyncFile};
+use executor::BlockingExecutor;
+
+/// Асинхронное изменение длины файла и выделение места под него
+pub trait AsyncSetLen {
+
+    /// Изменяет длину файла. Вызывается повторно с тем же `len`, пока не вернет `Ready`.
+    fn poll_set_len(&mut self, len: u64) -> Poll<(), std::io::Error>;
+
+    /// Выделяет место под первые `len` байт файла (см. `AsyncFileWrite::poll_allocate`).
+    /// Вызывается повторно с тем же `len`, пока не вернет `Ready`.
+    fn poll_allocate(&mut self, len: u64) -> Poll<(), std::io::Error>;
+}
+impl<E: BlockingExecutor> AsyncSetLen for AsyncFileWrite<E> {
+    #[inline]
+    fn poll_set_len(&mut self, len: u64) -> Poll<(), std::io::Error> {
+        AsyncFileWrite::poll_set_len(self, len)
+    }
+
+    #[inline]
+    fn poll_allocate(&mut self, len: u64) -> Poll<(), std::io::Error> {
+        AsyncFileWrite::poll_allocate(self, len)
+    }
+}
+impl<E: BlockingExecutor, B: AsRef<[u8]> + Send + 'static> AsyncSetLen for AsyncFileSink<E, B> {
+    #[inline]
+    fn poll_set_len(&mut self, len: u64) -> Poll<(), std::io::Error> {
+        AsyncFileSink::poll_set_len(self, len)
+    }
+
+    #[inline]
+    fn poll_allocate(&mut self, len: u64) -> Poll<(), std::io::Error> {
+        AsyncFileSink::poll_allocate(self, len)
+    }
+}
+impl<E: BlockingExecutor> AsyncSetLen for AsyncFile<E> {
+    #[inline]
+    fn poll_set_len(&mut self, len: u64) -> Poll<(), std::io::Error> {
+        AsyncFile::poll_set_len(self, len)
+    }
+
+    #[inline]
+    fn poll_allocate(&mut self, len: u64) -> Poll<(), std::io::Error> {
+        AsyncFile::poll_allocate(self, len)
+    }
+}
+
+
+// SetLen
+
+/// Future изменения длины файла или выделения места под него; результат - сам объект
+pub struct SetLen<T> {
+    inner: Option<T>,
+    len: u64,
+    allocate: bool,
+}
+impl<T> SetLen<T> {
+    pub fn new(inner: T, len: u64) -> SetLen<T> {
+        SetLen {
+            inner: Some(inner),
+            len,
+            allocate: false,
+        }
+    }
+
+    pub fn allocate(inner: T, len: u64) -> SetLen<T> {
+        SetLen {
+            inner: Some(inner),
+            len,
+            allocate: true,
+        }
+    }
+}
+impl<T: AsyncSetLen> Future for SetLen<T> {
+    type Item = T;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        {
+            let inner = self.inner.as_mut().expect("poll a SetLen after it's done");
+            if self.allocate {
+                try_ready!(inner.poll_allocate(self.len));
+            } else {
+                try_ready!(inner.poll_set_len(self.len));
+            }
+        }
+        Ok(Async::Ready(self.inner.take().unwrap()))
+    }
+}
+impl<T> std::fmt::Debug for SetLen<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SetLen")
+            .field("len", &self.len)
+            .field("allocate", &self.allocate)
+            .finish()
+    }
+}
@@ -0,0 +1,198 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::ffi::CString;
+use std::ops::{Deref, DerefMut};
+use std::os::unix::io::FromRawFd;
+use super::DEFAULT_CPU_POOL;
+use mmap::Mapping;
+use sys::retry;
+use MmapStream;
+
+/// Имя объекта разделяемой памяти в виде `/name`
+fn shm_name(name: &str) -> std::io::Result<CString> {
+    let name = if name.starts_with('/') { name.to_string() } else { format!("/{}", name) };
+    if name.len() < 2 || name[1..].contains('/') {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid shared memory name"));
+    }
+    CString::new(name).map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidInput, error))
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "ios")))]
+unsafe fn shm_open(name: &CString, oflag: libc::c_int) -> libc::c_int {
+    libc::shm_open(name.as_ptr(), oflag, 0o600 as libc::mode_t)
+}
+
+/// На macOS `shm_open` - функция с переменным числом аргументов
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+unsafe fn shm_open(name: &CString, oflag: libc::c_int) -> libc::c_int {
+    libc::shm_open(name.as_ptr(), oflag, 0o600 as libc::c_uint)
+}
+
+fn open_shm(name: &str, oflag: libc::c_int) -> std::io::Result<std::fs::File> {
+    let c_name = shm_name(name)?;
+    let fd = retry(|| {
+        let fd = unsafe { shm_open(&c_name, oflag) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(fd)
+    })?;
+    Ok(unsafe { std::fs::File::from_raw_fd(fd) })
+}
+
+/// Создает объект разделяемой памяти `name` размером `len` байт (содержимое заполнено нулями);
+/// если объект уже существует, возвращается ошибка `AlreadyExists`.
+///
+/// Имя - одна компонента вида `/name` (`/` в начале добавляется при необходимости);
+/// на macOS оно ограничено 31 символом. Объект существует до вызова `unlink`,
+/// даже после завершения всех процессов.
+#[inline]
+pub fn create(name: &str, len: usize) -> CpuFuture<SharedMemory, std::io::Error> {
+    create_with_pool(&DEFAULT_CPU_POOL, name, len)
+}
+
+pub fn create_with_pool(cpu_pool: &'static CpuPool, name: &str, len: usize) -> CpuFuture<SharedMemory, std::io::Error> {
+    assert!(len > 0, "shared memory length must be greater than zero");
+    let name = name.to_string();
+    cpu_pool.spawn_fn(move || {
+        let file = open_shm(&name, libc::O_RDWR | libc::O_CREAT | libc::O_EXCL)?;
+        if let Err(error) = retry(|| file.set_len(len as u64)) {
+            let _ = unlink_shm(&name);
+            return Err(error);
+        }
+        Ok(SharedMemory { cpu_pool, name, file, len })
+    })
+}
+
+/// Открывает существующий объект разделяемой памяти `name` на чтение и запись
+#[inline]
+pub fn open(name: &str) -> CpuFuture<SharedMemory, std::io::Error> {
+    open_with_pool(&DEFAULT_CPU_POOL, name)
+}
+
+pub fn open_with_pool(cpu_pool: &'static CpuPool, name: &str) -> CpuFuture<SharedMemory, std::io::Error> {
+    let name = name.to_string();
+    cpu_pool.spawn_fn(move || {
+        let file = open_shm(&name, libc::O_RDWR)?;
+        let len = file.metadata()?.len() as usize;
+        Ok(SharedMemory { cpu_pool, name, file, len })
+    })
+}
+
+fn unlink_shm(name: &str) -> std::io::Result<()> {
+    let c_name = shm_name(name)?;
+    if unsafe { libc::shm_unlink(c_name.as_ptr()) } == 0 {
+        return Ok(());
+    }
+    Err(std::io::Error::last_os_error())
+}
+
+/// Удаляет имя объекта разделяемой памяти; уже открытые объекты и отображения
+/// остаются действительными
+#[inline]
+pub fn unlink(name: &str) -> CpuFuture<(), std::io::Error> {
+    unlink_with_pool(&DEFAULT_CPU_POOL, name)
+}
+
+pub fn unlink_with_pool(cpu_pool: &'static CpuPool, name: &str) -> CpuFuture<(), std::io::Error> {
+    let name = name.to_string();
+    cpu_pool.spawn_fn(move || unlink_shm(&name))
+}
+
+
+// SharedMemory
+
+/// Открытый объект разделяемой памяти
+pub struct SharedMemory {
+    cpu_pool: &'static CpuPool,
+    name: String,
+    file: std::fs::File,
+    len: usize,
+}
+impl SharedMemory {
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Размер объекта на момент открытия
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    pub fn file(&self) -> &std::fs::File {
+        &self.file
+    }
+
+    /// Отображает объект в память на чтение и запись в пуле потоков.
+    /// Изменения сразу видны всем процессам, отобразившим тот же объект.
+    pub fn map(&self) -> CpuFuture<SharedMapping, std::io::Error> {
+        let file = self.file.try_clone();
+        self.cpu_pool.spawn_fn(move || {
+            match Mapping::map(&file?, true)? {
+                Some(mapping) => Ok(SharedMapping { mapping }),
+                None => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "shared memory object is empty")),
+            }
+        })
+    }
+
+    /// Поток содержимого объекта фрагментами без копирования (см. `MmapStream`)
+    pub fn stream(&self) -> std::io::Result<MmapStream> {
+        Ok(MmapStream::from_std(self.cpu_pool, self.file.try_clone()?))
+    }
+
+    /// Удаляет имя объекта (см. `unlink`)
+    #[inline]
+    pub fn unlink(&self) -> CpuFuture<(), std::io::Error> {
+        unlink_with_pool(self.cpu_pool, &self.name)
+    }
+
+    #[inline]
+    pub fn into_std(self) -> std::fs::File {
+        self.file
+    }
+}
+impl std::fmt::Debug for SharedMemory {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SharedMemory")
+            .field("name", &self.name)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+
+// SharedMapping
+
+/// Отображение объекта разделяемой памяти на чтение и запись.
+///
+/// Синхронизация доступа между процессами остается за вызывающим кодом.
+pub struct SharedMapping {
+    mapping: Mapping,
+}
+impl Deref for SharedMapping {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.mapping.ptr, self.mapping.len) }
+    }
+}
+impl DerefMut for SharedMapping {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.mapping.ptr, self.mapping.len) }
+    }
+}
+impl std::fmt::Debug for SharedMapping {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SharedMapping")
+            .field("len", &self.mapping.len)
+            .finish()
+    }
+}
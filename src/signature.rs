@@ -0,0 +1,194 @@
+use futures::{Poll, Future, Async, Sink, Stream, StartSend};
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use sys::{long_path, retry, sync_all, sync_parent_dir};
+
+static SIGNATURE_ALGORITHM: &str = "hmac-sha256";
+
+/// Путь к файлу подписи для `path`: `<path>.sig`
+pub fn signature_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut signature_path = path.as_ref().as_os_str().to_owned();
+    signature_path.push(".sig");
+    signature_path.into()
+}
+
+fn new_mac(key: &[u8]) -> Hmac<Sha256> {
+    Hmac::<Sha256>::new_varkey(key).expect("HMAC accepts keys of any length")
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+
+// SigningSink
+
+/// Адаптер, вычисляющий HMAC-SHA256 по данным, проходящим в нижележащий sink.
+///
+/// При закрытии sink (`Sink::close`), после закрытия нижележащего sink,
+/// подпись записывается в отдельный файл подписи, поэтому повторное чтение файла не требуется.
+pub struct SigningSink<S> {
+    cpu_pool: &'static CpuPool,
+    inner: S,
+    mac: Option<Hmac<Sha256>>,
+    signature_path: PathBuf,
+    writing: Option<CpuFuture<(), std::io::Error>>,
+}
+impl<S> SigningSink<S> {
+
+    pub fn new<P: AsRef<Path>>(cpu_pool: &'static CpuPool, inner: S, key: &[u8], signature_path: P) -> SigningSink<S> {
+        SigningSink {
+            cpu_pool,
+            inner,
+            mac: Some(new_mac(key)),
+            signature_path: signature_path.as_ref().into(),
+            writing: None,
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+impl<S> Sink for SigningSink<S>
+    where S: Sink<SinkItem = Bytes, SinkError = std::io::Error>
+{
+    type SinkItem = Bytes;
+    type SinkError = std::io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let mac = match self.mac {
+            Some(ref mut mac) => mac,
+            None => return Err(std::io::Error::other("signing sink already closed")),
+        };
+        let result = self.inner.start_send(item.clone())?;
+        if result.is_ready() {
+            mac.input(&item);
+        }
+        Ok(result)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.poll_complete()
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        if self.writing.is_none() {
+            try_ready!(self.inner.close());
+            let code = match self.mac.take() {
+                Some(mac) => mac.result().code(),
+                None => return Ok(Async::Ready(())),
+            };
+            let signature = format!("{} {}\n", SIGNATURE_ALGORITHM, to_hex(&code));
+            let signature_path = self.signature_path.clone();
+            self.writing = Some(self.cpu_pool.spawn_fn(move || {
+                let mut file = std::fs::File::create(long_path(&signature_path))?;
+                file.write_all(signature.as_bytes())?;
+                retry(|| sync_all(&file))?;
+                sync_parent_dir(&signature_path)
+            }));
+        }
+        try_ready!(self.writing.as_mut().unwrap().poll());
+        Ok(Async::Ready(()))
+    }
+}
+impl<S> std::fmt::Debug for SigningSink<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SigningSink")
+            .field("signature_path", &self.signature_path)
+            .finish()
+    }
+}
+
+
+// VerifyingStream
+
+/// Адаптер, проверяющий HMAC-SHA256 данных, проходящих через поток, по файлу подписи.
+///
+/// Фрагменты передаются дальше по мере чтения, а проверка подписи выполняется
+/// по окончании потока: при несовпадении поток завершается ошибкой `InvalidData`
+/// вместо признака окончания.
+pub struct VerifyingStream<S> {
+    cpu_pool: &'static CpuPool,
+    inner: S,
+    mac: Option<Hmac<Sha256>>,
+    signature_path: PathBuf,
+    reading: Option<CpuFuture<Vec<u8>, std::io::Error>>,
+}
+impl<S> VerifyingStream<S> {
+
+    pub fn new<P: AsRef<Path>>(cpu_pool: &'static CpuPool, inner: S, key: &[u8], signature_path: P) -> VerifyingStream<S> {
+        VerifyingStream {
+            cpu_pool,
+            inner,
+            mac: Some(new_mac(key)),
+            signature_path: signature_path.as_ref().into(),
+            reading: None,
+        }
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+impl<S> Stream for VerifyingStream<S>
+    where S: Stream<Item = Bytes, Error = std::io::Error>
+{
+    type Item = Bytes;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if self.reading.is_none() {
+            if self.mac.is_none() {
+                return Ok(Async::Ready(None));
+            }
+            if let Some(chunk) = try_ready!(self.inner.poll()) {
+                self.mac.as_mut().unwrap().input(&chunk);
+                return Ok(Async::Ready(Some(chunk)));
+            }
+            let signature_path = self.signature_path.clone();
+            self.reading = Some(self.cpu_pool.spawn_fn(move || {
+                let mut signature = String::new();
+                std::fs::File::open(long_path(&signature_path))?.read_to_string(&mut signature)?;
+                let mut parts = signature.split_whitespace();
+                if parts.next() != Some(SIGNATURE_ALGORITHM) {
+                    return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "unsupported signature algorithm"));
+                }
+                parts.next()
+                    .and_then(from_hex)
+                    .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed signature file"))
+            }));
+        }
+        let code = try_ready!(self.reading.as_mut().unwrap().poll());
+        self.reading = None;
+        match self.mac.take() {
+            Some(mac) => mac.verify(&code)
+                .map(|_| Async::Ready(None))
+                .map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidData, "signature mismatch")),
+            None => Ok(Async::Ready(None)),
+        }
+    }
+}
+impl<S> std::fmt::Debug for VerifyingStream<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("VerifyingStream")
+            .field("signature_path", &self.signature_path)
+            .finish()
+    }
+}
@@ -0,0 +1,587 @@
+use std::borrow::Cow;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Признак автоматического повтора системных вызовов, прерванных сигналом
+static RETRY_INTERRUPTED: AtomicBool = AtomicBool::new(true);
+
+/// Включает или отключает автоматический повтор блокирующих системных вызовов крейта,
+/// прерванных сигналом (`EINTR`). По умолчанию повтор включен; при отключении
+/// ошибка `Interrupted` возвращается вызывающему коду.
+pub fn set_retry_interrupted(retry: bool) {
+    RETRY_INTERRUPTED.store(retry, Ordering::Relaxed);
+}
+
+/// Выполняет блокирующую операцию, повторяя ее при ошибке `Interrupted`
+pub fn retry<T, F: FnMut() -> std::io::Result<T>>(mut f: F) -> std::io::Result<T> {
+    loop {
+        match f() {
+            Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted && RETRY_INTERRUPTED.load(Ordering::Relaxed) => {},
+            result => return result,
+        }
+    }
+}
+
+/// Записывает весь буфер по смещению `offset`, не изменяя позицию курсора файла (где это возможно)
+#[cfg(unix)]
+pub fn write_all_at(file: &std::fs::File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buf, offset)
+}
+
+#[cfg(windows)]
+pub fn write_all_at(file: &std::fs::File, mut buf: &[u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset) {
+            Ok(0) => return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "failed to write whole buffer")),
+            Ok(size) => {
+                buf = &buf[size..];
+                offset += size as u64;
+            },
+            Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => {},
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+/// Сбрасывает на диск запись о файле в родительской директории
+#[cfg(unix)]
+pub fn sync_parent_dir(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => {
+            let dir = retry(|| std::fs::File::open(long_path(parent)))?;
+            retry(|| dir.sync_all())
+        },
+        _ => {
+            let dir = retry(|| std::fs::File::open("."))?;
+            retry(|| dir.sync_all())
+        },
+    }
+}
+
+#[cfg(not(unix))]
+pub fn sync_parent_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Читает данные по смещению `offset`, не изменяя позицию курсора файла (где это возможно)
+#[cfg(unix)]
+pub fn read_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    use std::os::unix::fs::FileExt;
+    retry(|| file.read_at(buf, offset))
+}
+
+#[cfg(windows)]
+pub fn read_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    use std::os::windows::fs::FileExt;
+    retry(|| file.seek_read(buf, offset))
+}
+
+/// Читает данные по смещению `offset` до заполнения буфера или до конца файла
+pub fn read_full_at(file: &std::fs::File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<usize> {
+    let mut total = 0;
+    while !buf.is_empty() {
+        match read_at(file, buf, offset) {
+            Ok(0) => break,
+            Ok(size) => {
+                total += size;
+                offset += size as u64;
+                let tmp = buf;
+                buf = &mut tmp[size..];
+            },
+            Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => {},
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(total)
+}
+
+/// Читает ровно `buf.len()` байт по смещению `offset`; конец файла раньше - ошибка `UnexpectedEof`
+pub fn read_exact_at(file: &std::fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    if read_full_at(file, buf, offset)? < buf.len() {
+        return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "failed to fill whole buffer"));
+    }
+    Ok(())
+}
+
+/// Флаг `preadv2`: не ждать чтения с диска, если данных нет в кэше страниц
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+static RWF_NOWAIT: libc::c_int = 0x0000_0008;
+
+/// Признак того, что ядро не поддерживает `preadv2` с `RWF_NOWAIT` (ядра до 4.14)
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+static NOWAIT_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Пытается прочитать данные из текущей позиции файла без ожидания диска (`preadv2` с `RWF_NOWAIT`).
+/// Возвращает `None`, если данных нет в кэше страниц или такое чтение не поддерживается,
+/// и тогда чтение следует выполнить в пуле потоков.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+pub fn read_nowait(file: &std::fs::File, buf: &mut [u8]) -> std::io::Result<Option<usize>> {
+    use std::os::unix::io::AsRawFd;
+    if NOWAIT_UNSUPPORTED.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
+    let iovec = libc::iovec { iov_base: buf.as_mut_ptr() as *mut libc::c_void, iov_len: buf.len() };
+    // смещение -1: чтение из текущей позиции файла с ее перемещением
+    let size = unsafe { libc::preadv2(file.as_raw_fd(), &iovec, 1, -1, RWF_NOWAIT) };
+    if size >= 0 {
+        return Ok(Some(size as usize));
+    }
+    let error = std::io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::EAGAIN) | Some(libc::EINTR) => Ok(None),
+        // файловая система не поддерживает `RWF_NOWAIT`: прочие файлы могут ее поддерживать
+        Some(libc::EOPNOTSUPP) => Ok(None),
+        Some(libc::ENOSYS) | Some(libc::EINVAL) => {
+            NOWAIT_UNSUPPORTED.store(true, Ordering::Relaxed);
+            Ok(None)
+        },
+        _ => Err(error),
+    }
+}
+
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+pub fn read_nowait(_file: &std::fs::File, _buf: &mut [u8]) -> std::io::Result<Option<usize>> {
+    Ok(None)
+}
+
+/// `ioctl` клонирования содержимого файла (`_IOW(0x94, 9, int)`)
+#[cfg(any(target_os = "linux", target_os = "android"))]
+static FICLONE: libc::c_ulong = 0x4004_9409;
+
+/// Делает `dst` копией `src`, разделяющей с ним блоки (reflink, copy-on-write).
+/// `Ok(false)`, если файловая система или платформа не поддерживает такие копии.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn reflink(src: &std::fs::File, dst: &std::fs::File) -> std::io::Result<bool> {
+    use std::os::unix::io::AsRawFd;
+    let result = retry(|| {
+        if unsafe { libc::ioctl(dst.as_raw_fd(), FICLONE as _, src.as_raw_fd()) } == 0 {
+            return Ok(());
+        }
+        Err(std::io::Error::last_os_error())
+    });
+    match result {
+        Ok(()) => Ok(true),
+        Err(error) => match error.raw_os_error() {
+            // нет поддержки в файловой системе, разные файловые системы или старое ядро
+            Some(libc::EOPNOTSUPP) | Some(libc::EXDEV) | Some(libc::EINVAL) | Some(libc::ENOTTY) | Some(libc::ENOSYS) => Ok(false),
+            _ => Err(error),
+        },
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn reflink(_src: &std::fs::File, _dst: &std::fs::File) -> std::io::Result<bool> {
+    Ok(false)
+}
+
+/// Копирует до `len` байт из текущей позиции `src` в текущую позицию `dst`, не передавая данные
+/// через память процесса (`copy_file_range`, а если он недоступен - `sendfile`).
+/// `None`, если такое копирование для этих файлов не поддерживается.
+#[cfg(target_os = "linux")]
+pub fn copy_chunk(src: &std::fs::File, dst: &std::fs::File, len: usize) -> std::io::Result<Option<usize>> {
+    use std::os::unix::io::AsRawFd;
+    let result = retry(|| {
+        let size = unsafe {
+            libc::syscall(
+                libc::SYS_copy_file_range,
+                src.as_raw_fd(), std::ptr::null_mut::<libc::loff_t>(),
+                dst.as_raw_fd(), std::ptr::null_mut::<libc::loff_t>(),
+                len, 0 as libc::c_uint,
+            )
+        };
+        if size >= 0 {
+            return Ok(size as usize);
+        }
+        Err(std::io::Error::last_os_error())
+    });
+    match result {
+        Ok(size) => return Ok(Some(size)),
+        Err(error) => match error.raw_os_error() {
+            // старое ядро, разные файловые системы (до 5.3) или специальные файлы
+            Some(libc::ENOSYS) | Some(libc::EXDEV) | Some(libc::EINVAL) | Some(libc::EOPNOTSUPP) | Some(libc::EPERM) => {},
+            _ => return Err(error),
+        },
+    }
+    let result = retry(|| {
+        let size = unsafe { libc::sendfile(dst.as_raw_fd(), src.as_raw_fd(), std::ptr::null_mut(), len) };
+        if size >= 0 {
+            return Ok(size as usize);
+        }
+        Err(std::io::Error::last_os_error())
+    });
+    match result {
+        Ok(size) => Ok(Some(size)),
+        Err(error) => match error.raw_os_error() {
+            Some(libc::ENOSYS) | Some(libc::EINVAL) => Ok(None),
+            _ => Err(error),
+        },
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn copy_chunk(_src: &std::fs::File, _dst: &std::fs::File, _len: usize) -> std::io::Result<Option<usize>> {
+    Ok(None)
+}
+
+/// Наибольшее количество буферов в одном векторном системном вызове
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+static MAX_IOVECS: usize = 1024;
+
+/// Позиционное чтение в несколько буферов одним вызовом `preadv`
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn read_vectored_at(file: &std::fs::File, bufs: &mut [&mut [u8]], offset: u64) -> std::io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+    let iovecs: Vec<libc::iovec> = bufs.iter_mut()
+        .take(MAX_IOVECS)
+        .map(|buf| libc::iovec { iov_base: buf.as_mut_ptr() as *mut libc::c_void, iov_len: buf.len() })
+        .collect();
+    let size = unsafe { libc::preadv(file.as_raw_fd(), iovecs.as_ptr(), iovecs.len() as libc::c_int, offset as libc::off_t) };
+    if size < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(size as usize)
+}
+
+/// Там, где `preadv` недоступен, читается только первый непустой буфер
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn read_vectored_at(file: &std::fs::File, bufs: &mut [&mut [u8]], offset: u64) -> std::io::Result<usize> {
+    match bufs.iter_mut().find(|buf| !buf.is_empty()) {
+        Some(buf) => read_at(file, buf, offset),
+        None => Ok(0),
+    }
+}
+
+/// Позиционная запись нескольких буферов одним вызовом `pwritev`
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn write_vectored_at(file: &std::fs::File, bufs: &[&[u8]], offset: u64) -> std::io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+    let iovecs: Vec<libc::iovec> = bufs.iter()
+        .take(MAX_IOVECS)
+        .map(|buf| libc::iovec { iov_base: buf.as_ptr() as *mut libc::c_void, iov_len: buf.len() })
+        .collect();
+    let size = unsafe { libc::pwritev(file.as_raw_fd(), iovecs.as_ptr(), iovecs.len() as libc::c_int, offset as libc::off_t) };
+    if size < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(size as usize)
+}
+
+/// Там, где `pwritev` недоступен, записывается только первый непустой буфер
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn write_vectored_at(file: &std::fs::File, bufs: &[&[u8]], offset: u64) -> std::io::Result<usize> {
+    #[cfg(unix)]
+    use std::os::unix::fs::FileExt;
+    #[cfg(windows)]
+    use std::os::windows::fs::FileExt;
+    match bufs.iter().find(|buf| !buf.is_empty()) {
+        #[cfg(unix)]
+        Some(buf) => file.write_at(buf, offset),
+        #[cfg(windows)]
+        Some(buf) => file.seek_write(buf, offset),
+        None => Ok(0),
+    }
+}
+
+/// Продвигает позицию `(index, start)` в наборе буферов на `size` байт
+fn advance<B: AsRef<[u8]>>(bufs: &[B], index: &mut usize, start: &mut usize, mut size: usize) {
+    while size > 0 && *index < bufs.len() {
+        let available = bufs[*index].as_ref().len() - *start;
+        if size < available {
+            *start += size;
+            return;
+        }
+        size -= available;
+        *index += 1;
+        *start = 0;
+    }
+}
+
+/// Читает по смещению `offset` в последовательно расположенные буферы до их заполнения
+/// или до конца файла, повторяя векторное чтение после коротких чтений
+pub fn read_full_vectored_at(file: &std::fs::File, bufs: &mut [Vec<u8>], mut offset: u64) -> std::io::Result<usize> {
+    let mut total = 0;
+    let (mut index, mut start) = (0, 0);
+    while index < bufs.len() {
+        if start == bufs[index].len() {
+            index += 1;
+            start = 0;
+            continue;
+        }
+        let size = {
+            let mut slices: Vec<&mut [u8]> = Vec::with_capacity(bufs.len() - index);
+            let mut rest = bufs[index..].iter_mut();
+            slices.push(&mut rest.next().unwrap()[start..]);
+            slices.extend(rest.map(|buf| &mut buf[..]));
+            retry(|| read_vectored_at(file, &mut slices, offset))?
+        };
+        if size == 0 {
+            break;
+        }
+        total += size;
+        offset += size as u64;
+        advance(bufs, &mut index, &mut start, size);
+    }
+    Ok(total)
+}
+
+/// Записывает последовательно расположенные буферы целиком по смещению `offset`,
+/// повторяя векторную запись после коротких записей
+pub fn write_all_vectored_at<B: AsRef<[u8]>>(file: &std::fs::File, bufs: &[B], mut offset: u64) -> std::io::Result<()> {
+    let (mut index, mut start) = (0, 0);
+    while index < bufs.len() {
+        if start == bufs[index].as_ref().len() {
+            index += 1;
+            start = 0;
+            continue;
+        }
+        let size = {
+            let mut slices: Vec<&[u8]> = Vec::with_capacity(bufs.len() - index);
+            slices.push(&bufs[index].as_ref()[start..]);
+            slices.extend(bufs[index + 1..].iter().map(|buf| buf.as_ref()));
+            retry(|| write_vectored_at(file, &slices, offset))?
+        };
+        if size == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "failed to write whole buffer"));
+        }
+        offset += size as u64;
+        advance(bufs, &mut index, &mut start, size);
+    }
+    Ok(())
+}
+
+/// Читает с текущей позиции в последовательно расположенные буферы (`readv`) до их заполнения
+/// или до конца файла, повторяя векторное чтение после коротких чтений
+pub fn read_full_vectored(file: &mut std::fs::File, bufs: &mut [Vec<u8>]) -> std::io::Result<usize> {
+    let mut total = 0;
+    let (mut index, mut start) = (0, 0);
+    while index < bufs.len() {
+        if start == bufs[index].len() {
+            index += 1;
+            start = 0;
+            continue;
+        }
+        let size = {
+            let mut slices: Vec<std::io::IoSliceMut> = Vec::with_capacity(bufs.len() - index);
+            let mut rest = bufs[index..].iter_mut();
+            slices.push(std::io::IoSliceMut::new(&mut rest.next().unwrap()[start..]));
+            slices.extend(rest.map(|buf| std::io::IoSliceMut::new(&mut buf[..])));
+            retry(|| std::io::Read::read_vectored(file, &mut slices))?
+        };
+        if size == 0 {
+            break;
+        }
+        total += size;
+        advance(bufs, &mut index, &mut start, size);
+    }
+    Ok(total)
+}
+
+/// Записывает последовательно расположенные буферы целиком с текущей позиции (`writev`),
+/// повторяя векторную запись после коротких записей
+pub fn write_all_vectored<B: AsRef<[u8]>>(file: &mut std::fs::File, bufs: &[B]) -> std::io::Result<()> {
+    let (mut index, mut start) = (0, 0);
+    while index < bufs.len() {
+        if start == bufs[index].as_ref().len() {
+            index += 1;
+            start = 0;
+            continue;
+        }
+        let size = {
+            let mut slices: Vec<std::io::IoSlice> = Vec::with_capacity(bufs.len() - index);
+            slices.push(std::io::IoSlice::new(&bufs[index].as_ref()[start..]));
+            slices.extend(bufs[index + 1..].iter().map(|buf| std::io::IoSlice::new(buf.as_ref())));
+            retry(|| std::io::Write::write_vectored(file, &slices))?
+        };
+        if size == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "failed to write whole buffer"));
+        }
+        advance(bufs, &mut index, &mut start, size);
+    }
+    Ok(())
+}
+
+/// Длина пути, начиная с которой для Windows используется расширенный вид `\\?\`
+/// (с запасом для `CreateDirectory`, ограниченной 248 символами)
+#[cfg(windows)]
+static LONG_PATH_THRESHOLD: usize = 248;
+
+/// Приводит путь к виду, допускающему длину больше `MAX_PATH` на Windows (`\\?\C:\...`, `\\?\UNC\...`).
+/// Короткие пути и пути на остальных платформах возвращаются без изменений.
+#[cfg(windows)]
+pub fn long_path<'a>(path: &'a Path) -> Cow<'a, Path> {
+    if path.as_os_str().len() < LONG_PATH_THRESHOLD {
+        return Cow::Borrowed(path);
+    }
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        match std::env::current_dir() {
+            Ok(current_dir) => current_dir.join(path),
+            Err(_) => return Cow::Borrowed(path),
+        }
+    };
+    match absolute.to_str().and_then(verbatim_windows_path) {
+        Some(verbatim) => Cow::Owned(verbatim.into()),
+        None => Cow::Owned(absolute),
+    }
+}
+
+#[cfg(not(windows))]
+#[inline]
+pub fn long_path<'a>(path: &'a Path) -> Cow<'a, Path> {
+    Cow::Borrowed(path)
+}
+
+/// Преобразует абсолютный путь Windows в расширенный вид `\\?\`.
+///
+/// В расширенном виде система не нормализует путь, поэтому разделители `/` заменяются на `\`,
+/// а компоненты `.` и `..` разрешаются лексически. `None` для относительных путей
+/// и путей, уже имеющих расширенный вид или вид устройства (`\\.\`).
+#[cfg_attr(not(windows), allow(dead_code))]
+pub fn verbatim_windows_path(path: &str) -> Option<String> {
+    if path.starts_with(r"\\?\") || path.starts_with(r"\\.\") || path.starts_with("//?/") || path.starts_with("//./") {
+        return None;
+    }
+    let path = path.replace('/', "\\");
+    let (mut verbatim, root_len, rest) = if let Some(unc) = path.strip_prefix(r"\\") {
+        // UNC: \\server\share\rest
+        let mut parts = unc.splitn(3, '\\');
+        let server = parts.next().filter(|server| !server.is_empty())?;
+        let share = parts.next().filter(|share| !share.is_empty())?;
+        let verbatim = format!(r"\\?\UNC\{}\{}", server, share);
+        let root_len = verbatim.len();
+        (verbatim, root_len, parts.next().unwrap_or("").to_string())
+    } else {
+        let bytes = path.as_bytes();
+        if bytes.len() < 3 || !bytes[0].is_ascii_alphabetic() || bytes[1] != b':' || bytes[2] != b'\\' {
+            return None;
+        }
+        let verbatim = format!(r"\\?\{}", &path[..2]);
+        let root_len = verbatim.len();
+        (verbatim, root_len, path[3..].to_string())
+    };
+    for component in rest.split('\\') {
+        match component {
+            "" | "." => {},
+            ".." => {
+                let parent = verbatim.rfind('\\').unwrap_or(0);
+                if parent >= root_len {
+                    verbatim.truncate(parent);
+                }
+            },
+            component => {
+                verbatim.push('\\');
+                verbatim.push_str(component);
+            },
+        }
+    }
+    if verbatim.len() == root_len {
+        verbatim.push('\\');
+    }
+    Some(verbatim)
+}
+
+/// Дескриптор файла, сохраняемый оберткой, пока сам файл передан в пул потоков
+#[cfg(unix)]
+pub type RawFile = std::os::unix::io::RawFd;
+
+/// `HANDLE` хранится числом, чтобы обертки оставались `Send`
+#[cfg(windows)]
+pub type RawFile = usize;
+
+#[cfg(unix)]
+pub fn raw_file(file: &std::fs::File) -> RawFile {
+    use std::os::unix::io::AsRawFd;
+    file.as_raw_fd()
+}
+
+#[cfg(windows)]
+pub fn raw_file(file: &std::fs::File) -> RawFile {
+    use std::os::windows::io::AsRawHandle;
+    file.as_raw_handle() as usize
+}
+
+/// Дублирует дескриптор, сохраненный оберткой; копия закрывается независимо от оригинала
+#[cfg(unix)]
+pub fn clone_raw_file(raw: RawFile) -> std::io::Result<std::fs::File> {
+    use std::os::unix::io::FromRawFd;
+    let file = std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_fd(raw) });
+    file.try_clone()
+}
+
+#[cfg(windows)]
+pub fn clone_raw_file(raw: RawFile) -> std::io::Result<std::fs::File> {
+    use std::os::windows::io::FromRawHandle;
+    let file = std::mem::ManuallyDrop::new(unsafe { std::fs::File::from_raw_handle(raw as std::os::windows::io::RawHandle) });
+    file.try_clone()
+}
+
+/// Сбрасывает данные и метаданные файла на носитель.
+///
+/// На macOS `fsync` не сбрасывает кэш накопителя, поэтому используется `F_FULLFSYNC`;
+/// если файловая система его не поддерживает (например, сетевая), выполняется `fsync`.
+#[cfg(target_os = "macos")]
+pub fn sync_all(file: &std::fs::File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_FULLFSYNC) } == 0 {
+        return Ok(());
+    }
+    let error = std::io::Error::last_os_error();
+    match error.raw_os_error() {
+        Some(libc::ENOTSUP) | Some(libc::EINVAL) | Some(libc::ENOTTY) => file.sync_all(),
+        _ => Err(error),
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+pub fn sync_all(file: &std::fs::File) -> std::io::Result<()> {
+    file.sync_all()
+}
+
+/// Выделяет на диске место под первые `len` байт файла и при необходимости увеличивает
+/// его длину до `len`; файл не уменьшается. Если файловая система не умеет выделять место
+/// заранее, длина просто увеличивается (без резервирования).
+#[cfg(target_os = "linux")]
+pub fn allocate(file: &std::fs::File, len: u64) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    if len == 0 {
+        return Ok(());
+    }
+    let result = retry(|| {
+        if unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, len as libc::off_t) } == 0 {
+            return Ok(());
+        }
+        Err(std::io::Error::last_os_error())
+    });
+    match result {
+        Ok(()) => Ok(()),
+        Err(error) => match error.raw_os_error() {
+            Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS) => extend(file, len),
+            _ => Err(error),
+        },
+    }
+}
+
+#[cfg(target_os = "macos")]
+pub fn allocate(file: &std::fs::File, len: u64) -> std::io::Result<()> {
+    use macos::MacosFileExt;
+    match file.preallocate(len) {
+        Err(ref error) if error.raw_os_error() == Some(libc::ENOTSUP) => extend(file, len),
+        result => result,
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn allocate(file: &std::fs::File, len: u64) -> std::io::Result<()> {
+    extend(file, len)
+}
+
+fn extend(file: &std::fs::File, len: u64) -> std::io::Result<()> {
+    if file.metadata()?.len() < len {
+        retry(|| file.set_len(len))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use futures::{Poll, Future, Async, AsyncSink, Sink, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::path::{Path, PathBuf};
+use super::{AsyncFileSink, DEFAULT_CPU_POOL};
+use sys::{long_path, retry};
+
+
+// TeeToFile
+
+/// Поток, передающий блоки исходного потока дальше и одновременно записывающий их в файл.
+///
+/// Следующий блок запрашивается из исходного потока только после того, как записан
+/// предыдущий, поэтому скорость потока определяется более медленным из потребителя и диска.
+/// После завершения исходного потока файл сбрасывается на диск и закрывается, и только затем
+/// завершается сам поток. Ошибка создания или записи файла завершает поток с этой ошибкой.
+pub struct TeeToFile<S> {
+    inner: S,
+    cpu_pool: &'static CpuPool,
+    opening: Option<CpuFuture<std::fs::File, std::io::Error>>,
+    sink: Option<AsyncFileSink>,
+    finished: bool,
+}
+
+/// Передает блоки потока дальше, сохраняя их в файл `path` (файл создается или перезаписывается)
+#[inline]
+pub fn tee_to_file<S, P>(stream: S, path: P) -> TeeToFile<S>
+    where S: Stream<Item = Bytes>,
+          S::Error: From<std::io::Error>,
+          P: AsRef<Path>
+{
+    tee_to_file_with_pool(&DEFAULT_CPU_POOL, stream, path)
+}
+
+pub fn tee_to_file_with_pool<S, P>(cpu_pool: &'static CpuPool, stream: S, path: P) -> TeeToFile<S>
+    where S: Stream<Item = Bytes>,
+          S::Error: From<std::io::Error>,
+          P: AsRef<Path>
+{
+    let path: PathBuf = path.as_ref().into();
+    TeeToFile {
+        inner: stream,
+        cpu_pool,
+        opening: Some(cpu_pool.spawn_fn(move || retry(|| std::fs::File::create(long_path(&path))))),
+        sink: None,
+        finished: false,
+    }
+}
+
+impl<S> TeeToFile<S> {
+
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+impl<S> Stream for TeeToFile<S>
+    where S: Stream<Item = Bytes>,
+          S::Error: From<std::io::Error>
+{
+    type Item = Bytes;
+    type Error = S::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Some(ref mut opening) = self.opening {
+            let file = try_ready!(opening.poll());
+            self.sink = Some(AsyncFileSink::from_std(self.cpu_pool, file));
+        }
+        self.opening = None;
+        let sink = match self.sink {
+            Some(ref mut sink) => sink,
+            None => return Ok(Async::Ready(None)),
+        };
+        loop {
+            if self.finished {
+                try_ready!(sink.poll_close());
+                return Ok(Async::Ready(None));
+            }
+            try_ready!(sink.poll_ready());
+            match try_ready!(self.inner.poll()) {
+                Some(chunk) => {
+                    if let AsyncSink::NotReady(_) = sink.start_send(chunk.clone())? {
+                        unreachable!("sink is ready after `poll_ready`");
+                    }
+                    return Ok(Async::Ready(Some(chunk)));
+                },
+                None => self.finished = true,
+            }
+        }
+    }
+}
+impl<S> std::fmt::Debug for TeeToFile<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("TeeToFile")
+            .field("finished", &self.finished)
+            .finish()
+    }
+}
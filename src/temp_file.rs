@@ -0,0 +1,206 @@
+use futures::Poll;
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::{AsyncFile, AsyncSeek, ScopedDir, DEFAULT_CPU_POOL, DEFAULT_BUFFER_SIZE};
+use ephemeral::create_ephemeral;
+use sys::{long_path, retry};
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+static TEMP_FILE_PREFIX: &str = ".tmp";
+
+/// Непредсказуемое имя: `RandomState` получает ключи от генератора случайных чисел системы
+fn random_name() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_usize(TEMP_FILE_COUNTER.fetch_add(1, Ordering::SeqCst));
+    hasher.write_u32(std::process::id());
+    if let Ok(elapsed) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        hasher.write_u64(elapsed.as_secs());
+        hasher.write_u32(elapsed.subsec_nanos());
+    }
+    format!("{}{:016x}", TEMP_FILE_PREFIX, hasher.finish())
+}
+
+/// Создает новый файл со случайным именем, доступный только владельцу
+fn create_named(dir: &Path) -> std::io::Result<(std::fs::File, PathBuf)> {
+    loop {
+        let path = dir.join(random_name());
+        let mut options = std::fs::OpenOptions::new();
+        options.read(true).write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        match retry(|| options.open(long_path(&path))) {
+            Ok(file) => return Ok((file, path)),
+            Err(ref error) if error.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Создает временный каталог (см. `ScopedDir`) во временном каталоге системы
+#[inline]
+pub fn tempdir() -> CpuFuture<ScopedDir, std::io::Error> {
+    tempdir_with_pool(&DEFAULT_CPU_POOL)
+}
+
+pub fn tempdir_with_pool(cpu_pool: &'static CpuPool) -> CpuFuture<ScopedDir, std::io::Error> {
+    ScopedDir::create_with_pool(cpu_pool, TEMP_FILE_PREFIX)
+}
+
+
+// AsyncTempFile
+
+/// Временный файл для чтения и записи (см. `AsyncFile`), удаляемый вместе со структурой.
+///
+/// Файл, созданный `create`, имеет случайное имя и удаляется в пуле потоков без ожидания
+/// при освобождении структуры; `persist` сохраняет его под другим именем. Файл, созданный
+/// `unnamed`, не имеет имени (см. `EphemeralFile`) и исчезает при закрытии дескриптора,
+/// даже если процесс завершится аварийно.
+pub struct AsyncTempFile {
+    cpu_pool: &'static CpuPool,
+    file: Option<AsyncFile>,
+    path: Option<PathBuf>,
+}
+impl AsyncTempFile {
+
+    /// Создает файл со случайным именем во временном каталоге системы
+    #[inline]
+    pub fn create() -> CpuFuture<AsyncTempFile, std::io::Error> {
+        Self::create_in_with_pool(&DEFAULT_CPU_POOL, std::env::temp_dir())
+    }
+
+    pub fn create_with_pool(cpu_pool: &'static CpuPool) -> CpuFuture<AsyncTempFile, std::io::Error> {
+        Self::create_in_with_pool(cpu_pool, std::env::temp_dir())
+    }
+
+    /// Создает файл со случайным именем в каталоге `dir`
+    #[inline]
+    pub fn create_in<P: AsRef<Path>>(dir: P) -> CpuFuture<AsyncTempFile, std::io::Error> {
+        Self::create_in_with_pool(&DEFAULT_CPU_POOL, dir)
+    }
+
+    pub fn create_in_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, dir: P) -> CpuFuture<AsyncTempFile, std::io::Error> {
+        let dir: PathBuf = dir.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let (file, path) = create_named(&dir)?;
+            Ok(AsyncTempFile {
+                cpu_pool,
+                file: Some(AsyncFile::from_std(cpu_pool, file, DEFAULT_BUFFER_SIZE)),
+                path: Some(path),
+            })
+        })
+    }
+
+    /// Создает файл без имени во временном каталоге системы (`O_TMPFILE` на Linux)
+    #[inline]
+    pub fn unnamed() -> CpuFuture<AsyncTempFile, std::io::Error> {
+        Self::unnamed_in_with_pool(&DEFAULT_CPU_POOL, std::env::temp_dir())
+    }
+
+    /// Создает файл без имени в каталоге `dir`
+    pub fn unnamed_in_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, dir: P) -> CpuFuture<AsyncTempFile, std::io::Error> {
+        let dir: PathBuf = dir.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            Ok(AsyncTempFile {
+                cpu_pool,
+                file: Some(AsyncFile::from_std(cpu_pool, create_ephemeral(&dir)?, DEFAULT_BUFFER_SIZE)),
+                path: None,
+            })
+        })
+    }
+
+    /// Путь к файлу; `None` для файла без имени
+    #[inline]
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Переименовывает файл в `path` в пуле потоков и отменяет его удаление.
+    /// Записанные данные должны быть сброшены (`flush`) до вызова.
+    pub fn persist<P: AsRef<Path>>(mut self, path: P) -> CpuFuture<AsyncFile, std::io::Error> {
+        let target: PathBuf = path.as_ref().into();
+        let file = self.file.take().unwrap();
+        let source = self.path.take();
+        self.cpu_pool.spawn_fn(move || {
+            match source {
+                Some(source) => retry(|| std::fs::rename(long_path(&source), long_path(&target)))?,
+                None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "unnamed temporary file cannot be persisted")),
+            }
+            Ok(file)
+        })
+    }
+
+    /// Отменяет удаление файла и возвращает его вместе с путем
+    pub fn keep(mut self) -> (AsyncFile, Option<PathBuf>) {
+        (self.file.take().unwrap(), self.path.take())
+    }
+}
+impl Deref for AsyncTempFile {
+    type Target = AsyncFile;
+
+    fn deref(&self) -> &AsyncFile {
+        self.file.as_ref().unwrap()
+    }
+}
+impl DerefMut for AsyncTempFile {
+    fn deref_mut(&mut self) -> &mut AsyncFile {
+        self.file.as_mut().unwrap()
+    }
+}
+impl std::io::Read for AsyncTempFile {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        (**self).read(buf)
+    }
+}
+impl tokio::io::AsyncRead for AsyncTempFile {}
+impl std::io::Write for AsyncTempFile {
+    #[inline]
+    fn write(&mut self, src: &[u8]) -> std::io::Result<usize> {
+        (**self).write(src)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        (**self).flush()
+    }
+}
+impl tokio::io::AsyncWrite for AsyncTempFile {
+    #[inline]
+    fn shutdown(&mut self) -> Poll<(), std::io::Error> {
+        (**self).shutdown()
+    }
+}
+impl AsyncSeek for AsyncTempFile {
+    #[inline]
+    fn poll_seek(&mut self, pos: std::io::SeekFrom) -> Poll<u64, std::io::Error> {
+        (**self).poll_seek(pos)
+    }
+}
+impl Drop for AsyncTempFile {
+    fn drop(&mut self) {
+        // дескриптор закрывается раньше удаления (на Windows открытый файл не удаляется)
+        self.file.take();
+        if let Some(path) = self.path.take() {
+            self.cpu_pool.spawn_fn(move || {
+                match std::fs::remove_file(long_path(&path)) {
+                    Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                    result => result,
+                }
+            }).forget();
+        }
+    }
+}
+impl std::fmt::Debug for AsyncTempFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("AsyncTempFile")
+            .field("path", &self.path)
+            .finish()
+    }
+}
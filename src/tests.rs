@@ -1,11 +1,8 @@
-#[cfg(test)]
-
-extern crate test;
-
 use futures_cpupool::{CpuPool};
+#[cfg(test)] use futures::Future;
 
 #[allow(dead_code)]
-const TEST_TEMPORARY_DIR: &'static str = "./test_tmp/";
+const TEST_TEMPORARY_DIR: &str = "./test_tmp/";
 
 #[allow(dead_code)]
 static TEST_BUFFER_SIZE: usize = 8 * 1024;
@@ -175,3 +172,4134 @@ fn it_stream() {
 
     assert_eq!(output, b"Hello world!\n");
 }
+
+
+#[test]
+fn it_copy() {
+    use futures::Future;
+    use std::io::Read;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path: std::path::PathBuf = format!("{}it_copy.txt", TEST_TEMPORARY_DIR).into();
+
+    let async_file_read = AsyncFileRead::from_std(
+        &TEST_CPU_POOL,
+        std::fs::File::open("./assets/hello.txt").unwrap(),
+        TEST_BUFFER_SIZE,
+    );
+    let async_file_write = AsyncFileWrite::from_std(
+        &TEST_CPU_POOL,
+        std::fs::File::create(&test_file_path).unwrap(),
+        TEST_BUFFER_SIZE,
+    );
+
+    let (amt, _, _) = io::copy(async_file_read, async_file_write)
+        .buffer_size(5)
+        .limit(12)
+        .wait().unwrap();
+
+    let mut data: Vec<u8> = Vec::new();
+    std::fs::File::open(&test_file_path).unwrap()
+        .read_to_end(&mut data).unwrap();
+
+    assert_eq!(amt, 12);
+    assert_eq!(data, b"Hello world!");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[cfg(feature = "hyper")]
+#[test]
+fn it_serve_file() {
+    use futures::{Future, Stream};
+    use hyper::StatusCode;
+    use hyper::header::{self, HeaderValue};
+    use super::*;
+
+    let (parts, body) = serve_file_with_pool(
+        &TEST_CPU_POOL,
+        "./assets/hello.txt",
+        Some(HeaderValue::from_static("bytes=6-")),
+        None,
+    ).wait().unwrap();
+
+    assert_eq!(parts.status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(parts.headers[header::CONTENT_RANGE], "bytes 6-12/13");
+    assert_eq!(&body.concat2().wait().unwrap()[..], b"world!\n");
+
+    let last_modified = parts.headers[header::LAST_MODIFIED].clone();
+    let (parts, _) = serve_file_with_pool(&TEST_CPU_POOL, "./assets/hello.txt", None, Some(last_modified))
+        .wait().unwrap();
+
+    assert_eq!(parts.status, StatusCode::NOT_MODIFIED);
+
+    let (parts, body) = serve_file_with_pool(
+        &TEST_CPU_POOL,
+        "./assets/hello.txt",
+        Some(HeaderValue::from_static("bytes=5-3")),
+        None,
+    ).wait().unwrap();
+
+    assert_eq!(parts.status, StatusCode::OK);
+    assert_eq!(&body.concat2().wait().unwrap()[..], b"Hello world!\n");
+
+    let (parts, _) = serve_file_with_pool(
+        &TEST_CPU_POOL,
+        "./assets/hello.txt",
+        Some(HeaderValue::from_static("bytes=13-")),
+        None,
+    ).wait().unwrap();
+
+    assert_eq!(parts.status, StatusCode::RANGE_NOT_SATISFIABLE);
+}
+
+
+#[test]
+fn it_save_multipart() {
+    use futures::Stream;
+    use std::io::Read;
+    use super::*;
+
+    let test_dir_path: std::path::PathBuf = format!("{}it_save_multipart/", TEST_TEMPORARY_DIR).into();
+    let _ = std::fs::remove_dir_all(&test_dir_path);
+    std::fs::create_dir_all(&test_dir_path).unwrap();
+
+    let body = "--XyZ\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+        Greeting\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"upload\"; filename=\"../../etc/hello world.txt\"\r\n\
+        Content-Type: text/plain\r\n\r\n\
+        Hello world!\r\n\
+        --XyZ--\r\n";
+    let chunks = futures::stream::iter_ok::<_, std::io::Error>(
+        body.as_bytes().chunks(7).map(|chunk| chunk.to_vec()).collect::<Vec<_>>(),
+    );
+
+    let boundary = boundary_from_content_type("multipart/form-data; boundary=XyZ").unwrap();
+    let saved = save_multipart(chunks, &boundary, &test_dir_path)
+        .cpu_pool(&TEST_CPU_POOL)
+        .collect().wait().unwrap();
+
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[1].field_name, "upload");
+    assert_eq!(saved[1].path, test_dir_path.join("hello_world.txt"));
+    assert_eq!(saved[1].size, 12);
+
+    let mut data = String::new();
+    std::fs::File::open(&saved[1].path).unwrap()
+        .read_to_string(&mut data).unwrap();
+
+    assert_eq!(data, "Hello world!");
+    assert_eq!(std::fs::read_dir(&test_dir_path).unwrap().count(), 2);
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_upload_session() {
+    use futures::Future;
+    use std::io::Read;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path: std::path::PathBuf = format!("{}it_upload_session.txt", TEST_TEMPORARY_DIR).into();
+
+    let session = UploadSession::create_with_pool(&TEST_CPU_POOL, &test_file_path)
+        .and_then(|session| session.append(0, "Hello".into()))
+        .and_then(|session| session.flush())
+        .and_then(|session| session.append(5, " wor".into()))
+        .wait().unwrap();
+    assert_eq!(session.len(), 9);
+    assert!(!session.is_empty());
+    assert_eq!(session.durable_len(), 5);
+    drop(session);
+
+    let session = UploadSession::resume_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+    assert_eq!(session.durable_len(), 5);
+
+    let path = session.append(5, " world!".into())
+        .and_then(|session| session.complete())
+        .wait().unwrap();
+
+    let mut data: Vec<u8> = Vec::new();
+    std::fs::File::open(&path).unwrap()
+        .read_to_end(&mut data).unwrap();
+
+    assert_eq!(data, b"Hello world!");
+    assert!(UploadSession::resume_with_pool(&TEST_CPU_POOL, &test_file_path).wait().is_err());
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_offset_appender() {
+    use futures::Future;
+    use std::io::Read;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path: std::path::PathBuf = format!("{}it_offset_appender.txt", TEST_TEMPORARY_DIR).into();
+    let _ = std::fs::remove_file(&test_file_path);
+
+    let appender = OffsetAppender::open_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+    let first = appender.append("Hello".into());
+    let second = appender.append(" world!".into());
+
+    assert_eq!(appender.position(), 12);
+    assert_eq!(first.join(second).wait().unwrap(), (0, 5));
+    assert_eq!(appender.sync().wait().unwrap(), 12);
+
+    let mut data: Vec<u8> = Vec::new();
+    std::fs::File::open(&test_file_path).unwrap()
+        .read_to_end(&mut data).unwrap();
+
+    assert_eq!(data, b"Hello world!");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_offset_appender_dropped() {
+    use futures::Future;
+    use super::*;
+
+    lazy_static! {
+        static ref APPENDER_POOL: CpuPool = CpuPool::new(1);
+    }
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path: std::path::PathBuf = format!("{}it_offset_appender_dropped.txt", TEST_TEMPORARY_DIR).into();
+    let _ = std::fs::remove_file(&test_file_path);
+
+    let appender = OffsetAppender::open_with_pool(&APPENDER_POOL, &test_file_path).wait().unwrap();
+
+    // единственный поток пула занят, пока не будет отправлен сигнал
+    let (release, blocked) = std::sync::mpsc::channel::<()>();
+    APPENDER_POOL.spawn_fn(move || blocked.recv().map_err(|_| ())).forget();
+
+    // результаты освобождаются до начала операций в пуле
+    drop(appender.append("Hello".into()));
+    drop(appender.barrier(true));
+    drop(appender.append(" world!".into()));
+    release.send(()).unwrap();
+
+    assert_eq!(appender.barrier(false).wait().unwrap(), 12);
+    assert_eq!(appender.durable_position(), 5);
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"Hello world!");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_block_file() {
+    use futures::{Future, Sink, Stream};
+    use std::io::{Seek, SeekFrom, Write};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path: std::path::PathBuf = format!("{}it_block_file.bin", TEST_TEMPORARY_DIR).into();
+
+    let records = futures::stream::iter_ok::<_, std::io::Error>(
+        vec!["first", "second", "third"].into_iter().map(|v| v.into()),
+    );
+    let _ = BlockSink::with_block_size(
+        AsyncFileSink::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap()),
+        64,
+        0,
+    ).send_all(records).wait().unwrap();
+
+    {
+        let mut file = std::fs::OpenOptions::new().write(true).open(&test_file_path).unwrap();
+        file.seek(SeekFrom::Start(64 + 30)).unwrap();
+        file.write_all(b"X").unwrap();
+        file.seek(SeekFrom::End(0)).unwrap();
+        file.write_all(b"torn").unwrap();
+    }
+
+    let blocks = BlockStream::with_block_size(
+        AsyncFileStream::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE),
+        64,
+    ).collect().wait().unwrap();
+
+    assert_eq!(blocks, vec![
+        Block::Data { index: 0, sequence: 0, payload: "first".into() },
+        Block::Corrupt { index: 1, error: BlockError::Checksum },
+        Block::Data { index: 2, sequence: 2, payload: "third".into() },
+        Block::Corrupt { index: 3, error: BlockError::Torn },
+    ]);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[cfg(feature = "crypto")]
+#[test]
+fn it_encrypted_sink() {
+    use futures::{Future, Sink, Stream};
+    use std::io::Read;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path: std::path::PathBuf = format!("{}it_encrypted_sink.bin", TEST_TEMPORARY_DIR).into();
+    let key = [7u8; 32];
+
+    let chunks = futures::stream::iter_ok::<_, std::io::Error>(
+        vec!["Hello", " ", "world!"].into_iter().map(|v| v.into()),
+    );
+    let _ = EncryptedSink::new(
+        &TEST_CPU_POOL,
+        AsyncFileSink::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap()),
+        &key,
+    ).send_all(chunks).wait().unwrap();
+
+    let mut data: Vec<u8> = Vec::new();
+    std::fs::File::open(&test_file_path).unwrap()
+        .read_to_end(&mut data).unwrap();
+    assert!(data.windows(5).all(|window| window != b"Hello"));
+
+    let decrypt = |key: &[u8; 32]| DecryptingStream::new(
+        &TEST_CPU_POOL,
+        AsyncFileStream::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE),
+        key,
+    ).concat2().wait();
+
+    assert_eq!(&decrypt(&key).unwrap()[..], b"Hello world!");
+    assert_eq!(decrypt(&[8u8; 32]).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[cfg(feature = "crypto")]
+#[test]
+fn it_signing_sink() {
+    use futures::{Future, Sink, Stream};
+    use std::io::Write;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path: std::path::PathBuf = format!("{}it_signing_sink.txt", TEST_TEMPORARY_DIR).into();
+    let test_signature_path = signature_path(&test_file_path);
+
+    let chunks = futures::stream::iter_ok::<_, std::io::Error>(
+        vec!["Hello", " ", "world!"].into_iter().map(|v| v.into()),
+    );
+    let _ = SigningSink::new(
+        &TEST_CPU_POOL,
+        AsyncFileSink::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap()),
+        b"secret",
+        &test_signature_path,
+    ).send_all(chunks).wait().unwrap();
+
+    let verify = || VerifyingStream::new(
+        &TEST_CPU_POOL,
+        AsyncFileStream::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE),
+        b"secret",
+        &test_signature_path,
+    ).concat2().wait();
+
+    assert_eq!(&verify().unwrap()[..], b"Hello world!");
+
+    std::fs::OpenOptions::new().append(true).open(&test_file_path).unwrap()
+        .write_all(b"!").unwrap();
+
+    assert_eq!(verify().unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+    std::fs::remove_file(test_file_path).unwrap();
+    std::fs::remove_file(test_signature_path).unwrap();
+}
+
+
+#[cfg(feature = "gzip")]
+#[test]
+fn it_indexed_gz() {
+    use futures::{Future, Sink};
+    use std::io::Read;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path: std::path::PathBuf = format!("{}it_indexed_gz.gz", TEST_TEMPORARY_DIR).into();
+
+    let data: Vec<u8> = (0..10000u32).map(|i| (i % 251) as u8).collect();
+    let chunks = futures::stream::iter_ok::<_, std::io::Error>(
+        data.chunks(300).map(|chunk| chunk.to_vec().into()).collect::<Vec<_>>(),
+    );
+    let (sink, _) = IndexedGzSink::new(
+        &TEST_CPU_POOL,
+        AsyncFileSink::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap()),
+    ).span(1000).send_all(chunks).wait().unwrap();
+    let index = sink.index().clone();
+
+    assert_eq!(index.entries.len(), 10);
+    assert_eq!(index.uncompressed_len, 10000);
+    assert_eq!(GzIndex::scan(&TEST_CPU_POOL, &test_file_path).wait().unwrap(), index);
+
+    let mut output = Vec::new();
+    flate2::read::MultiGzDecoder::new(std::fs::File::open(&test_file_path).unwrap())
+        .read_to_end(&mut output).unwrap();
+    assert_eq!(output, data);
+
+    let file = IndexedGzFile::new(&TEST_CPU_POOL, &test_file_path, index);
+    assert_eq!(&file.read_range(4500, 1000).wait().unwrap()[..], &data[4500..5500]);
+    assert_eq!(&file.read_range(9990, 100).wait().unwrap()[..], &data[9990..]);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_files_equal() {
+    use futures::{Future, Stream};
+    use std::io::Write;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path_a: std::path::PathBuf = format!("{}it_files_equal_a.txt", TEST_TEMPORARY_DIR).into();
+    let test_file_path_b: std::path::PathBuf = format!("{}it_files_equal_b.txt", TEST_TEMPORARY_DIR).into();
+
+    std::fs::File::create(&test_file_path_a).unwrap().write_all(b"Hello world!").unwrap();
+    std::fs::File::create(&test_file_path_b).unwrap().write_all(b"Hello world!").unwrap();
+
+    assert!(files_equal_with_pool(&TEST_CPU_POOL, &test_file_path_a, &test_file_path_b).chunk_size(5).wait().unwrap());
+
+    std::fs::File::create(&test_file_path_b).unwrap().write_all(b"Jello wOrld!!!").unwrap();
+
+    assert!(!files_equal_with_pool(&TEST_CPU_POOL, &test_file_path_a, &test_file_path_b).wait().unwrap());
+    assert_eq!(
+        diff_ranges_with_pool(&TEST_CPU_POOL, &test_file_path_a, &test_file_path_b)
+            .chunk_size(4)
+            .collect().wait().unwrap(),
+        vec![0..1, 7..8, 12..14],
+    );
+
+    std::fs::remove_file(test_file_path_a).unwrap();
+    std::fs::remove_file(test_file_path_b).unwrap();
+}
+
+
+#[test]
+fn it_find_duplicates() {
+    use futures::Stream;
+    use std::io::Write;
+    use super::*;
+
+    let test_dir_path: std::path::PathBuf = format!("{}it_find_duplicates", TEST_TEMPORARY_DIR).into();
+
+    std::fs::create_dir_all(test_dir_path.join("nested")).unwrap();
+    std::fs::File::create(test_dir_path.join("a.txt")).unwrap().write_all(b"Hello world!").unwrap();
+    std::fs::File::create(test_dir_path.join("nested/b.txt")).unwrap().write_all(b"Hello world!").unwrap();
+    std::fs::File::create(test_dir_path.join("c.txt")).unwrap().write_all(b"Hello World!").unwrap();
+    std::fs::File::create(test_dir_path.join("d.txt")).unwrap().write_all(b"Hello").unwrap();
+    std::fs::File::create(test_dir_path.join("e.txt")).unwrap();
+    std::fs::File::create(test_dir_path.join("nested/f.txt")).unwrap();
+
+    let mut entries = walk_with_pool(&TEST_CPU_POOL, &test_dir_path)
+        .batch_size(2)
+        .map(|entry| (entry.path, entry.depth))
+        .collect().wait().unwrap();
+    entries.sort();
+    assert_eq!(entries.len(), 7);
+    assert_eq!(entries[4], (test_dir_path.join("nested"), 1));
+    assert_eq!(entries[6], (test_dir_path.join("nested/f.txt"), 2));
+
+    let groups = find_duplicates_with_pool(&TEST_CPU_POOL, &test_dir_path)
+        .partial_size(4)
+        .collect().wait().unwrap();
+    assert_eq!(groups, vec![DuplicateGroup {
+        size: 12,
+        paths: vec![test_dir_path.join("a.txt"), test_dir_path.join("nested/b.txt")],
+    }]);
+
+    // группа с совпавшими хешами, но разным содержимым (как при коллизии хешей)
+    let groups = duplicates::confirm_group(&TEST_CPU_POOL, DuplicateGroup {
+        size: 12,
+        paths: vec![test_dir_path.join("c.txt"), test_dir_path.join("a.txt"), test_dir_path.join("nested/b.txt")],
+    }).wait().unwrap();
+    assert_eq!(groups, vec![DuplicateGroup {
+        size: 12,
+        paths: vec![test_dir_path.join("a.txt"), test_dir_path.join("nested/b.txt")],
+    }]);
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_walk_reports() {
+    use futures::Stream;
+    use std::io::Write;
+    use super::*;
+
+    let test_dir_path: std::path::PathBuf = format!("{}it_walk_reports", TEST_TEMPORARY_DIR).into();
+
+    std::fs::create_dir_all(test_dir_path.join("nested")).unwrap();
+    std::fs::File::create(test_dir_path.join("a.txt")).unwrap().write_all(b"Hello world!").unwrap();
+    std::fs::File::create(test_dir_path.join("b.log")).unwrap().write_all(b"Hello").unwrap();
+    std::fs::File::create(test_dir_path.join("nested/c.txt")).unwrap().write_all(b"Hello!").unwrap();
+    std::fs::File::create(test_dir_path.join("nested/d")).unwrap().write_all(b"H").unwrap();
+
+    let largest = walk_with_pool(&TEST_CPU_POOL, &test_dir_path)
+        .batch_size(1)
+        .largest_files(2)
+        .collect().wait().unwrap();
+    let largest: Vec<_> = largest.last().unwrap().iter().map(|entry| entry.path.clone()).collect();
+    assert_eq!(largest, vec![test_dir_path.join("a.txt"), test_dir_path.join("nested/c.txt")]);
+
+    let totals = walk_with_pool(&TEST_CPU_POOL, &test_dir_path)
+        .extension_totals()
+        .collect().wait().unwrap();
+    let totals = totals.last().unwrap();
+    assert_eq!(totals.len(), 3);
+    assert_eq!(totals[&Some("txt".to_string())], ExtensionTotal { files: 2, size: 18 });
+    assert_eq!(totals[&None], ExtensionTotal { files: 1, size: 1 });
+
+    let future = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+    assert_eq!(walk_with_pool(&TEST_CPU_POOL, &test_dir_path).older_than(future).collect().wait().unwrap().len(), 4);
+    assert_eq!(walk_with_pool(&TEST_CPU_POOL, &test_dir_path).older_than(std::time::UNIX_EPOCH).collect().wait().unwrap().len(), 0);
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_list_dir() {
+    use std::io::Write;
+    use super::*;
+
+    let test_dir_path: std::path::PathBuf = format!("{}it_list_dir", TEST_TEMPORARY_DIR).into();
+
+    std::fs::create_dir_all(test_dir_path.join("d")).unwrap();
+    std::fs::File::create(test_dir_path.join("b.txt")).unwrap().write_all(b"Hello world!").unwrap();
+    std::fs::File::create(test_dir_path.join("a.txt")).unwrap().write_all(b"Hello").unwrap();
+    std::fs::File::create(test_dir_path.join("c.txt")).unwrap().write_all(b"Hello!").unwrap();
+
+    let names = |entries: Vec<ListEntry>| -> Vec<String> {
+        entries.into_iter().map(|entry| entry.file_name.into_string().unwrap()).collect()
+    };
+
+    let entries = list_dir_with_pool(&TEST_CPU_POOL, &test_dir_path, SortBy::Name, false).wait().unwrap();
+    assert!(entries.iter().all(|entry| entry.metadata.is_none()));
+    assert!(entries[3].file_type.is_dir());
+    assert_eq!(names(entries), vec!["a.txt", "b.txt", "c.txt", "d"]);
+
+    let entries = list_dir_with_pool(&TEST_CPU_POOL, &test_dir_path, SortBy::Size, true).wait().unwrap();
+    assert!(entries.iter().all(|entry| entry.metadata.is_some()));
+    let files = entries.into_iter().filter(|entry| entry.file_type.is_file()).collect();
+    assert_eq!(names(files), vec!["a.txt", "c.txt", "b.txt"]);
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_walk_ignore_rules() {
+    use futures::Stream;
+    use super::*;
+
+    let test_dir_path: std::path::PathBuf = format!("{}it_walk_ignore_rules", TEST_TEMPORARY_DIR).into();
+
+    for dir in &["target/debug", "src/target", "node_modules/lib", "docs"] {
+        std::fs::create_dir_all(test_dir_path.join(dir)).unwrap();
+    }
+    for file in &["src/lib.rs", "src/target/mod.rs", "target/debug/app", "node_modules/lib/index.js",
+                  "docs/a.log", "docs/keep.log", "docs/b[1].md"] {
+        std::fs::File::create(test_dir_path.join(file)).unwrap();
+    }
+
+    let rules = IgnoreRules::new()
+        .patterns("# build output\n/target/\nnode_modules/\n*.log\n!keep.log\n")
+        .pattern("docs/b\\[[0-9]\\].md")
+        .exclude_if(|entry| entry.path.extension().is_some_and(|extension| extension == "js"));
+    let mut paths = walk_with_pool(&TEST_CPU_POOL, &test_dir_path)
+        .with_ignore_rules(rules)
+        .map(|entry| entry.path.strip_prefix(&test_dir_path).unwrap().to_string_lossy().into_owned())
+        .collect().wait().unwrap();
+    paths.sort();
+    assert_eq!(paths, vec!["docs", "docs/keep.log", "src", "src/lib.rs", "src/target", "src/target/mod.rs"]);
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_safe_path() {
+    use super::*;
+
+    let test_dir_path: std::path::PathBuf = format!("{}it_safe_path", TEST_TEMPORARY_DIR).into();
+    let root = test_dir_path.join("root");
+
+    std::fs::create_dir_all(root.join("public")).unwrap();
+    std::fs::File::create(root.join("public/index.html")).unwrap();
+    std::fs::File::create(test_dir_path.join("secret.txt")).unwrap();
+
+    let resolve = |untrusted: &str| SafePath::resolve_with_pool(&TEST_CPU_POOL, &root, untrusted).wait();
+
+    let path = resolve("./public/../public/index.html").unwrap();
+    assert_eq!(path.relative(), std::path::Path::new("public/index.html"));
+    assert!(std::fs::File::open(&path).is_ok());
+    assert_eq!(resolve("public/new/file.txt").unwrap().relative(), std::path::Path::new("public/new/file.txt"));
+
+    assert_eq!(resolve("../secret.txt").unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+    assert_eq!(resolve("public/../../secret.txt").unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+    assert_eq!(resolve("/etc/passwd").unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink("../secret.txt", root.join("escape")).unwrap();
+        std::os::unix::fs::symlink("public", root.join("inside")).unwrap();
+        assert_eq!(resolve("escape").unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+        assert_eq!(resolve("inside/index.html").unwrap().relative(), std::path::Path::new("public/index.html"));
+    }
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[cfg(unix)]
+#[test]
+fn it_root_dir() {
+    use std::io::{Read, Write};
+    use super::*;
+
+    let test_dir_path: std::path::PathBuf = format!("{}it_root_dir", TEST_TEMPORARY_DIR).into();
+    let root_path = test_dir_path.join("root");
+
+    std::fs::create_dir_all(root_path.join("public")).unwrap();
+    std::fs::File::create(root_path.join("public/index.html")).unwrap().write_all(b"Hello world!").unwrap();
+    std::fs::File::create(test_dir_path.join("secret.txt")).unwrap();
+    std::os::unix::fs::symlink("../secret.txt", root_path.join("escape")).unwrap();
+    std::os::unix::fs::symlink("../../secret.txt", root_path.join("public/escape")).unwrap();
+
+    let root = RootDir::open_with_pool(&TEST_CPU_POOL, &root_path).wait().unwrap();
+
+    assert_eq!(&root.read("public/index.html").wait().unwrap()[..], b"Hello world!");
+    assert!(root.metadata("public").wait().unwrap().is_dir());
+    assert!(root.metadata("escape").wait().unwrap().file_type().is_symlink());
+
+    assert_eq!(root.open_file("../secret.txt").wait().unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+    assert_eq!(root.open_file("/etc/passwd").wait().unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    assert!(root.open_file("escape").wait().is_err());
+    assert!(root.open_file("public/escape").wait().is_err());
+
+    root.create_dir("uploads").wait().unwrap();
+    root.create_file("uploads/a.txt").wait().unwrap().write_all(b"uploaded").unwrap();
+    let uploads = root.open_dir("uploads").wait().unwrap();
+    let mut data = String::new();
+    uploads.open_file("a.txt").wait().unwrap().read_to_string(&mut data).unwrap();
+    assert_eq!(data, "uploaded");
+    uploads.remove_file("a.txt").wait().unwrap();
+    root.remove_dir("uploads").wait().unwrap();
+    assert!(!root_path.join("uploads").exists());
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_find_case_insensitive() {
+    use super::*;
+
+    let test_dir_path: std::path::PathBuf = format!("{}it_find_case_insensitive", TEST_TEMPORARY_DIR).into();
+
+    std::fs::create_dir_all(test_dir_path.join("Photos/2019")).unwrap();
+    std::fs::File::create(test_dir_path.join("Photos/2019/IMG_0001.JPG")).unwrap();
+
+    let find = |name: &str| find_case_insensitive_with_pool(&TEST_CPU_POOL, &test_dir_path, name).wait().unwrap();
+
+    assert_eq!(find("photos/2019/img_0001.jpg"), Some(test_dir_path.join("Photos/2019/IMG_0001.JPG")));
+    assert_eq!(find("./PHOTOS/2019"), Some(test_dir_path.join("Photos/2019")));
+    assert_eq!(find("photos/2019/img_0002.jpg"), None);
+    assert_eq!(find("videos/a.mp4"), None);
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_long_path() {
+    use futures::Stream;
+    use std::io::Write;
+    use super::*;
+
+    assert_eq!(sys::verbatim_windows_path(r"C:\data\a\..\b\.\file.txt").unwrap(), r"\\?\C:\data\b\file.txt");
+    assert_eq!(sys::verbatim_windows_path("C:/data/file.txt").unwrap(), r"\\?\C:\data\file.txt");
+    assert_eq!(sys::verbatim_windows_path(r"C:\..\..").unwrap(), r"\\?\C:\");
+    assert_eq!(sys::verbatim_windows_path(r"\\server\share\dir\file.txt").unwrap(), r"\\?\UNC\server\share\dir\file.txt");
+    assert_eq!(sys::verbatim_windows_path(r"\\server\share\..\x").unwrap(), r"\\?\UNC\server\share\x");
+    assert_eq!(sys::verbatim_windows_path(r"\\?\C:\data"), None);
+    assert_eq!(sys::verbatim_windows_path(r"\\.\PhysicalDrive0"), None);
+    assert_eq!(sys::verbatim_windows_path(r"data\file.txt"), None);
+    assert_eq!(sys::verbatim_windows_path(r"\\server"), None);
+
+    // глубокое дерево с путями длиннее MAX_PATH
+    let test_dir_path: std::path::PathBuf = format!("{}it_long_path", TEST_TEMPORARY_DIR).into();
+    let mut deep_path = test_dir_path.clone();
+    for i in 0..12 {
+        deep_path.push(format!("{:02}-{}", i, "x".repeat(24)));
+    }
+    assert!(deep_path.as_os_str().len() > 260);
+    std::fs::create_dir_all(sys::long_path(&deep_path)).unwrap();
+    std::fs::File::create(sys::long_path(&deep_path.join("file.txt"))).unwrap().write_all(b"Hello world!").unwrap();
+
+    let files = walk_with_pool(&TEST_CPU_POOL, &test_dir_path)
+        .filter(|entry| entry.metadata.is_file())
+        .collect().wait().unwrap();
+    assert_eq!(files.len(), 1);
+    assert_eq!(files[0].path, deep_path.join("file.txt"));
+    assert!(files_equal_with_pool(&TEST_CPU_POOL, &files[0].path, deep_path.join("file.txt")).wait().unwrap());
+
+    std::fs::remove_dir_all(sys::long_path(&test_dir_path)).unwrap();
+}
+
+
+#[test]
+fn it_async_open_options() {
+    use std::io::{Read, Write};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path: std::path::PathBuf = format!("{}it_async_open_options.txt", TEST_TEMPORARY_DIR).into();
+
+    let mut options = AsyncOpenOptions::new();
+    options.cpu_pool(&TEST_CPU_POOL).write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(&test_file_path).wait().unwrap().write_all(b"Hello world!").unwrap();
+    assert_eq!(options.open(&test_file_path).wait().unwrap_err().kind(), std::io::ErrorKind::AlreadyExists);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(std::fs::metadata(&test_file_path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    let mut data = String::new();
+    AsyncOpenOptions::new().cpu_pool(&TEST_CPU_POOL).read(true)
+        .open(&test_file_path).wait().unwrap()
+        .read_to_string(&mut data).unwrap();
+    assert_eq!(data, "Hello world!");
+
+    std::fs::remove_file(&test_file_path).unwrap();
+}
+
+
+#[cfg(windows)]
+#[test]
+fn it_async_open_options_windows() {
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path: std::path::PathBuf = format!("{}it_async_open_options_windows.txt", TEST_TEMPORARY_DIR).into();
+
+    let file = AsyncOpenOptions::new()
+        .cpu_pool(&TEST_CPU_POOL)
+        .write(true)
+        .create(true)
+        .share_mode(0)
+        .delete_on_close(true)
+        .open(&test_file_path).wait().unwrap();
+
+    // режим совместного доступа 0 запрещает повторное открытие
+    assert!(AsyncOpenOptions::new().read(true).open(&test_file_path).wait().is_err());
+
+    drop(file);
+    assert!(!test_file_path.exists());
+}
+
+
+#[test]
+fn it_file_lock() {
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path: std::path::PathBuf = format!("{}it_file_lock.lock", TEST_TEMPORARY_DIR).into();
+
+    let lock_a = FileLock::open_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+    let lock_b = FileLock::open_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+
+    let shared_a = lock_a.lock(LockMode::Shared).wait().unwrap();
+    let shared_b = lock_b.try_lock(LockMode::Shared).unwrap().unwrap();
+    drop(shared_b);
+
+    let error = lock_b.lock(LockMode::Exclusive)
+        .timeout(std::time::Duration::from_millis(20))
+        .wait().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+
+    let waiting = lock_b.lock(LockMode::Exclusive);
+    std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        shared_a.unlock().unwrap();
+    });
+    let exclusive = waiting.wait().unwrap();
+    assert_eq!(exclusive.mode(), LockMode::Exclusive);
+    assert!(lock_a.try_lock(LockMode::Shared).unwrap().is_none());
+    drop(exclusive);
+    assert!(lock_a.try_lock(LockMode::Exclusive).unwrap().is_some());
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_file_lock_range() {
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path: std::path::PathBuf = format!("{}it_file_lock_range.dat", TEST_TEMPORARY_DIR).into();
+
+    let lock_a = FileLock::open_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+    let lock_b = FileLock::open_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+
+    let first = lock_a.lock_range(0, 10, LockMode::Exclusive).wait().unwrap();
+    assert_eq!(first.range(), Some((0, 10)));
+
+    // непересекающиеся диапазоны не мешают друг другу
+    let second = lock_b.lock_range(10, 10, LockMode::Exclusive).wait().unwrap();
+    assert!(lock_b.try_lock_range(5, 10, LockMode::Shared).unwrap().is_none());
+    assert!(lock_a.try_lock_range(15, 10, LockMode::Exclusive).unwrap().is_none());
+
+    let error = lock_b.lock_range(0, 1, LockMode::Shared)
+        .timeout(std::time::Duration::from_millis(20))
+        .wait().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+
+    drop(first);
+    let shared_a = lock_a.try_lock_range(0, 10, LockMode::Shared).unwrap().unwrap();
+    let shared_b = lock_b.try_lock_range(0, 10, LockMode::Shared).unwrap().unwrap();
+    drop(shared_a);
+    drop(shared_b);
+    second.unlock().unwrap();
+
+    // блокировка диапазона за концом файла
+    let tail = lock_a.lock_range(1 << 40, 1, LockMode::Exclusive).wait().unwrap();
+    assert!(lock_b.try_lock_range(0, 1 << 40, LockMode::Exclusive).unwrap().is_some());
+    drop(tail);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_retry_interrupted() {
+    use super::*;
+
+    let mut attempts = 0;
+    let result = sys::retry(|| {
+        attempts += 1;
+        if attempts < 3 {
+            return Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "interrupted"));
+        }
+        Ok(attempts)
+    });
+    assert_eq!(result.unwrap(), 3);
+
+    let error = sys::retry(|| Err::<(), _>(std::io::Error::new(std::io::ErrorKind::NotFound, "not found"))).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+
+    set_retry_interrupted(false);
+    let mut attempts = 0;
+    let error = sys::retry(|| {
+        attempts += 1;
+        Err::<(), _>(std::io::Error::new(std::io::ErrorKind::Interrupted, "interrupted"))
+    }).unwrap_err();
+    set_retry_interrupted(true);
+    assert_eq!(error.kind(), std::io::ErrorKind::Interrupted);
+    assert_eq!(attempts, 1);
+}
+
+
+#[test]
+fn it_read_write_outside_task() {
+    use super::*;
+    use std::io::{Read, Write};
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_read_write_outside_task.txt", TEST_TEMPORARY_DIR);
+
+    // опрос без контекста задачи: `WouldBlock` до завершения операции в пуле
+    let mut writer = AsyncFileWrite::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap(), 1024);
+    let size = loop {
+        match writer.write(b"hello world") {
+            Ok(size) => break size,
+            Err(ref error) if error.kind() == std::io::ErrorKind::WouldBlock => std::thread::sleep(std::time::Duration::from_millis(1)),
+            Err(error) => panic!("{}", error),
+        }
+    };
+    assert_eq!(size, 11);
+    drop(writer);
+
+    let mut reader = AsyncFileRead::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), 1024);
+    let mut buf = [0u8; 64];
+    let size = loop {
+        match reader.read(&mut buf) {
+            Ok(size) => break size,
+            Err(ref error) if error.kind() == std::io::ErrorKind::WouldBlock => std::thread::sleep(std::time::Duration::from_millis(1)),
+            Err(error) => panic!("{}", error),
+        }
+    };
+    assert_eq!(&buf[..size], b"hello world");
+
+    // опрос внутри задачи: задача пробуждается по завершении чтения
+    let reader = AsyncFileRead::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), 1024);
+    let (_, data) = tokio::io::read_to_end(reader, Vec::new()).wait().unwrap();
+    assert_eq!(&data[..], b"hello world");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_poll_ready() {
+    use super::*;
+    use futures::Sink;
+    use std::io::Write;
+
+    lazy_static! {
+        static ref POLL_READY_POOL: futures_cpupool::CpuPool = futures_cpupool::CpuPool::new(1);
+    }
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_poll_ready.txt", TEST_TEMPORARY_DIR);
+
+    let mut sink = AsyncFileSink::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap());
+    futures::future::poll_fn(|| sink.poll_ready()).wait().unwrap();
+    for chunk in &["one ", "two ", "three"] {
+        assert!(sink.start_send(Bytes::from(*chunk)).unwrap().is_ready());
+        futures::future::poll_fn(|| sink.poll_ready()).wait().unwrap();
+    }
+    futures::future::poll_fn(|| sink.poll_complete()).wait().unwrap();
+    drop(sink);
+    assert_eq!(std::fs::read_to_string(&test_file_path).unwrap(), "one two three");
+
+    // единственный поток пула занят, пока не будет отправлен сигнал
+    let (release, blocked) = std::sync::mpsc::channel::<()>();
+    POLL_READY_POOL.spawn_fn(move || blocked.recv().map_err(|_| ())).forget();
+
+    let mut writer = AsyncFileWrite::from_std(&POLL_READY_POOL, std::fs::File::create(&test_file_path).unwrap(), 1024);
+    futures::future::poll_fn(|| writer.poll_ready()).wait().unwrap();
+    futures::future::lazy(|| {
+        let error = writer.write(b"four").unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::WouldBlock);
+        assert!(writer.poll_ready().unwrap().is_not_ready());
+        Ok::<_, ()>(())
+    }).wait().unwrap();
+    release.send(()).unwrap();
+    futures::future::poll_fn(|| writer.poll_ready()).wait().unwrap();
+    // после готовности результат начатой записи возвращается сразу
+    assert_eq!(writer.write(b"four").unwrap(), 4);
+    drop(writer);
+    assert_eq!(std::fs::read_to_string(&test_file_path).unwrap(), "four");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[cfg(feature = "futures03")]
+#[test]
+fn it_sink_futures03() {
+    use super::*;
+    use futures03::{SinkExt, StreamExt};
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_sink_futures03.txt", TEST_TEMPORARY_DIR);
+
+    let mut sink = AsyncFileSink::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap());
+    let mut chunks = futures03::stream::iter(vec!["one ", "two ", "three"]).map(|chunk| Ok(Bytes::from(chunk)));
+    futures03::executor::block_on(sink.send_all(&mut chunks)).unwrap();
+    futures03::executor::block_on(sink.send(Bytes::from(" four"))).unwrap();
+    futures03::executor::block_on(sink.close()).unwrap();
+    futures03::executor::block_on(sink.close()).unwrap();
+    assert!(std::fs::File::try_from(sink).is_err());
+    assert_eq!(std::fs::read_to_string(&test_file_path).unwrap(), "one two three four");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_timeouted() {
+    use super::*;
+    use futures::Stream;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_timeouted.txt", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"hello").unwrap();
+
+    // пул занят, поэтому чтение не успевает начаться до истечения срока
+    lazy_static! {
+        static ref SINGLE_THREAD_POOL: CpuPool = CpuPool::new(1);
+    }
+    let busy = SINGLE_THREAD_POOL.spawn_fn(|| {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        Ok::<(), ()>(())
+    });
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let mut stream = AsyncFileStream::from_std(&SINGLE_THREAD_POOL, file, 1024)
+        .timeout(std::time::Duration::from_millis(10));
+    let error = futures::future::poll_fn(|| stream.poll()).wait().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+    assert!(error.get_ref().is_some_and(|error| error.is::<Elapsed>()));
+
+    // начатая операция продолжает отслеживаться и завершается после освобождения пула
+    busy.wait().unwrap();
+    let mut stream = stream.into_inner();
+    let chunk = futures::future::poll_fn(|| stream.poll()).wait().unwrap();
+    assert_eq!(chunk, Some(Bytes::from("hello")));
+    assert_eq!(futures::future::poll_fn(|| stream.poll()).wait().unwrap(), None);
+    assert!(std::fs::File::try_from(stream).is_ok());
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_deadline_drop() {
+    use futures::Future;
+    use timeout::{Deadline, timer_entries};
+
+    // освобожденные до истечения сроки не накапливаются в очереди таймера
+    futures::future::lazy(|| {
+        for _ in 0..1000 {
+            let mut deadline = Deadline::new(std::time::Instant::now() + std::time::Duration::from_secs(3600));
+            assert!(!deadline.poll_elapsed());
+        }
+        Ok::<_, ()>(())
+    }).wait().unwrap();
+    assert!(timer_entries() < 100);
+}
+
+
+#[test]
+fn it_serialized_file() {
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_serialized_file.log", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_file(&test_file_path);
+
+    let log = SerializedFile::open_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+
+    // записи из разных потоков выполняются в порядке передачи каждым из них
+    let writers: Vec<_> = (0..4u8)
+        .map(|writer| {
+            let log = log.clone();
+            std::thread::spawn(move || {
+                let ops: Vec<_> = (0..25u8)
+                    .map(|i| log.write(Bytes::from(vec![writer, i])))
+                    .collect();
+                futures::future::join_all(ops).wait().unwrap();
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let sync = log.sync_data();
+    let metadata = log.metadata();
+    let panicked = log.submit(|_| -> std::io::Result<()> { panic!("operation failed") });
+    let data = log.read_to_end();
+    sync.wait().unwrap();
+    assert_eq!(metadata.wait().unwrap().len(), 200);
+    assert!(panicked.wait().is_err());
+    let data = data.wait().unwrap();
+    assert_eq!(data.len(), 200);
+    for writer in 0..4u8 {
+        let records: Vec<u8> = data.chunks(2)
+            .filter(|record| record[0] == writer)
+            .map(|record| record[1])
+            .collect();
+        assert_eq!(records, (0..25u8).collect::<Vec<_>>());
+    }
+
+    assert_eq!(log.read_at(198, 10).wait().unwrap().len(), 2);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_appender_barrier() {
+    use super::*;
+
+    lazy_static! {
+        static ref PARALLEL_POOL: CpuPool = CpuPool::new(4);
+    }
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_appender_barrier.log", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_file(&test_file_path);
+
+    let appender = OffsetAppender::open_with_pool(&PARALLEL_POOL, &test_file_path).wait().unwrap();
+
+    let before: Vec<_> = (0..32u8).map(|i| appender.append(Bytes::from(vec![i; 1024]))).collect();
+    // барьер ожидает все ранее переданные записи, не дожидаясь их на стороне вызывающего кода
+    let barrier = appender.barrier(true);
+    let after: Vec<_> = (0..32u8).map(|i| appender.append(Bytes::from(vec![i; 16]))).collect();
+    let second = appender.barrier(false);
+
+    assert_eq!(barrier.wait().unwrap(), 32 * 1024);
+    assert!(appender.durable_position() >= 32 * 1024);
+    assert_eq!(second.wait().unwrap(), 32 * 1024 + 32 * 16);
+    for (i, offset) in futures::future::join_all(before).wait().unwrap().into_iter().enumerate() {
+        assert_eq!(offset, i as u64 * 1024);
+    }
+    for offset in futures::future::join_all(after).wait().unwrap() {
+        assert!(offset >= 32 * 1024);
+    }
+
+    // пустая эпоха между барьерами
+    assert_eq!(appender.barrier(false).wait().unwrap(), 32 * 1024 + 32 * 16);
+    assert_eq!(std::fs::metadata(&test_file_path).unwrap().len(), 32 * 1024 + 32 * 16);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_append_file() {
+    use super::*;
+
+    lazy_static! {
+        static ref PARALLEL_POOL: CpuPool = CpuPool::new(4);
+    }
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_append_file.log", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_file(&test_file_path);
+
+    // отдельные дескрипторы ведут себя как независимые процессы
+    let handles: Vec<AppendFile> = (0..4)
+        .map(|_| AppendFile::open_with_pool(&PARALLEL_POOL, &test_file_path).wait().unwrap())
+        .collect();
+    let records: Vec<_> = (0..64usize)
+        .map(|i| {
+            let record = vec![b'a' + (i % 26) as u8; ATOMIC_APPEND_LIMIT];
+            (record.clone(), handles[i % handles.len()].append(Bytes::from(record)))
+        })
+        .collect();
+    let mut offsets = Vec::new();
+    for (record, offset) in records {
+        offsets.push((offset.wait().unwrap(), record));
+    }
+    handles[0].sync_data().wait().unwrap();
+
+    let data = std::fs::read(&test_file_path).unwrap();
+    assert_eq!(data.len(), 64 * ATOMIC_APPEND_LIMIT);
+    offsets.sort_by_key(|&(offset, _)| offset);
+    for (i, (offset, record)) in offsets.into_iter().enumerate() {
+        assert_eq!(offset, (i * ATOMIC_APPEND_LIMIT) as u64);
+        assert_eq!(&data[offset as usize..offset as usize + record.len()], &record[..]);
+    }
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_append_coordinator() {
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_append_coordinator.log", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"header\n").unwrap();
+
+    let coordinator = AppendCoordinator::open_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap()
+        .max_batch_size(64)
+        .sync_batches(true);
+
+    let tasks: Vec<_> = (0..4usize)
+        .map(|task| {
+            let coordinator = coordinator.clone();
+            std::thread::spawn(move || {
+                let records: Vec<(String, AppendRecord)> = (0..20usize)
+                    .map(|i| {
+                        let record = format!("task {} record {}\n", task, i);
+                        let offset = coordinator.append(Bytes::from(record.clone()));
+                        (record, offset)
+                    })
+                    .collect();
+                records.into_iter()
+                    .map(|(record, offset)| (record, offset.wait().unwrap()))
+                    .collect::<Vec<_>>()
+            })
+        })
+        .collect();
+    let records: Vec<(String, u64)> = tasks.into_iter()
+        .flat_map(|task| task.join().unwrap())
+        .collect();
+
+    let data = std::fs::read_to_string(&test_file_path).unwrap();
+    assert!(data.starts_with("header\n"));
+    assert_eq!(data.lines().count(), 81);
+    for (record, offset) in records {
+        assert!(offset >= 7);
+        assert_eq!(&data[offset as usize..offset as usize + record.len()], &record[..]);
+    }
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_set_len() {
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_set_len.txt", TEST_TEMPORARY_DIR);
+
+    let writer = AsyncFileWrite::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap(), 1024);
+    let (mut writer, _) = tokio::io::write_all(writer, b"hello world, corrupt tail").wait().unwrap();
+    futures::future::poll_fn(|| writer.poll_set_len(11)).wait().unwrap();
+    drop(writer);
+    assert_eq!(std::fs::read_to_string(&test_file_path).unwrap(), "hello world");
+
+    truncate_with_pool(&TEST_CPU_POOL, &test_file_path, 5).wait().unwrap();
+    assert_eq!(std::fs::read_to_string(&test_file_path).unwrap(), "hello");
+    truncate_with_pool(&TEST_CPU_POOL, &test_file_path, 8).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"hello\0\0\0");
+
+    let missing = format!("{}it_set_len_missing.txt", TEST_TEMPORARY_DIR);
+    let error = truncate_with_pool(&TEST_CPU_POOL, &missing, 0).wait().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_trim_on_shutdown() {
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_trim_on_shutdown.txt", TEST_TEMPORARY_DIR);
+
+    // предварительно выделенное место, заполненное нулями
+    let file = std::fs::File::create(&test_file_path).unwrap();
+    file.set_len(4096).unwrap();
+    let writer = AsyncFileWrite::from_std(&TEST_CPU_POOL, file, 1024).trim_on_shutdown(true);
+    let (writer, _) = tokio::io::write_all(writer, b"hello world").wait().unwrap();
+    tokio::io::shutdown(writer).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"hello world");
+
+    let file = std::fs::File::create(&test_file_path).unwrap();
+    file.set_len(4096).unwrap();
+    let mut sink = AsyncFileSink::from_std(&TEST_CPU_POOL, file).trim_on_close(true);
+    futures::Sink::start_send(&mut sink, Bytes::from("hello sink")).unwrap();
+    futures::future::poll_fn(|| sink.poll_close()).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"hello sink");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_stream_windows() {
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_stream_windows.txt", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"0123456789abcdefghij").unwrap();
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let mut stream = AsyncFileStream::from_std(&TEST_CPU_POOL, file, 4).limit(6);
+    let window = futures::Stream::concat2(&mut stream).wait().unwrap();
+    assert_eq!(&window[..], b"012345");
+    assert_eq!(stream.remaining(), Some(0));
+
+    // следующее окно того же файла
+    let position = futures::future::poll_fn(|| stream.poll_seek(std::io::SeekFrom::Start(10))).wait().unwrap();
+    assert_eq!(position, 10);
+    stream.set_limit(Some(3));
+    let window = futures::Stream::concat2(&mut stream).wait().unwrap();
+    assert_eq!(&window[..], b"abc");
+
+    // окно, выходящее за конец файла
+    futures::future::poll_fn(|| stream.poll_seek(std::io::SeekFrom::End(-2))).wait().unwrap();
+    stream.set_limit(Some(10));
+    let window = futures::Stream::concat2(&mut stream).wait().unwrap();
+    assert_eq!(&window[..], b"ij");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_skip_take_bytes() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_skip_take_bytes.txt", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"0123456789abcdefghij").unwrap();
+
+    let chunks = futures::stream::iter_ok::<_, std::io::Error>(vec![
+        Bytes::from("0123"), Bytes::from("4567"), Bytes::from("89ab"),
+    ]);
+    let range = take_bytes(skip_bytes(chunks, 3), 6).concat2().wait().unwrap();
+    assert_eq!(&range[..], b"345678");
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let stream = AsyncFileStream::from_std(&TEST_CPU_POOL, file, 4);
+    let range = take_bytes(skip_bytes_seek(stream, 7), 9).concat2().wait().unwrap();
+    assert_eq!(&range[..], b"789abcdef");
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let stream = AsyncFileStream::from_std(&TEST_CPU_POOL, file, 4);
+    let range = take_bytes(skip_bytes_seek(stream, 18), 9).concat2().wait().unwrap();
+    assert_eq!(&range[..], b"ij");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_concat_files() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let paths: Vec<String> = (0..3).map(|i| format!("{}it_concat_files_{}.txt", TEST_TEMPORARY_DIR, i)).collect();
+    std::fs::write(&paths[0], b"first ").unwrap();
+    std::fs::write(&paths[1], b"").unwrap();
+    std::fs::write(&paths[2], b"third segment").unwrap();
+
+    let body = concat_files_with_pool(&TEST_CPU_POOL, &paths).buffer_size(4).concat2().wait().unwrap();
+    assert_eq!(&body[..], b"first third segment");
+
+    let missing = format!("{}it_concat_files_missing.txt", TEST_TEMPORARY_DIR);
+    let result = concat_files_with_pool(&TEST_CPU_POOL, vec![&paths[0], &missing]).concat2().wait();
+    assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+    for path in paths {
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+
+#[test]
+fn it_tee_to_file() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_tee_to_file.txt", TEST_TEMPORARY_DIR);
+
+    let chunks = futures::stream::iter_ok::<_, std::io::Error>(vec![
+        Bytes::from("cached "), Bytes::from("proxied "), Bytes::from("response"),
+    ]);
+    let received = tee_to_file_with_pool(&TEST_CPU_POOL, chunks, &test_file_path).collect().wait().unwrap();
+    assert_eq!(received.len(), 3);
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"cached proxied response");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_broadcast() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_broadcast.txt", TEST_TEMPORARY_DIR);
+    let content: Vec<u8> = (0..100u8).collect();
+    std::fs::write(&test_file_path, &content).unwrap();
+
+    // медленный подписчик задерживает чтение, но получает все блоки
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let broadcast = Broadcast::new(AsyncFileStream::from_std(&TEST_CPU_POOL, file, 8)).capacity(2);
+    let first = broadcast.subscribe();
+    let second = broadcast.subscribe();
+    let (a, b) = first.concat2().join(second.concat2()).wait().unwrap();
+    assert_eq!(&a[..], &content[..]);
+    assert_eq!(&b[..], &content[..]);
+    assert_eq!(broadcast.subscribers(), 0);
+
+    // отстающий подписчик отключается
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let broadcast = Broadcast::new(AsyncFileStream::from_std(&TEST_CPU_POOL, file, 8))
+        .capacity(2)
+        .lag_policy(LagPolicy::Disconnect);
+    let fast = broadcast.subscribe();
+    let slow = broadcast.subscribe();
+    assert_eq!(&fast.concat2().wait().unwrap()[..], &content[..]);
+    assert!(slow.concat2().wait().is_err());
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_paged_reader() {
+    use futures::Future;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_paged_reader.txt", TEST_TEMPORARY_DIR);
+    let content: Vec<u8> = (0..250u8).collect();
+    std::fs::write(&test_file_path, &content).unwrap();
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let reader = PagedReader::with_page_size(&TEST_CPU_POOL, file, 64, 2);
+    assert_eq!(&reader.read_at(10, 20).wait().unwrap()[..], &content[10..30]);
+    assert_eq!(reader.cached_pages(), 1);
+    // чтение через границу страниц
+    assert_eq!(&reader.read_at(60, 10).wait().unwrap()[..], &content[60..70]);
+    assert_eq!(reader.cached_pages(), 2);
+    // вытеснение давно не использованной страницы
+    assert_eq!(&reader.read_at(200, 100).wait().unwrap()[..], &content[200..]);
+    assert_eq!(reader.cached_pages(), 2);
+    assert_eq!(reader.read_at(300, 10).wait().unwrap().len(), 0);
+    reader.invalidate();
+    assert_eq!(reader.cached_pages(), 0);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_exact_positional() {
+    use futures::Future;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_exact_positional.txt", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"0123456789").unwrap();
+
+    let file = Arc::new(std::fs::OpenOptions::new().read(true).write(true).open(&test_file_path).unwrap());
+    write_all_at_with_pool(&TEST_CPU_POOL, file.clone(), 8, Bytes::from("abcd")).wait().unwrap();
+    let data = read_exact_at_with_pool(&TEST_CPU_POOL, file.clone(), 6, 6).wait().unwrap();
+    assert_eq!(&data[..], b"67abcd");
+    let error = read_exact_at_with_pool(&TEST_CPU_POOL, file.clone(), 10, 4).wait().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    drop(file);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_vectored_positional() {
+    use futures::Future;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_vectored_positional.txt", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"..........").unwrap();
+
+    let file = Arc::new(std::fs::OpenOptions::new().read(true).write(true).open(&test_file_path).unwrap());
+    let bufs = vec![Bytes::from("head"), Bytes::new(), Bytes::from("body")];
+    write_all_vectored_at_with_pool(&TEST_CPU_POOL, file.clone(), 2, bufs).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"..headbody");
+
+    let parts = read_exact_vectored_at_with_pool(&TEST_CPU_POOL, file.clone(), 2, vec![4, 0, 4]).wait().unwrap();
+    assert_eq!(parts, vec![Bytes::from("head"), Bytes::new(), Bytes::from("body")]);
+    let error = read_exact_vectored_at_with_pool(&TEST_CPU_POOL, file.clone(), 6, vec![2, 4]).wait().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    drop(file);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_nowait_reads() {
+    use futures::{Future, Stream};
+    use std::io::Read;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_nowait_reads.txt", TEST_TEMPORARY_DIR);
+    let content: Vec<u8> = (0..200u8).collect();
+    // только что записанные данные находятся в кэше страниц
+    std::fs::write(&test_file_path, &content).unwrap();
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let stream = AsyncFileStream::from_std(&TEST_CPU_POOL, file, 64).nowait_reads(true).limit(150);
+    assert_eq!(&stream.concat2().wait().unwrap()[..], &content[..150]);
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let mut reader = AsyncFileRead::from_std(&TEST_CPU_POOL, file, 64).nowait_reads(true);
+    let mut buf = Vec::new();
+    loop {
+        let mut chunk = [0u8; 64];
+        match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(size) => buf.extend_from_slice(&chunk[..size]),
+            Err(ref error) if error.kind() == std::io::ErrorKind::WouldBlock => std::thread::sleep(std::time::Duration::from_millis(1)),
+            Err(error) => panic!("{}", error),
+        }
+    }
+    assert_eq!(buf, content);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[cfg(unix)]
+#[test]
+fn it_pollable_fifo() {
+    use futures::Future;
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_fifo_path = format!("{}it_pollable_fifo", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_file(&test_fifo_path);
+    let c_path = std::ffi::CString::new(test_fifo_path.clone()).unwrap();
+    assert_eq!(unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) }, 0);
+
+    let reader = PollableFile::open_with_pool(&TEST_CPU_POOL, &test_fifo_path).wait().unwrap();
+    let mut writer = std::fs::OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(&test_fifo_path)
+        .unwrap();
+    let writing = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        writer.write_all(b"hello fifo").unwrap();
+    });
+
+    let mut runtime = tokio::runtime::Runtime::new().unwrap();
+    let (_, data) = runtime.block_on(tokio::io::read_to_end(reader, Vec::new())).unwrap();
+    assert_eq!(data, b"hello fifo");
+    writing.join().unwrap();
+
+    let regular = std::fs::File::create(format!("{}it_pollable_regular.txt", TEST_TEMPORARY_DIR)).unwrap();
+    assert_eq!(PollableFile::from_std(regular).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+
+    std::fs::remove_file(format!("{}it_pollable_regular.txt", TEST_TEMPORARY_DIR)).unwrap();
+    std::fs::remove_file(test_fifo_path).unwrap();
+}
+
+
+#[test]
+fn it_tune() {
+    use futures::{Future, Stream};
+    use std::io::Write;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_tune.txt", TEST_TEMPORARY_DIR);
+    let content: Vec<u8> = (0..512 * 1024).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&test_file_path, &content).unwrap();
+
+    let report = tune_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+    assert_eq!(report.samples.len(), 5);
+    assert!(report.samples.iter().any(|&(size, _)| size == report.best));
+
+    // адаптивный режим изменяет только размер блоков, но не данные
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let stream = AsyncFileStream::from_std(&TEST_CPU_POOL, file, 4 * 1024).adaptive(true);
+    assert_eq!(&stream.concat2().wait().unwrap()[..], &content[..]);
+
+    let file = std::fs::File::create(&test_file_path).unwrap();
+    let mut writer = AsyncFileWrite::from_std(&TEST_CPU_POOL, file, 4 * 1024).adaptive(true);
+    let mut written = 0;
+    while written < content.len() {
+        match writer.write(&content[written..]) {
+            Ok(size) => written += size,
+            Err(ref error) if error.kind() == std::io::ErrorKind::WouldBlock => std::thread::sleep(std::time::Duration::from_millis(1)),
+            Err(error) => panic!("{}", error),
+        }
+    }
+    drop(writer);
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), content);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_huge_buffers() {
+    use futures::Future;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let mut buffer = IoBuffer::huge(3 * 1024 * 1024);
+    assert_eq!(buffer.len(), 3 * 1024 * 1024);
+    if buffer.is_huge() {
+        assert_eq!(buffer.as_ptr() as usize % HUGE_PAGE_SIZE, 0);
+    }
+    buffer[0] = 1;
+    let last = buffer.len() - 1;
+    buffer[last] = 2;
+    assert_eq!((buffer[0], buffer[last]), (1, 2));
+
+    let pool = BufferPool::new(HUGE_PAGE_SIZE, 1).hugepages(true);
+    let first = pool.get();
+    let ptr = first.as_ptr();
+    drop(first);
+    assert_eq!(pool.free(), 1);
+    assert_eq!(pool.get().as_ptr(), ptr);
+
+    let test_file_path = format!("{}it_huge_buffers.txt", TEST_TEMPORARY_DIR);
+    let async_file_read = AsyncFileRead::from_std(&TEST_CPU_POOL, std::fs::File::open("./assets/hello.txt").unwrap(), TEST_BUFFER_SIZE);
+    let async_file_write = AsyncFileWrite::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap(), TEST_BUFFER_SIZE);
+    let (amt, _, _) = io::copy(async_file_read, async_file_write)
+        .buffer(pool.get())
+        .limit(12)
+        .wait().unwrap();
+    assert_eq!(amt, 12);
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"Hello world!");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[cfg(unix)]
+#[test]
+fn it_mmap_stream() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_mmap_stream.txt", TEST_TEMPORARY_DIR);
+    let content: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&test_file_path, &content).unwrap();
+
+    let chunks = MmapStream::open_with_pool(&TEST_CPU_POOL, &test_file_path).chunk_size(4096).collect().wait().unwrap();
+    assert_eq!(chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>(), vec![4096, 4096, 1808]);
+    let data: Vec<u8> = chunks.iter().flat_map(|chunk| chunk.iter().cloned()).collect();
+    assert_eq!(data, content);
+    assert_eq!(&chunks[1].slice(10, 20)[..], &content[4106..4116]);
+    assert_eq!(&chunks[2].to_bytes()[..], &content[8192..]);
+    drop(chunks);
+
+    std::fs::write(&test_file_path, b"").unwrap();
+    assert_eq!(MmapStream::open_with_pool(&TEST_CPU_POOL, &test_file_path).collect().wait().unwrap().len(), 0);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_clone_file() {
+    use futures::Future;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_clone_file.txt", TEST_TEMPORARY_DIR);
+    let clone_path = format!("{}it_clone_file.clone.txt", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"original").unwrap();
+
+    let supported = supports_reflink_with_pool(&TEST_CPU_POOL, TEST_TEMPORARY_DIR).wait().unwrap();
+    let result = clone_file_with_pool(&TEST_CPU_POOL, &test_file_path, &clone_path).wait();
+    if supported {
+        result.unwrap();
+        assert_eq!(std::fs::read(&clone_path).unwrap(), b"original");
+
+        let snapshot = snapshot_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+        std::fs::write(&test_file_path, b"changed").unwrap();
+        assert_eq!(std::fs::read(snapshot.path()).unwrap(), b"original");
+        std::fs::remove_file(clone_path).unwrap();
+    } else {
+        // без поддержки reflink копия не создается
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::Other);
+        assert!(!std::path::Path::new(&clone_path).exists());
+        assert!(snapshot_with_pool(&TEST_CPU_POOL, &test_file_path).wait().is_err());
+    }
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_copy_dir_all() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    let src = format!("{}it_copy_dir_all/src", TEST_TEMPORARY_DIR);
+    let dst = format!("{}it_copy_dir_all/dst", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_dir_all(format!("{}it_copy_dir_all", TEST_TEMPORARY_DIR));
+    std::fs::create_dir_all(format!("{}/nested", src)).unwrap();
+    std::fs::write(format!("{}/a.txt", src), b"first").unwrap();
+    std::fs::write(format!("{}/nested/b.txt", src), b"second file").unwrap();
+
+    let entries = copy_dir_all_with_pool(&TEST_CPU_POOL, &src, &dst)
+        .mode(CopyMode::CloneWhenPossible)
+        .collect()
+        .wait()
+        .unwrap();
+    assert_eq!(entries.len(), 3);
+    for entry in &entries {
+        match entry.method {
+            CopyMethod::Directory => assert!(entry.dst.ends_with("nested")),
+            CopyMethod::Cloned | CopyMethod::Copied => assert!(entry.size > 0),
+            CopyMethod::Symlink => unreachable!(),
+        }
+    }
+    assert_eq!(std::fs::read(format!("{}/a.txt", dst)).unwrap(), b"first");
+    assert_eq!(std::fs::read(format!("{}/nested/b.txt", dst)).unwrap(), b"second file");
+
+    std::fs::remove_dir_all(format!("{}it_copy_dir_all", TEST_TEMPORARY_DIR)).unwrap();
+}
+
+
+#[test]
+fn it_dir_stats() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    let test_dir_path = format!("{}it_dir_stats", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_dir_all(&test_dir_path);
+    std::fs::create_dir_all(format!("{}/nested/deeper", test_dir_path)).unwrap();
+    std::fs::write(format!("{}/a.txt", test_dir_path), b"Hello").unwrap();
+    std::fs::write(format!("{}/nested/b.txt", test_dir_path), b"Hello world!").unwrap();
+    std::fs::write(format!("{}/nested/deeper/c.txt", test_dir_path), b"Hi").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("a.txt", format!("{}/link", test_dir_path)).unwrap();
+
+    let snapshots = dir_stats_with_pool(&TEST_CPU_POOL, &test_dir_path).collect().wait().unwrap();
+    assert!(!snapshots.is_empty());
+    let stats = snapshots.last().unwrap();
+    assert_eq!(stats.files, 3);
+    assert_eq!(stats.dirs, 2);
+    assert_eq!(stats.size, 19);
+    assert!(stats.largest.as_ref().unwrap().path.ends_with("nested/b.txt"));
+    #[cfg(unix)]
+    assert_eq!(stats.symlinks, 1);
+    // промежуточные итоги не убывают
+    for pair in snapshots.windows(2) {
+        assert!(pair[0].files <= pair[1].files && pair[0].size <= pair[1].size);
+    }
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_modified_since() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    let test_dir_path = format!("{}it_modified_since", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_dir_all(&test_dir_path);
+    std::fs::create_dir_all(format!("{}/nested", test_dir_path)).unwrap();
+    std::fs::write(format!("{}/old.txt", test_dir_path), b"old").unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    let cutoff = std::time::SystemTime::now();
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    std::fs::write(format!("{}/nested/new.txt", test_dir_path), b"new").unwrap();
+
+    let paths = modified_since_with_pool(&TEST_CPU_POOL, &test_dir_path, cutoff).collect().wait().unwrap();
+    assert_eq!(paths, vec![std::path::PathBuf::from(format!("{}/nested/new.txt", test_dir_path))]);
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_cleaner() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    let test_dir_path = format!("{}it_cleaner", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_dir_all(&test_dir_path);
+    std::fs::create_dir_all(format!("{}/nested", test_dir_path)).unwrap();
+    for name in &["a.log", "nested/b.log", "c.log"] {
+        std::fs::write(format!("{}/{}", test_dir_path, name), b"Hello").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+
+    let actions = Cleaner::new_with_pool(&TEST_CPU_POOL, &test_dir_path)
+        .max_age(std::time::Duration::from_secs(3600))
+        .max_size(10)
+        .dry_run(true)
+        .collect().wait().unwrap();
+    assert_eq!(actions.len(), 1);
+    assert!(actions[0].path.ends_with("a.log"));
+    assert_eq!(actions[0].reason, CleanupReason::Size);
+    assert!(!actions[0].removed);
+    assert!(std::path::Path::new(&format!("{}/a.log", test_dir_path)).exists());
+
+    let actions = Cleaner::new_with_pool(&TEST_CPU_POOL, &test_dir_path)
+        .max_size(5)
+        .collect().wait().unwrap();
+    let removed: Vec<_> = actions.iter().map(|action| action.path.clone()).collect();
+    assert_eq!(removed, vec![
+        std::path::PathBuf::from(format!("{}/a.log", test_dir_path)),
+        std::path::PathBuf::from(format!("{}/nested/b.log", test_dir_path)),
+    ]);
+    assert!(actions.iter().all(|action| action.removed));
+    assert!(!std::path::Path::new(&format!("{}/nested/b.log", test_dir_path)).exists());
+    assert!(std::path::Path::new(&format!("{}/c.log", test_dir_path)).exists());
+
+    let actions = Cleaner::new_with_pool(&TEST_CPU_POOL, &test_dir_path)
+        .max_age(std::time::Duration::from_secs(0))
+        .collect().wait().unwrap();
+    assert_eq!(actions.len(), 1);
+    assert_eq!(actions[0].reason, CleanupReason::Age);
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_scoped_dir() {
+    use futures::Future;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let dir = ScopedDir::create_in_with_pool(&TEST_CPU_POOL, TEST_TEMPORARY_DIR, "it_scoped_dir-").wait().unwrap();
+    let path = dir.path().to_path_buf();
+    std::fs::create_dir_all(path.join("nested")).unwrap();
+    std::fs::write(path.join("nested/a.txt"), b"Hello").unwrap();
+    dir.cleanup().wait().unwrap();
+    assert!(!path.exists());
+
+    let dir = ScopedDir::create_in_with_pool(&TEST_CPU_POOL, TEST_TEMPORARY_DIR, "it_scoped_dir-").wait().unwrap();
+    let path = dir.keep();
+    assert!(path.is_dir());
+    std::fs::remove_dir(path).unwrap();
+}
+
+
+#[test]
+fn it_ephemeral_file() {
+    use futures::{Future, Sink, Stream};
+    use super::*;
+
+    let test_dir_path = format!("{}it_ephemeral_file", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_dir_all(&test_dir_path);
+    std::fs::create_dir_all(&test_dir_path).unwrap();
+
+    let file = EphemeralFile::create_in_with_pool(&TEST_CPU_POOL, &test_dir_path).wait().unwrap();
+    #[cfg(unix)]
+    assert_eq!(std::fs::read_dir(&test_dir_path).unwrap().count(), 0);
+
+    let chunks = futures::stream::iter_ok::<_, std::io::Error>(vec![Bytes::from("Hello "), Bytes::from("world!")]);
+    let _ = file.sink().unwrap().send_all(chunks).wait().unwrap();
+    let data = file.stream().unwrap().concat2().wait().unwrap();
+    assert_eq!(&data[..], b"Hello world!");
+
+    drop(file);
+    assert_eq!(std::fs::read_dir(&test_dir_path).unwrap().count(), 0);
+    std::fs::remove_dir(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_follow_rotation() {
+    use super::*;
+
+    let test_dir_path = format!("{}it_follow_rotation", TEST_TEMPORARY_DIR);
+    let test_file_path = format!("{}/app.log", test_dir_path);
+    let _ = std::fs::remove_dir_all(&test_dir_path);
+    std::fs::create_dir_all(&test_dir_path).unwrap();
+    std::fs::write(&test_file_path, b"one\n").unwrap();
+
+    let mut follow = futures::executor::spawn(
+        AsyncFileFollow::open_with_pool(&TEST_CPU_POOL, &test_file_path, false)
+            .interval(std::time::Duration::from_millis(10)),
+    );
+    let mut next = move || follow.wait_stream().unwrap().unwrap();
+    assert_eq!(next(), FollowEvent::Data("one\n".into()));
+
+    {
+        use std::io::Write;
+        std::fs::OpenOptions::new().append(true).open(&test_file_path).unwrap().write_all(b"two\n").unwrap();
+    }
+    assert_eq!(next(), FollowEvent::Data("two\n".into()));
+
+    #[cfg(unix)]
+    {
+        std::fs::rename(&test_file_path, format!("{}.1", test_file_path)).unwrap();
+        std::fs::write(&test_file_path, b"three\n").unwrap();
+        assert_eq!(next(), FollowEvent::Rotated);
+        assert_eq!(next(), FollowEvent::Data("three\n".into()));
+    }
+
+    // copytruncate
+    std::fs::OpenOptions::new().write(true).open(&test_file_path).unwrap().set_len(0).unwrap();
+    assert_eq!(next(), FollowEvent::Rotated);
+    std::fs::write(&test_file_path, b"four\n").unwrap();
+    assert_eq!(next(), FollowEvent::Data("four\n".into()));
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[cfg(feature = "csv")]
+#[test]
+fn it_csv() {
+    use futures::{Future, Sink, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_csv.csv", TEST_TEMPORARY_DIR);
+
+    let records = vec![
+        ("first".to_string(), 1u32),
+        ("with, comma".to_string(), 2),
+        ("multi\nline".to_string(), 3),
+        ("fourth".to_string(), 4),
+        ("fifth".to_string(), 5),
+    ];
+    let _ = write_csv_with_pool(&TEST_CPU_POOL, &test_file_path)
+        .batch_size(2)
+        .send_all(futures::stream::iter_ok::<_, std::io::Error>(records.clone()))
+        .wait()
+        .unwrap();
+
+    // блоки по 7 байт разрывают записи
+    let read: Vec<(String, u32)> = CsvStream::new(
+        &TEST_CPU_POOL,
+        AsyncFileStream::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), 7),
+    ).has_headers(false).collect().wait().unwrap();
+    assert_eq!(read, records);
+
+    std::fs::write(&test_file_path, "name,count\nfirst,1\nsecond,x\nthird,3").unwrap();
+    let mut stream = read_csv_with_pool::<(String, u32), _>(&TEST_CPU_POOL, &test_file_path).wait();
+    assert_eq!(stream.next().unwrap().unwrap(), ("first".to_string(), 1));
+    assert_eq!(stream.next().unwrap().unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_hexdump() {
+    use futures::{Future, Stream};
+    use std::sync::{Arc, Mutex};
+    use super::*;
+
+    assert_eq!(format_hexdump(b"Hello world!\n", 16), vec![
+        "00000010  48 65 6c 6c 6f 20 77 6f  72 6c 64 21 0a           |Hello world!.|".to_string(),
+    ]);
+
+    let chunks = vec![Bytes::from(&b"0123456789abcdefXYZ"[..]), Bytes::from(&b"\x00\xff"[..])];
+    let dumped = Arc::new(Mutex::new(Vec::new()));
+    let output = dumped.clone();
+    let passed = hexdump(futures::stream::iter_ok::<_, std::io::Error>(chunks.clone()))
+        .enabled(true)
+        .label("in:")
+        .output(move |line| output.lock().unwrap().push(line.to_string()))
+        .collect().wait().unwrap();
+    assert_eq!(passed, chunks);
+    let dumped = dumped.lock().unwrap();
+    assert_eq!(dumped.len(), 3);
+    assert!(dumped[1].starts_with("in: 00000010  58 59 5a "));
+    assert!(dumped[2].starts_with("in: 00000013  00 ff ") && dumped[2].ends_with("|..|"));
+
+    let lines = hexdump_lines(futures::stream::iter_ok::<_, std::io::Error>(chunks)).collect().wait().unwrap();
+    assert_eq!(lines.len(), 3);
+}
+
+
+#[test]
+fn it_scrubber() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    let test_dir_path = format!("{}it_scrubber", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_dir_all(&test_dir_path);
+    std::fs::create_dir_all(format!("{}/data/nested", test_dir_path)).unwrap();
+    std::fs::write(format!("{}/data/a.bin", test_dir_path), vec![7u8; 10000]).unwrap();
+    std::fs::write(format!("{}/data/nested/b.bin", test_dir_path), b"Hello world!").unwrap();
+    std::fs::write(format!("{}/data/nested/c.bin", test_dir_path), b"Hello").unwrap();
+
+    let manifest_path = format!("{}/manifest", test_dir_path);
+    let manifest = ScrubManifest::build(&TEST_CPU_POOL, format!("{}/data", test_dir_path)).wait().unwrap();
+    assert_eq!(manifest.entries.len(), 3);
+    manifest.save(&TEST_CPU_POOL, &manifest_path).wait().unwrap();
+    let manifest = ScrubManifest::load(&TEST_CPU_POOL, &manifest_path).wait().unwrap();
+
+    std::fs::write(format!("{}/data/nested/b.bin", test_dir_path), b"Hello World!").unwrap();
+    std::fs::remove_file(format!("{}/data/nested/c.bin", test_dir_path)).unwrap();
+
+    let started = std::time::Instant::now();
+    let reports = Scrubber::new_with_pool(&TEST_CPU_POOL, format!("{}/data", test_dir_path), manifest)
+        .rate(100_000)
+        .chunk_size(4096)
+        .collect().wait().unwrap();
+    // 10012 байт со скоростью 100 КБ/с
+    assert!(started.elapsed() >= std::time::Duration::from_millis(90));
+    assert_eq!(reports.len(), 2);
+    assert!(reports[0].path.ends_with("nested/b.bin"));
+    match reports[0].problem {
+        ScrubProblem::ChecksumMismatch { .. } => {},
+        ref problem => panic!("unexpected problem {:?}", problem),
+    }
+    assert_eq!(reports[1].problem, ScrubProblem::Missing);
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_quarantine() {
+    use futures::Future;
+    use super::*;
+
+    let test_dir_path = format!("{}it_quarantine", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_dir_all(&test_dir_path);
+    std::fs::create_dir_all(format!("{}/data", test_dir_path)).unwrap();
+    let test_file_path = format!("{}/data/bad.bin", test_dir_path);
+    std::fs::write(&test_file_path, b"corrupted").unwrap();
+
+    let problem = ScrubProblem::ChecksumMismatch { expected: 1, actual: 2 };
+    let quarantined = quarantine_with_pool(&TEST_CPU_POOL, &test_file_path, format!("{}/quarantine", test_dir_path), &problem.to_string())
+        .wait()
+        .unwrap();
+    assert!(!std::path::Path::new(&test_file_path).exists());
+    assert!(quarantined.path.starts_with(format!("{}/quarantine", test_dir_path)));
+    assert!(quarantined.path.to_string_lossy().ends_with("-bad.bin"));
+    assert_eq!(std::fs::read(&quarantined.path).unwrap(), b"corrupted");
+    let sidecar = std::fs::read_to_string(&quarantined.sidecar).unwrap();
+    assert!(sidecar.contains("size: 9\n"));
+    assert!(sidecar.contains("reason: crc32 mismatch: expected 00000001, found 00000002\n"));
+
+    assert!(quarantine_with_pool(&TEST_CPU_POOL, &test_file_path, format!("{}/quarantine", test_dir_path), "again").wait().is_err());
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_write_if_changed() {
+    use futures::Future;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_write_if_changed.txt", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_file(&test_file_path);
+
+    assert!(write_if_changed_with_pool(&TEST_CPU_POOL, &test_file_path, "Hello world!".into()).wait().unwrap());
+    let modified = std::fs::metadata(&test_file_path).unwrap().modified().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    assert!(!write_if_changed_with_pool(&TEST_CPU_POOL, &test_file_path, "Hello world!".into()).wait().unwrap());
+    assert_eq!(std::fs::metadata(&test_file_path).unwrap().modified().unwrap(), modified);
+    assert!(write_if_changed_with_pool(&TEST_CPU_POOL, &test_file_path, "Hello World!".into()).wait().unwrap());
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"Hello World!");
+    assert!(write_if_changed_with_pool(&TEST_CPU_POOL, &test_file_path, "Hello".into()).wait().unwrap());
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"Hello");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_write_if_unmodified_since() {
+    use futures::Future;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_write_if_unmodified_since.json", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_file(&test_file_path);
+
+    let etag = write_if_unmodified_since_with_pool(&TEST_CPU_POOL, &test_file_path, "{}".into(), Precondition::Absent)
+        .wait().unwrap();
+    assert_eq!(etag, file_etag(&std::fs::metadata(&test_file_path).unwrap()));
+
+    let error = write_if_unmodified_since_with_pool(&TEST_CPU_POOL, &test_file_path, "{}".into(), Precondition::Absent)
+        .wait().unwrap_err();
+    let failed = error.get_ref().and_then(|error| error.downcast_ref::<PreconditionFailed>()).unwrap();
+    assert_eq!(failed.current, Some(etag.clone()));
+
+    let etag = write_if_unmodified_since_with_pool(&TEST_CPU_POOL, &test_file_path, "{\"a\":1}".into(), Precondition::ETag(etag))
+        .wait().unwrap();
+    std::thread::sleep(std::time::Duration::from_millis(20));
+    std::fs::write(&test_file_path, b"{\"b\":2}").unwrap();
+    let error = write_if_unmodified_since_with_pool(&TEST_CPU_POOL, &test_file_path, "{\"a\":2}".into(), Precondition::ETag(etag))
+        .wait().unwrap_err();
+    assert!(error.get_ref().unwrap().is::<PreconditionFailed>());
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"{\"b\":2}");
+
+    let past = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+    assert!(write_if_unmodified_since_with_pool(&TEST_CPU_POOL, &test_file_path, "x".into(), Precondition::UnmodifiedSince(past)).wait().is_err());
+    let future = std::time::SystemTime::now() + std::time::Duration::from_secs(3600);
+    write_if_unmodified_since_with_pool(&TEST_CPU_POOL, &test_file_path, "x".into(), Precondition::UnmodifiedSince(future)).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"x");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_versioned_file() {
+    use futures::Future;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_versioned_file.txt", TEST_TEMPORARY_DIR);
+    let lock_file_path = format!("{}.it_versioned_file.txt.lock", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_file(&test_file_path);
+
+    let file = VersionedFile::new_with_pool(&TEST_CPU_POOL, &test_file_path);
+    let (data, absent) = file.load().wait().unwrap();
+    assert!(data.is_none());
+    assert!(!absent.exists());
+
+    let first = file.store("1".into(), absent.clone()).wait().unwrap();
+    assert!(first.exists());
+    let error = file.store("2".into(), absent).wait().unwrap_err();
+    assert!(error.get_ref().unwrap().is::<PreconditionFailed>());
+
+    let (data, token) = file.load().wait().unwrap();
+    assert_eq!(data.unwrap(), Bytes::from("1"));
+    assert_eq!(token, first);
+    let second = file.store("2".into(), token).wait().unwrap();
+    // устаревшая версия отклоняется, даже если время изменения совпало
+    assert!(file.store("3".into(), first).wait().is_err());
+
+    let updates: Vec<_> = (0..8).map(|_| file.update(|data| {
+        let value: u32 = std::str::from_utf8(data.unwrap()).unwrap().parse().unwrap();
+        Ok((value + 1).to_string().into())
+    })).collect();
+    for update in updates {
+        update.wait().unwrap();
+    }
+    let (data, token) = file.load().wait().unwrap();
+    assert_eq!(data.unwrap(), Bytes::from("10"));
+    assert!(token != second);
+
+    std::fs::remove_file(test_file_path).unwrap();
+    std::fs::remove_file(lock_file_path).unwrap();
+}
+
+
+#[test]
+fn it_lease_file() {
+    use futures::{Future, Stream};
+    use std::time::{Duration, SystemTime};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_lease_file.lease", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_file(&test_file_path);
+
+    let ttl = Duration::from_millis(300);
+    let lease = LeaseFile::acquire_with_pool(&TEST_CPU_POOL, &test_file_path, ttl).wait().unwrap().unwrap();
+    assert!(LeaseFile::acquire_with_pool(&TEST_CPU_POOL, &test_file_path, ttl).wait().unwrap().is_none());
+    let info = LeaseFile::inspect_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap().unwrap();
+    assert_eq!(info.owner, lease.owner());
+    assert!(!info.is_expired());
+
+    // продление удерживает аренду дольше ttl
+    let expires_at = lease.expires_at();
+    let refreshed: Vec<SystemTime> = lease.keep_alive(Duration::from_millis(100)).take(4).collect().wait().unwrap();
+    assert!(refreshed[3] > expires_at + Duration::from_millis(300));
+    assert!(LeaseFile::acquire_with_pool(&TEST_CPU_POOL, &test_file_path, ttl).wait().unwrap().is_none());
+
+    // устаревшая аренда разрывается, а прежний владелец теряет ее
+    std::thread::sleep(ttl + Duration::from_millis(50));
+    let other = LeaseFile::acquire_with_pool(&TEST_CPU_POOL, &test_file_path, ttl).wait().unwrap().unwrap();
+    assert!(lease.refresh().wait().is_err());
+    drop(lease);
+    std::thread::sleep(Duration::from_millis(50));
+    let info = LeaseFile::inspect_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap().unwrap();
+    assert_eq!(info.owner, other.owner());
+
+    other.release().wait().unwrap();
+    assert!(LeaseFile::inspect_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap().is_none());
+}
+
+
+#[test]
+fn it_dir_lock() {
+    use futures::{Future, Stream};
+    use std::time::Duration;
+    use super::*;
+
+    let test_dir_path = format!("{}it_dir_lock", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_dir_all(&test_dir_path);
+
+    let dir_lock = DirLock::new_with_pool(&TEST_CPU_POOL, &test_dir_path)
+        .ttl(Duration::from_millis(300))
+        .retry_interval(Duration::from_millis(20))
+        .description("it_dir_lock");
+    let guard = dir_lock.try_lock().wait().unwrap().unwrap();
+    assert!(dir_lock.try_lock().wait().unwrap().is_none());
+    let owner = dir_lock.owner().wait().unwrap().unwrap();
+    assert_eq!(owner.owner, guard.owner());
+    assert_eq!(owner.pid, std::process::id());
+    assert_eq!(owner.description, "it_dir_lock");
+    assert!(!owner.is_expired());
+
+    // продление сохраняет сведения о владельце
+    guard.heartbeat().take(1).collect().wait().unwrap();
+    assert_eq!(dir_lock.owner().wait().unwrap().unwrap().description, "it_dir_lock");
+
+    let waiting = std::thread::spawn({
+        let dir_lock = dir_lock.clone();
+        move || dir_lock.lock().wait().unwrap()
+    });
+    std::thread::sleep(Duration::from_millis(100));
+    guard.release().wait().unwrap();
+    let guard = waiting.join().unwrap();
+
+    // блокировка завершившегося процесса перехватывается только по соответствующей политике
+    let mut content = std::fs::read_to_string(guard.path()).unwrap();
+    content = content.replace(&format!("pid: {}\n", std::process::id()), "pid: 2147483646\n");
+    content = content.replace(&format!("owner: {}\n", guard.owner()), "owner: dead\n");
+    std::fs::write(guard.path(), &content).unwrap();
+    let stale = DirLock::new_with_pool(&TEST_CPU_POOL, &test_dir_path);
+    assert!(stale.clone().stale_policy(StalePolicy::Expired).try_lock().wait().unwrap().is_none());
+    assert!(stale.clone().stale_policy(StalePolicy::Never).try_lock().wait().unwrap().is_none());
+    let taken = stale.stale_policy(StalePolicy::ExpiredOrDeadOwner).try_lock().wait().unwrap().unwrap();
+    assert!(guard.refresh().wait().is_err());
+    taken.release().wait().unwrap();
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[cfg(unix)]
+#[test]
+fn it_shared_memory() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    let name = format!("async_fs_it_shm_{}", std::process::id());
+    let _ = shm::unlink_with_pool(&TEST_CPU_POOL, &name).wait();
+
+    let created = shm::create_with_pool(&TEST_CPU_POOL, &name, 64 * 1024).wait().unwrap();
+    assert_eq!(created.len(), 64 * 1024);
+    assert_eq!(shm::create_with_pool(&TEST_CPU_POOL, &name, 16).wait().unwrap_err().kind(), std::io::ErrorKind::AlreadyExists);
+    let mut writer = created.map().wait().unwrap();
+    writer[..5].copy_from_slice(b"hello");
+
+    let opened = shm::open_with_pool(&TEST_CPU_POOL, &format!("/{}", name)).wait().unwrap();
+    assert_eq!(opened.len(), 64 * 1024);
+    let reader = opened.map().wait().unwrap();
+    assert_eq!(&reader[..5], b"hello");
+    writer[5] = b'!';
+    assert_eq!(&reader[..6], b"hello!");
+
+    let chunks: Vec<MmapChunk> = opened.stream().unwrap().collect().wait().unwrap();
+    assert_eq!(&chunks[0][..6], b"hello!");
+    assert_eq!(chunks.iter().map(|chunk| chunk.len()).sum::<usize>(), 64 * 1024);
+
+    opened.unlink().wait().unwrap();
+    assert_eq!(shm::open_with_pool(&TEST_CPU_POOL, &name).wait().unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    assert!(shm::open_with_pool(&TEST_CPU_POOL, "a/b").wait().is_err());
+}
+
+
+#[test]
+fn it_file_kind() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_file_kind.txt", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"data").unwrap();
+
+    assert_eq!(file_kind_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap(), FileKind::File);
+    assert_eq!(file_kind_with_pool(&TEST_CPU_POOL, TEST_TEMPORARY_DIR).wait().unwrap(), FileKind::Dir);
+    assert_eq!(std::fs::metadata(&test_file_path).unwrap().kind(), FileKind::File);
+
+    if cfg!(unix) {
+        assert_eq!(file_kind_with_pool(&TEST_CPU_POOL, "/dev/null").wait().unwrap(), FileKind::CharDevice);
+        assert!(FileKind::CharDevice.is_device() && !FileKind::CharDevice.has_reliable_size());
+
+        // символьные устройства не отображаются в память и не сравниваются
+        let error = MmapStream::open_with_pool(&TEST_CPU_POOL, "/dev/null").collect().wait().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        let error = files_equal_with_pool(&TEST_CPU_POOL, "/dev/null", &test_file_path).wait().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+
+        // нулевой размер устройства не ограничивает чтение
+        let (event, _) = AsyncFileFollow::open_with_pool(&TEST_CPU_POOL, "/dev/zero", true)
+            .buffer_size(16)
+            .into_future().wait().map_err(|(error, _)| error).unwrap();
+        match event.unwrap() {
+            FollowEvent::Data(data) => assert_eq!(&data[..], &[0u8; 16][..]),
+            FollowEvent::Rotated => panic!("unexpected rotation"),
+        }
+    }
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_block_device() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_block_device.img", TEST_TEMPORARY_DIR);
+    let data: Vec<u8> = (0..4 * 512 + 100).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&test_file_path, &data).unwrap();
+
+    let device = BlockDevice::open_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+    assert_eq!(device.kind(), FileKind::File);
+    assert_eq!(device.size(), data.len() as u64);
+    assert_eq!(device.sector_size(), 512);
+
+    let read: Vec<u8> = device.stream(512, 1024).wait().unwrap().concat2().wait().unwrap().to_vec();
+    assert_eq!(&read[..], &data[512..1536]);
+    // последний неполный сектор читается диапазоном до конца устройства
+    let read: Vec<u8> = device.stream(2048, 100).wait().unwrap().concat2().wait().unwrap().to_vec();
+    assert_eq!(&read[..], &data[2048..]);
+    let read: Vec<u8> = device.stream_all().wait().unwrap().concat2().wait().unwrap().to_vec();
+    assert_eq!(read, data);
+
+    assert_eq!(device.stream(100, 512).wait().unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(device.stream(0, 100).wait().unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    assert_eq!(device.stream(2048, 512).wait().unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    if cfg!(unix) {
+        assert!(BlockDevice::open_with_pool(&TEST_CPU_POOL, "/dev/null").wait().is_err());
+    }
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[cfg(unix)]
+#[test]
+fn it_raw_fd() {
+    use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd};
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_raw_fd.txt", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"raw descriptors").unwrap();
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let fd = file.as_raw_fd();
+    let stream = AsyncFileStream::from_std(&TEST_CPU_POOL, file, 4);
+    assert_eq!(stream.as_raw_fd(), fd);
+    let fd = stream.into_raw_fd();
+    let stream = unsafe { AsyncFileStream::from_raw_fd(fd) };
+    let (chunk, stream) = stream.into_future().wait().map_err(|(error, _)| error).unwrap();
+    assert_eq!(&chunk.unwrap()[..], &b"raw descriptors"[..]);
+    // дескриптор доступен и между операциями, и после них
+    assert_eq!(stream.as_raw_fd(), fd);
+
+    let sink = unsafe { AsyncFileSink::from_raw_fd(stream.into_raw_fd()) };
+    assert_eq!(sink.as_raw_fd(), fd);
+    let file = unsafe { std::fs::File::from_raw_fd(sink.into_raw_fd()) };
+    assert_eq!(file.metadata().unwrap().len(), 15);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_try_clone() {
+    use futures::{Future, Stream, Sink};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_try_clone.txt", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, vec![7u8; 64 * 1024]).unwrap();
+
+    let stream = AsyncFileStream::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), 1024);
+    let (chunk, stream) = stream.into_future().wait().map_err(|(error, _)| error).unwrap();
+    assert_eq!(chunk.unwrap().len(), 1024);
+    // копия читает с общей позиции и независимо закрывается
+    let clone = stream.try_clone().unwrap();
+    let (chunk, clone) = clone.into_future().wait().map_err(|(error, _)| error).unwrap();
+    assert_eq!(chunk.unwrap().len(), 1024);
+    drop(clone);
+    let rest = stream.concat2().wait().unwrap();
+    assert_eq!(rest.len(), 62 * 1024);
+
+    let file = AsyncOpenOptions::new().write(true).truncate(true).open(&test_file_path).wait().unwrap();
+    let sink = AsyncFileSink::from_std(&TEST_CPU_POOL, file);
+    let clone = sink.try_clone().unwrap();
+    let sink = sink.send(Bytes::from(&b"cloned"[..])).wait().unwrap();
+    let file: std::fs::File = std::convert::TryFrom::try_from(clone).unwrap();
+    assert_eq!(file.metadata().unwrap().len(), 6);
+    drop(sink);
+    assert!(AsyncFileRead::from_std(&TEST_CPU_POOL, file, 16).try_clone().is_ok());
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_with_file() {
+    use futures::{Future, Stream, Sink};
+    use std::io::{Seek, SeekFrom};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_with_file.txt", TEST_TEMPORARY_DIR);
+    let file = AsyncOpenOptions::new().read(true).write(true).create(true).truncate(true).open(&test_file_path).wait().unwrap();
+
+    let sink = AsyncFileSink::from_std(&TEST_CPU_POOL, file)
+        .send(Bytes::from(&b"0123456789"[..])).wait().unwrap();
+    // начатая запись завершается до вызова функции
+    let (sink, len) = sink.with_file(|file| Ok(file.metadata()?.len())).wait().unwrap();
+    assert_eq!(len, 10);
+    let (sink, ()) = sink.with_file(|file| file.set_len(4)).wait().unwrap();
+    let (sink, position) = sink.with_file(|file| file.seek(SeekFrom::Start(0))).wait().unwrap();
+    assert_eq!(position, 0);
+
+    let file: std::fs::File = std::convert::TryFrom::try_from(sink).unwrap();
+    let stream = AsyncFileStream::from_std(&TEST_CPU_POOL, file, 2);
+    let (chunk, stream) = stream.into_future().wait().map_err(|(error, _)| error).unwrap();
+    assert_eq!(&chunk.unwrap()[..], b"01");
+    let (stream, position) = stream.with_file(|file| file.stream_position()).wait().unwrap();
+    assert_eq!(position, 2);
+    assert_eq!(&stream.concat2().wait().unwrap()[..], b"23");
+
+    let stream = AsyncFileStream::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), 2);
+    let error = stream.with_file(|_| -> std::io::Result<()> {
+        Err(std::io::Error::other("failed"))
+    }).wait().unwrap_err();
+    assert_eq!(error.to_string(), "failed");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[cfg(target_os = "macos")]
+#[test]
+fn it_macos_file_ext() {
+    use futures::Future;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_macos_file_ext.txt", TEST_TEMPORARY_DIR);
+    let file = AsyncOpenOptions::new().cpu_pool(&TEST_CPU_POOL).read(true).write(true).create(true).truncate(true)
+        .no_cache(true).open(&test_file_path).wait().unwrap();
+
+    file.preallocate(1 << 20).unwrap();
+    assert_eq!(file.metadata().unwrap().len(), 1 << 20);
+    // уменьшать файл preallocate не должен
+    file.preallocate(16).unwrap();
+    assert_eq!(file.metadata().unwrap().len(), 1 << 20);
+
+    file.set_no_cache(false).unwrap();
+    file.full_fsync().unwrap();
+
+    drop(file);
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_async_open() {
+    use futures::{Future, Stream, Sink};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_async_open.txt", TEST_TEMPORARY_DIR);
+
+    let sink = AsyncFileSink::create_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+    sink.send(Bytes::from(&b"hello"[..])).and_then(|mut sink| futures::future::poll_fn(move || sink.poll_close())).wait().unwrap();
+    let stream = AsyncFileStream::open_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+    assert_eq!(&stream.concat2().wait().unwrap()[..], b"hello");
+
+    // create усекает существующий файл
+    let write = AsyncFileWrite::create_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+    let (write, _) = tokio::io::write_all(write, b"hi".to_vec()).wait().unwrap();
+    tokio::io::shutdown(write).wait().unwrap();
+    let read = AsyncFileRead::open_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+    let (_, data) = tokio::io::read_to_end(read, Vec::new()).wait().unwrap();
+    assert_eq!(data, b"hi");
+
+    let error = AsyncFileRead::open_with_pool(&TEST_CPU_POOL, format!("{}it_async_open.missing", TEST_TEMPORARY_DIR)).wait().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::NotFound);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_async_open_options_wrappers() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_async_open_options_wrappers.txt", TEST_TEMPORARY_DIR);
+
+    let write = AsyncOpenOptions::new().cpu_pool(&TEST_CPU_POOL).buffer_size(4)
+        .write(true).create_new(true).open_write(&test_file_path).wait().unwrap();
+    let (write, _) = tokio::io::write_all(write, b"0123456789".to_vec()).wait().unwrap();
+    tokio::io::shutdown(write).wait().unwrap();
+
+    let stream = AsyncOpenOptions::new().cpu_pool(&TEST_CPU_POOL).buffer_size(4)
+        .read(true).open_stream(&test_file_path).wait().unwrap();
+    let chunks: Vec<Bytes> = stream.collect().wait().unwrap();
+    assert_eq!(chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>(), vec![4, 4, 2]);
+
+    let file = AsyncOpenOptions::new().cpu_pool(&TEST_CPU_POOL)
+        .read(true).write(true).open_serialized(&test_file_path).wait().unwrap();
+    file.write_at(0, Bytes::from(&b"ab"[..])).wait().unwrap();
+    assert_eq!(&file.read_at(0, 4).wait().unwrap()[..], b"ab23");
+    drop(file);
+
+    let read = AsyncOpenOptions::new().cpu_pool(&TEST_CPU_POOL).read(true).open_read(&test_file_path).wait().unwrap();
+    let (_, data) = tokio::io::read_to_end(read, Vec::new()).wait().unwrap();
+    assert_eq!(data, b"ab23456789");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_pool_registry() {
+    use futures::Future;
+    use super::*;
+
+    lazy_static! {
+        static ref DATA_POOL: futures_cpupool::CpuPool = futures_cpupool::CpuPool::new(1);
+        static ref NESTED_POOL: futures_cpupool::CpuPool = futures_cpupool::CpuPool::new(1);
+    }
+    let same = |a: &futures_cpupool::CpuPool, b: &futures_cpupool::CpuPool| std::ptr::eq(a, b);
+
+    let registry = PoolRegistry::new();
+    registry.register("/data", &DATA_POOL).register("/data/nested", &NESTED_POOL);
+    assert!(same(registry.pool_for("/data/file.txt"), &DATA_POOL));
+    assert!(same(registry.pool_for("/data/nested/file.txt"), &NESTED_POOL));
+    // префикс сравнивается по компонентам пути
+    assert!(same(registry.pool_for("/database/file.txt"), &DEFAULT_CPU_POOL));
+    assert_eq!(registry.prefixes(), vec![std::path::PathBuf::from("/data/nested"), "/data".into()]);
+    assert!(registry.unregister("/data/nested"));
+    assert!(!registry.unregister("/data/nested"));
+    assert!(same(registry.pool_for("/data/nested/file.txt"), &DATA_POOL));
+
+    // конструкторы без явного пула используют глобальный реестр
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_dir = format!("{}it_pool_registry", TEST_TEMPORARY_DIR);
+    std::fs::create_dir_all(&test_dir).unwrap();
+    PoolRegistry::global().register(&test_dir, &DATA_POOL);
+    let test_file_path = format!("{}/file.txt", test_dir);
+    let sink = AsyncFileSink::create(&test_file_path).wait().unwrap();
+    assert!(same(sink.executor(), &DATA_POOL));
+    let stream = AsyncFileStream::open(&test_file_path).wait().unwrap();
+    assert!(same(stream.executor(), &DATA_POOL));
+    let read = AsyncOpenOptions::new().read(true).open_read(&test_file_path).wait().unwrap();
+    assert!(same(read.executor(), &DATA_POOL));
+    assert!(PoolRegistry::global().unregister(&test_dir));
+
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+
+#[test]
+fn it_blocking_executor() {
+    use futures::{Future, Stream, Sink};
+    use std::sync::Arc;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_blocking_executor.txt", TEST_TEMPORARY_DIR);
+
+    // пул, созданный во время работы
+    let cpu_pool = Arc::new(futures_cpupool::CpuPool::new(1));
+    let file = std::fs::File::create(&test_file_path).unwrap();
+    let sink = AsyncFileSink::from_std_with_executor(cpu_pool.clone(), file)
+        .send(Bytes::from(&b"0123456789"[..])).wait().unwrap();
+    let mut sink = sink.try_clone().unwrap();
+    futures::future::poll_fn(|| sink.poll_close()).wait().unwrap();
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let stream = AsyncFileStream::from_std_with_executor(cpu_pool.clone(), file, 4);
+    assert_eq!(&stream.concat2().wait().unwrap()[..], b"0123456789");
+
+    let task = cpu_pool.spawn_blocking(|| -> std::io::Result<()> { panic!("failed") });
+    assert_eq!(task.wait().unwrap_err().to_string(), "pool operation panicked");
+
+    // пул потоков tokio: операции помечаются `blocking`
+    let path = test_file_path.clone();
+    let (tx, rx) = futures::sync::oneshot::channel();
+    tokio::run(futures::future::lazy(move || {
+        let file = std::fs::File::open(&path).unwrap();
+        let read = AsyncFileRead::from_std_with_executor(TokioBlocking, file, 3);
+        tokio::io::read_to_end(read, Vec::new())
+            .map(|(_, data)| tx.send(data).unwrap())
+            .map_err(|error| panic!("{}", error))
+    }));
+    assert_eq!(rx.wait().unwrap(), b"0123456789");
+
+    // без исполнителя tokio операции выполняются в стандартном пуле
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let stream = AsyncFileStream::from_std_with_executor(TokioBlocking, file, 4);
+    assert_eq!(stream.collect().wait().unwrap().len(), 3);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_seek_and_positional() {
+    use futures::{Future, Stream};
+    use std::io::SeekFrom;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_seek_and_positional.txt", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"0123456789").unwrap();
+
+    let file = std::fs::OpenOptions::new().write(true).open(&test_file_path).unwrap();
+    let write = AsyncFileWrite::from_std(&TEST_CPU_POOL, file, TEST_BUFFER_SIZE);
+    let (write, position) = write.seek(SeekFrom::End(-2)).wait().unwrap();
+    assert_eq!(position, 8);
+    let (write, _) = tokio::io::write_all(write, b"xy".to_vec()).wait().unwrap();
+    write.write_at(0, Bytes::from(&b"ab"[..])).wait().unwrap();
+    // позиционная запись не меняет позицию записи
+    let (_, position) = write.seek(SeekFrom::Current(0)).wait().unwrap();
+    assert_eq!(position, 10);
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"ab234567xy");
+
+    let read = AsyncFileRead::open_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+    let reads: Vec<_> = (0..5).map(|i| read.read_at(i * 2, 2)).collect();
+    let chunks = futures::future::join_all(reads).wait().unwrap();
+    assert_eq!(chunks.concat(), b"ab234567xy");
+    assert_eq!(&read.read_at(8, 10).wait().unwrap()[..], b"xy");
+    let (read, _) = read.seek(SeekFrom::Start(6)).wait().unwrap();
+    let (_, data) = tokio::io::read_to_end(read, Vec::new()).wait().unwrap();
+    assert_eq!(data, b"67xy");
+
+    let stream = AsyncFileStream::open_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap();
+    assert_eq!(&stream.read_at(2, 3).wait().unwrap()[..], b"234");
+    let (stream, _) = Seek::new(stream.timeout(std::time::Duration::from_secs(5)), SeekFrom::Start(7)).wait().unwrap();
+    assert_eq!(&stream.concat2().wait().unwrap()[..], b"7xy");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_stream_from_range() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_stream_from_range.txt", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"0123456789").unwrap();
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let chunks: Vec<Bytes> = AsyncFileStream::from_range(&TEST_CPU_POOL, file, 3, 6, 4).collect().wait().unwrap();
+    // последний блок укорачивается до конца диапазона
+    assert_eq!(chunks, vec![Bytes::from(&b"3456"[..]), Bytes::from(&b"78"[..])]);
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let stream = AsyncFileStream::from_range(&TEST_CPU_POOL, file, 8, 100, 4);
+    assert_eq!(&stream.concat2().wait().unwrap()[..], b"89");
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let stream = AsyncFileStream::from_range(&TEST_CPU_POOL, file, 2, 0, 4);
+    assert!(stream.collect().wait().unwrap().is_empty());
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_stream_read_ahead() {
+    use futures::{Future, Stream};
+    use std::io::SeekFrom;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_stream_read_ahead.txt", TEST_TEMPORARY_DIR);
+    let data: Vec<u8> = (0..1000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&test_file_path, &data).unwrap();
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let stream = AsyncFileStream::from_std(&TEST_CPU_POOL, file, 64).read_ahead(4);
+    let chunks: Vec<Bytes> = stream.collect().wait().unwrap();
+    assert_eq!(chunks.len(), 16);
+    assert_eq!(chunks.concat(), data);
+
+    // диапазон и ограничение учитываются, последний блок укорачивается
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let stream = AsyncFileStream::from_range(&TEST_CPU_POOL, file, 100, 150, 64).read_ahead(3);
+    let chunks: Vec<Bytes> = stream.collect().wait().unwrap();
+    assert_eq!(chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>(), vec![64, 64, 22]);
+    assert_eq!(chunks.concat(), &data[100..250]);
+
+    // перемещение позиции продолжает чтение с конца отданных данных
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let stream = AsyncFileStream::from_std(&TEST_CPU_POOL, file, 100).read_ahead(2);
+    let (chunk, stream) = stream.into_future().wait().map_err(|(error, _)| error).unwrap();
+    assert_eq!(&chunk.unwrap()[..], &data[..100]);
+    let (stream, position) = stream.seek(SeekFrom::Current(50)).wait().unwrap();
+    assert_eq!(position, 150);
+    let (chunk, stream) = stream.into_future().wait().map_err(|(error, _)| error).unwrap();
+    assert_eq!(&chunk.unwrap()[..], &data[150..250]);
+    let mut file: std::fs::File = std::convert::TryFrom::try_from(stream).unwrap();
+    assert_eq!(std::io::Seek::seek(&mut file, SeekFrom::Current(0)).unwrap(), 250);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_sink_write_queue() {
+    use futures::{Future, Sink, AsyncSink};
+    use super::*;
+
+    lazy_static! {
+        static ref QUEUE_POOL: futures_cpupool::CpuPool = futures_cpupool::CpuPool::new(1);
+    }
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_sink_write_queue.txt", TEST_TEMPORARY_DIR);
+
+    // единственный поток пула занят, пока не будет отправлен сигнал
+    let (release, blocked) = std::sync::mpsc::channel::<()>();
+    QUEUE_POOL.spawn_fn(move || blocked.recv().map_err(|_| ())).forget();
+
+    let file = std::fs::File::create(&test_file_path).unwrap();
+    let mut sink = AsyncFileSink::from_std(&QUEUE_POOL, file).write_queue(3, 6);
+    futures::future::lazy(|| {
+        let item = |data: &'static [u8]| Bytes::from(data);
+        assert!(sink.start_send(item(b"ab")).unwrap().is_ready());
+        assert!(sink.poll_ready().unwrap().is_ready());
+        assert!(sink.start_send(item(b"cd")).unwrap().is_ready());
+        assert!(sink.start_send(item(b"ef")).unwrap().is_ready());
+        assert!(sink.start_send(item(b"gh")).unwrap().is_ready());
+        // в очереди 6 байт
+        assert!(sink.poll_ready().unwrap().is_not_ready());
+        match sink.start_send(item(b"ij")).unwrap() {
+            AsyncSink::NotReady(item) => assert_eq!(&item[..], b"ij"),
+            AsyncSink::Ready => panic!("the queue must be full"),
+        }
+        Ok::<_, ()>(())
+    }).wait().unwrap();
+
+    release.send(()).unwrap();
+    let mut sink = sink.send(Bytes::from(&b"ij"[..])).wait().unwrap();
+    futures::future::poll_fn(|| sink.poll_close()).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"abcdefghij");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_buf_writer() {
+    use futures::Future;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    // считает обращения к пулу потоков
+    #[derive(Clone)]
+    struct Counting(Arc<AtomicUsize>);
+    impl BlockingExecutor for Counting {
+        fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            TEST_CPU_POOL.execute(job);
+        }
+    }
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_buf_writer.txt", TEST_TEMPORARY_DIR);
+
+    let jobs = Arc::new(AtomicUsize::new(0));
+    let file = std::fs::File::create(&test_file_path).unwrap();
+    let async_file_write = AsyncFileWrite::from_std_with_executor(Counting(jobs.clone()), file, TEST_BUFFER_SIZE);
+    let mut writer = io::AsyncBufWriter::with_capacity(64, async_file_write);
+    let mut expected = Vec::new();
+    for i in 0..100 {
+        let record = format!("{};", i);
+        expected.extend_from_slice(record.as_bytes());
+        writer = tokio::io::write_all(writer, record).wait().unwrap().0;
+    }
+    assert!(!writer.buffer().is_empty());
+    let writer = tokio::io::flush(writer).wait().unwrap();
+    assert!(writer.buffer().is_empty());
+    // 290 байт: по записи на каждые 64 байта и сброс
+    assert!(jobs.load(Ordering::SeqCst) <= 6);
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), expected);
+
+    // запись больше буфера передается напрямую
+    let big = vec![b'x'; 100];
+    let writer = tokio::io::write_all(writer, big.clone()).wait().unwrap().0;
+    assert!(writer.buffer().is_empty());
+    tokio::io::shutdown(writer).wait().unwrap();
+    expected.extend_from_slice(&big);
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), expected);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_sync_and_shutdown() {
+    use futures::{Future, Sink};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_sync_and_shutdown.txt", TEST_TEMPORARY_DIR);
+
+    let file = std::fs::File::create(&test_file_path).unwrap();
+    let async_file_write = AsyncFileWrite::from_std(&TEST_CPU_POOL, file, TEST_BUFFER_SIZE);
+    let (mut async_file_write, _) = tokio::io::write_all(async_file_write, b"Hello").wait().unwrap();
+    futures::future::poll_fn(|| async_file_write.poll_sync_data()).wait().unwrap();
+    let (mut async_file_write, _) = tokio::io::write_all(async_file_write, b" world").wait().unwrap();
+    futures::future::poll_fn(|| async_file_write.poll_sync_all()).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"Hello world");
+
+    // `shutdown` дожидается начатой записи
+    futures::future::lazy(|| {
+        match std::io::Write::write(&mut async_file_write, b"!") {
+            Ok(1) => {},
+            Err(ref error) if error.kind() == std::io::ErrorKind::WouldBlock => {},
+            result => panic!("unexpected result: {:?}", result),
+        }
+        Ok::<_, ()>(())
+    }).wait().unwrap();
+    tokio::io::shutdown(async_file_write).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"Hello world!");
+
+    let file = std::fs::File::create(&test_file_path).unwrap();
+    let mut sink = AsyncFileSink::from_std(&TEST_CPU_POOL, file)
+        .send(Bytes::from(&b"first"[..])).wait().unwrap();
+    futures::future::poll_fn(|| sink.poll_sync_all()).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"first");
+    let mut sink = sink.send(Bytes::from(&b" second"[..])).wait().unwrap();
+    futures::future::poll_fn(|| sink.poll_sync_data()).wait().unwrap();
+    futures::future::poll_fn(|| sink.poll_close()).wait().unwrap();
+    assert!(futures::future::poll_fn(|| sink.poll_sync_all()).wait().is_err());
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"first second");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_read_write_whole_file() {
+    use futures::Future;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_read_write_whole_file.txt", TEST_TEMPORARY_DIR);
+
+    write(&test_file_path, "name = \"config\"\n").wait().unwrap();
+    assert_eq!(read_to_string(&test_file_path).wait().unwrap(), "name = \"config\"\n");
+
+    write_with_pool(&TEST_CPU_POOL, &test_file_path, vec![0xff, 0xfe]).wait().unwrap();
+    assert_eq!(read_with_pool(&TEST_CPU_POOL, &test_file_path).wait().unwrap(), vec![0xff, 0xfe]);
+    assert_eq!(read_to_string(&test_file_path).wait().unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+
+    std::fs::remove_file(&test_file_path).unwrap();
+    assert_eq!(read(&test_file_path).wait().unwrap_err().kind(), std::io::ErrorKind::NotFound);
+}
+
+
+#[test]
+fn it_ops() {
+    use futures::Future;
+    use super::*;
+
+    let test_dir_path = format!("{}it_ops/", TEST_TEMPORARY_DIR);
+    let nested = format!("{}a/b", test_dir_path);
+    ops::create_dir_all(&nested).wait().unwrap();
+    assert!(ops::metadata(&nested).wait().unwrap().is_dir());
+    assert_eq!(ops::create_dir(&nested).wait().unwrap_err().kind(), std::io::ErrorKind::AlreadyExists);
+
+    let file_path = format!("{}/file.txt", nested);
+    std::fs::write(&file_path, b"0123456789").unwrap();
+    let copy_path = format!("{}copy.txt", test_dir_path);
+    assert_eq!(ops::copy_with_pool(&TEST_CPU_POOL, &file_path, &copy_path).wait().unwrap(), 10);
+    let renamed_path = format!("{}renamed.txt", test_dir_path);
+    ops::rename(&copy_path, &renamed_path).wait().unwrap();
+    assert_eq!(ops::metadata(&copy_path).wait().unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    assert_eq!(ops::metadata(&renamed_path).wait().unwrap().len(), 10);
+
+    let link_path = format!("{}link.txt", test_dir_path);
+    ops::hard_link(&renamed_path, &link_path).wait().unwrap();
+    assert_eq!(std::fs::read(&link_path).unwrap(), b"0123456789");
+    ops::remove_file(&link_path).wait().unwrap();
+
+    #[cfg(unix)]
+    {
+        let symlink_path = format!("{}symlink.txt", test_dir_path);
+        ops::symlink("renamed.txt", &symlink_path).wait().unwrap();
+        assert!(ops::symlink_metadata(&symlink_path).wait().unwrap().file_type().is_symlink());
+        assert!(ops::metadata(&symlink_path).wait().unwrap().is_file());
+    }
+
+    ops::remove_dir(&nested).wait().unwrap_err();
+    ops::remove_dir_all(&test_dir_path).wait().unwrap();
+    assert!(!std::path::Path::new(&test_dir_path).exists());
+}
+
+
+#[test]
+fn it_read_dir() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    let test_dir_path = format!("{}it_read_dir/", TEST_TEMPORARY_DIR);
+    std::fs::create_dir_all(format!("{}sub", test_dir_path)).unwrap();
+    for i in 0..10 {
+        std::fs::write(format!("{}{}.txt", test_dir_path, i), vec![0; i]).unwrap();
+    }
+
+    let entries = read_dir_with_pool(&TEST_CPU_POOL, &test_dir_path)
+        .batch_size(3)
+        .collect().wait().unwrap();
+    assert_eq!(entries.len(), 11);
+    let sub = entries.iter().find(|entry| entry.file_name() == "sub").unwrap();
+    assert!(sub.file_type().is_dir());
+    let file = entries.iter().find(|entry| entry.file_name() == "7.txt").unwrap();
+    assert!(file.file_type().is_file());
+    assert_eq!(file.path(), std::path::Path::new(&format!("{}7.txt", test_dir_path)));
+    assert_eq!(file.metadata().wait().unwrap().len(), 7);
+
+    let missing = read_dir(format!("{}missing", test_dir_path)).collect().wait();
+    assert_eq!(missing.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_walk_options() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    let test_dir_path: std::path::PathBuf = format!("{}it_walk_options", TEST_TEMPORARY_DIR).into();
+    let _ = std::fs::remove_dir_all(&test_dir_path);
+    std::fs::create_dir_all(test_dir_path.join("a/b/c")).unwrap();
+    std::fs::create_dir_all(test_dir_path.join("skip/deep")).unwrap();
+    std::fs::write(test_dir_path.join("a/b/c/file.txt"), b"data").unwrap();
+    std::fs::write(test_dir_path.join("skip/deep/file.txt"), b"data").unwrap();
+
+    let paths = |stream: WalkStream| {
+        let mut paths: Vec<String> = stream.collect().wait().unwrap().into_iter()
+            .map(|entry| entry.path.strip_prefix(&test_dir_path).unwrap().to_string_lossy().replace('\\', "/"))
+            .collect();
+        paths.sort();
+        paths
+    };
+
+    assert_eq!(paths(walk_with_pool(&TEST_CPU_POOL, &test_dir_path).max_depth(2)), vec!["a", "a/b", "skip", "skip/deep"]);
+    assert_eq!(paths(walk_with_pool(&TEST_CPU_POOL, &test_dir_path).max_depth(0)).len(), 0);
+    assert_eq!(
+        paths(walk_with_pool(&TEST_CPU_POOL, &test_dir_path).filter_entry(|entry| !entry.path.ends_with("skip"))),
+        vec!["a", "a/b", "a/b/c", "a/b/c/file.txt"],
+    );
+
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink("../a", test_dir_path.join("skip/link")).unwrap();
+        assert_eq!(paths(walk_with_pool(&TEST_CPU_POOL, test_dir_path.join("skip"))), vec!["skip/deep", "skip/deep/file.txt", "skip/link"]);
+        let mut followed: Vec<_> = walk_with_pool(&TEST_CPU_POOL, test_dir_path.join("skip")).follow_links(true)
+            .collect().wait().unwrap().into_iter()
+            .map(|entry| entry.path.strip_prefix(test_dir_path.join("skip")).unwrap().to_string_lossy().into_owned())
+            .collect();
+        followed.sort();
+        assert_eq!(followed, vec!["deep", "deep/file.txt", "link", "link/b", "link/b/c", "link/b/c/file.txt"]);
+
+        // ссылка на каталог выше по дереву
+        std::os::unix::fs::symlink("..", test_dir_path.join("a/b/up")).unwrap();
+        let results: Vec<_> = walk_with_pool(&TEST_CPU_POOL, test_dir_path.join("a")).follow_links(true)
+            .then(Ok::<_, ()>)
+            .collect().wait().unwrap();
+        assert!(results.iter().any(|result| result.is_err()));
+    }
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_write_atomic() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    let test_dir_path = format!("{}it_write_atomic/", TEST_TEMPORARY_DIR);
+    std::fs::create_dir_all(&test_dir_path).unwrap();
+    let test_file_path = format!("{}config.toml", test_dir_path);
+    std::fs::write(&test_file_path, b"old").unwrap();
+
+    let chunks = vec![Bytes::from(&b"new "[..]), Bytes::from(&b"content"[..])];
+    let written = write_atomic_with_pool(&TEST_CPU_POOL, &test_file_path, futures::stream::iter_ok::<_, std::io::Error>(chunks))
+        .wait().unwrap();
+    assert_eq!(written, 11);
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"new content");
+    assert_eq!(std::fs::read_dir(&test_dir_path).unwrap().count(), 1);
+
+    // ошибка потока: целевой файл не изменяется, временный удаляется
+    let failing = futures::stream::iter_result(vec![
+        Ok(Bytes::from(&b"partial"[..])),
+        Err(std::io::Error::other("source failed")),
+    ]);
+    assert!(write_atomic_with_pool(&TEST_CPU_POOL, &test_file_path, failing).wait().is_err());
+
+    // уничтожение future до завершения
+    let (tx, rx) = futures::sync::mpsc::channel::<Bytes>(1);
+    let mut writing = write_atomic_with_pool(&TEST_CPU_POOL, &test_file_path, rx.map_err(|_| std::io::Error::other("channel")));
+    let tx = futures::Sink::send(tx, Bytes::from(&b"unfinished"[..])).wait().unwrap();
+    let temporary = writing.temporary_path().to_path_buf();
+    while !temporary.exists() {
+        futures::future::lazy(|| Ok::<_, ()>(writing.poll().unwrap().is_ready())).wait().unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+    drop(writing);
+    drop(tx);
+    for _ in 0..1000 {
+        if std::fs::read_dir(&test_dir_path).unwrap().count() == 1 {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+    assert!(!temporary.exists());
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"new content");
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_copy_with_progress() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let src_path = format!("{}it_copy_with_progress.src", TEST_TEMPORARY_DIR);
+    let dst_path = format!("{}it_copy_with_progress.dst", TEST_TEMPORARY_DIR);
+    let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&src_path, &data).unwrap();
+
+    let progress = copy_progress_with_pool(&TEST_CPU_POOL, &src_path, &dst_path).chunk_size(30_000);
+    assert_eq!(progress.collect().wait().unwrap(), vec![30_000, 60_000, 90_000, 100_000]);
+    assert_eq!(std::fs::read(&dst_path).unwrap(), data);
+
+    std::fs::write(&dst_path, b"longer previous content, truncated by the copy").unwrap();
+    std::fs::write(&src_path, b"short").unwrap();
+    assert_eq!(copy(&src_path, &dst_path).wait().unwrap(), 5);
+    assert_eq!(std::fs::read(&dst_path).unwrap(), b"short");
+
+    std::fs::remove_file(&src_path).unwrap();
+    assert_eq!(copy(&src_path, &dst_path).wait().unwrap_err().kind(), std::io::ErrorKind::NotFound);
+    std::fs::remove_file(dst_path).unwrap();
+}
+
+
+#[test]
+fn it_watch() {
+    use futures::Stream;
+    use super::*;
+
+    let test_dir_path: std::path::PathBuf = format!("{}it_watch", TEST_TEMPORARY_DIR).into();
+    let _ = std::fs::remove_dir_all(&test_dir_path);
+    std::fs::create_dir_all(&test_dir_path).unwrap();
+
+    // изменения выполняются после начала наблюдения
+    let changes = |dir: std::path::PathBuf| std::thread::spawn(move || {
+        let pause = || std::thread::sleep(std::time::Duration::from_millis(200));
+        pause();
+        std::fs::write(dir.join("a.txt"), b"data").unwrap();
+        pause();
+        std::fs::OpenOptions::new().append(true).open(dir.join("a.txt")).unwrap().write_all(b" more").unwrap();
+        pause();
+        std::fs::rename(dir.join("a.txt"), dir.join("b.txt")).unwrap();
+        pause();
+        std::fs::remove_file(dir.join("b.txt")).unwrap();
+    });
+    let collect = |stream: Watch| {
+        let mut events = Vec::new();
+        for event in stream.wait() {
+            let event = event.unwrap();
+            let done = event == FsEvent::Removed(test_dir_path.join("b.txt"));
+            events.push(event);
+            if done {
+                break;
+            }
+        }
+        events
+    };
+
+    let worker = changes(test_dir_path.clone());
+    let events = collect(watch_with_pool(&TEST_CPU_POOL, &test_dir_path));
+    worker.join().unwrap();
+    assert!(events.contains(&FsEvent::Created(test_dir_path.join("a.txt"))));
+    assert!(events.contains(&FsEvent::Modified(test_dir_path.join("a.txt"))));
+    #[cfg(target_os = "linux")]
+    assert!(events.contains(&FsEvent::Renamed { from: test_dir_path.join("a.txt"), to: test_dir_path.join("b.txt") }));
+
+    let worker = changes(test_dir_path.clone());
+    let stream = watch_with_pool(&TEST_CPU_POOL, &test_dir_path)
+        .polling(true)
+        .interval(std::time::Duration::from_millis(20));
+    let events = collect(stream);
+    worker.join().unwrap();
+    assert_eq!(events, vec![
+        FsEvent::Created(test_dir_path.join("a.txt")),
+        FsEvent::Modified(test_dir_path.join("a.txt")),
+        FsEvent::Removed(test_dir_path.join("a.txt")),
+        FsEvent::Created(test_dir_path.join("b.txt")),
+        FsEvent::Removed(test_dir_path.join("b.txt")),
+    ]);
+
+    let missing = watch_with_pool(&TEST_CPU_POOL, test_dir_path.join("missing")).into_future().wait();
+    assert_eq!(missing.map(|_| ()).unwrap_err().0.kind(), std::io::ErrorKind::NotFound);
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_follow_watch_changes() {
+    use super::*;
+
+    let test_dir_path = format!("{}it_follow_watch_changes", TEST_TEMPORARY_DIR);
+    let test_file_path = format!("{}/app.log", test_dir_path);
+    let _ = std::fs::remove_dir_all(&test_dir_path);
+    std::fs::create_dir_all(&test_dir_path).unwrap();
+    std::fs::write(&test_file_path, b"one\n").unwrap();
+
+    // без наблюдения следующая проверка была бы только через 5 с
+    let mut follow = futures::executor::spawn(
+        AsyncFileFollow::open_with_pool(&TEST_CPU_POOL, &test_file_path, false)
+            .interval(std::time::Duration::from_secs(5))
+            .watch_changes(true),
+    );
+    let mut next = move || follow.wait_stream().unwrap().unwrap();
+    assert_eq!(next(), FollowEvent::Data("one\n".into()));
+
+    let started = std::time::Instant::now();
+    let writer = {
+        let test_file_path = test_file_path.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(300));
+            std::fs::OpenOptions::new().append(true).open(&test_file_path).unwrap().write_all(b"two\n").unwrap();
+        })
+    };
+    assert_eq!(next(), FollowEvent::Data("two\n".into()));
+    writer.join().unwrap();
+    #[cfg(target_os = "linux")]
+    assert!(started.elapsed() < std::time::Duration::from_secs(3));
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_file_lines() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_file_lines.log", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"first line\r\nsecond\n\nspans several chunks\nlast").unwrap();
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let lines = AsyncFileStream::from_std(&TEST_CPU_POOL, file, 4).lines()
+        .map(|line| String::from_utf8(line.to_vec()).unwrap())
+        .collect().wait().unwrap();
+    assert_eq!(lines, vec!["first line", "second", "", "spans several chunks", "last"]);
+
+    // слишком длинная строка пропускается
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let results: Vec<_> = AsyncFileStream::from_std(&TEST_CPU_POOL, file, 4).lines()
+        .max_line_length(10)
+        .then(|result| Ok::<_, ()>(result.map_err(|error| error.kind())))
+        .collect().wait().unwrap();
+    assert_eq!(results, vec![
+        Ok(Bytes::from(&b"first line"[..])),
+        Ok(Bytes::from(&b"second"[..])),
+        Ok(Bytes::new()),
+        Err(std::io::ErrorKind::InvalidData),
+        Ok(Bytes::from(&b"last"[..])),
+    ]);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_framed() {
+    use futures::{Future, Sink, Stream};
+    use tokio::codec::{LengthDelimitedCodec, LinesCodec};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_framed.bin", TEST_TEMPORARY_DIR);
+
+    let records = vec![Bytes::from(&b"first"[..]), Bytes::new(), Bytes::from(vec![7u8; 20_000])];
+    let file = std::fs::File::create(&test_file_path).unwrap();
+    AsyncFileWrite::from_std(&TEST_CPU_POOL, file, TEST_BUFFER_SIZE)
+        .framed(LengthDelimitedCodec::new())
+        .send_all(futures::stream::iter_ok::<_, std::io::Error>(records.clone()))
+        .and_then(|(mut framed, _)| futures::future::poll_fn(move || framed.close()))
+        .wait().unwrap();
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let read: Vec<Bytes> = AsyncFileRead::from_std(&TEST_CPU_POOL, file, TEST_BUFFER_SIZE)
+        .framed(LengthDelimitedCodec::new())
+        .map(|record| record.freeze())
+        .collect().wait().unwrap();
+    assert_eq!(read, records);
+
+    std::fs::write(&test_file_path, b"one\ntwo\n").unwrap();
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let lines = AsyncFileRead::from_std(&TEST_CPU_POOL, file, TEST_BUFFER_SIZE)
+        .framed(LinesCodec::new())
+        .collect().wait().unwrap();
+    assert_eq!(lines, vec!["one", "two"]);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_async_file_read_modify_write() {
+    use futures::Future;
+    use std::io::SeekFrom;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_async_file.txt", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"hello world, hello file").unwrap();
+
+    let file = std::fs::OpenOptions::new().read(true).write(true).open(&test_file_path).unwrap();
+    // буфер больше читаемого фрагмента: запись должна начаться сразу после прочитанных данных
+    let file = AsyncFile::from_std(&TEST_CPU_POOL, file, TEST_BUFFER_SIZE);
+    let (file, head) = tokio::io::read_exact(file, vec![0u8; 6]).wait().unwrap();
+    assert_eq!(head, b"hello ");
+    let (file, _) = tokio::io::write_all(file, b"WORLD").wait().unwrap();
+    let (file, position) = file.seek(SeekFrom::Current(2)).wait().unwrap();
+    assert_eq!(position, 13);
+    let (file, rest) = tokio::io::read_to_end(file, Vec::new()).wait().unwrap();
+    assert_eq!(rest, b"hello file");
+    let (file, position) = file.seek(SeekFrom::Start(0)).wait().unwrap();
+    assert_eq!(position, 0);
+    let (file, all) = tokio::io::read_to_end(file, Vec::new()).wait().unwrap();
+    assert_eq!(all, b"hello WORLD, hello file");
+    tokio::io::shutdown(file).wait().unwrap();
+
+    let file = std::fs::OpenOptions::new().read(true).write(true).open(&test_file_path).unwrap();
+    let mut file = AsyncFile::from_std(&TEST_CPU_POOL, file, TEST_BUFFER_SIZE);
+    futures::future::poll_fn(|| file.poll_set_len(5)).wait().unwrap();
+    let (file, all) = tokio::io::read_to_end(file, Vec::new()).wait().unwrap();
+    assert_eq!(all, b"hello");
+    let mut file = std::fs::File::try_from(file).unwrap();
+    assert_eq!(std::io::Seek::seek(&mut file, SeekFrom::Current(0)).unwrap(), 5);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_read_bytes_without_copy() {
+    use futures::Future;
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_read_bytes.bin", TEST_TEMPORARY_DIR);
+    let content: Vec<u8> = (0..(TEST_BUFFER_SIZE * 3 + 17)).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&test_file_path, &content).unwrap();
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let mut file = AsyncFileRead::from_std(&TEST_CPU_POOL, file, TEST_BUFFER_SIZE);
+    let mut read = Vec::new();
+    while let Some(block) = futures::future::poll_fn(|| file.poll_read_bytes()).wait().unwrap() {
+        assert!(block.len() <= TEST_BUFFER_SIZE);
+        read.extend_from_slice(&block);
+    }
+    assert_eq!(read, content);
+
+    // повторный вызов `read` с буфером меньше исходного не теряет прочитанные данные;
+    // задачи выполняются вручную, чтобы первый вызов гарантированно вернул `WouldBlock`
+    type Job = Box<dyn FnOnce() + Send>;
+    #[derive(Clone)]
+    struct Deferred(Arc<Mutex<Vec<Job>>>);
+    impl BlockingExecutor for Deferred {
+        fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+            self.0.lock().unwrap().push(job);
+        }
+    }
+    let executor = Deferred(Arc::new(Mutex::new(Vec::new())));
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let mut file = AsyncFileRead::from_std_with_executor(executor.clone(), file, TEST_BUFFER_SIZE);
+    let mut large = vec![0u8; 64];
+    let mut small = [0u8; 10];
+    futures::future::poll_fn(|| {
+        assert_eq!(file.read(&mut large).unwrap_err().kind(), std::io::ErrorKind::WouldBlock);
+        let jobs: Vec<_> = executor.0.lock().unwrap().drain(..).collect();
+        for job in jobs {
+            job();
+        }
+        assert_eq!(file.read(&mut small).unwrap(), 10);
+        Ok::<_, std::io::Error>(Async::Ready(()))
+    }).wait().unwrap();
+    assert_eq!(&small[..], &content[..10]);
+    let mut file = std::fs::File::try_from(file).unwrap();
+    let mut rest = Vec::new();
+    file.read_to_end(&mut rest).unwrap();
+    assert_eq!(rest, &content[10..]);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_vectored_io() {
+    use futures::{Future, Sink};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_vectored_io.bin", TEST_TEMPORARY_DIR);
+
+    let file = std::fs::File::create(&test_file_path).unwrap();
+    let mut file = AsyncFileWrite::from_std(&TEST_CPU_POOL, file, TEST_BUFFER_SIZE);
+    let bufs = vec![Bytes::from(&b"HEAD"[..]), Bytes::new(), Bytes::from(vec![b'x'; 100_000])];
+    futures::future::poll_fn(|| file.poll_write_vectored(&bufs)).wait().unwrap();
+    let (file, _) = tokio::io::write_all(file, b"tail").wait().unwrap();
+    tokio::io::shutdown(file).wait().unwrap();
+
+    let file = std::fs::File::open(&test_file_path).unwrap();
+    let mut file = AsyncFileRead::from_std(&TEST_CPU_POOL, file, TEST_BUFFER_SIZE);
+    let parts = futures::future::poll_fn(|| file.poll_read_vectored(&[4, 100_000, 2])).wait().unwrap();
+    assert_eq!(parts[0], Bytes::from(&b"HEAD"[..]));
+    assert_eq!(parts[1], Bytes::from(vec![b'x'; 100_000]));
+    assert_eq!(parts[2], Bytes::from(&b"ta"[..]));
+    // в конце файла последний фрагмент короче, следующие пусты
+    let parts = futures::future::poll_fn(|| file.poll_read_vectored(&[3, 5])).wait().unwrap();
+    assert_eq!(parts, vec![Bytes::from(&b"il"[..]), Bytes::new()]);
+
+    let file = std::fs::File::create(&test_file_path).unwrap();
+    let mut sink = AsyncFileSink::from_std(&TEST_CPU_POOL, file);
+    let items = vec![Bytes::from(&b"header:"[..]), Bytes::from(&b"body;"[..])];
+    futures::future::poll_fn(|| {
+        match sink.start_send_vectored(items.clone())? {
+            AsyncSink::Ready => Ok(Async::Ready(())),
+            AsyncSink::NotReady(_) => Ok::<_, std::io::Error>(Async::NotReady),
+        }
+    }).wait().unwrap();
+    let mut sink = sink.send(Bytes::from(&b"next"[..])).wait().unwrap();
+    futures::future::poll_fn(|| sink.poll_close()).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"header:body;next");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_direct_io() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_direct_io.bin", TEST_TEMPORARY_DIR);
+    let content: Vec<u8> = (0..(DIRECT_IO_ALIGNMENT * 5 + 123)).map(|i| (i % 253) as u8).collect();
+
+    let mut options = AsyncOpenOptions::new();
+    options.write(true).create(true).truncate(true).cpu_pool(&TEST_CPU_POOL).buffer_size(DIRECT_IO_ALIGNMENT * 2);
+    #[cfg(target_os = "linux")]
+    options.direct(true);
+    // файловая система может не поддерживать `O_DIRECT` (например, tmpfs)
+    let file = match options.open_write(&test_file_path).wait() {
+        Ok(file) => file,
+        Err(ref error) if error.raw_os_error() == Some(22) => {
+            AsyncFileWrite::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap(), DIRECT_IO_ALIGNMENT * 2)
+                .direct_io(DIRECT_IO_ALIGNMENT)
+        },
+        Err(error) => panic!("{}", error),
+    };
+    // неполный блок записывается при `flush` и перезаписывается следующими данными
+    let (file, _) = tokio::io::write_all(file, content[..100].to_vec()).wait().unwrap();
+    let file = tokio::io::flush(file).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), &content[..100]);
+    let (file, _) = tokio::io::write_all(file, content[100..].to_vec()).wait().unwrap();
+    tokio::io::shutdown(file).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), content);
+
+    let mut options = AsyncOpenOptions::new();
+    options.read(true).cpu_pool(&TEST_CPU_POOL).buffer_size(DIRECT_IO_ALIGNMENT);
+    #[cfg(target_os = "linux")]
+    options.direct(true);
+    let stream = match options.open_stream(&test_file_path).wait() {
+        Ok(stream) => stream,
+        Err(ref error) if error.raw_os_error() == Some(22) => {
+            AsyncFileStream::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), DIRECT_IO_ALIGNMENT)
+                .direct_io(DIRECT_IO_ALIGNMENT)
+        },
+        Err(error) => panic!("{}", error),
+    };
+    let read = stream.concat2().wait().unwrap();
+    assert_eq!(&read[..], &content[..]);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_io_uring() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_io_uring.txt", TEST_TEMPORARY_DIR);
+    let content: Vec<u8> = (0..(TEST_BUFFER_SIZE * 3 + 17)).map(|i| (i % 251) as u8).collect();
+
+    // без io_uring (старое ядро, seccomp или сборка без возможности) операции выполняются в пуле
+    let file = AsyncFileWrite::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap(), TEST_BUFFER_SIZE)
+        .io_uring(true);
+    let (file, _) = tokio::io::write_all(file, content.clone()).wait().unwrap();
+    tokio::io::shutdown(file).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), content);
+
+    let file = AsyncFileRead::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE)
+        .io_uring(true);
+    let (_, read) = tokio::io::read_to_end(file, Vec::new()).wait().unwrap();
+    assert_eq!(read, content);
+
+    // маленький буфер хранит данные внутри себя
+    let file = AsyncFileRead::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), 7)
+        .io_uring(true);
+    let (_, read) = tokio::io::read_to_end(file, Vec::new()).wait().unwrap();
+    assert_eq!(read, content);
+
+    let stream = AsyncFileStream::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE)
+        .io_uring(true)
+        .range(5, 10000);
+    let read = stream.concat2().wait().unwrap();
+    assert_eq!(&read[..], &content[5..10005]);
+
+    #[cfg(all(feature = "io_uring", target_os = "linux"))]
+    println!("io_uring available: {}", io_uring_available());
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+#[test]
+fn it_io_uring_submit_failure() {
+    use futures::Future;
+    use super::*;
+
+    if !io_uring_available() {
+        return;
+    }
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_io_uring_submit_failure.txt", TEST_TEMPORARY_DIR);
+    let file = std::fs::File::create(&test_file_path).unwrap();
+    let finish = |file: std::fs::File, buf: Vec<u8>, result: std::io::Result<usize>| result.map(|size| (file, buf, size));
+
+    // отправка не удалась: файл и буфер возвращаются для выполнения операции в пуле
+    uring::FAIL_SUBMIT.with(|fail| fail.set(true));
+    let (file, buf) = uring::write(file, b"one ".to_vec(), finish).err().unwrap();
+
+    let (file, _, size) = uring::write(file, buf, finish).ok().unwrap().wait().unwrap();
+    assert_eq!(size, 4);
+    drop(file);
+    // запись, убранная из очереди при ошибке, не отправлена ядру повторно
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"one ");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[cfg(unix)]
+#[test]
+fn it_mmap_prefault() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let test_file_path = format!("{}it_mmap_prefault.txt", TEST_TEMPORARY_DIR);
+    let content: Vec<u8> = (0..100000).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&test_file_path, &content).unwrap();
+
+    let chunks = MmapStream::open_with_pool(&TEST_CPU_POOL, &test_file_path)
+        .chunk_size(10000)
+        .prefault(3)
+        .collect().wait().unwrap();
+    assert_eq!(chunks.len(), 10);
+    let data: Vec<u8> = chunks.iter().flat_map(|chunk| chunk.iter().cloned()).collect();
+    assert_eq!(data, content);
+    drop(chunks);
+
+    std::fs::write(&test_file_path, b"").unwrap();
+    assert_eq!(MmapStream::open_with_pool(&TEST_CPU_POOL, &test_file_path).prefault(2).collect().wait().unwrap().len(), 0);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_allocate_and_set_len() {
+    use futures::{Future, Sink};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_allocate_and_set_len.txt", TEST_TEMPORARY_DIR);
+
+    let file = AsyncFileWrite::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap(), TEST_BUFFER_SIZE)
+        .trim_on_shutdown(true);
+    let file = file.allocate(1 << 20).wait().unwrap();
+    assert_eq!(std::fs::metadata(&test_file_path).unwrap().len(), 1 << 20);
+    // выделение места не уменьшает файл
+    let file = file.allocate(16).wait().unwrap();
+    assert_eq!(std::fs::metadata(&test_file_path).unwrap().len(), 1 << 20);
+    let (file, _) = tokio::io::write_all(file, b"hello".to_vec()).wait().unwrap();
+    tokio::io::shutdown(file).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"hello");
+
+    // элементы, принятые до изменения длины, записываются раньше
+    let sink = AsyncFileSink::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap());
+    let sink = sink.send(Bytes::from(&b"0123456789"[..])).wait().unwrap();
+    let sink = sink.set_len(4).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"0123");
+    let sink = sink.allocate(4096).wait().unwrap();
+    assert_eq!(std::fs::metadata(&test_file_path).unwrap().len(), 4096);
+    drop(sink);
+
+    let file = AsyncFile::from_std(&TEST_CPU_POOL, std::fs::OpenOptions::new().read(true).write(true).open(&test_file_path).unwrap(), TEST_BUFFER_SIZE);
+    let file = file.set_len(2).wait().unwrap();
+    let file = file.allocate(10).wait().unwrap();
+    drop(file);
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"01\0\0\0\0\0\0\0\0");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[cfg(any(unix, windows))]
+#[test]
+fn it_lock_file_handles() {
+    use futures::Future;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_lock_file_handles.spool", TEST_TEMPORARY_DIR);
+
+    let guard = lock_with_pool(&TEST_CPU_POOL, &test_file_path, LockMode::Exclusive).wait().unwrap();
+    let error = lock_with_pool(&TEST_CPU_POOL, &test_file_path, LockMode::Shared)
+        .timeout(std::time::Duration::from_millis(20))
+        .wait().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+
+    let file = AsyncFileWrite::from_std(&TEST_CPU_POOL, std::fs::OpenOptions::new().write(true).open(&test_file_path).unwrap(), TEST_BUFFER_SIZE);
+    assert!(file.try_lock(LockMode::Exclusive).unwrap().is_none());
+    let waiting = file.lock_exclusive().unwrap();
+    std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        drop(guard);
+    });
+    let exclusive = waiting.wait().unwrap();
+
+    // блокировка действует для записи через саму обертку
+    let (file, _) = tokio::io::write_all(file, b"spool".to_vec()).wait().unwrap();
+    let reader = AsyncFileRead::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE);
+    assert!(reader.try_lock(LockMode::Shared).unwrap().is_none());
+    drop(exclusive);
+    let shared = reader.lock_shared().unwrap().wait().unwrap();
+    assert!(file.try_lock(LockMode::Shared).unwrap().is_some());
+    drop(shared);
+    drop(file);
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"spool");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_rotating_file_sink() {
+    use futures::{Future, Sink};
+    use super::*;
+
+    let test_dir_path = format!("{}it_rotating_file_sink", TEST_TEMPORARY_DIR);
+    let _ = std::fs::remove_dir_all(&test_dir_path);
+    std::fs::create_dir_all(&test_dir_path).unwrap();
+    let log_path = format!("{}/app.log", test_dir_path);
+    std::fs::write(&log_path, b"old;").unwrap();
+
+    let sink = RotatingFileSink::open_with_pool(&TEST_CPU_POOL, &log_path).wait().unwrap()
+        .max_size(10)
+        .keep(2);
+    // существующее содержимое учитывается в размере
+    let records = (0..5).map(|i| Bytes::from(format!("record{};", i)));
+    let sink = sink.send_all(futures::stream::iter_ok::<_, std::io::Error>(records)).wait().unwrap().0;
+    futures::future::poll_fn({
+        let mut sink = sink;
+        move || sink.close()
+    }).wait().unwrap();
+
+    assert_eq!(std::fs::read(&log_path).unwrap(), b"record4;");
+    let mut rotated: Vec<String> = std::fs::read_dir(&test_dir_path).unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name != "app.log")
+        .collect();
+    rotated.sort();
+    assert_eq!(rotated.len(), 2);
+    assert!(rotated.iter().all(|name| name.starts_with("app.log.20")));
+    let mut contents: Vec<Vec<u8>> = rotated.iter()
+        .map(|name| std::fs::read(format!("{}/{}", test_dir_path, name)).unwrap())
+        .collect();
+    contents.sort();
+    assert_eq!(contents, vec![b"record2;".to_vec(), b"record3;".to_vec()]);
+
+    std::fs::remove_dir_all(test_dir_path).unwrap();
+}
+
+
+#[test]
+fn it_temp_file() {
+    use futures::Future;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+
+    let file = AsyncTempFile::create_in_with_pool(&TEST_CPU_POOL, TEST_TEMPORARY_DIR).wait().unwrap();
+    let path = file.path().unwrap().to_path_buf();
+    assert!(path.exists());
+    let (file, _) = tokio::io::write_all(file, b"temporary".to_vec()).wait().unwrap();
+    let file = tokio::io::flush(file).wait().unwrap();
+    let (file, _) = Seek::new(file, std::io::SeekFrom::Start(0)).wait().unwrap();
+    let (file, data) = tokio::io::read_to_end(file, Vec::new()).wait().unwrap();
+    assert_eq!(data, b"temporary");
+    drop(file);
+    // файл удаляется в пуле потоков
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+    while path.exists() && std::time::Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+    assert!(!path.exists());
+
+    let file = AsyncTempFile::create_in_with_pool(&TEST_CPU_POOL, TEST_TEMPORARY_DIR).wait().unwrap();
+    let persisted_path = format!("{}it_temp_file.txt", TEST_TEMPORARY_DIR);
+    let (file, _) = tokio::io::write_all(file, b"kept".to_vec()).wait().unwrap();
+    let file = tokio::io::flush(file).wait().unwrap();
+    drop(file.persist(&persisted_path).wait().unwrap());
+    assert_eq!(std::fs::read(&persisted_path).unwrap(), b"kept");
+    std::fs::remove_file(persisted_path).unwrap();
+
+    let file = AsyncTempFile::unnamed_in_with_pool(&TEST_CPU_POOL, TEST_TEMPORARY_DIR).wait().unwrap();
+    assert!(file.path().is_none());
+    let (file, _) = tokio::io::write_all(file, b"unnamed".to_vec()).wait().unwrap();
+    let file = tokio::io::flush(file).wait().unwrap();
+    let (file, _) = Seek::new(file, std::io::SeekFrom::Start(2)).wait().unwrap();
+    let (_, data) = tokio::io::read_to_end(file, Vec::new()).wait().unwrap();
+    assert_eq!(data, b"named");
+
+    let dir = tempdir_with_pool(&TEST_CPU_POOL).wait().unwrap();
+    assert!(dir.path().is_dir());
+    let dir_path = dir.path().to_path_buf();
+    dir.cleanup().wait().unwrap();
+    assert!(!dir_path.exists());
+}
+
+
+#[test]
+fn it_cancel_pool_task() {
+    use futures::{Future, Async};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    type Job = Box<dyn FnOnce() + Send>;
+    #[derive(Clone)]
+    struct Deferred(Arc<Mutex<Vec<Job>>>);
+    impl BlockingExecutor for Deferred {
+        fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+            self.0.lock().unwrap().push(job);
+        }
+    }
+    let executor = Deferred(Arc::new(Mutex::new(Vec::new())));
+    let run = |executor: &Deferred| {
+        let jobs: Vec<_> = executor.0.lock().unwrap().drain(..).collect();
+        for job in jobs {
+            job();
+        }
+    };
+
+    // освобожденная до начала выполнения операция не выполняется, а `forget` ее сохраняет
+    let counter = Arc::new(AtomicUsize::new(0));
+    let cancelled = { let counter = counter.clone(); executor.spawn_blocking(move || Ok(counter.fetch_add(1, Ordering::SeqCst))) };
+    let forgotten = { let counter = counter.clone(); executor.spawn_blocking(move || Ok(counter.fetch_add(1, Ordering::SeqCst))) };
+    drop(cancelled);
+    forgotten.forget();
+    run(&executor);
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+
+    // после ошибки операции обертка сообщает об ошибке, а не ожидает бесконечно
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_cancel_pool_task.txt", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"").unwrap();
+    let read_only = std::fs::File::open(&test_file_path).unwrap();
+    let mut file = AsyncFileWrite::from_std_with_executor(executor.clone(), read_only, TEST_BUFFER_SIZE);
+    futures::future::poll_fn(|| {
+        assert_eq!(file.write(b"data").unwrap_err().kind(), std::io::ErrorKind::WouldBlock);
+        run(&executor);
+        assert!(file.write(b"data").is_err());
+        let error = file.write(b"data").unwrap_err();
+        assert_ne!(error.kind(), std::io::ErrorKind::WouldBlock);
+        Ok::<_, std::io::Error>(Async::Ready(()))
+    }).wait().unwrap();
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_metrics() {
+    use futures::Future;
+    use std::sync::Arc;
+    use super::*;
+
+    lazy_static! {
+        static ref METRICS_POOL: CpuPool = CpuPool::new(1);
+    }
+    // последний `finished` вызывается после пробуждения ожидающей задачи
+    let settle = |metrics: &PoolMetrics| {
+        let started = std::time::Instant::now();
+        while metrics.in_flight() != 0 && started.elapsed() < std::time::Duration::from_secs(5) {
+            std::thread::yield_now();
+        }
+    };
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_metrics.txt", TEST_TEMPORARY_DIR);
+
+    // счетчики отдельного файла
+    let metrics = Arc::new(PoolMetrics::new());
+    let executor = Metered::new(&*TEST_CPU_POOL, metrics.clone());
+    let file = AsyncFileWrite::from_std_with_executor(executor.clone(), std::fs::File::create(&test_file_path).unwrap(), TEST_BUFFER_SIZE);
+    let file = tokio::io::write_all(file, b"Hello world").and_then(|(file, _)| tokio::io::flush(file)).wait().unwrap();
+    tokio::io::shutdown(file).wait().unwrap();
+    let file = AsyncFileRead::from_std_with_executor(executor, std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE);
+    let (_, data) = tokio::io::read_to_end(file, Vec::new()).wait().unwrap();
+    assert_eq!(data, b"Hello world");
+    settle(&metrics);
+    assert_eq!(metrics.bytes_written(), 11);
+    assert_eq!(metrics.bytes_read(), 11);
+    assert_eq!(metrics.queue_depth(), 0);
+    assert_eq!(metrics.in_flight(), 0);
+    assert!(metrics.completed() >= 4);
+    assert_eq!(metrics.latency_histogram().count(), metrics.completed());
+    assert_eq!(metrics.wait_histogram().count(), metrics.completed());
+    assert!(metrics.latency_histogram().quantile(0.5).is_none_or(|bound| bound <= Histogram::bucket_bound(HISTOGRAM_BUCKETS - 2).unwrap()));
+
+    // счетчики пула из реестра
+    let registry = PoolRegistry::new();
+    registry.register(TEST_TEMPORARY_DIR, &METRICS_POOL);
+    let pool_counters = registry.metrics_for(&test_file_path);
+    assert_eq!(registry.metrics().len(), 1);
+    assert!(Arc::ptr_eq(&pool_counters, &pool_metrics(&METRICS_POOL)));
+    let file = AsyncFileRead::from_std(registry.pool_for(&test_file_path), std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE);
+    let (_, data) = tokio::io::read_to_end(file, Vec::new()).wait().unwrap();
+    assert_eq!(data, b"Hello world");
+    settle(&pool_counters);
+    assert_eq!(pool_counters.bytes_read(), 11);
+    assert_eq!(pool_counters.bytes_written(), 0);
+    assert!(pool_counters.completed() >= 2);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_priority_pool() {
+    use futures::Future;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::channel;
+    use super::*;
+
+    let pool = PriorityPool::new(1);
+    assert_eq!(pool.priority(), Priority::Normal);
+
+    // единственный поток занят, пока операции ставятся в очередь
+    let (release, blocked) = channel::<()>();
+    let blocker = pool.spawn_blocking(move || {
+        blocked.recv().unwrap();
+        Ok(())
+    });
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let record = |priority: Priority, name: &'static str| {
+        let order = order.clone();
+        pool.with_priority(priority).spawn_blocking(move || {
+            order.lock().unwrap().push(name);
+            Ok(())
+        })
+    };
+    let tasks = vec![
+        record(Priority::Background, "background 1"),
+        record(Priority::Normal, "normal"),
+        record(Priority::Background, "background 2"),
+        record(Priority::High, "high"),
+    ];
+    assert_eq!(pool.queued(Priority::Background), 2);
+    release.send(()).unwrap();
+    blocker.wait().unwrap();
+    for task in tasks {
+        task.wait().unwrap();
+    }
+    assert_eq!(*order.lock().unwrap(), vec!["high", "normal", "background 1", "background 2"]);
+
+    // приоритет обертки задается ее копией пула
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_priority_pool.txt", TEST_TEMPORARY_DIR);
+    std::fs::write(&test_file_path, b"priority").unwrap();
+    let file = AsyncFileRead::from_std_with_executor(pool.with_priority(Priority::High), std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE);
+    assert_eq!(file.executor().priority(), Priority::High);
+    let (_, data) = tokio::io::read_to_end(file, Vec::new()).wait().unwrap();
+    assert_eq!(data, b"priority");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_throttle() {
+    use futures::{Future, Stream, Sink};
+    use std::time::{Duration, Instant};
+    use bytes::Bytes;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_throttle.txt", TEST_TEMPORARY_DIR);
+    let data: Vec<u8> = (0..48 * 1024).map(|i| i as u8).collect();
+    std::fs::write(&test_file_path, &data).unwrap();
+
+    // 16 КиБ сразу, остальные 32 КиБ - за 0,5 с
+    let limiter = RateLimiter::with_burst(64 * 1024, 16 * 1024);
+    let started = Instant::now();
+    let stream = AsyncFileStream::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), 4 * 1024)
+        .throttle(limiter.clone());
+    let chunks = stream.collect().wait().unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(400));
+    assert_eq!(chunks.concat(), data);
+
+    // общий бюджет израсходован чтением, поэтому запись тоже ожидает
+    let started = Instant::now();
+    let sink = AsyncFileSink::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap())
+        .throttle(limiter);
+    let items: Vec<Bytes> = data[..16 * 1024].chunks(4 * 1024).map(Bytes::from).collect();
+    let sink = sink.send_all(futures::stream::iter_ok::<_, std::io::Error>(items)).wait().unwrap().0;
+    let mut sink = sink.into_inner();
+    futures::future::poll_fn(|| sink.close()).wait().unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(150));
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), &data[..16 * 1024]);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_hashing_stream_and_sink() {
+    use futures::{Future, Stream, Sink};
+    use bytes::Bytes;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_hashing_stream_and_sink.txt", TEST_TEMPORARY_DIR);
+    let data: Vec<u8> = (0..100 * 1024).map(|i| (i % 251) as u8).collect();
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&data);
+    let crc32 = hasher.finalize();
+
+    let sink = AsyncFileSink::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap());
+    let sink = HashingSink::new(&TEST_CPU_POOL, sink, HashAlgorithm::Crc32);
+    let items: Vec<Bytes> = data.chunks(7 * 1024).map(Bytes::from).collect();
+    let (mut sink, _) = sink.send_all(futures::stream::iter_ok::<_, std::io::Error>(items)).wait().unwrap();
+    futures::future::poll_fn(|| sink.close()).wait().unwrap();
+    assert_eq!(sink.digest().and_then(|digest| digest.crc32()), Some(crc32));
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), data);
+
+    let open = || AsyncFileStream::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE);
+    let mut stream = HashingStream::new(&TEST_CPU_POOL, open(), HashAlgorithm::Crc32);
+    assert!(stream.digest().is_none());
+    let mut received = Vec::new();
+    futures::future::poll_fn(|| {
+        while let Some(chunk) = try_ready!(stream.poll()) {
+            received.extend_from_slice(&chunk);
+        }
+        Ok::<_, std::io::Error>(futures::Async::Ready(()))
+    }).wait().unwrap();
+    assert_eq!(received, data);
+    let digest = stream.digest().unwrap().clone();
+    assert_eq!(digest.crc32(), Some(crc32));
+    assert_eq!(digest.to_string(), format!("{:08x}", crc32));
+
+    // ожидаемая контрольная сумма проверяется по окончании потока
+    let expected = Digest::from_hex(HashAlgorithm::Crc32, &digest.to_string()).unwrap();
+    let verified = HashingStream::new(&TEST_CPU_POOL, open(), HashAlgorithm::Crc32).expect(expected);
+    assert_eq!(verified.concat2().wait().unwrap().len(), data.len());
+    let wrong = Digest::new(HashAlgorithm::Crc32, vec![0, 0, 0, 0]);
+    let error = HashingStream::new(&TEST_CPU_POOL, open(), HashAlgorithm::Crc32).expect(wrong).concat2().wait().unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+    #[cfg(feature = "crypto")]
+    {
+        let stream = futures::stream::iter_ok::<_, std::io::Error>(vec![Bytes::from_static(b"a"), Bytes::from_static(b"bc")]);
+        let mut stream = HashingStream::new(&TEST_CPU_POOL, stream, HashAlgorithm::Sha256);
+        futures::future::poll_fn(|| {
+            while try_ready!(stream.poll()).is_some() {}
+            Ok::<_, std::io::Error>(futures::Async::Ready(()))
+        }).wait().unwrap();
+        assert_eq!(stream.digest().unwrap().to_string(), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_file_cache() {
+    use futures::{Future, Stream};
+    use std::sync::Arc;
+    use super::*;
+
+    let test_dir = format!("{}it_file_cache/", TEST_TEMPORARY_DIR);
+    std::fs::create_dir_all(&test_dir).unwrap();
+    let paths: Vec<String> = (0..3).map(|i| format!("{}file{}.txt", test_dir, i)).collect();
+    for path in &paths {
+        std::fs::write(path, path.as_bytes()).unwrap();
+    }
+
+    let cache = FileCache::with_pool(&TEST_CPU_POOL, 2);
+    let first = cache.open(&paths[0]).wait().unwrap();
+    let again = cache.open(&paths[0]).wait().unwrap();
+    assert!(Arc::ptr_eq(first.file(), again.file()));
+    assert_eq!(first.len(), paths[0].len() as u64);
+    assert_eq!(&first.read_at(2, 4).wait().unwrap()[..], &paths[0].as_bytes()[2..6]);
+    let data = first.stream().buffer_size(5).concat2().wait().unwrap();
+    assert_eq!(&data[..], paths[0].as_bytes());
+
+    // измененный файл открывается заново
+    std::fs::write(&paths[0], b"changed").unwrap();
+    let changed = cache.open(&paths[0]).wait().unwrap();
+    assert!(!Arc::ptr_eq(first.file(), changed.file()));
+    assert_eq!(&changed.stream_range(1, 3).concat2().wait().unwrap()[..], b"han");
+
+    // вытесняется давно не использованный файл
+    cache.open(&paths[1]).wait().unwrap();
+    cache.open(&paths[0]).wait().unwrap();
+    cache.open(&paths[2]).wait().unwrap();
+    assert_eq!(cache.len(), 2);
+    assert!(!cache.invalidate(&paths[1]));
+    assert!(cache.invalidate(&paths[2]));
+
+    // без проверки изменения обнаруживаются наблюдением за каталогом
+    let cache = cache.validate(false);
+    let cached = cache.open(&paths[0]).wait().unwrap();
+    let watch = cache.watch(&test_dir);
+    let path = paths[0].clone();
+    let writer = std::thread::spawn(move || {
+        // наблюдение начинается с первым опросом потока
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        std::fs::write(&path, b"changed again").unwrap();
+    });
+    let target = pool_registry::absolute_path(std::path::Path::new(&paths[0]));
+    let (event, _) = watch
+        .filter(|event| *event == FsEvent::Modified(target.clone()))
+        .into_future()
+        .map_err(|(error, _)| error)
+        .wait()
+        .unwrap();
+    assert!(event.is_some());
+    writer.join().unwrap();
+    let reopened = cache.open(&paths[0]).wait().unwrap();
+    assert!(!Arc::ptr_eq(cached.file(), reopened.file()));
+    assert_eq!(reopened.len(), 13);
+
+    std::fs::remove_dir_all(test_dir).unwrap();
+}
+
+
+#[test]
+fn it_sink_generic_items() {
+    use futures::{Future, Sink};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use super::*;
+
+    // считает обращения к пулу потоков
+    #[derive(Clone)]
+    struct Counting(Arc<AtomicUsize>);
+    impl BlockingExecutor for Counting {
+        fn execute(&self, job: Box<dyn FnOnce() + Send>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            TEST_CPU_POOL.execute(job);
+        }
+    }
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_sink_generic_items.txt", TEST_TEMPORARY_DIR);
+
+    // элементы `Vec<u8>` записываются без преобразования в `Bytes`
+    let file = std::fs::File::create(&test_file_path).unwrap();
+    let sink: AsyncFileSink<_, Vec<u8>> = AsyncFileSink::from_std_with_executor(&*TEST_CPU_POOL, file);
+    let items = vec![b"hello ".to_vec(), b"world".to_vec()];
+    let mut sink = sink.send_all(futures::stream::iter_ok::<_, std::io::Error>(items)).wait().unwrap().0;
+    futures::future::poll_fn(|| sink.poll_close()).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"hello world");
+
+    // большой элемент записывается частями по `max_chunk_size` байт
+    let jobs = Arc::new(AtomicUsize::new(0));
+    let file = std::fs::File::create(&test_file_path).unwrap();
+    let sink = AsyncFileSink::from_std_with_executor(Counting(jobs.clone()), file).max_chunk_size(4);
+    let data: Vec<u8> = (0..18).collect();
+    let mut sink = sink.send(data.clone()).wait().unwrap();
+    assert_eq!(sink.queued_bytes(), 0);
+    assert_eq!(jobs.load(Ordering::SeqCst), 5);
+    sink = sink.send(vec![18, 19]).wait().unwrap();
+    futures::future::poll_fn(|| sink.poll_close()).wait().unwrap();
+    let mut expected = data;
+    expected.extend_from_slice(&[18, 19]);
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), expected);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_write_recover() {
+    use futures::Future;
+    use tokio::io::AsyncWrite;
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_write_recover.txt", TEST_TEMPORARY_DIR);
+
+    let file = AsyncFileWrite::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap(), TEST_BUFFER_SIZE);
+    let (mut file, _) = tokio::io::write_all(file, b"hello".to_vec()).wait().unwrap();
+    assert_eq!((file.written(), file.synced()), (5, 0));
+    futures::future::poll_fn(|| file.poll_sync_data()).wait().unwrap();
+    assert_eq!(file.synced(), 5);
+
+    // запись в файл, открытый только для чтения, завершается ошибкой, но файл сохраняется
+    let mut file = AsyncFileWrite::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE);
+    assert!(futures::future::poll_fn(|| file.poll_write(b"world")).wait().is_err());
+    assert_eq!(file.written(), 0);
+    file.recover().unwrap();
+    assert!(file.poll_ready().unwrap().is_ready());
+    assert!(futures::future::poll_fn(|| file.poll_write(b"world")).wait().is_err());
+    let (file, unwritten) = file.into_parts().unwrap();
+    assert!(unwritten.is_empty());
+    assert_eq!(file.metadata().unwrap().len(), 5);
+
+    // в режиме `direct_io` принятые данные возвращаются вместе с файлом
+    let mut file = AsyncFileWrite::from_std(&TEST_CPU_POOL, file, TEST_BUFFER_SIZE).direct_io(512);
+    assert_eq!(futures::future::poll_fn(|| file.poll_write(b"world")).wait().unwrap(), 5);
+    assert!(futures::future::poll_fn(|| file.poll_flush()).wait().is_err());
+    let (_, unwritten) = file.into_parts().unwrap();
+    assert_eq!(unwritten, b"world");
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), b"hello");
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[test]
+fn it_overlapped() {
+    use futures::{Future, Stream};
+    use super::*;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_overlapped.txt", TEST_TEMPORARY_DIR);
+    let content: Vec<u8> = (0..(TEST_BUFFER_SIZE * 3 + 17)).map(|i| (i % 241) as u8).collect();
+
+    // вне Windows (или без возможности `overlapped`) операции выполняются в пуле
+    let file = AsyncFileWrite::from_std(&TEST_CPU_POOL, std::fs::File::create(&test_file_path).unwrap(), TEST_BUFFER_SIZE)
+        .overlapped(true);
+    let (file, _) = tokio::io::write_all(file, content.clone()).wait().unwrap();
+    tokio::io::shutdown(file).wait().unwrap();
+    assert_eq!(std::fs::read(&test_file_path).unwrap(), content);
+
+    // файл, открытый только для дозаписи, записывается в пуле
+    let append = std::fs::OpenOptions::new().append(true).open(&test_file_path).unwrap();
+    let file = AsyncFileWrite::from_std(&TEST_CPU_POOL, append, TEST_BUFFER_SIZE).overlapped(true);
+    let (file, _) = tokio::io::write_all(file, b"tail".to_vec()).wait().unwrap();
+    tokio::io::shutdown(file).wait().unwrap();
+    let mut expected = content.clone();
+    expected.extend_from_slice(b"tail");
+
+    // чтение продолжается с позиции файла
+    let file = AsyncFileRead::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE)
+        .overlapped(true);
+    let file = file.seek(std::io::SeekFrom::Start(3)).wait().unwrap().0;
+    let (_, read) = tokio::io::read_to_end(file, Vec::new()).wait().unwrap();
+    assert_eq!(read, &expected[3..]);
+
+    let stream = AsyncFileStream::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE)
+        .overlapped(true);
+    let read = stream.concat2().wait().unwrap();
+    assert_eq!(&read[..], &expected[..]);
+
+    #[cfg(all(feature = "overlapped", windows))]
+    println!("overlapped available: {}", overlapped_available());
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
+
+
+#[cfg(feature = "futures03")]
+#[test]
+fn it_compat03() {
+    use super::*;
+    use futures03::{AsyncReadExt, AsyncWriteExt, TryStreamExt};
+    use futures03::executor::block_on;
+
+    std::fs::create_dir_all(TEST_TEMPORARY_DIR).unwrap();
+    let test_file_path = format!("{}it_compat03.txt", TEST_TEMPORARY_DIR);
+    let content: Vec<u8> = (0..(TEST_BUFFER_SIZE * 2 + 5)).map(|i| (i % 239) as u8).collect();
+
+    let file = block_on(AsyncFileWrite::create_with_pool(&TEST_CPU_POOL, &test_file_path).compat03()).unwrap();
+    let mut file = file.compat03();
+    block_on(file.write_all(&content)).unwrap();
+    block_on(file.close()).unwrap();
+
+    let file = AsyncFileRead::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE);
+    let mut read = Vec::new();
+    block_on(file.compat03().read_to_end(&mut read)).unwrap();
+    assert_eq!(read, content);
+
+    let stream = AsyncFileStream::from_std(&TEST_CPU_POOL, std::fs::File::open(&test_file_path).unwrap(), TEST_BUFFER_SIZE);
+    let chunks: Vec<Bytes> = block_on(stream.compat03().try_collect()).unwrap();
+    assert_eq!(chunks.len(), 3);
+    assert_eq!(chunks.concat(), content);
+
+    std::fs::remove_file(test_file_path).unwrap();
+}
@@ -0,0 +1,258 @@
+use futures::{Poll, Async, AsyncSink, Sink, Stream, StartSend};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use seek::AsyncSeek;
+use timeout::Deadline;
+
+struct Bucket {
+    rate: f64,
+    burst: f64,
+    /// Доступные байты; отрицательное значение - долг после элемента больше доступного
+    tokens: f64,
+    updated: Instant,
+}
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now - self.updated;
+        let elapsed = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now;
+    }
+}
+
+
+// RateLimiter
+
+/// Ограничение скорости (байт в секунду) по алгоритму token bucket.
+///
+/// Копии ограничителя (`clone`) разделяют общий бюджет: так одним ограничением можно
+/// охватить несколько файлов (например, все файлы фонового копирования).
+/// Элемент больше доступного бюджета не задерживается и не разделяется, а уходит в долг,
+/// который следующие операции ожидают.
+#[derive(Clone)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+impl RateLimiter {
+
+    /// Ограничение `bytes_per_second` с запасом на одну секунду
+    #[inline]
+    pub fn new(bytes_per_second: u64) -> RateLimiter {
+        Self::with_burst(bytes_per_second, bytes_per_second)
+    }
+
+    /// Ограничение `bytes_per_second`, допускающее всплеск до `burst` байт после простоя
+    pub fn with_burst(bytes_per_second: u64, burst: u64) -> RateLimiter {
+        let burst = std::cmp::max(burst, 1) as f64;
+        RateLimiter {
+            bucket: Arc::new(Mutex::new(Bucket {
+                rate: std::cmp::max(bytes_per_second, 1) as f64,
+                burst,
+                tokens: burst,
+                updated: Instant::now(),
+            })),
+        }
+    }
+
+    #[inline]
+    pub fn rate(&self) -> u64 {
+        self.bucket.lock().unwrap().rate as u64
+    }
+
+    /// Изменяет ограничение для всех копий; накопленный бюджет сохраняется
+    pub fn set_rate(&self, bytes_per_second: u64) {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill();
+        bucket.rate = std::cmp::max(bytes_per_second, 1) as f64;
+    }
+
+    #[inline]
+    pub fn burst(&self) -> u64 {
+        self.bucket.lock().unwrap().burst as u64
+    }
+
+    /// `Ok`, если бюджет есть; иначе момент, когда он появится
+    fn check(&self) -> Result<(), Instant> {
+        let mut bucket = self.bucket.lock().unwrap();
+        bucket.refill();
+        if bucket.tokens > 0.0 {
+            return Ok(());
+        }
+        let wait = (1.0 - bucket.tokens) / bucket.rate;
+        Err(bucket.updated + Duration::new(wait as u64, (wait.fract() * 1e9) as u32))
+    }
+
+    fn consume(&self, bytes: usize) {
+        self.bucket.lock().unwrap().tokens -= bytes as f64;
+    }
+}
+impl std::fmt::Debug for RateLimiter {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let bucket = self.bucket.lock().unwrap();
+        f.debug_struct("RateLimiter")
+            .field("rate", &bucket.rate)
+            .field("burst", &bucket.burst)
+            .field("tokens", &bucket.tokens)
+            .finish()
+    }
+}
+
+
+// Throttled
+
+/// Обертка, ограничивающая скорость чтения и записи (см. `RateLimiter`).
+///
+/// Пока бюджет исчерпан, операции возвращают `NotReady` (`WouldBlock` для `Read`/`Write`),
+/// а текущая задача пробуждается таймером, когда бюджет восстановится. Для `Read`/`Write`
+/// одна операция ограничена `burst` байтами; элементы `Stream`/`Sink` учитываются целиком.
+pub struct Throttled<T> {
+    inner: T,
+    limiter: RateLimiter,
+    deadline: Option<Deadline>,
+}
+impl<T> Throttled<T> {
+
+    pub fn new(inner: T, limiter: RateLimiter) -> Throttled<T> {
+        Throttled {
+            inner,
+            limiter,
+            deadline: None,
+        }
+    }
+
+    #[inline]
+    pub fn limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Ожидает бюджет, регистрируя пробуждение текущей задачи по таймеру
+    fn poll_budget(&mut self) -> Async<()> {
+        loop {
+            match self.limiter.check() {
+                Ok(()) => {
+                    self.deadline = None;
+                    return Async::Ready(());
+                },
+                Err(at) => {
+                    let mut deadline = self.deadline.take().unwrap_or_else(|| Deadline::new(at));
+                    // бюджет общего ограничителя мог быть израсходован другой копией
+                    if deadline.poll_elapsed() {
+                        continue;
+                    }
+                    self.deadline = Some(deadline);
+                    return Async::NotReady;
+                },
+            }
+        }
+    }
+
+    /// Размер одной операции `Read`/`Write`
+    #[inline]
+    fn max_chunk(&self, len: usize) -> usize {
+        std::cmp::min(len as u64, self.limiter.burst()) as usize
+    }
+}
+impl<T: Stream> Stream for Throttled<T> where T::Item: AsRef<[u8]> {
+    type Item = T::Item;
+    type Error = T::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        if let Async::NotReady = self.poll_budget() {
+            return Ok(Async::NotReady);
+        }
+        let item = try_ready!(self.inner.poll());
+        if let Some(ref item) = item {
+            self.limiter.consume(item.as_ref().len());
+        }
+        Ok(Async::Ready(item))
+    }
+}
+impl<T: Sink> Sink for Throttled<T> where T::SinkItem: AsRef<[u8]> {
+    type SinkItem = T::SinkItem;
+    type SinkError = T::SinkError;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        if let Async::NotReady = self.poll_budget() {
+            return Ok(AsyncSink::NotReady(item));
+        }
+        let len = item.as_ref().len();
+        let result = self.inner.start_send(item)?;
+        if let AsyncSink::Ready = result {
+            self.limiter.consume(len);
+        }
+        Ok(result)
+    }
+
+    #[inline]
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.poll_complete()
+    }
+
+    #[inline]
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        self.inner.close()
+    }
+}
+impl<T: std::io::Read> std::io::Read for Throttled<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if let Async::NotReady = self.poll_budget() {
+            return Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "rate limited"));
+        }
+        let len = self.max_chunk(buf.len());
+        let size = self.inner.read(&mut buf[..len])?;
+        self.limiter.consume(size);
+        Ok(size)
+    }
+}
+impl<T: tokio::io::AsyncRead> tokio::io::AsyncRead for Throttled<T> {}
+impl<T: std::io::Write> std::io::Write for Throttled<T> {
+    fn write(&mut self, src: &[u8]) -> std::io::Result<usize> {
+        if let Async::NotReady = self.poll_budget() {
+            return Err(std::io::Error::new(std::io::ErrorKind::WouldBlock, "rate limited"));
+        }
+        let len = self.max_chunk(src.len());
+        let size = self.inner.write(&src[..len])?;
+        self.limiter.consume(size);
+        Ok(size)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+impl<T: tokio::io::AsyncWrite> tokio::io::AsyncWrite for Throttled<T> {
+    #[inline]
+    fn shutdown(&mut self) -> Poll<(), std::io::Error> {
+        self.inner.shutdown()
+    }
+}
+impl<T: AsyncSeek> AsyncSeek for Throttled<T> {
+    #[inline]
+    fn poll_seek(&mut self, pos: std::io::SeekFrom) -> Poll<u64, std::io::Error> {
+        self.inner.poll_seek(pos)
+    }
+}
+impl<T> std::fmt::Debug for Throttled<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Throttled")
+            .field("limiter", &self.limiter)
+            .finish()
+    }
+}
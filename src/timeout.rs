@@ -0,0 +1,342 @@
+use futures::{Poll, Future, Sink, Stream, StartSend};
+use futures::task::{self, AtomicTask};
+use std::collections::BinaryHeap;
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use seek::AsyncSeek;
+
+struct DeadlineState {
+    fired: AtomicBool,
+    /// Срок освобожден до истечения: запись в очереди таймера только ждет удаления
+    cancelled: AtomicBool,
+    task: AtomicTask,
+}
+
+struct TimerEntry {
+    at: Instant,
+    state: Arc<DeadlineState>,
+}
+impl PartialEq for TimerEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at
+    }
+}
+impl Eq for TimerEntry {}
+impl PartialOrd for TimerEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for TimerEntry {
+    // ближайший срок должен оказаться на вершине кучи
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        other.at.cmp(&self.at)
+    }
+}
+
+/// Сроки, ожидающие истечения, и количество отмененных среди них
+struct TimerQueue {
+    heap: BinaryHeap<TimerEntry>,
+    cancelled: usize,
+}
+impl TimerQueue {
+    /// Отменяет еще не истекший срок; когда отмененных записей больше половины,
+    /// они удаляются из очереди, чтобы она не росла из-за освобожденных сроков
+    fn cancel(&mut self, state: &DeadlineState) {
+        if state.fired.load(Ordering::SeqCst) {
+            return;
+        }
+        state.cancelled.store(true, Ordering::SeqCst);
+        self.cancelled += 1;
+        if self.cancelled * 2 > self.heap.len() {
+            let mut entries = std::mem::take(&mut self.heap).into_vec();
+            entries.retain(|entry| !entry.state.cancelled.load(Ordering::SeqCst));
+            self.heap = entries.into();
+            self.cancelled = 0;
+        }
+    }
+}
+
+/// Поток, пробуждающий задачи по истечении сроков ожидания.
+/// Не зависит от исполнителя, поэтому сроки работают и с `Future::wait`.
+struct Timer {
+    entries: Mutex<TimerQueue>,
+    condvar: Condvar,
+}
+impl Timer {
+    fn run(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let next = entries.heap.peek().map(|entry| entry.at);
+            match next {
+                Some(at) if at <= now => {
+                    let entry = entries.heap.pop().unwrap();
+                    if entry.state.cancelled.load(Ordering::SeqCst) {
+                        entries.cancelled -= 1;
+                        continue;
+                    }
+                    entry.state.fired.store(true, Ordering::SeqCst);
+                    entry.state.task.notify();
+                },
+                Some(at) => entries = self.condvar.wait_timeout(entries, at - now).unwrap().0,
+                None => entries = self.condvar.wait(entries).unwrap(),
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref TIMER: Arc<Timer> = {
+        let timer = Arc::new(Timer {
+            entries: Mutex::new(TimerQueue {
+                heap: BinaryHeap::new(),
+                cancelled: 0,
+            }),
+            condvar: Condvar::new(),
+        });
+        let runner = timer.clone();
+        std::thread::Builder::new()
+            .name("async_fs-timer".into())
+            .spawn(move || runner.run())
+            .expect("failed to start the timer thread");
+        timer
+    };
+}
+
+/// Количество записей в очереди таймера
+#[cfg(test)]
+pub fn timer_entries() -> usize {
+    TIMER.entries.lock().unwrap().heap.len()
+}
+
+/// Срок ожидания, по истечении которого ожидающая задача пробуждается
+pub struct Deadline {
+    at: Instant,
+    state: Arc<DeadlineState>,
+    scheduled: bool,
+}
+impl Deadline {
+    pub fn new(at: Instant) -> Deadline {
+        Deadline {
+            at,
+            state: Arc::new(DeadlineState {
+                fired: AtomicBool::new(false),
+                cancelled: AtomicBool::new(false),
+                task: AtomicTask::new(),
+            }),
+            scheduled: false,
+        }
+    }
+
+    /// `true`, если срок истек; иначе текущая задача (если она есть) будет пробуждена по его истечении
+    pub fn poll_elapsed(&mut self) -> bool {
+        if self.state.fired.load(Ordering::SeqCst) || Instant::now() >= self.at {
+            return true;
+        }
+        if task::is_in_task() {
+            self.state.task.register();
+            if !self.scheduled {
+                self.scheduled = true;
+                TIMER.entries.lock().unwrap().heap.push(TimerEntry { at: self.at, state: self.state.clone() });
+                TIMER.condvar.notify_one();
+            }
+        }
+        false
+    }
+}
+impl Drop for Deadline {
+    fn drop(&mut self) {
+        if self.scheduled {
+            TIMER.entries.lock().unwrap().cancel(&self.state);
+        }
+    }
+}
+
+
+// Elapsed
+
+/// Ошибка истечения срока ожидания операции.
+///
+/// Передается внутри `std::io::Error` с видом `TimedOut`:
+/// `error.get_ref().is_some_and(|error| error.is::<Elapsed>())`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed {
+    timeout: Duration,
+}
+impl Elapsed {
+
+    /// Истекший срок ожидания
+    #[inline]
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+}
+impl std::fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "operation timed out after {:?}", self.timeout)
+    }
+}
+impl std::error::Error for Elapsed {}
+
+
+// Timeouted
+
+/// Обертка, ограничивающая время ожидания каждой операции.
+///
+/// Если операция не завершилась за `timeout`, возвращается ошибка `TimedOut` (`Elapsed`),
+/// но начатая в пуле потоков операция не отменяется: обертка продолжает ее отслеживать,
+/// поэтому операцию можно дождаться повторным вызовом, а `File` - получить из `into_inner()`
+/// после ее завершения. Срок отсчитывается заново для каждой операции.
+///
+/// Чтобы отказаться от зависшей операции (например, на умирающем диске или недоступном NFS),
+/// обертку освобождают: операция, еще ожидающая в очереди пула, отменяется, а выполняющаяся
+/// отсоединяется и освобождает файл в пуле потоков по завершении (см. `PoolTask`).
+pub struct Timeouted<T> {
+    inner: T,
+    timeout: Duration,
+    deadline: Option<Deadline>,
+}
+impl<T> Timeouted<T> {
+
+    pub fn new(inner: T, timeout: Duration) -> Timeouted<T> {
+        Timeouted {
+            inner,
+            timeout,
+            deadline: None,
+        }
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn elapsed_error(&self) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::TimedOut, Elapsed { timeout: self.timeout })
+    }
+
+    /// Учитывает результат опроса операции: `Err`, если операция не готова и срок истек
+    fn check<R>(&mut self, ready: bool, result: R) -> std::io::Result<R> {
+        if ready {
+            self.deadline = None;
+            return Ok(result);
+        }
+        let timeout = self.timeout;
+        let elapsed = self.deadline
+            .get_or_insert_with(|| Deadline::new(Instant::now() + timeout))
+            .poll_elapsed();
+        if elapsed {
+            self.deadline = None;
+            return Err(self.elapsed_error());
+        }
+        Ok(result)
+    }
+
+    fn check_io<R>(&mut self, result: std::io::Result<R>) -> std::io::Result<R> {
+        match result {
+            Err(error) => {
+                if error.kind() == std::io::ErrorKind::WouldBlock {
+                    self.check(false, ())?;
+                } else {
+                    self.deadline = None;
+                }
+                Err(error)
+            },
+            Ok(result) => self.check(true, result),
+        }
+    }
+}
+impl<T: Future<Error = std::io::Error>> Future for Timeouted<T> {
+    type Item = T::Item;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let result = self.inner.poll()?;
+        let ready = result.is_ready();
+        self.check(ready, result)
+    }
+}
+impl<T: Stream<Error = std::io::Error>> Stream for Timeouted<T> {
+    type Item = T::Item;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        let result = self.inner.poll()?;
+        let ready = result.is_ready();
+        self.check(ready, result)
+    }
+}
+impl<T: Sink<SinkError = std::io::Error>> Sink for Timeouted<T> {
+    type SinkItem = T::SinkItem;
+    type SinkError = std::io::Error;
+
+    fn start_send(&mut self, item: Self::SinkItem) -> StartSend<Self::SinkItem, Self::SinkError> {
+        let result = self.inner.start_send(item)?;
+        let ready = result.is_ready();
+        // по истечении срока непринятый элемент не записывается и возвращается только ошибка
+        self.check(ready, result)
+    }
+
+    fn poll_complete(&mut self) -> Poll<(), Self::SinkError> {
+        let result = self.inner.poll_complete()?;
+        let ready = result.is_ready();
+        self.check(ready, result)
+    }
+
+    fn close(&mut self) -> Poll<(), Self::SinkError> {
+        let result = self.inner.close()?;
+        let ready = result.is_ready();
+        self.check(ready, result)
+    }
+}
+impl<T: AsyncSeek> AsyncSeek for Timeouted<T> {
+    fn poll_seek(&mut self, pos: std::io::SeekFrom) -> Poll<u64, std::io::Error> {
+        let result = self.inner.poll_seek(pos)?;
+        let ready = result.is_ready();
+        self.check(ready, result)
+    }
+}
+impl<T: std::io::Read> std::io::Read for Timeouted<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let result = self.inner.read(buf);
+        self.check_io(result)
+    }
+}
+impl<T: std::io::Write> std::io::Write for Timeouted<T> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let result = self.inner.write(buf);
+        self.check_io(result)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        let result = self.inner.flush();
+        self.check_io(result)
+    }
+}
+impl<T: tokio::io::AsyncRead> tokio::io::AsyncRead for Timeouted<T> {}
+impl<T: tokio::io::AsyncWrite> tokio::io::AsyncWrite for Timeouted<T> {
+    fn shutdown(&mut self) -> Poll<(), std::io::Error> {
+        let result = self.inner.shutdown()?;
+        let ready = result.is_ready();
+        self.check(ready, result)
+    }
+}
+impl<T> std::fmt::Debug for Timeouted<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Timeouted")
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
@@ -0,0 +1,20 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::path::{Path, PathBuf};
+use super::DEFAULT_CPU_POOL;
+use sys::{long_path, retry};
+
+/// Изменяет длину существующего файла `path`: усекает его (например, отрезая поврежденный
+/// "хвост" при восстановлении или лишнюю предварительно выделенную область)
+/// или дополняет нулями
+#[inline]
+pub fn truncate<P: AsRef<Path>>(path: P, len: u64) -> CpuFuture<(), std::io::Error> {
+    truncate_with_pool(&DEFAULT_CPU_POOL, path, len)
+}
+
+pub fn truncate_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P, len: u64) -> CpuFuture<(), std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || {
+        let file = retry(|| std::fs::OpenOptions::new().write(true).open(long_path(&path)))?;
+        retry(|| file.set_len(len))
+    })
+}
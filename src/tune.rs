@@ -0,0 +1,125 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use super::DEFAULT_CPU_POOL;
+use sys::{self, long_path, retry};
+
+/// Размеры блока, среди которых выбирает `tune`
+static TUNE_CANDIDATES: &[usize] = &[4 * 1024, 16 * 1024, 64 * 1024, 256 * 1024, 1024 * 1024];
+
+/// Сколько байт читается для оценки каждого размера блока
+static DEFAULT_TUNE_SAMPLE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Границы размера блока в адаптивном режиме
+static ADAPTIVE_MIN_SIZE: usize = 4 * 1024;
+static ADAPTIVE_MAX_SIZE: usize = 4 * 1024 * 1024;
+
+/// Изменение скорости, которое считается значимым (5%)
+static ADAPTIVE_THRESHOLD: f64 = 0.05;
+
+fn rate(bytes: u64, elapsed: Duration) -> f64 {
+    let seconds = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+    bytes as f64 / seconds.max(1e-9)
+}
+
+/// Подбор размера блока во время передачи: размер удваивается или уменьшается вдвое,
+/// пока это увеличивает достигнутую скорость, и перестает изменяться на "плато"
+pub struct AdaptiveSize {
+    size: usize,
+    grow: bool,
+    last_rate: Option<f64>,
+    settled: bool,
+}
+impl AdaptiveSize {
+    pub fn new(size: usize) -> AdaptiveSize {
+        AdaptiveSize {
+            size,
+            grow: true,
+            last_rate: None,
+            settled: false,
+        }
+    }
+
+    /// Учитывает операцию над `bytes` байт и возвращает размер следующего блока.
+    /// Неполные блоки (например, в конце файла) не учитываются.
+    pub fn record(&mut self, bytes: usize, elapsed: Duration) -> usize {
+        if bytes < self.size || self.settled {
+            return self.size;
+        }
+        let current = rate(bytes as u64, elapsed);
+        match self.last_rate {
+            Some(last) if current < last * (1.0 - ADAPTIVE_THRESHOLD) => self.grow = !self.grow,
+            Some(last) if current < last * (1.0 + ADAPTIVE_THRESHOLD) => self.settled = true,
+            _ => {},
+        }
+        self.last_rate = Some(current);
+        if !self.settled {
+            self.size = if self.grow {
+                std::cmp::min(self.size * 2, ADAPTIVE_MAX_SIZE)
+            } else {
+                std::cmp::max(self.size / 2, ADAPTIVE_MIN_SIZE)
+            };
+        }
+        self.size
+    }
+}
+
+
+// TuneReport
+
+/// Результат замера скорости чтения при разных размерах блока
+#[derive(Debug, Clone)]
+pub struct TuneReport {
+    /// Размер блока с наибольшей скоростью
+    pub best: usize,
+    /// Размер блока и достигнутая скорость в байтах в секунду
+    pub samples: Vec<(usize, f64)>,
+}
+
+/// Замеряет скорость последовательного чтения файла (или блочного устройства) блоками разного
+/// размера от 4 КиБ до 1 МиБ и выбирает лучший размер для `AsyncFileStream` и `AsyncFileWrite`.
+///
+/// Каждый размер блока оценивается на собственном участке файла длиной до 8 МиБ.
+/// Данные, уже находящиеся в кэше страниц, читаются со скоростью памяти, поэтому замер
+/// имеет смысл на данных, которые давно не читались, или на устройстве.
+#[inline]
+pub fn tune<P: AsRef<Path>>(path: P) -> CpuFuture<TuneReport, std::io::Error> {
+    tune_with_pool(&DEFAULT_CPU_POOL, path)
+}
+
+pub fn tune_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<TuneReport, std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || {
+        let file = retry(|| std::fs::File::open(long_path(&path)))?;
+        let len = {
+            use std::io::{Seek, SeekFrom};
+            // длина блочного устройства не отражается в метаданных
+            (&file).seek(SeekFrom::End(0))?
+        };
+        if len == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "file is empty"));
+        }
+        let sample_size = std::cmp::min(DEFAULT_TUNE_SAMPLE_SIZE, len);
+        let mut buf = vec![0u8; *TUNE_CANDIDATES.last().unwrap()];
+        let mut samples = Vec::with_capacity(TUNE_CANDIDATES.len());
+        for (index, &size) in TUNE_CANDIDATES.iter().enumerate() {
+            let mut offset = (index as u64 * sample_size) % len;
+            let mut total = 0u64;
+            let started = Instant::now();
+            while total < sample_size {
+                let read = sys::read_at(&file, &mut buf[..size], offset)?;
+                if read == 0 {
+                    offset = 0;
+                    continue;
+                }
+                total += read as u64;
+                offset += read as u64;
+            }
+            samples.push((size, rate(total, started.elapsed())));
+        }
+        let best = samples.iter()
+            .fold((TUNE_CANDIDATES[0], 0.0), |best, &(size, rate)| if rate > best.1 { (size, rate) } else { best })
+            .0;
+        Ok(TuneReport { best, samples })
+    })
+}
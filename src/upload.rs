@@ -0,0 +1,198 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use super::DEFAULT_CPU_POOL;
+use sys::{long_path, retry, sync_all, sync_parent_dir};
+
+static CHECKPOINT_HEADER: &str = "async_fs-upload-checkpoint 1";
+
+
+// UploadSession
+
+/// Сессия возобновляемой загрузки файла.
+///
+/// Данные записываются во временный файл `<path>.part`, а после каждого `flush()`
+/// длина гарантированно сохраненных на диск данных фиксируется в файле
+/// контрольной точки `<path>.checkpoint`. После сбоя `resume()` восстанавливает сессию,
+/// отбрасывая все, что было записано после последней контрольной точки.
+/// `complete()` атомарно переименовывает временный файл в целевой.
+///
+/// Все операции выполняются в пуле потоков и возвращают сессию обратно.
+pub struct UploadSession {
+    cpu_pool: &'static CpuPool,
+    path: PathBuf,
+    file: std::fs::File,
+    len: u64,
+    durable_len: u64,
+}
+
+impl UploadSession {
+
+    /// Создает новую сессию загрузки в `path`, отбрасывая предыдущую, если она была
+    #[inline]
+    pub fn create<P: AsRef<Path>>(path: P) -> CpuFuture<UploadSession, std::io::Error> {
+        Self::create_with_pool(&DEFAULT_CPU_POOL, path)
+    }
+
+    pub fn create_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<UploadSession, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let file = std::fs::File::create(long_path(&part_path(&path)))?;
+            write_checkpoint(&path, 0)?;
+            Ok(UploadSession {
+                cpu_pool,
+                path,
+                file,
+                len: 0,
+                durable_len: 0,
+            })
+        })
+    }
+
+    /// Восстанавливает сессию загрузки в `path` по ее контрольной точке
+    #[inline]
+    pub fn resume<P: AsRef<Path>>(path: P) -> CpuFuture<UploadSession, std::io::Error> {
+        Self::resume_with_pool(&DEFAULT_CPU_POOL, path)
+    }
+
+    pub fn resume_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<UploadSession, std::io::Error> {
+        let path: PathBuf = path.as_ref().into();
+        cpu_pool.spawn_fn(move || {
+            let durable_len = read_checkpoint(&path)?;
+            let file = std::fs::OpenOptions::new().write(true).open(long_path(&part_path(&path)))?;
+            if file.metadata()?.len() < durable_len {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "upload data is shorter than its checkpoint"));
+            }
+            // все, что записано после контрольной точки, могло не дойти до диска
+            retry(|| file.set_len(durable_len))?;
+            Ok(UploadSession {
+                cpu_pool,
+                path,
+                file,
+                len: durable_len,
+                durable_len,
+            })
+        })
+    }
+
+    /// Путь к целевому файлу
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Количество принятых байт
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// `true`, если еще не принято ни одного байта
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Количество байт, гарантированно сохраненных на диск
+    #[inline]
+    pub fn durable_len(&self) -> u64 {
+        self.durable_len
+    }
+
+    /// Записывает фрагмент данных по смещению `offset`.
+    ///
+    /// Смещение не может превышать количество уже принятых байт, а повторная отправка
+    /// уже принятого фрагмента просто перезаписывает его.
+    pub fn append(mut self, offset: u64, data: Bytes) -> CpuFuture<UploadSession, std::io::Error> {
+        let cpu_pool = self.cpu_pool;
+        cpu_pool.spawn_fn(move || {
+            if offset > self.len {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("chunk offset {} is beyond the uploaded length {}", offset, self.len),
+                ));
+            }
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.write_all(&data)?;
+            let end = offset + data.len() as u64;
+            if end > self.len {
+                self.len = end;
+            }
+            Ok(self)
+        })
+    }
+
+    /// Сбрасывает данные на диск и сохраняет контрольную точку
+    pub fn flush(mut self) -> CpuFuture<UploadSession, std::io::Error> {
+        let cpu_pool = self.cpu_pool;
+        cpu_pool.spawn_fn(move || {
+            retry(|| self.file.sync_data())?;
+            write_checkpoint(&self.path, self.len)?;
+            self.durable_len = self.len;
+            Ok(self)
+        })
+    }
+
+    /// Завершает загрузку: сбрасывает данные на диск, атомарно переименовывает временный файл
+    /// в целевой и удаляет контрольную точку. Результатом является путь к целевому файлу.
+    pub fn complete(self) -> CpuFuture<PathBuf, std::io::Error> {
+        let cpu_pool = self.cpu_pool;
+        cpu_pool.spawn_fn(move || {
+            let UploadSession { path, file, len, .. } = self;
+            retry(|| file.set_len(len))?;
+            retry(|| sync_all(&file))?;
+            drop(file);
+            std::fs::rename(long_path(&part_path(&path)), long_path(&path))?;
+            std::fs::remove_file(long_path(&checkpoint_path(&path)))?;
+            sync_parent_dir(&path)?;
+            Ok(path)
+        })
+    }
+}
+impl std::fmt::Debug for UploadSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("UploadSession")
+            .field("path", &self.path)
+            .field("len", &self.len)
+            .field("durable_len", &self.durable_len)
+            .finish()
+    }
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut part_path = path.as_os_str().to_owned();
+    part_path.push(".part");
+    part_path.into()
+}
+
+fn checkpoint_path(path: &Path) -> PathBuf {
+    let mut checkpoint_path = path.as_os_str().to_owned();
+    checkpoint_path.push(".checkpoint");
+    checkpoint_path.into()
+}
+
+fn write_checkpoint(path: &Path, durable_len: u64) -> std::io::Result<()> {
+    let checkpoint_path = checkpoint_path(path);
+    let mut temp_path = checkpoint_path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    {
+        let mut file = std::fs::File::create(long_path(Path::new(&temp_path)))?;
+        write!(file, "{}\n{}\n", CHECKPOINT_HEADER, durable_len)?;
+        retry(|| sync_all(&file))?;
+    }
+    std::fs::rename(long_path(Path::new(&temp_path)), long_path(&checkpoint_path))?;
+    sync_parent_dir(&checkpoint_path)
+}
+
+fn read_checkpoint(path: &Path) -> std::io::Result<u64> {
+    let mut data = String::new();
+    std::fs::File::open(long_path(&checkpoint_path(path)))?.read_to_string(&mut data)?;
+    let mut lines = data.lines();
+    if lines.next() != Some(CHECKPOINT_HEADER) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "unknown upload checkpoint format"));
+    }
+    lines.next()
+        .and_then(|line| line.trim().parse().ok())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed upload checkpoint"))
+}
@@ -0,0 +1,390 @@
+use std::collections::HashMap;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+use pool_task::{PoolTask, Completion};
+
+static SYS_IO_URING_SETUP: libc::c_long = 425;
+static SYS_IO_URING_ENTER: libc::c_long = 426;
+
+static IORING_OFF_SQ_RING: libc::off_t = 0;
+static IORING_OFF_CQ_RING: libc::off_t = 0x0800_0000;
+static IORING_OFF_SQES: libc::off_t = 0x1000_0000;
+static IORING_ENTER_GETEVENTS: libc::c_uint = 1;
+/// Чтение и запись со смещением -1 используют и перемещают текущую позицию файла (Linux 5.6)
+static IORING_FEAT_RW_CUR_POS: u32 = 1 << 3;
+static IORING_OP_READ: u8 = 22;
+static IORING_OP_WRITE: u8 = 23;
+
+/// Размер очереди отправки; при ее заполнении операции выполняются в пуле потоков
+static RING_ENTRIES: u32 = 256;
+
+lazy_static! {
+    static ref URING: Option<Arc<Uring>> = Uring::new(RING_ENTRIES).ok();
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Следующая отправка операций в этом потоке завершится ошибкой (`EAGAIN`)
+    pub static FAIL_SUBMIT: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Доступен ли io_uring: ядро не ниже 5.6 и системный вызов не запрещен (например, seccomp)
+pub fn io_uring_available() -> bool {
+    URING.is_some()
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct SqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqringOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    resv2: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqringOffsets,
+    cq_off: CqringOffsets,
+}
+
+#[repr(C)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    buf_index: u16,
+    personality: u16,
+    splice_fd_in: i32,
+    pad: [u64; 2],
+}
+
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+fn enter(fd: RawFd, to_submit: u32, min_complete: u32, flags: libc::c_uint) -> std::io::Result<usize> {
+    let result = unsafe {
+        libc::syscall(SYS_IO_URING_ENTER, fd, to_submit, min_complete, flags, std::ptr::null::<libc::sigset_t>(), 0usize)
+    };
+    if result < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(result as usize)
+}
+
+
+// Mapping
+
+/// Область кольца, отображенная из дескриптора io_uring
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+impl Mapping {
+    fn new(fd: RawFd, len: usize, offset: libc::off_t) -> std::io::Result<Mapping> {
+        let ptr = unsafe {
+            libc::mmap(std::ptr::null_mut(), len, libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED | libc::MAP_POPULATE, fd, offset)
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    #[inline]
+    unsafe fn at<T>(&self, offset: u32) -> *mut T {
+        self.ptr.add(offset as usize) as *mut T
+    }
+}
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+
+// Operation
+
+/// Операция, ожидающая завершения в кольце: владеет файлом и буфером до завершения
+trait Operation: Send {
+    fn complete(self: Box<Self>, result: std::io::Result<usize>);
+}
+
+struct ReadWrite<B, F, T> {
+    file: std::fs::File,
+    buf: B,
+    finish: F,
+    completion: Completion<T>,
+}
+impl<B, F, T> Operation for ReadWrite<B, F, T>
+    where B: Send,
+          F: FnOnce(std::fs::File, B, std::io::Result<usize>) -> std::io::Result<T> + Send,
+          T: Send
+{
+    fn complete(self: Box<Self>, result: std::io::Result<usize>) {
+        let operation = *self;
+        let result = (operation.finish)(operation.file, operation.buf, result);
+        operation.completion.complete(result);
+    }
+}
+
+
+// Uring
+
+struct Submitter {
+    next_id: u64,
+    pending: HashMap<u64, Box<dyn Operation>>,
+}
+
+/// Кольцо io_uring с потоком, ожидающим завершения операций и пробуждающим задачи
+struct Uring {
+    file: std::fs::File,
+    params: Params,
+    sq: Mapping,
+    cq: Mapping,
+    sqes: Mapping,
+    submitter: Mutex<Submitter>,
+}
+unsafe impl Send for Uring {}
+unsafe impl Sync for Uring {}
+impl Uring {
+    fn new(entries: u32) -> std::io::Result<Arc<Uring>> {
+        let mut params = Params::default();
+        let fd = unsafe { libc::syscall(SYS_IO_URING_SETUP, entries, &mut params as *mut Params) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let file = unsafe { std::fs::File::from_raw_fd(fd as RawFd) };
+        if params.features & IORING_FEAT_RW_CUR_POS == 0 {
+            return Err(std::io::Error::other("io_uring does not support reads at the current file position"));
+        }
+        let fd = file.as_raw_fd();
+        let sq = Mapping::new(fd, params.sq_off.array as usize + params.sq_entries as usize * std::mem::size_of::<u32>(), IORING_OFF_SQ_RING)?;
+        let cq = Mapping::new(fd, params.cq_off.cqes as usize + params.cq_entries as usize * std::mem::size_of::<Cqe>(), IORING_OFF_CQ_RING)?;
+        let sqes = Mapping::new(fd, params.sq_entries as usize * std::mem::size_of::<Sqe>(), IORING_OFF_SQES)?;
+        let uring = Arc::new(Uring {
+            file,
+            params,
+            sq,
+            cq,
+            sqes,
+            submitter: Mutex::new(Submitter {
+                next_id: 0,
+                pending: HashMap::new(),
+            }),
+        });
+        let reaper = uring.clone();
+        std::thread::Builder::new()
+            .name("async_fs-io_uring".into())
+            .spawn(move || reaper.reap())?;
+        Ok(uring)
+    }
+
+    /// Помещает операцию в очередь отправки и отправляет ее ядру; если очередь заполнена
+    /// или отправка не удалась, возвращает операцию обратно
+    fn submit<O: Operation + 'static>(&self, opcode: u8, fd: RawFd, addr: u64, len: u32, operation: Box<O>) -> Result<(), Box<O>> {
+        let mut submitter = self.submitter.lock().unwrap();
+        let tail_ptr = unsafe { self.sq.at::<AtomicU32>(self.params.sq_off.tail) };
+        // очередь пополняется только под блокировкой
+        let tail = unsafe { (*tail_ptr).load(Ordering::Relaxed) };
+        let id = submitter.next_id;
+        unsafe {
+            let head = (*self.sq.at::<AtomicU32>(self.params.sq_off.head)).load(Ordering::Acquire);
+            if tail.wrapping_sub(head) >= self.params.sq_entries {
+                return Err(operation);
+            }
+            let index = tail & *self.sq.at::<u32>(self.params.sq_off.ring_mask);
+            std::ptr::write((self.sqes.ptr as *mut Sqe).add(index as usize), Sqe {
+                opcode,
+                flags: 0,
+                ioprio: 0,
+                fd,
+                // текущая позиция файла
+                off: u64::MAX,
+                addr,
+                len,
+                rw_flags: 0,
+                user_data: id,
+                buf_index: 0,
+                personality: 0,
+                splice_fd_in: 0,
+                pad: [0; 2],
+            });
+            *self.sq.at::<u32>(self.params.sq_off.array).add(index as usize) = index;
+            (*tail_ptr).store(tail.wrapping_add(1), Ordering::Release);
+        }
+        loop {
+            match self.enter_submit() {
+                Err(ref error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    // при ошибке (например, EAGAIN или EBUSY) ядро не забрало ни одной записи,
+                    // а без SQPOLL читает очередь только в `io_uring_enter` под этой же
+                    // блокировкой: запись убирается из очереди, операция выполнится в пуле
+                    unsafe {
+                        (*tail_ptr).store(tail, Ordering::Release);
+                    }
+                    return Err(operation);
+                },
+                Ok(_) => break,
+            }
+        }
+        // завершение не может быть обработано раньше: поток завершений ждет блокировку
+        submitter.next_id += 1;
+        submitter.pending.insert(id, operation);
+        Ok(())
+    }
+
+    /// Отправляет ядру записи очереди отправки
+    fn enter_submit(&self) -> std::io::Result<usize> {
+        #[cfg(test)]
+        {
+            if FAIL_SUBMIT.with(|fail| fail.replace(false)) {
+                return Err(std::io::Error::from_raw_os_error(libc::EAGAIN));
+            }
+        }
+        enter(self.file.as_raw_fd(), self.params.sq_entries, 0, 0)
+    }
+
+    /// Ожидает завершения операций и передает их результаты
+    fn reap(&self) {
+        let fd = self.file.as_raw_fd();
+        loop {
+            if let Err(error) = enter(fd, 0, 1, IORING_ENTER_GETEVENTS) {
+                if error.kind() != std::io::ErrorKind::Interrupted {
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                }
+            }
+            let mut completed = Vec::new();
+            unsafe {
+                let head_ptr = self.cq.at::<AtomicU32>(self.params.cq_off.head);
+                let mut head = (*head_ptr).load(Ordering::Relaxed);
+                let tail = (*self.cq.at::<AtomicU32>(self.params.cq_off.tail)).load(Ordering::Acquire);
+                let mask = *self.cq.at::<u32>(self.params.cq_off.ring_mask);
+                while head != tail {
+                    let cqe = std::ptr::read(self.cq.at::<Cqe>(self.params.cq_off.cqes).add((head & mask) as usize));
+                    completed.push((cqe.user_data, cqe.res));
+                    head = head.wrapping_add(1);
+                }
+                (*head_ptr).store(head, Ordering::Release);
+            }
+            if completed.is_empty() {
+                continue;
+            }
+            let operations: Vec<_> = {
+                let mut submitter = self.submitter.lock().unwrap();
+                completed.into_iter()
+                    .filter_map(|(id, res)| submitter.pending.remove(&id).map(|operation| (operation, res)))
+                    .collect()
+            };
+            for (operation, res) in operations {
+                let result = if res < 0 {
+                    Err(std::io::Error::from_raw_os_error(-res))
+                } else {
+                    Ok(res as usize)
+                };
+                operation.complete(result);
+            }
+        }
+    }
+}
+
+fn submit_rw<B, F, T, L>(opcode: u8, file: std::fs::File, buf: B, locate: L, finish: F) -> Result<PoolTask<T>, (std::fs::File, B)>
+    where B: Send + 'static,
+          F: FnOnce(std::fs::File, B, std::io::Result<usize>) -> std::io::Result<T> + Send + 'static,
+          T: Send + 'static,
+          L: FnOnce(&mut B) -> (u64, usize)
+{
+    let uring = match *URING {
+        Some(ref uring) => uring,
+        None => return Err((file, buf)),
+    };
+    let fd = file.as_raw_fd();
+    let (task, completion) = PoolTask::pending();
+    // адрес данных берется после перемещения буфера в кучу: небольшие буферы
+    // (например, `BytesMut` до 31 байта) хранят данные внутри себя
+    let mut operation = Box::new(ReadWrite {
+        file,
+        buf,
+        finish,
+        completion,
+    });
+    let (addr, len) = locate(&mut operation.buf);
+    let len = std::cmp::min(len, u32::MAX as usize) as u32;
+    match uring.submit(opcode, fd, addr, len, operation) {
+        Ok(()) => Ok(task),
+        Err(operation) => {
+            let operation = *operation;
+            Err((operation.file, operation.buf))
+        },
+    }
+}
+
+/// Читает в `buf` с текущей позиции файла через io_uring; `finish` получает файл, буфер
+/// и количество прочитанных байт. Если io_uring недоступен или очередь заполнена,
+/// файл и буфер возвращаются для выполнения операции в пуле потоков.
+pub fn read<B, F, T>(file: std::fs::File, buf: B, finish: F) -> Result<PoolTask<T>, (std::fs::File, B)>
+    where B: AsMut<[u8]> + Send + 'static,
+          F: FnOnce(std::fs::File, B, std::io::Result<usize>) -> std::io::Result<T> + Send + 'static,
+          T: Send + 'static
+{
+    submit_rw(IORING_OP_READ, file, buf, |buf| {
+        let slice = buf.as_mut();
+        (slice.as_mut_ptr() as u64, slice.len())
+    }, finish)
+}
+
+/// Записывает `buf` с текущей позиции файла через io_uring (см. `read`)
+pub fn write<B, F, T>(file: std::fs::File, buf: B, finish: F) -> Result<PoolTask<T>, (std::fs::File, B)>
+    where B: AsRef<[u8]> + Send + 'static,
+          F: FnOnce(std::fs::File, B, std::io::Result<usize>) -> std::io::Result<T> + Send + 'static,
+          T: Send + 'static
+{
+    submit_rw(IORING_OP_WRITE, file, buf, |buf| {
+        let slice = buf.as_ref();
+        (slice.as_ptr() as u64, slice.len())
+    }, finish)
+}
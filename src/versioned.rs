@@ -0,0 +1,217 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use bytes::Bytes;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use super::{DEFAULT_CPU_POOL, DEFAULT_BUFFER_SIZE};
+use conditional::{file_etag, PreconditionFailed};
+use lock::{try_lock_file, unlock_file, LockMode};
+use sys::{long_path, retry, sync_all};
+
+static VERSIONED_COUNTER: AtomicUsize = AtomicUsize::new(0);
+static DEFAULT_MAX_RETRIES: usize = 8;
+static VERSIONED_MAX_BACKOFF_MS: u64 = 50;
+
+/// Версия содержимого `VersionedFile`, полученная при чтении
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct VersionToken {
+    /// Версия файла (`file_etag`); `None`, если файла не было
+    etag: Option<String>,
+    /// Контрольная сумма содержимого: защищает от совпадения версий при грубой
+    /// точности времени изменения на файловой системе
+    crc32: u32,
+}
+impl VersionToken {
+
+    /// Существовал ли файл при чтении
+    #[inline]
+    pub fn exists(&self) -> bool {
+        self.etag.is_some()
+    }
+
+    #[inline]
+    pub fn etag(&self) -> Option<&str> {
+        self.etag.as_deref()
+    }
+}
+impl std::fmt::Display for VersionToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.etag {
+            Some(ref etag) => write!(f, "{}-{:08x}", etag, self.crc32),
+            None => write!(f, "absent"),
+        }
+    }
+}
+
+fn content_crc32(data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Читает файл целиком вместе с его версией; версия берется у открытого файла,
+/// поэтому соответствует прочитанному содержимому даже при одновременной замене файла
+fn read_versioned(path: &Path) -> std::io::Result<(Option<Bytes>, VersionToken)> {
+    let mut file = match std::fs::File::open(long_path(path)) {
+        Ok(file) => file,
+        Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => {
+            return Ok((None, VersionToken { etag: None, crc32: 0 }));
+        },
+        Err(error) => return Err(error),
+    };
+    let metadata = file.metadata()?;
+    let mut data = Vec::with_capacity(metadata.len() as usize);
+    let mut buf = vec![0u8; DEFAULT_BUFFER_SIZE];
+    loop {
+        let read = retry(|| file.read(&mut buf))?;
+        if read == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..read]);
+    }
+    let crc32 = content_crc32(&data);
+    Ok((Some(data.into()), VersionToken { etag: Some(file_etag(&metadata)), crc32 }))
+}
+
+fn sibling_path(path: &Path, suffix: &str) -> std::io::Result<PathBuf> {
+    let name = path.file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "path has no file name"))?;
+    Ok(path.with_file_name(format!(".{}.{}", name.to_string_lossy(), suffix)))
+}
+
+/// Ожидает исключительную блокировку файла блокировки рядом с `path`.
+/// Блокировка удерживается только на время сравнения версий и переименования.
+fn lock_versioned(path: &Path) -> std::io::Result<std::fs::File> {
+    let lock_path = sibling_path(path, "lock")?;
+    let file = retry(|| std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(long_path(&lock_path)))?;
+    let mut backoff = Duration::from_millis(1);
+    while !try_lock_file(&file, LockMode::Exclusive)? {
+        std::thread::sleep(backoff);
+        backoff = std::cmp::min(backoff * 2, Duration::from_millis(VERSIONED_MAX_BACKOFF_MS));
+    }
+    Ok(file)
+}
+
+fn store_versioned(path: &Path, data: &[u8], token: &VersionToken) -> std::io::Result<VersionToken> {
+    let temporary = sibling_path(path, &format!("write-{}-{}", std::process::id(), VERSIONED_COUNTER.fetch_add(1, Ordering::SeqCst)))?;
+    let result = (|| {
+        let mut file = retry(|| std::fs::OpenOptions::new().write(true).create_new(true).open(long_path(&temporary)))?;
+        file.write_all(data)?;
+        retry(|| sync_all(&file))?;
+
+        let lock = lock_versioned(path)?;
+        let result = (|| {
+            let (_, current) = read_versioned(path)?;
+            if current != *token {
+                return Err(std::io::Error::other(PreconditionFailed {
+                    current: current.etag,
+                }));
+            }
+            retry(|| std::fs::rename(long_path(&temporary), long_path(path)))?;
+            Ok(VersionToken { etag: Some(file_etag(&file.metadata()?)), crc32: content_crc32(data) })
+        })();
+        let _ = unlock_file(&lock);
+        result
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(long_path(&temporary));
+    }
+    result
+}
+
+fn is_precondition_failed(error: &std::io::Error) -> bool {
+    error.get_ref().is_some_and(|error| error.is::<PreconditionFailed>())
+}
+
+
+// VersionedFile
+
+/// Файл с оптимистичной проверкой версий: `load` возвращает содержимое и его версию,
+/// а `store` записывает новое содержимое, только если файл не изменился с момента чтения
+/// (иначе - ошибка `PreconditionFailed`).
+///
+/// Данные записываются во временный файл рядом с `path`, после чего под блокировкой
+/// файла `.{name}.lock` версия сравнивается с текущей и временный файл атомарно заменяет
+/// `path`. Поэтому одновременные `store` нескольких процессов не теряют обновлений,
+/// а читатели видят либо старое, либо новое содержимое без блокировок.
+#[derive(Clone)]
+pub struct VersionedFile {
+    cpu_pool: &'static CpuPool,
+    path: PathBuf,
+    max_retries: usize,
+}
+impl VersionedFile {
+
+    #[inline]
+    pub fn new<P: AsRef<Path>>(path: P) -> VersionedFile {
+        Self::new_with_pool(&DEFAULT_CPU_POOL, path)
+    }
+
+    pub fn new_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> VersionedFile {
+        VersionedFile {
+            cpu_pool,
+            path: path.as_ref().into(),
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+
+    /// Задает количество повторных попыток `update` при конфликте версий
+    #[inline]
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Читает содержимое файла (`None`, если файла нет) и его версию
+    pub fn load(&self) -> CpuFuture<(Option<Bytes>, VersionToken), std::io::Error> {
+        let path = self.path.clone();
+        self.cpu_pool.spawn_fn(move || read_versioned(&path))
+    }
+
+    /// Записывает `data`, если версия файла все еще `token`, и возвращает новую версию
+    pub fn store(&self, data: Bytes, token: VersionToken) -> CpuFuture<VersionToken, std::io::Error> {
+        let path = self.path.clone();
+        self.cpu_pool.spawn_fn(move || store_versioned(&path, &data, &token))
+    }
+
+    /// Читает содержимое, вычисляет новое функцией `update` и записывает его;
+    /// при конфликте версий чтение и вычисление повторяются (не более `max_retries` раз).
+    /// Возвращает записанное содержимое и его версию.
+    ///
+    /// `update` может вызываться несколько раз и должна зависеть только от переданного
+    /// содержимого; ошибка `update` прекращает попытки.
+    pub fn update<F>(&self, mut update: F) -> CpuFuture<(Bytes, VersionToken), std::io::Error>
+        where F: FnMut(Option<&Bytes>) -> std::io::Result<Bytes> + Send + 'static
+    {
+        let path = self.path.clone();
+        let max_retries = self.max_retries;
+        self.cpu_pool.spawn_fn(move || {
+            let mut attempt = 0;
+            loop {
+                let (data, token) = read_versioned(&path)?;
+                let data = update(data.as_ref())?;
+                match store_versioned(&path, &data, &token) {
+                    Ok(token) => return Ok((data, token)),
+                    Err(ref error) if is_precondition_failed(error) && attempt < max_retries => {
+                        attempt += 1;
+                    },
+                    Err(error) => return Err(error),
+                }
+            }
+        })
+    }
+}
+impl std::fmt::Debug for VersionedFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("VersionedFile")
+            .field("path", &self.path)
+            .field("max_retries", &self.max_retries)
+            .finish()
+    }
+}
@@ -0,0 +1,263 @@
+use futures::{Poll, Future, Async, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use super::DEFAULT_CPU_POOL;
+use ignore::IgnoreRules;
+use sys::long_path;
+
+/// Количество записей, читаемых за одно обращение к пулу потоков
+static DEFAULT_WALK_BATCH_SIZE: usize = 256;
+
+
+// WalkEntry
+
+/// Запись, найденная при обходе дерева каталогов
+#[derive(Debug, Clone)]
+pub struct WalkEntry {
+    pub path: PathBuf,
+    /// Глубина вложенности: 1 для записей корневого каталога
+    pub depth: usize,
+    /// Метаданные записи (символические ссылки разыменовываются только при `follow_links`)
+    pub metadata: std::fs::Metadata,
+}
+
+fn filesystem_loop(path: &Path) -> std::io::Error {
+    std::io::Error::other(format!("filesystem loop at {}", path.display()))
+}
+
+
+// WalkStream
+
+/// Фильтр записей обхода (см. `WalkStream::filter_entry`)
+type EntryFilter = Arc<dyn Fn(&WalkEntry) -> bool + Send + Sync>;
+
+/// Пакет записей, прочитанный в пуле потоков, вместе с состоянием обхода
+type WalkBatch = (WalkState, VecDeque<std::io::Result<WalkEntry>>);
+
+/// Состояние обхода, передаваемое в пул потоков и обратно
+struct WalkState {
+    base: PathBuf,
+    root: Option<PathBuf>,
+    rules: Option<Arc<IgnoreRules>>,
+    filter: Option<EntryFilter>,
+    max_depth: Option<usize>,
+    follow_links: bool,
+    /// Открытые каталоги, глубина их записей и, при `follow_links`, канонический путь каталога
+    stack: Vec<(std::fs::ReadDir, usize, Option<PathBuf>)>,
+}
+impl WalkState {
+    fn is_done(&self) -> bool {
+        self.root.is_none() && self.stack.is_empty()
+    }
+
+    fn read_batch(&mut self, batch_size: usize) -> VecDeque<std::io::Result<WalkEntry>> {
+        let mut entries = VecDeque::with_capacity(batch_size);
+        if let Some(root) = self.root.take() {
+            if self.max_depth == Some(0) {
+                return entries;
+            }
+            match self.open_dir(&root) {
+                Ok((dir, canonical)) => self.stack.push((dir, 1, canonical)),
+                Err(error) => entries.push_back(Err(error)),
+            }
+        }
+        while entries.len() < batch_size {
+            let (entry, depth) = match self.stack.last_mut() {
+                Some(&mut (ref mut dir, depth, _)) => match dir.next() {
+                    Some(entry) => (entry, depth),
+                    None => {
+                        self.stack.pop();
+                        continue;
+                    },
+                },
+                None => break,
+            };
+            let follow_links = self.follow_links;
+            let entry = entry.and_then(|entry| {
+                let path = entry.path();
+                let metadata = match follow_links {
+                    // для "висячей" ссылки - метаданные самой ссылки
+                    true => std::fs::metadata(long_path(&path)).or_else(|_| entry.metadata())?,
+                    false => entry.metadata()?,
+                };
+                Ok(WalkEntry {
+                    path,
+                    depth,
+                    metadata,
+                })
+            });
+            if let Ok(ref entry) = entry {
+                if self.is_ignored(entry) || !self.is_accepted(entry) {
+                    continue;
+                }
+                if entry.metadata.is_dir() && self.max_depth.is_none_or(|max_depth| depth < max_depth) {
+                    match self.open_dir(&entry.path) {
+                        Ok((dir, canonical)) => self.stack.push((dir, depth + 1, canonical)),
+                        Err(error) => {
+                            entries.push_back(Ok(entry.clone()));
+                            entries.push_back(Err(error));
+                            continue;
+                        },
+                    }
+                }
+            }
+            entries.push_back(entry);
+        }
+        entries
+    }
+
+    /// Открывает каталог; при `follow_links` проверяет, что он не является одним из
+    /// обходимых каталогов (иначе ссылка образует цикл)
+    fn open_dir(&self, path: &Path) -> std::io::Result<(std::fs::ReadDir, Option<PathBuf>)> {
+        let canonical = if self.follow_links {
+            let canonical = std::fs::canonicalize(long_path(path))?;
+            if self.stack.iter().any(|(_, _, ancestor)| ancestor.as_ref() == Some(&canonical)) {
+                return Err(filesystem_loop(path));
+            }
+            Some(canonical)
+        } else {
+            None
+        };
+        Ok((std::fs::read_dir(long_path(path))?, canonical))
+    }
+
+    fn is_accepted(&self, entry: &WalkEntry) -> bool {
+        match self.filter {
+            Some(ref filter) => filter(entry),
+            None => true,
+        }
+    }
+
+    fn is_ignored(&self, entry: &WalkEntry) -> bool {
+        match self.rules {
+            Some(ref rules) => rules.is_ignored(entry, entry.path.strip_prefix(&self.base).unwrap_or(&entry.path)),
+            None => false,
+        }
+    }
+}
+
+/// Поток записей дерева каталогов (обход в глубину, каталог предшествует своему содержимому).
+///
+/// Записи читаются в пуле потоков пакетами. Ошибки чтения отдельных записей и каталогов
+/// передаются как ошибки потока, после чего обход можно продолжить.
+pub struct WalkStream {
+    cpu_pool: &'static CpuPool,
+    batch_size: usize,
+    state: Option<WalkState>,
+    reading: Option<CpuFuture<WalkBatch, std::io::Error>>,
+    entries: VecDeque<std::io::Result<WalkEntry>>,
+}
+
+/// Обходит дерево каталогов `root` (сам корневой каталог в поток не попадает)
+#[inline]
+pub fn walk<P: AsRef<Path>>(root: P) -> WalkStream {
+    walk_with_pool(&DEFAULT_CPU_POOL, root)
+}
+
+pub fn walk_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, root: P) -> WalkStream {
+    WalkStream {
+        cpu_pool,
+        batch_size: DEFAULT_WALK_BATCH_SIZE,
+        state: Some(WalkState {
+            base: root.as_ref().into(),
+            root: Some(root.as_ref().into()),
+            rules: None,
+            filter: None,
+            max_depth: None,
+            follow_links: false,
+            stack: Vec::new(),
+        }),
+        reading: None,
+        entries: VecDeque::new(),
+    }
+}
+
+impl WalkStream {
+
+    /// Задает количество записей, читаемых за одно обращение к пулу потоков
+    #[inline]
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "batch size must be greater than zero");
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Ограничивает глубину обхода: 1 - только записи корневого каталога
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        if let Some(ref mut state) = self.state {
+            state.max_depth = Some(max_depth);
+        }
+        self
+    }
+
+    /// Разыменовывать ли символические ссылки: ссылки на каталоги обходятся, а метаданные
+    /// записей - метаданные цели ссылки. Ссылка на каталог, уже обходимый выше по дереву,
+    /// передается как запись, за которой следует ошибка потока.
+    pub fn follow_links(mut self, follow_links: bool) -> Self {
+        if let Some(ref mut state) = self.state {
+            state.follow_links = follow_links;
+        }
+        self
+    }
+
+    /// Задает условие, которому должны удовлетворять записи: остальные записи не попадают
+    /// в поток, а каталоги не обходятся. Проверяется в пуле потоков.
+    pub fn filter_entry<F>(mut self, filter: F) -> Self
+        where F: Fn(&WalkEntry) -> bool + Send + Sync + 'static
+    {
+        if let Some(ref mut state) = self.state {
+            state.filter = Some(Arc::new(filter));
+        }
+        self
+    }
+
+    /// Задает правила исключения записей: исключенные записи не попадают в поток,
+    /// а исключенные каталоги не обходятся
+    pub fn with_ignore_rules(mut self, rules: IgnoreRules) -> Self {
+        if let Some(ref mut state) = self.state {
+            state.rules = Some(Arc::new(rules));
+        }
+        self
+    }
+}
+impl Stream for WalkStream {
+    type Item = WalkEntry;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(entry) = self.entries.pop_front() {
+                return entry.map(|entry| Async::Ready(Some(entry)));
+            }
+            if let Some(ref mut reading) = self.reading {
+                let (state, entries) = try_ready!(reading.poll());
+                self.state = Some(state);
+                self.entries = entries;
+            }
+            self.reading = None;
+            if !self.entries.is_empty() {
+                continue;
+            }
+            match self.state.take() {
+                Some(ref state) if state.is_done() => return Ok(Async::Ready(None)),
+                Some(mut state) => {
+                    let batch_size = self.batch_size;
+                    self.reading = Some(self.cpu_pool.spawn_fn(move || {
+                        let entries = state.read_batch(batch_size);
+                        Ok((state, entries))
+                    }));
+                },
+                None => return Ok(Async::Ready(None)),
+            }
+        }
+    }
+}
+impl std::fmt::Debug for WalkStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("WalkStream")
+            .field("batch_size", &self.batch_size)
+            .finish()
+    }
+}
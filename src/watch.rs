@@ -0,0 +1,402 @@
+use futures::{Poll, Future, Async, Stream};
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::collections::{BTreeMap, VecDeque};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use pool_registry;
+use timeout::Deadline;
+use sys::long_path;
+
+/// Интервал проверки изменений при опросе файловой системы
+static DEFAULT_POLLING_INTERVAL_MS: u64 = 1000;
+
+/// Интервал чтения очереди событий inotify
+#[cfg(target_os = "linux")]
+static DEFAULT_INOTIFY_INTERVAL_MS: u64 = 50;
+
+/// Изменение, обнаруженное `Watch`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsEvent {
+    Created(PathBuf),
+    Modified(PathBuf),
+    Removed(PathBuf),
+    Renamed {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    /// События могли быть потеряны (например, при переполнении очереди inotify):
+    /// состояние файлов следует перечитать
+    Rescan,
+}
+
+
+// Snapshot
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EntryState {
+    len: u64,
+    modified: Option<SystemTime>,
+    is_dir: bool,
+}
+impl EntryState {
+    fn new(metadata: &std::fs::Metadata) -> EntryState {
+        EntryState {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            is_dir: metadata.is_dir(),
+        }
+    }
+}
+
+/// Состояние наблюдаемого пути для обнаружения изменений опросом
+enum Snapshot {
+    Missing,
+    File(EntryState),
+    Dir(BTreeMap<OsString, EntryState>),
+}
+impl Snapshot {
+    fn read(path: &Path) -> std::io::Result<Snapshot> {
+        let metadata = match std::fs::metadata(long_path(path)) {
+            Ok(metadata) => metadata,
+            Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Snapshot::Missing),
+            Err(error) => return Err(error),
+        };
+        if !metadata.is_dir() {
+            return Ok(Snapshot::File(EntryState::new(&metadata)));
+        }
+        let mut entries = BTreeMap::new();
+        for entry in std::fs::read_dir(long_path(path))? {
+            let entry = entry?;
+            match entry.metadata() {
+                Ok(metadata) => {
+                    entries.insert(entry.file_name(), EntryState::new(&metadata));
+                },
+                // запись удалена после чтения каталога
+                Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => {},
+                Err(error) => return Err(error),
+            }
+        }
+        Ok(Snapshot::Dir(entries))
+    }
+
+    /// События, переводящие наблюдаемый путь из состояния `self` в `new`.
+    /// Переименования опросом не обнаруживаются: они выглядят как удаление и создание.
+    fn diff(&self, path: &Path, new: &Snapshot) -> Vec<FsEvent> {
+        match (self, new) {
+            (Snapshot::Missing, Snapshot::Missing) => Vec::new(),
+            (Snapshot::Missing, _) => vec![FsEvent::Created(path.into())],
+            (_, Snapshot::Missing) => vec![FsEvent::Removed(path.into())],
+            (Snapshot::File(old), Snapshot::File(new)) => {
+                if old != new {
+                    vec![FsEvent::Modified(path.into())]
+                } else {
+                    Vec::new()
+                }
+            },
+            (Snapshot::Dir(old), Snapshot::Dir(new)) => {
+                let mut events = Vec::new();
+                for name in old.keys() {
+                    if !new.contains_key(name) {
+                        events.push(FsEvent::Removed(path.join(name)));
+                    }
+                }
+                for (name, state) in new {
+                    match old.get(name) {
+                        None => events.push(FsEvent::Created(path.join(name))),
+                        Some(old) if old.is_dir != state.is_dir => {
+                            events.push(FsEvent::Removed(path.join(name)));
+                            events.push(FsEvent::Created(path.join(name)));
+                        },
+                        // изменения внутри вложенных каталогов не отслеживаются
+                        Some(old) if old != state && !state.is_dir => events.push(FsEvent::Modified(path.join(name))),
+                        _ => {},
+                    }
+                }
+                events
+            },
+            _ => vec![FsEvent::Removed(path.into()), FsEvent::Created(path.into())],
+        }
+    }
+}
+
+
+// Inotify
+
+#[cfg(target_os = "linux")]
+struct Inotify {
+    /// Дескриптор очереди inotify в неблокирующем режиме
+    file: std::fs::File,
+    buf: Vec<u8>,
+}
+#[cfg(target_os = "linux")]
+impl Inotify {
+    fn new(path: &Path) -> std::io::Result<Inotify> {
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::io::{AsRawFd, FromRawFd};
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let file = unsafe { std::fs::File::from_raw_fd(fd) };
+        let c_path = std::ffi::CString::new(long_path(path).as_os_str().as_bytes())
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidInput, error))?;
+        let mask = libc::IN_CREATE | libc::IN_DELETE | libc::IN_MODIFY | libc::IN_MOVED_FROM | libc::IN_MOVED_TO
+            | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF;
+        if unsafe { libc::inotify_add_watch(file.as_raw_fd(), c_path.as_ptr(), mask) } < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        Ok(Inotify {
+            file,
+            buf: vec![0; 16 * 1024],
+        })
+    }
+
+    /// Читает накопленные события, не блокируя поток
+    fn read_events(&mut self, path: &Path) -> std::io::Result<Vec<FsEvent>> {
+        use std::io::Read;
+        use std::os::unix::ffi::OsStrExt;
+        let header = std::mem::size_of::<libc::inotify_event>();
+        let mut events = Vec::new();
+        // события `IN_MOVED_FROM`, ожидающие парного `IN_MOVED_TO`
+        let mut moved: Vec<(u32, PathBuf)> = Vec::new();
+        loop {
+            let size = match self.file.read(&mut self.buf) {
+                Ok(size) => size,
+                Err(ref error) if error.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(ref error) if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            };
+            let mut offset = 0;
+            while offset + header <= size {
+                let event = unsafe { std::ptr::read_unaligned(self.buf[offset..].as_ptr() as *const libc::inotify_event) };
+                let name = &self.buf[offset + header..offset + header + event.len as usize];
+                offset += header + event.len as usize;
+                let target = match name.iter().position(|&byte| byte == 0).unwrap_or(name.len()) {
+                    0 => path.to_path_buf(),
+                    len => path.join(std::ffi::OsStr::from_bytes(&name[..len])),
+                };
+                let event = if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                    FsEvent::Rescan
+                } else if event.mask & libc::IN_MOVED_FROM != 0 {
+                    moved.push((event.cookie, target));
+                    continue;
+                } else if event.mask & libc::IN_MOVED_TO != 0 {
+                    match moved.iter().position(|&(cookie, _)| cookie == event.cookie) {
+                        Some(index) => FsEvent::Renamed {
+                            from: moved.remove(index).1,
+                            to: target,
+                        },
+                        None => FsEvent::Created(target),
+                    }
+                } else if event.mask & libc::IN_CREATE != 0 {
+                    FsEvent::Created(target)
+                } else if event.mask & (libc::IN_DELETE | libc::IN_DELETE_SELF | libc::IN_MOVE_SELF) != 0 {
+                    FsEvent::Removed(target)
+                } else if event.mask & libc::IN_MODIFY != 0 {
+                    FsEvent::Modified(target)
+                } else {
+                    continue;
+                };
+                // запись файла порождает серию одинаковых событий
+                if events.last() != Some(&event) {
+                    events.push(event);
+                }
+            }
+        }
+        // перемещенные за пределы наблюдаемого каталога
+        events.extend(moved.into_iter().map(|(_, path)| FsEvent::Removed(path)));
+        Ok(events)
+    }
+}
+
+
+// Watch
+
+enum Watcher {
+    #[cfg(target_os = "linux")]
+    Inotify(Inotify),
+    Polling(Snapshot),
+}
+impl Watcher {
+    #[cfg_attr(not(target_os = "linux"), allow(unused_variables))]
+    fn open(path: &Path, polling: bool) -> std::io::Result<Watcher> {
+        let snapshot = Snapshot::read(path)?;
+        if let Snapshot::Missing = snapshot {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("{} not found", path.display())));
+        }
+        #[cfg(target_os = "linux")]
+        {
+            if !polling {
+                match Inotify::new(path) {
+                    Ok(inotify) => return Ok(Watcher::Inotify(inotify)),
+                    // исчерпан лимит inotify: используется опрос
+                    Err(ref error) if error.raw_os_error() == Some(libc::ENOSPC) || error.raw_os_error() == Some(libc::EMFILE) => {},
+                    Err(error) => return Err(error),
+                }
+            }
+        }
+        Ok(Watcher::Polling(snapshot))
+    }
+
+    fn default_interval(&self) -> Duration {
+        match *self {
+            #[cfg(target_os = "linux")]
+            Watcher::Inotify(_) => Duration::from_millis(DEFAULT_INOTIFY_INTERVAL_MS),
+            Watcher::Polling(_) => Duration::from_millis(DEFAULT_POLLING_INTERVAL_MS),
+        }
+    }
+}
+
+enum WatchState {
+    New,
+    Opening(CpuFuture<Watcher, std::io::Error>),
+    Ready(Watcher),
+    Scanning(CpuFuture<(Watcher, std::io::Result<Vec<FsEvent>>), std::io::Error>),
+    Waiting(Watcher, Deadline),
+    Swapping,
+}
+
+/// Поток изменений файла или содержимого каталога (без вложенных каталогов).
+///
+/// На Linux изменения получаются через inotify: очередь событий читается без блокировки
+/// в текущем потоке с коротким интервалом. На других платформах, а также при исчерпании лимита
+/// inotify и при `polling(true)` (например, для сетевых файловых систем) состояние пути
+/// периодически сравнивается с предыдущим в пуле потоков; переименования при этом
+/// передаются как удаление и создание.
+///
+/// Путь должен существовать на момент первого опроса потока.
+pub struct Watch {
+    cpu_pool: &'static CpuPool,
+    path: PathBuf,
+    polling: bool,
+    interval: Option<Duration>,
+    state: WatchState,
+    events: VecDeque<FsEvent>,
+}
+
+/// Наблюдает за изменениями `path` в пуле потоков, выбранном по пути (см. `PoolRegistry`)
+#[inline]
+pub fn watch<P: AsRef<Path>>(path: P) -> Watch {
+    watch_with_pool(pool_registry::pool_for(path.as_ref()), path)
+}
+
+pub fn watch_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> Watch {
+    Watch {
+        cpu_pool,
+        path: path.as_ref().into(),
+        polling: false,
+        interval: None,
+        state: WatchState::New,
+        events: VecDeque::new(),
+    }
+}
+
+impl Watch {
+
+    /// Обнаруживать ли изменения только опросом файловой системы
+    #[inline]
+    pub fn polling(mut self, polling: bool) -> Self {
+        self.polling = polling;
+        self
+    }
+
+    /// Задает интервал проверки изменений
+    /// (по умолчанию 50 мс для inotify и 1 с для опроса)
+    #[inline]
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = Some(interval);
+        self
+    }
+
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn wait(&mut self, watcher: Watcher) {
+        let interval = self.interval.unwrap_or_else(|| watcher.default_interval());
+        self.state = WatchState::Waiting(watcher, Deadline::new(Instant::now() + interval));
+    }
+}
+impl Stream for Watch {
+    type Item = FsEvent;
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Async::Ready(Some(event)));
+            }
+            match std::mem::replace(&mut self.state, WatchState::Swapping) {
+                WatchState::New => {
+                    let path = self.path.clone();
+                    let polling = self.polling;
+                    self.state = WatchState::Opening(self.cpu_pool.spawn_fn(move || Watcher::open(&path, polling)));
+                },
+                WatchState::Opening(mut future) => match future.poll() {
+                    Ok(Async::Ready(watcher)) => self.wait(watcher),
+                    Ok(Async::NotReady) => {
+                        self.state = WatchState::Opening(future);
+                        return Ok(Async::NotReady);
+                    },
+                    Err(error) => {
+                        // повторный опрос снова начинает наблюдение
+                        self.state = WatchState::New;
+                        return Err(error);
+                    },
+                },
+                #[cfg(target_os = "linux")]
+                WatchState::Ready(Watcher::Inotify(mut inotify)) => {
+                    let result = inotify.read_events(&self.path);
+                    self.wait(Watcher::Inotify(inotify));
+                    self.events.extend(result?);
+                },
+                WatchState::Ready(Watcher::Polling(snapshot)) => {
+                    let path = self.path.clone();
+                    self.state = WatchState::Scanning(self.cpu_pool.spawn_fn(move || {
+                        match Snapshot::read(&path) {
+                            Ok(new) => {
+                                let events = snapshot.diff(&path, &new);
+                                Ok((Watcher::Polling(new), Ok(events)))
+                            },
+                            Err(error) => Ok((Watcher::Polling(snapshot), Err(error))),
+                        }
+                    }));
+                },
+                WatchState::Scanning(mut future) => match future.poll() {
+                    Ok(Async::Ready((watcher, result))) => {
+                        self.wait(watcher);
+                        self.events.extend(result?);
+                    },
+                    Ok(Async::NotReady) => {
+                        self.state = WatchState::Scanning(future);
+                        return Ok(Async::NotReady);
+                    },
+                    Err(error) => {
+                        self.state = WatchState::New;
+                        return Err(error);
+                    },
+                },
+                WatchState::Waiting(watcher, mut deadline) => {
+                    if deadline.poll_elapsed() {
+                        self.state = WatchState::Ready(watcher);
+                    } else {
+                        self.state = WatchState::Waiting(watcher, deadline);
+                        return Ok(Async::NotReady);
+                    }
+                },
+                WatchState::Swapping => unreachable!(),
+            }
+        }
+    }
+}
+impl std::fmt::Debug for Watch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Watch")
+            .field("path", &self.path)
+            .field("polling", &self.polling)
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
@@ -0,0 +1,58 @@
+use futures_cpupool::{CpuPool, CpuFuture};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use pool_registry;
+use sys::{long_path, retry};
+
+/// Читает файл `path` целиком: открытие, чтение и закрытие выполняются одной операцией
+/// в пуле потоков (пул выбирается по пути, см. `PoolRegistry`)
+#[inline]
+pub fn read<P: AsRef<Path>>(path: P) -> CpuFuture<Vec<u8>, std::io::Error> {
+    read_with_pool(pool_registry::pool_for(path.as_ref()), path)
+}
+
+pub fn read_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<Vec<u8>, std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || {
+        let mut file = retry(|| std::fs::File::open(long_path(&path)))?;
+        let size = file.metadata().map(|metadata| metadata.len() as usize).unwrap_or(0);
+        let mut data = Vec::with_capacity(size);
+        file.read_to_end(&mut data)?;
+        Ok(data)
+    })
+}
+
+/// Читает файл `path` целиком как строку UTF-8 (см. `read`)
+#[inline]
+pub fn read_to_string<P: AsRef<Path>>(path: P) -> CpuFuture<String, std::io::Error> {
+    read_to_string_with_pool(pool_registry::pool_for(path.as_ref()), path)
+}
+
+pub fn read_to_string_with_pool<P: AsRef<Path>>(cpu_pool: &'static CpuPool, path: P) -> CpuFuture<String, std::io::Error> {
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || {
+        let mut file = retry(|| std::fs::File::open(long_path(&path)))?;
+        let size = file.metadata().map(|metadata| metadata.len() as usize).unwrap_or(0);
+        let mut data = String::with_capacity(size);
+        file.read_to_string(&mut data)?;
+        Ok(data)
+    })
+}
+
+/// Создает (или усекает) файл `path` и записывает в него `data` одной операцией в пуле потоков.
+/// Данные не сбрасываются на диск принудительно.
+#[inline]
+pub fn write<P: AsRef<Path>, C: AsRef<[u8]> + Send + 'static>(path: P, data: C) -> CpuFuture<(), std::io::Error> {
+    write_with_pool(pool_registry::pool_for(path.as_ref()), path, data)
+}
+
+pub fn write_with_pool<P, C>(cpu_pool: &'static CpuPool, path: P, data: C) -> CpuFuture<(), std::io::Error>
+    where P: AsRef<Path>,
+          C: AsRef<[u8]> + Send + 'static
+{
+    let path: PathBuf = path.as_ref().into();
+    cpu_pool.spawn_fn(move || {
+        let mut file = retry(|| std::fs::File::create(long_path(&path)))?;
+        file.write_all(data.as_ref())
+    })
+}
@@ -0,0 +1,242 @@
+use futures::{Poll, Future, Async};
+use executor::BlockingExecutor;
+use pool_task::PoolTask;
+use super::{AsyncFileWrite, AsyncFileWriteState, AsyncFileSink, AsyncFileSinkState};
+use super::{AsyncFileRead, AsyncFileReadState, AsyncFileStream, AsyncFileStreamState};
+use super::{AsyncFile, AsyncFileState};
+
+fn already_shutdown() -> std::io::Error {
+    std::io::Error::other("`File` instance already shutdown")
+}
+
+/// Обертка, из которой можно временно забрать файл между операциями
+pub trait FileSlot {
+    type Executor: BlockingExecutor;
+
+    fn executor(&self) -> &Self::Executor;
+
+    /// Дожидается завершения начатой операции и забирает файл
+    fn poll_take_file(&mut self) -> Poll<std::fs::File, std::io::Error>;
+
+    fn put_file(&mut self, file: std::fs::File);
+}
+
+impl<E: BlockingExecutor> FileSlot for AsyncFileWrite<E> {
+    type Executor = E;
+
+    fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    fn poll_take_file(&mut self) -> Poll<std::fs::File, std::io::Error> {
+        loop {
+            match self.state {
+                AsyncFileWriteState::Write(ref mut task) => {
+                    let (file, _, _) = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Flush(ref mut task) | AsyncFileWriteState::SetLen(ref mut task) | AsyncFileWriteState::Shutdown(ref mut task) | AsyncFileWriteState::Sync(ref mut task) | AsyncFileWriteState::WriteVectored(ref mut task) => {
+                    let file = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Seek(ref mut task) => {
+                    let (file, _) = try_ready!(task.poll());
+                    self.state = AsyncFileWriteState::Ready(file);
+                },
+                AsyncFileWriteState::Ready(_) => {
+                    if let AsyncFileWriteState::Ready(file) = std::mem::replace(&mut self.state, AsyncFileWriteState::Swapping) {
+                        return Ok(Async::Ready(file));
+                    }
+                },
+                AsyncFileWriteState::Swapping => return Err(already_shutdown()),
+            }
+        }
+    }
+
+    fn put_file(&mut self, file: std::fs::File) {
+        self.state = AsyncFileWriteState::Ready(file);
+    }
+}
+
+impl<E: BlockingExecutor, B: AsRef<[u8]> + Send + 'static> FileSlot for AsyncFileSink<E, B> {
+    type Executor = E;
+
+    fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    fn poll_take_file(&mut self) -> Poll<std::fs::File, std::io::Error> {
+        try_ready!(futures::Sink::poll_complete(self));
+        match std::mem::replace(&mut self.state, AsyncFileSinkState::Swapping) {
+            AsyncFileSinkState::Ready(file) => Ok(Async::Ready(file)),
+            state => {
+                self.state = state;
+                Err(already_shutdown())
+            },
+        }
+    }
+
+    fn put_file(&mut self, file: std::fs::File) {
+        self.state = AsyncFileSinkState::Ready(file);
+    }
+}
+
+impl<E: BlockingExecutor> FileSlot for AsyncFileRead<E> {
+    type Executor = E;
+
+    fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    fn poll_take_file(&mut self) -> Poll<std::fs::File, std::io::Error> {
+        loop {
+            match self.state {
+                AsyncFileReadState::Read(ref mut task) => {
+                    let (file, data) = try_ready!(task.poll());
+                    self.state = AsyncFileReadState::Ready(file);
+                    self.reuse_buffer(data);
+                },
+                AsyncFileReadState::ReadVectored(ref mut task) => {
+                    let (file, _) = try_ready!(task.poll());
+                    self.state = AsyncFileReadState::Ready(file);
+                },
+                AsyncFileReadState::Seek(ref mut task) => {
+                    let (file, _) = try_ready!(task.poll());
+                    self.state = AsyncFileReadState::Ready(file);
+                },
+                AsyncFileReadState::Ready(_) => {
+                    if let AsyncFileReadState::Ready(mut file) = std::mem::replace(&mut self.state, AsyncFileReadState::Swapping) {
+                        // позиция файла возвращается к прочитанным, но не отданным данным
+                        let pending = self.pending.len() as i64;
+                        if pending > 0 {
+                            if let Err(error) = std::io::Seek::seek(&mut file, std::io::SeekFrom::Current(-pending)) {
+                                self.state = AsyncFileReadState::Ready(file);
+                                return Err(error);
+                            }
+                            self.pending.clear();
+                        }
+                        return Ok(Async::Ready(file));
+                    }
+                },
+                AsyncFileReadState::Swapping => return Err(already_shutdown()),
+            }
+        }
+    }
+
+    fn put_file(&mut self, file: std::fs::File) {
+        self.state = AsyncFileReadState::Ready(file);
+    }
+}
+
+impl<E: BlockingExecutor> FileSlot for AsyncFileStream<E> {
+    type Executor = E;
+
+    fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    fn poll_take_file(&mut self) -> Poll<std::fs::File, std::io::Error> {
+        self.stop_prefetch()?;
+        loop {
+            match self.state {
+                AsyncFileStreamState::Read(ref mut future) => {
+                    let (file, _, _) = try_ready!(future.poll());
+                    self.state = AsyncFileStreamState::Ready(file);
+                },
+                AsyncFileStreamState::Seek(ref mut future) => {
+                    let (file, _) = try_ready!(future.poll());
+                    self.state = AsyncFileStreamState::Ready(file);
+                },
+                AsyncFileStreamState::Ready(_) => {
+                    if let AsyncFileStreamState::Ready(file) = std::mem::replace(&mut self.state, AsyncFileStreamState::Swapping) {
+                        return Ok(Async::Ready(file));
+                    }
+                },
+                AsyncFileStreamState::Swapping => return Err(already_shutdown()),
+            }
+        }
+    }
+
+    fn put_file(&mut self, file: std::fs::File) {
+        self.state = AsyncFileStreamState::Ready(file);
+    }
+}
+
+impl<E: BlockingExecutor> FileSlot for AsyncFile<E> {
+    type Executor = E;
+
+    fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    fn poll_take_file(&mut self) -> Poll<std::fs::File, std::io::Error> {
+        try_ready!(self.poll_idle());
+        let back = self.discard_read_ahead();
+        let mut file = self.take_ready_file();
+        if back != 0 {
+            if let Err(error) = std::io::Seek::seek(&mut file, std::io::SeekFrom::Current(back)) {
+                self.state = AsyncFileState::Ready(file);
+                return Err(error);
+            }
+        }
+        Ok(Async::Ready(file))
+    }
+
+    fn put_file(&mut self, file: std::fs::File) {
+        self.state = AsyncFileState::Ready(file);
+    }
+}
+
+
+// WithFile
+
+/// Future выполнения функции над файлом обертки в ее исполнителе (см. `with_file`
+/// у `AsyncFileWrite`, `AsyncFileSink`, `AsyncFileRead`, `AsyncFileStream` и `AsyncFile`).
+///
+/// Результат - обертка, готовая к дальнейшей работе, и результат функции.
+pub struct WithFile<W, F, R> {
+    wrapper: Option<W>,
+    f: Option<F>,
+    running: Option<PoolTask<(std::fs::File, std::io::Result<R>)>>,
+}
+impl<W, F, R> WithFile<W, F, R> {
+    pub fn new(wrapper: W, f: F) -> WithFile<W, F, R> {
+        WithFile {
+            wrapper: Some(wrapper),
+            f: Some(f),
+            running: None,
+        }
+    }
+}
+impl<W, F, R> Future for WithFile<W, F, R>
+    where W: FileSlot,
+          F: FnOnce(&mut std::fs::File) -> std::io::Result<R> + Send + 'static,
+          R: Send + 'static
+{
+    type Item = (W, R);
+    type Error = std::io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if self.running.is_none() {
+            let wrapper = self.wrapper.as_mut().expect("poll a WithFile after it's done");
+            let mut file = try_ready!(wrapper.poll_take_file());
+            let f = self.f.take().unwrap();
+            self.running = Some(wrapper.executor().spawn_blocking(move || {
+                let result = f(&mut file);
+                Ok((file, result))
+            }));
+        }
+        let (file, result) = try_ready!(self.running.as_mut().unwrap().poll());
+        self.running = None;
+        let mut wrapper = self.wrapper.take().unwrap();
+        wrapper.put_file(file);
+        Ok(Async::Ready((wrapper, result?)))
+    }
+}
+impl<W, F, R> std::fmt::Debug for WithFile<W, F, R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("WithFile")
+            .field("running", &self.running.is_some())
+            .finish()
+    }
+}